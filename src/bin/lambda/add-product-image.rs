@@ -0,0 +1,44 @@
+use lambda_http::{service_fn, Request};
+use products::{entrypoints::lambda::apigateway::add_product_image, utils::*};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+fn main() -> Result<(), E> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize stores
+    let store = get_store().await;
+    let history = get_history_store().await;
+    let images = get_image_store().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_http`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `add_product_image` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // This uses a closure to pass the Service without having to reinstantiate
+    // it for every call. This is a bit of a hack, but it's the only way to
+    // pass a store to a lambda function.
+    //
+    // Furthermore, we don't await the result of `add_product_image` because
+    // async closures aren't stable yet. This way, the closure returns a Future,
+    // which matches the signature of the lambda function.
+    // See https://github.com/rust-lang/rust/issues/62290
+    lambda_http::run(service_fn(|event: Request| {
+        add_product_image(&store, &store, &history, &images, event)
+    }))
+    .await?;
+    Ok(())
+}
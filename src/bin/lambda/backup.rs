@@ -0,0 +1,36 @@
+use lambda_runtime::{service_fn, LambdaEvent};
+use products::{
+    entrypoints::lambda::backup::{backup, BackupRequest},
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize store
+    let store = get_store().await;
+
+    // Initialize object store
+    let objects = get_object_store().await;
+
+    // Run the Lambda function
+    //
+    // This function is not attached to any event source: operators invoke it
+    // directly with an `export` or `import` request.
+    lambda_runtime::run(service_fn(|event: LambdaEvent<BackupRequest>| {
+        let (event, ctx) = event.into_parts();
+        backup(&store, &store, &store, &objects, event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
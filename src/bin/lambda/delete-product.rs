@@ -1,13 +1,30 @@
 use lambda_http::{service_fn, Request};
-use products::{entrypoints::lambda::apigateway::delete_product, utils::*};
+use products::{
+    entrypoints::lambda::{alb, apigateway::delete_product},
+    utils::*,
+};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     // Initialize logger
     setup_tracing();
 
-    // Initialize store
+    // Initialize stores
     let store = get_store().await;
+    let audit = get_audit_store().await;
+    let idempotency = get_idempotency_store().await;
+    let allow_hard_delete = allow_hard_delete();
+
+    // Route of the function when it sits behind a load balancer
+    let route = get_alb_route("/{id}");
 
     // Run the Lambda function
     //
@@ -24,6 +41,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // async closures aren't stable yet. This way, the closure returns a Future,
     // which matches the signature of the lambda function.
     // See https://github.com/rust-lang/rust/issues/62290
-    lambda_http::run(service_fn(|event: Request| delete_product(&store, event))).await?;
+    lambda_http::run(service_fn(|event: Request| {
+        alb::handle(&route, event, |event| {
+            delete_product(
+                &store,
+                &store,
+                &store,
+                &audit,
+                idempotency.as_ref(),
+                event,
+                allow_hard_delete,
+            )
+        })
+    }))
+    .await?;
     Ok(())
 }
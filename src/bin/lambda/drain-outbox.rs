@@ -0,0 +1,42 @@
+use lambda_runtime::{service_fn, LambdaEvent};
+use products::{entrypoints::lambda::scheduled::drain_outbox, utils::*};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize the outbox and the event bus
+    let outbox = get_outbox().await;
+    let event_bus = get_event_bus().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `drain_outbox` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // This uses a closure to pass the Service without having to reinstantiate
+    // it for every call. This is a bit of a hack, but it's the only way to
+    // pass the outbox to a lambda function.
+    //
+    // Furthermore, we don't await the result of `drain_outbox` because
+    // async closures aren't stable yet. This way, the closure returns a Future,
+    // which matches the signature of the lambda function.
+    // See https://github.com/rust-lang/rust/issues/62290
+    lambda_runtime::run(service_fn(|event: LambdaEvent<serde_json::Value>| {
+        let (event, ctx) = event.into_parts();
+        drain_outbox(&outbox, event_bus.as_ref(), event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
@@ -1,16 +1,39 @@
 use lambda_runtime::{service_fn, LambdaEvent};
 use products::{
-    entrypoints::lambda::dynamodb::{model::DynamoDBEvent, parse_events},
+    entrypoints::lambda::dynamodb::{metrics::StreamMetrics, model::DynamoDBEvent, parse_events},
     utils::*,
 };
+use std::sync::Arc;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     // Initialize logger
     setup_tracing();
 
-    // Initialize event bus
+    // Initialize the decoder of stream records, configured as the store
+    let decoder = get_item_decoder().await;
+
+    // Initialize event bus, duplicate suppression and checkpoints
     let event_bus = get_event_bus().await;
+    let idempotency = get_idempotency().await;
+    let checkpoints = get_checkpoints().await;
+
+    // Initialize metrics, served locally if a port is set
+    let metrics = Arc::new(StreamMetrics::new());
+    if let Ok(port) = std::env::var("METRICS_PORT") {
+        metrics
+            .clone()
+            .serve(port.parse().expect("METRICS_PORT must be a port number"))
+            .await?;
+    }
 
     // Run the Lambda function
     //
@@ -29,7 +52,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // See https://github.com/rust-lang/rust/issues/62290
     lambda_runtime::run(service_fn(|event: LambdaEvent<DynamoDBEvent>| {
         let (event, ctx) = event.into_parts();
-        parse_events(&event_bus, event, ctx)
+        parse_events(
+            &decoder,
+            event_bus.as_ref(),
+            idempotency.as_ref(),
+            checkpoints.as_ref(),
+            metrics.as_ref(),
+            event,
+            ctx,
+        )
     }))
     .await?;
     Ok(())
@@ -0,0 +1,123 @@
+use lambda_http::{service_fn, IntoResponse, Request};
+use products::{
+    entrypoints::lambda::{
+        apigateway::*,
+        function_url::{route, Route},
+    },
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+fn main() -> Result<(), E> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize stores
+    //
+    // This function serves every route, so it initializes the stores of all
+    // the handlers once, at cold start.
+    let store = get_store().await;
+    let tiered = get_tiered_store().await;
+    let audit = get_audit_store().await;
+    let popularity = get_popularity_store().await;
+    let history = get_history_store().await;
+    let changes = get_change_store().await;
+    let images = get_image_store().await;
+    let webhooks = get_webhook_store().await;
+    let lag = get_lag_store().await;
+    let idempotency = get_idempotency_store().await;
+    let recommendations = get_recommendations().await;
+    let event_bus = get_event_bus().await;
+    let index = get_search_index();
+    let tax = get_tax_calculator();
+    let budget = batch_get_budget();
+    let allow_hard_delete = allow_hard_delete();
+
+    // References are copied into the future of each request
+    let (store, tiered, audit, popularity) = (&store, &tiered, &audit, &popularity);
+    let (history, changes, images, index) = (&history, &changes, &images, &index);
+    let webhooks = &webhooks;
+    let lag = lag.as_ref();
+    let idempotency = idempotency.as_ref();
+    let recommendations = recommendations.as_ref();
+    let event_bus = event_bus.as_ref();
+    let tax = tax.as_ref();
+
+    // Run the Lambda function
+    //
+    // A Function URL sends all requests to this function, so it finds the
+    // route of each request and calls the handler of that route, as API
+    // Gateway would.
+    lambda_http::run(service_fn(|event: Request| async move {
+        let (route, event) = match route(event) {
+            Ok(routed) => routed,
+            Err(res) => return Ok::<_, E>(res),
+        };
+
+        Ok(match route {
+            Route::GetProducts => get_products(store, store, event, budget)
+                .await?
+                .into_response(),
+            Route::FindProducts => find_products(store, event).await?.into_response(),
+            Route::GetStats => get_stats(store, event).await?.into_response(),
+            Route::GetPopularProducts => get_popular_products(popularity, store, event)
+                .await?
+                .into_response(),
+            Route::SearchProducts => search_products(index, event).await?.into_response(),
+            Route::GetChanges => get_changes(changes, event).await?.into_response(),
+            Route::GetConsumers => get_consumers(lag, event).await?.into_response(),
+            Route::GetWebhooks => get_webhooks(webhooks, event).await?.into_response(),
+            Route::GetWebhook => get_webhook(webhooks, event).await?.into_response(),
+            Route::PutWebhook => put_webhook(webhooks, event).await?.into_response(),
+            Route::DeleteWebhook => delete_webhook(webhooks, event).await?.into_response(),
+            Route::ArchiveCategory => archive_category(store, store, history, event_bus, event)
+                .await?
+                .into_response(),
+            Route::GetProduct => get_product(tiered, audit, popularity, event)
+                .await?
+                .into_response(),
+            Route::PutProduct => put_product(store, store, history, audit, idempotency, event)
+                .await?
+                .into_response(),
+            Route::PatchProduct => patch_product(store, store, history, audit, event)
+                .await?
+                .into_response(),
+            Route::DeleteProduct => delete_product(
+                store,
+                store,
+                store,
+                audit,
+                idempotency,
+                event,
+                allow_hard_delete,
+            )
+            .await?
+            .into_response(),
+            Route::GetProductPrice => get_product_price(store, tax, event).await?.into_response(),
+            Route::GetRelatedProducts => get_related_products(recommendations, store, event)
+                .await?
+                .into_response(),
+            Route::GetProductImages => get_product_images(store, images, event)
+                .await?
+                .into_response(),
+            Route::AddProductImage => add_product_image(store, store, history, images, event)
+                .await?
+                .into_response(),
+            Route::GetProductAudit => get_product_audit(audit, event).await?.into_response(),
+            Route::GetProductHistory => get_product_history(history, event).await?.into_response(),
+            Route::RestoreProduct => restore_product(store, audit, event).await?.into_response(),
+        })
+    }))
+    .await?;
+    Ok(())
+}
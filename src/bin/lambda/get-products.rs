@@ -1,15 +1,30 @@
 use lambda_http::{service_fn, Request};
-use products::{entrypoints::lambda::apigateway::get_products, utils::*};
+use products::{
+    entrypoints::lambda::{alb, apigateway::get_products},
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 type E = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-#[tokio::main]
-async fn main() -> Result<(), E> {
+fn main() -> Result<(), E> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), E> {
     // Initialize logger
     setup_tracing();
 
     // Initialize store
     let store = get_store().await;
+    let budget = batch_get_budget();
+
+    // Route of the function when it sits behind a load balancer
+    let route = get_alb_route("/");
 
     // Run the Lambda function
     //
@@ -26,6 +41,11 @@ async fn main() -> Result<(), E> {
     // async closures aren't stable yet. This way, the closure returns a Future,
     // which matches the signature of the lambda function.
     // See https://github.com/rust-lang/rust/issues/62290
-    lambda_http::run(service_fn(|event: Request| get_products(&store, event))).await?;
+    lambda_http::run(service_fn(|event: Request| {
+        alb::handle(&route, event, |event| {
+            get_products(&store, &store, event, budget)
+        })
+    }))
+    .await?;
     Ok(())
 }
@@ -0,0 +1,41 @@
+use lambda_runtime::{service_fn, LambdaEvent};
+use products::{entrypoints::lambda::scheduled::materialize_popular_products, utils::*};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize popularity store
+    let popularity = get_popularity_store().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `materialize_popular_products` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // This uses a closure to pass the Service without having to reinstantiate
+    // it for every call. This is a bit of a hack, but it's the only way to
+    // pass the popularity store to a lambda function.
+    //
+    // Furthermore, we don't await the result of `materialize_popular_products` because
+    // async closures aren't stable yet. This way, the closure returns a Future,
+    // which matches the signature of the lambda function.
+    // See https://github.com/rust-lang/rust/issues/62290
+    lambda_runtime::run(service_fn(|event: LambdaEvent<serde_json::Value>| {
+        let (event, ctx) = event.into_parts();
+        materialize_popular_products(&popularity, &popularity, event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
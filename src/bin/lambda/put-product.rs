@@ -1,15 +1,32 @@
 use lambda_http::{service_fn, Request};
-use products::{entrypoints::lambda::apigateway::put_product, utils::*};
+use products::{
+    entrypoints::lambda::{alb, apigateway::put_product},
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 type E = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-#[tokio::main]
-async fn main() -> Result<(), E> {
+fn main() -> Result<(), E> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), E> {
     // Initialize logger
     setup_tracing();
 
-    // Initialize store
+    // Initialize stores
     let store = get_store().await;
+    let history = get_history_store().await;
+    let audit = get_audit_store().await;
+    let idempotency = get_idempotency_store().await;
+
+    // Route of the function when it sits behind a load balancer
+    let route = get_alb_route("/{id}");
 
     // Run the Lambda function
     //
@@ -26,6 +43,18 @@ async fn main() -> Result<(), E> {
     // async closures aren't stable yet. This way, the closure returns a Future,
     // which matches the signature of the lambda function.
     // See https://github.com/rust-lang/rust/issues/62290
-    lambda_http::run(service_fn(|event: Request| put_product(&store, event))).await?;
+    lambda_http::run(service_fn(|event: Request| {
+        alb::handle(&route, event, |event| {
+            put_product(
+                &store,
+                &store,
+                &history,
+                &audit,
+                idempotency.as_ref(),
+                event,
+            )
+        })
+    }))
+    .await?;
     Ok(())
 }
@@ -0,0 +1,39 @@
+use lambda_runtime::{service_fn, LambdaEvent};
+use products::{
+    entrypoints::lambda::sns::{model::SnsEvent, process_notifications},
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize stores
+    let store = get_store().await;
+    let history = get_history_store().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `process_notifications` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // Failed invocations are retried by Lambda, as SNS invokes the function
+    // asynchronously.
+    lambda_runtime::run(service_fn(|event: LambdaEvent<SnsEvent>| {
+        let (event, ctx) = event.into_parts();
+        process_notifications(&store, &store, &history, event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
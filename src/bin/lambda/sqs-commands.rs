@@ -0,0 +1,50 @@
+use lambda_runtime::{service_fn, LambdaEvent};
+use products::{
+    entrypoints::lambda::sqs::{model::SqsEvent, process_messages},
+    utils::*,
+};
+
+// Optional allocator, enabled with `--features mimalloc`
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize stores and checkpoints
+    let store = get_store().await;
+    let history = get_history_store().await;
+    let audit = get_audit_store().await;
+    let checkpoints = get_checkpoints().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `process_messages` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // The function returns the messages that failed, so that Lambda only
+    // returns those to the queue.
+    lambda_runtime::run(service_fn(|event: LambdaEvent<SqsEvent>| {
+        let (event, ctx) = event.into_parts();
+        process_messages(
+            &store,
+            &store,
+            &store,
+            &history,
+            &audit,
+            checkpoints.as_ref(),
+            event,
+            ctx,
+        )
+    }))
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Sample worker consuming events from a queue
+//!
+//! Usage: `event-worker`, with `EVENT_QUEUE_URL` set to an SQS queue
+//! receiving events, such as the queue of the SQS FIFO bus. The worker
+//! prints every event it receives, until it is stopped.
+//!
+//! Events are delivered at least once, so the worker claims the id of each
+//! event before processing it, in `IDEMPOTENCY_TABLE_NAME` if set or in
+//! memory otherwise, and skips the events it already processed.
+
+use products::{consumer::process_once, event_bus::EventConsumer, utils::*, Error, EventEnvelope};
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    setup_tracing();
+
+    let consumer = get_event_consumer().await;
+    let idempotency = get_idempotency().await;
+
+    let handler = |envelopes: Vec<EventEnvelope>| {
+        let idempotency = idempotency.as_ref();
+        async move {
+            // Events published before metadata existed have no id to claim
+            let mut items = Vec::new();
+            let mut unclaimed = Vec::new();
+            for envelope in envelopes {
+                match envelope.metadata.as_ref().map(|m| m.event_id.clone()) {
+                    Some(id) => items.push((id, envelope)),
+                    None => unclaimed.push(envelope),
+                }
+            }
+
+            process_once(idempotency, items, |envelopes| async move {
+                envelopes.iter().for_each(print_event);
+                Ok::<(), Error>(())
+            })
+            .await?;
+            unclaimed.iter().for_each(print_event);
+            Ok(())
+        }
+    };
+
+    consumer.subscribe(&handler).await?;
+    Ok(())
+}
+
+fn print_event(envelope: &EventEnvelope) {
+    let event = &envelope.event;
+    match &envelope.metadata {
+        Some(metadata) => println!(
+            "[{}] {} {} (correlation {})",
+            metadata.event_id,
+            event.event_type(),
+            event.id(),
+            metadata.correlation_id
+        ),
+        None => println!("{} {}", event.event_type(), event.id()),
+    }
+}
@@ -0,0 +1,70 @@
+//! Rebuild projections from the change feed
+//!
+//! Usage: `rebuild-projection [--since SEQUENCE]`. Without `--since`, the
+//! projections are cleared and the whole change feed is replayed. With
+//! `--since`, the replay resumes after the given checkpoint without clearing
+//! the projections, e.g. after an interrupted rebuild.
+//!
+//! The change feed is read from `CHANGES_TABLE_NAME` and the product
+//! projection is written to `PROJECTION_TABLE_NAME`. Checkpoints are
+//! recorded as the position of each projection in `LAG_TABLE_NAME`, if set.
+
+use products::{
+    projection::{rebuild, replay, EventHandler, ProductProjection, ReplayProgress},
+    utils::*,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    setup_tracing();
+
+    let mut args = std::env::args().skip(1);
+    let since = match (args.next().as_deref(), args.next()) {
+        (None, _) => None,
+        (Some("--since"), Some(since)) => Some(since),
+        _ => return Err("usage: rebuild-projection [--since SEQUENCE]".into()),
+    };
+
+    let changes = get_change_store().await;
+    let projection = ProductProjection::new(get_projection_store().await);
+    // Write buffered products if the rebuild is interrupted
+    let shutdown = projection.buffer().spawn_flush_on_shutdown();
+    let handlers: [&dyn EventHandler; 1] = [&projection];
+    let lag = get_lag_store().await;
+
+    let report = |progress: &ReplayProgress| {
+        eprintln!(
+            "Replayed {} changes, checkpoint: {}",
+            progress.processed,
+            progress.checkpoint.as_deref().unwrap_or("-")
+        );
+    };
+    let replayed = async {
+        match since {
+            Some(since) => {
+                replay(&changes, &handlers, Some(lag.as_ref()), Some(since), report).await
+            }
+            None => rebuild(&changes, &handlers, Some(lag.as_ref()), report).await,
+        }
+    };
+    let progress = tokio::select! {
+        progress = replayed => progress?,
+        // An interrupted rebuild fails, whether the buffer could be flushed
+        // or not
+        flushed = shutdown => {
+            let flushed = flushed??;
+            eprintln!("Interrupted after flushing {} buffered products", flushed);
+            return Err("rebuild interrupted".into());
+        }
+    };
+
+    println!(
+        "Done: replayed {} changes, checkpoint: {}",
+        progress.processed,
+        progress.checkpoint.as_deref().unwrap_or("-")
+    );
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Compare the catalog with an external source of truth
+//!
+//! Usage: `reconcile (--http URL | --s3 KEY) --report KEY [--emit-events]`.
+//! The source is either an HTTP endpoint returning pages of products, or a
+//! JSON lines export in `BACKUP_BUCKET_NAME`. The discrepancy report is
+//! written as JSON lines to `BACKUP_BUCKET_NAME`.
+//!
+//! With `--emit-events`, the events that would bring consumers in line with
+//! the source are sent to the event bus.
+
+use products::{
+    reconcile::{reconcile, CatalogSource, HttpCatalogSource, ObjectCatalogSource},
+    utils::*,
+};
+
+const USAGE: &str = "usage: reconcile (--http URL | --s3 KEY) --report KEY [--emit-events]";
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    setup_tracing();
+
+    let mut http = None;
+    let mut s3 = None;
+    let mut report_key = None;
+    let mut emit_events = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--http" => http = args.next(),
+            "--s3" => s3 = args.next(),
+            "--report" => report_key = args.next(),
+            "--emit-events" => emit_events = true,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let report_key = report_key.ok_or(USAGE)?;
+
+    let source: Box<dyn CatalogSource> = match (http, s3) {
+        (Some(endpoint), None) => {
+            Box::new(HttpCatalogSource::new(reqwest::Client::new(), endpoint))
+        }
+        (None, Some(key)) => Box::new(ObjectCatalogSource::new(get_object_store().await, key)),
+        _ => return Err(USAGE.into()),
+    };
+
+    let store = get_store().await;
+    let objects = get_object_store().await;
+    let event_bus = if emit_events {
+        Some(get_event_bus().await)
+    } else {
+        None
+    };
+
+    let summary = reconcile(
+        &store,
+        &store,
+        source.as_ref(),
+        &objects,
+        &report_key,
+        event_bus.as_deref().map(|bus| bus as _),
+    )
+    .await?;
+
+    println!(
+        "Done: checked {} products, {} missing in the store, {} missing in the source, {} mismatched, {} events sent",
+        summary.checked,
+        summary.missing_in_store,
+        summary.missing_in_source,
+        summary.mismatched,
+        summary.events
+    );
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Publish past events again from the product history
+//!
+//! Usage: `replay-events FROM [TO]`, with times in milliseconds since the
+//! UNIX epoch. Without `TO`, events are replayed up to now.
+//!
+//! Revisions are read from `HISTORY_TABLE_NAME` and the events are published
+//! through the event bus configured as for the stream function, e.g. with
+//! `EVENT_BUS_NAME`.
+
+use products::{replay::replay, utils::*};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    runtime().block_on(run())
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    setup_tracing();
+
+    let usage = "usage: replay-events FROM [TO]";
+    let mut args = std::env::args().skip(1);
+    let from: u64 = args.next().ok_or(usage)?.parse().map_err(|_| usage)?;
+    let to: u64 = match args.next() {
+        Some(to) => to.parse().map_err(|_| usage)?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+    };
+
+    let history = get_history_store().await;
+    let event_bus = get_event_bus().await;
+
+    let count = replay(
+        &history,
+        &history,
+        event_bus.as_ref(),
+        from,
+        to,
+        |published, version| eprintln!("Replayed {} events, up to {}", published, version),
+    )
+    .await?;
+
+    println!("Done: replayed {} events", count);
+    Ok(())
+}
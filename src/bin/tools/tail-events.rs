@@ -0,0 +1,73 @@
+//! Print events written by the file bus
+//!
+//! Usage: `tail-events [PATH]`, where `PATH` defaults to the `EVENT_BUS_FILE`
+//! environment variable. The tool prints all events currently in the file,
+//! then waits for new events, following the file across rotations.
+
+use products::event_bus::FileEnvelope;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    thread,
+    time::Duration,
+};
+
+/// Delay between two checks for new events
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var("EVENT_BUS_FILE").ok())
+        .ok_or("usage: tail-events [PATH], or set EVENT_BUS_FILE")?;
+
+    let mut offset = 0;
+    let mut line = String::new();
+    loop {
+        // The file might not exist yet, or be in the middle of a rotation
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        // Start from the beginning if the file was rotated
+        if file.metadata()?.len() < offset {
+            offset = 0;
+        }
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(offset))?;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            // Stop on partial lines, they will be read again once complete
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            offset += read as u64;
+            print_line(&line);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn print_line(line: &str) {
+    match serde_json::from_str::<FileEnvelope>(line) {
+        Ok(envelope) => {
+            println!(
+                "[{}] {} {}",
+                envelope.time,
+                envelope.detail.event.event_type(),
+                envelope.resource
+            );
+            if let Ok(detail) = serde_json::to_string_pretty(&envelope.detail) {
+                println!("{}", detail);
+            }
+        }
+        Err(_) => eprintln!("Skipping invalid line: {}", line.trim_end()),
+    }
+}
@@ -0,0 +1,184 @@
+//! # DynamoDB checkpoints implementation
+//!
+//! Checkpoints are items keyed by partition, holding the sequence number as
+//! a number so that conditional updates can compare it. The `expires_at`
+//! attribute holds the expiry time in seconds since the UNIX epoch, and
+//! should be set as the TTL attribute of the table: once the records of a
+//! partition have left the stream, its checkpoint is no longer needed.
+
+use super::Checkpoints;
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use aws_smithy_http::result::SdkError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Default time after which a checkpoint expires
+///
+/// This matches the retention period of DynamoDB Streams.
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// DynamoDB checkpoints implementation.
+pub struct DynamoDBCheckpoints {
+    client: Client,
+    table_name: String,
+    ttl: Duration,
+}
+
+impl DynamoDBCheckpoints {
+    pub fn new(client: Client, table_name: String) -> DynamoDBCheckpoints {
+        DynamoDBCheckpoints {
+            client,
+            table_name,
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    /// Set the time after which a checkpoint expires
+    pub fn with_ttl(mut self, ttl: Duration) -> DynamoDBCheckpoints {
+        self.ttl = ttl;
+        self
+    }
+}
+
+#[async_trait]
+impl Checkpoints for DynamoDBCheckpoints {
+    /// Get the checkpoint of a partition
+    #[instrument(skip(self))]
+    async fn get(&self, partition: &str) -> Result<Option<String>, Error> {
+        info!("Getting checkpoint of '{}' from DynamoDB table", partition);
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(partition.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        Ok(res
+            .item
+            .and_then(|item| item.get("sequence_number")?.as_n().ok().cloned()))
+    }
+
+    /// Move the checkpoint of a partition forward
+    ///
+    /// The condition only lets the write through if the new sequence number
+    /// is greater than the stored one.
+    #[instrument(skip(self))]
+    async fn advance(&self, partition: &str, sequence: &str) -> Result<(), Error> {
+        info!(
+            "Advancing checkpoint of '{}' to {} in DynamoDB table",
+            partition, sequence
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_secs();
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(partition.to_string()))
+            .update_expression("SET sequence_number = :sequence, expires_at = :expires_at")
+            .condition_expression("attribute_not_exists(id) OR sequence_number < :sequence")
+            .expression_attribute_values(":sequence", AttributeValue::N(sequence.to_string()))
+            .expression_attribute_values(
+                ":expires_at",
+                AttributeValue::N((now + self.ttl.as_secs()).to_string()),
+            )
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            // Already at or past this sequence number
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_get() -> Result<(), Error> {
+        // GIVEN a checkpoint table with a checkpoint for the partition
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(
+                    r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Item": {"id": {"S": "1"}, "sequence_number": {"N": "111100000000010440376838"}}}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let checkpoints = DynamoDBCheckpoints::new(client, "test".to_string());
+
+        // WHEN getting the checkpoint
+        let sequence = checkpoints.get("1").await?;
+
+        // THEN the sequence number is returned as is
+        assert_eq!(sequence.as_deref(), Some("111100000000010440376838"));
+        // AND the read is strongly consistent
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_advance_behind() -> Result<(), Error> {
+        // GIVEN a checkpoint table with a more recent checkpoint
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let checkpoints = DynamoDBCheckpoints::new(client, "test".to_string());
+
+        // WHEN advancing to an older sequence number
+        let res = checkpoints.advance("1", "100").await;
+
+        // THEN the checkpoint is left as is without an error
+        assert!(res.is_ok());
+        // AND the update is conditional
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains(
+            r#""ConditionExpression":"attribute_not_exists(id) OR sequence_number < :sequence""#
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+//! # In-memory checkpoints implementation
+//!
+//! Checkpoints only last for the lifetime of the process, which is enough to
+//! skip records re-delivered to the same function instance or in tests.
+
+use super::{compare_sequences, Checkpoints};
+use crate::Error;
+use async_trait::async_trait;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryCheckpoints {
+    sequences: RwLock<HashMap<String, String>>,
+}
+
+impl MemoryCheckpoints {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl Checkpoints for MemoryCheckpoints {
+    async fn get(&self, partition: &str) -> Result<Option<String>, Error> {
+        Ok(self.sequences.read().unwrap().get(partition).cloned())
+    }
+
+    async fn advance(&self, partition: &str, sequence: &str) -> Result<(), Error> {
+        let mut sequences = self.sequences.write().unwrap();
+        match sequences.get(partition) {
+            Some(current) if compare_sequences(current, sequence) != Ordering::Less => {}
+            _ => {
+                sequences.insert(partition.to_string(), sequence.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_advance() -> Result<(), Error> {
+        let checkpoints = MemoryCheckpoints::new();
+
+        checkpoints.advance("1", "200").await?;
+        checkpoints.advance("1", "100").await?;
+
+        assert_eq!(checkpoints.get("1").await?.as_deref(), Some("200"));
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! # Stream checkpoints
+//!
+//! Checkpoints record the sequence number of the last processed record of
+//! each partition of a stream. Records of a partition are delivered in
+//! order, so a record at or before the checkpoint was already processed,
+//! e.g. when Lambda re-drives a batch after a failure, and can be skipped.
+//!
+//! Lambda doesn't tell which shard a DynamoDB Streams record comes from, so
+//! partitions are the item keys: all the records of an item are delivered in
+//! order, even across shards.
+
+use crate::Error;
+use async_trait::async_trait;
+use futures::future::join_all;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::info;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBCheckpoints;
+pub use memory::MemoryCheckpoints;
+
+/// Trait for storing stream checkpoints
+#[async_trait]
+pub trait Checkpoints: Send + Sync {
+    /// Sequence number of the last processed record of a partition
+    async fn get(&self, partition: &str) -> Result<Option<String>, Error>;
+
+    /// Move the checkpoint of a partition forward
+    ///
+    /// Checkpoints never move backwards: if the partition is already at or
+    /// past `sequence`, nothing changes.
+    async fn advance(&self, partition: &str, sequence: &str) -> Result<(), Error>;
+}
+
+/// Compare two sequence numbers
+///
+/// Sequence numbers are decimal strings that can be longer than any integer
+/// type, so they are compared by length first.
+pub fn compare_sequences(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Process the items that come after the checkpoint of their partition
+///
+/// `items` are tuples of partitions, sequence numbers and values. Values
+/// after the checkpoint of their partition are passed to `process` in a
+/// single call. Checkpoints are only moved forward once it succeeds, so that
+/// a failed batch is processed again when it is retried. Returns the number
+/// of processed items.
+pub async fn process_after_checkpoints<T, F, Fut>(
+    checkpoints: &dyn Checkpoints,
+    items: Vec<(String, String, T)>,
+    process: F,
+) -> Result<usize, Error>
+where
+    F: FnOnce(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut partitions: Vec<&str> = items.iter().map(|(p, _, _)| p.as_str()).collect();
+    partitions.sort_unstable();
+    partitions.dedup();
+    let results = join_all(partitions.iter().map(|p| checkpoints.get(p))).await;
+    let current = partitions
+        .into_iter()
+        .zip(results)
+        .map(|(p, checkpoint)| Ok((p.to_string(), checkpoint?)))
+        .collect::<Result<HashMap<String, Option<String>>, Error>>()?;
+
+    // Keep new items, and the last sequence number of each partition
+    let mut latest: HashMap<String, String> = HashMap::new();
+    let mut values = Vec::new();
+    for (partition, sequence, value) in items {
+        if let Some(Some(checkpoint)) = current.get(&partition) {
+            if compare_sequences(&sequence, checkpoint) != Ordering::Greater {
+                continue;
+            }
+        }
+        match latest.get(&partition) {
+            Some(last) if compare_sequences(last, &sequence) != Ordering::Less => {}
+            _ => {
+                latest.insert(partition, sequence);
+            }
+        }
+        values.push(value);
+    }
+    info!("Processing {} records after their checkpoint", values.len());
+    if values.is_empty() {
+        return Ok(0);
+    }
+
+    let count = values.len();
+    process(values).await?;
+
+    join_all(
+        latest
+            .iter()
+            .map(|(partition, sequence)| checkpoints.advance(partition, sequence)),
+    )
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_sequences() {
+        assert_eq!(compare_sequences("9", "10"), Ordering::Less);
+        assert_eq!(
+            compare_sequences("111100000000010440376838", "111100000000010440376837"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_sequences("0042", "42"), Ordering::Equal);
+    }
+
+    #[tokio::test]
+    async fn test_process_after_checkpoints() -> Result<(), Error> {
+        // GIVEN a partition checkpointed at sequence 200
+        let checkpoints = MemoryCheckpoints::new();
+        checkpoints.advance("1", "200").await?;
+
+        // WHEN processing a re-driven batch with records before and after it
+        let mut processed = Vec::new();
+        let count = process_after_checkpoints(
+            &checkpoints,
+            vec![
+                ("1".to_string(), "100".to_string(), 'a'),
+                ("1".to_string(), "200".to_string(), 'b'),
+                ("1".to_string(), "300".to_string(), 'c'),
+                ("2".to_string(), "150".to_string(), 'd'),
+            ],
+            |values| {
+                processed = values;
+                async { Ok(()) }
+            },
+        )
+        .await?;
+
+        // THEN only the records after the checkpoint are processed
+        assert_eq!(count, 2);
+        assert_eq!(processed, vec!['c', 'd']);
+        // AND the checkpoints move forward
+        assert_eq!(checkpoints.get("1").await?.as_deref(), Some("300"));
+        assert_eq!(checkpoints.get("2").await?.as_deref(), Some("150"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_after_checkpoints_failure() -> Result<(), Error> {
+        // GIVEN a new record
+        let checkpoints = MemoryCheckpoints::new();
+
+        // WHEN processing fails
+        let res = process_after_checkpoints(
+            &checkpoints,
+            vec![("1".to_string(), "100".to_string(), 'a')],
+            |_| async { Err(Error::InternalError("failure")) },
+        )
+        .await;
+
+        // THEN the error is returned
+        assert!(res.is_err());
+        // AND the checkpoint doesn't move
+        assert_eq!(checkpoints.get("1").await?, None);
+
+        Ok(())
+    }
+}
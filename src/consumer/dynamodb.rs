@@ -0,0 +1,286 @@
+//! # DynamoDB idempotency implementation
+//!
+//! Claims are items keyed by event id, written with a conditional put. The
+//! `expires_at` attribute holds the expiry time in seconds since the UNIX
+//! epoch, and should be set as the TTL attribute of the table so that
+//! DynamoDB removes expired claims. The `status` attribute tells claims in
+//! progress from completed ones, and claims without it are completed.
+
+use super::Idempotency;
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use aws_smithy_http::result::SdkError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Default time after which a completed claim expires
+///
+/// This matches the retention period of DynamoDB Streams.
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default time after which a claim in progress expires
+///
+/// This is the maximum timeout of Lambda functions.
+const DEFAULT_CLAIM_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Status of claims in progress
+const IN_PROGRESS: &str = "IN_PROGRESS";
+
+/// Status of completed claims
+const COMPLETED: &str = "COMPLETED";
+
+/// DynamoDB idempotency implementation.
+pub struct DynamoDBIdempotency {
+    client: Client,
+    table_name: String,
+    ttl: Duration,
+    claim_ttl: Duration,
+}
+
+impl DynamoDBIdempotency {
+    pub fn new(client: Client, table_name: String) -> DynamoDBIdempotency {
+        DynamoDBIdempotency {
+            client,
+            table_name,
+            ttl: DEFAULT_TTL,
+            claim_ttl: DEFAULT_CLAIM_TTL,
+        }
+    }
+
+    /// Set the time after which a completed claim expires
+    pub fn with_ttl(mut self, ttl: Duration) -> DynamoDBIdempotency {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the time after which a claim in progress expires
+    ///
+    /// This should be about the timeout of the function, so that events
+    /// claimed by an invocation that crashed are retried soon after.
+    pub fn with_claim_ttl(mut self, claim_ttl: Duration) -> DynamoDBIdempotency {
+        self.claim_ttl = claim_ttl;
+        self
+    }
+
+    /// Whether the existing claim of an event id is in progress
+    async fn is_in_progress(&self, event_id: &str) -> Result<bool, Error> {
+        let item = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(event_id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?
+            .item;
+        Ok(matches!(
+            item.as_ref()
+                .and_then(|item| item.get("status"))
+                .and_then(|status| status.as_s().ok()),
+            Some(status) if status == IN_PROGRESS
+        ))
+    }
+}
+
+/// Current time in seconds since the UNIX epoch
+fn now() -> Result<u64, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_secs())
+}
+
+#[async_trait]
+impl Idempotency for DynamoDBIdempotency {
+    /// Claim an event id
+    ///
+    /// The condition only lets the write through if there is no claim for
+    /// this event id, or if the claim has expired but wasn't removed yet.
+    /// The claim is in progress until it is completed, and expires after
+    /// the claim TTL.
+    #[instrument(skip(self))]
+    async fn claim(&self, event_id: &str) -> Result<bool, Error> {
+        info!("Claiming event '{}' in DynamoDB table", event_id);
+        let now = now()?;
+        let res = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(event_id.to_string()))
+            .item("status", AttributeValue::S(IN_PROGRESS.to_string()))
+            .item(
+                "expires_at",
+                AttributeValue::N((now + self.claim_ttl.as_secs()).to_string()),
+            )
+            .condition_expression("attribute_not_exists(id) OR expires_at < :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // Already claimed
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                if self.is_in_progress(event_id).await? {
+                    return Err(Error::InternalError(
+                        "Event is being processed by another invocation",
+                    ));
+                }
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Complete a claim
+    ///
+    /// The claim then expires after the TTL, instead of the claim TTL.
+    #[instrument(skip(self))]
+    async fn complete(&self, event_id: &str) -> Result<(), Error> {
+        info!("Completing event '{}' in DynamoDB table", event_id);
+        let now = now()?;
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(event_id.to_string()))
+            .item("status", AttributeValue::S(COMPLETED.to_string()))
+            .item(
+                "expires_at",
+                AttributeValue::N((now + self.ttl.as_secs()).to_string()),
+            )
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Release a claim
+    #[instrument(skip(self))]
+    async fn release(&self, event_id: &str) -> Result<(), Error> {
+        info!("Releasing event '{}' in DynamoDB table", event_id);
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(event_id.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_claim() -> Result<(), Error> {
+        // GIVEN an empty idempotency table
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let idempotency = DynamoDBIdempotency::new(client, "test".to_string());
+
+        // WHEN claiming an event
+        let claimed = idempotency.claim("1").await?;
+
+        // THEN the claim succeeds
+        assert!(claimed);
+        // AND the request is a conditional put
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body
+            .contains(r#""ConditionExpression":"attribute_not_exists(id) OR expires_at < :now""#));
+        // AND the claim is in progress
+        assert!(body.contains(r#""status":{"S":"IN_PROGRESS"}"#));
+
+        Ok(())
+    }
+
+    /// Connection answering a claim with an existing claim of `status`
+    fn get_claimed_connection(status: &str) -> TestConnection<SdkBody> {
+        TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(
+                        r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                    ))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(format!(
+                        r#"{{"Item": {{"id": {{"S": "1"}}, "status": {{"S": "{}"}}, "expires_at": {{"N": "1646906400"}}}}}}"#,
+                        status
+                    )))
+                    .unwrap(),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_claim_duplicate() -> Result<(), Error> {
+        // GIVEN an idempotency table with a completed claim for the event
+        let conn = get_claimed_connection(COMPLETED);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let idempotency = DynamoDBIdempotency::new(client, "test".to_string());
+
+        // WHEN claiming the event again
+        let claimed = idempotency.claim("1").await?;
+
+        // THEN the claim fails
+        assert!(!claimed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim_in_progress() -> Result<(), Error> {
+        // GIVEN an idempotency table with a claim in progress for the event
+        let conn = get_claimed_connection(IN_PROGRESS);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let idempotency = DynamoDBIdempotency::new(client, "test".to_string());
+
+        // WHEN claiming the event again
+        let res = idempotency.claim("1").await;
+
+        // THEN it fails, for the event to be retried later
+        assert!(res.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! # In-memory idempotency implementation
+//!
+//! Claims only last for the lifetime of the process, which is enough to
+//! suppress duplicates within a single function instance or in tests.
+
+use super::Idempotency;
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Default time after which a completed claim expires
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default time after which a claim in progress expires
+const DEFAULT_CLAIM_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Claim of an event id
+struct Claim {
+    expires_at: Instant,
+    completed: bool,
+}
+
+pub struct MemoryIdempotency {
+    ttl: Duration,
+    claim_ttl: Duration,
+    claims: RwLock<HashMap<String, Claim>>,
+}
+
+impl Default for MemoryIdempotency {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TTL,
+            claim_ttl: DEFAULT_CLAIM_TTL,
+            claims: Default::default(),
+        }
+    }
+}
+
+impl MemoryIdempotency {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the time after which a completed claim expires
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the time after which a claim in progress expires
+    pub fn with_claim_ttl(mut self, claim_ttl: Duration) -> Self {
+        self.claim_ttl = claim_ttl;
+        self
+    }
+}
+
+#[async_trait]
+impl Idempotency for MemoryIdempotency {
+    async fn claim(&self, event_id: &str) -> Result<bool, Error> {
+        let now = Instant::now();
+        let mut claims = self.claims.write().unwrap();
+        claims.retain(|_, claim| claim.expires_at > now);
+        match claims.get(event_id) {
+            Some(claim) if claim.completed => return Ok(false),
+            Some(_) => {
+                return Err(Error::InternalError(
+                    "Event is being processed by another invocation",
+                ))
+            }
+            None => {}
+        }
+        claims.insert(
+            event_id.to_string(),
+            Claim {
+                expires_at: now + self.claim_ttl,
+                completed: false,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn complete(&self, event_id: &str) -> Result<(), Error> {
+        self.claims.write().unwrap().insert(
+            event_id.to_string(),
+            Claim {
+                expires_at: Instant::now() + self.ttl,
+                completed: true,
+            },
+        );
+        Ok(())
+    }
+
+    async fn release(&self, event_id: &str) -> Result<(), Error> {
+        self.claims.write().unwrap().remove(event_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claim() -> Result<(), Error> {
+        let idempotency = MemoryIdempotency::new();
+
+        assert!(idempotency.claim("1").await?);
+        assert!(idempotency.claim("1").await.is_err());
+        idempotency.complete("1").await?;
+        assert!(!idempotency.claim("1").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim_expired() -> Result<(), Error> {
+        let idempotency = MemoryIdempotency::new()
+            .with_ttl(Duration::ZERO)
+            .with_claim_ttl(Duration::ZERO);
+
+        assert!(idempotency.claim("1").await?);
+        assert!(idempotency.claim("1").await?);
+        idempotency.complete("1").await?;
+        assert!(idempotency.claim("1").await?);
+
+        Ok(())
+    }
+}
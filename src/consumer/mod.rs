@@ -0,0 +1,186 @@
+//! # Event consumers
+//!
+//! Helpers shared by the functions consuming events. Event sources such as
+//! DynamoDB Streams and EventBridge deliver events at least once, so
+//! consumers need to suppress duplicates before acting on them.
+//!
+//! Consumers claim each event id before processing it. Claims are in
+//! progress at first, and expire after about the timeout of the function,
+//! so that an invocation crashing before it settles its claims doesn't hold
+//! them for long. Once the event is processed, the claim is completed and
+//! expires after a day, so that the idempotency table doesn't grow forever.
+//! If processing fails, the claim is released so that the event can be
+//! retried.
+//!
+//! Stream consumers can also skip records with checkpoints, which cost a
+//! single write per partition and batch instead of one per event.
+
+use crate::Error;
+use async_trait::async_trait;
+use futures::future::join_all;
+use std::future::Future;
+use tracing::{info, warn};
+
+mod checkpoints;
+mod dynamodb;
+mod memory;
+
+pub use checkpoints::{
+    compare_sequences, process_after_checkpoints, Checkpoints, DynamoDBCheckpoints,
+    MemoryCheckpoints,
+};
+pub use dynamodb::DynamoDBIdempotency;
+pub use memory::MemoryIdempotency;
+
+/// Trait for suppressing duplicate events
+#[async_trait]
+pub trait Idempotency: Send + Sync {
+    /// Claim an event id before processing the event
+    ///
+    /// Returns `false` if the event was already processed and the claim
+    /// hasn't expired, in which case the event should be skipped. Fails if
+    /// another invocation is still processing the event, so that it is
+    /// retried once that claim is completed, released or expired.
+    async fn claim(&self, event_id: &str) -> Result<bool, Error>;
+
+    /// Complete a claim once the event is processed
+    async fn complete(&self, event_id: &str) -> Result<(), Error>;
+
+    /// Release a claim, so that the event can be processed again
+    async fn release(&self, event_id: &str) -> Result<(), Error>;
+}
+
+/// Process the items whose event id wasn't claimed yet
+///
+/// `items` are pairs of event ids and values. Values with a new event id are
+/// passed to `process` in a single call, and their claims are completed once
+/// it succeeds. If claiming or processing fails, the claims are released
+/// before returning the error. Returns the number of processed items.
+pub async fn process_once<T, F, Fut>(
+    idempotency: &dyn Idempotency,
+    items: Vec<(String, T)>,
+    process: F,
+) -> Result<usize, Error>
+where
+    F: FnOnce(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let claims = join_all(items.iter().map(|(id, _)| idempotency.claim(id))).await;
+    if let Some(index) = claims.iter().position(Result::is_err) {
+        let claimed = items
+            .iter()
+            .zip(&claims)
+            .filter(|(_, claim)| matches!(claim, Ok(true)))
+            .map(|((id, _), _)| id);
+        join_all(claimed.map(|id| idempotency.release(id)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        return match claims.into_iter().nth(index) {
+            Some(Err(err)) => Err(err),
+            _ => Err(Error::InternalError("Unable to claim events")),
+        };
+    }
+    let claims: Vec<bool> = claims.into_iter().flatten().collect();
+
+    let (ids, values): (Vec<String>, Vec<T>) = items
+        .into_iter()
+        .zip(claims)
+        .filter(|(_, claimed)| *claimed)
+        .map(|(item, _)| item)
+        .unzip();
+    info!("Processing {} new events", ids.len());
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    if let Err(err) = process(values).await {
+        join_all(ids.iter().map(|id| idempotency.release(id)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        return Err(err);
+    }
+
+    // The items are processed, so failing to complete their claims only
+    // delays retries until the claims expire
+    for res in join_all(ids.iter().map(|id| idempotency.complete(id))).await {
+        if let Err(err) = res {
+            warn!("Unable to complete event claim: {}", err);
+        }
+    }
+
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_once() -> Result<(), Error> {
+        // GIVEN an event that was already processed
+        let idempotency = MemoryIdempotency::new();
+        idempotency.claim("1").await?;
+        idempotency.complete("1").await?;
+
+        // WHEN processing it again with a new event
+        let mut processed = Vec::new();
+        let count = process_once(
+            &idempotency,
+            vec![("1".to_string(), 1), ("2".to_string(), 2)],
+            |values| {
+                processed = values;
+                async { Ok(()) }
+            },
+        )
+        .await?;
+
+        // THEN only the new event is processed
+        assert_eq!(count, 1);
+        assert_eq!(processed, vec![2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_once_failure() -> Result<(), Error> {
+        // GIVEN a new event
+        let idempotency = MemoryIdempotency::new();
+
+        // WHEN processing fails
+        let res = process_once(&idempotency, vec![("1".to_string(), 1)], |_| async {
+            Err(Error::InternalError("failure"))
+        })
+        .await;
+
+        // THEN the error is returned
+        assert!(res.is_err());
+        // AND the event can be claimed again
+        assert!(idempotency.claim("1").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_once_in_progress() -> Result<(), Error> {
+        // GIVEN an event being processed by another invocation
+        let idempotency = MemoryIdempotency::new();
+        idempotency.claim("1").await?;
+
+        // WHEN processing it with a new event
+        let res = process_once(
+            &idempotency,
+            vec![("1".to_string(), 1), ("2".to_string(), 2)],
+            |_| async { Ok(()) },
+        )
+        .await;
+
+        // THEN it fails, for the batch to be retried
+        assert!(res.is_err());
+        // AND the claim of the new event is released
+        assert!(idempotency.claim("2").await?);
+
+        Ok(())
+    }
+}
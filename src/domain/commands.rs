@@ -0,0 +1,821 @@
+//! # Write commands
+//!
+//! Commands describe a change to the catalog. They are validated when they
+//! are constructed, and carry request metadata such as the idempotency key
+//! and the identity of the caller through the write path.
+//!
+//! Commands with an expected version only apply if the product is at that
+//! version, see `Product::version`. They are run with `execute_if_version`
+//! and a conditional store, and fail with `Error::PreconditionFailed`
+//! otherwise. `execute` rejects them, so that the version can't be ignored.
+//!
+//! Successful commands are recorded in the audit log, on a best-effort basis.
+
+use super::{
+    create_product, delete_product, delete_product_if_version, hard_delete_product, now_millis,
+    put_product, put_product_if_version, restore_product, schedule_product_deletion,
+    update_product, update_product_if_version, validate_attributes,
+};
+use crate::{
+    error::Error,
+    model::{AuditAction, AuditEntry, Event, Product, ProductPatch},
+    store::{
+        StoreAppendHistory, StoreDelete, StoreExpire, StorePut, StorePutConditional,
+        StoreRecordAudit, StoreRestore, StoreUpdate,
+    },
+};
+use std::time::Duration;
+use tracing::{error, info, instrument};
+
+/// Request metadata shared by all commands
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandContext {
+    /// Client-provided key to detect retried requests
+    pub idempotency_key: Option<String>,
+    /// Identity of the caller, if the request was authenticated
+    pub caller: Option<String>,
+    /// Id of the API key used for the request, if any
+    pub api_key_id: Option<String>,
+    /// Version the product must be at for the command to apply
+    pub expected_version: Option<String>,
+}
+
+impl CommandContext {
+    /// Expected version of a command run with `execute_if_version`
+    fn expected_version(&self) -> Result<&str, Error> {
+        self.expected_version
+            .as_deref()
+            .ok_or(Error::InternalError("Missing expected version"))
+    }
+
+    /// Fail if the command has an expected version, which requires
+    /// `execute_if_version`
+    fn check_unconditional(&self) -> Result<(), Error> {
+        match self.expected_version {
+            Some(_) => Err(Error::InternalError(
+                "Commands with an expected version need execute_if_version",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Error of conditional commands for products at another version
+const VERSION_MISMATCH: Error = Error::PreconditionFailed("product is not at the expected version");
+
+/// Create a new product
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreateProduct {
+    pub product: Product,
+    pub context: CommandContext,
+}
+
+impl CreateProduct {
+    pub fn new(product: Product, context: CommandContext) -> Result<Self, Error> {
+        validate_product(&product)?;
+        Ok(Self { product, context })
+    }
+
+    /// Returns the `Created` or `Updated` event matching the write
+    #[instrument(skip(self, store, history, audit), fields(id = %self.product.id))]
+    pub async fn execute(
+        &self,
+        store: &dyn StorePut,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Event, Error> {
+        info!("Executing CreateProduct with context {:?}", self.context);
+        self.context.check_unconditional()?;
+        let event = put_product(store, history, &self.product).await?;
+        record_audit(audit, &self.product.id, AuditAction::Put, &self.context).await;
+        Ok(event)
+    }
+
+    /// Returns the `Updated` event, if the product was at the expected
+    /// version
+    #[instrument(skip(self, store, history, audit), fields(id = %self.product.id))]
+    pub async fn execute_if_version(
+        &self,
+        store: &dyn StorePutConditional,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Event, Error> {
+        info!(
+            "Executing conditional CreateProduct with context {:?}",
+            self.context
+        );
+        put_if_version(store, history, audit, &self.product, &self.context).await
+    }
+
+    /// Returns the `Created` event, or `None` if the product already exists
+    ///
+    /// Only creations are audited.
+    #[instrument(skip(self, store, history, audit), fields(id = %self.product.id))]
+    pub async fn execute_if_absent(
+        &self,
+        store: &dyn StorePutConditional,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Option<Event>, Error> {
+        info!(
+            "Executing create-only CreateProduct with context {:?}",
+            self.context
+        );
+        self.context.check_unconditional()?;
+        let event = create_product(store, history, &self.product).await?;
+        if event.is_some() {
+            record_audit(audit, &self.product.id, AuditAction::Put, &self.context).await;
+        }
+        Ok(event)
+    }
+}
+
+/// Replace an existing product
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateProduct {
+    pub product: Product,
+    pub context: CommandContext,
+}
+
+impl UpdateProduct {
+    pub fn new(product: Product, context: CommandContext) -> Result<Self, Error> {
+        validate_product(&product)?;
+        Ok(Self { product, context })
+    }
+
+    /// Returns the `Created` or `Updated` event matching the write
+    #[instrument(skip(self, store, history, audit), fields(id = %self.product.id))]
+    pub async fn execute(
+        &self,
+        store: &dyn StorePut,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Event, Error> {
+        info!("Executing UpdateProduct with context {:?}", self.context);
+        self.context.check_unconditional()?;
+        let event = put_product(store, history, &self.product).await?;
+        record_audit(audit, &self.product.id, AuditAction::Put, &self.context).await;
+        Ok(event)
+    }
+
+    /// Returns the `Updated` event, if the product was at the expected
+    /// version
+    #[instrument(skip(self, store, history, audit), fields(id = %self.product.id))]
+    pub async fn execute_if_version(
+        &self,
+        store: &dyn StorePutConditional,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Event, Error> {
+        info!(
+            "Executing conditional UpdateProduct with context {:?}",
+            self.context
+        );
+        put_if_version(store, history, audit, &self.product, &self.context).await
+    }
+}
+
+/// Partially update an existing product
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchProduct {
+    pub id: String,
+    pub patch: ProductPatch,
+    pub context: CommandContext,
+}
+
+impl PatchProduct {
+    pub fn new(id: String, patch: ProductPatch, context: CommandContext) -> Result<Self, Error> {
+        validate_id(&id)?;
+        if patch.is_empty() {
+            return Err(Error::ClientError("patch must change at least one field"));
+        }
+        if let Some(name) = &patch.name {
+            validate_name(name)?;
+        }
+        if let Some(price) = patch.price {
+            validate_price(price)?;
+        }
+        Ok(Self { id, patch, context })
+    }
+
+    /// Returns the updated product, or `None` if it doesn't exist
+    ///
+    /// Only updates of existing products are audited.
+    #[instrument(skip(self, store, history, audit), fields(id = %self.id))]
+    pub async fn execute(
+        &self,
+        store: &dyn StoreUpdate,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Option<Product>, Error> {
+        info!("Executing PatchProduct with context {:?}", self.context);
+        self.context.check_unconditional()?;
+        let product = update_product(store, history, &self.id, &self.patch).await?;
+        if product.is_some() {
+            record_audit(audit, &self.id, AuditAction::Patch, &self.context).await;
+        }
+        Ok(product)
+    }
+
+    /// Returns the updated product, if it was at the expected version
+    #[instrument(skip(self, store, history, audit), fields(id = %self.id))]
+    pub async fn execute_if_version(
+        &self,
+        store: &dyn StorePutConditional,
+        history: &dyn StoreAppendHistory,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<Product, Error> {
+        info!(
+            "Executing conditional PatchProduct with context {:?}",
+            self.context
+        );
+        let version = self.context.expected_version()?;
+        let product = update_product_if_version(store, history, &self.id, &self.patch, version)
+            .await?
+            .ok_or(VERSION_MISMATCH)?;
+        record_audit(audit, &self.id, AuditAction::Patch, &self.context).await;
+        Ok(product)
+    }
+}
+
+/// Delete a product
+///
+/// Products are soft-deleted unless `hard` is set. With a `delay`, the
+/// soft-deleted product is permanently removed once the delay has passed.
+/// Hard deletes can't have an expected version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeleteProduct {
+    pub id: String,
+    pub hard: bool,
+    pub delay: Option<Duration>,
+    pub context: CommandContext,
+}
+
+impl DeleteProduct {
+    pub fn new(id: String, hard: bool, context: CommandContext) -> Result<Self, Error> {
+        validate_id(&id)?;
+        if hard && context.expected_version.is_some() {
+            return Err(Error::ClientError("hard deletes cannot be conditional"));
+        }
+        Ok(Self {
+            id,
+            hard,
+            delay: None,
+            context,
+        })
+    }
+
+    /// Defer the permanent removal of the product
+    pub fn with_delay(mut self, delay: Duration) -> Result<Self, Error> {
+        if self.hard {
+            return Err(Error::ClientError("hard deletes cannot be delayed"));
+        }
+        self.delay = Some(delay);
+        Ok(self)
+    }
+
+    /// Returns `false` if there was nothing to delete
+    ///
+    /// Only deletions of existing products are audited.
+    #[instrument(skip(self, store, expire, audit), fields(id = %self.id))]
+    pub async fn execute(
+        &self,
+        store: &dyn StoreDelete,
+        expire: &dyn StoreExpire,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<bool, Error> {
+        info!("Executing DeleteProduct with context {:?}", self.context);
+        self.context.check_unconditional()?;
+        let (deleted, action) = if self.hard {
+            (
+                hard_delete_product(store, &self.id).await?,
+                AuditAction::HardDelete,
+            )
+        } else if let Some(delay) = self.delay {
+            (
+                schedule_product_deletion(store, expire, &self.id, delay).await?,
+                AuditAction::Delete,
+            )
+        } else {
+            (delete_product(store, &self.id).await?, AuditAction::Delete)
+        };
+        if deleted {
+            record_audit(audit, &self.id, action, &self.context).await;
+        }
+        Ok(deleted)
+    }
+
+    /// Soft delete the product, if it was at the expected version
+    #[instrument(skip(self, store, expire, audit), fields(id = %self.id))]
+    pub async fn execute_if_version(
+        &self,
+        store: &dyn StorePutConditional,
+        expire: &dyn StoreExpire,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<(), Error> {
+        info!(
+            "Executing conditional DeleteProduct with context {:?}",
+            self.context
+        );
+        let version = self.context.expected_version()?;
+        if !delete_product_if_version(store, expire, &self.id, version, self.delay).await? {
+            return Err(VERSION_MISMATCH);
+        }
+        record_audit(audit, &self.id, AuditAction::Delete, &self.context).await;
+        Ok(())
+    }
+}
+
+/// Restore a soft-deleted product
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestoreProduct {
+    pub id: String,
+    pub context: CommandContext,
+}
+
+impl RestoreProduct {
+    pub fn new(id: String, context: CommandContext) -> Result<Self, Error> {
+        validate_id(&id)?;
+        Ok(Self { id, context })
+    }
+
+    /// Returns `false` if there was no deleted product to restore
+    ///
+    /// Only restores of deleted products are audited.
+    #[instrument(skip(self, store, audit), fields(id = %self.id))]
+    pub async fn execute(
+        &self,
+        store: &dyn StoreRestore,
+        audit: &dyn StoreRecordAudit,
+    ) -> Result<bool, Error> {
+        info!("Executing RestoreProduct with context {:?}", self.context);
+        self.context.check_unconditional()?;
+        let restored = restore_product(store, &self.id).await?;
+        if restored {
+            record_audit(audit, &self.id, AuditAction::Restore, &self.context).await;
+        }
+        Ok(restored)
+    }
+}
+
+/// Replace a product at the expected version of the context, and audit it
+async fn put_if_version(
+    store: &dyn StorePutConditional,
+    history: &dyn StoreAppendHistory,
+    audit: &dyn StoreRecordAudit,
+    product: &Product,
+    context: &CommandContext,
+) -> Result<Event, Error> {
+    let version = context.expected_version()?;
+    let event = put_product_if_version(store, history, product, version)
+        .await?
+        .ok_or(VERSION_MISMATCH)?;
+    record_audit(audit, &product.id, AuditAction::Put, context).await;
+    Ok(event)
+}
+
+/// Record a mutation in the audit log
+///
+/// Like revisions, audit entries are best-effort: the mutation already
+/// happened, so a failure is logged rather than returned, as the client
+/// would otherwise retry a write that succeeded.
+async fn record_audit(
+    audit: &dyn StoreRecordAudit,
+    id: &str,
+    action: AuditAction,
+    context: &CommandContext,
+) {
+    let res = match now_millis() {
+        Ok(timestamp) => {
+            audit
+                .record(&AuditEntry {
+                    id: id.to_string(),
+                    timestamp,
+                    action,
+                    caller: context.caller.clone(),
+                    api_key_id: context.api_key_id.clone(),
+                })
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        error!("Failed to record audit entry of product {}: {}", id, err);
+    }
+}
+
+/// Validate a product id
+fn validate_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() {
+        return Err(Error::ClientError("id must not be empty"));
+    }
+    Ok(())
+}
+
+/// Validate the fields of a product
+pub(crate) fn validate_product(product: &Product) -> Result<(), Error> {
+    validate_id(&product.id)?;
+    validate_name(&product.name)?;
+    validate_price(product.price)?;
+    validate_attributes(&product.attributes)
+}
+
+/// Validate a product name
+fn validate_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::ClientError("name must not be empty"));
+    }
+    Ok(())
+}
+
+/// Validate a product price
+pub(crate) fn validate_price(price: f64) -> Result<(), Error> {
+    if !price.is_finite() || price < 0.0 {
+        return Err(Error::ClientError("price must be a positive number"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        MemoryAuditStore, MemoryHistoryStore, MemoryStore, ReadConsistency, StoreGet,
+        StoreGetAudit, StoreGetHistory, StorePut,
+    };
+    use async_trait::async_trait;
+
+    fn get_product() -> Product {
+        Product {
+            id: "1".to_string(),
+            name: "foo".to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_create_product_invalid_name() {
+        let mut product = get_product();
+        product.name = " ".to_string();
+
+        let res = CreateProduct::new(product, CommandContext::default());
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[test]
+    fn test_create_product_invalid_price() {
+        let mut product = get_product();
+        product.price = -1.0;
+
+        let res = CreateProduct::new(product, CommandContext::default());
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[test]
+    fn test_create_product_invalid_attributes() {
+        let mut product = get_product();
+        product
+            .attributes
+            .insert("tags".to_string(), serde_json::json!(["a", "b"]));
+
+        let res = CreateProduct::new(product, CommandContext::default());
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[test]
+    fn test_delete_product_empty_id() {
+        let res = DeleteProduct::new("".to_string(), false, CommandContext::default());
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_product_execute() -> Result<(), Error> {
+        // GIVEN an empty store and a valid command
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let command = CreateProduct::new(get_product(), CommandContext::default())?;
+
+        // WHEN executing the command
+        let event = command.execute(&store, &history, &audit).await?;
+
+        // THEN the product is stored
+        assert_eq!(
+            store.get("1", ReadConsistency::Eventual, None).await?,
+            Some(get_product())
+        );
+        // AND the product is reported as created
+        assert_eq!(
+            event,
+            Event::Created {
+                product: get_product()
+            }
+        );
+        // AND a revision is recorded
+        assert_eq!(history.history("1").await?.len(), 1);
+        // AND the mutation is audited
+        let entries = audit.entries("1").await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AuditAction::Put);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_product_execute() -> Result<(), Error> {
+        // GIVEN a store with a product and a command changing its price
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let mut product = get_product();
+        product.price = 12.0;
+        let command = UpdateProduct::new(product.clone(), CommandContext::default())?;
+
+        // WHEN executing the command
+        let event = command.execute(&store, &history, &audit).await?;
+
+        // THEN the product is reported as updated, with its previous version
+        assert_eq!(
+            event,
+            Event::Updated {
+                old: get_product(),
+                new: product
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_product_execute_if_version() -> Result<(), Error> {
+        // GIVEN a store with a product, and commands changing its price at
+        // another version and at its current version
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let mut product = get_product();
+        product.price = 12.0;
+        let at_version = |version: String| {
+            let context = CommandContext {
+                expected_version: Some(version),
+                ..Default::default()
+            };
+            UpdateProduct::new(product.clone(), context)
+        };
+        let stale = at_version("0123".to_string())?;
+        let current = at_version(get_product().version())?;
+
+        // WHEN executing the commands
+        let stale = stale.execute_if_version(&store, &history, &audit).await;
+        let current = current.execute_if_version(&store, &history, &audit).await?;
+
+        // THEN the stale command fails without changing the product
+        assert!(matches!(stale, Err(Error::PreconditionFailed(_))));
+        // AND the other one updates it
+        assert_eq!(
+            current,
+            Event::Updated {
+                old: get_product(),
+                new: product.clone()
+            }
+        );
+        assert_eq!(
+            store.get("1", ReadConsistency::Eventual, None).await?,
+            Some(product)
+        );
+        assert_eq!(audit.entries("1").await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_expected_version() -> Result<(), Error> {
+        // GIVEN a command with an expected version
+        let store = MemoryStore::new();
+        let context = CommandContext {
+            expected_version: Some(get_product().version()),
+            ..Default::default()
+        };
+        let command = DeleteProduct::new("1".to_string(), false, context)?;
+
+        // WHEN executing it without condition
+        let res = command
+            .execute(&store, &store, &MemoryAuditStore::new())
+            .await;
+
+        // THEN it is rejected
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_product_empty() {
+        let res = PatchProduct::new(
+            "1".to_string(),
+            ProductPatch::default(),
+            CommandContext::default(),
+        );
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_execute() -> Result<(), Error> {
+        // GIVEN a store with a product and a command changing its price
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let patch = ProductPatch {
+            price: Some(12.345),
+            ..Default::default()
+        };
+        let command = PatchProduct::new("1".to_string(), patch, CommandContext::default())?;
+
+        // WHEN executing the command
+        let product = command.execute(&store, &history, &audit).await?;
+
+        // THEN only the price is updated, and rounded
+        let product = product.unwrap();
+        assert_eq!(product.name, "foo");
+        assert_eq!(product.price, 12.35);
+        // AND a revision is recorded
+        assert_eq!(history.history("1").await?.len(), 1);
+        // AND the mutation is audited
+        assert_eq!(audit.entries("1").await?[0].action, AuditAction::Patch);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_execute_missing() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let patch = ProductPatch {
+            name: Some("bar".to_string()),
+            ..Default::default()
+        };
+        let command = PatchProduct::new("1".to_string(), patch, CommandContext::default())?;
+
+        // WHEN executing the command
+        let product = command.execute(&store, &history, &audit).await?;
+
+        // THEN nothing is updated or audited
+        assert_eq!(product, None);
+        assert!(audit.entries("1").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_product_hard_with_delay() {
+        let res = DeleteProduct::new("1".to_string(), true, CommandContext::default())
+            .and_then(|command| command.with_delay(Duration::from_secs(60)));
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_delete_product_execute_delay() -> Result<(), Error> {
+        // GIVEN a store with a product and a deferred delete command
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let audit = MemoryAuditStore::new();
+        let command = DeleteProduct::new("1".to_string(), false, CommandContext::default())?
+            .with_delay(Duration::from_secs(24 * 60 * 60))?;
+
+        // WHEN executing the command
+        command.execute(&store, &store, &audit).await?;
+
+        // THEN the product is hidden immediately
+        assert_eq!(store.get("1", ReadConsistency::Eventual, None).await?, None);
+        // AND its removal is scheduled in a day
+        let expires_at = store.expires_at("1").unwrap();
+        let now = now_millis()? / 1000;
+        assert!(expires_at > now + 24 * 60 * 60 - 5 && expires_at <= now + 24 * 60 * 60);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_product_execute_missing() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let command = DeleteProduct::new("1".to_string(), false, CommandContext::default())?;
+
+        // WHEN executing the command
+        let deleted = command.execute(&store, &store, &audit).await?;
+
+        // THEN nothing is deleted or audited
+        assert!(!deleted);
+        assert!(audit.entries("1").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_product_execute_audit() -> Result<(), Error> {
+        // GIVEN a store with a product and an authenticated delete command
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let audit = MemoryAuditStore::new();
+        let context = CommandContext {
+            caller: Some("arn:aws:iam::123456789012:user/alice".to_string()),
+            ..Default::default()
+        };
+        let command = DeleteProduct::new("1".to_string(), true, context)?;
+
+        // WHEN executing the command
+        command.execute(&store, &store, &audit).await?;
+
+        // THEN the mutation is audited with the caller identity
+        let entries = audit.entries("1").await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AuditAction::HardDelete);
+        assert_eq!(
+            entries[0].caller.as_deref(),
+            Some("arn:aws:iam::123456789012:user/alice")
+        );
+
+        Ok(())
+    }
+
+    /// Audit store failing every record
+    struct FailingAudit;
+
+    #[async_trait]
+    impl StoreRecordAudit for FailingAudit {
+        async fn record(&self, _: &AuditEntry) -> Result<(), Error> {
+            Err(Error::InternalError("audit log unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_product_execute_audit_failure() -> Result<(), Error> {
+        // GIVEN a store with a product and a failing audit store
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let command = DeleteProduct::new("1".to_string(), false, CommandContext::default())?;
+
+        // WHEN executing the command
+        let deleted = command.execute(&store, &store, &FailingAudit).await?;
+
+        // THEN the product is deleted despite the missing audit entry
+        assert!(deleted);
+        assert!(store
+            .get("1", ReadConsistency::Strong, None)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_product_execute_audit() -> Result<(), Error> {
+        // GIVEN a store with a deleted product
+        let store = MemoryStore::new();
+        store.put(&get_product()).await?;
+        let audit = MemoryAuditStore::new();
+        DeleteProduct::new("1".to_string(), false, CommandContext::default())?
+            .execute(&store, &store, &audit)
+            .await?;
+        let context = CommandContext {
+            caller: Some("arn:aws:iam::123456789012:user/alice".to_string()),
+            ..Default::default()
+        };
+        let command = RestoreProduct::new("1".to_string(), context)?;
+
+        // WHEN executing the command twice
+        let restored = command.execute(&store, &audit).await?;
+        let restored_again = command.execute(&store, &audit).await?;
+
+        // THEN the product is restored once
+        assert!(restored);
+        assert!(!restored_again);
+        assert_eq!(
+            store.get("1", ReadConsistency::Eventual, None).await?,
+            Some(get_product())
+        );
+        // AND the restore is audited with the caller identity
+        let entries = audit.entries("1").await?;
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .any(|entry| entry.action == AuditAction::Restore
+                && entry.caller.as_deref() == Some("arn:aws:iam::123456789012:user/alice")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1887 @@
+//! Domain logic for the application.
+
+pub mod commands;
+
+use crate::{
+    error::Error,
+    event_bus::EventBus,
+    images::{new_image_key, ImageStore},
+    model::{
+        AdminProduct, AuditEntry, ChangeRange, Event, PriceBreakdown, PricePoint, Product,
+        ProductBatch, ProductHits, ProductImage, ProductPatch, ProductRange, ProductRevision,
+        WebhookSubscription,
+    },
+    object_store::ObjectStore,
+    recommendations::Recommendations,
+    reconcile::{diff_products, FieldDiff},
+    store::{
+        parse_export_data, parse_export_manifest, ProductField, ProductFilter, Query,
+        ReadConsistency, StoreAppendHistory, StoreBatchGet, StoreCount, StoreDelete,
+        StoreDeleteWebhook, StoreExpire, StoreFind, StoreGet, StoreGetAll, StoreGetAudit,
+        StoreGetChanges, StoreGetHistory, StoreGetHits, StoreGetPopular, StoreGetPriceHistory,
+        StoreGetWebhook, StoreHealth, StoreIndex, StoreListWebhooks, StorePut, StorePutConditional,
+        StorePutMany, StorePutPopular, StorePutWebhook, StoreRecordHit, StoreRecordPrices,
+        StoreRestore, StoreScanAll, StoreSearch, StoreUpdate,
+    },
+    tax::TaxCalculator,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Number of products returned by `get_products` when no limit is provided
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Maximum number of products returned by `get_products`
+pub const MAX_PAGE_SIZE: usize = 100;
+
+pub async fn get_products(
+    store: &dyn StoreGetAll,
+    next: Option<&str>,
+    limit: Option<usize>,
+    filter: &ProductFilter,
+    fields: Option<&[ProductField]>,
+) -> Result<ProductRange, Error> {
+    // Validate the page size
+    //
+    // A limit of 0 is rejected, while limits above the maximum are capped.
+    let limit = match limit {
+        Some(0) => return Err(Error::ClientError("limit must be greater than 0")),
+        Some(limit) => limit.min(MAX_PAGE_SIZE),
+        None => DEFAULT_PAGE_SIZE,
+    };
+    validate_filter(filter)?;
+
+    store.all(next, limit, filter, fields).await
+}
+
+/// Search products
+///
+/// Queries are validated like listings: a limit of 0 is rejected, while
+/// limits above the maximum are capped.
+pub async fn find_products(store: &dyn StoreFind, query: &Query) -> Result<ProductRange, Error> {
+    if query.limit == 0 {
+        return Err(Error::ClientError("limit must be greater than 0"));
+    }
+    validate_filter(&query.filter)?;
+
+    let query = Query {
+        limit: query.limit.min(MAX_PAGE_SIZE),
+        ..query.clone()
+    };
+    store.find(&query).await
+}
+
+/// Validate the price range and attribute filters of a listing
+fn validate_filter(filter: &ProductFilter) -> Result<(), Error> {
+    if let (Some(min), Some(max)) = (filter.min_price, filter.max_price) {
+        if min > max {
+            return Err(Error::ClientError("min_price must not exceed max_price"));
+        }
+    }
+
+    if filter.attributes.len() > MAX_ATTRIBUTE_FILTERS {
+        return Err(Error::ClientError("too many attribute filters"));
+    }
+    for (key, _) in &filter.attributes {
+        validate_attribute_key(key)?;
+    }
+    Ok(())
+}
+
+/// Maximum number of ids when retrieving products by id
+pub const MAX_BATCH_GET_IDS: usize = 1000;
+
+/// Retrieve products by id within a latency budget
+///
+/// Products are returned in the order of the ids, and products that don't
+/// exist are skipped. If the budget is exceeded, the products retrieved so
+/// far are returned and the batch is flagged as partial.
+pub async fn get_products_by_ids(
+    store: &dyn StoreBatchGet,
+    ids: &[String],
+    budget: Duration,
+) -> Result<ProductBatch, Error> {
+    if ids.is_empty() {
+        return Err(Error::ClientError("ids must not be empty"));
+    }
+    if ids.len() > MAX_BATCH_GET_IDS {
+        return Err(Error::ClientError("too many ids"));
+    }
+
+    // Remove duplicates, keeping the first position of each id
+    let mut positions = HashMap::new();
+    let mut unique = Vec::new();
+    for id in ids {
+        if !positions.contains_key(id) {
+            positions.insert(id.clone(), unique.len());
+            unique.push(id.clone());
+        }
+    }
+
+    let mut batch = store.get_many_within(&unique, budget).await?;
+    batch
+        .products
+        .sort_by_key(|p| positions.get(&p.id).copied());
+
+    Ok(batch)
+}
+
+/// Maximum number of custom attributes on a product
+pub const MAX_ATTRIBUTES: usize = 20;
+
+/// Maximum length of a custom attribute key
+pub const MAX_ATTRIBUTE_KEY_LENGTH: usize = 64;
+
+/// Maximum length of a custom attribute string value
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 256;
+
+/// Maximum number of attribute filters when listing products
+pub const MAX_ATTRIBUTE_FILTERS: usize = 5;
+
+/// Validate the custom attributes of a product
+///
+/// Keys are made of ASCII letters, digits, `_` and `-`, so that they can be
+/// used in query strings and store expressions. Values are strings, numbers
+/// or booleans: nested values would make filtering and size limits harder
+/// to reason about.
+pub fn validate_attributes(attributes: &HashMap<String, Value>) -> Result<(), Error> {
+    if attributes.len() > MAX_ATTRIBUTES {
+        return Err(Error::ClientError("too many attributes"));
+    }
+    for (key, value) in attributes {
+        validate_attribute_key(key)?;
+        match value {
+            Value::String(s) if s.chars().count() > MAX_ATTRIBUTE_VALUE_LENGTH => {
+                return Err(Error::ClientError("attribute value is too long"))
+            }
+            Value::String(_) | Value::Number(_) | Value::Bool(_) => {}
+            _ => {
+                return Err(Error::ClientError(
+                    "attribute values must be strings, numbers or booleans",
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validate a custom attribute key
+fn validate_attribute_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() || key.len() > MAX_ATTRIBUTE_KEY_LENGTH {
+        return Err(Error::ClientError("invalid attribute key length"));
+    }
+    if !key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(Error::ClientError("invalid character in attribute key"));
+    }
+    Ok(())
+}
+
+/// Count the products in the store
+pub async fn get_product_count(store: &dyn StoreCount) -> Result<usize, Error> {
+    store.count().await
+}
+
+/// Check that the store is reachable
+pub async fn check_health(store: &dyn StoreHealth) -> Result<(), Error> {
+    store.ping().await
+}
+
+/// Maximum number of parallel segments used by `export_products`
+pub const MAX_EXPORT_SEGMENTS: usize = 16;
+
+/// Retrieve every product in the store for bulk export
+///
+/// The store can split the work into `total_segments` parallel scans, which
+/// must be between 1 and `MAX_EXPORT_SEGMENTS`.
+pub async fn export_products(
+    store: &dyn StoreScanAll,
+    total_segments: usize,
+) -> Result<Vec<Product>, Error> {
+    if total_segments == 0 || total_segments > MAX_EXPORT_SEGMENTS {
+        return Err(Error::ClientError("total_segments is out of range"));
+    }
+
+    store.scan_all(total_segments).await
+}
+
+/// Number of parallel segments used to back up products
+pub const BACKUP_SEGMENTS: usize = 4;
+
+/// Export all products to S3 as JSON lines
+///
+/// Returns the number of exported products.
+pub async fn export_products_to_s3(
+    store: &dyn StoreScanAll,
+    objects: &dyn ObjectStore,
+    key: &str,
+) -> Result<usize, Error> {
+    let products = export_products(store, BACKUP_SEGMENTS).await?;
+
+    let mut body = Vec::new();
+    for product in &products {
+        serde_json::to_writer(&mut body, product)
+            .map_err(|_| Error::InternalError("Unable to serialize product"))?;
+        body.push(b'\n');
+    }
+    objects.put_object(key, body).await?;
+
+    Ok(products.len())
+}
+
+/// Import products from a JSON lines file in S3
+///
+/// Existing products with the same id are replaced. The whole file is
+/// parsed before writing anything, so that an invalid file doesn't result in
+/// a partial import. Returns the number of imported products.
+pub async fn import_products_from_s3(
+    store: &dyn StorePutMany,
+    objects: &dyn ObjectStore,
+    key: &str,
+) -> Result<usize, Error> {
+    let body = objects.get_object(key).await?;
+    let products = parse_json_lines(&body)
+        .into_iter()
+        .map(|(_, product)| product)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    store.put_many(&products).await?;
+    Ok(products.len())
+}
+
+/// Parse the products of a JSON lines file, with their line number
+///
+/// Blank lines are skipped, and each line is parsed on its own, so that a
+/// preview can report every invalid line.
+fn parse_json_lines(body: &[u8]) -> Vec<(usize, Result<Product, Error>)> {
+    body.split(|b| *b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(index, line)| {
+            let product = serde_json::from_slice::<Product>(line)
+                .map_err(|_| Error::ClientError("Invalid product in backup file"));
+            (index + 1, product)
+        })
+        .collect()
+}
+
+/// Import products from a native DynamoDB export to S3
+///
+/// `manifest_key` is the key of the `manifest-files.json` file of the export,
+/// which must be in the same bucket as its data files. As with
+/// `import_products_from_s3`, all data files are parsed before writing
+/// anything. Returns the number of imported products.
+pub async fn import_products_from_dynamodb_export(
+    store: &dyn StorePutMany,
+    objects: &dyn ObjectStore,
+    manifest_key: &str,
+) -> Result<usize, Error> {
+    let manifest = objects.get_object(manifest_key).await?;
+    let mut products = Vec::new();
+    for key in parse_export_manifest(&manifest)? {
+        let body = objects.get_object(&key).await?;
+        products.extend(parse_export_data(&body)?);
+    }
+
+    store.put_many(&products).await?;
+    Ok(products.len())
+}
+
+/// What an import would do with a row
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ImportOutcome {
+    /// The row couldn't be parsed, or the product is invalid
+    Invalid { error: String },
+    /// A later row has the same id, and would replace this one
+    Duplicate { replaced_by: usize },
+    /// There is no product with that id in the store
+    Create,
+    /// The product in the store would change, with `store` the current
+    /// value of each field and `source` the imported one
+    Update { fields: Vec<FieldDiff> },
+    /// The product in the store is the same
+    Unchanged,
+}
+
+/// Dry run report for a row of an import
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImportRow {
+    /// Line of a JSON lines file, or position of the product in a DynamoDB
+    /// export, starting from 1
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub outcome: ImportOutcome,
+}
+
+/// Number of existing products fetched at once by an import dry run
+pub const PREVIEW_BATCH_SIZE: usize = 100;
+
+/// Preview the import of a JSON lines file in S3, without writing anything
+///
+/// Returns a report for each row of the file, in order. Unlike an import,
+/// invalid rows don't stop the preview.
+pub async fn preview_import_from_s3(
+    store: &dyn StoreBatchGet,
+    objects: &dyn ObjectStore,
+    key: &str,
+) -> Result<Vec<ImportRow>, Error> {
+    let body = objects.get_object(key).await?;
+    preview_import(store, parse_json_lines(&body)).await
+}
+
+/// Preview the import of a native DynamoDB export, without writing anything
+///
+/// Data files are parsed as for `import_products_from_dynamodb_export`, so
+/// an invalid file fails the preview, but each product is then validated on
+/// its own.
+pub async fn preview_import_from_dynamodb_export(
+    store: &dyn StoreBatchGet,
+    objects: &dyn ObjectStore,
+    manifest_key: &str,
+) -> Result<Vec<ImportRow>, Error> {
+    let manifest = objects.get_object(manifest_key).await?;
+    let mut products = Vec::new();
+    for key in parse_export_manifest(&manifest)? {
+        let body = objects.get_object(&key).await?;
+        products.extend(parse_export_data(&body)?);
+    }
+
+    let rows = products
+        .into_iter()
+        .enumerate()
+        .map(|(index, product)| (index + 1, Ok(product)))
+        .collect();
+    preview_import(store, rows).await
+}
+
+/// Validate the rows of an import, detect duplicate ids and compare the
+/// products with the ones in the store
+///
+/// Products are validated as if they were sent to the API. As with
+/// `StorePutMany`, the last row with an id wins, so earlier rows with the
+/// same id are reported as duplicates.
+async fn preview_import(
+    store: &dyn StoreBatchGet,
+    rows: Vec<(usize, Result<Product, Error>)>,
+) -> Result<Vec<ImportRow>, Error> {
+    // Keep the ids of invalid products, for the report
+    let rows: Vec<(usize, Option<String>, Result<Product, Error>)> = rows
+        .into_iter()
+        .map(|(row, product)| match product {
+            Ok(product) => (
+                row,
+                Some(product.id.clone()),
+                commands::validate_product(&product).map(|_| product),
+            ),
+            Err(err) => (row, None, Err(err)),
+        })
+        .collect();
+
+    // Last row of each id
+    let mut last_rows: HashMap<&str, usize> = HashMap::new();
+    for (row, _, product) in &rows {
+        if let Ok(product) = product {
+            last_rows.insert(&product.id, *row);
+        }
+    }
+
+    let ids: Vec<String> = last_rows.keys().map(|id| id.to_string()).collect();
+    let mut existing = HashMap::new();
+    for chunk in ids.chunks(PREVIEW_BATCH_SIZE) {
+        for product in store.get_many(chunk).await? {
+            existing.insert(product.id.clone(), product);
+        }
+    }
+
+    Ok(rows
+        .iter()
+        .map(|(row, id, product)| {
+            let product = match product {
+                Ok(product) => product,
+                Err(err) => {
+                    return ImportRow {
+                        row: *row,
+                        id: id.clone(),
+                        outcome: ImportOutcome::Invalid {
+                            error: match err {
+                                Error::ClientError(msg) => msg.to_string(),
+                                err => err.to_string(),
+                            },
+                        },
+                    }
+                }
+            };
+            let outcome = match (last_rows[product.id.as_str()], existing.get(&product.id)) {
+                (last, _) if last != *row => ImportOutcome::Duplicate { replaced_by: last },
+                (_, None) => ImportOutcome::Create,
+                (_, Some(current)) => {
+                    let fields = diff_products(current, product);
+                    if fields.is_empty() {
+                        ImportOutcome::Unchanged
+                    } else {
+                        ImportOutcome::Update { fields }
+                    }
+                }
+            };
+            ImportRow {
+                row: *row,
+                id: Some(product.id.clone()),
+                outcome,
+            }
+        })
+        .collect())
+}
+
+pub async fn get_product(
+    store: &dyn StoreGet,
+    id: &str,
+    consistency: ReadConsistency,
+) -> Result<Option<Product>, Error> {
+    store.get(id, consistency, None).await
+}
+
+/// Retrieve a product with the details of its audit log
+pub async fn get_admin_product(
+    store: &dyn StoreGet,
+    audit: &dyn StoreGetAudit,
+    id: &str,
+    consistency: ReadConsistency,
+) -> Result<Option<AdminProduct>, Error> {
+    let product = match store.get(id, consistency, None).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+    // Entries are sorted from the newest to the oldest
+    let entries = audit.entries(id).await?;
+
+    Ok(Some(AdminProduct {
+        product,
+        version: entries.len(),
+        created_at: entries.last().map(|entry| entry.timestamp),
+        updated_at: entries.first().map(|entry| entry.timestamp),
+        updated_by: entries.first().and_then(|entry| entry.caller.clone()),
+    }))
+}
+
+/// Compute the price of a product in a country, including tax
+///
+/// The country is an ISO 3166-1 alpha-2 code, in any case. Tax amounts are
+/// rounded to the nearest cent.
+pub async fn get_product_price(
+    store: &dyn StoreGet,
+    tax: &dyn TaxCalculator,
+    id: &str,
+    country: &str,
+) -> Result<Option<PriceBreakdown>, Error> {
+    if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(Error::ClientError(
+            "country must be a two-letter country code",
+        ));
+    }
+    let country = country.to_ascii_uppercase();
+
+    let product = match store.get(id, ReadConsistency::Eventual, None).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+
+    let rate = tax.rate(&country).await?;
+    let net = product.price;
+    let tax = (net * rate * 100.0).round() / 100.0;
+
+    Ok(Some(PriceBreakdown {
+        id: product.id,
+        country,
+        net,
+        tax,
+        gross: net + tax,
+        rate,
+    }))
+}
+
+/// Maximum number of images of a product
+pub const MAX_IMAGES: usize = 10;
+
+/// Add an image to a product
+///
+/// The key of the new image is recorded on the product right away, and a
+/// presigned URL to upload the image is returned. The content type must be
+/// an image type, e.g. `image/png`. Returns `None` if the product doesn't
+/// exist.
+pub async fn add_product_image(
+    store: &dyn StoreGet,
+    put: &dyn StorePut,
+    history: &dyn StoreAppendHistory,
+    images: &dyn ImageStore,
+    id: &str,
+    content_type: &str,
+) -> Result<Option<ProductImage>, Error> {
+    if !content_type.starts_with("image/") {
+        return Err(Error::ClientError("content_type must be an image type"));
+    }
+
+    let mut product = match store.get(id, ReadConsistency::Strong, None).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+    if product.images.len() >= MAX_IMAGES {
+        return Err(Error::ClientError("too many images"));
+    }
+
+    let key = new_image_key(id)?;
+    let url = images.upload_url(&key, content_type).await?;
+    product.images.push(key.clone());
+    put_product(put, history, &product).await?;
+
+    Ok(Some(ProductImage { key, url }))
+}
+
+/// Retrieve the images of a product with presigned download URLs
+///
+/// Returns `None` if the product doesn't exist.
+pub async fn get_product_images(
+    store: &dyn StoreGet,
+    images: &dyn ImageStore,
+    id: &str,
+) -> Result<Option<Vec<ProductImage>>, Error> {
+    let product = match store.get(id, ReadConsistency::Eventual, None).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+
+    let mut urls = Vec::with_capacity(product.images.len());
+    for key in product.images {
+        let url = images.download_url(&key).await?;
+        urls.push(ProductImage { key, url });
+    }
+
+    Ok(Some(urls))
+}
+
+/// Record a product that was just written as a new revision
+///
+/// The write time is used as the version. Recording the revision is
+/// best-effort: the product is already written, so a failure is logged
+/// rather than returned, as the client would otherwise retry a write that
+/// succeeded. The history then misses that revision.
+async fn append_revision(history: &dyn StoreAppendHistory, product: &Product) {
+    let res = match now_millis() {
+        Ok(version) => {
+            history
+                .append(&ProductRevision {
+                    version,
+                    product: product.clone(),
+                })
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        error!(
+            "Failed to record revision of product {}: {}",
+            product.id, err
+        );
+    }
+}
+
+/// Create or replace a product
+///
+/// Every write is also recorded as a new revision in the history store, see
+/// `append_revision`.
+/// Returns a `Created` event if there was no product with that id, or an
+/// `Updated` event with the replaced product otherwise, so that callers can
+/// publish it without waiting for the DynamoDB stream. Stores configured with
+/// an outbox write the same event to it, in the same transaction as the
+/// product.
+pub async fn put_product(
+    store: &dyn StorePut,
+    history: &dyn StoreAppendHistory,
+    product: &Product,
+) -> Result<Event, Error> {
+    // Round price to 2 decimal digits
+    let mut product = product.clone();
+    product.price = (product.price * 100.0).round() / 100.0;
+
+    let previous = store.put(&product).await?;
+
+    append_revision(history, &product).await;
+
+    Ok(match previous {
+        Some(old) => Event::Updated { old, new: product },
+        None => Event::Created { product },
+    })
+}
+
+/// Create a product only if it doesn't exist
+///
+/// Returns the `Created` event, or `None` without writing anything if there
+/// is already a product with that id. The price is rounded and the revision
+/// recorded as with `put_product`.
+pub async fn create_product(
+    store: &dyn StorePutConditional,
+    history: &dyn StoreAppendHistory,
+    product: &Product,
+) -> Result<Option<Event>, Error> {
+    let mut product = product.clone();
+    product.price = (product.price * 100.0).round() / 100.0;
+
+    if !store.put_if_absent(&product).await? {
+        return Ok(None);
+    }
+
+    append_revision(history, &product).await;
+
+    Ok(Some(Event::Created { product }))
+}
+
+/// Replace a product only if it is at the expected version
+///
+/// Returns the `Updated` event, or `None` without writing anything if there
+/// is no product with that id or it is at another version. The price is
+/// rounded and the revision recorded as with `put_product`.
+pub async fn put_product_if_version(
+    store: &dyn StorePutConditional,
+    history: &dyn StoreAppendHistory,
+    product: &Product,
+    version: &str,
+) -> Result<Option<Event>, Error> {
+    let mut product = product.clone();
+    product.price = (product.price * 100.0).round() / 100.0;
+
+    let old = match store.put_if_version(&product, version).await? {
+        Some(old) => old,
+        None => return Ok(None),
+    };
+
+    append_revision(history, &product).await;
+
+    Ok(Some(Event::Updated { old, new: product }))
+}
+
+/// Partially update a product only if it is at the expected version
+///
+/// Returns the updated product, or `None` without writing anything if there
+/// is no product with that id or it is at another version. The price is
+/// rounded and the revision recorded as with `update_product`.
+pub async fn update_product_if_version(
+    store: &dyn StorePutConditional,
+    history: &dyn StoreAppendHistory,
+    id: &str,
+    patch: &ProductPatch,
+    version: &str,
+) -> Result<Option<Product>, Error> {
+    let mut patch = patch.clone();
+    patch.price = patch.price.map(|price| (price * 100.0).round() / 100.0);
+
+    let product = match store.update_if_version(id, &patch, version).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+
+    append_revision(history, &product).await;
+    Ok(Some(product))
+}
+
+/// Partially update a product
+///
+/// Returns the updated product, or `None` if it doesn't exist. The price is
+/// rounded as with `put_product`, and the result is recorded as a new
+/// revision.
+pub async fn update_product(
+    store: &dyn StoreUpdate,
+    history: &dyn StoreAppendHistory,
+    id: &str,
+    patch: &ProductPatch,
+) -> Result<Option<Product>, Error> {
+    let mut patch = patch.clone();
+    patch.price = patch.price.map(|price| (price * 100.0).round() / 100.0);
+
+    let product = match store.update(id, &patch).await? {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+
+    append_revision(history, &product).await;
+    Ok(Some(product))
+}
+
+/// Maximum number of products returned by `get_related_products`
+pub const MAX_RELATED_PRODUCTS: usize = 10;
+
+/// Retrieve the products related to a product
+///
+/// Related products are returned from the most to the least relevant.
+/// Recommended products that no longer exist are skipped.
+pub async fn get_related_products(
+    recommendations: &dyn Recommendations,
+    store: &dyn StoreBatchGet,
+    id: &str,
+) -> Result<Vec<Product>, Error> {
+    let ids = recommendations.related(id, MAX_RELATED_PRODUCTS).await?;
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Restore the order of the recommendations
+    let mut products = store.get_many(&ids).await?;
+    products.sort_by_key(|p| ids.iter().position(|id| id == &p.id));
+
+    Ok(products)
+}
+
+/// Number of products kept in the list of popular products
+pub const MAX_POPULAR_PRODUCTS: usize = 10;
+
+/// Count a read of a product
+pub async fn record_hit(hits: &dyn StoreRecordHit, id: &str) -> Result<(), Error> {
+    hits.record_hit(id).await
+}
+
+/// Recompute the list of popular products from the hit counters
+///
+/// Returns the number of products in the list.
+pub async fn materialize_popular_products(
+    hits: &dyn StoreGetHits,
+    popular: &dyn StorePutPopular,
+) -> Result<usize, Error> {
+    let mut products: Vec<ProductHits> = hits.hits().await?;
+    // Break ties on the id, so that the list is stable between runs
+    products.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.id.cmp(&b.id)));
+    products.truncate(MAX_POPULAR_PRODUCTS);
+
+    popular.put_popular(&products).await?;
+    Ok(products.len())
+}
+
+/// Retrieve the most popular products
+///
+/// Products are returned from the most to the least popular, as of the last
+/// time the list was materialized. Products that no longer exist are skipped.
+pub async fn get_popular_products(
+    popular: &dyn StoreGetPopular,
+    store: &dyn StoreBatchGet,
+) -> Result<Vec<Product>, Error> {
+    let ids: Vec<String> = popular.popular().await?.into_iter().map(|p| p.id).collect();
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Restore the order of the list
+    let mut products = store.get_many(&ids).await?;
+    products.sort_by_key(|p| ids.iter().position(|id| id == &p.id));
+
+    Ok(products)
+}
+
+/// Retrieve the audit log of a product, newest first
+pub async fn get_product_audit(
+    audit: &dyn StoreGetAudit,
+    id: &str,
+) -> Result<Vec<AuditEntry>, Error> {
+    audit.entries(id).await
+}
+
+/// Retrieve the past versions of a product, newest first
+pub async fn get_product_history(
+    history: &dyn StoreGetHistory,
+    id: &str,
+) -> Result<Vec<ProductRevision>, Error> {
+    history.history(id).await
+}
+
+/// Number of products returned by `search_products` when no limit is provided
+pub const DEFAULT_SEARCH_SIZE: usize = 20;
+
+/// Maximum number of products returned by `search_products`
+pub const MAX_SEARCH_SIZE: usize = 100;
+
+/// Maximum length of a search query
+pub const MAX_SEARCH_QUERY_LENGTH: usize = 256;
+
+/// Search products by name, from the most to the least relevant
+pub async fn search_products(
+    index: &dyn StoreSearch,
+    query: &str,
+    limit: Option<usize>,
+) -> Result<Vec<Product>, Error> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(Error::ClientError("query must not be empty"));
+    }
+    if query.chars().count() > MAX_SEARCH_QUERY_LENGTH {
+        return Err(Error::ClientError("query must be at most 256 characters"));
+    }
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_SIZE);
+    if limit == 0 || limit > MAX_SEARCH_SIZE {
+        return Err(Error::ClientError("limit must be between 1 and 100"));
+    }
+    index.search(query, limit).await
+}
+
+/// Apply a batch of events to the search index
+///
+/// Only the last event of each product is applied, so that a product
+/// created then deleted in the same batch isn't indexed.
+pub async fn index_events(index: &dyn StoreIndex, events: &[Event]) -> Result<(), Error> {
+    let mut latest: HashMap<&str, &Event> = HashMap::new();
+    for event in events {
+        // Reports don't change any product
+        if let Event::ArchiveCompleted { .. } = event {
+            continue;
+        }
+        latest.insert(event.id(), event);
+    }
+
+    let mut products = Vec::new();
+    let mut removed = Vec::new();
+    for event in latest.into_values() {
+        match event {
+            Event::Created { product } => products.push(product.clone()),
+            Event::Updated { new, .. } => products.push(new.clone()),
+            Event::Archived { product } => products.push(product.clone()),
+            Event::Deleted { product } | Event::DeletionScheduled { product, .. } => {
+                removed.push(product.id.clone())
+            }
+            Event::ArchiveCompleted { .. } => (),
+        }
+    }
+
+    index.index(&products).await?;
+    index.remove(&removed).await
+}
+
+/// Maximum time range of `get_price_history`, in milliseconds (366 days)
+pub const MAX_PRICE_HISTORY_RANGE: u64 = 366 * 24 * 60 * 60 * 1000;
+
+/// Record the price changes in a batch of events
+///
+/// `events` are pairs of times, in milliseconds since the UNIX epoch, and
+/// events. Only updates changing the price are recorded. Returns the number
+/// of recorded price changes.
+pub async fn record_price_changes(
+    store: &dyn StoreRecordPrices,
+    events: &[(u64, Event)],
+) -> Result<usize, Error> {
+    let prices: Vec<(String, PricePoint)> = events
+        .iter()
+        .filter_map(|(time, event)| match event {
+            Event::Updated { old, new } if old.price != new.price => Some((
+                new.id.clone(),
+                PricePoint {
+                    time: *time,
+                    price: new.price,
+                },
+            )),
+            _ => None,
+        })
+        .collect();
+
+    if !prices.is_empty() {
+        store.record_prices(&prices).await?;
+    }
+    Ok(prices.len())
+}
+
+/// Retrieve the price history of a product in a time range, oldest first
+///
+/// The range is in milliseconds since the UNIX epoch, and can't span more
+/// than `MAX_PRICE_HISTORY_RANGE`.
+pub async fn get_price_history(
+    store: &dyn StoreGetPriceHistory,
+    id: &str,
+    range: Range<u64>,
+) -> Result<Vec<PricePoint>, Error> {
+    if range.is_empty() {
+        return Err(Error::ClientError("range must not be empty"));
+    }
+    if range.end - range.start > MAX_PRICE_HISTORY_RANGE {
+        return Err(Error::ClientError("range must be at most 366 days"));
+    }
+    store.price_history(id, range).await
+}
+
+/// Soft delete a product
+///
+/// The product can be brought back with `restore_product`. Returns `false`
+/// if there was no product with that id.
+pub async fn delete_product(store: &dyn StoreDelete, id: &str) -> Result<bool, Error> {
+    store.delete(id).await
+}
+
+/// Soft delete a product and schedule its permanent removal after `delay`
+///
+/// The product can be brought back with `restore_product` until then, which
+/// also cancels the removal. Returns `false` if there was no product with
+/// that id, in which case no removal is scheduled.
+pub async fn schedule_product_deletion(
+    store: &dyn StoreDelete,
+    expire: &dyn StoreExpire,
+    id: &str,
+    delay: Duration,
+) -> Result<bool, Error> {
+    if !store.delete(id).await? {
+        return Ok(false);
+    }
+    let at = now_millis()? / 1000 + delay.as_secs();
+    expire.set_expiry(id, at).await?;
+    Ok(true)
+}
+
+/// Soft delete a product only if it is at the expected version
+///
+/// With a `delay`, its permanent removal is scheduled as with
+/// `schedule_product_deletion`. Returns `false` without deleting anything if
+/// there is no product with that id or it is at another version.
+pub async fn delete_product_if_version(
+    store: &dyn StorePutConditional,
+    expire: &dyn StoreExpire,
+    id: &str,
+    version: &str,
+    delay: Option<Duration>,
+) -> Result<bool, Error> {
+    if !store.delete_if_version(id, version).await? {
+        return Ok(false);
+    }
+    if let Some(delay) = delay {
+        let at = now_millis()? / 1000 + delay.as_secs();
+        expire.set_expiry(id, at).await?;
+    }
+    Ok(true)
+}
+
+/// Permanently delete a product
+///
+/// Returns `false` if there was no product with that id, including
+/// soft-deleted ones.
+pub async fn hard_delete_product(store: &dyn StoreDelete, id: &str) -> Result<bool, Error> {
+    store.hard_delete(id).await
+}
+
+/// Restore a soft-deleted product
+///
+/// Returns `false` if there was no deleted product with that id.
+pub async fn restore_product(store: &dyn StoreRestore, id: &str) -> Result<bool, Error> {
+    store.restore(id).await
+}
+
+/// Attribute holding the lifecycle status of a product
+pub const STATUS_ATTRIBUTE: &str = "status";
+
+/// Status of discontinued products
+pub const ARCHIVED_STATUS: &str = "archived";
+
+/// Number of products listed and written at once by `archive_category`
+const ARCHIVE_PAGE_SIZE: usize = 100;
+
+/// Archive all the products of a category
+///
+/// Products with that `category` attribute are listed page by page. The
+/// products of each page that aren't archived yet get their `status`
+/// attribute set to `archived`, are written together and recorded as new
+/// revisions, then an `Archived` event is published for each of them, in
+/// listing order. Once every page is done, an `ArchiveCompleted` event
+/// reports the number of archived products, which is also returned.
+///
+/// Products that are already archived are skipped, so that an interrupted
+/// run can be started again.
+pub async fn archive_category(
+    store: &dyn StoreGetAll,
+    writer: &dyn StorePutMany,
+    history: &dyn StoreAppendHistory,
+    event_bus: &dyn EventBus<E = Event>,
+    category: &str,
+) -> Result<usize, Error> {
+    if category.is_empty() {
+        return Err(Error::ClientError("category must not be empty"));
+    }
+    let filter = ProductFilter {
+        attributes: vec![("category".to_string(), category.to_string())],
+        ..Default::default()
+    };
+
+    let mut archived = 0;
+    let mut next: Option<String> = None;
+    loop {
+        let range = store
+            .all(next.as_deref(), ARCHIVE_PAGE_SIZE, &filter, None)
+            .await?;
+        let products: Vec<Product> = range
+            .products
+            .into_iter()
+            .filter(|product| {
+                product.attributes.get(STATUS_ATTRIBUTE) != Some(&Value::from(ARCHIVED_STATUS))
+            })
+            .map(|mut product| {
+                product
+                    .attributes
+                    .insert(STATUS_ATTRIBUTE.to_string(), ARCHIVED_STATUS.into());
+                product
+            })
+            .collect();
+
+        if !products.is_empty() {
+            writer.put_many(&products).await?;
+            for product in &products {
+                append_revision(history, product).await;
+            }
+            let events: Vec<Event> = products
+                .into_iter()
+                .map(|product| Event::Archived { product })
+                .collect();
+            event_bus.send_events(&events).await?;
+            archived += events.len();
+        }
+
+        next = range.next;
+        if next.is_none() {
+            break;
+        }
+    }
+
+    event_bus
+        .send_event(&Event::ArchiveCompleted {
+            category: category.to_string(),
+            archived,
+        })
+        .await?;
+    event_bus.flush().await?;
+    Ok(archived)
+}
+
+/// Maximum number of changes returned by `get_changes`
+pub const MAX_CHANGES: usize = 100;
+
+/// Maximum time `get_changes` can wait for new changes
+pub const MAX_CHANGES_WAIT: Duration = Duration::from_secs(20);
+
+/// Interval between two polls of the change store
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Retrieve changes since a token
+///
+/// If there are no new changes, this polls the store for up to `wait` before
+/// returning an empty range. The returned token should be passed as `since`
+/// on the next call.
+pub async fn get_changes(
+    store: &dyn StoreGetChanges,
+    since: Option<&str>,
+    wait: Duration,
+) -> Result<ChangeRange, Error> {
+    if wait > MAX_CHANGES_WAIT {
+        return Err(Error::ClientError("wait must be at most 20 seconds"));
+    }
+
+    let deadline = Instant::now() + wait;
+    loop {
+        let changes = store.since(since, MAX_CHANGES).await?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !changes.is_empty() || remaining.is_zero() {
+            // Keep the same token if there are no new changes
+            let token = changes
+                .last()
+                .map(|c| c.sequence.clone())
+                .or_else(|| since.map(|s| s.to_string()));
+            return Ok(ChangeRange { changes, token });
+        }
+
+        tokio::time::sleep(CHANGES_POLL_INTERVAL.min(remaining)).await;
+    }
+}
+
+pub async fn send_events(
+    event_bus: &dyn EventBus<E = Event>,
+    events: &[Event],
+) -> Result<(), Error> {
+    event_bus.send_events(events).await
+}
+
+/// Event types webhook subscribers can filter on
+pub const WEBHOOK_EVENT_TYPES: [&str; 6] = [
+    "Created",
+    "Updated",
+    "Deleted",
+    "DeletionScheduled",
+    "Archived",
+    "ArchiveCompleted",
+];
+
+/// Retrieve all webhook subscriptions
+pub async fn get_webhooks(
+    store: &dyn StoreListWebhooks,
+) -> Result<Vec<WebhookSubscription>, Error> {
+    store.webhooks().await
+}
+
+/// Retrieve a single webhook subscription
+pub async fn get_webhook(
+    store: &dyn StoreGetWebhook,
+    id: &str,
+) -> Result<Option<WebhookSubscription>, Error> {
+    store.webhook(id).await
+}
+
+/// Create or replace a webhook subscription
+///
+/// Subscribers must use HTTPS and a non-empty secret, and can only filter on
+/// known event types.
+pub async fn put_webhook(
+    store: &dyn StorePutWebhook,
+    subscription: &WebhookSubscription,
+) -> Result<(), Error> {
+    if subscription.id.is_empty() {
+        return Err(Error::ClientError("id must not be empty"));
+    }
+    if !subscription.url.starts_with("https://") {
+        return Err(Error::ClientError("url must use https"));
+    }
+    if subscription.secret.is_empty() {
+        return Err(Error::ClientError("secret must not be empty"));
+    }
+    if subscription
+        .event_types
+        .iter()
+        .any(|t| !WEBHOOK_EVENT_TYPES.contains(&t.as_str()))
+    {
+        return Err(Error::ClientError("unknown event type"));
+    }
+
+    store.put_webhook(subscription).await
+}
+
+/// Delete a webhook subscription
+pub async fn delete_webhook(store: &dyn StoreDeleteWebhook, id: &str) -> Result<(), Error> {
+    store.delete_webhook(id).await
+}
+
+/// Current time in milliseconds since the UNIX epoch
+fn now_millis() -> Result<u64, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::ProgrammableBus,
+        images::MemoryImageStore,
+        object_store::MemoryObjectStore,
+        recommendations::StaticRecommendations,
+        store::{
+            MemoryChangeStore, MemoryHistoryStore, MemoryIndex, MemoryPopularityStore,
+            MemoryPriceHistory, MemoryStore, MemoryWebhookStore, StoreAppendChanges, StorePut,
+        },
+        tax::StaticTaxCalculator,
+        Change,
+    };
+    use async_trait::async_trait;
+
+    fn get_change(sequence: &str) -> Change {
+        Change {
+            sequence: sequence.to_string(),
+            event: Event::Deleted {
+                product: Product {
+                    id: "1".to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_validate_attributes() {
+        // GIVEN valid attributes
+        let mut attributes = HashMap::from([
+            ("color".to_string(), serde_json::json!("red")),
+            ("weight_kg".to_string(), serde_json::json!(1.5)),
+            ("in-stock".to_string(), serde_json::json!(true)),
+        ]);
+
+        // THEN they are accepted
+        assert!(validate_attributes(&attributes).is_ok());
+
+        // WHEN an attribute key contains an invalid character
+        attributes.insert("size.eu".to_string(), serde_json::json!(42));
+
+        // THEN the attributes are rejected
+        assert!(matches!(
+            validate_attributes(&attributes),
+            Err(Error::ClientError(_))
+        ));
+
+        // WHEN a string value is too long
+        attributes.remove("size.eu");
+        attributes.insert(
+            "description".to_string(),
+            serde_json::json!("a".repeat(MAX_ATTRIBUTE_VALUE_LENGTH + 1)),
+        );
+
+        // THEN the attributes are rejected
+        assert!(matches!(
+            validate_attributes(&attributes),
+            Err(Error::ClientError(_))
+        ));
+    }
+
+    /// History store failing every append
+    struct FailingHistory;
+
+    #[async_trait]
+    impl StoreAppendHistory for FailingHistory {
+        async fn append(&self, _: &ProductRevision) -> Result<(), Error> {
+            Err(Error::InternalError("history unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_product_history_failure() -> Result<(), Error> {
+        // GIVEN a store and a failing history store
+        let store = MemoryStore::new();
+        let product = Product {
+            id: "1".to_string(),
+            name: "foo".to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+
+        // WHEN putting a product
+        let event = put_product(&store, &FailingHistory, &product).await?;
+
+        // THEN the write succeeds despite the missing revision
+        assert_eq!(event, Event::Created { product });
+        assert!(store
+            .get("1", ReadConsistency::Eventual, None)
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_products_too_many_attribute_filters() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+
+        // WHEN filtering on too many attributes
+        let filter = ProductFilter {
+            attributes: (0..=MAX_ATTRIBUTE_FILTERS)
+                .map(|i| (format!("key{}", i), "value".to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let res = get_products(&store, None, None, &filter, None).await;
+
+        // THEN the request is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_find_products_limit() -> Result<(), Error> {
+        // GIVEN a store with more products than the maximum page size
+        let store = MemoryStore::new();
+        for i in 0..=MAX_PAGE_SIZE {
+            store
+                .put(&Product {
+                    id: format!("{:03}", i),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+
+        // WHEN querying with a larger limit
+        let res = find_products(&store, &Query::new().limit(MAX_PAGE_SIZE + 10)).await?;
+
+        // THEN the page is capped
+        assert_eq!(res.products.len(), MAX_PAGE_SIZE);
+        // AND an empty limit is rejected
+        let res = find_products(&store, &Query::new().limit(0)).await;
+        assert!(matches!(res, Err(Error::ClientError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_changes() -> Result<(), Error> {
+        // GIVEN a change store with two changes
+        let store = MemoryChangeStore::new();
+        store
+            .append(&[get_change("001"), get_change("002")])
+            .await?;
+
+        // WHEN getting changes since the first one
+        let res = get_changes(&store, Some("001"), Duration::ZERO).await?;
+
+        // THEN we get the second change
+        assert_eq!(res.changes, vec![get_change("002")]);
+        // AND the token points to the last change
+        assert_eq!(res.token, Some("002".to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_changes_wait() -> Result<(), Error> {
+        // GIVEN an empty change store
+        let store = MemoryChangeStore::new();
+
+        // WHEN waiting for changes
+        let start = Instant::now();
+        let res = get_changes(&store, Some("001"), Duration::from_millis(50)).await?;
+
+        // THEN no changes are returned after the wait
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(res.changes.is_empty());
+        // AND the token is unchanged
+        assert_eq!(res.token, Some("001".to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_changes_wait_too_long() {
+        let store = MemoryChangeStore::new();
+
+        let res = get_changes(&store, None, Duration::from_secs(60)).await;
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    fn get_subscription() -> WebhookSubscription {
+        WebhookSubscription {
+            id: "1".to_string(),
+            url: "https://example.com/hook".to_string(),
+            event_types: vec!["Created".to_string()],
+            secret: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_webhook_insecure_url() {
+        // GIVEN a subscription with a plain HTTP url
+        let store = MemoryWebhookStore::new();
+        let mut subscription = get_subscription();
+        subscription.url = "http://example.com/hook".to_string();
+
+        // WHEN putting the subscription
+        let res = put_webhook(&store, &subscription).await;
+
+        // THEN it is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_webhook_unknown_event_type() {
+        // GIVEN a subscription filtering on an unknown event type
+        let store = MemoryWebhookStore::new();
+        let mut subscription = get_subscription();
+        subscription.event_types = vec!["Renamed".to_string()];
+
+        // WHEN putting the subscription
+        let res = put_webhook(&store, &subscription).await;
+
+        // THEN it is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_webhook() -> Result<(), Error> {
+        // GIVEN a valid subscription
+        let store = MemoryWebhookStore::new();
+
+        // WHEN putting the subscription
+        put_webhook(&store, &get_subscription()).await?;
+
+        // THEN it is stored
+        assert_eq!(get_webhook(&store, "1").await?, Some(get_subscription()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_products_by_ids() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let store = MemoryStore::new();
+        for id in ["1", "2"] {
+            store
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+        let ids: Vec<String> = ["2", "3", "1", "2"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        // WHEN getting products by id, with a missing and a duplicate id
+        let batch = get_products_by_ids(&store, &ids, Duration::from_secs(1)).await?;
+
+        // THEN the existing products are returned once, in the order of the ids
+        assert_eq!(
+            batch.products.into_iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec!["2", "1"]
+        );
+        // AND the batch is complete
+        assert!(!batch.partial);
+
+        // WHEN getting too many products
+        let ids = vec!["1".to_string(); MAX_BATCH_GET_IDS + 1];
+        let res = get_products_by_ids(&store, &ids, Duration::from_secs(1)).await;
+
+        // THEN the request is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_related_products() -> Result<(), Error> {
+        // GIVEN two related products, one of which no longer exists
+        let store = MemoryStore::new();
+        for id in ["1", "3"] {
+            store
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+        let recommendations = StaticRecommendations::new(
+            [(
+                "books".to_string(),
+                vec!["1".to_string(), "3".to_string(), "2".to_string()],
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        // WHEN getting the products related to the first product
+        let related = get_related_products(&recommendations, &store, "1").await?;
+
+        // THEN only the existing product is returned
+        assert_eq!(
+            related.into_iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec!["3"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_import_s3() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let source = MemoryStore::new();
+        for id in ["1", "2"] {
+            source
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+        let objects = MemoryObjectStore::new();
+
+        // WHEN exporting the products and importing them in another store
+        let exported = export_products_to_s3(&source, &objects, "backup.jsonl").await?;
+        let target = MemoryStore::new();
+        let imported = import_products_from_s3(&target, &objects, "backup.jsonl").await?;
+
+        // THEN all products are copied
+        assert_eq!(exported, 2);
+        assert_eq!(imported, 2);
+        let mut products = target.scan_all(1).await?;
+        products.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(products.len(), 2);
+        assert_eq!(products[0].id, "1");
+        assert_eq!(products[1].id, "2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_invalid_s3() -> Result<(), Error> {
+        // GIVEN a backup file with an invalid line
+        let objects = MemoryObjectStore::new();
+        objects
+            .put_object(
+                "backup.jsonl",
+                br#"{"id": "1", "name": "foo", "price": 10.0}
+not json
+"#
+                .to_vec(),
+            )
+            .await?;
+        let store = MemoryStore::new();
+
+        // WHEN importing the file
+        let res = import_products_from_s3(&store, &objects, "backup.jsonl").await;
+
+        // THEN the import is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+        // AND nothing was written
+        assert_eq!(store.count().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_product_image() -> Result<(), Error> {
+        // GIVEN a product without images
+        let store = MemoryStore::new();
+        store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+        let history = MemoryHistoryStore::new();
+        let images = MemoryImageStore::new();
+
+        // WHEN adding an image to the product
+        let image = add_product_image(&store, &store, &history, &images, "1", "image/png")
+            .await?
+            .unwrap();
+
+        // THEN an upload URL is returned for a new key
+        assert!(image.key.starts_with("products/1/"));
+        assert!(image.url.contains(&image.key));
+        // AND the key is recorded on the product
+        let urls = get_product_images(&store, &images, "1").await?.unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].key, image.key);
+
+        // WHEN adding a file that isn't an image
+        let res = add_product_image(&store, &store, &history, &images, "1", "text/plain").await;
+
+        // THEN the request is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+
+        // WHEN adding an image to a missing product
+        let res = add_product_image(&store, &store, &history, &images, "2", "image/png").await?;
+
+        // THEN nothing is returned
+        assert_eq!(res, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_product_price() -> Result<(), Error> {
+        // GIVEN a product and a 20% tax rate in France
+        let store = MemoryStore::new();
+        store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+        let tax =
+            StaticTaxCalculator::new(std::collections::BTreeMap::from([("FR".to_string(), 0.2)]));
+
+        // WHEN getting the price of the product in France
+        let price = get_product_price(&store, &tax, "1", "fr").await?;
+
+        // THEN the tax is added to the price
+        assert_eq!(
+            price,
+            Some(PriceBreakdown {
+                id: "1".to_string(),
+                country: "FR".to_string(),
+                net: 10.0,
+                tax: 2.0,
+                gross: 12.0,
+                rate: 0.2,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_product_price_invalid_country() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let tax = StaticTaxCalculator::default();
+
+        // WHEN getting a price with an invalid country code
+        let res = get_product_price(&store, &tax, "1", "France").await;
+
+        // THEN the request is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_import_dynamodb_export() -> Result<(), Error> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        // GIVEN a DynamoDB export with one data file
+        let objects = MemoryObjectStore::new();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(br#"{"Item":{"id":{"S":"1"},"name":{"S":"foo"},"price":{"N":"10"}}}"#)
+            .unwrap();
+        objects
+            .put_object("export/data/a.json.gz", encoder.finish().unwrap())
+            .await?;
+        objects
+            .put_object(
+                "export/manifest-files.json",
+                br#"{"itemCount":1,"dataFileS3Key":"export/data/a.json.gz"}"#.to_vec(),
+            )
+            .await?;
+        let store = MemoryStore::new();
+
+        // WHEN importing the export
+        let imported =
+            import_products_from_dynamodb_export(&store, &objects, "export/manifest-files.json")
+                .await?;
+
+        // THEN the product is imported
+        assert_eq!(imported, 1);
+        assert_eq!(
+            store.get("1", ReadConsistency::Eventual, None).await?,
+            Some(Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_popular_products() -> Result<(), Error> {
+        // GIVEN three products with different numbers of hits
+        let store = MemoryStore::new();
+        let hits = MemoryPopularityStore::new();
+        for (id, count) in [("1", 1), ("2", 3), ("3", 2)] {
+            store
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+            for _ in 0..count {
+                record_hit(&hits, id).await?;
+            }
+        }
+
+        // WHEN materializing and getting the popular products
+        let count = materialize_popular_products(&hits, &hits).await?;
+        let products = get_popular_products(&hits, &store).await?;
+
+        // THEN they are sorted by hits
+        assert_eq!(count, 3);
+        assert_eq!(
+            products.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+            vec!["2", "3", "1"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_price_history() -> Result<(), Error> {
+        // GIVEN events changing a product and its price
+        let store = MemoryPriceHistory::new();
+        let product = |price| Product {
+            id: "1".to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+        let mut renamed = product(10.0);
+        renamed.name = "bar".to_string();
+        let events = [
+            (
+                100,
+                Event::Created {
+                    product: product(10.0),
+                },
+            ),
+            (
+                200,
+                Event::Updated {
+                    old: product(10.0),
+                    new: renamed.clone(),
+                },
+            ),
+            (
+                300,
+                Event::Updated {
+                    old: renamed,
+                    new: product(12.0),
+                },
+            ),
+        ];
+
+        // WHEN recording the price changes
+        let count = record_price_changes(&store, &events).await?;
+
+        // THEN only the price change is recorded
+        assert_eq!(count, 1);
+        assert_eq!(
+            get_price_history(&store, "1", 0..1000).await?,
+            vec![PricePoint {
+                time: 300,
+                price: 12.0
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_price_history_invalid_range() {
+        let store = MemoryPriceHistory::new();
+
+        let res = get_price_history(&store, "1", 0..MAX_PRICE_HISTORY_RANGE + 1).await;
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_index_events() -> Result<(), Error> {
+        // GIVEN a product created then deleted, and a product created then updated
+        let index = MemoryIndex::new();
+        let product = |id: &str, name: &str| Product {
+            id: id.to_string(),
+            name: name.to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+        let events = [
+            Event::Created {
+                product: product("1", "Red shoes"),
+            },
+            Event::Created {
+                product: product("2", "Blue shoes"),
+            },
+            Event::Deleted {
+                product: product("1", "Red shoes"),
+            },
+            Event::Updated {
+                old: product("2", "Blue shoes"),
+                new: product("2", "Green shoes"),
+            },
+        ];
+
+        // WHEN applying the events to the index
+        index_events(&index, &events).await?;
+
+        // THEN only the latest version of live products can be found
+        assert_eq!(
+            search_products(&index, "shoes", None).await?,
+            vec![product("2", "Green shoes")]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_products_empty_query() {
+        let index = MemoryIndex::new();
+
+        let res = search_products(&index, "  ", None).await;
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_archive_category() -> Result<(), Error> {
+        // GIVEN more shoes than fit in a page, one of them archived, and a hat
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let bus = ProgrammableBus::new();
+        let product = |id: String, category: &str| Product {
+            id,
+            name: "foo".to_string(),
+            price: 10.0,
+            attributes: [("category".to_string(), category.into())]
+                .into_iter()
+                .collect(),
+            images: Default::default(),
+        };
+        for i in 0..ARCHIVE_PAGE_SIZE + 10 {
+            store.put(&product(format!("{:03}", i), "shoes")).await?;
+        }
+        let mut archived = product("000".to_string(), "shoes");
+        archived
+            .attributes
+            .insert(STATUS_ATTRIBUTE.to_string(), ARCHIVED_STATUS.into());
+        store.put(&archived).await?;
+        store.put(&product("hat".to_string(), "hats")).await?;
+
+        // WHEN archiving the shoes
+        let count = archive_category(&store, &store, &history, &bus, "shoes").await?;
+
+        // THEN the other shoes are archived
+        assert_eq!(count, ARCHIVE_PAGE_SIZE + 9);
+        let hat = store
+            .get("hat", ReadConsistency::Eventual, None)
+            .await?
+            .unwrap();
+        assert_eq!(hat.attributes.get(STATUS_ATTRIBUTE), None);
+        assert_eq!(history.history("001").await?.len(), 1);
+        // AND their events are published in order, followed by the report
+        let events = bus.delivered();
+        let ids: Vec<&str> = events.iter().map(|event| event.id()).collect();
+        let mut expected: Vec<String> = (1..ARCHIVE_PAGE_SIZE + 10)
+            .map(|i| format!("{:03}", i))
+            .collect();
+        expected.push("shoes".to_string());
+        assert_eq!(ids, expected);
+        assert_eq!(
+            events.last(),
+            Some(&Event::ArchiveCompleted {
+                category: "shoes".to_string(),
+                archived: count,
+            })
+        );
+
+        Ok(())
+    }
+}
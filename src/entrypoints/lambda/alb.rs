@@ -0,0 +1,229 @@
+//! Application Load Balancer entrypoint
+//!
+//! Functions registered in an ALB target group receive events with their
+//! own shape, which `lambda_http` parses like API Gateway events. Responses
+//! are also written in the shape of the request, with multi-value headers
+//! when the target group has them enabled.
+//!
+//! ALBs don't extract path parameters, since requests reach a function
+//! through a listener rule matching a path pattern, and they send health
+//! checks to the function itself. `handle` wraps the API Gateway handlers to
+//! fill in the path parameters from a route template, such as
+//! `/products/{id}`, and to answer health checks without calling the
+//! handler. Requests coming from API Gateway go through unchanged, so the
+//! same function can sit behind both.
+
+use lambda_http::{
+    http::StatusCode, request::RequestContext, Body, IntoResponse, Request, RequestExt, Response,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::{info, warn};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Default path of the target group health checks
+pub const DEFAULT_HEALTH_CHECK_PATH: &str = "/health";
+
+/// User agent of the target group health checks
+const HEALTH_CHECKER_USER_AGENT: &str = "ELB-HealthChecker/";
+
+/// Route of a function behind an ALB
+#[derive(Clone, Debug)]
+pub struct AlbRoute {
+    template: String,
+    health_check_path: String,
+}
+
+impl AlbRoute {
+    /// Route matching paths such as `/products/{id}`
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            health_check_path: DEFAULT_HEALTH_CHECK_PATH.to_string(),
+        }
+    }
+
+    /// Set the path of the target group health checks
+    pub fn with_health_check_path(mut self, health_check_path: impl Into<String>) -> Self {
+        self.health_check_path = health_check_path.into();
+        self
+    }
+
+    /// Whether the request is a target group health check
+    fn is_health_check(&self, event: &Request) -> bool {
+        event.uri().path() == self.health_check_path
+            || event
+                .headers()
+                .get("user-agent")
+                .and_then(|v| v.to_str().ok())
+                .map_or(false, |v| v.starts_with(HEALTH_CHECKER_USER_AGENT))
+    }
+}
+
+/// Match a path with a route template
+///
+/// Segments of the template between braces, such as `{id}`, match any
+/// non-empty segment and return it under their name. Other segments must be
+/// equal. Trailing slashes are ignored.
+pub fn match_path(template: &str, path: &str) -> Option<HashMap<String, String>> {
+    let segments = |value: &str| -> Vec<String> {
+        value
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let template = segments(template);
+    let path = segments(path);
+    if template.len() != path.len() {
+        return None;
+    }
+
+    let mut parameters = HashMap::new();
+    for (expected, actual) in template.iter().zip(path) {
+        match expected
+            .strip_prefix('{')
+            .and_then(|name| name.strip_suffix('}'))
+        {
+            Some(name) => {
+                parameters.insert(name.to_string(), actual);
+            }
+            None if expected == &actual => {}
+            None => return None,
+        }
+    }
+    Some(parameters)
+}
+
+/// Call an API Gateway handler for a request coming from an ALB
+///
+/// Health checks get a `200 OK` without calling the handler, and requests
+/// not matching the route get a `404 Not Found`.
+pub async fn handle<F, Fut, R>(
+    route: &AlbRoute,
+    event: Request,
+    handler: F,
+) -> Result<Response<Body>, E>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = Result<R, E>>,
+    R: IntoResponse,
+{
+    if !matches!(event.request_context(), RequestContext::Alb(_)) {
+        return Ok(handler(event).await?.into_response());
+    }
+
+    if route.is_health_check(&event) {
+        info!("Answering target group health check");
+        return Ok(json_response(StatusCode::OK, json!({"status": "ok"})));
+    }
+
+    let parameters = match match_path(&route.template, event.uri().path()) {
+        Some(parameters) => parameters,
+        None => {
+            warn!("Path {} doesn't match the route", event.uri().path());
+            return Ok(json_response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Not found"}),
+            ));
+        }
+    };
+    let parameters: HashMap<String, Vec<String>> = parameters
+        .into_iter()
+        .map(|(name, value)| (name, vec![value]))
+        .collect();
+    Ok(handler(event.with_path_parameters(parameters))
+        .await?
+        .into_response())
+}
+
+/// HTTP Response with a JSON payload
+fn json_response(status_code: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// GET /products/1 as sent by an ALB with multi-value headers
+    fn get_event(path: &str, user_agent: &str) -> Result<Request, E> {
+        let event = json!({
+            "requestContext": {
+                "elb": {
+                    "targetGroupArn": "arn:aws:elasticloadbalancing:eu-west-1:123456789012:targetgroup/products/0123456789abcdef"
+                }
+            },
+            "httpMethod": "GET",
+            "path": path,
+            "multiValueQueryStringParameters": {},
+            "multiValueHeaders": {
+                "host": ["products-123456789.eu-west-1.elb.amazonaws.com"],
+                "user-agent": [user_agent]
+            },
+            "body": "",
+            "isBase64Encoded": false
+        });
+        Ok(lambda_http::request::from_str(&event.to_string())?)
+    }
+
+    #[test]
+    fn test_match_path() {
+        let parameters = match_path("/products/{id}", "/products/1/").unwrap();
+        assert_eq!(parameters["id"], "1");
+        assert_eq!(match_path("/products/{id}", "/products"), None);
+        assert_eq!(match_path("/products/{id}", "/orders/1"), None);
+        assert_eq!(match_path("/", "/").unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle() -> Result<(), E> {
+        // GIVEN a route for products
+        let route = AlbRoute::new("/products/{id}");
+
+        // WHEN handling a request for a product
+        let res = handle(
+            &route,
+            get_event("/products/1", "curl/7.79.1")?,
+            |event| async move {
+                let id = event.path_parameters().first("id").unwrap().to_string();
+                Ok::<_, E>(json_response(StatusCode::OK, json!({ "id": id })))
+            },
+        )
+        .await?;
+
+        // THEN the handler gets the id from the path
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(body["id"], "1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_health_check() -> Result<(), E> {
+        // GIVEN a route for products
+        let route = AlbRoute::new("/products/{id}");
+
+        // WHEN the target group checks the health of the function
+        let res = handle(
+            &route,
+            get_event("/", "ELB-HealthChecker/2.0")?,
+            |_| async { Err::<Response<Body>, E>("unexpected call".into()) },
+        )
+        .await?;
+
+        // THEN it is healthy, without calling the handler
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
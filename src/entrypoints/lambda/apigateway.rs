@@ -1,16 +1,70 @@
-use crate::{domain, store, Product};
-use lambda_http::{http::StatusCode, IntoResponse, Request, RequestExt, Response};
-use serde_json::json;
+use crate::{
+    domain::{
+        self,
+        commands::{CommandContext, CreateProduct, DeleteProduct, PatchProduct, RestoreProduct},
+    },
+    event_bus::{EventBus, EventContext},
+    idempotency::{self, Begin, IdempotencyStore, StoredResponse},
+    images::ImageStore,
+    lag::{self, LagStore},
+    recommendations::Recommendations,
+    store,
+    tax::TaxCalculator,
+    Error, Event, Product, ProductPatch, ProductView, PublicProduct, WebhookSubscription,
+};
+use lambda_http::{
+    http::{
+        header::{CONTENT_TYPE, ETAG},
+        HeaderValue, StatusCode,
+    },
+    request::RequestContext,
+    IntoResponse, Request, RequestExt, Response,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
 use tracing::{error, info, instrument, warn};
 
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
 /// Delete a product
-#[instrument(skip(store))]
+///
+/// Products are soft-deleted by default. Passing `?hard=true` deletes the
+/// product permanently, but only if `allow_hard_delete` is set. Passing an
+/// ISO 8601 duration such as `?delay=PT24H` soft-deletes the product and
+/// schedules its permanent removal once the delay has passed.
+///
+/// Requests with an `If-Match` header holding the `ETag` of the product only
+/// delete it if it hasn't changed since, and fail with a 412 Precondition
+/// Failed otherwise. Requests with an `Idempotency-Key` header are only
+/// applied once.
+#[instrument(skip(store, conditional, expire, audit, idempotency))]
 pub async fn delete_product(
     store: &dyn store::StoreDelete,
+    conditional: &dyn store::StorePutConditional,
+    expire: &dyn store::StoreExpire,
+    audit: &dyn store::StoreRecordAudit,
+    idempotency: &dyn IdempotencyStore,
     event: Request,
+    allow_hard_delete: bool,
 ) -> Result<impl IntoResponse, E> {
+    idempotent(idempotency, &event, || {
+        execute_delete_product(store, conditional, expire, audit, &event, allow_hard_delete)
+    })
+    .await
+}
+
+/// Delete a product once the request is known to be new
+async fn execute_delete_product(
+    store: &dyn store::StoreDelete,
+    conditional: &dyn store::StorePutConditional,
+    expire: &dyn store::StoreExpire,
+    audit: &dyn store::StoreRecordAudit,
+    event: &Request,
+    allow_hard_delete: bool,
+) -> Result<Response<String>, E> {
     // Retrieve product ID from event
     //
     // If the event doesn't contain a product ID, we return a 400 Bad Request.
@@ -26,23 +80,95 @@ pub async fn delete_product(
         }
     };
 
+    // Check if this is a hard delete
+    //
+    // Hard deletes are only allowed if the function is configured for it,
+    // otherwise we return a 403 Forbidden.
+    let hard = event.query_string_parameters().first("hard") == Some("true");
+    if hard && !allow_hard_delete {
+        warn!("Hard delete requested for product {} but not allowed", id);
+        return Ok(response(
+            StatusCode::FORBIDDEN,
+            json!({ "message": "Hard delete is not allowed" }).to_string(),
+        ));
+    }
+
+    // Parse the deletion delay
+    let delay = match event.query_string_parameters().first("delay") {
+        Some(delay) => match parse_duration(delay) {
+            Some(delay) => Some(delay),
+            None => {
+                warn!("Invalid delay '{}' for product {}", delay, id);
+                return Ok(response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "message": "delay must be an ISO 8601 duration, e.g. PT24H" })
+                        .to_string(),
+                ));
+            }
+        },
+        None => None,
+    };
+
+    // Build the command
+    let command =
+        DeleteProduct::new(id.to_string(), hard, command_context(event)).and_then(|command| {
+            match delay {
+                Some(delay) => command.with_delay(delay),
+                None => Ok(command),
+            }
+        });
+    let command = match command {
+        Ok(command) => command,
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid delete request for product {}: {}", id, msg);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
     // Delete product
-    info!("Deleting product {}", id);
-    let res = domain::delete_product(store, id).await;
+    info!(
+        "Deleting product {} (hard: {}, delay: {:?})",
+        id, hard, delay
+    );
+    let res = match command.context.expected_version {
+        Some(_) => command
+            .execute_if_version(conditional, expire, audit)
+            .await
+            .map(|_| true),
+        None => command.execute(store, expire, audit).await,
+    };
 
     // Return response
     //
     // The service returns a Result based on the success of the operation. If
-    // the operation was successful, the Result is Ok(()), otherwise it will
-    // contain an Err with the reason.
+    // the operation was successful, the Result tells whether a product was
+    // deleted, otherwise it will contain an Err with the reason.
     match res {
-        Ok(_) => {
+        Ok(true) => {
             info!("Product {} deleted", id);
             Ok(response(
                 StatusCode::OK,
                 json!({"message": "Product deleted"}).to_string(),
             ))
         }
+        Ok(false) => {
+            warn!("Product not found: {}", id);
+            Ok(response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            ))
+        }
+        Err(Error::PreconditionFailed(msg)) => {
+            warn!("Product {} not deleted: {}", id, msg);
+            Ok(response(
+                StatusCode::PRECONDITION_FAILED,
+                json!({ "message": msg }).to_string(),
+            ))
+        }
         Err(err) => {
             // Log the error message
             error!("Error deleting the product {}: {}", id, err);
@@ -54,10 +180,23 @@ pub async fn delete_product(
     }
 }
 
+/// Scope required for the admin view of products
+const ADMIN_SCOPE: &str = "products/admin";
+
 /// Get a product
-#[instrument(skip(store))]
+///
+/// `?view=public` returns a minimal product, while `?view=admin` adds the
+/// version and timestamps from the audit log. The admin view is only
+/// available to callers with the `products/admin` scope.
+///
+/// The `ETag` header holds the version of the product, to send in the
+/// `If-Match` header of conditional writes. Successful reads are counted as
+/// hits for the popular products.
+#[instrument(skip(store, audit, hits))]
 pub async fn get_product(
     store: &dyn store::StoreGet,
+    audit: &dyn store::StoreGetAudit,
+    hits: &dyn store::StoreRecordHit,
     event: Request,
 ) -> Result<impl IntoResponse, E> {
     // Retrieve product ID from event.
@@ -75,9 +214,61 @@ pub async fn get_product(
         }
     };
 
+    // Use a strongly consistent read if requested
+    //
+    // This is useful for clients that need to read their own writes. With a
+    // global table, strongly consistent reads go to the writer region.
+    let params = event.query_string_parameters();
+    let consistency = match (params.first("consistent"), params.first("consistency")) {
+        (Some("true"), _) | (_, Some("strong")) => store::ReadConsistency::Strong,
+        _ => store::ReadConsistency::Eventual,
+    };
+
+    // Select the response profile
+    //
+    // Callers without the admin scope get a 403 Forbidden for the admin view.
+    let view = match event.query_string_parameters().first("view") {
+        Some(view) => match view.parse::<ProductView>() {
+            Ok(view) => Some(view),
+            Err(Error::ClientError(msg)) => {
+                warn!("Invalid view '{}': {}", view, msg);
+                return Ok(response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "message": msg }).to_string(),
+                ));
+            }
+            Err(err) => return Err(err.into()),
+        },
+        None => None,
+    };
+    if view == Some(ProductView::Admin) && !caller_scopes(&event).iter().any(|s| s == ADMIN_SCOPE) {
+        warn!(
+            "Admin view of product {} requested without the admin scope",
+            id
+        );
+        return Ok(response(
+            StatusCode::FORBIDDEN,
+            json!({ "message": "The admin view requires the products/admin scope" }).to_string(),
+        ));
+    }
+
     // Retrieve product
-    info!("Fetching product {}", id);
-    let product = domain::get_product(store, id).await;
+    info!("Fetching product {} (view: {:?})", id, view);
+    let product = match view {
+        Some(ProductView::Admin) => domain::get_admin_product(store, audit, id, consistency)
+            .await
+            .map(|product| product.map(|product| (product.product.version(), json!(product)))),
+        Some(ProductView::Public) => {
+            domain::get_product(store, id, consistency)
+                .await
+                .map(|product| {
+                    product.map(|product| (product.version(), json!(PublicProduct::from(product))))
+                })
+        }
+        None => domain::get_product(store, id, consistency)
+            .await
+            .map(|product| product.map(|product| (product.version(), json!(product)))),
+    };
 
     // Return response
     //
@@ -86,7 +277,18 @@ pub async fn get_product(
     // an error.
     Ok(match product {
         // Product exists
-        Ok(Some(product)) => response(StatusCode::OK, json!(product).to_string()),
+        //
+        // Failing to count the hit shouldn't fail the request.
+        Ok(Some((version, product))) => {
+            if let Err(err) = domain::record_hit(hits, id).await {
+                warn!("Error recording hit on product {}: {}", id, err);
+            }
+            let mut res = response(StatusCode::OK, product.to_string());
+            if let Ok(etag) = HeaderValue::from_str(&format!("\"{}\"", version)) {
+                res.headers_mut().insert(ETAG, etag);
+            }
+            res
+        }
         // Product doesn't exist
         Ok(None) => {
             warn!("Product not found: {}", id);
@@ -106,38 +308,93 @@ pub async fn get_product(
     })
 }
 
-/// Retrieve products
-#[instrument(skip(store))]
-pub async fn get_products(
-    store: &dyn store::StoreGetAll,
-    _event: Request,
+/// Get the price of a product in a country, including tax
+#[instrument(skip(store, tax))]
+pub async fn get_product_price(
+    store: &dyn store::StoreGet,
+    tax: &dyn TaxCalculator,
+    event: Request,
 ) -> Result<impl IntoResponse, E> {
-    // Retrieve products
-    // TODO: Add pagination
-    let res = domain::get_products(store, None).await;
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Retrieve the country from the query string
+    let query_parameters = event.query_string_parameters();
+    let country = match query_parameters.first("country") {
+        Some(country) => country,
+        None => {
+            warn!("Missing 'country' parameter in query string");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'country' parameter in query string" }).to_string(),
+            ));
+        }
+    };
+
+    // Compute the price
+    info!("Computing price of product {} in {}", id, country);
+    let price = domain::get_product_price(store, tax, id, country).await;
 
     // Return response
-    Ok(match res {
-        // Return a list of products
-        Ok(res) => response(StatusCode::OK, json!(res).to_string()),
-        // Return an error
+    Ok(match price {
+        Ok(Some(price)) => response(StatusCode::OK, json!(price).to_string()),
+        Ok(None) => {
+            warn!("Product not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            )
+        }
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
         Err(err) => {
-            error!("Something went wrong: {:?}", err);
+            error!("Error computing product price: {}", err);
             response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                json!({ "message": format!("Something went wrong: {:?}", err) }).to_string(),
+                json!({"message": "Error computing product price"}).to_string(),
             )
         }
     })
 }
 
-/// Put a product
-#[instrument(skip(store))]
-pub async fn put_product(
-    store: &dyn store::StorePut,
+/// Body of a request to add an image to a product
+#[derive(Deserialize)]
+struct NewImage {
+    content_type: String,
+}
+
+/// Add an image to a product
+///
+/// The body gives the content type of the image, e.g.
+/// `{"content_type": "image/png"}`. The response contains the key of the
+/// image and a presigned URL to upload it with a `PUT` request and the same
+/// `Content-Type` header.
+#[instrument(skip(store, put, history, images))]
+pub async fn add_product_image(
+    store: &dyn store::StoreGet,
+    put: &dyn store::StorePut,
+    history: &dyn store::StoreAppendHistory,
+    images: &dyn ImageStore,
     event: Request,
 ) -> Result<impl IntoResponse, E> {
-    // Retrieve product ID from event.
+    // Retrieve product ID from event
     //
     // If the event doesn't contain a product ID, we return a 400 Bad Request.
     let path_parameters = event.path_parameters();
@@ -152,70 +409,2014 @@ pub async fn put_product(
         }
     };
 
-    // Read product from request
-    let product: Product = match event.payload() {
-        Ok(Some(product)) => product,
+    // Read the content type from request
+    let image: NewImage = match json_payload(&event) {
+        Ok(Some(image)) => image,
         Ok(None) => {
-            warn!("Missing product in request body");
+            warn!("Missing image in request body");
             return Ok(response(
                 StatusCode::BAD_REQUEST,
-                json!({"message": "Missing product in request body"}).to_string(),
+                json!({"message": "Missing image in request body"}).to_string(),
             ));
         }
         Err(err) => {
-            warn!("Failed to parse product from request body: {}", err);
+            warn!("Failed to parse image from request body: {}", err);
             return Ok(response(
                 StatusCode::BAD_REQUEST,
-                json!({"message": "Failed to parse product from request body"}).to_string(),
+                json!({"message": "Failed to parse image from request body"}).to_string(),
             ));
         }
     };
-    info!("Parsed product: {:?}", product);
 
-    // Compare product ID with product ID in body
-    if product.id != id {
-        warn!(
-            "Product ID in path ({}) does not match product ID in body ({})",
-            id, product.id
-        );
-        return Ok(response(
-            StatusCode::BAD_REQUEST,
-            json!({"message": "Product ID in path does not match product ID in body"}).to_string(),
-        ));
-    }
+    // Add the image
+    info!("Adding image to product {}", id);
+    let res = domain::add_product_image(store, put, history, images, id, &image.content_type).await;
+
+    // Return response
+    Ok(match res {
+        Ok(Some(image)) => response(StatusCode::CREATED, json!(image).to_string()),
+        Ok(None) => {
+            warn!("Product not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            )
+        }
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error adding product image: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error adding product image"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Get the images of a product with presigned download URLs
+#[instrument(skip(store, images))]
+pub async fn get_product_images(
+    store: &dyn store::StoreGet,
+    images: &dyn ImageStore,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Retrieve the images
+    info!("Fetching images of product {}", id);
+    let res = domain::get_product_images(store, images, id).await;
+
+    // Return response
+    Ok(match res {
+        Ok(Some(images)) => response(StatusCode::OK, json!({ "images": images }).to_string()),
+        Ok(None) => {
+            warn!("Product not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error fetching product images: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching product images"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Get the past versions of a product
+#[instrument(skip(history))]
+pub async fn get_product_history(
+    history: &dyn store::StoreGetHistory,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
 
-    // Put product
-    let res = domain::put_product(store, &product).await;
+    // Retrieve history
+    info!("Fetching history of product {}", id);
+    let res = domain::get_product_history(history, id).await;
 
     // Return response
     //
-    // If the put was successful, we return a 201 Created. Otherwise, we return
-    // a 500 Internal Server Error.
+    // A product without any revision was never written, so we return a 404
+    // Not Found.
     Ok(match res {
-        // Product created
-        Ok(_) => {
-            info!("Created product {:?}", product.id);
+        Ok(revisions) if revisions.is_empty() => {
+            warn!("Product not found: {}", id);
             response(
-                StatusCode::CREATED,
-                json!({"message": "Product created"}).to_string(),
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
             )
         }
-        // Error creating product
+        Ok(revisions) => response(
+            StatusCode::OK,
+            json!({ "revisions": revisions }).to_string(),
+        ),
         Err(err) => {
-            error!("Failed to create product {}: {}", product.id, err);
+            error!("Error fetching product history: {}", err);
             response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                json!({"message": "Failed to create product"}).to_string(),
+                json!({"message": "Error fetching product history"}).to_string(),
             )
         }
     })
 }
 
-/// HTTP Response with a JSON payload
-fn response(status_code: StatusCode, body: String) -> Response<String> {
-    Response::builder()
-        .status(status_code)
-        .header("Content-Type", "application/json")
-        .body(body)
-        .unwrap()
+/// Get the products related to a product
+#[instrument(skip(recommendations, store))]
+pub async fn get_related_products(
+    recommendations: &dyn Recommendations,
+    store: &dyn store::StoreBatchGet,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Retrieve related products
+    info!("Fetching products related to {}", id);
+    let res = domain::get_related_products(recommendations, store, id).await;
+
+    // Return response
+    Ok(match res {
+        Ok(products) => response(StatusCode::OK, json!({ "products": products }).to_string()),
+        Err(err) => {
+            error!("Error fetching related products: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching related products"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Get the most popular products
+#[instrument(skip(popular, store))]
+pub async fn get_popular_products(
+    popular: &dyn store::StoreGetPopular,
+    store: &dyn store::StoreBatchGet,
+    _event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve popular products
+    info!("Fetching popular products");
+    let res = domain::get_popular_products(popular, store).await;
+
+    // Return response
+    Ok(match res {
+        Ok(products) => response(StatusCode::OK, json!({ "products": products }).to_string()),
+        Err(err) => {
+            error!("Error fetching popular products: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching popular products"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Search products by name
+///
+/// The query is passed as `?q=`, and results are sorted by relevance.
+#[instrument(skip(index))]
+pub async fn search_products(
+    index: &dyn store::StoreSearch,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve the query and page size from the query string
+    //
+    // If the query is missing or the limit is not a valid number, we return a
+    // 400 Bad Request.
+    let query_parameters = event.query_string_parameters();
+    let query = match query_parameters.first("q") {
+        Some(query) => query,
+        None => {
+            warn!("Missing 'q' parameter in query string");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'q' parameter in query string" }).to_string(),
+            ));
+        }
+    };
+    let limit = match query_parameters.first("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            warn!("Invalid 'limit' parameter in query string");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Invalid 'limit' parameter in query string" }).to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    // Search products
+    info!("Searching products");
+    let res = domain::search_products(index, query, limit).await;
+
+    // Return response
+    Ok(match res {
+        Ok(products) => response(StatusCode::OK, json!({ "products": products }).to_string()),
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid search request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error searching products: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error searching products"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Get the audit log of a product
+#[instrument(skip(audit))]
+pub async fn get_product_audit(
+    audit: &dyn store::StoreGetAudit,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Retrieve audit log
+    info!("Fetching audit log of product {}", id);
+    let res = domain::get_product_audit(audit, id).await;
+
+    // Return response
+    //
+    // A product without any audit entry was never written, so we return a 404
+    // Not Found.
+    Ok(match res {
+        Ok(entries) if entries.is_empty() => {
+            warn!("Product not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            )
+        }
+        Ok(entries) => response(StatusCode::OK, json!({ "entries": entries }).to_string()),
+        Err(err) => {
+            error!("Error fetching product audit log: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching product audit log"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Retrieve products
+///
+/// Passing a comma-separated list of ids as `?ids=1,2,3` retrieves these
+/// products instead of a page of products. If they can't all be retrieved
+/// within `budget`, the products retrieved so far are returned with
+/// `"partial": true`.
+#[instrument(skip(store, batch))]
+pub async fn get_products(
+    store: &dyn store::StoreGetAll,
+    batch: &dyn store::StoreBatchGet,
+    event: Request,
+    budget: Duration,
+) -> Result<impl IntoResponse, E> {
+    let query_parameters = event.query_string_parameters();
+    if let Some(ids) = query_parameters.first("ids") {
+        let ids: Vec<String> = ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect();
+        return Ok(
+            match domain::get_products_by_ids(batch, &ids, budget).await {
+                Ok(res) => {
+                    if res.partial {
+                        warn!("Latency budget exceeded, returning partial results");
+                    }
+                    response(StatusCode::OK, json!(res).to_string())
+                }
+                Err(Error::ClientError(msg)) => {
+                    warn!("Invalid request: {}", msg);
+                    response(
+                        StatusCode::BAD_REQUEST,
+                        json!({ "message": msg }).to_string(),
+                    )
+                }
+                Err(err) => {
+                    error!("Something went wrong: {:?}", err);
+                    response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        json!({ "message": format!("Something went wrong: {:?}", err) })
+                            .to_string(),
+                    )
+                }
+            },
+        );
+    }
+
+    // Retrieve the page size from the query string
+    //
+    // If the limit is not a valid number, we return a 400 Bad Request.
+    let limit = match query_parameters.first("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            warn!("Invalid 'limit' parameter in query string");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Invalid 'limit' parameter in query string" }).to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    // Retrieve the filter from the query string
+    //
+    // If a price bound is not a valid number, we return a 400 Bad Request.
+    let mut filter = store::ProductFilter {
+        name_contains: query_parameters.first("name").map(|n| n.to_string()),
+        attributes: query_parameters
+            .iter()
+            .filter_map(|(param, value)| {
+                let key = param.strip_prefix("attr.")?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect(),
+        ..Default::default()
+    };
+    for (param, bound) in [
+        ("min_price", &mut filter.min_price),
+        ("max_price", &mut filter.max_price),
+    ] {
+        *bound = match query_parameters.first(param).map(|p| p.parse::<f64>()) {
+            Some(Ok(price)) => Some(price),
+            Some(Err(_)) => {
+                warn!("Invalid '{}' parameter in query string", param);
+                return Ok(response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "message": format!("Invalid '{}' parameter in query string", param) })
+                        .to_string(),
+                ));
+            }
+            None => None,
+        };
+    }
+
+    // Retrieve the fields to return from the query string
+    //
+    // If a field is unknown, we return a 400 Bad Request.
+    let fields = match query_parameters.first("fields").map(|f| {
+        f.split(',')
+            .map(|f| f.trim().parse::<store::ProductField>())
+            .collect::<Result<Vec<_>, _>>()
+    }) {
+        Some(Ok(fields)) => Some(fields),
+        Some(Err(_)) => {
+            warn!("Invalid 'fields' parameter in query string");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Invalid 'fields' parameter in query string" }).to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    // Retrieve products
+    // TODO: Add pagination
+    let res = domain::get_products(store, None, limit, &filter, fields.as_deref()).await;
+
+    // Return response
+    Ok(match res {
+        // Return a list of products
+        //
+        // If only some fields were requested, the other fields are removed
+        // from the products.
+        Ok(res) => {
+            let mut body = json!(res);
+            if let (Some(fields), Some(products)) = (&fields, body["products"].as_array_mut()) {
+                for product in products.iter_mut().filter_map(|p| p.as_object_mut()) {
+                    product.retain(|key, _| fields.iter().any(|f| f.as_str() == key));
+                }
+            }
+            response(StatusCode::OK, body.to_string())
+        }
+        // Invalid request
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        // Return an error
+        Err(err) => {
+            error!("Something went wrong: {:?}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": format!("Something went wrong: {:?}", err) }).to_string(),
+            )
+        }
+    })
+}
+
+/// Body of a product query
+///
+/// Attribute values can be strings, numbers or booleans, and match like
+/// `attr.<key>` query parameters.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct QueryBody {
+    category: Option<String>,
+    name: Option<String>,
+    min_price: Option<f64>,
+    max_price: Option<f64>,
+    attributes: HashMap<String, Value>,
+    limit: Option<usize>,
+    next: Option<String>,
+}
+
+impl From<QueryBody> for store::Query {
+    fn from(body: QueryBody) -> Self {
+        let mut query = store::Query::new();
+        if let Some(category) = &body.category {
+            query = query.category(category);
+        }
+        for (key, value) in &body.attributes {
+            query = match value {
+                Value::String(value) => query.attribute(key, value),
+                value => query.attribute(key, &value.to_string()),
+            };
+        }
+        if let Some(name) = &body.name {
+            query = query.name_contains(name);
+        }
+        query.filter.min_price = body.min_price;
+        query.filter.max_price = body.max_price;
+        if let Some(limit) = body.limit {
+            query = query.limit(limit);
+        }
+        query.next = body.next;
+        query
+    }
+}
+
+/// Search products with a query in the request body
+///
+/// An empty body returns the first page of all products.
+#[instrument(skip(store))]
+pub async fn find_products(
+    store: &dyn store::StoreFind,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Read the query from the request
+    let query: store::Query = match json_payload::<QueryBody>(&event) {
+        Ok(body) => body.unwrap_or_default().into(),
+        Err(err) => {
+            warn!("Failed to parse query from request body: {}", err);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Failed to parse query from request body"}).to_string(),
+            ));
+        }
+    };
+
+    // Run the query
+    let res = domain::find_products(store, &query).await;
+
+    // Return response
+    Ok(match res {
+        Ok(res) => response(StatusCode::OK, json!(res).to_string()),
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Something went wrong: {:?}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": format!("Something went wrong: {:?}", err) }).to_string(),
+            )
+        }
+    })
+}
+
+/// Retrieve statistics about the products
+#[instrument(skip(store))]
+pub async fn get_stats(
+    store: &dyn store::StoreCount,
+    _event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Count products
+    let res = domain::get_product_count(store).await;
+
+    // Return response
+    Ok(match res {
+        Ok(count) => response(StatusCode::OK, json!({ "count": count }).to_string()),
+        Err(err) => {
+            error!("Error counting products: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error counting products"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Retrieve the lag of downstream consumers
+///
+/// Each consumer is returned with the position of the last event it
+/// processed, its lag in milliseconds and the time since it last recorded a
+/// position.
+#[instrument(skip(lag))]
+pub async fn get_consumers(lag: &dyn LagStore, _event: Request) -> Result<impl IntoResponse, E> {
+    // Retrieve consumer positions
+    let res = lag::consumer_lags(lag).await;
+
+    // Return response
+    Ok(match res {
+        Ok(consumers) => response(
+            StatusCode::OK,
+            json!({ "consumers": consumers }).to_string(),
+        ),
+        Err(err) => {
+            error!("Error fetching consumer positions: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching consumer positions"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Put a product
+///
+/// Requests with an `If-None-Match: *` header only create the product, and
+/// fail with a 412 Precondition Failed if it already exists. Requests with
+/// an `If-Match` header holding the `ETag` of the product only replace it if
+/// it hasn't changed since, and fail with a 412 Precondition Failed
+/// otherwise. Requests with an `Idempotency-Key` header are only applied
+/// once.
+#[instrument(skip(store, conditional, history, audit, idempotency))]
+pub async fn put_product(
+    store: &dyn store::StorePut,
+    conditional: &dyn store::StorePutConditional,
+    history: &dyn store::StoreAppendHistory,
+    audit: &dyn store::StoreRecordAudit,
+    idempotency: &dyn IdempotencyStore,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    idempotent(idempotency, &event, || {
+        execute_put_product(store, conditional, history, audit, &event)
+    })
+    .await
+}
+
+/// Put a product once the request is known to be new
+async fn execute_put_product(
+    store: &dyn store::StorePut,
+    conditional: &dyn store::StorePutConditional,
+    history: &dyn store::StoreAppendHistory,
+    audit: &dyn store::StoreRecordAudit,
+    event: &Request,
+) -> Result<Response<String>, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Read product from request
+    let product: Product = match json_payload(event) {
+        Ok(Some(product)) => product,
+        Ok(None) => {
+            warn!("Missing product in request body");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Missing product in request body"}).to_string(),
+            ));
+        }
+        Err(err) => {
+            warn!("Failed to parse product from request body: {}", err);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Failed to parse product from request body"}).to_string(),
+            ));
+        }
+    };
+    info!("Parsed product: {:?}", product);
+
+    // Compare product ID with product ID in body
+    if product.id != id {
+        warn!(
+            "Product ID in path ({}) does not match product ID in body ({})",
+            id, product.id
+        );
+        return Ok(response(
+            StatusCode::BAD_REQUEST,
+            json!({"message": "Product ID in path does not match product ID in body"}).to_string(),
+        ));
+    }
+
+    // Build the command
+    //
+    // This validates the product fields and returns a 400 Bad Request if they
+    // are invalid.
+    let command = match CreateProduct::new(product, command_context(event)) {
+        Ok(command) => command,
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid product: {}", msg);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let product = &command.product;
+    if create_only(event) && command.context.expected_version.is_some() {
+        warn!(
+            "Both If-None-Match and If-Match set for product {}",
+            product.id
+        );
+        return Ok(response(
+            StatusCode::BAD_REQUEST,
+            json!({"message": "If-None-Match and If-Match cannot be combined"}).to_string(),
+        ));
+    }
+
+    // Create the product only if it doesn't exist yet
+    if create_only(event) {
+        return Ok(
+            match command.execute_if_absent(conditional, history, audit).await {
+                Ok(Some(_)) => {
+                    info!("Created product {:?}", product.id);
+                    response(
+                        StatusCode::CREATED,
+                        json!({"message": "Product created"}).to_string(),
+                    )
+                }
+                Ok(None) => {
+                    warn!("Product {} already exists", product.id);
+                    response(
+                        StatusCode::PRECONDITION_FAILED,
+                        json!({"message": "Product already exists"}).to_string(),
+                    )
+                }
+                Err(err) => {
+                    error!("Failed to create product {}: {}", product.id, err);
+                    response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        json!({"message": "Failed to create product"}).to_string(),
+                    )
+                }
+            },
+        );
+    }
+
+    // Put product, only at the expected version if there is one
+    let res = match command.context.expected_version {
+        Some(_) => {
+            command
+                .execute_if_version(conditional, history, audit)
+                .await
+        }
+        None => command.execute(store, history, audit).await,
+    };
+
+    // Return response
+    //
+    // If the put created the product, we return a 201 Created, and a 200 OK
+    // if it replaced an existing one. Otherwise, we return a 500 Internal
+    // Server Error.
+    Ok(match res {
+        // Product replaced
+        Ok(Event::Updated { .. }) => {
+            info!("Updated product {:?}", product.id);
+            response(
+                StatusCode::OK,
+                json!({"message": "Product updated"}).to_string(),
+            )
+        }
+        // Product created
+        Ok(_) => {
+            info!("Created product {:?}", product.id);
+            response(
+                StatusCode::CREATED,
+                json!({"message": "Product created"}).to_string(),
+            )
+        }
+        // Product changed since the expected version
+        Err(Error::PreconditionFailed(msg)) => {
+            warn!("Product {} not updated: {}", product.id, msg);
+            response(
+                StatusCode::PRECONDITION_FAILED,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        // Error creating product
+        Err(err) => {
+            error!("Failed to create product {}: {}", product.id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to create product"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Partially update a product
+///
+/// Only the fields present in the request body are changed. Requests with
+/// an `If-Match` header holding the `ETag` of the product only update it if
+/// it hasn't changed since, and fail with a 412 Precondition Failed
+/// otherwise.
+#[instrument(skip(store, conditional, history, audit))]
+pub async fn patch_product(
+    store: &dyn store::StoreUpdate,
+    conditional: &dyn store::StorePutConditional,
+    history: &dyn store::StoreAppendHistory,
+    audit: &dyn store::StoreRecordAudit,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Read patch from request
+    let patch: ProductPatch = match json_payload(&event) {
+        Ok(Some(patch)) => patch,
+        Ok(None) => {
+            warn!("Missing patch in request body");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Missing patch in request body"}).to_string(),
+            ));
+        }
+        Err(err) => {
+            warn!("Failed to parse patch from request body: {}", err);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Failed to parse patch from request body"}).to_string(),
+            ));
+        }
+    };
+    info!("Parsed patch: {:?}", patch);
+
+    // Build the command
+    let command = match PatchProduct::new(id.to_string(), patch, command_context(&event)) {
+        Ok(command) => command,
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid patch for product {}: {}", id, msg);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // Update product, only at the expected version if there is one
+    let res = match command.context.expected_version {
+        Some(_) => command
+            .execute_if_version(conditional, history, audit)
+            .await
+            .map(Some),
+        None => command.execute(store, history, audit).await,
+    };
+
+    // Return response
+    //
+    // If the product was updated, we return it with a 200 OK. If it doesn't
+    // exist, we return a 404 Not Found.
+    Ok(match res {
+        // Product updated
+        Ok(Some(product)) => {
+            info!("Updated product {:?}", product.id);
+            response(StatusCode::OK, json!(product).to_string())
+        }
+        // Product not found
+        Ok(None) => {
+            warn!("Product {} not found", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Product not found"}).to_string(),
+            )
+        }
+        // Product changed since the expected version
+        Err(Error::PreconditionFailed(msg)) => {
+            warn!("Product {} not updated: {}", id, msg);
+            response(
+                StatusCode::PRECONDITION_FAILED,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        // Error updating product
+        Err(err) => {
+            error!("Failed to update product {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to update product"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Restore a deleted product
+#[instrument(skip(store, audit))]
+pub async fn restore_product(
+    store: &dyn store::StoreRestore,
+    audit: &dyn store::StoreRecordAudit,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Restore product
+    info!("Restoring product {}", id);
+    let res = match RestoreProduct::new(id.to_string(), command_context(&event)) {
+        Ok(command) => command.execute(store, audit).await,
+        Err(err) => Err(err),
+    };
+
+    // Return response
+    //
+    // If there was no deleted product with that ID, we return a 404 Not Found.
+    Ok(match res {
+        Ok(true) => {
+            info!("Product {} restored", id);
+            response(
+                StatusCode::OK,
+                json!({"message": "Product restored"}).to_string(),
+            )
+        }
+        Ok(false) => {
+            warn!("Deleted product not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Deleted product not found"}).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error restoring the product {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to restore product"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Retrieve changes since a token
+///
+/// This supports long-polling: with `?wait=20s`, the request waits up to 20
+/// seconds for new changes if there are none yet.
+#[instrument(skip(store))]
+pub async fn get_changes(
+    store: &dyn store::StoreGetChanges,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    let query_parameters = event.query_string_parameters();
+    let since = query_parameters.first("since");
+
+    // Parse the wait duration
+    //
+    // This accepts both a number of seconds (`20`) and a suffixed value
+    // (`20s`). If the duration is invalid, we return a 400 Bad Request.
+    let wait = match query_parameters.first("wait") {
+        Some(wait) => match wait.trim_end_matches('s').parse::<u64>() {
+            Ok(wait) => Duration::from_secs(wait),
+            Err(_) => {
+                warn!("Invalid 'wait' parameter in query string: {}", wait);
+                return Ok(response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "message": "Invalid 'wait' parameter in query string" }).to_string(),
+                ));
+            }
+        },
+        None => Duration::ZERO,
+    };
+
+    // Retrieve changes
+    info!("Fetching changes since {:?}", since);
+    let res = domain::get_changes(store, since, wait).await;
+
+    // Return response
+    Ok(match res {
+        Ok(res) => response(StatusCode::OK, json!(res).to_string()),
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid request: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error fetching changes: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching changes"}).to_string(),
+            )
+        }
+    })
+}
+
+/// List webhook subscriptions
+#[instrument(skip(store))]
+pub async fn get_webhooks(
+    store: &dyn store::StoreListWebhooks,
+    _event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve subscriptions
+    let res = domain::get_webhooks(store).await;
+
+    // Return response
+    //
+    // Secrets are never serialized, so they are not returned to the caller.
+    Ok(match res {
+        Ok(webhooks) => response(StatusCode::OK, json!({ "webhooks": webhooks }).to_string()),
+        Err(err) => {
+            error!("Error fetching webhooks: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching webhooks"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Get a webhook subscription
+#[instrument(skip(store))]
+pub async fn get_webhook(
+    store: &dyn store::StoreGetWebhook,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve subscription ID from event
+    //
+    // If the event doesn't contain a subscription ID, we return a 400 Bad
+    // Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Retrieve subscription
+    info!("Fetching webhook {}", id);
+    let res = domain::get_webhook(store, id).await;
+
+    // Return response
+    Ok(match res {
+        Ok(Some(webhook)) => response(StatusCode::OK, json!(webhook).to_string()),
+        Ok(None) => {
+            warn!("Webhook not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "Webhook not found"}).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error fetching webhook: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching webhook"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Create or replace a webhook subscription
+#[instrument(skip(store, event))]
+pub async fn put_webhook(
+    store: &dyn store::StorePutWebhook,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve subscription ID from event
+    //
+    // If the event doesn't contain a subscription ID, we return a 400 Bad
+    // Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Read subscription from request
+    //
+    // The subscription is not logged as it contains the secret.
+    let webhook: WebhookSubscription = match json_payload(&event) {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => {
+            warn!("Missing webhook in request body");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Missing webhook in request body"}).to_string(),
+            ));
+        }
+        Err(err) => {
+            warn!("Failed to parse webhook from request body: {}", err);
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Failed to parse webhook from request body"}).to_string(),
+            ));
+        }
+    };
+
+    // Compare subscription ID with subscription ID in body
+    if webhook.id != id {
+        warn!(
+            "Webhook ID in path ({}) does not match webhook ID in body ({})",
+            id, webhook.id
+        );
+        return Ok(response(
+            StatusCode::BAD_REQUEST,
+            json!({"message": "Webhook ID in path does not match webhook ID in body"}).to_string(),
+        ));
+    }
+
+    // Put subscription
+    let res = domain::put_webhook(store, &webhook).await;
+
+    // Return response
+    Ok(match res {
+        Ok(_) => {
+            info!("Created webhook {}", id);
+            response(
+                StatusCode::CREATED,
+                json!({"message": "Webhook created"}).to_string(),
+            )
+        }
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid webhook: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Failed to create webhook {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to create webhook"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Delete a webhook subscription
+#[instrument(skip(store))]
+pub async fn delete_webhook(
+    store: &dyn store::StoreDeleteWebhook,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve subscription ID from event
+    //
+    // If the event doesn't contain a subscription ID, we return a 400 Bad
+    // Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.first("id") {
+        Some(id) => id,
+        None => {
+            warn!("Missing 'id' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Delete subscription
+    info!("Deleting webhook {}", id);
+    let res = domain::delete_webhook(store, id).await;
+
+    // Return response
+    Ok(match res {
+        Ok(_) => {
+            info!("Webhook {} deleted", id);
+            response(
+                StatusCode::OK,
+                json!({"message": "Webhook deleted"}).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error deleting the webhook {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to delete webhook"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Archive all the products of a category
+///
+/// Responds once every product is archived and the report event is sent.
+#[instrument(skip(store, writer, history, event_bus))]
+pub async fn archive_category(
+    store: &dyn store::StoreGetAll,
+    writer: &dyn store::StorePutMany,
+    history: &dyn store::StoreAppendHistory,
+    event_bus: &dyn EventBus<E = Event>,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve category from event
+    //
+    // If the event doesn't contain a category, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let category = match path_parameters.first("category") {
+        Some(category) => category,
+        None => {
+            warn!("Missing 'category' parameter in path");
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": "Missing 'category' parameter in path" }).to_string(),
+            ));
+        }
+    };
+
+    // Archive products
+    info!("Archiving products of category {}", category);
+    let archive = domain::archive_category(store, writer, history, event_bus, category);
+    let res = match event_context(&event) {
+        Some(context) => context.scope(archive).await,
+        None => archive.await,
+    };
+
+    // Return response
+    Ok(match res {
+        Ok(archived) => {
+            info!("Archived {} products of category {}", archived, category);
+            response(
+                StatusCode::OK,
+                json!({ "category": category, "archived": archived }).to_string(),
+            )
+        }
+        Err(Error::ClientError(msg)) => {
+            warn!("Invalid category '{}': {}", category, msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error archiving category {}: {}", category, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to archive category"}).to_string(),
+            )
+        }
+    })
+}
+
+/// Run a write request at most once per idempotency key
+///
+/// Requests without an `Idempotency-Key` header always run. Otherwise,
+/// retries replay the stored response, retries sent while the request is
+/// still running get a 409 Conflict, and reusing a key for a different
+/// request gets a 422 Unprocessable Entity. Server errors are not stored, so
+/// that the request can be retried.
+async fn idempotent<F, Fut>(
+    idempotency: &dyn IdempotencyStore,
+    event: &Request,
+    run: F,
+) -> Result<Response<String>, E>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Response<String>, E>>,
+{
+    let key = match command_context(event).idempotency_key {
+        Some(key) => key,
+        None => return run().await,
+    };
+    let fingerprint = idempotency::fingerprint(
+        event.method().as_str(),
+        &request_path(event),
+        event.body().as_ref(),
+    );
+
+    match idempotency::begin(idempotency, &key, &fingerprint).await {
+        Ok(Begin::New) => {}
+        Ok(Begin::Completed(stored)) => {
+            info!("Replaying response for idempotency key {}", key);
+            let mut res = response(StatusCode::from_u16(stored.status)?, stored.body);
+            res.headers_mut()
+                .insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+            return Ok(res);
+        }
+        Ok(Begin::InProgress) => {
+            warn!("Request with idempotency key {} is in progress", key);
+            return Ok(response(
+                StatusCode::CONFLICT,
+                json!({"message": "A request with this idempotency key is in progress"})
+                    .to_string(),
+            ));
+        }
+        Ok(Begin::Mismatch) => {
+            warn!("Idempotency key {} reused for a different request", key);
+            return Ok(response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({"message": "Idempotency key was used for a different request"}).to_string(),
+            ));
+        }
+        Err(err) => {
+            error!("Failed to check idempotency key {}: {}", key, err);
+            return Ok(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to check idempotency key"}).to_string(),
+            ));
+        }
+    }
+
+    // Store the response, or release the key if the request failed
+    let res = run().await;
+    match &res {
+        Ok(res) if !res.status().is_server_error() => {
+            let stored = StoredResponse {
+                status: res.status().as_u16(),
+                body: res.body().clone(),
+            };
+            if let Err(err) = idempotency.complete(&key, &stored).await {
+                error!(
+                    "Failed to store response for idempotency key {}: {}",
+                    key, err
+                );
+            }
+        }
+        _ => {
+            if let Err(err) = idempotency.abandon(&key).await {
+                error!("Failed to release idempotency key {}: {}", key, err);
+            }
+        }
+    }
+    res
+}
+
+/// Path of the request, relative to the API stage
+///
+/// HTTP APIs (payload format 2.0) include the stage in the path of requests
+/// to a named stage, e.g. `/prod/1`, while REST APIs (1.0) don't. The stage
+/// is removed so that a request has the same path with both, e.g. to keep
+/// idempotency keys valid when moving from one to the other.
+fn request_path(event: &Request) -> String {
+    let path = event.uri().path();
+    if let RequestContext::ApiGatewayV2(ctx) = event.request_context() {
+        if ctx.stage != "$default" {
+            if let Some(rest) = path.strip_prefix(&format!("/{}", ctx.stage)) {
+                if rest.is_empty() {
+                    return "/".to_string();
+                }
+                if rest.starts_with('/') {
+                    return rest.to_string();
+                }
+            }
+        }
+    }
+    path.to_string()
+}
+
+/// Whether the request has an `If-None-Match: *` header
+fn create_only(event: &Request) -> bool {
+    event
+        .headers()
+        .get("If-None-Match")
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.trim() == "*")
+}
+
+/// Build the command context from the request
+///
+/// The idempotency key comes from the `Idempotency-Key` header, while the
+/// caller identity is only available when the API uses IAM authorization.
+/// API keys only exist for REST APIs, and the request context carries the
+/// key id but not its usage plan. The expected version comes from the
+/// `If-Match` header, holding the `ETag` of a previous read.
+fn command_context(event: &Request) -> CommandContext {
+    let idempotency_key = event
+        .headers()
+        .get("Idempotency-Key")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let expected_version = event
+        .headers()
+        .get("If-Match")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.trim()
+                .trim_start_matches("W/")
+                .trim_matches('"')
+                .to_string()
+        });
+    let (caller, api_key_id) = match event.request_context() {
+        RequestContext::ApiGatewayV2(ctx) => (ctx.authorizer.and_then(|a| a.iam?.user_arn), None),
+        RequestContext::ApiGatewayV1(ctx) => (ctx.identity.user_arn, ctx.identity.api_key_id),
+        _ => (None, None),
+    };
+    if let Some(api_key_id) = &api_key_id {
+        info!("Request made with API key {}", api_key_id);
+    }
+
+    CommandContext {
+        idempotency_key,
+        caller,
+        api_key_id,
+        expected_version,
+    }
+}
+
+/// OAuth scopes of the caller
+///
+/// Scopes are only available when the API uses a JWT authorizer (HTTP APIs)
+/// or a Cognito user pool authorizer (REST APIs).
+fn caller_scopes(event: &Request) -> Vec<String> {
+    match event.request_context() {
+        RequestContext::ApiGatewayV2(ctx) => ctx
+            .authorizer
+            .and_then(|a| a.jwt?.scopes)
+            .unwrap_or_default(),
+        RequestContext::ApiGatewayV1(ctx) => ctx
+            .authorizer
+            .get("claims")
+            .and_then(|claims| claims.get("scope")?.as_str())
+            .map(|scope| scope.split(' ').map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Context of the events published while handling a request
+///
+/// Callers can pass their own correlation id in the `X-Correlation-Id`
+/// header. Otherwise, events are correlated by the X-Ray trace id of the
+/// request, and are caused by the API Gateway request.
+fn event_context(event: &Request) -> Option<EventContext> {
+    let header = |name: &str| {
+        event
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+    let request_id = match event.request_context() {
+        RequestContext::ApiGatewayV2(ctx) => Some(ctx.request_id),
+        RequestContext::ApiGatewayV1(ctx) => Some(ctx.request_id),
+        _ => None,
+    };
+
+    let correlation_id = header("x-correlation-id")
+        .or_else(|| header("x-amzn-trace-id"))
+        .or_else(|| request_id.clone())?;
+    let context = EventContext::new(correlation_id);
+    Some(match request_id {
+        Some(request_id) => context.with_causation(request_id),
+        None => context,
+    })
+}
+
+/// Parse a JSON request body
+///
+/// API Gateway REST APIs (payload format 1.0) and HTTP APIs (2.0) both go
+/// through `Request`, but clients and test consoles don't always send a
+/// `Content-Type` header. Bodies without one are parsed as JSON instead of
+/// being ignored.
+fn json_payload<T: DeserializeOwned>(event: &Request) -> Result<Option<T>, E> {
+    if event.headers().contains_key(CONTENT_TYPE) {
+        return Ok(event.payload()?);
+    }
+    let body: &[u8] = event.body().as_ref();
+    if body.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(body)?))
+}
+
+/// Parse an ISO 8601 duration
+///
+/// Only days, hours, minutes and seconds are supported, as years and months
+/// don't have a fixed length, e.g. `P1D`, `PT24H` or `P1DT12H30M`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.strip_prefix('P')?;
+    if value.is_empty() || value.ends_with('T') {
+        return None;
+    }
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let secs = parse_duration_units(date, &[('D', 86400)])?.checked_add(parse_duration_units(
+        time,
+        &[('H', 3600), ('M', 60), ('S', 1)],
+    )?)?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Sum the `<n><unit>` components of a duration, in the order of `units`
+fn parse_duration_units(mut value: &str, units: &[(char, u64)]) -> Option<u64> {
+    let mut secs: u64 = 0;
+    for (unit, factor) in units {
+        if let Some((n, rest)) = value.split_once(*unit) {
+            secs = secs.checked_add(n.parse::<u64>().ok()?.checked_mul(*factor)?)?;
+            value = rest;
+        }
+    }
+    value.is_empty().then_some(secs)
+}
+
+/// HTTP Response with a JSON payload
+fn response(status_code: StatusCode, body: String) -> Response<String> {
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lag::{ConsumerPosition, MemoryLagStore},
+        store::{
+            MemoryAuditStore, MemoryHistoryStore, MemoryPopularityStore, MemoryStore, StoreGet,
+            StoreGetAudit, StoreGetHistory, StorePut, StoreRecordAudit,
+        },
+        AuditAction, AuditEntry, ProductRange,
+    };
+
+    /// PATCH /1 as sent by a REST API (payload format 1.0)
+    const PATCH_V1: &str = r#"{
+        "resource": "/{id}",
+        "path": "/1",
+        "httpMethod": "PATCH",
+        "headers": {"Host": "abc.execute-api.eu-west-1.amazonaws.com"},
+        "multiValueHeaders": {"Host": ["abc.execute-api.eu-west-1.amazonaws.com"]},
+        "queryStringParameters": null,
+        "multiValueQueryStringParameters": null,
+        "pathParameters": {"id": "1"},
+        "stageVariables": null,
+        "requestContext": {
+            "accountId": "123456789012",
+            "apiId": "abc",
+            "resourceId": "xyz",
+            "resourcePath": "/{id}",
+            "httpMethod": "PATCH",
+            "path": "/prod/1",
+            "protocol": "HTTP/1.1",
+            "requestId": "c6af9ac6-7b61-11e6-9a41-93e8deadbeef",
+            "stage": "prod",
+            "identity": {
+                "sourceIp": "192.0.2.1",
+                "userAgent": "curl/7.79.1",
+                "userArn": "arn:aws:iam::123456789012:user/alice",
+                "apiKeyId": "abcdef1234"
+            }
+        },
+        "body": "{\"price\": 12.5}",
+        "isBase64Encoded": false
+    }"#;
+
+    /// PATCH /1 as sent by an HTTP API (payload format 2.0)
+    const PATCH_V2: &str = r#"{
+        "version": "2.0",
+        "routeKey": "PATCH /{id}",
+        "rawPath": "/1",
+        "rawQueryString": "",
+        "headers": {"host": "abc.execute-api.eu-west-1.amazonaws.com"},
+        "requestContext": {
+            "accountId": "123456789012",
+            "apiId": "abc",
+            "authorizer": {
+                "iam": {"userArn": "arn:aws:iam::123456789012:user/alice"}
+            },
+            "domainName": "abc.execute-api.eu-west-1.amazonaws.com",
+            "domainPrefix": "abc",
+            "http": {
+                "method": "PATCH",
+                "path": "/1",
+                "protocol": "HTTP/1.1",
+                "sourceIp": "192.0.2.1",
+                "userAgent": "curl/7.79.1"
+            },
+            "requestId": "JKJaXmPLvHcESHA=",
+            "routeKey": "PATCH /{id}",
+            "stage": "$default",
+            "time": "10/Mar/2022:10:00:00 +0000",
+            "timeEpoch": 1646906400000
+        },
+        "pathParameters": {"id": "1"},
+        "body": "{\"price\": 12.5}",
+        "isBase64Encoded": false
+    }"#;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT24H"), Some(Duration::from_secs(86400)));
+        assert_eq!(
+            parse_duration("P1DT12H30M"),
+            Some(Duration::from_secs(131400))
+        );
+        assert_eq!(parse_duration("PT90S"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("P1M"), None);
+        assert_eq!(parse_duration("PT"), None);
+        assert_eq!(parse_duration("24H"), None);
+        assert_eq!(parse_duration("PT30M1H"), None);
+    }
+
+    /// GET /1 as sent by an HTTP API with a JWT authorizer
+    fn get_event(view: &str, scopes: &[&str]) -> Result<Request, E> {
+        let event = json!({
+            "version": "2.0",
+            "routeKey": "GET /{id}",
+            "rawPath": "/1",
+            "rawQueryString": format!("view={}", view),
+            "headers": {"host": "abc.execute-api.eu-west-1.amazonaws.com"},
+            "queryStringParameters": {"view": view},
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "abc",
+                "authorizer": {
+                    "jwt": {"claims": {"sub": "alice"}, "scopes": scopes}
+                },
+                "domainName": "abc.execute-api.eu-west-1.amazonaws.com",
+                "domainPrefix": "abc",
+                "http": {
+                    "method": "GET",
+                    "path": "/1",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "192.0.2.1",
+                    "userAgent": "curl/7.79.1"
+                },
+                "requestId": "JKJaXmPLvHcESHA=",
+                "routeKey": "GET /{id}",
+                "stage": "$default",
+                "time": "10/Mar/2022:10:00:00 +0000",
+                "timeEpoch": 1646906400000
+            },
+            "pathParameters": {"id": "1"},
+            "isBase64Encoded": false
+        });
+        Ok(lambda_http::request::from_str(&event.to_string())?)
+    }
+
+    /// Store with a product with custom attributes, and its audit log
+    async fn get_stores() -> Result<(MemoryStore, MemoryAuditStore), E> {
+        let store = MemoryStore::new();
+        store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: [("cost".to_string(), json!(4.0))].into_iter().collect(),
+                images: Default::default(),
+            })
+            .await?;
+        let audit = MemoryAuditStore::new();
+        for (timestamp, caller) in [(1000, "alice"), (2000, "bob")] {
+            audit
+                .record(&AuditEntry {
+                    id: "1".to_string(),
+                    timestamp,
+                    action: AuditAction::Put,
+                    caller: Some(caller.to_string()),
+                    api_key_id: None,
+                })
+                .await?;
+        }
+        Ok((store, audit))
+    }
+
+    #[tokio::test]
+    async fn test_get_product_public_view() -> Result<(), E> {
+        // GIVEN a store with a product
+        let (store, audit) = get_stores().await?;
+        let hits = MemoryPopularityStore::new();
+
+        // WHEN getting the public view of the product
+        let res = get_product(&store, &audit, &hits, get_event("public", &[])?)
+            .await?
+            .into_response();
+
+        // THEN only the public fields are returned
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(body, json!({"id": "1", "name": "foo", "price": 10.0}));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_product_admin_view() -> Result<(), E> {
+        // GIVEN a store with a product updated twice
+        let (store, audit) = get_stores().await?;
+        let hits = MemoryPopularityStore::new();
+
+        // WHEN getting the admin view of the product with the admin scope
+        let res = get_product(&store, &audit, &hits, get_event("admin", &[ADMIN_SCOPE])?)
+            .await?
+            .into_response();
+
+        // THEN the audit details are returned
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(body["attributes"]["cost"], 4.0);
+        assert_eq!(body["version"], 2);
+        assert_eq!(body["created_at"], 1000);
+        assert_eq!(body["updated_at"], 2000);
+        assert_eq!(body["updated_by"], "bob");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_product_admin_view_forbidden() -> Result<(), E> {
+        // GIVEN a store with a product
+        let (store, audit) = get_stores().await?;
+        let hits = MemoryPopularityStore::new();
+
+        // WHEN getting the admin view of the product without the admin scope
+        let res = get_product(
+            &store,
+            &audit,
+            &hits,
+            get_event("admin", &["products/read"])?,
+        )
+        .await?
+        .into_response();
+
+        // THEN the request is forbidden
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    async fn assert_patch(event: &str) -> Result<AuditEntry, E> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let event = lambda_http::request::from_str(event)?;
+
+        // WHEN patching the product
+        let res = patch_product(&store, &store, &history, &audit, event)
+            .await?
+            .into_response();
+
+        // THEN the product is updated
+        assert_eq!(res.status(), StatusCode::OK);
+        let product: Product = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(product.price, 12.5);
+        // AND the caller is recorded in the audit log
+        let mut entries = audit.entries("1").await?;
+        assert_eq!(
+            entries[0].caller.as_deref(),
+            Some("arn:aws:iam::123456789012:user/alice")
+        );
+
+        Ok(entries.remove(0))
+    }
+
+    #[test]
+    fn test_request_path() -> Result<(), E> {
+        // GIVEN the same request sent to a named stage of each kind of API
+        let mut v1: Value = serde_json::from_str(PATCH_V1)?;
+        v1["requestContext"]["stage"] = json!("prod");
+        let mut v2: Value = serde_json::from_str(PATCH_V2)?;
+        v2["rawPath"] = json!("/prod/1");
+        v2["requestContext"]["http"]["path"] = json!("/prod/1");
+        v2["requestContext"]["stage"] = json!("prod");
+
+        // WHEN reading their path
+        let v1 = request_path(&lambda_http::request::from_str(&v1.to_string())?);
+        let v2 = request_path(&lambda_http::request::from_str(&v2.to_string())?);
+
+        // THEN the stage isn't part of it
+        assert_eq!(v1, "/1");
+        assert_eq!(v2, "/1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_v1() -> Result<(), E> {
+        let entry = assert_patch(PATCH_V1).await?;
+
+        // AND the API key is recorded in the audit log
+        assert_eq!(entry.api_key_id.as_deref(), Some("abcdef1234"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_v2() -> Result<(), E> {
+        let entry = assert_patch(PATCH_V2).await?;
+
+        // AND HTTP APIs don't have API keys
+        assert_eq!(entry.api_key_id, None);
+
+        Ok(())
+    }
+
+    /// PATCH /1 with an `If-Match` header, as sent by a REST API
+    fn get_conditional_patch_event(etag: &str) -> Result<Request, E> {
+        let mut event: serde_json::Value = serde_json::from_str(PATCH_V1)?;
+        event["headers"]["If-Match"] = json!(etag);
+        event["multiValueHeaders"]["If-Match"] = json!([etag]);
+        Ok(lambda_http::request::from_str(&event.to_string())?)
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_if_match() -> Result<(), E> {
+        // GIVEN a store with a product, and its ETag
+        let (store, audit) = get_stores().await?;
+        let history = MemoryHistoryStore::new();
+        let res = get_product(
+            &store,
+            &audit,
+            &MemoryPopularityStore::new(),
+            get_event("public", &[])?,
+        )
+        .await?
+        .into_response();
+        let etag = res.headers()[ETAG].to_str()?.to_string();
+
+        // WHEN patching the product with a stale ETag, then the current one
+        let stale = patch_product(
+            &store,
+            &store,
+            &history,
+            &audit,
+            get_conditional_patch_event("\"0123\"")?,
+        )
+        .await?
+        .into_response();
+        let current = patch_product(
+            &store,
+            &store,
+            &history,
+            &audit,
+            get_conditional_patch_event(&etag)?,
+        )
+        .await?
+        .into_response();
+
+        // THEN the stale request fails without changing the product
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        // AND the other one updates it
+        assert_eq!(current.status(), StatusCode::OK);
+        let product = store.get("1", store::ReadConsistency::Strong, None).await?;
+        assert_eq!(product.unwrap().price, 12.5);
+        assert_eq!(history.history("1").await?.len(), 1);
+
+        Ok(())
+    }
+
+    /// PUT /1 with `If-None-Match: *`, as sent by a REST API
+    fn get_create_event(price: f64) -> Result<Request, E> {
+        let mut event: serde_json::Value = serde_json::from_str(PATCH_V1)?;
+        event["httpMethod"] = json!("PUT");
+        event["requestContext"]["httpMethod"] = json!("PUT");
+        event["headers"]["If-None-Match"] = json!("*");
+        event["multiValueHeaders"]["If-None-Match"] = json!(["*"]);
+        event["body"] = json!(json!({"id": "1", "name": "foo", "price": price}).to_string());
+        Ok(lambda_http::request::from_str(&event.to_string())?)
+    }
+
+    #[tokio::test]
+    async fn test_put_product_create_only() -> Result<(), E> {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let idempotency = crate::idempotency::MemoryIdempotencyStore::new();
+
+        // WHEN creating the same product twice
+        let first = put_product(
+            &store,
+            &store,
+            &history,
+            &audit,
+            &idempotency,
+            get_create_event(10.0)?,
+        )
+        .await?
+        .into_response();
+        let second = put_product(
+            &store,
+            &store,
+            &history,
+            &audit,
+            &idempotency,
+            get_create_event(20.0)?,
+        )
+        .await?
+        .into_response();
+
+        // THEN the first request creates the product
+        assert_eq!(first.status(), StatusCode::CREATED);
+        // AND the second one fails without changing it
+        assert_eq!(second.status(), StatusCode::PRECONDITION_FAILED);
+        let product = store.get("1", store::ReadConsistency::Strong, None).await?;
+        assert_eq!(product.unwrap().price, 10.0);
+        assert_eq!(audit.entries("1").await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_products() -> Result<(), E> {
+        // GIVEN a store with products in two categories
+        let store = MemoryStore::new();
+        for (id, category) in [("1", "shoes"), ("2", "hats")] {
+            store
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: [("category".to_string(), json!(category))]
+                        .into_iter()
+                        .collect(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+        let event = lambda_http::request::from_str(
+            &json!({
+                "version": "2.0",
+                "routeKey": "POST /query",
+                "rawPath": "/query",
+                "rawQueryString": "",
+                "headers": {"host": "abc.execute-api.eu-west-1.amazonaws.com"},
+                "requestContext": {
+                    "accountId": "123456789012",
+                    "apiId": "abc",
+                    "domainName": "abc.execute-api.eu-west-1.amazonaws.com",
+                    "domainPrefix": "abc",
+                    "http": {
+                        "method": "POST",
+                        "path": "/query",
+                        "protocol": "HTTP/1.1",
+                        "sourceIp": "192.0.2.1",
+                        "userAgent": "curl/7.79.1"
+                    },
+                    "requestId": "JKJaXmPLvHcESHA=",
+                    "routeKey": "POST /query",
+                    "stage": "$default",
+                    "time": "10/Mar/2022:10:00:00 +0000",
+                    "timeEpoch": 1646906400000
+                },
+                "body": r#"{"category": "shoes", "max_price": 20, "limit": 10}"#,
+                "isBase64Encoded": false
+            })
+            .to_string(),
+        )?;
+
+        // WHEN querying shoes
+        let res = find_products(&store, event).await?.into_response();
+
+        // THEN only the matching product is returned
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: ProductRange = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(body.products.len(), 1);
+        assert_eq!(body.products[0].id, "1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_consumers() -> Result<(), E> {
+        // GIVEN a consumer that recorded its position
+        let lag = MemoryLagStore::new();
+        lag.record(&ConsumerPosition {
+            consumer: "search-index".to_string(),
+            sequence: "200".to_string(),
+            event_time: Some(1_000),
+            recorded_at: 2_500,
+        })
+        .await?;
+
+        // WHEN listing the consumers
+        let res = get_consumers(&lag, get_event("public", &[])?)
+            .await?
+            .into_response();
+
+        // THEN the consumer is returned with its lag
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body().as_ref())?;
+        assert_eq!(body["consumers"][0]["consumer"], "search-index");
+        assert_eq!(body["consumers"][0]["sequence"], "200");
+        assert_eq!(body["consumers"][0]["lag_ms"], 1_500);
+
+        Ok(())
+    }
 }
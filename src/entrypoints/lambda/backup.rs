@@ -0,0 +1,251 @@
+//! # Backup handler
+//!
+//! Exports the product catalog to S3 as JSON lines, or restores it from a
+//! previous export. This is invoked on demand by operators, for example:
+//!
+//! ```bash
+//! aws lambda invoke --function-name $BACKUP_FUNCTION \
+//!     --payload '{"action": "export", "key": "backups/products.jsonl"}' out.json
+//! ```
+//!
+//! Products can also be seeded from a point-in-time recovery export of a
+//! DynamoDB table, with the `import-dynamodb-export` action and the key of the
+//! `manifest-files.json` file of the export.
+//!
+//! Imports accept `"dry_run": true` to preview the changes without writing
+//! anything: every row is validated, and the response reports what the
+//! import would do with it.
+
+use crate::{
+    domain::{self, ImportOutcome, ImportRow},
+    object_store::ObjectStore,
+    store::{StoreBatchGet, StorePutMany, StoreScanAll},
+};
+use lambda_runtime::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Backup operation requested by the caller
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum BackupRequest {
+    /// Export all products to the given key
+    Export { key: String },
+    /// Import all products from the given key
+    Import {
+        key: String,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Import all products from a native DynamoDB export, given the key of
+    /// its `manifest-files.json` file
+    #[serde(rename = "import-dynamodb-export")]
+    ImportDynamoDBExport {
+        key: String,
+        #[serde(default)]
+        dry_run: bool,
+    },
+}
+
+/// Result of a backup operation
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BackupResponse {
+    /// Number of exported or imported products, or of products a dry run
+    /// would import
+    pub count: usize,
+    /// Report of each row, for dry runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<ImportRow>>,
+}
+
+/// Run a backup operation
+///
+/// Dry runs compare the imported products with the ones in `existing`.
+#[instrument(skip(source, target, existing, objects))]
+pub async fn backup(
+    source: &dyn StoreScanAll,
+    target: &dyn StorePutMany,
+    existing: &dyn StoreBatchGet,
+    objects: &dyn ObjectStore,
+    event: BackupRequest,
+    _: Context,
+) -> Result<BackupResponse, E> {
+    let count = match &event {
+        BackupRequest::Export { key } => {
+            info!("Exporting products to {}", key);
+            domain::export_products_to_s3(source, objects, key).await?
+        }
+        BackupRequest::Import {
+            key,
+            dry_run: false,
+        } => {
+            info!("Importing products from {}", key);
+            domain::import_products_from_s3(target, objects, key).await?
+        }
+        BackupRequest::ImportDynamoDBExport {
+            key,
+            dry_run: false,
+        } => {
+            info!("Importing products from DynamoDB export {}", key);
+            domain::import_products_from_dynamodb_export(target, objects, key).await?
+        }
+        BackupRequest::Import { key, dry_run: true } => {
+            info!("Previewing import from {}", key);
+            let rows = domain::preview_import_from_s3(existing, objects, key).await?;
+            return Ok(preview_response(rows));
+        }
+        BackupRequest::ImportDynamoDBExport { key, dry_run: true } => {
+            info!("Previewing import from DynamoDB export {}", key);
+            let rows = domain::preview_import_from_dynamodb_export(existing, objects, key).await?;
+            return Ok(preview_response(rows));
+        }
+    };
+    info!("Processed {} products", count);
+
+    Ok(BackupResponse { count, rows: None })
+}
+
+/// Response of a dry run, counting the rows that would be imported
+fn preview_response(rows: Vec<ImportRow>) -> BackupResponse {
+    let count = rows
+        .iter()
+        .filter(|row| !matches!(row.outcome, ImportOutcome::Invalid { .. }))
+        .count();
+    info!("Previewed {} rows, {} valid", rows.len(), count);
+
+    BackupResponse {
+        count,
+        rows: Some(rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        object_store::MemoryObjectStore,
+        store::{MemoryStore, StoreCount, StorePut},
+        Product,
+    };
+
+    #[test]
+    fn test_parse_request() {
+        let request: BackupRequest =
+            serde_json::from_str(r#"{"action": "import", "key": "products.jsonl"}"#).unwrap();
+
+        assert_eq!(
+            request,
+            BackupRequest::Import {
+                key: "products.jsonl".to_string(),
+                dry_run: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backup_export() -> Result<(), E> {
+        // GIVEN a store with one product
+        let store = MemoryStore::new();
+        store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+        let objects = MemoryObjectStore::new();
+
+        // WHEN exporting the products
+        let res = backup(
+            &store,
+            &store,
+            &store,
+            &objects,
+            BackupRequest::Export {
+                key: "products.jsonl".to_string(),
+            },
+            Context::default(),
+        )
+        .await?;
+
+        // THEN the product is written to the object store
+        assert_eq!(
+            res,
+            BackupResponse {
+                count: 1,
+                rows: None
+            }
+        );
+        assert!(!objects.get_object("products.jsonl").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup_import_dry_run() -> Result<(), E> {
+        // GIVEN a store with two products
+        let store = MemoryStore::new();
+        for (id, price) in [("1", 10.0), ("2", 20.0)] {
+            store
+                .put(&Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                })
+                .await?;
+        }
+        // AND a file changing, keeping, adding and repeating products
+        let objects = MemoryObjectStore::new();
+        objects
+            .put_object(
+                "products.jsonl",
+                br#"{"id": "1", "name": "foo", "price": 12.0}
+{"id": "2", "name": "foo", "price": 20.0}
+{"id": "3", "name": "bar", "price": 5.0}
+{"id": "3", "name": "bar", "price": 6.0}
+{"id": "4", "name": " ", "price": 5.0}
+not json
+"#
+                .to_vec(),
+            )
+            .await?;
+
+        // WHEN previewing the import
+        let res = backup(
+            &store,
+            &store,
+            &store,
+            &objects,
+            serde_json::from_str(
+                r#"{"action": "import", "key": "products.jsonl", "dry_run": true}"#,
+            )?,
+            Context::default(),
+        )
+        .await?;
+
+        // THEN each row is reported
+        let rows: Vec<ImportOutcome> = res
+            .rows
+            .unwrap()
+            .into_iter()
+            .map(|row| row.outcome)
+            .collect();
+        assert!(matches!(&rows[0], ImportOutcome::Update { fields } if fields[0].field == "price"));
+        assert_eq!(rows[1], ImportOutcome::Unchanged);
+        assert_eq!(rows[2], ImportOutcome::Duplicate { replaced_by: 4 });
+        assert_eq!(rows[3], ImportOutcome::Create);
+        assert!(matches!(rows[4], ImportOutcome::Invalid { .. }));
+        assert!(matches!(rows[5], ImportOutcome::Invalid { .. }));
+        assert_eq!(res.count, 4);
+        // AND nothing was written
+        assert_eq!(store.count().await?, 2);
+
+        Ok(())
+    }
+}
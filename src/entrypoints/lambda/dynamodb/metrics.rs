@@ -0,0 +1,259 @@
+//! # Stream metrics
+//!
+//! Registry of the metrics of the stream processor: records processed,
+//! records that couldn't be converted to events, publish latency and batch
+//! sizes. Each batch is flushed to CloudWatch as a line in the embedded
+//! metric format, and the running totals can be served in the OpenMetrics
+//! text format on a local port, for a Lambda extension or a sidecar to
+//! scrape.
+//!
+//! The listener only answers while the execution environment runs, i.e.
+//! during invocations, which is when extensions receive their events.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+/// CloudWatch namespace of the stream metrics
+const METRICS_NAMESPACE: &str = "rust-products";
+
+/// Metrics of a single batch of records
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchMetrics {
+    /// Records in the batch, excluding TTL deletions
+    pub records: u64,
+    /// Records that couldn't be converted to events
+    pub conversion_failures: u64,
+    /// Time spent sending events and flushing the bus, in milliseconds
+    pub publish_latency_ms: u64,
+    /// Time spent processing the batch, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl BatchMetrics {
+    /// Records processed per second during the batch
+    pub fn records_per_second(&self) -> f64 {
+        if self.duration_ms == 0 {
+            return 0.0;
+        }
+        self.records as f64 * 1000.0 / self.duration_ms as f64
+    }
+
+    /// Metrics of the batch in the CloudWatch embedded metric format
+    fn emf_line(&self, timestamp: u64) -> String {
+        serde_json::json!({
+            "_aws": {
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [[]],
+                    "Metrics": [
+                        {"Name": "StreamRecords", "Unit": "Count"},
+                        {"Name": "StreamRecordsPerSecond", "Unit": "Count/Second"},
+                        {"Name": "ConversionFailures", "Unit": "Count"},
+                        {"Name": "PublishLatency", "Unit": "Milliseconds"},
+                    ],
+                }],
+            },
+            "StreamRecords": self.records,
+            "StreamRecordsPerSecond": self.records_per_second(),
+            "ConversionFailures": self.conversion_failures,
+            "PublishLatency": self.publish_latency_ms,
+        })
+        .to_string()
+    }
+}
+
+/// Running count, sum and maximum of observed values
+#[derive(Default)]
+struct Summary {
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Summary {
+    fn observe(&self, value: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        out.push_str(&format!(
+            "# TYPE {} summary\n# HELP {} {}\n",
+            name, name, help
+        ));
+        out.push_str(&format!(
+            "{}_count {}\n{}_sum {}\n",
+            name,
+            self.count.load(Ordering::Relaxed),
+            name,
+            self.sum.load(Ordering::Relaxed)
+        ));
+    }
+}
+
+/// Metrics registry of the stream processor
+#[derive(Default)]
+pub struct StreamMetrics {
+    records: AtomicU64,
+    conversion_failures: AtomicU64,
+    batch_size: Summary,
+    publish_latency: Summary,
+    /// Records per second of the last batch, as the bits of an `f64`
+    records_per_second: AtomicU64,
+}
+
+impl StreamMetrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the metrics of a batch, and flush them as an EMF line
+    pub fn record(&self, batch: &BatchMetrics) {
+        self.records.fetch_add(batch.records, Ordering::Relaxed);
+        self.conversion_failures
+            .fetch_add(batch.conversion_failures, Ordering::Relaxed);
+        self.batch_size.observe(batch.records);
+        self.publish_latency.observe(batch.publish_latency_ms);
+        self.records_per_second
+            .store(batch.records_per_second().to_bits(), Ordering::Relaxed);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        println!("{}", batch.emf_line(timestamp));
+    }
+
+    /// Running totals in the OpenMetrics text format
+    pub fn openmetrics(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "# TYPE stream_records counter\n# HELP stream_records Records processed\nstream_records_total {}\n",
+            self.records.load(Ordering::Relaxed)
+        ));
+        out.push_str(&format!(
+            "# TYPE stream_conversion_failures counter\n# HELP stream_conversion_failures Records that couldn't be converted to events\nstream_conversion_failures_total {}\n",
+            self.conversion_failures.load(Ordering::Relaxed)
+        ));
+        out.push_str(&format!(
+            "# TYPE stream_records_per_second gauge\n# HELP stream_records_per_second Throughput of the last batch\nstream_records_per_second {}\n",
+            f64::from_bits(self.records_per_second.load(Ordering::Relaxed))
+        ));
+        self.batch_size
+            .write(&mut out, "stream_batch_size", "Records per batch");
+        out.push_str(&format!(
+            "# TYPE stream_batch_size_max gauge\n# HELP stream_batch_size_max Largest batch\nstream_batch_size_max {}\n",
+            self.batch_size.max.load(Ordering::Relaxed)
+        ));
+        self.publish_latency.write(
+            &mut out,
+            "stream_publish_latency_milliseconds",
+            "Time spent publishing the events of a batch",
+        );
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Serve the metrics on `127.0.0.1:port`
+    ///
+    /// Every request gets the OpenMetrics text, whatever its path.
+    pub async fn serve(self: Arc<Self>, port: u16) -> std::io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        info!("Serving stream metrics on port {}", port);
+        Ok(tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        warn!("Unable to accept metrics connection: {}", err);
+                        continue;
+                    }
+                };
+                let body = self.openmetrics();
+                tokio::spawn(async move {
+                    // The request itself doesn't matter, only read its head
+                    let mut buf = [0; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    if let Err(err) = socket.write_all(response.as_bytes()).await {
+                        warn!("Unable to write metrics: {}", err);
+                    }
+                });
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_emf_line() {
+        // GIVEN a batch of 50 records processed in 250 ms
+        let batch = BatchMetrics {
+            records: 50,
+            conversion_failures: 1,
+            publish_latency_ms: 120,
+            duration_ms: 250,
+        };
+
+        // WHEN formatting its metrics
+        let line: Value = serde_json::from_str(&batch.emf_line(1_000)).unwrap();
+
+        // THEN the throughput and latency are reported
+        assert_eq!(line["StreamRecordsPerSecond"], 200.0);
+        assert_eq!(line["PublishLatency"], 120);
+        assert_eq!(line["ConversionFailures"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_serve() -> std::io::Result<()> {
+        // GIVEN a registry with two batches, served on a local port
+        let metrics = Arc::new(StreamMetrics::new());
+        for records in [10, 30] {
+            metrics.record(&BatchMetrics {
+                records,
+                publish_latency_ms: 5,
+                duration_ms: 100,
+                ..Default::default()
+            });
+        }
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+            listener.local_addr()?.port()
+        };
+        metrics.clone().serve(port).await?;
+
+        // WHEN scraping the metrics
+        let body = reqwest::get(format!("http://127.0.0.1:{}/metrics", port))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        // THEN the totals are returned in the OpenMetrics format
+        assert!(body.contains("stream_records_total 40\n"));
+        assert!(body.contains("stream_batch_size_count 2\n"));
+        assert!(body.contains("stream_batch_size_max 30\n"));
+        assert!(body.contains("stream_publish_latency_milliseconds_sum 10\n"));
+        assert!(body.ends_with("# EOF\n"));
+
+        Ok(())
+    }
+}
@@ -1,16 +1,152 @@
-use crate::{domain, event_bus::EventBus, Event};
+use crate::{
+    consumer::{self, Checkpoints, Idempotency},
+    domain,
+    event_bus::{context, EventBus, EventContext},
+    lag::{self, LagStore},
+    store::{ItemDecoder, StoreAppendChanges, StoreIndex, StoreRecordPrices},
+    Change, Event,
+};
 use lambda_runtime::Context;
 use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 use tracing::{info, instrument};
 
+pub mod metrics;
 pub mod model;
 
+use metrics::{BatchMetrics, StreamMetrics};
+
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
 /// Parse events from DynamoDB Streams
-#[instrument(skip(event_bus, event))]
+///
+/// Images are decoded with `decoder`, which must use the codec and the key
+/// prefix of the store writing the table. Records of other namespaces are
+/// skipped, as they belong to other instances sharing the table.
+///
+/// Records that were already dispatched, e.g. when Lambda retries a batch
+/// after a partial failure, are skipped: records at or before the checkpoint
+/// of their item first, then the remaining ones based on their event id.
+///
+/// The event bus is flushed before the records are marked as processed, so
+/// that events it buffers are sent within the invocation.
+///
+/// Each event gets a deterministic id derived from its record, so that the
+/// event bus can drop events a retried batch already published.
+///
+/// The metrics of the batch are added to `metrics`, whether it succeeds or
+/// not.
+#[instrument(skip(decoder, event_bus, idempotency, checkpoints, metrics, event))]
 pub async fn parse_events(
+    decoder: &ItemDecoder,
     event_bus: &dyn EventBus<E = Event>,
+    idempotency: &dyn Idempotency,
+    checkpoints: &dyn Checkpoints,
+    metrics: &StreamMetrics,
+    event: model::DynamoDBEvent,
+    ctx: Context,
+) -> Result<(), E> {
+    let started = Instant::now();
+    let publish_latency = AtomicU64::new(0);
+
+    info!("Transform events");
+    let results = event
+        .records
+        .par_iter()
+        .filter(|record| !record.is_purge() && record.belongs_to(decoder))
+        .map(|record| {
+            Ok((
+                record.partition()?.to_string(),
+                record.dynamodb.sequence_number.clone(),
+                (record.event_id.clone(), record.to_event(decoder)?),
+            ))
+        })
+        .collect::<Vec<Result<(String, String, (String, Event)), crate::Error>>>();
+    let mut batch = BatchMetrics {
+        records: results.len() as u64,
+        conversion_failures: results.iter().filter(|r| r.is_err()).count() as u64,
+        ..Default::default()
+    };
+
+    let event_ids: Vec<(Event, String)> = results
+        .iter()
+        .flatten()
+        .map(|(_, sequence_number, (_, event))| {
+            (
+                event.clone(),
+                context::event_id(sequence_number, event.event_type()),
+            )
+        })
+        .collect();
+
+    let dispatch = async {
+        let events = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        info!("Dispatching {} events", events.len());
+        let publish_latency = &publish_latency;
+        let count = consumer::process_after_checkpoints(checkpoints, events, |events| async move {
+            let count = consumer::process_once(idempotency, events, |events| async move {
+                let publish_started = Instant::now();
+                domain::send_events(event_bus, &events).await?;
+                let res = event_bus.flush().await;
+                publish_latency.store(
+                    publish_started.elapsed().as_millis() as u64,
+                    Ordering::Relaxed,
+                );
+                res
+            })
+            .await?;
+            info!("Done dispatching {} new events", count);
+            Ok(())
+        })
+        .await?;
+        info!("Done processing {} events after their checkpoint", count);
+        Ok::<(), crate::Error>(())
+    };
+    let res = EventContext::from_invocation(&ctx.request_id, &ctx.xray_trace_id)
+        .with_event_ids(event_ids.iter().map(|(event, id)| (event, id.clone())))
+        .scope(dispatch)
+        .await;
+
+    batch.publish_latency_ms = publish_latency.load(Ordering::Relaxed);
+    batch.duration_ms = started.elapsed().as_millis() as u64;
+    metrics.record(&batch);
+
+    res?;
+    Ok(())
+}
+
+/// Record changes from DynamoDB Streams in the change store
+#[instrument(skip(decoder, store, event))]
+pub async fn record_changes(
+    decoder: &ItemDecoder,
+    store: &dyn StoreAppendChanges,
+    event: model::DynamoDBEvent,
+    _: Context,
+) -> Result<(), E> {
+    info!("Transform changes");
+    let changes = event
+        .records
+        .par_iter()
+        .filter(|record| !record.is_purge() && record.belongs_to(decoder))
+        .map(|record| record.to_change(decoder))
+        .collect::<Result<Vec<Change>, _>>()?;
+
+    info!("Recording {} changes", changes.len());
+    store.append(&changes).await?;
+    info!("Done recording changes");
+
+    Ok(())
+}
+
+/// Record price changes from DynamoDB Streams in the price history
+///
+/// Price points are timestamped with the approximate time of the write.
+#[instrument(skip(decoder, store, event))]
+pub async fn record_price_history(
+    decoder: &ItemDecoder,
+    store: &dyn StoreRecordPrices,
     event: model::DynamoDBEvent,
     _: Context,
 ) -> Result<(), E> {
@@ -18,12 +154,66 @@ pub async fn parse_events(
     let events = event
         .records
         .par_iter()
-        .map(|record| record.try_into())
-        .collect::<Result<Vec<_>, _>>()?;
+        .filter(|record| !record.is_purge() && record.belongs_to(decoder))
+        .map(|record| {
+            let time = record
+                .dynamodb
+                .approximate_creation_date_time
+                .map(|t| (t * 1000.0) as u64)
+                .ok_or(crate::Error::InternalError("Missing creation time"))?;
+            Ok((time, record.to_event(decoder)?))
+        })
+        .collect::<Result<Vec<(u64, Event)>, crate::Error>>()?;
+
+    let count = domain::record_price_changes(store, &events).await?;
+    info!("Done recording {} price changes", count);
+
+    Ok(())
+}
+
+/// Name of the search indexer in the lag store
+const SEARCH_INDEX_CONSUMER: &str = "search-index";
+
+/// Update the search index from DynamoDB Streams
+///
+/// Once the batch is indexed, the position of its last record is recorded
+/// to monitor the lag of the index.
+#[instrument(skip(decoder, index, lag, event))]
+pub async fn index_products(
+    decoder: &ItemDecoder,
+    index: &dyn StoreIndex,
+    lag: &dyn LagStore,
+    event: model::DynamoDBEvent,
+    _: Context,
+) -> Result<(), E> {
+    info!("Transform events");
+    let events = event
+        .records
+        .par_iter()
+        .filter(|record| !record.is_purge() && record.belongs_to(decoder))
+        .map(|record| record.to_event(decoder))
+        .collect::<Result<Vec<Event>, _>>()?;
+
+    info!("Indexing {} events", events.len());
+    domain::index_events(index, &events).await?;
+    info!("Done indexing events");
 
-    info!("Dispatching {} events", events.len());
-    domain::send_events(event_bus, &events).await?;
-    info!("Done dispatching events");
+    let last = event.records.iter().max_by(|a, b| {
+        consumer::compare_sequences(&a.dynamodb.sequence_number, &b.dynamodb.sequence_number)
+    });
+    if let Some(record) = last {
+        let event_time = record
+            .dynamodb
+            .approximate_creation_date_time
+            .map(|t| (t * 1000.0) as u64);
+        lag::record_position(
+            lag,
+            SEARCH_INDEX_CONSUMER,
+            &record.dynamodb.sequence_number,
+            event_time,
+        )
+        .await;
+    }
 
     Ok(())
 }
@@ -3,12 +3,17 @@
 //! Models for the DynamoDB event entrypoint.
 //!
 //! We cannot use the models provided by the AWS SDK for Rust, as they do not
-//! implement the `serde::Serialize` and `serde::Deserialize` traits.
+//! implement the `serde::Serialize` and `serde::Deserialize` traits. Images
+//! are converted into SDK attribute values to be decoded by the
+//! `ItemDecoder` of the store, so that they decode like the items it reads.
 
 use crate::{
-    model::{Event, Product},
+    model::{Change, Event, Product},
+    store::ItemDecoder,
     Error,
 };
+use aws_sdk_dynamodb::{model::AttributeValue as SdkAttributeValue, Blob};
+use aws_smithy_types::base64;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -42,28 +47,181 @@ pub struct DynamoDBRecord {
     pub event_version: String,
 }
 
-impl TryFrom<&DynamoDBRecord> for Event {
-    type Error = Error;
+/// Attribute set on soft-deleted items by the DynamoDB store
+const DELETED_AT: &str = "deleted_at";
 
-    /// Try converting a DynamoDB record to an event.
-    fn try_from(value: &DynamoDBRecord) -> Result<Self, Self::Error> {
-        match value.event_name.as_str() {
+/// TTL attribute set on items scheduled for removal by the DynamoDB store
+const EXPIRES_AT: &str = "expires_at";
+
+/// Attribute holding the shard number of the items of sharded products
+const SHARD: &str = "shard";
+
+/// Attribute holding the time of the writes to sharded products
+const UPDATED_AT: &str = "updated_at";
+
+impl DynamoDBRecord {
+    /// Whether this record is the removal of an already soft-deleted item
+    ///
+    /// A `Deleted` event was already sent when the item was soft-deleted, so
+    /// these records should not produce another event.
+    pub fn is_purge(&self) -> bool {
+        self.event_name == "REMOVE" && self.dynamodb.old_image.contains_key(DELETED_AT)
+    }
+
+    /// Partition of the record, for checkpoints
+    ///
+    /// This is the key of the item, as the records of an item are delivered
+    /// in order.
+    pub fn partition(&self) -> Result<&str, Error> {
+        self.dynamodb
+            .keys
+            .get("id")
+            .and_then(|id| id.as_s())
+            .ok_or(Error::InternalError("Missing key in record"))
+    }
+
+    /// Whether the item of this record belongs to the namespace of `decoder`
+    ///
+    /// Multiple instances of the application can share the same table, and
+    /// each of them should only handle the records of its own items.
+    pub fn belongs_to(&self, decoder: &ItemDecoder) -> bool {
+        self.partition()
+            .map(|key| decoder.owns(key))
+            .unwrap_or(false)
+    }
+
+    /// Whether this record is a write to one of the shards of a product
+    ///
+    /// Other changes to shards only modify their `deleted_at` and
+    /// `expires_at` attributes, and keep the time of their last write.
+    fn is_shard_write(&self) -> bool {
+        let new_image = &self.dynamodb.new_image;
+        let old_image = &self.dynamodb.old_image;
+        let updated_at =
+            |image: &HashMap<String, AttributeValue>| image.get(UPDATED_AT).and_then(|v| v.as_n());
+        new_image.contains_key(SHARD)
+            && match self.event_name.as_str() {
+                "INSERT" => true,
+                "MODIFY" => updated_at(new_image) != updated_at(old_image),
+                _ => false,
+            }
+    }
+
+    /// Convert the record into an event, decoding its images with `decoder`
+    ///
+    /// The namespace of `decoder` is removed from the id of the product.
+    ///
+    /// Writes to the shards of a product are compared with the latest
+    /// version across shards at the time of the write, which the store
+    /// records in the new image, rather than with the previous copy in the
+    /// same shard. The first write of a product is a `Created` event, and
+    /// the next ones are `Updated` events, whichever shard they go to.
+    /// DynamoDB Streams doesn't order the records of different shards
+    /// though, so the events of a sharded product can be out of order.
+    pub fn to_event(&self, decoder: &ItemDecoder) -> Result<Event, Error> {
+        let decode = |image: &HashMap<String, AttributeValue>| decode_image(decoder, image);
+        if self.is_shard_write() {
+            let item = to_sdk_item(&self.dynamodb.new_image)?;
+            let new = decoder.decode(&item)?;
+            return Ok(match decoder.decode_previous(&item)? {
+                Some(old) => Event::Updated { old, new },
+                None => Event::Created { product: new },
+            });
+        }
+        match self.event_name.as_str() {
             "INSERT" => {
-                let product = (&value.dynamodb.new_image).try_into()?;
+                let product = decode(&self.dynamodb.new_image)?;
                 Ok(Event::Created { product })
             }
             "MODIFY" => {
-                let old = (&value.dynamodb.old_image).try_into()?;
-                let new = (&value.dynamodb.new_image).try_into()?;
-                Ok(Event::Updated { old, new })
+                // Soft deletes and restores are modifications of the
+                // `deleted_at` attribute.
+                let was_deleted = self.dynamodb.old_image.contains_key(DELETED_AT);
+                let is_deleted = self.dynamodb.new_image.contains_key(DELETED_AT);
+                let expires_at = self
+                    .dynamodb
+                    .new_image
+                    .get(EXPIRES_AT)
+                    .and_then(|v| v.as_n());
+                let old_expires_at = self
+                    .dynamodb
+                    .old_image
+                    .get(EXPIRES_AT)
+                    .and_then(|v| v.as_n());
+                match (was_deleted, is_deleted) {
+                    (false, true) => {
+                        let product = decode(&self.dynamodb.old_image)?;
+                        Ok(Event::Deleted { product })
+                    }
+                    (true, false) => {
+                        let product = decode(&self.dynamodb.new_image)?;
+                        Ok(Event::Created { product })
+                    }
+                    _ => match expires_at {
+                        // Deferred deletes set the TTL attribute on deleted
+                        // items
+                        Some(expires_at) if old_expires_at != Some(expires_at) => {
+                            let product = decode(&self.dynamodb.new_image)?;
+                            Ok(Event::DeletionScheduled {
+                                product,
+                                expires_at: expires_at as u64,
+                            })
+                        }
+                        _ => {
+                            let old = decode(&self.dynamodb.old_image)?;
+                            let new = decode(&self.dynamodb.new_image)?;
+                            Ok(Event::Updated { old, new })
+                        }
+                    },
+                }
             }
             "REMOVE" => {
-                let product = (&value.dynamodb.old_image).try_into()?;
+                let product = decode(&self.dynamodb.old_image)?;
                 Ok(Event::Deleted { product })
             }
             _ => Err(Error::InternalError("Unknown event type")),
         }
     }
+
+    /// Convert the record into a change, decoding its images with `decoder`
+    ///
+    /// The sequence number is zero-padded so that changes can be sorted
+    /// lexicographically.
+    pub fn to_change(&self, decoder: &ItemDecoder) -> Result<Change, Error> {
+        Ok(Change {
+            sequence: format!(
+                "{:0>width$}",
+                self.dynamodb.sequence_number,
+                width = SEQUENCE_WIDTH
+            ),
+            event: self.to_event(decoder)?,
+        })
+    }
+}
+
+/// Width of the zero-padded change sequence
+///
+/// DynamoDB Streams sequence numbers have between 21 and 40 digits.
+const SEQUENCE_WIDTH: usize = 40;
+
+/// Decode the image of a record into a product
+///
+/// This could fail as the image might be missing some fields.
+pub fn decode_image(
+    decoder: &ItemDecoder,
+    image: &HashMap<String, AttributeValue>,
+) -> Result<Product, Error> {
+    decoder.decode(&to_sdk_item(image)?)
+}
+
+/// Convert the image of a record into an item of the AWS SDK
+fn to_sdk_item(
+    image: &HashMap<String, AttributeValue>,
+) -> Result<HashMap<String, SdkAttributeValue>, Error> {
+    image
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), v.to_sdk()?)))
+        .collect()
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -93,29 +251,48 @@ pub struct DynamoDBStreamRecord {
 /// Attribute Value
 ///
 /// This is a copy of the `AttributeValue` struct from the AWS SDK for Rust,
-/// but without blob and `is_`-prefixed methods.
+/// but without `is_`-prefixed methods. Variants are renamed to the type
+/// descriptors used in DynamoDB Streams records. Binary values are kept
+/// base64-encoded, as they appear in the records.
 /// See https://docs.rs/aws-sdk-dynamodb/0.0.22-alpha/aws_sdk_dynamodb/model/enum.AttributeValue.html
 #[derive(Deserialize, Serialize, Debug)]
 pub enum AttributeValue {
-    // B(Blob),
+    B(String),
+    #[serde(rename = "BOOL")]
     Bool(bool),
-    // Bs(Vec<Blob>),
+    #[serde(rename = "BS")]
+    Bs(Vec<String>),
     L(Vec<AttributeValue>),
     M(HashMap<String, AttributeValue>),
     N(String),
+    #[serde(rename = "NS")]
     Ns(Vec<String>),
+    #[serde(rename = "NULL")]
     Null(bool),
     S(String),
+    #[serde(rename = "SS")]
     Ss(Vec<String>),
 }
 
 impl AttributeValue {
+    pub fn as_b(&self) -> Option<&str> {
+        match self {
+            AttributeValue::B(b) => Some(b),
+            _ => None,
+        }
+    }
     pub fn as_bool(&self) -> Option<bool> {
         match self {
             AttributeValue::Bool(b) => Some(*b),
             _ => None,
         }
     }
+    pub fn as_bs(&self) -> Vec<String> {
+        match self {
+            AttributeValue::Bs(bs) => bs.to_owned(),
+            _ => Default::default(),
+        }
+    }
     pub fn as_l(&self) -> Option<&Vec<AttributeValue>> {
         match self {
             AttributeValue::L(l) => Some(l),
@@ -160,31 +337,35 @@ impl AttributeValue {
     }
 }
 
-impl TryFrom<&HashMap<String, AttributeValue>> for Product {
-    type Error = Error;
-
-    /// Try to convert a DynamoDB item into a Product
+impl AttributeValue {
+    /// Convert into the attribute value of the AWS SDK
     ///
-    /// This could fail as the DynamoDB item might be missing some fields.
-    fn try_from(value: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        Ok(Product {
-            id: value
-                .get("id")
-                .ok_or(Error::InternalError("Missing id"))?
-                .as_s()
-                .ok_or(Error::InternalError("id is not a string"))?
-                .to_string(),
-            name: value
-                .get("name")
-                .ok_or(Error::InternalError("Missing name"))?
-                .as_s()
-                .ok_or(Error::InternalError("name is not a string"))?
-                .to_string(),
-            price: value
-                .get("price")
-                .ok_or(Error::InternalError("Missing price"))?
-                .as_n()
-                .ok_or(Error::InternalError("price is not a number"))?,
+    /// This fails if a binary value isn't valid base64.
+    pub fn to_sdk(&self) -> Result<SdkAttributeValue, Error> {
+        let decode = |b: &str| {
+            base64::decode(b)
+                .map(Blob::new)
+                .map_err(|_| Error::InternalError("Invalid binary value in record"))
+        };
+        Ok(match self {
+            AttributeValue::B(b) => SdkAttributeValue::B(decode(b)?),
+            AttributeValue::Bool(b) => SdkAttributeValue::Bool(*b),
+            AttributeValue::Bs(bs) => {
+                SdkAttributeValue::Bs(bs.iter().map(|b| decode(b)).collect::<Result<_, _>>()?)
+            }
+            AttributeValue::L(l) => {
+                SdkAttributeValue::L(l.iter().map(|v| v.to_sdk()).collect::<Result<_, _>>()?)
+            }
+            AttributeValue::M(m) => SdkAttributeValue::M(
+                m.iter()
+                    .map(|(k, v)| Ok((k.clone(), v.to_sdk()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            AttributeValue::N(n) => SdkAttributeValue::N(n.clone()),
+            AttributeValue::Ns(ns) => SdkAttributeValue::Ns(ns.clone()),
+            AttributeValue::Null(null) => SdkAttributeValue::Null(*null),
+            AttributeValue::S(s) => SdkAttributeValue::S(s.clone()),
+            AttributeValue::Ss(ss) => SdkAttributeValue::Ss(ss.clone()),
         })
     }
 }
@@ -192,6 +373,7 @@ impl TryFrom<&HashMap<String, AttributeValue>> for Product {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{ItemCodec, SerdeCodec};
 
     fn get_ddb_event() -> DynamoDBEvent {
         let data = r#"
@@ -307,7 +489,7 @@ mod tests {
         let events = ddb_event
             .records
             .iter()
-            .map(|r| r.try_into())
+            .map(|r| r.to_event(&ItemDecoder::default()))
             .collect::<Result<Vec<Event>, _>>()
             .unwrap();
 
@@ -338,15 +520,311 @@ mod tests {
     }
 
     #[test]
-    fn test_dynamodb_into_product() {
+    fn test_soft_delete_into_event() {
+        let data = r#"
+        {
+            "eventID": "3",
+            "eventVersion": "1.1",
+            "dynamodb": {
+              "OldImage": {
+                "id": { "S": "103" },
+                "name": { "S": "item3" },
+                "price": { "N": "10.5" }
+              },
+              "NewImage": {
+                "id": { "S": "103" },
+                "name": { "S": "item3" },
+                "price": { "N": "10.5" },
+                "deleted_at": { "N": "1640995200" }
+              },
+              "SequenceNumber": "333",
+              "SizeBytes": 59,
+              "StreamViewType": "NEW_AND_OLD_IMAGES"
+            },
+            "awsRegion": "us-west-2",
+            "eventName": "MODIFY",
+            "eventSourceARN": "someARN",
+            "eventSource": "aws:dynamodb"
+        }"#;
+        let record: DynamoDBRecord = serde_json::from_str(data).unwrap();
+
+        let event = record.to_event(&ItemDecoder::default()).unwrap();
+
+        match event {
+            Event::Deleted { product } => assert_eq!(product.id, "103"),
+            _ => panic!("Expected a Deleted event"),
+        }
+        assert!(!record.is_purge());
+    }
+
+    #[test]
+    fn test_deletion_scheduled_into_event() {
+        let data = r#"
+        {
+            "eventID": "4",
+            "eventVersion": "1.1",
+            "dynamodb": {
+              "OldImage": {
+                "id": { "S": "103" },
+                "name": { "S": "item3" },
+                "price": { "N": "10.5" },
+                "deleted_at": { "N": "1640995200" }
+              },
+              "NewImage": {
+                "id": { "S": "103" },
+                "name": { "S": "item3" },
+                "price": { "N": "10.5" },
+                "deleted_at": { "N": "1640995200" },
+                "expires_at": { "N": "1641081600" }
+              },
+              "SequenceNumber": "444",
+              "SizeBytes": 72,
+              "StreamViewType": "NEW_AND_OLD_IMAGES"
+            },
+            "awsRegion": "us-west-2",
+            "eventName": "MODIFY",
+            "eventSourceARN": "someARN",
+            "eventSource": "aws:dynamodb"
+        }"#;
+        let record: DynamoDBRecord = serde_json::from_str(data).unwrap();
+
+        let event = record.to_event(&ItemDecoder::default()).unwrap();
+
+        match event {
+            Event::DeletionScheduled {
+                product,
+                expires_at,
+            } => {
+                assert_eq!(product.id, "103");
+                assert_eq!(expires_at, 1641081600);
+            }
+            _ => panic!("Expected a DeletionScheduled event"),
+        }
+    }
+
+    #[test]
+    fn test_dynamodb_prefixed_record() {
+        // GIVEN a record of an item with a prefixed key
+        let data = r#"
+        {
+            "eventID": "5",
+            "eventVersion": "1.1",
+            "dynamodb": {
+              "Keys": {
+                "id": { "S": "staging#104" }
+              },
+              "NewImage": {
+                "id": { "S": "staging#104" },
+                "name": { "S": "item4" },
+                "price": { "N": "10.5" }
+              },
+              "SequenceNumber": "555",
+              "SizeBytes": 40,
+              "StreamViewType": "NEW_AND_OLD_IMAGES"
+            },
+            "awsRegion": "us-west-2",
+            "eventName": "INSERT",
+            "eventSourceARN": "someARN",
+            "eventSource": "aws:dynamodb"
+        }"#;
+        let record: DynamoDBRecord = serde_json::from_str(data).unwrap();
+        let decoder = ItemDecoder::default().with_key_prefix("staging#");
+
+        // WHEN converting it with the decoder of that namespace
+        let event = record.to_event(&decoder).unwrap();
+
+        // THEN it belongs to the namespace, and the prefix is removed
+        assert!(record.belongs_to(&decoder));
+        assert_eq!(event.id(), "104");
+        // AND it doesn't belong to other namespaces
+        assert!(!record.belongs_to(&ItemDecoder::default().with_key_prefix("prod#")));
+        assert!(record.belongs_to(&ItemDecoder::default()));
+    }
+
+    /// Record of a write to a shard of product 105, with the latest version
+    /// before the write if any
+    fn get_shard_record(event_name: &str, previous: Option<&str>) -> DynamoDBRecord {
+        let mut new_image = serde_json::json!({
+            "id": { "S": "105#2" },
+            "name": { "S": "new" },
+            "price": { "N": "20" },
+            "shard": { "N": "2" },
+            "updated_at": { "N": "2000000" }
+        });
+        if let Some(name) = previous {
+            new_image["previous"] = serde_json::json!({ "M": {
+                "id": { "S": "105" },
+                "name": { "S": name },
+                "price": { "N": "10" }
+            }});
+        }
+        // The previous copy in the same shard is outdated
+        let old_image = serde_json::json!({
+            "id": { "S": "105#2" },
+            "name": { "S": "outdated" },
+            "price": { "N": "5" },
+            "shard": { "N": "2" },
+            "updated_at": { "N": "1000000" }
+        });
+        serde_json::from_value(serde_json::json!({
+            "eventID": "6",
+            "eventVersion": "1.1",
+            "dynamodb": {
+                "Keys": { "id": { "S": "105#2" } },
+                "NewImage": new_image,
+                "OldImage": if event_name == "MODIFY" { old_image } else { serde_json::json!({}) },
+                "SequenceNumber": "666",
+                "SizeBytes": 80,
+                "StreamViewType": "NEW_AND_OLD_IMAGES"
+            },
+            "awsRegion": "us-west-2",
+            "eventName": event_name,
+            "eventSourceARN": "someARN",
+            "eventSource": "aws:dynamodb"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dynamodb_shard_into_event() {
+        // GIVEN a write to a shard replacing the latest version
+        let record = get_shard_record("MODIFY", Some("latest"));
+
+        // WHEN converting it into an event
+        let event = record.to_event(&ItemDecoder::default()).unwrap();
+
+        // THEN it updates the latest version, not the copy in the shard
+        match event {
+            Event::Updated { old, new } => {
+                assert_eq!(old.id, "105");
+                assert_eq!(old.name, "latest");
+                assert_eq!(new.id, "105");
+                assert_eq!(new.name, "new");
+                // AND the shard attributes are left out
+                assert!(new.attributes.is_empty());
+            }
+            _ => panic!("Expected an Updated event"),
+        }
+        // AND inserting a shard updates the latest version too
+        let record = get_shard_record("INSERT", Some("latest"));
+        match record.to_event(&ItemDecoder::default()).unwrap() {
+            Event::Updated { old, .. } => assert_eq!(old.name, "latest"),
+            _ => panic!("Expected an Updated event"),
+        }
+        // AND only the first write creates the product
+        let record = get_shard_record("INSERT", None);
+        match record.to_event(&ItemDecoder::default()).unwrap() {
+            Event::Created { product } => assert_eq!(product.id, "105"),
+            _ => panic!("Expected a Created event"),
+        }
+    }
+
+    #[test]
+    fn test_dynamodb_custom_codec() {
+        // GIVEN a decoder with a custom codec
+        struct UpperCaseCodec;
+        impl ItemCodec<Product> for UpperCaseCodec {
+            fn encode(&self, value: &Product) -> Result<HashMap<String, SdkAttributeValue>, Error> {
+                SerdeCodec.encode(value)
+            }
+
+            fn decode(&self, item: &HashMap<String, SdkAttributeValue>) -> Result<Product, Error> {
+                let product: Product = SerdeCodec.decode(item)?;
+                Ok(Product {
+                    name: product.name.to_uppercase(),
+                    ..product
+                })
+            }
+        }
+        let decoder = ItemDecoder::new(UpperCaseCodec);
+
+        // WHEN converting a record
+        let event = get_ddb_event().records[0].to_event(&decoder).unwrap();
+
+        // THEN its image is decoded with that codec
+        match event {
+            Event::Created { product } => assert_eq!(product.name, "NEW-ITEM"),
+            _ => panic!("Expected a Created event"),
+        }
+    }
+
+    #[test]
+    fn test_dynamodb_into_change() {
         let ddb_event = get_ddb_event();
 
-        let product: Product = (&ddb_event.records[0].dynamodb.new_image)
-            .try_into()
+        let change = ddb_event.records[0]
+            .to_change(&ItemDecoder::default())
             .unwrap();
 
+        assert_eq!(change.sequence.len(), 40);
+        assert!(change.sequence.ends_with("0111"));
+        assert_eq!(change.event.id(), "101");
+    }
+
+    #[test]
+    fn test_dynamodb_into_product() {
+        let ddb_event = get_ddb_event();
+
+        let product = decode_image(
+            &ItemDecoder::default(),
+            &ddb_event.records[0].dynamodb.new_image,
+        )
+        .unwrap();
+
         assert_eq!(product.id, "101");
         assert_eq!(product.name, "new-item");
         assert_eq!(product.price, 10.5);
     }
+
+    #[test]
+    fn test_dynamodb_into_product_attributes() {
+        let data = r#"{
+            "id": {"S": "101"},
+            "name": {"S": "new-item"},
+            "price": {"N": "10.5"},
+            "attributes": {"M": {
+                "color": {"S": "red"},
+                "weight": {"N": "2"},
+                "fragile": {"BOOL": true}
+            }}
+        }"#;
+        let image: HashMap<String, AttributeValue> = serde_json::from_str(data).unwrap();
+
+        let product = decode_image(&ItemDecoder::default(), &image).unwrap();
+
+        assert_eq!(product.attributes.len(), 3);
+        assert_eq!(product.attributes["color"], "red");
+        assert_eq!(product.attributes["weight"], 2);
+        assert_eq!(product.attributes["fragile"], true);
+    }
+
+    #[test]
+    fn test_dynamodb_binary_attributes() {
+        // GIVEN an item with binary attributes
+        let data = r#"{
+            "id": {"S": "101"},
+            "name": {"S": "new-item"},
+            "price": {"N": "10.5"},
+            "thumbnail": {"B": "aGVsbG8="},
+            "attributes": {"M": {
+                "color": {"S": "red"},
+                "checksums": {"BS": ["YQ==", "Yg=="]}
+            }}
+        }"#;
+
+        // WHEN deserializing and converting it into a product
+        let image: HashMap<String, AttributeValue> = serde_json::from_str(data).unwrap();
+        let product = decode_image(&ItemDecoder::default(), &image).unwrap();
+
+        // THEN binary values are kept base64-encoded
+        assert_eq!(image["thumbnail"].as_b(), Some("aGVsbG8="));
+        // AND converted to bytes for the SDK
+        assert_eq!(
+            image["thumbnail"].to_sdk().unwrap(),
+            SdkAttributeValue::B(Blob::new("hello"))
+        );
+        // AND binary attributes are left out of the product
+        assert_eq!(product.attributes.len(), 1);
+        assert_eq!(product.attributes["color"], "red");
+    }
 }
@@ -1,2 +1,16 @@
+#[cfg(feature = "apigateway")]
+pub mod alb;
+#[cfg(feature = "apigateway")]
 pub mod apigateway;
+#[cfg(feature = "backup")]
+pub mod backup;
+#[cfg(feature = "streams")]
 pub mod dynamodb;
+#[cfg(feature = "apigateway")]
+pub mod function_url;
+#[cfg(feature = "scheduled")]
+pub mod scheduled;
+#[cfg(feature = "messaging")]
+pub mod sns;
+#[cfg(feature = "messaging")]
+pub mod sqs;
@@ -0,0 +1,49 @@
+//! # Scheduled handlers
+//!
+//! Handlers triggered periodically by EventBridge schedules. The content of
+//! the scheduled event is ignored.
+
+use crate::{
+    domain,
+    event_bus::{EventBus, EventContext},
+    outbox::{self, Outbox},
+    store::{StoreGetHits, StorePutPopular},
+    Event,
+};
+use lambda_runtime::Context;
+use serde_json::Value;
+use tracing::{info, instrument};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Recompute the list of popular products
+#[instrument(skip(hits, popular, _event))]
+pub async fn materialize_popular_products(
+    hits: &dyn StoreGetHits,
+    popular: &dyn StorePutPopular,
+    _event: Value,
+    _: Context,
+) -> Result<(), E> {
+    info!("Materializing popular products");
+    let count = domain::materialize_popular_products(hits, popular).await?;
+    info!("Materialized {} popular products", count);
+
+    Ok(())
+}
+
+/// Send the events waiting in the outbox
+#[instrument(skip(outbox, event_bus, _event))]
+pub async fn drain_outbox(
+    outbox: &dyn Outbox,
+    event_bus: &dyn EventBus<E = Event>,
+    _event: Value,
+    ctx: Context,
+) -> Result<(), E> {
+    info!("Draining the outbox");
+    let count = EventContext::from_invocation(&ctx.request_id, &ctx.xray_trace_id)
+        .scope(outbox::drain(outbox, event_bus))
+        .await?;
+    info!("Sent {} events from the outbox", count);
+
+    Ok(())
+}
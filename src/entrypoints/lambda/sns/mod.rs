@@ -0,0 +1,194 @@
+//! # SNS handler
+//!
+//! Applies the product commands published to an SNS topic by other
+//! services, such as a pricing service publishing price updates. Each
+//! notification holds either a full product, or the new price of an
+//! existing product, which only updates the price so that concurrent
+//! changes of other fields are kept.
+//!
+//! SNS invokes the function asynchronously, so failed notifications are
+//! retried by Lambda, then sent to the on-failure destination of the
+//! function if it has one. Notifications that can't be parsed and price
+//! updates for unknown products are skipped, as retrying them wouldn't help.
+
+use crate::{
+    domain::{
+        self,
+        commands::{validate_price, validate_product},
+    },
+    store::{StoreAppendHistory, StorePut, StoreUpdate},
+    Error, ProductPatch,
+};
+use lambda_runtime::Context;
+use tracing::{info, instrument, warn};
+
+pub mod model;
+
+use model::{ProductCommand, SnsEvent, SnsMessage};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Apply the product commands of SNS notifications
+#[instrument(skip(store, updater, history, event))]
+pub async fn process_notifications(
+    store: &dyn StorePut,
+    updater: &dyn StoreUpdate,
+    history: &dyn StoreAppendHistory,
+    event: SnsEvent,
+    _: Context,
+) -> Result<(), E> {
+    info!("Processing {} notifications", event.records.len());
+    for record in &event.records {
+        process_notification(store, updater, history, &record.sns).await?;
+    }
+    info!("Done processing notifications");
+
+    Ok(())
+}
+
+/// Parse and apply the command of a notification
+async fn process_notification(
+    store: &dyn StorePut,
+    updater: &dyn StoreUpdate,
+    history: &dyn StoreAppendHistory,
+    message: &SnsMessage,
+) -> Result<(), Error> {
+    let command: ProductCommand = match serde_json::from_str(&message.message) {
+        Ok(command) => command,
+        Err(err) => {
+            warn!(
+                "Skipping invalid notification {}: {}",
+                message.message_id, err
+            );
+            return Ok(());
+        }
+    };
+
+    let product = match command {
+        ProductCommand::Put(product) => product,
+        ProductCommand::UpdatePrice { id, price } => {
+            validate_price(price)?;
+            let patch = ProductPatch {
+                price: Some(price),
+                ..Default::default()
+            };
+            info!(
+                "Updating price of product {} from notification {}",
+                id, message.message_id
+            );
+            if domain::update_product(updater, history, &id, &patch)
+                .await?
+                .is_none()
+            {
+                warn!(
+                    "Skipping price update {} for unknown product {}",
+                    message.message_id, id
+                );
+            }
+            return Ok(());
+        }
+    };
+    validate_product(&product)?;
+
+    info!(
+        "Putting product {} from notification {}",
+        product.id, message.message_id
+    );
+    domain::put_product(store, history, &product).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::{MemoryHistoryStore, MemoryStore, ReadConsistency, StoreGet},
+        Product,
+    };
+    use serde_json::json;
+
+    fn get_event(messages: &[&str]) -> Result<SnsEvent, E> {
+        let records: Vec<_> = messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                json!({
+                    "EventSource": "aws:sns",
+                    "EventVersion": "1.0",
+                    "EventSubscriptionArn": "arn:aws:sns:eu-west-1:123456789012:prices:0123abcd",
+                    "Sns": {
+                        "Type": "Notification",
+                        "MessageId": format!("message-{}", i),
+                        "TopicArn": "arn:aws:sns:eu-west-1:123456789012:prices",
+                        "Subject": null,
+                        "Message": message,
+                        "Timestamp": "2022-03-10T10:00:00.000Z",
+                        "SignatureVersion": "1",
+                        "Signature": "",
+                        "SigningCertUrl": "",
+                        "UnsubscribeUrl": "",
+                        "MessageAttributes": {}
+                    }
+                })
+            })
+            .collect();
+        Ok(serde_json::from_value(json!({ "Records": records }))?)
+    }
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_notifications() -> Result<(), E> {
+        // GIVEN a product, and notifications with a product and price updates
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        store.put(&get_product("1", 10.0)).await?;
+        let event = get_event(&[
+            serde_json::to_string(&get_product("2", 20.0))?.as_str(),
+            r#"{"id": "1", "price": 12.344}"#,
+            r#"{"id": "3", "price": 30.0}"#,
+            "not json",
+        ])?;
+
+        // WHEN processing the notifications
+        process_notifications(&store, &store, &history, event, Context::default()).await?;
+
+        // THEN the products are written, with rounded prices
+        let get = |id: &'static str| store.get(id, ReadConsistency::default(), None);
+        assert_eq!(get("1").await?, Some(get_product("1", 12.34)));
+        assert_eq!(get("2").await?, Some(get_product("2", 20.0)));
+        // AND price updates of unknown products and invalid messages are
+        // skipped
+        assert_eq!(get("3").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_notifications_invalid() -> Result<(), E> {
+        // GIVEN a notification with a negative price
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let event = get_event(&[serde_json::to_string(&get_product("1", -1.0))?.as_str()])?;
+
+        // WHEN processing the notification
+        let res = process_notifications(&store, &store, &history, event, Context::default()).await;
+
+        // THEN it fails without writing the product
+        assert!(res.is_err());
+        assert_eq!(
+            store.get("1", ReadConsistency::default(), None).await?,
+            None
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! # SNS Event models
+//!
+//! Models for the SNS event entrypoint, and the product commands carried by
+//! the notifications.
+
+use crate::model::Product;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SnsEvent {
+    #[serde(rename = "Records")]
+    pub records: Vec<SnsRecord>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SnsRecord {
+    #[serde(rename = "EventSource")]
+    pub event_source: String,
+
+    #[serde(rename = "EventVersion")]
+    pub event_version: String,
+
+    #[serde(rename = "EventSubscriptionArn")]
+    pub event_subscription_arn: String,
+
+    #[serde(rename = "Sns")]
+    pub sns: SnsMessage,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SnsMessage {
+    #[serde(rename = "Type")]
+    pub message_type: String,
+
+    #[serde(rename = "MessageId")]
+    pub message_id: String,
+
+    #[serde(rename = "TopicArn")]
+    pub topic_arn: String,
+
+    #[serde(rename = "Subject", default)]
+    pub subject: Option<String>,
+
+    #[serde(rename = "Message")]
+    pub message: String,
+
+    #[serde(rename = "Timestamp")]
+    pub timestamp: String,
+
+    #[serde(rename = "MessageAttributes", default)]
+    pub message_attributes: HashMap<String, Value>,
+}
+
+/// Product command carried in a notification
+///
+/// Notifications hold either a full product, which is created or replaced,
+/// or a price update for an existing product, such as
+/// `{"id": "1", "price": 9.99}`.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ProductCommand {
+    Put(Product),
+    UpdatePrice { id: String, price: f64 },
+}
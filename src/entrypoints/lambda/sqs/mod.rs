@@ -0,0 +1,401 @@
+//! # SQS handler
+//!
+//! Applies the commands of messages from an SQS queue to the catalog. The
+//! body of each message is either an event, such as the events of another
+//! catalog sent by the SQS FIFO bus, or a product to create or replace:
+//!
+//! - `Created`, `Updated` and `Archived` events put their product,
+//! - `Deleted` events soft-delete the product, and `DeletionScheduled`
+//!   events also schedule its removal at the same time,
+//! - `ArchiveCompleted` events don't change anything.
+//!
+//! Messages are processed in order, and the ones that fail are reported in
+//! the response, so that Lambda only returns those to the queue. Messages
+//! from FIFO queues that follow a failed message of the same group are
+//! reported too without being processed, to keep the order of the group.
+//!
+//! Events sent by the SQS FIFO bus carry a sequence number per product. They
+//! are only applied if they come after the checkpoint of their product, so
+//! that redelivered or replayed events don't revert later changes.
+
+use crate::{
+    consumer::{process_after_checkpoints, Checkpoints},
+    domain::commands::{CommandContext, CreateProduct, DeleteProduct, UpdateProduct},
+    store::{StoreAppendHistory, StoreDelete, StoreExpire, StorePut, StoreRecordAudit},
+    Error, Event,
+};
+use lambda_runtime::Context;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, instrument, warn};
+
+pub mod model;
+
+use model::{BatchItemFailure, Command, SqsBatchResponse, SqsEvent, SqsMessage};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Apply the commands of a batch of SQS messages
+///
+/// Returns the messages that failed, to be retried or sent to the dead
+/// letter queue, while the others are deleted from the queue.
+#[instrument(skip(store, deleter, expire, history, audit, checkpoints, event))]
+#[allow(clippy::too_many_arguments)]
+pub async fn process_messages(
+    store: &dyn StorePut,
+    deleter: &dyn StoreDelete,
+    expire: &dyn StoreExpire,
+    history: &dyn StoreAppendHistory,
+    audit: &dyn StoreRecordAudit,
+    checkpoints: &dyn Checkpoints,
+    event: SqsEvent,
+    _: Context,
+) -> Result<SqsBatchResponse, E> {
+    info!("Processing {} messages", event.records.len());
+    let mut response = SqsBatchResponse::default();
+    let mut failed_groups = HashSet::new();
+    for message in &event.records {
+        if let Some(group) = message.message_group_id() {
+            if failed_groups.contains(group) {
+                warn!(
+                    "Skipping message {} after a failure in group {}",
+                    message.message_id, group
+                );
+                response.batch_item_failures.push(BatchItemFailure {
+                    item_identifier: message.message_id.clone(),
+                });
+                continue;
+            }
+        }
+
+        if let Err(err) =
+            process_message(store, deleter, expire, history, audit, checkpoints, message).await
+        {
+            error!("Failed to process message {}: {}", message.message_id, err);
+            if let Some(group) = message.message_group_id() {
+                failed_groups.insert(group.to_string());
+            }
+            response.batch_item_failures.push(BatchItemFailure {
+                item_identifier: message.message_id.clone(),
+            });
+        }
+    }
+
+    info!(
+        "Done processing messages, {} failed",
+        response.batch_item_failures.len()
+    );
+    Ok(response)
+}
+
+/// Parse and apply the command of a message
+async fn process_message(
+    store: &dyn StorePut,
+    deleter: &dyn StoreDelete,
+    expire: &dyn StoreExpire,
+    history: &dyn StoreAppendHistory,
+    audit: &dyn StoreRecordAudit,
+    checkpoints: &dyn Checkpoints,
+    message: &SqsMessage,
+) -> Result<(), Error> {
+    let command: Command = serde_json::from_str(&message.body)
+        .map_err(|_| Error::ClientError("Invalid message body"))?;
+
+    // Retried messages keep their id
+    let context = CommandContext {
+        idempotency_key: Some(message.message_id.clone()),
+        caller: Some(message.event_source_arn.clone()),
+        ..Default::default()
+    };
+
+    match command {
+        Command::Product(product) => {
+            CreateProduct::new(product, context)?
+                .execute(store, history, audit)
+                .await?;
+        }
+        Command::Event(envelope) => {
+            apply_event(
+                store,
+                deleter,
+                expire,
+                history,
+                audit,
+                envelope.event,
+                context,
+            )
+            .await?;
+        }
+        Command::Ordered(envelope) => {
+            // Checkpoints are per queue, as other consumers can share the
+            // checkpoint table
+            let (product_id, sequence, event) = envelope.into_checkpoint_item();
+            let partition = format!("{}#{}", message.event_source_arn, product_id);
+            let count = process_after_checkpoints(
+                checkpoints,
+                vec![(partition, sequence.clone(), event)],
+                |events| async move {
+                    for event in events {
+                        apply_event(
+                            store,
+                            deleter,
+                            expire,
+                            history,
+                            audit,
+                            event,
+                            context.clone(),
+                        )
+                        .await?;
+                    }
+                    Ok(())
+                },
+            )
+            .await?;
+            if count == 0 {
+                warn!(
+                    "Skipping event {} of product {} at or before its checkpoint",
+                    sequence, product_id
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply an event to the catalog
+async fn apply_event(
+    store: &dyn StorePut,
+    deleter: &dyn StoreDelete,
+    expire: &dyn StoreExpire,
+    history: &dyn StoreAppendHistory,
+    audit: &dyn StoreRecordAudit,
+    event: Event,
+    context: CommandContext,
+) -> Result<(), Error> {
+    match event {
+        Event::Created { product } => {
+            CreateProduct::new(product, context)?
+                .execute(store, history, audit)
+                .await?;
+        }
+        Event::Updated { new: product, .. } | Event::Archived { product } => {
+            UpdateProduct::new(product, context)?
+                .execute(store, history, audit)
+                .await?;
+        }
+        Event::Deleted { product } => {
+            DeleteProduct::new(product.id, false, context)?
+                .execute(deleter, expire, audit)
+                .await?;
+        }
+        Event::DeletionScheduled {
+            product,
+            expires_at,
+        } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| Error::InternalError("System clock is before the UNIX epoch"))?
+                .as_secs();
+            DeleteProduct::new(product.id, false, context)?
+                .with_delay(Duration::from_secs(expires_at.saturating_sub(now)))?
+                .execute(deleter, expire, audit)
+                .await?;
+        }
+        Event::ArchiveCompleted { category, .. } => {
+            info!("Ignoring completed archive of category {}", category);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consumer::MemoryCheckpoints,
+        event_bus::{EventBus, SqsFifoBus},
+        store::{MemoryAuditStore, MemoryHistoryStore, MemoryStore, ReadConsistency, StoreGet},
+        test_util::{mock_sdk_config, request_builder},
+        Product,
+    };
+    use aws_sdk_sqs::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+    use serde_json::json;
+
+    fn get_message(id: &str, group: Option<&str>, body: &str) -> serde_json::Value {
+        let mut attributes = json!({
+            "ApproximateReceiveCount": "1",
+            "SentTimestamp": "1646906400000"
+        });
+        if let Some(group) = group {
+            attributes["MessageGroupId"] = json!(group);
+        }
+        json!({
+            "messageId": id,
+            "receiptHandle": format!("handle-{}", id),
+            "body": body,
+            "attributes": attributes,
+            "messageAttributes": {},
+            "md5OfBody": "",
+            "eventSource": "aws:sqs",
+            "eventSourceARN": "arn:aws:sqs:eu-west-1:123456789012:products.fifo",
+            "awsRegion": "eu-west-1"
+        })
+    }
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_messages() -> Result<(), E> {
+        // GIVEN messages with a product, an event and an invalid body
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+        let event: SqsEvent = serde_json::from_value(json!({
+            "Records": [
+                get_message("1", Some("a"), &serde_json::to_string(&get_product("1", 10.0))?),
+                get_message(
+                    "2",
+                    Some("b"),
+                    &serde_json::to_string(&Event::Created {
+                        product: get_product("2", 20.0),
+                    })?
+                ),
+                get_message("3", Some("a"), "not json"),
+                get_message("4", Some("a"), &serde_json::to_string(&get_product("4", 40.0))?),
+            ]
+        }))?;
+
+        // WHEN processing the messages
+        let res = process_messages(
+            &store,
+            &store,
+            &store,
+            &history,
+            &audit,
+            &MemoryCheckpoints::new(),
+            event,
+            Context::default(),
+        )
+        .await?;
+
+        // THEN the valid messages are applied
+        let get = |id: &'static str| store.get(id, ReadConsistency::default(), None);
+        assert_eq!(get("1").await?, Some(get_product("1", 10.0)));
+        assert_eq!(get("2").await?, Some(get_product("2", 20.0)));
+        // AND the invalid message fails, with the next message of its group
+        assert_eq!(get("4").await?, None);
+        assert_eq!(
+            res.batch_item_failures,
+            vec![
+                BatchItemFailure {
+                    item_identifier: "3".to_string()
+                },
+                BatchItemFailure {
+                    item_identifier: "4".to_string()
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_messages_ordered() -> Result<(), E> {
+        // GIVEN the messages sent by the SQS FIFO bus for the creation and
+        // update of a product, with the creation delivered again afterwards
+        let conn = TestConnection::new(vec![(
+            request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from(""))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    "<SendMessageBatchResponse><SendMessageBatchResult></SendMessageBatchResult></SendMessageBatchResponse>",
+                ))
+                .unwrap(),
+        )]);
+        let bus = SqsFifoBus::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/products.fifo".to_string(),
+        );
+        bus.send_events(&[
+            Event::Created {
+                product: get_product("1", 10.0),
+            },
+            Event::Updated {
+                old: get_product("1", 10.0),
+                new: get_product("1", 20.0),
+            },
+        ])
+        .await?;
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap())?;
+        let bodies: Vec<String> =
+            reqwest::Url::parse(&format!("https://sqs.eu-west-1.amazonaws.com/?{}", body))?
+                .query_pairs()
+                .filter(|(key, _)| key.ends_with(".MessageBody"))
+                .map(|(_, value)| value.into_owned())
+                .collect();
+        assert_eq!(bodies.len(), 2);
+        let event: SqsEvent = serde_json::from_value(json!({
+            "Records": [
+                get_message("1", Some("1"), &bodies[0]),
+                get_message("2", Some("1"), &bodies[1]),
+                get_message("3", Some("1"), &bodies[0]),
+            ]
+        }))?;
+        let store = MemoryStore::new();
+        let history = MemoryHistoryStore::new();
+        let audit = MemoryAuditStore::new();
+
+        // WHEN processing the messages
+        let res = process_messages(
+            &store,
+            &store,
+            &store,
+            &history,
+            &audit,
+            &MemoryCheckpoints::new(),
+            event,
+            Context::default(),
+        )
+        .await?;
+
+        // THEN all messages succeed
+        assert_eq!(res.batch_item_failures, vec![]);
+        // AND the late creation doesn't revert the update
+        assert_eq!(
+            store.get("1", ReadConsistency::default(), None).await?,
+            Some(get_product("1", 20.0))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_response() -> Result<(), E> {
+        let res = SqsBatchResponse {
+            batch_item_failures: vec![BatchItemFailure {
+                item_identifier: "1".to_string(),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&res)?,
+            json!({"batchItemFailures": [{"itemIdentifier": "1"}]})
+        );
+        Ok(())
+    }
+}
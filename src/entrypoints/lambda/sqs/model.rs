@@ -0,0 +1,85 @@
+//! # SQS Event models
+//!
+//! Models for the SQS event entrypoint, and the commands carried by the
+//! messages.
+
+use crate::{
+    event_bus::OrderedEnvelope,
+    model::{EventEnvelope, Product},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SqsEvent {
+    #[serde(rename = "Records")]
+    pub records: Vec<SqsMessage>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SqsMessage {
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
+    #[serde(rename = "receiptHandle")]
+    pub receipt_handle: String,
+
+    pub body: String,
+
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+
+    #[serde(rename = "messageAttributes", default)]
+    pub message_attributes: HashMap<String, Value>,
+
+    #[serde(rename = "md5OfBody", default)]
+    pub md5_of_body: String,
+
+    #[serde(rename = "eventSource")]
+    pub event_source: String,
+
+    #[serde(rename = "eventSourceARN")]
+    pub event_source_arn: String,
+
+    #[serde(rename = "awsRegion")]
+    pub aws_region: String,
+}
+
+impl SqsMessage {
+    /// Message group of messages from FIFO queues
+    pub fn message_group_id(&self) -> Option<&str> {
+        self.attributes.get("MessageGroupId").map(String::as_str)
+    }
+}
+
+/// Response reporting the messages that failed
+///
+/// Lambda deletes the other messages of the batch from the queue. This
+/// requires `ReportBatchItemFailures` in the function response types of the
+/// event source mapping.
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct SqsBatchResponse {
+    #[serde(rename = "batchItemFailures")]
+    pub batch_item_failures: Vec<BatchItemFailure>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct BatchItemFailure {
+    #[serde(rename = "itemIdentifier")]
+    pub item_identifier: String,
+}
+
+/// Command carried in the body of a message
+///
+/// Bodies are either events, as published by the event buses, which are
+/// applied to the catalog, or plain products, which are created or
+/// replaced. The SQS FIFO bus wraps its events in an `OrderedEnvelope`,
+/// with the sequence number of the event among the events of its product.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Command {
+    Ordered(OrderedEnvelope),
+    Event(EventEnvelope),
+    Product(Product),
+}
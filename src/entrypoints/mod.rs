@@ -1,2 +1,7 @@
-#[cfg(feature = "lambda")]
+#[cfg(any(
+    feature = "apigateway",
+    feature = "streams",
+    feature = "backup",
+    feature = "scheduled"
+))]
 pub mod lambda;
@@ -1,5 +1,8 @@
+use crate::Event;
+#[cfg(any(feature = "store", feature = "event-bus"))]
 use aws_sdk_dynamodb::model::AttributeValue;
-use aws_smithy_http::result::SdkError;
+use aws_smithy_http::{operation, result::SdkError};
+use aws_smithy_types::retry::{ErrorKind, ProvideErrorKind};
 use std::error;
 use std::fmt;
 
@@ -8,7 +11,34 @@ pub enum Error {
     InitError(&'static str),
     ClientError(&'static str),
     InternalError(&'static str),
-    SdkError(String),
+    SdkError(SdkErrorDetails),
+    /// Events an event bus couldn't deliver, after retrying them
+    EventsFailed(Vec<FailedEvent>),
+    /// An event doesn't match its schema
+    InvalidEvent(String),
+    /// A conditional write found the product in another state than expected
+    PreconditionFailed(&'static str),
+}
+
+impl Error {
+    /// Whether the operation that caused this error can be retried
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::SdkError(details) => details.kind.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Whether this error was caused by throttling from an AWS service
+    pub fn is_throttling(&self) -> bool {
+        matches!(
+            self,
+            Error::SdkError(SdkErrorDetails {
+                kind: SdkErrorKind::Throttling,
+                ..
+            })
+        )
+    }
 }
 
 impl fmt::Display for Error {
@@ -17,19 +47,136 @@ impl fmt::Display for Error {
             Error::InitError(msg) => write!(f, "InitError: {}", msg),
             Error::ClientError(msg) => write!(f, "ClientError: {}", msg),
             Error::InternalError(msg) => write!(f, "InternalError: {}", msg),
-            Error::SdkError(err) => write!(f, "SdkError: {}", err),
+            Error::SdkError(details) => write!(f, "SdkError: {}", details),
+            Error::EventsFailed(failed) => {
+                write!(f, "EventsFailed: {} events failed", failed.len())?;
+                if let Some(first) = failed.first() {
+                    write!(f, " (first: {}: {})", first.code, first.message)?;
+                }
+                Ok(())
+            }
+            Error::InvalidEvent(msg) => write!(f, "InvalidEvent: {}", msg),
+            Error::PreconditionFailed(msg) => write!(f, "PreconditionFailed: {}", msg),
         }
     }
 }
 
 impl error::Error for Error {}
 
+/// Event that an event bus rejected
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailedEvent {
+    pub event: Event,
+    /// Error code returned for this event, e.g. `MalformedDetail`
+    pub code: String,
+    pub message: String,
+}
+
+/// Classification of an error returned by an AWS SDK
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SdkErrorKind {
+    /// The service throttled the request
+    Throttling,
+    /// The request failed because of a network issue, a timeout or a server
+    /// error
+    Transient,
+    /// The service rejected the request parameters
+    Validation,
+    /// The service rejected the request for another reason, such as a failed
+    /// condition or a missing permission
+    Client,
+    /// The request could not be built or the response could not be parsed
+    Other,
+}
+
+impl SdkErrorKind {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SdkErrorKind::Throttling | SdkErrorKind::Transient)
+    }
+}
+
+/// Details of an error returned by an AWS SDK
+#[derive(Clone, Debug, PartialEq)]
+pub struct SdkErrorDetails {
+    pub kind: SdkErrorKind,
+    /// Error code returned by the service, e.g. `ValidationException`
+    pub code: Option<String>,
+    pub message: String,
+    /// HTTP status code of the response, if any
+    pub status: Option<u16>,
+    /// Request id assigned by the service, if any
+    pub request_id: Option<String>,
+}
+
+impl fmt::Display for SdkErrorDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self.kind)?;
+        if let Some(code) = &self.code {
+            write!(f, " {}", code)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(status) = self.status {
+            write!(f, " (status: {})", status)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id: {})", request_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error codes used by AWS services for throttling
+const THROTTLING_CODES: &[&str] = &[
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "ProvisionedThroughputExceededException",
+    "TransactionInProgressException",
+    "RequestLimitExceeded",
+    "LimitExceededException",
+    "SlowDown",
+];
+
+/// Error codes used by AWS services for transient errors
+const TRANSIENT_CODES: &[&str] = &[
+    "RequestTimeout",
+    "RequestTimeoutException",
+    "InternalServerError",
+];
+
+/// Classify a service error from its error code, retry hint and HTTP status
+fn classify(code: Option<&str>, retry_kind: Option<ErrorKind>, status: u16) -> SdkErrorKind {
+    match (code, retry_kind) {
+        (Some(code), _) if THROTTLING_CODES.contains(&code) => SdkErrorKind::Throttling,
+        (_, Some(ErrorKind::ThrottlingError)) => SdkErrorKind::Throttling,
+        (Some(code), _) if TRANSIENT_CODES.contains(&code) => SdkErrorKind::Transient,
+        (_, Some(ErrorKind::TransientError | ErrorKind::ServerError)) => SdkErrorKind::Transient,
+        (Some("ValidationException"), _) => SdkErrorKind::Validation,
+        _ if status == 429 => SdkErrorKind::Throttling,
+        _ if status >= 500 => SdkErrorKind::Transient,
+        _ => SdkErrorKind::Client,
+    }
+}
+
+/// Retrieve the request id from the headers of a raw response
+fn request_id(raw: &operation::Response) -> Option<String> {
+    let headers = raw.http().headers();
+    headers
+        .get("x-amzn-requestid")
+        .or_else(|| headers.get("x-amz-request-id"))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
 impl From<std::num::ParseFloatError> for Error {
     fn from(_: std::num::ParseFloatError) -> Error {
         Error::InternalError("Unable to parse float")
     }
 }
 
+#[cfg(any(feature = "store", feature = "event-bus"))]
 impl From<&AttributeValue> for Error {
     fn from(_: &AttributeValue) -> Error {
         Error::InternalError("Invalid value type")
@@ -38,9 +185,105 @@ impl From<&AttributeValue> for Error {
 
 impl<E> From<SdkError<E>> for Error
 where
-    E: error::Error,
+    E: error::Error + ProvideErrorKind,
 {
     fn from(value: SdkError<E>) -> Error {
-        Error::SdkError(format!("{}", value))
+        let details = match value {
+            SdkError::ServiceError { err, raw } => {
+                let status = raw.http().status().as_u16();
+                SdkErrorDetails {
+                    kind: classify(err.code(), err.retryable_error_kind(), status),
+                    code: err.code().map(|c| c.to_string()),
+                    message: err.to_string(),
+                    status: Some(status),
+                    request_id: request_id(&raw),
+                }
+            }
+            SdkError::ResponseError { err, raw } => SdkErrorDetails {
+                kind: SdkErrorKind::Other,
+                code: None,
+                message: err.to_string(),
+                status: Some(raw.http().status().as_u16()),
+                request_id: request_id(&raw),
+            },
+            SdkError::TimeoutError(err) => SdkErrorDetails {
+                kind: SdkErrorKind::Transient,
+                code: None,
+                message: err.to_string(),
+                status: None,
+                request_id: None,
+            },
+            SdkError::DispatchFailure(err) => SdkErrorDetails {
+                kind: SdkErrorKind::Transient,
+                code: None,
+                message: err.to_string(),
+                status: None,
+                request_id: None,
+            },
+            SdkError::ConstructionFailure(err) => SdkErrorDetails {
+                kind: SdkErrorKind::Other,
+                code: None,
+                message: err.to_string(),
+                status: None,
+                request_id: None,
+            },
+        };
+
+        Error::SdkError(details)
+    }
+}
+
+#[cfg(all(test, any(feature = "store", feature = "event-bus")))]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::error::GetItemError;
+    use aws_smithy_http::body::SdkBody;
+
+    fn service_error(code: &str, status: u16) -> SdkError<GetItemError> {
+        let err = GetItemError::generic(
+            aws_smithy_types::Error::builder()
+                .code(code)
+                .message("something went wrong")
+                .build(),
+        );
+        let raw = operation::Response::new(
+            http::Response::builder()
+                .status(status)
+                .header("x-amzn-RequestId", "request-1")
+                .body(SdkBody::empty())
+                .unwrap(),
+        );
+        SdkError::ServiceError { err, raw }
+    }
+
+    #[test]
+    fn test_from_sdk_error_throttling() {
+        let err: Error = service_error("ProvisionedThroughputExceededException", 400).into();
+
+        assert!(err.is_throttling());
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_from_sdk_error_validation() {
+        let err: Error = service_error("ValidationException", 400).into();
+
+        match err {
+            Error::SdkError(details) => {
+                assert_eq!(details.kind, SdkErrorKind::Validation);
+                assert_eq!(details.code.as_deref(), Some("ValidationException"));
+                assert_eq!(details.status, Some(400));
+                assert_eq!(details.request_id.as_deref(), Some("request-1"));
+            }
+            _ => panic!("Expected an SdkError"),
+        }
+    }
+
+    #[test]
+    fn test_from_sdk_error_server() {
+        let err: Error = service_error("InternalFailure", 503).into();
+
+        assert!(!err.is_throttling());
+        assert!(err.is_retryable());
     }
 }
@@ -0,0 +1,360 @@
+//! API destination bus implementation
+//!
+//! Bus implementation delivering events to third-party HTTP endpoints, such
+//! as SaaS webhooks, the same way EventBridge API destinations do: each
+//! event is sent in a `POST` request to the endpoint of its event type, with
+//! the authorization of a connection.
+//!
+//! Connections authorize requests with an API key header, with basic
+//! authentication, or with an OAuth access token obtained with the client
+//! credentials grant. Access tokens are cached until shortly before they
+//! expire, and requested again if the endpoint answers with a 401 status.
+
+use super::{context, EventBus};
+use crate::{Error, Event, EventEnvelope};
+use async_trait::async_trait;
+use futures::future::join_all;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{error, info, instrument};
+
+/// Access tokens are refreshed this long before they expire
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Lifetime of access tokens returned without `expires_in`
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Authorization of the requests, as in an EventBridge connection
+#[derive(Clone)]
+pub enum ConnectionAuth {
+    /// Send an API key in the header `name`
+    ApiKey {
+        name: String,
+        value: String,
+    },
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// Send an access token obtained with the OAuth client credentials grant
+    OAuthClientCredentials {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        scope: Option<String>,
+    },
+}
+
+impl std::fmt::Debug for ConnectionAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Don't leak credentials in logs
+        f.write_str(match self {
+            ConnectionAuth::ApiKey { .. } => "ApiKey(..)",
+            ConnectionAuth::Basic { .. } => "Basic(..)",
+            ConnectionAuth::OAuthClientCredentials { .. } => "OAuthClientCredentials(..)",
+        })
+    }
+}
+
+/// Response of an OAuth token endpoint
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// API destination bus implementation.
+pub struct ApiDestinationBus {
+    client: Client,
+    auth: ConnectionAuth,
+    /// Endpoint for each event type
+    endpoints: HashMap<String, String>,
+    /// Endpoint for event types without their own endpoint
+    default_endpoint: Option<String>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl ApiDestinationBus {
+    pub fn new(client: Client, auth: ConnectionAuth) -> Self {
+        Self {
+            client,
+            auth,
+            endpoints: HashMap::new(),
+            default_endpoint: None,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Send events of this type, such as `Created`, to this endpoint
+    pub fn with_endpoint(mut self, event_type: &str, endpoint: String) -> Self {
+        self.endpoints.insert(event_type.to_string(), endpoint);
+        self
+    }
+
+    /// Send events without an endpoint for their type to this endpoint
+    ///
+    /// Without a default endpoint, these events are skipped.
+    pub fn with_default_endpoint(mut self, endpoint: String) -> Self {
+        self.default_endpoint = Some(endpoint);
+        self
+    }
+
+    fn endpoint(&self, event: &Event) -> Option<&str> {
+        self.endpoints
+            .get(event.event_type())
+            .or(self.default_endpoint.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Add the authorization of the connection to a request
+    async fn authorize(&self, req: RequestBuilder) -> Result<RequestBuilder, Error> {
+        Ok(match &self.auth {
+            ConnectionAuth::ApiKey { name, value } => req.header(name.as_str(), value.as_str()),
+            ConnectionAuth::Basic { username, password } => {
+                req.basic_auth(username, Some(password))
+            }
+            ConnectionAuth::OAuthClientCredentials { .. } => {
+                req.bearer_auth(self.access_token().await?)
+            }
+        })
+    }
+
+    /// Return the cached access token, or request a new one
+    async fn access_token(&self) -> Result<String, Error> {
+        let (token_url, client_id, client_secret, scope) = match &self.auth {
+            ConnectionAuth::OAuthClientCredentials {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            } => (token_url, client_id, client_secret, scope),
+            _ => return Err(Error::InternalError("Connection doesn't use OAuth")),
+        };
+
+        // Holding the lock while requesting a token prevents concurrent
+        // requests from all refreshing it
+        let mut token = self.token.lock().await;
+        if let Some((value, expires_at)) = token.as_ref() {
+            if Instant::now() < *expires_at {
+                return Ok(value.clone());
+            }
+        }
+
+        info!("Requesting OAuth access token");
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = scope {
+            form.push(("scope", scope.as_str()));
+        }
+        let res = self
+            .client
+            .post(token_url)
+            .basic_auth(client_id, Some(client_secret))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling OAuth token endpoint: {}", err);
+                Error::InternalError("Unable to reach OAuth token endpoint")
+            })?;
+        if !res.status().is_success() {
+            error!("OAuth token endpoint returned status {}", res.status());
+            return Err(Error::InternalError("Unable to get an OAuth access token"));
+        }
+        let body: TokenResponse = res
+            .json()
+            .await
+            .map_err(|_| Error::InternalError("Invalid response from OAuth token endpoint"))?;
+
+        let lifetime = body
+            .expires_in
+            .map_or(DEFAULT_TOKEN_LIFETIME, Duration::from_secs);
+        *token = Some((
+            body.access_token.clone(),
+            Instant::now() + lifetime.saturating_sub(TOKEN_EXPIRY_MARGIN),
+        ));
+        Ok(body.access_token)
+    }
+
+    async fn build_request(
+        &self,
+        endpoint: &str,
+        envelope: &EventEnvelope,
+    ) -> Result<RequestBuilder, Error> {
+        let req = self
+            .client
+            .post(endpoint)
+            .header("x-event-type", envelope.event.event_type())
+            .json(envelope);
+        self.authorize(req).await
+    }
+
+    async fn deliver(&self, endpoint: &str, event: &Event) -> Result<(), Error> {
+        let send = |req: RequestBuilder| async move {
+            req.send().await.map_err(|err| {
+                error!("Error calling API destination: {}", err);
+                Error::InternalError("Unable to reach API destination")
+            })
+        };
+
+        let envelope = context::envelope(event)?;
+        let mut res = send(self.build_request(endpoint, &envelope).await?).await?;
+        if res.status() == StatusCode::UNAUTHORIZED
+            && matches!(self.auth, ConnectionAuth::OAuthClientCredentials { .. })
+        {
+            // The token may have been revoked: get a new one and try again
+            *self.token.lock().await = None;
+            res = send(self.build_request(endpoint, &envelope).await?).await?;
+        }
+
+        if !res.status().is_success() {
+            error!("API destination returned status {}", res.status());
+            return Err(Error::InternalError("API destination returned an error"));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventBus for ApiDestinationBus {
+    type E = Event;
+
+    /// Deliver an event to the endpoint of its type.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        match self.endpoint(event) {
+            Some(endpoint) => {
+                info!("Delivering event to API destination");
+                self.deliver(endpoint, event).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Deliver a batch of events, one request per event.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let res = join_all(events.iter().map(|event| self.send_event(event))).await;
+
+        // If any of the requests failed, we'll return an error.
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+
+    fn get_event() -> Event {
+        Event::Created {
+            product: Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_endpoint() {
+        // GIVEN a bus with an endpoint for created products only
+        let bus = ApiDestinationBus::new(
+            Client::new(),
+            ConnectionAuth::ApiKey {
+                name: "x-api-key".to_string(),
+                value: "secret".to_string(),
+            },
+        )
+        .with_endpoint("Created", "https://example.com/created".to_string());
+
+        // THEN other events have no endpoint
+        assert_eq!(
+            bus.endpoint(&get_event()),
+            Some("https://example.com/created")
+        );
+        assert_eq!(
+            bus.endpoint(&Event::Deleted {
+                product: Product {
+                    id: "1".to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                }
+            }),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_request_api_key() -> Result<(), Error> {
+        // GIVEN a bus authorizing requests with an API key
+        let bus = ApiDestinationBus::new(
+            Client::new(),
+            ConnectionAuth::ApiKey {
+                name: "x-api-key".to_string(),
+                value: "secret".to_string(),
+            },
+        );
+
+        // WHEN building the request for an event
+        let req = bus
+            .build_request(
+                "https://example.com/events",
+                &context::envelope(&get_event())?,
+            )
+            .await?
+            .build()
+            .unwrap();
+
+        // THEN the request contains the API key and the event
+        assert_eq!(req.headers()["x-api-key"], "secret");
+        assert_eq!(req.headers()["x-event-type"], "Created");
+        let body: Event = serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body, get_event());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_request_cached_token() -> Result<(), Error> {
+        // GIVEN an OAuth connection with a cached token
+        let bus = ApiDestinationBus::new(
+            Client::new(),
+            ConnectionAuth::OAuthClientCredentials {
+                token_url: "https://example.com/token".to_string(),
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+                scope: None,
+            },
+        );
+        *bus.token.lock().await = Some((
+            "token".to_string(),
+            Instant::now() + Duration::from_secs(60),
+        ));
+
+        // WHEN building a request
+        let req = bus
+            .build_request(
+                "https://example.com/events",
+                &context::envelope(&get_event())?,
+            )
+            .await?
+            .build()
+            .unwrap();
+
+        // THEN the cached token is used
+        assert_eq!(req.headers()["authorization"], "Bearer token");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! Batching bus
+//!
+//! Bus wrapper buffering events and sending them to the inner bus in
+//! batches, which cuts the number of `PutEvents` calls for bursty streams
+//! that send a few events at a time.
+//!
+//! Events are kept in memory until the buffer holds `max_size` events, or
+//! until the oldest buffered event is older than `max_latency`. The latency
+//! is checked when events are sent, and by the task started with
+//! `spawn_flusher` for buses that can go quiet with events still buffered.
+//! Lambda functions must call `flush()` before the end of each invocation,
+//! as the execution environment can be frozen or shut down afterwards.
+//!
+//! If a flush fails, the events stay in the buffer and the error is
+//! returned, so that the flush can be retried.
+
+use super::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, instrument, warn};
+
+/// Default number of events sent in a single batch
+///
+/// This matches the maximum number of entries of a `PutEvents` request.
+const DEFAULT_MAX_SIZE: usize = 10;
+
+/// Default time after which buffered events are sent
+const DEFAULT_MAX_LATENCY: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct Buffer {
+    events: Vec<Event>,
+    /// Time of the oldest buffered event
+    since: Option<Instant>,
+}
+
+/// State shared with the background task
+struct Shared<B> {
+    inner: B,
+    buffer: Mutex<Buffer>,
+}
+
+/// Bus buffering events and sending them in batches.
+pub struct BatchingBus<B> {
+    shared: Arc<Shared<B>>,
+    max_size: usize,
+    max_latency: Duration,
+}
+
+impl<B> BatchingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                inner,
+                buffer: Default::default(),
+            }),
+            max_size: DEFAULT_MAX_SIZE,
+            max_latency: DEFAULT_MAX_LATENCY,
+        }
+    }
+
+    /// Set the number of buffered events that triggers a flush
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    /// Set the age of the oldest buffered event that triggers a flush
+    pub fn with_max_latency(mut self, max_latency: Duration) -> Self {
+        self.max_latency = max_latency;
+        self
+    }
+
+    /// Number of events waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.shared.buffer.lock().unwrap().events.len()
+    }
+
+    /// Buffer events, and flush the buffer if it is full or too old
+    async fn buffer(&self, events: &[Event]) -> Result<(), Error> {
+        let should_flush = {
+            let mut buffer = self.shared.buffer.lock().unwrap();
+            buffer.events.extend_from_slice(events);
+            let since = *buffer.since.get_or_insert_with(Instant::now);
+            buffer.events.len() >= self.max_size || since.elapsed() >= self.max_latency
+        };
+
+        if should_flush {
+            self.shared.flush(self.max_size).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<B> Shared<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    /// Take all buffered events
+    fn take(&self) -> Vec<Event> {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.since = None;
+        mem::take(&mut buffer.events)
+    }
+
+    /// Put events back at the front of the buffer after a failed send
+    fn restore(&self, mut events: Vec<Event>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        events.append(&mut buffer.events);
+        buffer.events = events;
+        buffer.since.get_or_insert_with(Instant::now);
+    }
+
+    /// Send all buffered events, in batches of `max_size`
+    async fn flush(&self, max_size: usize) -> Result<(), Error> {
+        let events = self.take();
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        info!("Flushing {} buffered events", events.len());
+        for (index, batch) in events.chunks(max_size).enumerate() {
+            if let Err(err) = self.inner.send_events(batch).await {
+                warn!("Failed to flush buffered events: {}", err);
+                self.restore(events[index * max_size..].to_vec());
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B> BatchingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync + 'static,
+{
+    /// Flush the buffer every `max_latency`, even if nothing is sent
+    ///
+    /// The task stops once the bus is dropped.
+    pub fn spawn_flusher(&self) -> JoinHandle<()> {
+        let shared = Arc::downgrade(&self.shared);
+        let period = self.max_latency.max(Duration::from_millis(1));
+        let max_size = self.max_size;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match shared.upgrade() {
+                    Some(shared) => {
+                        if let Err(err) = shared.flush(max_size).await {
+                            error!("Failed to flush buffered events: {}", err);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for BatchingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.buffer(std::slice::from_ref(event)).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        self.buffer(events).await
+    }
+
+    /// Send all buffered events
+    #[instrument(skip(self))]
+    async fn flush(&self) -> Result<(), Error> {
+        self.shared.flush(self.max_size).await?;
+        self.shared.inner.flush().await
+    }
+}
+
+impl<B> Drop for BatchingBus<B> {
+    fn drop(&mut self) {
+        let pending = self.shared.buffer.lock().map_or(0, |b| b.events.len());
+        if pending > 0 {
+            warn!("Dropping batching bus with {} unsent events", pending);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Bus counting batches, failing the next `fail` sends
+    #[derive(Default)]
+    struct CountingBus {
+        batches: Mutex<Vec<usize>>,
+        fail: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EventBus for CountingBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            if self.fail.load(Ordering::Relaxed) > 0 {
+                self.fail.fetch_sub(1, Ordering::Relaxed);
+                return Err(Error::InternalError("Bus is unavailable"));
+            }
+            self.batches.lock().unwrap().push(events.len());
+            Ok(())
+        }
+    }
+
+    fn get_event(id: usize) -> Event {
+        Event::Created {
+            product: Product {
+                id: id.to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events_batches() -> Result<(), Error> {
+        // GIVEN a bus sending batches of 10 events at most every minute
+        let bus =
+            BatchingBus::new(CountingBus::default()).with_max_latency(Duration::from_secs(60));
+
+        // WHEN sending 25 events one at a time, then flushing
+        for id in 0..25 {
+            bus.send_event(&get_event(id)).await?;
+        }
+        assert_eq!(bus.pending(), 5);
+        bus.flush().await?;
+
+        // THEN the events are sent in three batches
+        assert_eq!(*bus.shared.inner.batches.lock().unwrap(), vec![10, 10, 5]);
+        assert_eq!(bus.pending(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flush_failure() -> Result<(), Error> {
+        // GIVEN a bus with buffered events and an unavailable inner bus
+        let bus =
+            BatchingBus::new(CountingBus::default()).with_max_latency(Duration::from_secs(60));
+        bus.send_events(&[get_event(1), get_event(2)]).await?;
+        bus.shared.inner.fail.store(1, Ordering::Relaxed);
+
+        // WHEN flushing
+        let res = bus.flush().await;
+
+        // THEN the error is returned and the events are kept
+        assert!(res.is_err());
+        assert_eq!(bus.pending(), 2);
+        // AND they are sent once the inner bus is back
+        bus.flush().await?;
+        assert_eq!(*bus.shared.inner.batches.lock().unwrap(), vec![2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spawn_flusher() -> Result<(), Error> {
+        // GIVEN a bus with a flusher and a short latency
+        let bus =
+            BatchingBus::new(CountingBus::default()).with_max_latency(Duration::from_millis(10));
+        bus.spawn_flusher();
+
+        // WHEN an event stays buffered without new events
+        bus.send_event(&get_event(1)).await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // THEN it is sent by the flusher
+        assert_eq!(bus.pending(), 0);
+        assert_eq!(*bus.shared.inner.batches.lock().unwrap(), vec![1]);
+
+        Ok(())
+    }
+}
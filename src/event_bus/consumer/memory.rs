@@ -0,0 +1,77 @@
+//! # In-memory consumer implementation
+//!
+//! Events are pushed by the caller instead of a bus, which is useful to test
+//! handlers. Events are delivered again on each receive until they are
+//! acknowledged, as with a queue without visibility timeout.
+
+use super::{Delivery, EventConsumer};
+use crate::{Error, EventEnvelope};
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+/// Default maximum number of events received at once
+const DEFAULT_BATCH_SIZE: usize = 10;
+
+pub struct MemoryEventConsumer {
+    batch_size: usize,
+    /// Events that weren't acknowledged, with their receipt
+    events: Mutex<Vec<(String, EventEnvelope)>>,
+    next_receipt: Mutex<u64>,
+}
+
+impl Default for MemoryEventConsumer {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            events: Default::default(),
+            next_receipt: Default::default(),
+        }
+    }
+}
+
+impl MemoryEventConsumer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the maximum number of events received at once
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Add an event to the queue
+    pub fn push(&self, envelope: EventEnvelope) {
+        let mut next_receipt = self.next_receipt.lock().unwrap();
+        *next_receipt += 1;
+        self.events
+            .lock()
+            .unwrap()
+            .push((next_receipt.to_string(), envelope));
+    }
+}
+
+#[async_trait]
+impl EventConsumer for MemoryEventConsumer {
+    async fn receive(&self) -> Result<Vec<Delivery>, Error> {
+        Ok(self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .take(self.batch_size)
+            .map(|(receipt, envelope)| Delivery {
+                envelope: envelope.clone(),
+                receipt: receipt.clone(),
+            })
+            .collect())
+    }
+
+    async fn ack(&self, deliveries: &[Delivery]) -> Result<(), Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .retain(|(receipt, _)| !deliveries.iter().any(|d| &d.receipt == receipt));
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Consumer side of the event bus
+//!
+//! `EventBus` publishes events, and `EventConsumer` receives them from a
+//! queue subscribed to the bus, such as the SQS FIFO queue of `SqsFifoBus`
+//! or a queue targeted by an EventBridge rule.
+//!
+//! Queues deliver events at least once: a received event stays in the queue
+//! until it is acknowledged, and is delivered again if it isn't, e.g. when
+//! the handler fails. Handlers should suppress duplicates with the event id
+//! of the metadata, see `consumer::process_once`.
+
+use crate::{Error, EventEnvelope};
+use async_trait::async_trait;
+use std::future::Future;
+use tracing::{error, info};
+
+mod memory;
+#[cfg(feature = "sqs")]
+mod sqs;
+
+pub use memory::MemoryEventConsumer;
+#[cfg(feature = "sqs")]
+pub use sqs::SqsEventConsumer;
+
+/// Event received from a queue
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub envelope: EventEnvelope,
+    /// Handle to acknowledge the event with
+    pub receipt: String,
+}
+
+/// Trait for processing the events received by a consumer
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    async fn handle(&self, events: Vec<EventEnvelope>) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl<F, Fut> EventHandler for F
+where
+    F: Fn(Vec<EventEnvelope>) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), Error>> + Send,
+{
+    async fn handle(&self, events: Vec<EventEnvelope>) -> Result<(), Error> {
+        self(events).await
+    }
+}
+
+/// Trait for receiving events
+#[async_trait]
+pub trait EventConsumer: Send + Sync {
+    /// Receive the next batch of events
+    ///
+    /// Consumers wait a while for events to arrive, and return an empty
+    /// batch if none did.
+    async fn receive(&self) -> Result<Vec<Delivery>, Error>;
+
+    /// Acknowledge processed events, so that they aren't delivered again
+    async fn ack(&self, deliveries: &[Delivery]) -> Result<(), Error>;
+
+    /// Receive a batch of events and pass it to a handler
+    ///
+    /// The batch is acknowledged if the handler succeeds. Otherwise, the
+    /// error is logged and the events are left in the queue, to be delivered
+    /// again. Returns the number of processed events.
+    async fn poll(&self, handler: &dyn EventHandler) -> Result<usize, Error> {
+        let deliveries = self.receive().await?;
+        if deliveries.is_empty() {
+            return Ok(0);
+        }
+
+        let events = deliveries.iter().map(|d| d.envelope.clone()).collect();
+        if let Err(err) = handler.handle(events).await {
+            error!(
+                "Failed to process {} events, leaving them in the queue: {}",
+                deliveries.len(),
+                err
+            );
+            return Ok(0);
+        }
+        self.ack(&deliveries).await?;
+        info!("Processed {} events", deliveries.len());
+        Ok(deliveries.len())
+    }
+
+    /// Pass every batch of events to a handler, until receiving fails
+    async fn subscribe(&self, handler: &dyn EventHandler) -> Result<(), Error> {
+        loop {
+            self.poll(handler).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::golden;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_poll_retry() -> Result<(), Error> {
+        // GIVEN a queue with events, and a handler failing the first time
+        let consumer = MemoryEventConsumer::new();
+        let envelopes: Vec<EventEnvelope> = golden::get_events()
+            .into_iter()
+            .map(|(_, event)| EventEnvelope {
+                event,
+                metadata: Some(golden::get_metadata()),
+            })
+            .collect();
+        for envelope in &envelopes {
+            consumer.push(envelope.clone());
+        }
+        let calls = AtomicUsize::new(0);
+        let handler = |events: Vec<EventEnvelope>| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                assert!(!events.is_empty());
+                match call {
+                    0 => Err(Error::InternalError("failure")),
+                    _ => Ok(()),
+                }
+            }
+        };
+
+        // WHEN polling the queue until it is empty
+        let first = consumer.poll(&handler).await?;
+        let second = consumer.poll(&handler).await?;
+        let third = consumer.poll(&handler).await?;
+
+        // THEN the failed batch is delivered again
+        assert_eq!(first, 0);
+        assert_eq!(second, envelopes.len());
+        // AND acknowledged events are not
+        assert_eq!(third, 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! SQS consumer implementation
+//!
+//! Receives events from an SQS queue with long polling. Messages can be the
+//! `OrderedEnvelope` of `SqsFifoBus`, an EventBridge event delivered by a
+//! rule targeting the queue, or a bare `EventEnvelope`: the first two carry
+//! the envelope in their `detail` field.
+//!
+//! Messages that can't be parsed are never acknowledged, so that the redrive
+//! policy of the queue moves them to its dead-letter queue.
+
+use super::{Delivery, EventConsumer};
+use crate::{Error, EventEnvelope};
+use async_trait::async_trait;
+use aws_sdk_sqs::{model::DeleteMessageBatchRequestEntry, Client};
+use serde::Deserialize;
+use tracing::{error, info, instrument, warn};
+
+/// Maximum number of messages in a ReceiveMessage or DeleteMessageBatch
+/// request
+const BATCH_SIZE: usize = 10;
+
+/// Default time to wait for messages, in seconds
+const DEFAULT_WAIT_TIME: i32 = 20;
+
+/// Message wrapping an envelope in its `detail` field
+#[derive(Deserialize)]
+struct Detail {
+    detail: EventEnvelope,
+}
+
+/// Parse the envelope of a message body
+fn parse_body(body: &str) -> Option<EventEnvelope> {
+    serde_json::from_str::<Detail>(body)
+        .map(|message| message.detail)
+        .or_else(|_| serde_json::from_str::<EventEnvelope>(body))
+        .ok()
+}
+
+/// SQS consumer implementation.
+pub struct SqsEventConsumer {
+    client: Client,
+    queue_url: String,
+    wait_time: i32,
+}
+
+impl SqsEventConsumer {
+    pub fn new(client: Client, queue_url: String) -> Self {
+        Self {
+            client,
+            queue_url,
+            wait_time: DEFAULT_WAIT_TIME,
+        }
+    }
+
+    /// Set the time to wait for messages, in seconds, up to 20
+    pub fn with_wait_time(mut self, wait_time: i32) -> Self {
+        self.wait_time = wait_time;
+        self
+    }
+}
+
+#[async_trait]
+impl EventConsumer for SqsEventConsumer {
+    /// Receive up to 10 messages from the queue
+    #[instrument(skip(self))]
+    async fn receive(&self) -> Result<Vec<Delivery>, Error> {
+        let res = self
+            .client
+            .receive_message()
+            .queue_url(&self.queue_url)
+            .max_number_of_messages(BATCH_SIZE as i32)
+            .wait_time_seconds(self.wait_time)
+            .send()
+            .await?;
+
+        let deliveries: Vec<Delivery> = res
+            .messages
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|message| {
+                let receipt = message.receipt_handle?;
+                match message.body.as_deref().and_then(parse_body) {
+                    Some(envelope) => Some(Delivery { envelope, receipt }),
+                    None => {
+                        warn!(
+                            "Skipping invalid message {}",
+                            message.message_id.unwrap_or_default()
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+        info!("Received {} events from SQS", deliveries.len());
+        Ok(deliveries)
+    }
+
+    /// Delete messages from the queue, in batches of 10 messages
+    #[instrument(skip(self, deliveries))]
+    async fn ack(&self, deliveries: &[Delivery]) -> Result<(), Error> {
+        for chunk in deliveries.chunks(BATCH_SIZE) {
+            let entries = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, delivery)| {
+                    DeleteMessageBatchRequestEntry::builder()
+                        .id(i.to_string())
+                        .receipt_handle(&delivery.receipt)
+                        .build()
+                })
+                .collect();
+            let res = self
+                .client
+                .delete_message_batch()
+                .queue_url(&self.queue_url)
+                .set_entries(Some(entries))
+                .send()
+                .await?;
+
+            if let Some(failed) = res.failed {
+                if !failed.is_empty() {
+                    error!("SQS failed to delete {} messages", failed.len());
+                    return Err(Error::InternalError("Failed to delete messages from SQS"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::golden,
+        test_util::{mock_sdk_config, request_builder},
+    };
+    use aws_sdk_sqs::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn message(id: &str, body: &str) -> String {
+        format!(
+            "<Message><MessageId>{}</MessageId><ReceiptHandle>receipt-{}</ReceiptHandle><MD5OfBody>0</MD5OfBody><Body>{}</Body></Message>",
+            id, id, body
+        )
+    }
+
+    #[tokio::test]
+    async fn test_receive_ack() -> Result<(), Error> {
+        // GIVEN a queue with a FIFO bus message, an EventBridge event and
+        // an invalid message
+        let envelope = EventEnvelope {
+            event: golden::get_events().remove(0).1,
+            metadata: Some(golden::get_metadata()),
+        };
+        let detail = serde_json::to_string(&envelope).unwrap();
+        let messages = [
+            message(
+                "1",
+                &format!(
+                    r#"{{"product_id": "1", "sequence": 1, "detail": {}}}"#,
+                    detail
+                ),
+            ),
+            message(
+                "2",
+                &format!(
+                    r#"{{"detail-type": "ProductCreated", "detail": {}}}"#,
+                    detail
+                ),
+            ),
+            message("3", "not json"),
+        ];
+        let conn = TestConnection::new(vec![
+            (
+                request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                    .body(SdkBody::from(""))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(format!(
+                        "<ReceiveMessageResponse><ReceiveMessageResult>{}</ReceiveMessageResult></ReceiveMessageResponse>",
+                        messages.concat()
+                    )))
+                    .unwrap(),
+            ),
+            (
+                request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                    .body(SdkBody::from(""))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        "<DeleteMessageBatchResponse><DeleteMessageBatchResult><DeleteMessageBatchResultEntry><Id>0</Id></DeleteMessageBatchResultEntry><DeleteMessageBatchResultEntry><Id>1</Id></DeleteMessageBatchResultEntry></DeleteMessageBatchResult></DeleteMessageBatchResponse>",
+                    ))
+                    .unwrap(),
+            ),
+        ]);
+        let consumer = SqsEventConsumer::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events.fifo".to_string(),
+        );
+
+        // WHEN receiving and acknowledging events
+        let deliveries = consumer.receive().await?;
+        consumer.ack(&deliveries).await?;
+
+        // THEN the valid messages are parsed
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].envelope, envelope);
+        assert_eq!(deliveries[1].envelope, envelope);
+        // AND deleted with their receipt
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body = std::str::from_utf8(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("Action=DeleteMessageBatch"));
+        assert!(body.contains("ReceiptHandle=receipt-1"));
+        assert!(body.contains("ReceiptHandle=receipt-2"));
+        assert!(!body.contains("receipt-3"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Event context
+//!
+//! Every event published by a bus carries an `EventMetadata` with a unique
+//! event id, a correlation id and a causation id, so that the events caused
+//! by a request can be traced across services.
+//!
+//! Entrypoints run their handler within an `EventContext` scope, built from
+//! the API Gateway request or the Lambda invocation, so that buses can read
+//! it without it being passed around. Events published outside of a scope,
+//! such as by tools or by a background flush, start their own correlation.
+//!
+//! Event ids are random, unless the context holds a deterministic id for
+//! the event, such as one derived from the stream record it comes from.
+//! Each serialization of the event then uses the next of its ids, so that a
+//! record retried by the stream is published with the same id again.
+
+use crate::{Error, Event, EventEnvelope, EventMetadata};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+tokio::task_local! {
+    static CONTEXT: EventContext;
+}
+
+/// Request or invocation publishing events
+///
+/// The default context has no correlation id, so each event starts its own
+/// correlation.
+#[derive(Clone, Debug, Default)]
+pub struct EventContext {
+    correlation_id: Option<String>,
+    causation_id: Option<String>,
+    tags: BTreeMap<String, String>,
+    /// Deterministic ids by serialized event, shared with the copies of
+    /// this context
+    event_ids: Arc<Mutex<HashMap<String, VecDeque<String>>>>,
+}
+
+impl EventContext {
+    pub fn new(correlation_id: impl Into<String>) -> Self {
+        Self {
+            correlation_id: Some(correlation_id.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Context of a Lambda invocation
+    ///
+    /// Events are correlated by the X-Ray trace id if there is one, or by
+    /// the request id of the invocation otherwise, and are caused by the
+    /// invocation.
+    pub fn from_invocation(request_id: &str, trace_id: &str) -> Self {
+        let correlation_id = if trace_id.is_empty() {
+            request_id
+        } else {
+            trace_id
+        };
+        Self::new(correlation_id).with_causation(request_id)
+    }
+
+    /// Set the id of the request or invocation causing the events
+    pub fn with_causation(mut self, causation_id: impl Into<String>) -> Self {
+        self.causation_id = Some(causation_id.into());
+        self
+    }
+
+    /// Add tags to the metadata of the events
+    pub fn with_tags(mut self, tags: &BTreeMap<String, String>) -> Self {
+        self.tags
+            .extend(tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+
+    /// Set deterministic ids for events, in the order they are published
+    pub fn with_event_ids<'a>(self, ids: impl IntoIterator<Item = (&'a Event, String)>) -> Self {
+        for (event, id) in ids {
+            if let Some(key) = event_key(event) {
+                self.event_ids
+                    .lock()
+                    .unwrap()
+                    .entry(key)
+                    .or_default()
+                    .push_back(id);
+            }
+        }
+        self
+    }
+
+    /// Take the next deterministic id of an event, if any
+    pub fn take_event_id(&self, event: &Event) -> Option<String> {
+        let key = event_key(event)?;
+        self.event_ids.lock().unwrap().get_mut(&key)?.pop_front()
+    }
+
+    /// Give back an id taken with `take_event_id`, to be used next
+    pub fn restore_event_id(&self, event: &Event, id: String) {
+        if let Some(key) = event_key(event) {
+            self.event_ids
+                .lock()
+                .unwrap()
+                .entry(key)
+                .or_default()
+                .push_front(id);
+        }
+    }
+
+    /// Run a future with this context
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CONTEXT.scope(self, f).await
+    }
+
+    /// Context of the running task, if any
+    pub fn current() -> Option<Self> {
+        CONTEXT.try_with(Clone::clone).ok()
+    }
+}
+
+fn event_key(event: &Event) -> Option<String> {
+    serde_json::to_string(event).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Deterministic id of the event of a stream record
+///
+/// This is a hash of the sequence number of the record and the type of the
+/// event, with the same length as random ids.
+pub fn event_id(sequence_number: &str, event_type: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sequence_number.as_bytes());
+    hasher.update(b":");
+    hasher.update(event_type.as_bytes());
+    to_hex(&hasher.finalize()[..16])
+}
+
+/// Metadata of an event published now, in the current context
+pub fn metadata() -> Result<EventMetadata, Error> {
+    let mut id = [0; 16];
+    getrandom::getrandom(&mut id)
+        .map_err(|_| Error::InternalError("Failed to generate event id"))?;
+    let event_id = to_hex(&id);
+    let occurred_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_millis() as u64;
+
+    let context = EventContext::current().unwrap_or_default();
+    Ok(EventMetadata {
+        correlation_id: context.correlation_id.unwrap_or_else(|| event_id.clone()),
+        event_id,
+        causation_id: context.causation_id,
+        occurred_at,
+        tags: context.tags,
+    })
+}
+
+/// Wrap an event with new metadata, before serializing it
+///
+/// The event gets its next deterministic id from the current context, if
+/// there is one.
+pub fn envelope(event: &Event) -> Result<EventEnvelope, Error> {
+    let mut metadata = metadata()?;
+    if let Some(event_id) = EventContext::current().and_then(|c| c.take_event_id(event)) {
+        if metadata.correlation_id == metadata.event_id {
+            metadata.correlation_id = event_id.clone();
+        }
+        metadata.event_id = event_id;
+    }
+    Ok(EventEnvelope {
+        event: event.clone(),
+        metadata: Some(metadata),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metadata_scope() -> Result<(), Error> {
+        // GIVEN a context for a request
+        let context = EventContext::new("correlation").with_causation("request");
+
+        // WHEN getting metadata within and outside of its scope
+        let (first, second) = context.scope(async { (metadata(), metadata()) }).await;
+        let outside = metadata()?;
+        let (first, second) = (first?, second?);
+
+        // THEN events in the scope share its correlation id
+        assert_eq!(first.correlation_id, "correlation");
+        assert_eq!(first.causation_id.as_deref(), Some("request"));
+        assert_eq!(second.correlation_id, "correlation");
+        // AND each event has its own id
+        assert_ne!(first.event_id, second.event_id);
+        // AND events outside start their own correlation
+        assert_eq!(outside.correlation_id, outside.event_id);
+        assert_eq!(outside.causation_id, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_envelope_event_ids() -> Result<(), Error> {
+        // GIVEN a context with the ids of an event retried by the stream
+        let event = crate::event_bus::golden::get_events().remove(0).1;
+        let id = event_id("111", event.event_type());
+        let context = EventContext::default().with_event_ids([(&event, id.clone())]);
+
+        // WHEN serializing the event twice in its scope
+        let (first, second) = context
+            .scope(async { (envelope(&event), envelope(&event)) })
+            .await;
+
+        // THEN the first serialization uses the deterministic id
+        assert_eq!(first?.metadata.unwrap().event_id, id);
+        assert_eq!(id, event_id("111", "Created"));
+        assert_ne!(id, event_id("112", "Created"));
+        // AND later ones get a random id
+        assert_ne!(second?.metadata.unwrap().event_id, id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! DynamoDB dead-letter queue
+//!
+//! Each dead letter is stored as an item with a random `id`, the product id,
+//! the event type, the error code and message, the time of the failure and
+//! the serialized event.
+
+use super::{DeadLetter, DeadLetterQueue};
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use futures::future::join_all;
+use tracing::{info, instrument};
+
+/// DynamoDB dead-letter queue implementation.
+pub struct DynamoDBDeadLetterQueue {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBDeadLetterQueue {
+    pub fn new(client: Client, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    async fn put_letter(&self, letter: &DeadLetter) -> Result<(), Error> {
+        let mut id = [0; 16];
+        getrandom::getrandom(&mut id)
+            .map_err(|_| Error::InternalError("Failed to generate dead letter id"))?;
+        let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+        let event = serde_json::to_string(&letter.event)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(id))
+            .item(
+                "product_id",
+                AttributeValue::S(letter.event.id().to_string()),
+            )
+            .item(
+                "event_type",
+                AttributeValue::S(letter.event.event_type().to_string()),
+            )
+            .item("error_code", AttributeValue::S(letter.code.clone()))
+            .item("error_message", AttributeValue::S(letter.message.clone()))
+            .item("failed_at", AttributeValue::N(letter.failed_at.to_string()))
+            .item("event", AttributeValue::S(event))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DeadLetterQueue for DynamoDBDeadLetterQueue {
+    /// Store dead letters in the table
+    #[instrument(skip(self, letters))]
+    async fn push(&self, letters: &[DeadLetter]) -> Result<(), Error> {
+        info!("Storing {} dead letters in DynamoDB", letters.len());
+        let res = join_all(letters.iter().map(|letter| self.put_letter(letter))).await;
+
+        // If any of the requests failed, we'll return an error.
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
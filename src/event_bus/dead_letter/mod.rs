@@ -0,0 +1,252 @@
+//! Dead-letter channel for undeliverable events
+//!
+//! `DeadLetterBus` wraps another bus. When the inner bus gives up on some
+//! events, after its own retries, they are written to a `DeadLetterQueue`
+//! with the reason of the failure, so that they can be inspected and sent
+//! again later instead of being lost.
+//!
+//! Events rejected individually, as reported by `Error::EventsFailed`, are
+//! dead-lettered with their own error code. If the whole request failed,
+//! every event of the batch is dead-lettered with the error of the request.
+//! Once the events are safely in the queue, sending them is considered
+//! successful. If the queue can't be written to either, the original error
+//! is returned.
+
+use super::EventBus;
+use crate::{Error, Event, FailedEvent};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, instrument, warn};
+
+mod dynamodb;
+#[cfg(feature = "sqs")]
+mod sqs;
+
+pub use dynamodb::DynamoDBDeadLetterQueue;
+#[cfg(feature = "sqs")]
+pub use sqs::SqsDeadLetterQueue;
+
+/// Event that couldn't be delivered, with the reason of the failure
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeadLetter {
+    pub event: Event,
+    /// Error code, e.g. `MalformedDetail`
+    pub code: String,
+    pub message: String,
+    /// Time of the failure, in milliseconds since the epoch
+    pub failed_at: u64,
+}
+
+/// Trait for storing undeliverable events
+#[async_trait]
+pub trait DeadLetterQueue: Send + Sync {
+    async fn push(&self, letters: &[DeadLetter]) -> Result<(), Error>;
+}
+
+/// Bus sending undeliverable events to a dead-letter queue
+pub struct DeadLetterBus<B> {
+    inner: B,
+    queue: Box<dyn DeadLetterQueue>,
+}
+
+impl<B> DeadLetterBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B, queue: Box<dyn DeadLetterQueue>) -> Self {
+        Self { inner, queue }
+    }
+
+    /// Write the events a failed call couldn't deliver to the queue
+    async fn dead_letter(&self, events: &[Event], err: Error) -> Result<(), Error> {
+        let failed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_millis() as u64;
+        let letters: Vec<DeadLetter> = match &err {
+            Error::EventsFailed(failed) => failed
+                .iter()
+                .map(
+                    |FailedEvent {
+                         event,
+                         code,
+                         message,
+                     }| DeadLetter {
+                        event: event.clone(),
+                        code: code.clone(),
+                        message: message.clone(),
+                        failed_at,
+                    },
+                )
+                .collect(),
+            _ => {
+                let code = match &err {
+                    Error::SdkError(details) => details.code.clone(),
+                    _ => None,
+                };
+                events
+                    .iter()
+                    .map(|event| DeadLetter {
+                        event: event.clone(),
+                        code: code.clone().unwrap_or_else(|| "RequestFailed".to_string()),
+                        message: err.to_string(),
+                        failed_at,
+                    })
+                    .collect()
+            }
+        };
+
+        warn!("Sending {} events to the dead-letter queue", letters.len());
+        if let Err(queue_err) = self.queue.push(&letters).await {
+            error!("Failed to write to the dead-letter queue: {}", queue_err);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for DeadLetterBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        match self.inner.send_event(event).await {
+            Ok(()) => Ok(()),
+            Err(err) => self.dead_letter(std::slice::from_ref(event), err).await,
+        }
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        match self.inner.send_events(events).await {
+            Ok(()) => Ok(()),
+            Err(err) => self.dead_letter(events, err).await,
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::VoidBus, Product};
+    use std::sync::{Arc, Mutex};
+
+    /// Queue keeping dead letters in memory
+    #[derive(Default)]
+    struct MemoryQueue {
+        letters: Arc<Mutex<Vec<DeadLetter>>>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl DeadLetterQueue for MemoryQueue {
+        async fn push(&self, letters: &[DeadLetter]) -> Result<(), Error> {
+            if self.fail {
+                return Err(Error::InternalError("Queue is unavailable"));
+            }
+            self.letters.lock().unwrap().extend_from_slice(letters);
+            Ok(())
+        }
+    }
+
+    /// Bus rejecting the first event of every batch
+    struct RejectFirstBus;
+
+    #[async_trait]
+    impl EventBus for RejectFirstBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            Err(Error::EventsFailed(vec![FailedEvent {
+                event: events[0].clone(),
+                code: "MalformedDetail".to_string(),
+                message: "Detail is malformed".to_string(),
+            }]))
+        }
+    }
+
+    fn get_events() -> Vec<Event> {
+        (0..2)
+            .map(|i| Event::Created {
+                product: Product {
+                    id: i.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_send_events_rejected() -> Result<(), Error> {
+        // GIVEN a bus rejecting one event
+        let queue = MemoryQueue::default();
+        let letters = queue.letters.clone();
+        let bus = DeadLetterBus::new(RejectFirstBus, Box::new(queue));
+        let events = get_events();
+
+        // WHEN sending events
+        bus.send_events(&events).await?;
+
+        // THEN only the rejected event is dead-lettered, with its error code
+        let letters = letters.lock().unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].event, events[0]);
+        assert_eq!(letters[0].code, "MalformedDetail");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_failed() -> Result<(), Error> {
+        // GIVEN a bus failing every request
+        let queue = MemoryQueue::default();
+        let letters = queue.letters.clone();
+        let bus = DeadLetterBus::new(VoidBus, Box::new(queue));
+        let events = get_events();
+
+        // WHEN sending events
+        bus.send_events(&events).await?;
+
+        // THEN every event is dead-lettered
+        let letters = letters.lock().unwrap();
+        assert_eq!(
+            letters.iter().map(|l| &l.event).collect::<Vec<_>>(),
+            events.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(letters[0].code, "RequestFailed");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_queue_failed() {
+        // GIVEN a failing bus and a failing queue
+        let queue = MemoryQueue {
+            fail: true,
+            ..Default::default()
+        };
+        let bus = DeadLetterBus::new(VoidBus, Box::new(queue));
+
+        // WHEN sending events
+        let res = bus.send_events(&get_events()).await;
+
+        // THEN the error of the bus is returned
+        assert!(matches!(res, Err(Error::InternalError(_))));
+    }
+}
@@ -0,0 +1,152 @@
+//! SQS dead-letter queue
+//!
+//! Each dead letter is sent as a JSON message, with the event type and the
+//! error code as message attributes so that they can be filtered without
+//! parsing the body.
+
+use super::{DeadLetter, DeadLetterQueue};
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_sqs::{
+    model::{MessageAttributeValue, SendMessageBatchRequestEntry},
+    Client,
+};
+use futures::future::join_all;
+use tracing::{error, info, instrument};
+
+/// Maximum number of messages in a SendMessageBatch request
+const BATCH_SIZE: usize = 10;
+
+/// SQS dead-letter queue implementation.
+pub struct SqsDeadLetterQueue {
+    client: Client,
+    queue_url: String,
+}
+
+impl SqsDeadLetterQueue {
+    pub fn new(client: Client, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+
+    async fn send_batch(&self, letters: &[DeadLetter]) -> Result<(), Error> {
+        let entries = letters
+            .iter()
+            .enumerate()
+            .map(|(i, letter)| {
+                let body = serde_json::to_string(letter)
+                    .map_err(|_| Error::InternalError("Unable to serialize dead letter"))?;
+                Ok(SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(body)
+                    .message_attributes("event_type", string_attribute(letter.event.event_type()))
+                    .message_attributes("error_code", string_attribute(&letter.code))
+                    .build())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let res = self
+            .client
+            .send_message_batch()
+            .queue_url(&self.queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await?;
+
+        match res.failed {
+            Some(failed) if !failed.is_empty() => {
+                error!("SQS rejected {} dead letters", failed.len());
+                Err(Error::InternalError("Failed to send dead letters to SQS"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn string_attribute(value: &str) -> MessageAttributeValue {
+    MessageAttributeValue::builder()
+        .data_type("String")
+        .string_value(value)
+        .build()
+}
+
+#[async_trait]
+impl DeadLetterQueue for SqsDeadLetterQueue {
+    /// Send dead letters to the queue, in batches of 10 messages
+    #[instrument(skip(self, letters))]
+    async fn push(&self, letters: &[DeadLetter]) -> Result<(), Error> {
+        info!("Sending {} dead letters to SQS", letters.len());
+        let res = join_all(
+            letters
+                .chunks(BATCH_SIZE)
+                .map(|chunk| self.send_batch(chunk)),
+        )
+        .await;
+
+        // If any of the requests failed, we'll return an error.
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{mock_sdk_config, request_builder},
+        Event, Product,
+    };
+    use aws_sdk_sqs::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_push() -> Result<(), Error> {
+        // GIVEN an SQS dead-letter queue
+        let conn = TestConnection::new(vec![(
+            request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from(""))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    "<SendMessageBatchResponse><SendMessageBatchResult><SendMessageBatchResultEntry><Id>0</Id><MessageId>1</MessageId><MD5OfMessageBody>0</MD5OfMessageBody></SendMessageBatchResultEntry></SendMessageBatchResult></SendMessageBatchResponse>",
+                ))
+                .unwrap(),
+        )]);
+        let queue = SqsDeadLetterQueue::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/dead-letters".to_string(),
+        );
+
+        // WHEN pushing a dead letter
+        queue
+            .push(&[DeadLetter {
+                event: Event::Created {
+                    product: Product {
+                        id: "1".to_string(),
+                        name: "foo".to_string(),
+                        price: 10.0,
+                        attributes: Default::default(),
+                        images: Default::default(),
+                    },
+                },
+                code: "MalformedDetail".to_string(),
+                message: "Detail is malformed".to_string(),
+                failed_at: 1646906400000,
+            }])
+            .await?;
+
+        // THEN a single message is sent with its error code
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("Action=SendMessageBatch"));
+        assert!(body.contains("MalformedDetail"));
+
+        Ok(())
+    }
+}
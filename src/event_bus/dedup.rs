@@ -0,0 +1,227 @@
+//! Deduplicating bus
+//!
+//! Lambda retries a whole batch of stream records when any of them fails,
+//! so events that were already published would be published again. This
+//! bus wrapper claims the deterministic id of each event (see `context`) in
+//! an idempotency table before sending it, and drops events whose id was
+//! already claimed.
+//!
+//! Claims are in progress while the events are sent, and only expire after
+//! about the timeout of the function, so that events aren't lost if the
+//! function crashes before sending them. Claims of delivered events are then
+//! completed, and claims of events that the inner bus couldn't deliver are
+//! released, so that they are sent again when the batch is retried. Events
+//! without a deterministic id, e.g. published by the API, are always sent.
+
+use super::{EventBus, EventContext};
+use crate::{consumer::Idempotency, Error, Event};
+use async_trait::async_trait;
+use futures::future::join_all;
+use tracing::{info, instrument, warn};
+
+/// Bus dropping events that were already published.
+pub struct DedupBus<B> {
+    inner: B,
+    idempotency: Box<dyn Idempotency>,
+}
+
+impl<B> DedupBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B, idempotency: Box<dyn Idempotency>) -> Self {
+        Self { inner, idempotency }
+    }
+
+    /// Settle the claims of the events sent to the inner bus
+    ///
+    /// The claims of the events it didn't deliver are released, and the
+    /// others are completed. Errors other than `EventsFailed` mean that no
+    /// event was delivered.
+    async fn settle(&self, claimed: &[(&Event, String)], err: Option<&Error>) -> Result<(), Error> {
+        let mut failed: Vec<&Event> = match err {
+            None => Vec::new(),
+            Some(Error::EventsFailed(failed)) => failed.iter().map(|f| &f.event).collect(),
+            Some(_) => claimed.iter().map(|(event, _)| *event).collect(),
+        };
+        let mut released = Vec::new();
+        let mut completed = Vec::new();
+        for (event, id) in claimed {
+            match failed.iter().position(|f| f == event) {
+                Some(index) => {
+                    failed.swap_remove(index);
+                    released.push(id);
+                }
+                None => completed.push(id),
+            }
+        }
+
+        // Delivered events were sent anyway, so failing to complete their
+        // claims only delays retries until the claims expire
+        info!("Completing {} event claims", completed.len());
+        for res in join_all(completed.iter().map(|id| self.idempotency.complete(id))).await {
+            if let Err(err) = res {
+                warn!("Unable to complete event claim: {}", err);
+            }
+        }
+        info!("Releasing {} event claims", released.len());
+        join_all(released.iter().map(|id| self.idempotency.release(id)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for DedupBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let context = match EventContext::current() {
+            Some(context) => context,
+            None => return self.inner.send_events(events).await,
+        };
+
+        let ids: Vec<Option<String>> = events
+            .iter()
+            .map(|event| context.take_event_id(event))
+            .collect();
+        let claims = join_all(ids.iter().map(|id| async move {
+            match id {
+                Some(id) => self.idempotency.claim(id).await,
+                None => Ok(true),
+            }
+        }))
+        .await;
+
+        let mut new_events = Vec::new();
+        let mut claimed = Vec::new();
+        let mut err = None;
+        for ((event, id), claim) in events.iter().zip(ids).zip(claims) {
+            match claim {
+                Ok(true) => {
+                    new_events.push(event.clone());
+                    if let Some(id) = id {
+                        claimed.push((event, id));
+                    }
+                }
+                Ok(false) => info!("Dropping duplicate {} event", event.event_type()),
+                Err(e) => err = Some(e),
+            }
+        }
+        // Give the ids back, for the inner bus to publish events with them
+        for (event, id) in claimed.iter().rev() {
+            context.restore_event_id(event, id.clone());
+        }
+        if let Some(err) = err {
+            // Nothing was sent, so all the claims are released
+            self.settle(&claimed, Some(&err)).await?;
+            return Err(err);
+        }
+        if new_events.is_empty() {
+            return Ok(());
+        }
+
+        let res = self.inner.send_events(&new_events).await;
+        self.settle(&claimed, res.as_ref().err()).await?;
+        res
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consumer::MemoryIdempotency,
+        event_bus::{
+            context,
+            programmable::{Outcome, ProgrammableBus},
+        },
+        Product,
+    };
+    use std::sync::Arc;
+
+    fn get_events(count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|i| Event::Created {
+                product: Product {
+                    id: i.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            })
+            .collect()
+    }
+
+    /// Context of a stream batch, where each event comes from a record
+    fn get_context(events: &[Event]) -> EventContext {
+        EventContext::default().with_event_ids(
+            events
+                .iter()
+                .enumerate()
+                .map(|(i, event)| (event, context::event_id(&i.to_string(), "Created"))),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_events_retried() -> Result<(), Error> {
+        // GIVEN a bus rejecting the last event of the first batch
+        let inner = Arc::new(ProgrammableBus::new().then(Outcome::PartialFailure(2)));
+        let bus = DedupBus::new(inner.clone(), Box::new(MemoryIdempotency::new()));
+        let events = get_events(3);
+
+        // WHEN the stream sends the batch, then retries it
+        let res = get_context(&events).scope(bus.send_events(&events)).await;
+        assert!(res.is_err());
+        get_context(&events).scope(bus.send_events(&events)).await?;
+
+        // THEN each event is delivered once
+        assert_eq!(inner.delivered(), events);
+        assert_eq!(inner.calls()[1].events, events[2..].to_vec());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_in_progress() -> Result<(), Error> {
+        // GIVEN an event that another invocation is sending
+        let inner = Arc::new(ProgrammableBus::new());
+        let idempotency = MemoryIdempotency::new();
+        idempotency
+            .claim(&context::event_id("0", "Created"))
+            .await?;
+        let bus = DedupBus::new(inner.clone(), Box::new(idempotency));
+        let events = get_events(2);
+
+        // WHEN sending it with another event
+        let res = get_context(&events).scope(bus.send_events(&events)).await;
+
+        // THEN the batch fails without sending anything, to be retried
+        assert!(res.is_err());
+        assert!(inner.calls().is_empty());
+        // AND the other event is sent once the first claim is completed
+        bus.idempotency
+            .complete(&context::event_id("0", "Created"))
+            .await?;
+        get_context(&events).scope(bus.send_events(&events)).await?;
+        assert_eq!(inner.delivered(), events[1..].to_vec());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! Discard bus
+//!
+//! Bus accepting events without sending them anywhere, for services
+//! configured without a destination for their events. Events are counted by
+//! type, and each call logs the counts so far, so that discarded events
+//! still show up in the logs.
+
+use super::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Bus discarding the events it receives.
+#[derive(Default)]
+pub struct DiscardBus {
+    counts: Mutex<BTreeMap<String, u64>>,
+}
+
+impl DiscardBus {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of discarded events, by event type
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EventBus for DiscardBus {
+    type E = Event;
+
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let mut counts = self.counts.lock().unwrap();
+        for event in events {
+            *counts.entry(event.event_type().to_string()).or_default() += 1;
+        }
+        info!(
+            "Discarded {} events, {:?} since startup",
+            events.len(),
+            counts
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::golden;
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN a discard bus
+        let bus = DiscardBus::new();
+        let events: Vec<Event> = golden::get_events().into_iter().map(|(_, e)| e).collect();
+
+        // WHEN sending events
+        bus.send_events(&events).await?;
+        bus.send_event(&events[0]).await?;
+
+        // THEN they are accepted and counted by type
+        let counts = bus.counts();
+        assert_eq!(counts.values().sum::<u64>(), events.len() as u64 + 1);
+        assert!(counts[events[0].event_type()] >= 2);
+
+        Ok(())
+    }
+}
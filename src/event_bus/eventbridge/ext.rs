@@ -1,4 +1,4 @@
-use crate::Event;
+use crate::{Event, EventEnvelope};
 use aws_sdk_eventbridge::model::PutEventsRequestEntry;
 
 static SOURCE: &str = "rust-products";
@@ -7,17 +7,20 @@ pub trait EventExt {
     fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry;
 }
 
-impl EventExt for Event {
+impl EventExt for EventEnvelope {
     fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry {
         PutEventsRequestEntry::builder()
             .event_bus_name(bus_name)
             .source(SOURCE)
-            .detail_type(match self {
+            .detail_type(match self.event {
                 Event::Created { .. } => "ProductCreated",
                 Event::Updated { .. } => "ProductUpdated",
                 Event::Deleted { .. } => "ProductDeleted",
+                Event::DeletionScheduled { .. } => "ProductDeletionScheduled",
+                Event::Archived { .. } => "ProductArchived",
+                Event::ArchiveCompleted { .. } => "ProductArchiveCompleted",
             })
-            .resources(self.id())
+            .resources(self.event.id())
             .detail(serde_json::to_string(self).unwrap())
             .build()
     }
@@ -26,7 +29,7 @@ impl EventExt for Event {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Product;
+    use crate::{event_bus::golden::get_metadata, Product};
 
     #[test]
     fn test_to_eventbridge() {
@@ -35,16 +38,44 @@ mod tests {
                 id: "123".to_string(),
                 name: "test".to_string(),
                 price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
             },
         };
-        let entry = event.to_eventbridge("test-bus");
+        let envelope = EventEnvelope {
+            event,
+            metadata: Some(get_metadata()),
+        };
+        let entry = envelope.to_eventbridge("test-bus");
         assert_eq!(entry.event_bus_name.unwrap(), "test-bus");
         assert_eq!(entry.source.unwrap(), SOURCE);
         assert_eq!(entry.detail_type.unwrap(), "ProductCreated");
         assert_eq!(entry.resources.unwrap(), vec!["123".to_string()]);
         assert_eq!(
             entry.detail.unwrap(),
-            serde_json::to_string(&event).unwrap()
+            serde_json::to_string(&envelope).unwrap()
         );
     }
+
+    #[test]
+    fn test_to_eventbridge_golden() {
+        for (name, event) in crate::event_bus::golden::get_events() {
+            let envelope = EventEnvelope {
+                event,
+                metadata: Some(get_metadata()),
+            };
+            let entry = envelope.to_eventbridge("test-bus");
+            let detail: serde_json::Value = serde_json::from_str(&entry.detail.unwrap()).unwrap();
+            crate::event_bus::golden::assert_golden(
+                &format!("eventbridge_{}", name),
+                &serde_json::json!({
+                    "EventBusName": entry.event_bus_name,
+                    "Source": entry.source,
+                    "DetailType": entry.detail_type,
+                    "Resources": entry.resources,
+                    "Detail": detail,
+                }),
+            );
+        }
+    }
 }
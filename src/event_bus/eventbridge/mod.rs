@@ -1,26 +1,154 @@
 //! EventBridge bus implementation
 //!
 //! Bus implementation using the AWS SDK for EventBridge.
+//!
+//! `PutEvents` can reject some entries of a request while still returning a
+//! successful response. Rejected entries with a transient error code, and
+//! events missing from the entries of the response, are sent again with an
+//! exponential backoff, and events that still failed are returned as
+//! `Error::EventsFailed`.
 
-use super::EventBus;
-use crate::{Error, Event};
+use super::{context, EventBus};
+use crate::{Error, Event, EventEnvelope, FailedEvent};
 use async_trait::async_trait;
 use aws_sdk_eventbridge::Client;
 use futures::future::join_all;
-use tracing::{info, instrument};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, info, instrument, warn};
 
 mod ext;
 use ext::EventExt;
 
+/// Maximum number of entries in a `PutEvents` request
+const BATCH_SIZE: usize = 10;
+
+/// Default number of attempts to send an event
+const MAX_ATTEMPTS: usize = 3;
+
+/// Default delay before the first retry, doubled after each attempt
+const BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Error code of events without an entry in the response
+const MISSING_ENTRY_CODE: &str = "MissingEntry";
+
+/// Entry error codes worth retrying
+const RETRYABLE_CODES: &[&str] = &["InternalFailure", "ThrottlingException", MISSING_ENTRY_CODE];
+
 /// EventBridge bus implementation.
 pub struct EventBridgeBus {
     client: Client,
     bus_name: String,
+    max_attempts: usize,
+    base_delay: Duration,
 }
 
 impl EventBridgeBus {
     pub fn new(client: Client, bus_name: String) -> Self {
-        Self { client, bus_name }
+        Self {
+            client,
+            bus_name,
+            max_attempts: MAX_ATTEMPTS,
+            base_delay: BASE_DELAY,
+        }
+    }
+
+    /// Set how many times rejected entries are sent, and the delay before
+    /// the first retry
+    pub fn with_retries(mut self, max_attempts: usize, base_delay: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Check that the event bus exists and can be described
+    ///
+    /// This is used at startup to detect missing permissions early.
+    #[instrument(skip(self))]
+    pub async fn verify_access(&self) -> Result<(), Error> {
+        info!("Describing EventBridge bus '{}'", self.bus_name);
+        self.client
+            .describe_event_bus()
+            .name(&self.bus_name)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Send up to 10 events, retrying rejected entries
+    ///
+    /// Returns the events that couldn't be delivered. Errors for the whole
+    /// request are left to the SDK retry policy and returned as is.
+    async fn put_events(&self, events: &[&Event]) -> Result<Vec<FailedEvent>, Error> {
+        // Metadata is generated once, so that retries keep the same event ids
+        let mut pending: Vec<EventEnvelope> = events
+            .iter()
+            .map(|event| context::envelope(event))
+            .collect::<Result<_, _>>()?;
+        let mut failed = Vec::new();
+        let mut attempts = 0;
+        while !pending.is_empty() {
+            attempts += 1;
+            let res = self
+                .client
+                .put_events()
+                .set_entries(Some(
+                    pending
+                        .iter()
+                        .map(|e| e.to_eventbridge(&self.bus_name))
+                        .collect(),
+                ))
+                .send()
+                .await?;
+
+            // Entries in the response are in the same order as in the
+            // request, and rejected entries have an error code. If the
+            // response has entries but fewer than the request, events
+            // without an entry may not have been delivered, so they are
+            // handled as rejected too.
+            let failed_count = res.failed_entry_count.max(0) as usize;
+            let mut entries = res.entries.map(|entries| entries.into_iter());
+            let mut rejected = 0;
+            let mut retry = Vec::new();
+            for event in pending {
+                let (code, message) = match entries.as_mut().map(|entries| entries.next()) {
+                    None => continue,
+                    Some(Some(entry)) => match entry.error_code {
+                        Some(code) => (code, entry.error_message.unwrap_or_default()),
+                        None => continue,
+                    },
+                    Some(None) => (
+                        MISSING_ENTRY_CODE.to_owned(),
+                        "No entry for the event in the response".to_owned(),
+                    ),
+                };
+                rejected += 1;
+                if attempts < self.max_attempts && RETRYABLE_CODES.contains(&code.as_str()) {
+                    retry.push(event);
+                } else {
+                    failed.push(FailedEvent {
+                        event: event.event,
+                        code,
+                        message,
+                    });
+                }
+            }
+            if rejected < failed_count {
+                error!(
+                    "EventBridge reported {} failed entries, but only {} events were rejected",
+                    failed_count, rejected
+                );
+            }
+
+            pending = retry;
+            if !pending.is_empty() {
+                warn!("Retrying {} events rejected by EventBridge", pending.len());
+                sleep(self.base_delay * 2u32.pow(attempts as u32 - 1)).await;
+            }
+        }
+
+        Ok(failed)
     }
 }
 
@@ -32,11 +160,10 @@ impl EventBus for EventBridgeBus {
     #[instrument(skip(self))]
     async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
         info!("Publishing event to EventBridge");
-        self.client
-            .put_events()
-            .entries(event.to_eventbridge(&self.bus_name))
-            .send()
-            .await?;
+        let failed = self.put_events(&[event]).await?;
+        if !failed.is_empty() {
+            return Err(Error::EventsFailed(failed));
+        }
 
         Ok(())
     }
@@ -52,23 +179,28 @@ impl EventBus for EventBridgeBus {
         // futures to complete. This means we can send all batches at the same time
         // and not have to wait for each batch to complete before sending the next one.
         info!("Publishing events to EventBridge");
-        let res = join_all(events.iter().collect::<Vec<_>>().chunks(10).map(|chunk| {
-            self.client
-                .put_events()
-                .set_entries(Some(
-                    chunk
-                        .iter()
-                        .map(|e| e.to_eventbridge(&self.bus_name))
-                        .collect::<Vec<_>>(),
-                ))
-                .send()
-        }))
+        let res = join_all(
+            events
+                .iter()
+                .collect::<Vec<_>>()
+                .chunks(BATCH_SIZE)
+                .map(|chunk| self.put_events(chunk)),
+        )
         .await;
 
         // Retrieve errors from the response vector
         //
-        // If any of the responses contained an error, we'll return an error.
-        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+        // If any of the requests failed, we'll return an error. Otherwise,
+        // the events rejected by all batches are returned together.
+        let failed: Vec<FailedEvent> = res
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        if !failed.is_empty() {
+            return Err(Error::EventsFailed(failed));
+        }
 
         Ok(())
     }
@@ -77,53 +209,57 @@ impl EventBus for EventBridgeBus {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Event, Product};
-    use aws_sdk_eventbridge::{Client, Config, Credentials, Region};
+    use crate::{
+        test_util::{json_request_builder, mock_sdk_config},
+        Event, Product,
+    };
+    use aws_sdk_eventbridge::{Client, Config};
     use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
     use aws_smithy_http::body::SdkBody;
 
-    // Config for mocking EventBridge
-    async fn get_mock_config() -> Config {
-        let cfg = aws_config::from_env()
-            .region(Region::new("eu-west-1"))
-            .credentials_provider(Credentials::new(
-                "accesskey",
-                "privatekey",
-                None,
-                None,
-                "dummy",
-            ))
-            .load()
-            .await;
-
-        Config::new(&cfg)
+    /// Request body, with the generated metadata removed from the details
+    fn get_body(request: &http::Request<SdkBody>, has_metadata: bool) -> serde_json::Value {
+        let mut body: serde_json::Value =
+            serde_json::from_slice(request.body().bytes().unwrap()).unwrap();
+        for entry in body["Entries"].as_array_mut().unwrap() {
+            let mut detail: serde_json::Value =
+                serde_json::from_str(entry["Detail"].as_str().unwrap()).unwrap();
+            let metadata = detail.as_object_mut().unwrap().remove("metadata");
+            assert_eq!(metadata.is_some(), has_metadata);
+            entry["Detail"] = detail;
+        }
+        body
     }
 
-    fn get_request_builder() -> http::request::Builder {
-        http::Request::builder()
-            .header("content-type", "application/x-amz-json-1.1")
-            .uri(http::uri::Uri::from_static(
-                "https://events.eu-west-1.amazonaws.com/",
-            ))
+    /// Check that requests match the expected ones, apart from the metadata
+    fn assert_requests_match(conn: &TestConnection<SdkBody>) {
+        for request in conn.requests().iter() {
+            assert_eq!(
+                get_body(&request.actual, true),
+                get_body(&request.expected, false)
+            );
+        }
     }
 
+    /// Endpoint of the mocked EventBridge client
+    const ENDPOINT: &str = "https://events.eu-west-1.amazonaws.com/";
+
     #[tokio::test]
     async fn test_send_event() -> Result<(), Error> {
         // GIVEN a mock EventBridge client
         let conn = TestConnection::new(vec![(
-            get_request_builder()
+            json_request_builder("1.1", ENDPOINT)
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
                     r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"price\":10.0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
+            get_response(r#"{"FailedEntryCount":0,"Entries":[{"EventId":"1"}]}"#),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let event_bus = EventBridgeBus::new(client, "test-bus".to_string());
 
         // WHEN we send an event
@@ -132,13 +268,15 @@ mod tests {
                 id: "test-id".to_string(),
                 name: "test-name".to_string(),
                 price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
             },
         };
         event_bus.send_event(&event).await?;
 
         // THEN the request should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 1);
-        conn.assert_requests_match(&vec![]);
+        assert_requests_match(&conn);
 
         Ok(())
     }
@@ -147,19 +285,20 @@ mod tests {
     async fn test_send_events() -> Result<(), Error> {
         // GIVEN a mock EventBridge client
         let conn = TestConnection::new(vec![(
-            get_request_builder()
+            json_request_builder("1.1", ENDPOINT)
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
                     r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"price\":10.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductDeleted","Detail":"{\"type\":\"Deleted\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"price\":20.0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
+            get_response(
+                r#"{"FailedEntryCount":0,"Entries":[{"EventId":"1"},{"EventId":"2"}]}"#,
+            ),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let event_bus = EventBridgeBus::new(client, "test-bus".to_string());
 
         // WHEN we send a batch of events
@@ -169,6 +308,8 @@ mod tests {
                     id: "test-id".to_string(),
                     name: "test-name".to_string(),
                     price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
                 },
             },
             Event::Deleted {
@@ -176,6 +317,8 @@ mod tests {
                     id: "test-id-2".to_string(),
                     name: "test-name-2".to_string(),
                     price: 20.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
                 },
             },
         ];
@@ -183,7 +326,7 @@ mod tests {
 
         // THEN the request should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 1);
-        conn.assert_requests_match(&vec![]);
+        assert_requests_match(&conn);
 
         Ok(())
     }
@@ -192,8 +335,10 @@ mod tests {
     async fn test_send_events0() -> Result<(), Error> {
         // GIVEN a mock EventBridge client
         let conn: TestConnection<SdkBody> = TestConnection::new(vec![]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let event_bus = EventBridgeBus::new(client, "test-bus".to_string());
 
         // WHEN we send zero events
@@ -210,30 +355,30 @@ mod tests {
     async fn test_send_events15() -> Result<(), Error> {
         // GIVEN a mock EventBridge client
         let conn = TestConnection::new(vec![(
-            get_request_builder()
+            json_request_builder("1.1", ENDPOINT)
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
                     r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-0"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-0\",\"name\":\"test-name-0\",\"price\":10.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-1"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-1\",\"name\":\"test-name-1\",\"price\":11.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"price\":12.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-3"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-3\",\"name\":\"test-name-3\",\"price\":13.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-4"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-4\",\"name\":\"test-name-4\",\"price\":14.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-5"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-5\",\"name\":\"test-name-5\",\"price\":15.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-6"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-6\",\"name\":\"test-name-6\",\"price\":16.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-7"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-7\",\"name\":\"test-name-7\",\"price\":17.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-8"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-8\",\"name\":\"test-name-8\",\"price\":18.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-9"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-9\",\"name\":\"test-name-9\",\"price\":19.0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
+            get_response(
+                r#"{"FailedEntryCount":0,"Entries":[{"EventId":"1"},{"EventId":"2"},{"EventId":"3"},{"EventId":"4"},{"EventId":"5"},{"EventId":"6"},{"EventId":"7"},{"EventId":"8"},{"EventId":"9"},{"EventId":"10"}]}"#,
+            ),
         ), (
-            get_request_builder()
+            json_request_builder("1.1", ENDPOINT)
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
                     r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-10"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-10\",\"name\":\"test-name-10\",\"price\":20.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-11"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-11\",\"name\":\"test-name-11\",\"price\":21.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-12"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-12\",\"name\":\"test-name-12\",\"price\":22.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-13"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-13\",\"name\":\"test-name-13\",\"price\":23.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-14"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-14\",\"name\":\"test-name-14\",\"price\":24.0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
+            get_response(
+                r#"{"FailedEntryCount":0,"Entries":[{"EventId":"11"},{"EventId":"12"},{"EventId":"13"},{"EventId":"14"},{"EventId":"15"}]}"#,
+            ),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let event_bus = EventBridgeBus::new(client, "test-bus".to_string());
 
         // WHEN we send 15 events
@@ -243,6 +388,8 @@ mod tests {
                     id: format!("test-id-{}", i),
                     name: format!("test-name-{}", i),
                     price: 10.0 + i as f64,
+                    attributes: Default::default(),
+                    images: Default::default(),
                 },
             })
             .collect::<Vec<_>>();
@@ -250,7 +397,170 @@ mod tests {
 
         // THEN two requests should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 2);
-        conn.assert_requests_match(&vec![]);
+        assert_requests_match(&conn);
+
+        Ok(())
+    }
+
+    fn get_response(body: &'static str) -> http::Response<SdkBody> {
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::from(body))
+            .unwrap()
+    }
+
+    fn get_events(count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|i| Event::Created {
+                product: Product {
+                    id: format!("test-id-{}", i),
+                    name: format!("test-name-{}", i),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_send_events_retry() -> Result<(), Error> {
+        // GIVEN EventBridge rejecting one entry with a transient error
+        let request = || {
+            json_request_builder("1.1", ENDPOINT)
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from("{}"))
+                .unwrap()
+        };
+        let conn = TestConnection::new(vec![
+            (
+                request(),
+                get_response(
+                    r#"{"FailedEntryCount":1,"Entries":[{"EventId":"1"},{"ErrorCode":"InternalFailure","ErrorMessage":"Internal failure"}]}"#,
+                ),
+            ),
+            (
+                request(),
+                get_response(r#"{"FailedEntryCount":0,"Entries":[{"EventId":"2"}]}"#),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let event_bus = EventBridgeBus::new(client, "test-bus".to_string())
+            .with_retries(3, Duration::from_millis(1));
+
+        // WHEN we send two events
+        event_bus.send_events(&get_events(2)).await?;
+
+        // THEN the rejected event is sent again on its own
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(body["Entries"].as_array().unwrap().len(), 1);
+        assert_eq!(body["Entries"][0]["Resources"][0], "test-id-1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_failed() -> Result<(), Error> {
+        // GIVEN EventBridge rejecting one entry with a permanent error
+        let conn = TestConnection::new(vec![(
+            json_request_builder("1.1", ENDPOINT)
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            get_response(
+                r#"{"FailedEntryCount":1,"Entries":[{"ErrorCode":"MalformedDetail","ErrorMessage":"Detail is malformed"},{"EventId":"2"}]}"#,
+            ),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let event_bus = EventBridgeBus::new(client, "test-bus".to_string())
+            .with_retries(3, Duration::from_millis(1));
+        let events = get_events(2);
+
+        // WHEN we send two events
+        let res = event_bus.send_events(&events).await;
+
+        // THEN the rejected event is returned without being retried
+        assert_eq!(conn.requests().len(), 1);
+        match res {
+            Err(Error::EventsFailed(failed)) => assert_eq!(
+                failed,
+                vec![FailedEvent {
+                    event: events[0].clone(),
+                    code: "MalformedDetail".to_string(),
+                    message: "Detail is malformed".to_string(),
+                }]
+            ),
+            _ => panic!("Expected EventsFailed"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_missing_entry() -> Result<(), Error> {
+        // GIVEN EventBridge returning fewer entries than events
+        let conn = TestConnection::new(vec![(
+            json_request_builder("1.1", ENDPOINT)
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            get_response(r#"{"FailedEntryCount":1,"Entries":[{"EventId":"1"}]}"#),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let event_bus = EventBridgeBus::new(client, "test-bus".to_string())
+            .with_retries(1, Duration::from_millis(1));
+        let events = get_events(2);
+
+        // WHEN we send two events
+        let res = event_bus.send_events(&events).await;
+
+        // THEN the event without an entry is returned as failed
+        match res {
+            Err(Error::EventsFailed(failed)) => {
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].event, events[1]);
+                assert_eq!(failed[0].code, MISSING_ENTRY_CODE);
+            }
+            _ => panic!("Expected EventsFailed"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_no_entries() -> Result<(), Error> {
+        // GIVEN EventBridge returning no entries and no failures
+        let conn = TestConnection::new(vec![(
+            json_request_builder("1.1", ENDPOINT)
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            get_response(r#"{"FailedEntryCount":0}"#),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let event_bus = EventBridgeBus::new(client, "test-bus".to_string())
+            .with_retries(3, Duration::from_millis(1));
+
+        // WHEN we send two events
+        event_bus.send_events(&get_events(2)).await?;
+
+        // THEN the events are not retried
+        assert_eq!(conn.requests().len(), 1);
 
         Ok(())
     }
@@ -0,0 +1,374 @@
+//! SQS FIFO bus implementation
+//!
+//! Bus implementation sending events to an SQS FIFO queue, with the product
+//! id as message group, so that the events of a product are delivered in the
+//! order they were sent while different products are processed in parallel.
+//!
+//! Each message is an `OrderedEnvelope` carrying a per-product sequence
+//! number. DynamoDB Streams delivers the records of a product in order, so
+//! its events are sent one invocation after the other, and their sequence
+//! numbers keep increasing. Sequence numbers are microsecond timestamps,
+//! bumped when needed so that they never repeat within a process.
+//!
+//! Messages are deduplicated by the id of their event, which is derived from
+//! the stream record for events published from DynamoDB Streams, so that
+//! SQS drops the messages of a retried batch that were already sent within
+//! its 5-minute deduplication interval.
+//!
+//! Consumers reading from other sources than the FIFO queue, or replaying
+//! messages, can use the sequence numbers to detect out-of-order deliveries,
+//! either with `reorder` within a batch or with `process_after_checkpoints`
+//! across batches.
+
+use super::{context, EventBus};
+use crate::{Error, Event, EventEnvelope};
+use async_trait::async_trait;
+use aws_sdk_sqs::{
+    model::{MessageAttributeValue, SendMessageBatchRequestEntry},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, instrument};
+
+/// Maximum number of messages in a SendMessageBatch request
+const BATCH_SIZE: usize = 10;
+
+/// Event, as sent to the FIFO queue
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OrderedEnvelope {
+    /// Id of the product, which is also the message group
+    pub product_id: String,
+    /// Sequence number of the event among the events of the product
+    pub sequence: u64,
+    pub detail: EventEnvelope,
+}
+
+impl OrderedEnvelope {
+    /// Item for `process_after_checkpoints`, with the product as partition
+    pub fn into_checkpoint_item(self) -> (String, String, Event) {
+        (
+            self.product_id,
+            self.sequence.to_string(),
+            self.detail.event,
+        )
+    }
+}
+
+/// Sort a batch of envelopes by sequence number
+///
+/// Only the events of a product are sorted among themselves: each product
+/// keeps the positions its envelopes had in the batch. Returns the number of
+/// envelopes that were delivered after a later event of the same product.
+pub fn reorder(envelopes: &mut [OrderedEnvelope]) -> usize {
+    let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut latest: HashMap<String, u64> = HashMap::new();
+    let mut out_of_order = 0;
+    for (index, envelope) in envelopes.iter().enumerate() {
+        positions
+            .entry(envelope.product_id.clone())
+            .or_default()
+            .push(index);
+        let last = latest.entry(envelope.product_id.clone()).or_insert(0);
+        if envelope.sequence < *last {
+            out_of_order += 1;
+        } else {
+            *last = envelope.sequence;
+        }
+    }
+    if out_of_order == 0 {
+        return 0;
+    }
+
+    info!("Reordering {} out-of-order events", out_of_order);
+    for indices in positions.values() {
+        let mut sorted: Vec<OrderedEnvelope> =
+            indices.iter().map(|&i| envelopes[i].clone()).collect();
+        sorted.sort_by_key(|envelope| envelope.sequence);
+        for (&index, envelope) in indices.iter().zip(sorted) {
+            envelopes[index] = envelope;
+        }
+    }
+    out_of_order
+}
+
+/// SQS FIFO bus implementation.
+pub struct SqsFifoBus {
+    client: Client,
+    queue_url: String,
+    last_sequence: AtomicU64,
+}
+
+impl SqsFifoBus {
+    pub fn new(client: Client, queue_url: String) -> Self {
+        Self {
+            client,
+            queue_url,
+            last_sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Next sequence number, greater than all the previous ones
+    fn next_sequence(&self) -> Result<u64, Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_micros() as u64;
+        let previous = self
+            .last_sequence
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_default();
+        Ok(now.max(previous + 1))
+    }
+
+    async fn send_batch(&self, events: &[Event]) -> Result<(), Error> {
+        let entries = events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| {
+                let envelope = OrderedEnvelope {
+                    product_id: event.id().to_string(),
+                    sequence: self.next_sequence()?,
+                    detail: context::envelope(event)?,
+                };
+                let body = serde_json::to_string(&envelope)
+                    .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+                Ok(SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(body)
+                    .message_group_id(&envelope.product_id)
+                    .message_deduplication_id(deduplication_id(&envelope))
+                    .message_attributes("event_type", string_attribute(event.event_type()))
+                    .message_attributes("sequence", number_attribute(envelope.sequence))
+                    .build())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let res = self
+            .client
+            .send_message_batch()
+            .queue_url(&self.queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await?;
+
+        match res.failed {
+            Some(failed) if !failed.is_empty() => {
+                error!("SQS rejected {} events", failed.len());
+                Err(Error::InternalError("Failed to send events to SQS"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Deduplication id of the message of an envelope
+///
+/// Sequence numbers change when an event is sent again, so they are only
+/// used for envelopes without metadata.
+fn deduplication_id(envelope: &OrderedEnvelope) -> String {
+    match &envelope.detail.metadata {
+        Some(metadata) => metadata.event_id.clone(),
+        None => format!("{}-{}", envelope.product_id, envelope.sequence),
+    }
+}
+
+fn string_attribute(value: &str) -> MessageAttributeValue {
+    MessageAttributeValue::builder()
+        .data_type("String")
+        .string_value(value)
+        .build()
+}
+
+fn number_attribute(value: u64) -> MessageAttributeValue {
+    MessageAttributeValue::builder()
+        .data_type("Number")
+        .string_value(value.to_string())
+        .build()
+}
+
+#[async_trait]
+impl EventBus for SqsFifoBus {
+    type E = Event;
+
+    /// Send an event to the queue.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        info!("Sending event to SQS");
+        self.send_batch(std::slice::from_ref(event)).await
+    }
+
+    /// Send a batch of events to the queue.
+    ///
+    /// Events are sent in batches of 10 messages. Batches are sent one after
+    /// the other, as the events of a product could otherwise be enqueued out
+    /// of order.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        info!("Sending {} events to SQS", events.len());
+        for batch in events.chunks(BATCH_SIZE) {
+            self.send_batch(batch).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::EventContext,
+        test_util::{mock_sdk_config, request_builder},
+        Product,
+    };
+    use aws_sdk_sqs::{Client, Config, Region};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_event(id: &str) -> Event {
+        Event::Created {
+            product: Product {
+                id: id.to_string(),
+                name: "foo".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    fn get_envelope(id: &str, sequence: u64) -> OrderedEnvelope {
+        OrderedEnvelope {
+            product_id: id.to_string(),
+            sequence,
+            detail: EventEnvelope {
+                event: get_event(id),
+                metadata: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN an SQS FIFO bus
+        let conn = TestConnection::new(vec![(
+            request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from(""))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    "<SendMessageBatchResponse><SendMessageBatchResult><SendMessageBatchResultEntry><Id>0</Id><MessageId>1</MessageId><MD5OfMessageBody>0</MD5OfMessageBody></SendMessageBatchResultEntry></SendMessageBatchResult></SendMessageBatchResponse>",
+                ))
+                .unwrap(),
+        )]);
+        let bus = SqsFifoBus::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events.fifo".to_string(),
+        );
+
+        // WHEN sending two events of the same product
+        bus.send_events(&[get_event("1"), get_event("1")]).await?;
+
+        // THEN both are sent in a single batch, grouped by product
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("Action=SendMessageBatch"));
+        assert!(body.contains("SendMessageBatchRequestEntry.1.MessageGroupId=1"));
+        assert!(body.contains("SendMessageBatchRequestEntry.2.MessageGroupId=1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_retried() -> Result<(), Error> {
+        // GIVEN an SQS FIFO bus
+        let get_response = || {
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    "<SendMessageBatchResponse><SendMessageBatchResult></SendMessageBatchResult></SendMessageBatchResponse>",
+                ))
+                .unwrap()
+        };
+        let get_request = || {
+            request_builder("https://sqs.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from(""))
+                .unwrap()
+        };
+        let conn = TestConnection::new(vec![
+            (get_request(), get_response()),
+            (get_request(), get_response()),
+        ]);
+        let bus = SqsFifoBus::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events.fifo".to_string(),
+        );
+        let event = get_event("1");
+        let get_context = || EventContext::default().with_event_ids([(&event, "abc".to_string())]);
+
+        // WHEN sending the event of a stream record twice, as when a batch
+        // is retried
+        get_context().scope(bus.send_event(&event)).await?;
+        get_context().scope(bus.send_event(&event)).await?;
+
+        // THEN both messages have the id of the event as deduplication id
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            let body = std::str::from_utf8(request.actual.body().bytes().unwrap()).unwrap();
+            assert!(body.contains("SendMessageBatchRequestEntry.1.MessageDeduplicationId=abc"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_sequence() -> Result<(), Error> {
+        // GIVEN a bus whose last sequence number is in the future
+        let client = Client::from_conf(Config::builder().region(Region::new("eu-west-1")).build());
+        let bus = SqsFifoBus::new(client, "queue".to_string());
+        bus.last_sequence.store(u64::MAX / 2, Ordering::SeqCst);
+
+        // WHEN getting sequence numbers
+        let first = bus.next_sequence()?;
+        let second = bus.next_sequence()?;
+
+        // THEN they keep increasing
+        assert_eq!(first, u64::MAX / 2 + 1);
+        assert_eq!(second, first + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorder() {
+        // GIVEN a batch where an event of product 1 arrived late
+        let mut envelopes = vec![
+            get_envelope("1", 20),
+            get_envelope("2", 15),
+            get_envelope("1", 10),
+            get_envelope("1", 30),
+        ];
+
+        // WHEN reordering the batch
+        let out_of_order = reorder(&mut envelopes);
+
+        // THEN the late event is detected
+        assert_eq!(out_of_order, 1);
+        // AND the events of product 1 are sorted in their own positions
+        let sequences: Vec<u64> = envelopes.iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![10, 15, 20, 30]);
+    }
+}
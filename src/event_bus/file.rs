@@ -0,0 +1,251 @@
+//! File bus implementation
+//!
+//! Bus implementation appending events to a local NDJSON file, one envelope
+//! per line. This lets developers observe the event flow without access to
+//! EventBridge, for example with the `tail-events` tool.
+//!
+//! Once the file grows beyond `max_bytes`, it is rotated: `events.ndjson`
+//! becomes `events.ndjson.1`, `events.ndjson.1` becomes `events.ndjson.2`,
+//! and so on, up to `max_files` rotated files.
+
+use super::{context, EventBus};
+use crate::{Error, Event, EventEnvelope};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, instrument};
+
+static SOURCE: &str = "rust-products";
+
+/// Default size after which the file is rotated
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default number of rotated files to keep
+pub const DEFAULT_MAX_FILES: usize = 3;
+
+/// Event, as written to the file
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FileEnvelope {
+    /// Time at which the event was written, in milliseconds since the epoch
+    pub time: u64,
+    pub source: String,
+    /// Id of the product
+    pub resource: String,
+    pub detail: EventEnvelope,
+}
+
+/// File bus implementation.
+pub struct FileBus {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    // Serialize writes so that lines from concurrent calls don't interleave
+    lock: Mutex<()>,
+}
+
+impl FileBus {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_files: DEFAULT_MAX_FILES,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Size after which the file is rotated
+    pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+        Self { max_bytes, ..self }
+    }
+
+    /// Number of rotated files to keep
+    pub fn with_max_files(self, max_files: usize) -> Self {
+        Self { max_files, ..self }
+    }
+
+    /// Append envelopes to the file, rotating it first if needed
+    fn append(&self, events: &[Event]) -> Result<(), Error> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_millis() as u64;
+
+        let mut buf = Vec::new();
+        for event in events {
+            let envelope = FileEnvelope {
+                time,
+                source: SOURCE.to_string(),
+                resource: event.id().to_string(),
+                detail: context::envelope(event)?,
+            };
+            serde_json::to_writer(&mut buf, &envelope)
+                .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+            buf.push(b'\n');
+        }
+
+        let _guard = self.lock.lock().unwrap();
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&buf))
+            .map_err(|_| Error::InternalError("Unable to write to event file"))
+    }
+
+    /// Shift rotated files by one, dropping the oldest one
+    fn rotate(&self) -> Result<(), Error> {
+        info!("Rotating event file {}", self.path.display());
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path)
+                .map_err(|_| Error::InternalError("Unable to rotate event file"));
+        }
+
+        for n in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, n + 1))
+                    .map_err(|_| Error::InternalError("Unable to rotate event file"))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+            .map_err(|_| Error::InternalError("Unable to rotate event file"))
+    }
+}
+
+/// Path of the n-th rotated file
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[async_trait]
+impl EventBus for FileBus {
+    type E = Event;
+
+    /// Append an event to the file.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        info!("Writing event to {}", self.path.display());
+        self.append(std::slice::from_ref(event))
+    }
+
+    /// Append a batch of events to the file.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        info!("Writing {} events to {}", events.len(), self.path.display());
+        self.append(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+
+    fn get_event(id: &str) -> Event {
+        Event::Created {
+            product: Product {
+                id: id.to_string(),
+                name: "test".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    fn get_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file-bus-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("events.ndjson")
+    }
+
+    fn read_envelopes(path: &Path) -> Vec<FileEnvelope> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN a file bus
+        let path = get_path("send");
+        let bus = FileBus::new(path.clone());
+
+        // WHEN sending events
+        bus.send_event(&get_event("1")).await?;
+        bus.send_events(&[get_event("2"), get_event("3")]).await?;
+
+        // THEN all events are appended to the file
+        let envelopes = read_envelopes(&path);
+        assert_eq!(envelopes.len(), 3);
+        assert_eq!(envelopes[0].source, SOURCE);
+        assert_eq!(envelopes[0].resource, "1");
+        assert_eq!(envelopes[2].detail.event, get_event("3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_envelope_golden() -> Result<(), Error> {
+        for (name, event) in crate::event_bus::golden::get_events() {
+            // GIVEN a file bus
+            let path = get_path(&format!("golden-{}", name));
+            let bus = FileBus::new(path.clone());
+
+            // WHEN sending an event
+            bus.send_event(&event).await?;
+
+            // THEN the envelope matches the golden file, apart from the time
+            // and the metadata
+            let line = fs::read_to_string(&path).unwrap();
+            let mut envelope: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+            assert!(envelope["time"].is_u64());
+            envelope["time"] = 1646906400000u64.into();
+            assert!(envelope["detail"]["metadata"]["event_id"].is_string());
+            envelope["detail"]["metadata"] =
+                serde_json::to_value(crate::event_bus::golden::get_metadata()).unwrap();
+            crate::event_bus::golden::assert_golden(&format!("envelope_{}", name), &envelope);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rotate() -> Result<(), Error> {
+        // GIVEN a file bus that rotates after every event
+        let path = get_path("rotate");
+        let bus = FileBus::new(path.clone())
+            .with_max_bytes(1)
+            .with_max_files(2);
+
+        // WHEN sending more events than there are rotated files
+        for id in ["1", "2", "3", "4"] {
+            bus.send_event(&get_event(id)).await?;
+        }
+
+        // THEN the most recent events are kept
+        assert_eq!(read_envelopes(&path)[0].resource, "4");
+        assert_eq!(read_envelopes(&rotated_path(&path, 1))[0].resource, "3");
+        assert_eq!(read_envelopes(&rotated_path(&path, 2))[0].resource, "2");
+        // AND older files are dropped
+        assert!(!rotated_path(&path, 3).exists());
+
+        Ok(())
+    }
+}
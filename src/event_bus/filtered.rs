@@ -0,0 +1,220 @@
+//! Filtered bus
+//!
+//! Bus wrapper dropping the events that deployments don't want to publish,
+//! such as an event type no consumer listens to, or updates that barely
+//! change a price. Events are published only if every `EventFilter`
+//! accepts them, and dropped events are not an error.
+
+use super::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use tracing::{info, instrument};
+
+/// Trait for deciding whether an event is published
+pub trait EventFilter: Send + Sync {
+    fn accept(&self, event: &Event) -> bool;
+}
+
+impl<F> EventFilter for F
+where
+    F: Fn(&Event) -> bool + Send + Sync,
+{
+    fn accept(&self, event: &Event) -> bool {
+        self(event)
+    }
+}
+
+/// Filter accepting only some event types, e.g. `Created` and `Deleted`
+pub struct EventTypeFilter {
+    types: HashSet<String>,
+}
+
+impl EventTypeFilter {
+    pub fn new<I, S>(types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            types: types.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl EventFilter for EventTypeFilter {
+    fn accept(&self, event: &Event) -> bool {
+        self.types.contains(event.event_type())
+    }
+}
+
+/// Filter dropping updates that only change the price by a small amount
+///
+/// The change is relative to the old price. Updates that change any other
+/// field, and other event types, are accepted.
+pub struct PriceChangeFilter {
+    min_price_change: f64,
+}
+
+impl PriceChangeFilter {
+    pub fn new(min_price_change: f64) -> Self {
+        Self { min_price_change }
+    }
+}
+
+impl EventFilter for PriceChangeFilter {
+    fn accept(&self, event: &Event) -> bool {
+        match event {
+            Event::Updated { old, new } => {
+                let mut repriced = old.clone();
+                repriced.price = new.price;
+                if &repriced != new {
+                    return true;
+                }
+                let change = if old.price == 0.0 {
+                    new.price.abs()
+                } else {
+                    ((new.price - old.price) / old.price).abs()
+                };
+                change >= self.min_price_change
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Bus publishing only the events accepted by its filters.
+pub struct FilteredBus<B> {
+    inner: B,
+    filters: Vec<Box<dyn EventFilter>>,
+}
+
+impl<B> FilteredBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            filters: Vec::new(),
+        }
+    }
+
+    /// Add a filter events must pass, on top of the existing ones
+    pub fn with_filter(mut self, filter: impl EventFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    fn accept(&self, event: &Event) -> bool {
+        self.filters.iter().all(|filter| filter.accept(event))
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for FilteredBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        if !self.accept(event) {
+            info!("Dropping {} event", event.event_type());
+            return Ok(());
+        }
+        self.inner.send_event(event).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let accepted: Vec<Event> = events
+            .iter()
+            .filter(|event| self.accept(event))
+            .cloned()
+            .collect();
+        if accepted.len() < events.len() {
+            info!("Dropping {} events", events.len() - accepted.len());
+        }
+        if accepted.is_empty() {
+            return Ok(());
+        }
+        self.inner.send_events(&accepted).await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::ProgrammableBus, Product};
+    use std::sync::Arc;
+
+    fn get_product(name: &str, price: f64) -> Product {
+        Product {
+            id: "1".to_string(),
+            name: name.to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    fn get_update(old: Product, new: Product) -> Event {
+        Event::Updated { old, new }
+    }
+
+    #[test]
+    fn test_price_change_filter() {
+        let filter = PriceChangeFilter::new(0.05);
+
+        // Small price changes are dropped
+        assert!(!filter.accept(&get_update(
+            get_product("foo", 100.0),
+            get_product("foo", 102.0)
+        )));
+        assert!(filter.accept(&get_update(
+            get_product("foo", 100.0),
+            get_product("foo", 110.0)
+        )));
+        // Updates changing other fields are kept
+        assert!(filter.accept(&get_update(
+            get_product("foo", 100.0),
+            get_product("bar", 100.0)
+        )));
+        assert!(filter.accept(&Event::Deleted {
+            product: get_product("foo", 100.0)
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_send_events_filtered() -> Result<(), Error> {
+        // GIVEN a bus publishing only creations and deletions
+        let inner = Arc::new(ProgrammableBus::new());
+        let bus = FilteredBus::new(inner.clone())
+            .with_filter(EventTypeFilter::new(["Created", "Deleted"]));
+        let created = Event::Created {
+            product: get_product("foo", 10.0),
+        };
+        let deleted = Event::Deleted {
+            product: get_product("foo", 10.0),
+        };
+        let updated = get_update(get_product("foo", 10.0), get_product("foo", 20.0));
+
+        // WHEN sending events of every type
+        bus.send_events(&[created.clone(), updated.clone(), deleted.clone()])
+            .await?;
+        bus.send_event(&updated).await?;
+
+        // THEN only creations and deletions are delivered
+        assert_eq!(inner.delivered(), vec![created, deleted]);
+        // AND batches without accepted events aren't sent
+        assert_eq!(inner.calls().len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Kinesis Data Firehose bus implementation
+//!
+//! Bus implementation writing events to a Kinesis Data Firehose delivery
+//! stream, which delivers them to S3 for analytics, for example as Parquet
+//! with record format conversion.
+//!
+//! Each record is a serialized event followed by a newline, so that the
+//! objects delivered to S3 are NDJSON files. Events serialized in a binary
+//! format, such as Avro, are written without the newline.
+
+use super::{
+    context,
+    serializer::{EventSerializer, JsonSerializer, JSON_CONTENT_TYPE},
+    EventBus,
+};
+use crate::{Error, Event};
+use async_trait::async_trait;
+use aws_sdk_firehose::{model::Record, Blob, Client};
+use futures::future::join_all;
+use tracing::{error, info, instrument};
+
+/// Maximum number of records in a PutRecordBatch request
+const BATCH_SIZE: usize = 500;
+
+/// Kinesis Data Firehose bus implementation.
+pub struct FirehoseBus {
+    client: Client,
+    delivery_stream_name: String,
+    serializer: Box<dyn EventSerializer>,
+}
+
+impl FirehoseBus {
+    pub fn new(client: Client, delivery_stream_name: String) -> Self {
+        Self {
+            client,
+            delivery_stream_name,
+            serializer: Box::new(JsonSerializer),
+        }
+    }
+
+    /// Serialize events with this serializer instead of JSON
+    pub fn with_serializer(mut self, serializer: Box<dyn EventSerializer>) -> Self {
+        self.serializer = serializer;
+        self
+    }
+
+    /// Send a batch of up to 500 records
+    ///
+    /// PutRecordBatch doesn't fail when some records are rejected, so the
+    /// number of failed records is checked instead.
+    async fn put_record_batch(&self, events: &[Event]) -> Result<(), Error> {
+        let records = events
+            .iter()
+            .map(|event| {
+                let mut data = self.serializer.serialize(&context::envelope(event)?)?;
+                if self.serializer.content_type() == JSON_CONTENT_TYPE {
+                    data.push(b'\n');
+                }
+                Ok(Record::builder().data(Blob::new(data)).build())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let res = self
+            .client
+            .put_record_batch()
+            .delivery_stream_name(&self.delivery_stream_name)
+            .set_records(Some(records))
+            .send()
+            .await?;
+
+        match res.failed_put_count {
+            Some(count) if count > 0 => {
+                error!("Firehose rejected {} records", count);
+                Err(Error::InternalError("Failed to put records to Firehose"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for FirehoseBus {
+    type E = Event;
+
+    /// Write an event to the delivery stream.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        info!("Writing event to Firehose");
+        self.put_record_batch(std::slice::from_ref(event)).await
+    }
+
+    /// Write a batch of events to the delivery stream.
+    ///
+    /// Events are sent in batches of 500 records, the limit of
+    /// `PutRecordBatch`.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        info!("Writing {} events to Firehose", events.len());
+        let res = join_all(
+            events
+                .chunks(BATCH_SIZE)
+                .map(|chunk| self.put_record_batch(chunk)),
+        )
+        .await;
+
+        // If any of the requests failed, we'll return an error.
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{json_request_builder, mock_sdk_config},
+        Product,
+    };
+    use aws_sdk_firehose::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_request() -> http::Request<SdkBody> {
+        json_request_builder("1.1", "https://firehose.eu-west-1.amazonaws.com/")
+            .header("x-amz-target", "Firehose_20150804.PutRecordBatch")
+            .body(SdkBody::from("{}"))
+            .unwrap()
+    }
+
+    fn get_response(failed_put_count: usize) -> http::Response<SdkBody> {
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::from(format!(
+                r#"{{"FailedPutCount": {}, "RequestResponses": []}}"#,
+                failed_put_count
+            )))
+            .unwrap()
+    }
+
+    fn get_event(id: &str) -> Event {
+        Event::Created {
+            product: Product {
+                id: id.to_string(),
+                name: "test".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    fn get_bus(conn: &TestConnection<SdkBody>, config: Config) -> FirehoseBus {
+        FirehoseBus::new(
+            Client::from_conf_conn(config, DynConnector::new(conn.clone())),
+            "products".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN a Firehose bus
+        let conn = TestConnection::new(vec![
+            (get_request(), get_response(0)),
+            (get_request(), get_response(0)),
+        ]);
+        let bus = get_bus(&conn, Config::new(&mock_sdk_config().await));
+        let events: Vec<Event> = (0..501).map(|i| get_event(&i.to_string())).collect();
+
+        // WHEN sending more events than fit in a batch
+        bus.send_events(&events).await?;
+
+        // THEN the events are split in two requests
+        let mut sizes: Vec<usize> = conn
+            .requests()
+            .iter()
+            .map(|req| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(req.actual.body().bytes().unwrap()).unwrap();
+                assert_eq!(body["DeliveryStreamName"], "products");
+                body["Records"].as_array().unwrap().len()
+            })
+            .collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![1, 500]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_event_failed_records() -> Result<(), Error> {
+        // GIVEN a Firehose bus rejecting records
+        let conn = TestConnection::new(vec![(get_request(), get_response(1))]);
+        let bus = get_bus(&conn, Config::new(&mock_sdk_config().await));
+
+        // WHEN sending an event
+        let res = bus.send_event(&get_event("1")).await;
+
+        // THEN an error is returned
+        assert!(res.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! Golden files for published payloads
+//!
+//! Consumers parse the events published by this crate, so any change to
+//! their serialized form is a breaking change. Tests compare each payload
+//! shape with a file in `tests/golden/`, and fail if a field is renamed or
+//! an enum tag changes.
+//!
+//! After an intended change, regenerate the files with
+//! `UPDATE_GOLDEN=1 cargo test golden` and review the diff.
+
+use crate::{Event, EventMetadata, Product};
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+/// Compare a payload with its golden file, or write it with `UPDATE_GOLDEN`
+pub fn assert_golden(name: &str, actual: &Value) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.json", name));
+
+    if std::env::var("UPDATE_GOLDEN").map_or(false, |v| v == "1") {
+        let mut data = serde_json::to_string_pretty(actual).unwrap();
+        data.push('\n');
+        fs::write(&path, data).unwrap();
+        return;
+    }
+
+    let expected: Value = match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data).unwrap(),
+        Err(err) => panic!(
+            "Unable to read {}: {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display(),
+            err
+        ),
+    };
+    assert_eq!(
+        actual,
+        &expected,
+        "{} doesn't match {}, run with UPDATE_GOLDEN=1 if this change is intended",
+        name,
+        path.display()
+    );
+}
+
+/// Product with every field set
+pub fn get_product(price: f64) -> Product {
+    Product {
+        id: "1".to_string(),
+        name: "foo".to_string(),
+        price,
+        attributes: [("color".to_string(), "red".into())].into_iter().collect(),
+        images: vec!["products/1/0123456789abcdef0123456789abcdef".to_string()],
+    }
+}
+
+/// Metadata with every field set
+pub fn get_metadata() -> EventMetadata {
+    EventMetadata {
+        event_id: "0123456789abcdef0123456789abcdef".to_string(),
+        correlation_id: "c6a9a2a4-3f1e-4f5e-9b7a-2d1f0e8c7b6a".to_string(),
+        causation_id: Some("5d3b1f0e-8c7b-4a6a-9e2d-1f0e8c7b6a5d".to_string()),
+        occurred_at: 1646906400000,
+        tags: [("team".to_string(), "pricing".to_string())]
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// One event of each type, with the name of its golden file
+pub fn get_events() -> Vec<(&'static str, Event)> {
+    vec![
+        (
+            "created",
+            Event::Created {
+                product: get_product(10.0),
+            },
+        ),
+        (
+            "updated",
+            Event::Updated {
+                old: get_product(10.0),
+                new: get_product(12.5),
+            },
+        ),
+        (
+            "deleted",
+            Event::Deleted {
+                product: get_product(10.0),
+            },
+        ),
+        (
+            "deletion_scheduled",
+            Event::DeletionScheduled {
+                product: get_product(10.0),
+                expires_at: 1646906400,
+            },
+        ),
+        (
+            "archived",
+            Event::Archived {
+                product: get_product(10.0),
+            },
+        ),
+        (
+            "archive_completed",
+            Event::ArchiveCompleted {
+                category: "shoes".to_string(),
+                archived: 3,
+            },
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_golden() {
+        // The event is the EventBridge detail and the IoT Core payload
+        for (name, event) in get_events() {
+            assert_golden(
+                &format!("event_{}", name),
+                &serde_json::to_value(&event).unwrap(),
+            );
+        }
+    }
+}
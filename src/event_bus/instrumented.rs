@@ -0,0 +1,205 @@
+//! Instrumented bus
+//!
+//! Bus wrapper emitting metrics for every call to the bus under it, in the
+//! CloudWatch embedded metric format: events published per type, batch
+//! sizes, publish latency and failed events, so that alarms can catch a
+//! failing or stalled event pipeline.
+//!
+//! Metrics are written to stdout, which Lambda sends to CloudWatch Logs,
+//! with a `Bus` dimension to tell apart the buses of a function, such as
+//! the targets of event rules.
+
+use super::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::instrument;
+
+/// CloudWatch namespace of the bus metrics
+const METRICS_NAMESPACE: &str = "rust-products";
+
+/// Metrics of a single call to a bus
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PublishMetrics {
+    /// Events delivered, by event type
+    pub published: BTreeMap<String, u64>,
+    /// Events in the call
+    pub batch_size: u64,
+    /// Events the bus couldn't deliver
+    pub failures: u64,
+    /// Time spent in the call, in milliseconds
+    pub latency_ms: u64,
+}
+
+impl PublishMetrics {
+    /// Metrics of sending events, given the result of the call
+    ///
+    /// All events count as failed, unless the bus reports which ones failed.
+    pub fn new(events: &[Event], res: &Result<(), Error>, latency: Duration) -> Self {
+        let mut failed: Vec<&Event> = match res {
+            Ok(()) => Vec::new(),
+            Err(Error::EventsFailed(failed)) => failed.iter().map(|f| &f.event).collect(),
+            Err(_) => events.iter().collect(),
+        };
+        let failures = failed.len() as u64;
+
+        let mut published = BTreeMap::new();
+        for event in events {
+            if let Some(index) = failed.iter().position(|f| *f == event) {
+                failed.swap_remove(index);
+                continue;
+            }
+            *published.entry(event.event_type().to_string()).or_default() += 1;
+        }
+
+        Self {
+            published,
+            batch_size: events.len() as u64,
+            failures,
+            latency_ms: latency.as_millis() as u64,
+        }
+    }
+
+    /// Metrics in the CloudWatch embedded metric format
+    ///
+    /// The first line has the metrics of the call, and the next ones the
+    /// events published for each event type.
+    fn emf_lines(&self, bus: &str, timestamp: u64) -> Vec<String> {
+        let mut lines = vec![serde_json::json!({
+            "_aws": {
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [["Bus"]],
+                    "Metrics": [
+                        {"Name": "EventBatchSize", "Unit": "Count"},
+                        {"Name": "EventPublishLatency", "Unit": "Milliseconds"},
+                        {"Name": "EventPublishFailures", "Unit": "Count"},
+                    ],
+                }],
+            },
+            "Bus": bus,
+            "EventBatchSize": self.batch_size,
+            "EventPublishLatency": self.latency_ms,
+            "EventPublishFailures": self.failures,
+        })
+        .to_string()];
+        for (event_type, count) in &self.published {
+            lines.push(
+                serde_json::json!({
+                    "_aws": {
+                        "Timestamp": timestamp,
+                        "CloudWatchMetrics": [{
+                            "Namespace": METRICS_NAMESPACE,
+                            "Dimensions": [["Bus", "EventType"]],
+                            "Metrics": [{"Name": "EventsPublished", "Unit": "Count"}],
+                        }],
+                    },
+                    "Bus": bus,
+                    "EventType": event_type,
+                    "EventsPublished": count,
+                })
+                .to_string(),
+            );
+        }
+        lines
+    }
+}
+
+/// Bus emitting metrics about the events it sends.
+pub struct InstrumentedBus<B> {
+    inner: B,
+    name: String,
+}
+
+impl<B> InstrumentedBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    /// Wrap a bus, with the name used as the `Bus` dimension
+    pub fn new(inner: B, name: impl Into<String>) -> Self {
+        Self {
+            inner,
+            name: name.into(),
+        }
+    }
+
+    fn record(&self, events: &[Event], res: &Result<(), Error>, started: Instant) {
+        let metrics = PublishMetrics::new(events, res, started.elapsed());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        for line in metrics.emf_lines(&self.name, timestamp) {
+            println!("{}", line);
+        }
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for InstrumentedBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        let started = Instant::now();
+        let res = self.inner.send_event(event).await;
+        self.record(std::slice::from_ref(event), &res, started);
+        res
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let started = Instant::now();
+        let res = self.inner.send_events(events).await;
+        self.record(events, &res, started);
+        res
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::golden, FailedEvent};
+    use serde_json::Value;
+
+    #[test]
+    fn test_publish_metrics() {
+        // GIVEN a call where the bus rejected one of the events
+        let events: Vec<Event> = golden::get_events().into_iter().map(|(_, e)| e).collect();
+        let res = Err(Error::EventsFailed(vec![FailedEvent {
+            event: events[0].clone(),
+            code: "InternalFailure".to_string(),
+            message: "failure".to_string(),
+        }]));
+
+        // WHEN computing its metrics
+        let metrics = PublishMetrics::new(&events, &res, Duration::from_millis(120));
+        let lines: Vec<Value> = metrics
+            .emf_lines("default", 1_000)
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        // THEN the call and the delivered events are reported
+        assert_eq!(lines[0]["EventBatchSize"], events.len());
+        assert_eq!(lines[0]["EventPublishFailures"], 1);
+        assert_eq!(lines[0]["EventPublishLatency"], 120);
+        assert_eq!(lines[0]["Bus"], "default");
+        // AND each delivered event type has its own line
+        let published: u64 = lines[1..]
+            .iter()
+            .map(|line| line["EventsPublished"].as_u64().unwrap())
+            .sum();
+        assert_eq!(published, events.len() as u64 - 1);
+        assert!(lines[1..].iter().all(|line| line["EventType"].is_string()));
+    }
+}
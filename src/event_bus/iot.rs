@@ -0,0 +1,186 @@
+//! IoT Core bus implementation
+//!
+//! Bus implementation publishing events to AWS IoT Core MQTT topics, for
+//! devices and edge applications subscribing to product changes.
+//!
+//! Topics are rendered from a template where `{id}` is replaced by the
+//! product id and `{type}` by the event type, such as `Created`.
+
+use super::{context, EventBus};
+use crate::{Error, Event};
+use async_trait::async_trait;
+use aws_sdk_iotdataplane::{Blob, Client};
+use futures::future::join_all;
+use tracing::{info, instrument};
+
+/// Default topic template
+pub const DEFAULT_TOPIC_TEMPLATE: &str = "products/{id}/events";
+
+/// IoT Core bus implementation.
+pub struct IotMqttBus {
+    client: Client,
+    topic_template: String,
+    qos: i32,
+}
+
+impl IotMqttBus {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            topic_template: DEFAULT_TOPIC_TEMPLATE.to_string(),
+            qos: 0,
+        }
+    }
+
+    /// Publish to topics rendered from this template instead of the default
+    pub fn with_topic_template(mut self, topic_template: String) -> Self {
+        self.topic_template = topic_template;
+        self
+    }
+
+    /// Publish with this MQTT quality of service level
+    ///
+    /// IoT Core only supports QoS 0 (at most once) and 1 (at least once).
+    pub fn with_qos(mut self, qos: i32) -> Result<Self, Error> {
+        if !(0..=1).contains(&qos) {
+            return Err(Error::ClientError("QoS must be 0 or 1"));
+        }
+        self.qos = qos;
+        Ok(self)
+    }
+
+    /// Topic for an event
+    pub fn topic(&self, event: &Event) -> String {
+        self.topic_template
+            .replace("{id}", event.id())
+            .replace("{type}", event.event_type())
+    }
+}
+
+#[async_trait]
+impl EventBus for IotMqttBus {
+    type E = Event;
+
+    /// Publish an event to its topic.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        info!("Publishing event to IoT Core");
+        let payload = serde_json::to_vec(&context::envelope(event)?)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+        self.client
+            .publish()
+            .topic(self.topic(event))
+            .qos(self.qos)
+            .payload(Blob::new(payload))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish a batch of events to their topics.
+    ///
+    /// IoT Core has no batch publish API, so events are published
+    /// concurrently, one request per event.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        info!("Publishing events to IoT Core");
+        let res = join_all(events.iter().map(|event| self.send_event(event))).await;
+
+        // If any of the requests failed, we'll return an error.
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{mock_sdk_config, request_builder},
+        Event, EventEnvelope, Product,
+    };
+    use aws_sdk_iotdataplane::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_event() -> Event {
+        Event::Created {
+            product: Product {
+                id: "1".to_string(),
+                name: "test".to_string(),
+                price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_topic_template() -> Result<(), Error> {
+        // GIVEN an IotMqttBus with a custom topic template
+        let client = Client::from_conf(Config::builder().build());
+        let bus = IotMqttBus::new(client).with_topic_template("catalog/{type}/{id}".to_string());
+
+        // WHEN rendering the topic of an event
+        let topic = bus.topic(&get_event());
+
+        // THEN the placeholders are replaced
+        assert_eq!(topic, "catalog/Created/1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_qos() {
+        // GIVEN an IotMqttBus
+        let client = Client::from_conf(Config::builder().build());
+
+        // WHEN setting an unsupported QoS level
+        let res = IotMqttBus::new(client).with_qos(2);
+
+        // THEN an error is returned
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_send_event() -> Result<(), Error> {
+        // GIVEN a mock IoT Core client
+        let conn = TestConnection::new(vec![(
+            request_builder(
+                "https://data-ats.iot.eu-west-1.amazonaws.com/topics/products%2F1%2Fevents?qos=1",
+            )
+            .body(SdkBody::from(serde_json::to_vec(&get_event()).unwrap()))
+            .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let bus = IotMqttBus::new(client).with_qos(1)?;
+
+        // WHEN sending an event
+        bus.send_event(&get_event()).await?;
+
+        // THEN the event is published to the product topic
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].actual.uri().path(),
+            "/topics/products%2F1%2Fevents"
+        );
+        assert_eq!(requests[0].actual.uri().query(), Some("qos=1"));
+        // AND the payload is the serialized event with its metadata
+        let payload: EventEnvelope =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(payload.event, get_event());
+        assert!(payload.metadata.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! Kinesis Data Streams bus implementation
+//!
+//! Bus implementation writing events to a Kinesis data stream, with the
+//! product id as partition key, so that the events of a product land on the
+//! same shard in the order they were sent.
+//!
+//! With aggregation, events are packed into few Kinesis records with the
+//! aggregation format of the Kinesis Producer Library (KPL), which cuts the
+//! number of records billed for high-volume updates. Consumers built with
+//! the KCL, or with a deaggregation library such as `aws-kinesis-agg`,
+//! unpack them transparently. An aggregated record is written with the
+//! partition key of its first event, so the events of a product keep their
+//! order within a batch, but not across batches.
+
+use super::{
+    context,
+    serializer::{
+        write_bytes, write_key, write_varint, EventSerializer, JsonSerializer, LENGTH_DELIMITED,
+        VARINT,
+    },
+    EventBus,
+};
+use crate::{Error, Event};
+use async_trait::async_trait;
+use aws_sdk_kinesis::{model::PutRecordsRequestEntry, Blob, Client};
+use md5::{Digest, Md5};
+use tracing::{error, info, instrument};
+
+/// Maximum number of records in a PutRecords request
+const BATCH_SIZE: usize = 500;
+
+/// Maximum size of a PutRecords request, with partition keys
+const MAX_REQUEST_BYTES: usize = 5 * 1024 * 1024;
+
+/// Default maximum size of an aggregated record, as in the KPL
+pub const DEFAULT_AGGREGATION_MAX_BYTES: usize = 50 * 1024;
+
+/// Magic number at the start of aggregated records
+const AGGREGATION_MAGIC: [u8; 4] = [0xf3, 0x89, 0x9a, 0xc2];
+
+/// Estimated overhead of each event in an aggregated record: field keys,
+/// lengths and partition key index
+const AGGREGATION_OVERHEAD: usize = 16;
+
+/// Record with its partition key, before it is sent
+#[derive(Clone, Debug, PartialEq)]
+struct KinesisRecord {
+    partition_key: String,
+    data: Vec<u8>,
+}
+
+/// Events packed into a single aggregated record
+#[derive(Default)]
+struct Aggregate {
+    partition_keys: Vec<String>,
+    /// Index of the partition key and data of each event
+    records: Vec<(usize, Vec<u8>)>,
+    size: usize,
+}
+
+impl Aggregate {
+    /// Size the aggregate would have with this record
+    fn size_with(&self, record: &KinesisRecord) -> usize {
+        let key_size = if self.partition_keys.contains(&record.partition_key) {
+            0
+        } else {
+            record.partition_key.len() + AGGREGATION_OVERHEAD
+        };
+        self.size + key_size + record.data.len() + AGGREGATION_OVERHEAD
+    }
+
+    fn push(&mut self, record: KinesisRecord) {
+        self.size = self.size_with(&record);
+        let index = match self
+            .partition_keys
+            .iter()
+            .position(|key| key == &record.partition_key)
+        {
+            Some(index) => index,
+            None => {
+                self.partition_keys.push(record.partition_key);
+                self.partition_keys.len() - 1
+            }
+        };
+        self.records.push((index, record.data));
+    }
+
+    /// Encode the aggregate: the magic number, an `AggregatedRecord`
+    /// message and the MD5 digest of the message
+    fn encode(self) -> KinesisRecord {
+        let mut message = Vec::with_capacity(self.size);
+        for key in &self.partition_keys {
+            write_key(&mut message, 1, LENGTH_DELIMITED);
+            write_bytes(&mut message, key.as_bytes());
+        }
+        for (index, data) in &self.records {
+            let mut record = Vec::with_capacity(data.len() + 8);
+            write_key(&mut record, 1, VARINT);
+            write_varint(&mut record, *index as u64);
+            write_key(&mut record, 3, LENGTH_DELIMITED);
+            write_bytes(&mut record, data);
+
+            write_key(&mut message, 3, LENGTH_DELIMITED);
+            write_bytes(&mut message, &record);
+        }
+
+        let mut data = AGGREGATION_MAGIC.to_vec();
+        data.extend_from_slice(&message);
+        data.extend_from_slice(&Md5::digest(&message));
+        KinesisRecord {
+            partition_key: self.partition_keys.into_iter().next().unwrap_or_default(),
+            data,
+        }
+    }
+}
+
+/// Pack records into aggregated records of up to `max_bytes`
+///
+/// Records that are too large to share an aggregated record with others are
+/// aggregated on their own.
+fn aggregate(records: Vec<KinesisRecord>, max_bytes: usize) -> Vec<KinesisRecord> {
+    let mut aggregated = Vec::new();
+    let mut current = Aggregate::default();
+    for record in records {
+        if !current.records.is_empty() && current.size_with(&record) > max_bytes {
+            aggregated.push(std::mem::take(&mut current).encode());
+        }
+        current.push(record);
+    }
+    if !current.records.is_empty() {
+        aggregated.push(current.encode());
+    }
+    aggregated
+}
+
+/// Kinesis Data Streams bus implementation.
+pub struct KinesisBus {
+    client: Client,
+    stream_name: String,
+    serializer: Box<dyn EventSerializer>,
+    /// Maximum size of aggregated records, if events are aggregated
+    aggregation_max_bytes: Option<usize>,
+}
+
+impl KinesisBus {
+    pub fn new(client: Client, stream_name: String) -> Self {
+        Self {
+            client,
+            stream_name,
+            serializer: Box::new(JsonSerializer),
+            aggregation_max_bytes: None,
+        }
+    }
+
+    /// Serialize events with this serializer instead of JSON
+    pub fn with_serializer(mut self, serializer: Box<dyn EventSerializer>) -> Self {
+        self.serializer = serializer;
+        self
+    }
+
+    /// Pack events into aggregated records of up to `max_bytes`
+    pub fn with_aggregation(mut self, max_bytes: usize) -> Self {
+        self.aggregation_max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn records(&self, events: &[Event]) -> Result<Vec<KinesisRecord>, Error> {
+        let records = events
+            .iter()
+            .map(|event| {
+                Ok(KinesisRecord {
+                    partition_key: event.id().to_string(),
+                    data: self.serializer.serialize(&context::envelope(event)?)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(match self.aggregation_max_bytes {
+            Some(max_bytes) => aggregate(records, max_bytes),
+            None => records,
+        })
+    }
+
+    /// Send a batch of records
+    ///
+    /// PutRecords doesn't fail when some records are rejected, so the
+    /// number of failed records is checked instead.
+    async fn put_records(&self, records: &[KinesisRecord]) -> Result<(), Error> {
+        let entries = records
+            .iter()
+            .map(|record| {
+                PutRecordsRequestEntry::builder()
+                    .partition_key(&record.partition_key)
+                    .data(Blob::new(record.data.clone()))
+                    .build()
+            })
+            .collect();
+
+        let res = self
+            .client
+            .put_records()
+            .stream_name(&self.stream_name)
+            .set_records(Some(entries))
+            .send()
+            .await?;
+
+        match res.failed_record_count {
+            Some(count) if count > 0 => {
+                error!("Kinesis rejected {} records", count);
+                Err(Error::InternalError("Failed to put records to Kinesis"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for KinesisBus {
+    type E = Event;
+
+    /// Write an event to the stream.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    /// Write a batch of events to the stream.
+    ///
+    /// Records are sent in order, in requests of up to 500 records and
+    /// 5 MiB, the limits of `PutRecords`.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let records = self.records(events)?;
+        info!(
+            "Writing {} events to Kinesis in {} records",
+            events.len(),
+            records.len()
+        );
+
+        let mut start = 0;
+        let mut size = 0;
+        for (end, record) in records.iter().enumerate() {
+            let record_size = record.partition_key.len() + record.data.len();
+            if end - start == BATCH_SIZE || size + record_size > MAX_REQUEST_BYTES {
+                self.put_records(&records[start..end]).await?;
+                start = end;
+                size = 0;
+            }
+            size += record_size;
+        }
+        if start < records.len() {
+            self.put_records(&records[start..]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::golden,
+        test_util::{json_request_builder, mock_sdk_config},
+    };
+    use aws_sdk_kinesis::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_record(partition_key: &str, data: &[u8]) -> KinesisRecord {
+        KinesisRecord {
+            partition_key: partition_key.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        // GIVEN records of two products
+        let records = vec![
+            get_record("1", b"a"),
+            get_record("2", b"b"),
+            get_record("1", b"c"),
+        ];
+
+        // WHEN aggregating them
+        let aggregated = aggregate(records, DEFAULT_AGGREGATION_MAX_BYTES);
+
+        // THEN they are packed into a single record, keyed by the first one
+        assert_eq!(aggregated.len(), 1);
+        assert_eq!(aggregated[0].partition_key, "1");
+        // AND the record has the magic number, the message and its digest
+        let data = &aggregated[0].data;
+        let (message, digest) = data[4..].split_at(data.len() - 20);
+        assert_eq!(data[..4], AGGREGATION_MAGIC);
+        assert_eq!(digest, Md5::digest(message).as_slice());
+        assert_eq!(
+            message,
+            [
+                0x0a, 1, b'1', 0x0a, 1, b'2', // partition key table
+                0x1a, 5, 0x08, 0, 0x1a, 1, b'a', // records
+                0x1a, 5, 0x08, 1, 0x1a, 1, b'b', 0x1a, 5, 0x08, 0, 0x1a, 1, b'c',
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_max_bytes() {
+        // GIVEN records too large to all fit in one aggregated record
+        let records: Vec<KinesisRecord> = (0..3)
+            .map(|i| get_record(&i.to_string(), &[0; 100]))
+            .collect();
+
+        // WHEN aggregating them
+        let aggregated = aggregate(records, 300);
+
+        // THEN they are split across records
+        assert_eq!(aggregated.len(), 2);
+        assert_eq!(aggregated[0].partition_key, "0");
+        assert_eq!(aggregated[1].partition_key, "2");
+    }
+
+    #[tokio::test]
+    async fn test_send_events_aggregated() -> Result<(), Error> {
+        // GIVEN a Kinesis bus aggregating events
+        let conn = TestConnection::new(vec![(
+            json_request_builder("1.1", "https://kinesis.eu-west-1.amazonaws.com/")
+                .header("x-amz-target", "Kinesis_20131202.PutRecords")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"FailedRecordCount": 0, "Records": []}"#))
+                .unwrap(),
+        )]);
+        let bus = KinesisBus::new(
+            Client::from_conf_conn(
+                Config::new(&mock_sdk_config().await),
+                DynConnector::new(conn.clone()),
+            ),
+            "products".to_string(),
+        )
+        .with_aggregation(DEFAULT_AGGREGATION_MAX_BYTES);
+        let events: Vec<Event> = golden::get_events().into_iter().map(|(_, e)| e).collect();
+
+        // WHEN sending events
+        bus.send_events(&events).await?;
+
+        // THEN they are written in a single record
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(body["StreamName"], "products");
+        assert_eq!(body["Records"].as_array().unwrap().len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,11 +1,72 @@
 use crate::Error;
 use async_trait::async_trait;
+use std::sync::Arc;
 
+#[cfg(feature = "http")]
+pub mod api_destination;
+mod batching;
+pub mod consumer;
+pub mod context;
+pub mod dead_letter;
+mod dedup;
+mod discard;
 mod eventbridge;
+#[cfg(feature = "sqs")]
+pub mod fifo;
+pub mod file;
+pub mod filtered;
+#[cfg(feature = "kinesis")]
+mod firehose;
+#[cfg(test)]
+pub(crate) mod golden;
+pub mod instrumented;
+#[cfg(feature = "iot")]
+pub mod iot;
+#[cfg(feature = "kinesis")]
+pub mod kinesis;
+pub mod priority;
+#[cfg(any(test, feature = "test-util"))]
+pub mod programmable;
+mod routing;
+pub mod serializer;
+mod sfn;
+mod validating;
 mod void;
+#[cfg(all(feature = "store", feature = "http"))]
+pub mod webhook;
 
+#[cfg(feature = "http")]
+pub use api_destination::ApiDestinationBus;
+pub use batching::BatchingBus;
+#[cfg(feature = "sqs")]
+pub use consumer::SqsEventConsumer;
+pub use consumer::{EventConsumer, EventHandler, MemoryEventConsumer};
+pub use context::EventContext;
+pub use dead_letter::DeadLetterBus;
+pub use dedup::DedupBus;
+pub use discard::DiscardBus;
 pub use eventbridge::EventBridgeBus;
+#[cfg(feature = "sqs")]
+pub use fifo::{OrderedEnvelope, SqsFifoBus};
+pub use file::{FileBus, FileEnvelope};
+pub use filtered::FilteredBus;
+#[cfg(feature = "kinesis")]
+pub use firehose::FirehoseBus;
+pub use instrumented::InstrumentedBus;
+#[cfg(feature = "iot")]
+pub use iot::IotMqttBus;
+#[cfg(feature = "kinesis")]
+pub use kinesis::KinesisBus;
+pub use priority::PriorityBus;
+#[cfg(any(test, feature = "test-util"))]
+pub use programmable::ProgrammableBus;
+pub use routing::RoutingBus;
+pub use serializer::{AvroSerializer, EventSerializer, JsonSerializer, ProtobufSerializer};
+pub use sfn::StepFunctionsBus;
+pub use validating::ValidatingBus;
 pub use void::VoidBus;
+#[cfg(all(feature = "store", feature = "http"))]
+pub use webhook::WebhookBus;
 
 #[async_trait]
 pub trait EventBus {
@@ -13,4 +74,54 @@ pub trait EventBus {
 
     async fn send_event(&self, event: &Self::E) -> Result<(), Error>;
     async fn send_events(&self, events: &[Self::E]) -> Result<(), Error>;
+
+    /// Send events the bus buffered
+    ///
+    /// Buses that send events right away have nothing to do. Lambda
+    /// functions call this before the end of each invocation.
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for Box<B>
+where
+    B: EventBus + Send + Sync + ?Sized,
+    B::E: Sync,
+{
+    type E = B::E;
+
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        (**self).send_event(event).await
+    }
+
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        (**self).send_events(events).await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        (**self).flush().await
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for Arc<B>
+where
+    B: EventBus + Send + Sync + ?Sized,
+    B::E: Sync,
+{
+    type E = B::E;
+
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        (**self).send_event(event).await
+    }
+
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        (**self).send_events(events).await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        (**self).flush().await
+    }
 }
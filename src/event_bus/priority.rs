@@ -0,0 +1,324 @@
+//! Priority bus
+//!
+//! Bus wrapper publishing events through two lanes. Critical events, such
+//! as deletions, are sent right away and retried on transient errors, so
+//! that the caller knows they were delivered before returning. Low-priority
+//! events, such as minor updates, go through a `BatchingBus` and are sent
+//! with the next batch.
+//!
+//! An `EventClassifier` decides the lane of each event. Before a critical
+//! event is sent, the batching lane is flushed, so that the events of a
+//! product are never delivered out of order across lanes.
+
+use super::{BatchingBus, EventBus};
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, instrument, warn};
+
+/// Default number of attempts to send critical events
+const MAX_ATTEMPTS: usize = 3;
+
+/// Default delay before the first retry, doubled after each attempt
+const BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Default relative price change under which an update is minor
+const MINOR_PRICE_CHANGE: f64 = 0.01;
+
+/// Lane an event is published through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Sent right away, with retries
+    Critical,
+    /// Buffered and sent in batches
+    Low,
+}
+
+/// Trait for choosing the lane of an event
+pub trait EventClassifier: Send + Sync {
+    fn classify(&self, event: &Event) -> Priority;
+}
+
+impl<F> EventClassifier for F
+where
+    F: Fn(&Event) -> Priority + Send + Sync,
+{
+    fn classify(&self, event: &Event) -> Priority {
+        self(event)
+    }
+}
+
+/// Default classifier
+///
+/// Updates that keep the name and change the price by less than
+/// `minor_price_change` are low priority. All other events are critical.
+pub struct DefaultClassifier {
+    minor_price_change: f64,
+}
+
+impl DefaultClassifier {
+    /// Set the relative price change under which an update is minor
+    pub fn with_minor_price_change(minor_price_change: f64) -> Self {
+        Self { minor_price_change }
+    }
+}
+
+impl Default for DefaultClassifier {
+    fn default() -> Self {
+        Self::with_minor_price_change(MINOR_PRICE_CHANGE)
+    }
+}
+
+impl EventClassifier for DefaultClassifier {
+    fn classify(&self, event: &Event) -> Priority {
+        match event {
+            Event::Updated { old, new } if old.name == new.name => {
+                let change = if old.price == 0.0 {
+                    new.price.abs()
+                } else {
+                    ((new.price - old.price) / old.price).abs()
+                };
+                if change < self.minor_price_change {
+                    Priority::Low
+                } else {
+                    Priority::Critical
+                }
+            }
+            _ => Priority::Critical,
+        }
+    }
+}
+
+/// Bus publishing events through a critical and a batching lane.
+pub struct PriorityBus<B> {
+    critical: Arc<B>,
+    batched: BatchingBus<Arc<B>>,
+    classifier: Box<dyn EventClassifier>,
+    max_attempts: usize,
+    base_delay: Duration,
+}
+
+impl<B> PriorityBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B) -> Self {
+        let inner = Arc::new(inner);
+        Self {
+            critical: inner.clone(),
+            batched: BatchingBus::new(inner),
+            classifier: Box::new(DefaultClassifier::default()),
+            max_attempts: MAX_ATTEMPTS,
+            base_delay: BASE_DELAY,
+        }
+    }
+
+    /// Set the classifier choosing the lane of each event
+    pub fn with_classifier(mut self, classifier: impl EventClassifier + 'static) -> Self {
+        self.classifier = Box::new(classifier);
+        self
+    }
+
+    /// Set how many times critical events are sent, and the delay before
+    /// the first retry
+    pub fn with_retries(mut self, max_attempts: usize, base_delay: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Set the number of buffered low-priority events that triggers a flush
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.batched = self.batched.with_max_size(max_size);
+        self
+    }
+
+    /// Set the age of the oldest low-priority event that triggers a flush
+    pub fn with_max_latency(mut self, max_latency: Duration) -> Self {
+        self.batched = self.batched.with_max_latency(max_latency);
+        self
+    }
+
+    /// Number of low-priority events waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.batched.pending()
+    }
+
+    /// Send critical events, retrying transient errors
+    async fn send_critical(&self, events: &[Event]) -> Result<(), Error> {
+        self.batched.flush().await?;
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.critical.send_events(events).await {
+                Err(err) if attempts < self.max_attempts && err.is_retryable() => {
+                    warn!("Retrying {} critical events: {}", events.len(), err);
+                    sleep(self.base_delay * 2u32.pow(attempts as u32 - 1)).await;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for PriorityBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    /// Buffer low-priority events, then send critical events
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let (critical, low): (Vec<Event>, Vec<Event>) = events
+            .iter()
+            .cloned()
+            .partition(|event| self.classifier.classify(event) == Priority::Critical);
+        info!(
+            "Sending {} critical and {} low-priority events",
+            critical.len(),
+            low.len()
+        );
+
+        if !low.is_empty() {
+            self.batched.send_events(&low).await?;
+        }
+        if !critical.is_empty() {
+            self.send_critical(&critical).await?;
+        }
+        Ok(())
+    }
+
+    /// Send all buffered low-priority events
+    #[instrument(skip(self))]
+    async fn flush(&self) -> Result<(), Error> {
+        self.batched.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+    use std::sync::Mutex;
+
+    /// Bus recording the event types of each batch
+    #[derive(Default)]
+    struct RecordingBus {
+        batches: Mutex<Vec<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl EventBus for RecordingBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            self.batches
+                .lock()
+                .unwrap()
+                .push(events.iter().map(|e| e.event_type()).collect());
+            Ok(())
+        }
+    }
+
+    fn get_product(price: f64) -> Product {
+        Product {
+            id: "1".to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    fn get_update(old_price: f64, new_price: f64) -> Event {
+        Event::Updated {
+            old: get_product(old_price),
+            new: get_product(new_price),
+        }
+    }
+
+    #[test]
+    fn test_default_classifier() {
+        let classifier = DefaultClassifier::default();
+
+        assert_eq!(
+            classifier.classify(&get_update(100.0, 100.5)),
+            Priority::Low
+        );
+        assert_eq!(
+            classifier.classify(&get_update(100.0, 150.0)),
+            Priority::Critical
+        );
+        assert_eq!(
+            classifier.classify(&Event::Deleted {
+                product: get_product(100.0)
+            }),
+            Priority::Critical
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_events_lanes() -> Result<(), Error> {
+        // GIVEN a priority bus batching minor updates for a minute
+        let bus =
+            PriorityBus::new(RecordingBus::default()).with_max_latency(Duration::from_secs(60));
+
+        // WHEN sending minor updates
+        bus.send_event(&get_update(100.0, 100.1)).await?;
+        bus.send_event(&get_update(100.1, 100.2)).await?;
+
+        // THEN they are buffered
+        assert_eq!(bus.pending(), 2);
+        assert!(bus.critical.batches.lock().unwrap().is_empty());
+
+        // WHEN sending a deletion
+        bus.send_event(&Event::Deleted {
+            product: get_product(100.2),
+        })
+        .await?;
+
+        // THEN the updates are sent first, then the deletion right away
+        assert_eq!(bus.pending(), 0);
+        assert_eq!(
+            *bus.critical.batches.lock().unwrap(),
+            vec![vec!["Updated", "Updated"], vec!["Deleted"]]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_classifier() -> Result<(), Error> {
+        // GIVEN a priority bus batching every event
+        let bus = PriorityBus::new(RecordingBus::default())
+            .with_max_latency(Duration::from_secs(60))
+            .with_classifier(|_: &Event| Priority::Low);
+
+        // WHEN sending a deletion
+        bus.send_event(&Event::Deleted {
+            product: get_product(100.0),
+        })
+        .await?;
+
+        // THEN it is buffered until the bus is flushed
+        assert_eq!(bus.pending(), 1);
+        bus.flush().await?;
+        assert_eq!(*bus.critical.batches.lock().unwrap(), vec![vec!["Deleted"]]);
+
+        Ok(())
+    }
+}
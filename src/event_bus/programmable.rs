@@ -0,0 +1,232 @@
+//! Programmable bus
+//!
+//! Test double for exercising bus wrappers, such as retries, dead-lettering
+//! or batching, without mocking HTTP responses. Tests script the outcome of
+//! each call in advance, then check which events were delivered and in
+//! which order the calls were made.
+//!
+//! Calls beyond the scripted outcomes succeed. Flushes are recorded, but
+//! always succeed without using an outcome. Only available in tests and with
+//! the `test-util` feature.
+
+use super::EventBus;
+use crate::{Error, Event, FailedEvent, SdkErrorDetails, SdkErrorKind};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Scripted outcome of a call
+#[derive(Debug)]
+pub enum Outcome {
+    /// Deliver all events
+    Ok,
+    /// Reject the whole call with a throttling error
+    Throttle,
+    /// Deliver the first events, and reject the others individually
+    PartialFailure(usize),
+    /// Reject the whole call with this error
+    Fail(Error),
+}
+
+/// Bus method that was called
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    SendEvent,
+    SendEvents,
+    Flush,
+}
+
+/// Call made to the bus
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub method: Method,
+    /// Events passed to the call, whether they were delivered or not
+    pub events: Vec<Event>,
+}
+
+/// Bus returning scripted outcomes.
+#[derive(Default)]
+pub struct ProgrammableBus {
+    outcomes: Mutex<VecDeque<Outcome>>,
+    calls: Mutex<Vec<Call>>,
+    delivered: Mutex<Vec<Event>>,
+}
+
+impl ProgrammableBus {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Script the outcome of a call, after the outcomes already scripted
+    pub fn then(self, outcome: Outcome) -> Self {
+        self.push(outcome);
+        self
+    }
+
+    /// Script the outcome of a call on a shared bus
+    pub fn push(&self, outcome: Outcome) {
+        self.outcomes.lock().unwrap().push_back(outcome);
+    }
+
+    /// Calls made so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Methods called so far, in order
+    pub fn methods(&self) -> Vec<Method> {
+        self.calls().into_iter().map(|call| call.method).collect()
+    }
+
+    /// Events delivered so far, in order
+    pub fn delivered(&self) -> Vec<Event> {
+        self.delivered.lock().unwrap().clone()
+    }
+
+    /// Number of scripted outcomes not used yet
+    pub fn remaining(&self) -> usize {
+        self.outcomes.lock().unwrap().len()
+    }
+
+    fn call(&self, method: Method, events: &[Event]) -> Result<(), Error> {
+        self.calls.lock().unwrap().push(Call {
+            method,
+            events: events.to_vec(),
+        });
+        let outcome = self
+            .outcomes
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Outcome::Ok);
+
+        match outcome {
+            Outcome::Ok => {
+                self.delivered.lock().unwrap().extend_from_slice(events);
+                Ok(())
+            }
+            Outcome::Throttle => Err(Error::SdkError(SdkErrorDetails {
+                kind: SdkErrorKind::Throttling,
+                code: Some("ThrottlingException".to_string()),
+                message: "Rate exceeded".to_string(),
+                status: Some(400),
+                request_id: None,
+            })),
+            Outcome::PartialFailure(accepted) => {
+                let accepted = accepted.min(events.len());
+                self.delivered
+                    .lock()
+                    .unwrap()
+                    .extend_from_slice(&events[..accepted]);
+                if accepted == events.len() {
+                    return Ok(());
+                }
+                Err(Error::EventsFailed(
+                    events[accepted..]
+                        .iter()
+                        .map(|event| FailedEvent {
+                            event: event.clone(),
+                            code: "InternalFailure".to_string(),
+                            message: "Scripted failure".to_string(),
+                        })
+                        .collect(),
+                ))
+            }
+            Outcome::Fail(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for ProgrammableBus {
+    type E = Event;
+
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.call(Method::SendEvent, std::slice::from_ref(event))
+    }
+
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        self.call(Method::SendEvents, events)
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.calls.lock().unwrap().push(Call {
+            method: Method::Flush,
+            events: Vec::new(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::PriorityBus, Product};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn get_events(count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|i| Event::Deleted {
+                product: Product {
+                    id: i.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure() {
+        // GIVEN a bus rejecting all but the first event of the first call
+        let bus = ProgrammableBus::new().then(Outcome::PartialFailure(1));
+        let events = get_events(3);
+
+        // WHEN sending events
+        let res = bus.send_events(&events).await;
+
+        // THEN the other events are rejected individually
+        match res {
+            Err(Error::EventsFailed(failed)) => {
+                let failed: Vec<Event> = failed.into_iter().map(|f| f.event).collect();
+                assert_eq!(failed, events[1..].to_vec());
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(bus.delivered(), events[..1].to_vec());
+        assert_eq!(bus.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_retries_throttling() -> Result<(), Error> {
+        // GIVEN a priority bus over a bus throttling the first two calls
+        let inner = Arc::new(
+            ProgrammableBus::new()
+                .then(Outcome::Throttle)
+                .then(Outcome::Throttle),
+        );
+        let bus = PriorityBus::new(inner.clone()).with_retries(3, Duration::ZERO);
+        let events = get_events(2);
+
+        // WHEN sending critical events
+        bus.send_events(&events).await?;
+
+        // THEN the batching lane is flushed first
+        // AND the events are sent again until they are delivered
+        assert_eq!(
+            inner.methods(),
+            vec![
+                Method::Flush,
+                Method::SendEvents,
+                Method::SendEvents,
+                Method::SendEvents
+            ]
+        );
+        assert_eq!(inner.delivered(), events);
+
+        Ok(())
+    }
+}
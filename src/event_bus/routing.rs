@@ -0,0 +1,166 @@
+//! Routing bus
+//!
+//! Bus wrapper applying event rules (see `rules`): events a rule suppresses
+//! are dropped, events a rule routes are sent to the target bus instead of
+//! the default one, and the tags of a rule are added to the metadata of its
+//! events. Events no rule matches go to the default bus unchanged.
+//!
+//! Events are sent in order, in runs of consecutive events matched by the
+//! same rule. Tags are set through the `EventContext` of each run, so buses
+//! under this one must serialize events before returning, rather than
+//! buffering them.
+
+use super::{EventBus, EventContext};
+use crate::{
+    rules::{Action, Rules},
+    Error, Event,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tracing::{error, info, instrument};
+
+/// Bus sending events according to rules.
+pub struct RoutingBus<B> {
+    default: B,
+    targets: HashMap<String, B>,
+    rules: Rules,
+}
+
+impl<B> RoutingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(default: B, rules: Rules) -> Self {
+        Self {
+            default,
+            targets: HashMap::new(),
+            rules,
+        }
+    }
+
+    /// Set the bus events routed to `name` are sent to
+    pub fn with_target(mut self, name: impl Into<String>, bus: B) -> Self {
+        self.targets.insert(name.into(), bus);
+        self
+    }
+
+    /// Send a run of events matched by the same rule, if any
+    async fn send_run(&self, action: Option<&Action>, events: &[Event]) -> Result<(), Error> {
+        let (target, tags) = match action {
+            None => return self.default.send_events(events).await,
+            Some(Action::Suppress) => {
+                info!("Suppressing {} events", events.len());
+                return Ok(());
+            }
+            Some(Action::Publish { target, tags }) => (target, tags),
+        };
+
+        let bus = match target {
+            Some(target) => self.targets.get(target).ok_or_else(|| {
+                error!("No bus for event target {}", target);
+                Error::InternalError("Unknown event target")
+            })?,
+            None => &self.default,
+        };
+        if tags.is_empty() {
+            return bus.send_events(events).await;
+        }
+        EventContext::current()
+            .unwrap_or_default()
+            .with_tags(tags)
+            .scope(bus.send_events(events))
+            .await
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for RoutingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let actions: Vec<Option<&Action>> = events
+            .iter()
+            .map(|event| self.rules.evaluate(event).map(|rule| &rule.action))
+            .collect();
+
+        let mut start = 0;
+        for end in 1..=events.len() {
+            if end == events.len() || actions[end] != actions[start] {
+                self.send_run(actions[start], &events[start..end]).await?;
+                start = end;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.default.flush().await?;
+        for bus in self.targets.values() {
+            bus.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::ProgrammableBus, Product};
+    use std::sync::Arc;
+
+    fn get_event(id: &str, price: f64) -> Event {
+        Event::Created {
+            product: Product {
+                id: id.to_string(),
+                name: "foo".to_string(),
+                price,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events_routed() -> Result<(), Error> {
+        // GIVEN rules routing cheap products and suppressing free ones
+        let rules = Rules::parse(
+            r#"[
+                {"name": "free", "when": {"field": "product.price", "op": "eq", "value": 0}, "action": "suppress"},
+                {"name": "deals", "when": {"field": "product.price", "op": "lt", "value": 10}, "action": "publish", "target": "deals"}
+            ]"#,
+        )?;
+        let default = Arc::new(ProgrammableBus::new());
+        let deals = Arc::new(ProgrammableBus::new());
+        let bus = RoutingBus::new(default.clone(), rules).with_target("deals", deals.clone());
+        let events = vec![
+            get_event("1", 20.0),
+            get_event("2", 5.0),
+            get_event("3", 0.0),
+            get_event("4", 30.0),
+            get_event("5", 40.0),
+        ];
+
+        // WHEN sending the events
+        bus.send_events(&events).await?;
+
+        // THEN each event goes to the bus of its rule
+        assert_eq!(deals.delivered(), vec![events[1].clone()]);
+        assert_eq!(
+            default.delivered(),
+            vec![events[0].clone(), events[3].clone(), events[4].clone()]
+        );
+        // AND consecutive events of the same rule are sent together
+        assert_eq!(default.calls().len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Avro serialization
+//!
+//! Events are written with the Avro binary encoding of the `ProductEvent`
+//! schema in `schema/event.avsc`, without the schema, which consumers get
+//! from the bundled file or from a schema registry.
+
+use super::{encoded_attributes, frame, EventFields, EventSerializer};
+use crate::{Error, EventEnvelope, EventMetadata, Product};
+
+/// Avro schema of product events
+pub static AVRO_SCHEMA: &str = include_str!("../../schema/event.avsc");
+
+/// Serialize events with the Avro binary encoding.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AvroSerializer {
+    schema_id: Option<u32>,
+}
+
+impl AvroSerializer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prefix events with the id of the schema in a Confluent-compatible
+    /// schema registry
+    pub fn with_schema_id(mut self, schema_id: u32) -> Self {
+        self.schema_id = Some(schema_id);
+        self
+    }
+}
+
+impl EventSerializer for AvroSerializer {
+    fn content_type(&self) -> &'static str {
+        "avro/binary"
+    }
+
+    fn serialize(&self, envelope: &EventEnvelope) -> Result<Vec<u8>, Error> {
+        let fields = EventFields::from(&envelope.event);
+        let mut buf = Vec::new();
+        write_string(&mut buf, envelope.event.event_type());
+        write_optional(&mut buf, fields.product, write_product);
+        write_optional(&mut buf, fields.old, write_product);
+        write_optional(&mut buf, fields.new, write_product);
+        write_optional(&mut buf, fields.expires_at, |buf, v| {
+            write_long(buf, v as i64)
+        });
+        write_optional(&mut buf, fields.category, write_string);
+        write_optional(&mut buf, fields.archived, |buf, v| {
+            write_long(buf, v as i64)
+        });
+        write_optional(&mut buf, envelope.metadata.as_ref(), write_metadata);
+        Ok(frame(self.schema_id, buf))
+    }
+}
+
+/// Write a long as a zig-zag encoded variable-length integer
+fn write_long(buf: &mut Vec<u8>, value: i64) {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_long(buf, value.len() as i64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Write a `["null", T]` union
+fn write_optional<T>(buf: &mut Vec<u8>, value: Option<T>, write: impl Fn(&mut Vec<u8>, T)) {
+    match value {
+        Some(value) => {
+            write_long(buf, 1);
+            write(buf, value);
+        }
+        None => write_long(buf, 0),
+    }
+}
+
+/// Write a map of strings, as a single block
+fn write_map<'a>(buf: &mut Vec<u8>, entries: impl ExactSizeIterator<Item = (&'a str, &'a str)>) {
+    if entries.len() > 0 {
+        write_long(buf, entries.len() as i64);
+        for (key, value) in entries {
+            write_string(buf, key);
+            write_string(buf, value);
+        }
+    }
+    write_long(buf, 0);
+}
+
+fn write_product(buf: &mut Vec<u8>, product: &Product) {
+    write_string(buf, &product.id);
+    write_string(buf, &product.name);
+    buf.extend_from_slice(&product.price.to_le_bytes());
+    let attributes = encoded_attributes(product);
+    write_map(buf, attributes.iter().map(|(k, v)| (*k, v.as_str())));
+    if !product.images.is_empty() {
+        write_long(buf, product.images.len() as i64);
+        for image in &product.images {
+            write_string(buf, image);
+        }
+    }
+    write_long(buf, 0);
+}
+
+fn write_metadata(buf: &mut Vec<u8>, metadata: &EventMetadata) {
+    write_string(buf, &metadata.event_id);
+    write_string(buf, &metadata.correlation_id);
+    write_optional(buf, metadata.causation_id.as_deref(), write_string);
+    write_long(buf, metadata.occurred_at as i64);
+    write_map(
+        buf,
+        metadata.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::golden, Event};
+
+    #[test]
+    fn test_write_long() {
+        // GIVEN values from the Avro specification
+        let cases: [(i64, &[u8]); 5] = [
+            (0, &[0x00]),
+            (-1, &[0x01]),
+            (1, &[0x02]),
+            (-64, &[0x7f]),
+            (64, &[0x80, 0x01]),
+        ];
+
+        for (value, expected) in cases {
+            // WHEN encoding the value
+            let mut buf = Vec::new();
+            write_long(&mut buf, value);
+
+            // THEN it matches the zig-zag encoding
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[test]
+    fn test_serialize() -> Result<(), Error> {
+        // GIVEN a completed archive, without metadata
+        let envelope = EventEnvelope {
+            event: Event::ArchiveCompleted {
+                category: "toys".to_string(),
+                archived: 2,
+            },
+            metadata: None,
+        };
+
+        // WHEN serializing it with a schema id
+        let data = AvroSerializer::new()
+            .with_schema_id(7)
+            .serialize(&envelope)?;
+
+        // THEN the record follows the wire format header
+        let mut expected = vec![0, 0, 0, 0, 7];
+        expected.extend_from_slice(&[0x20]);
+        expected.extend_from_slice(b"ArchiveCompleted");
+        // AND unset fields are null
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        expected.extend_from_slice(&[2, 8]);
+        expected.extend_from_slice(b"toys");
+        expected.extend_from_slice(&[2, 4, 0]);
+        assert_eq!(data, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_product() -> Result<(), Error> {
+        // GIVEN a product with every field set
+        let product = golden::get_product(10.0);
+
+        // WHEN serializing it
+        let mut buf = Vec::new();
+        write_product(&mut buf, &product);
+
+        // THEN attributes are JSON-encoded
+        let mut expected = vec![2, b'1', 6];
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&10.0f64.to_le_bytes());
+        expected.extend_from_slice(&[2, 10]);
+        expected.extend_from_slice(b"color");
+        expected.extend_from_slice(&[10]);
+        expected.extend_from_slice(b"\"red\"");
+        expected.extend_from_slice(&[0, 2]);
+        write_string(&mut expected, &product.images[0]);
+        expected.push(0);
+        assert_eq!(buf, expected);
+
+        Ok(())
+    }
+}
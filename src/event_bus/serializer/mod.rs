@@ -0,0 +1,116 @@
+//! Event serialization
+//!
+//! Buses serialize events as JSON by default. Consumers with schema
+//! registry tooling, such as Kafka or Kinesis consumers, can instead read
+//! events in Avro or Protobuf, with the schemas bundled in `schema/`:
+//! `event.avsc` and `event.proto`.
+//!
+//! Both schemas flatten the event types into a single `ProductEvent`
+//! record, where the fields that don't apply to the type of an event are
+//! unset. Attribute values are JSON-encoded, since they can be strings,
+//! numbers or booleans.
+//!
+//! With a schema id, binary payloads start with the Confluent wire format
+//! header: a zero byte followed by the id of the schema in the registry, so
+//! that registry-aware deserializers can find the schema.
+
+use crate::{Error, Event, EventEnvelope, Product};
+use std::collections::BTreeMap;
+
+mod avro;
+mod protobuf;
+
+pub use avro::AvroSerializer;
+pub use protobuf::ProtobufSerializer;
+pub(crate) use protobuf::{write_bytes, write_key, write_varint, LENGTH_DELIMITED, VARINT};
+
+/// Content type of JSON events
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Trait for serializing events before sending them
+pub trait EventSerializer: Send + Sync {
+    /// Content type of the serialized events
+    fn content_type(&self) -> &'static str;
+
+    fn serialize(&self, envelope: &EventEnvelope) -> Result<Vec<u8>, Error>;
+}
+
+/// Serialize events as JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonSerializer;
+
+impl EventSerializer for JsonSerializer {
+    fn content_type(&self) -> &'static str {
+        JSON_CONTENT_TYPE
+    }
+
+    fn serialize(&self, envelope: &EventEnvelope) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(envelope).map_err(|_| Error::InternalError("Unable to serialize event"))
+    }
+}
+
+/// Fields of an event, as in the Avro and Protobuf schemas
+#[derive(Default)]
+struct EventFields<'a> {
+    product: Option<&'a Product>,
+    old: Option<&'a Product>,
+    new: Option<&'a Product>,
+    expires_at: Option<u64>,
+    category: Option<&'a str>,
+    archived: Option<u64>,
+}
+
+impl<'a> From<&'a Event> for EventFields<'a> {
+    fn from(event: &'a Event) -> Self {
+        match event {
+            Event::Created { product }
+            | Event::Deleted { product }
+            | Event::Archived { product } => Self {
+                product: Some(product),
+                ..Default::default()
+            },
+            Event::Updated { old, new } => Self {
+                old: Some(old),
+                new: Some(new),
+                ..Default::default()
+            },
+            Event::DeletionScheduled {
+                product,
+                expires_at,
+            } => Self {
+                product: Some(product),
+                expires_at: Some(*expires_at),
+                ..Default::default()
+            },
+            Event::ArchiveCompleted { category, archived } => Self {
+                category: Some(category),
+                archived: Some(*archived as u64),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// JSON-encoded attributes of a product, sorted by name
+fn encoded_attributes(product: &Product) -> BTreeMap<&str, String> {
+    product
+        .attributes
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.to_string()))
+        .collect()
+}
+
+/// Add the Confluent wire format header before a payload, if a schema id is
+/// set
+fn frame(schema_id: Option<u32>, payload: Vec<u8>) -> Vec<u8> {
+    match schema_id {
+        Some(schema_id) => {
+            let mut data = Vec::with_capacity(payload.len() + 5);
+            data.push(0);
+            data.extend_from_slice(&schema_id.to_be_bytes());
+            data.extend(payload);
+            data
+        }
+        None => payload,
+    }
+}
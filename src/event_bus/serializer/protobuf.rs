@@ -0,0 +1,214 @@
+//! Protobuf serialization
+//!
+//! Events are written as `ProductEvent` messages of `schema/event.proto`.
+//! Fields with their default value are left out, as proto3 encoders do,
+//! except for the `optional` ones that are set.
+
+use super::{encoded_attributes, frame, EventFields, EventSerializer};
+use crate::{Error, EventEnvelope, EventMetadata, Product};
+
+/// Protobuf schema of product events
+pub static PROTOBUF_SCHEMA: &str = include_str!("../../schema/event.proto");
+
+/// Wire types of the encoded fields
+pub(crate) const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+pub(crate) const LENGTH_DELIMITED: u64 = 2;
+
+/// Serialize events as Protobuf messages.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProtobufSerializer {
+    schema_id: Option<u32>,
+}
+
+impl ProtobufSerializer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prefix events with the id of the schema in a Confluent-compatible
+    /// schema registry
+    pub fn with_schema_id(mut self, schema_id: u32) -> Self {
+        self.schema_id = Some(schema_id);
+        self
+    }
+}
+
+impl EventSerializer for ProtobufSerializer {
+    fn content_type(&self) -> &'static str {
+        "application/x-protobuf"
+    }
+
+    fn serialize(&self, envelope: &EventEnvelope) -> Result<Vec<u8>, Error> {
+        let fields = EventFields::from(&envelope.event);
+        let mut buf = Vec::new();
+        if self.schema_id.is_some() {
+            // Message indexes of the wire format: `ProductEvent` is the
+            // first message of the schema
+            buf.push(0);
+        }
+        write_string(&mut buf, 1, envelope.event.event_type());
+        for (field, product) in [(2, fields.product), (3, fields.old), (4, fields.new)] {
+            if let Some(product) = product {
+                write_message(&mut buf, field, &encode_product(product));
+            }
+        }
+        if let Some(expires_at) = fields.expires_at {
+            write_uint64(&mut buf, 5, expires_at);
+        }
+        if let Some(category) = fields.category {
+            write_key(&mut buf, 6, LENGTH_DELIMITED);
+            write_bytes(&mut buf, category.as_bytes());
+        }
+        if let Some(archived) = fields.archived {
+            write_uint64(&mut buf, 7, archived);
+        }
+        if let Some(metadata) = &envelope.metadata {
+            write_message(&mut buf, 8, &encode_metadata(metadata));
+        }
+        Ok(frame(self.schema_id, buf))
+    }
+}
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+pub(crate) fn write_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buf, (field << 3) | wire_type);
+}
+
+pub(crate) fn write_bytes(buf: &mut Vec<u8>, value: &[u8]) {
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Write a field that is always set, such as an `optional` one
+fn write_uint64(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_key(buf, field, VARINT);
+    write_varint(buf, value);
+}
+
+/// Write a string field, unless it is empty
+fn write_string(buf: &mut Vec<u8>, field: u64, value: &str) {
+    if !value.is_empty() {
+        write_key(buf, field, LENGTH_DELIMITED);
+        write_bytes(buf, value.as_bytes());
+    }
+}
+
+fn write_message(buf: &mut Vec<u8>, field: u64, message: &[u8]) {
+    write_key(buf, field, LENGTH_DELIMITED);
+    write_bytes(buf, message);
+}
+
+/// Write the entries of a map of strings
+fn write_map<'a>(buf: &mut Vec<u8>, field: u64, entries: impl Iterator<Item = (&'a str, &'a str)>) {
+    for (key, value) in entries {
+        let mut entry = Vec::new();
+        write_string(&mut entry, 1, key);
+        write_string(&mut entry, 2, value);
+        write_message(buf, field, &entry);
+    }
+}
+
+fn encode_product(product: &Product) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_string(&mut buf, 1, &product.id);
+    write_string(&mut buf, 2, &product.name);
+    if product.price != 0.0 {
+        write_key(&mut buf, 3, FIXED64);
+        buf.extend_from_slice(&product.price.to_le_bytes());
+    }
+    let attributes = encoded_attributes(product);
+    write_map(
+        &mut buf,
+        4,
+        attributes.iter().map(|(k, v)| (*k, v.as_str())),
+    );
+    for image in &product.images {
+        // Repeated strings keep empty values
+        write_key(&mut buf, 5, LENGTH_DELIMITED);
+        write_bytes(&mut buf, image.as_bytes());
+    }
+    buf
+}
+
+fn encode_metadata(metadata: &EventMetadata) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_string(&mut buf, 1, &metadata.event_id);
+    write_string(&mut buf, 2, &metadata.correlation_id);
+    if let Some(causation_id) = &metadata.causation_id {
+        write_key(&mut buf, 3, LENGTH_DELIMITED);
+        write_bytes(&mut buf, causation_id.as_bytes());
+    }
+    if metadata.occurred_at != 0 {
+        write_uint64(&mut buf, 4, metadata.occurred_at);
+    }
+    write_map(
+        &mut buf,
+        5,
+        metadata.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+    );
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::golden, Event};
+
+    #[test]
+    fn test_serialize() -> Result<(), Error> {
+        // GIVEN a completed archive, without metadata
+        let envelope = EventEnvelope {
+            event: Event::ArchiveCompleted {
+                category: "toys".to_string(),
+                archived: 300,
+            },
+            metadata: None,
+        };
+
+        // WHEN serializing it with a schema id
+        let data = ProtobufSerializer::new()
+            .with_schema_id(7)
+            .serialize(&envelope)?;
+
+        // THEN the message follows the wire format header and message index
+        let mut expected = vec![0, 0, 0, 0, 7, 0];
+        expected.extend_from_slice(&[0x0a, 16]);
+        expected.extend_from_slice(b"ArchiveCompleted");
+        expected.extend_from_slice(&[0x32, 4]);
+        expected.extend_from_slice(b"toys");
+        expected.extend_from_slice(&[0x38, 0xac, 0x02]);
+        assert_eq!(data, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_product() {
+        // GIVEN a product with every field set
+        let product = golden::get_product(10.0);
+
+        // WHEN encoding it
+        let buf = encode_product(&product);
+
+        // THEN every field is written, with JSON-encoded attributes
+        let mut expected = vec![0x0a, 1, b'1', 0x12, 3];
+        expected.extend_from_slice(b"foo");
+        expected.push(0x19);
+        expected.extend_from_slice(&10.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x22, 14, 0x0a, 5]);
+        expected.extend_from_slice(b"color");
+        expected.extend_from_slice(&[0x12, 5]);
+        expected.extend_from_slice(b"\"red\"");
+        expected.extend_from_slice(&[0x2a, product.images[0].len() as u8]);
+        expected.extend_from_slice(product.images[0].as_bytes());
+        assert_eq!(buf, expected);
+    }
+}
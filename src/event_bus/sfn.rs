@@ -0,0 +1,229 @@
+//! Step Functions bus implementation
+//!
+//! Bus implementation starting an AWS Step Functions state machine execution
+//! for each event, with the serialized event as input. This is used to start
+//! workflows, such as fulfillment, when products change.
+//!
+//! With `with_batching`, a batch of events starts a single execution, whose
+//! input is `{"events": [...]}`. Execution inputs are limited to 256 KB.
+
+use super::{context, EventBus};
+use crate::{Error, Event, EventEnvelope};
+use async_trait::async_trait;
+use aws_sdk_sfn::Client;
+use futures::future::join_all;
+use serde_json::json;
+use tracing::{info, instrument};
+
+/// Step Functions bus implementation.
+pub struct StepFunctionsBus {
+    client: Client,
+    state_machine_arn: String,
+    event_types: Option<Vec<String>>,
+    batching: bool,
+}
+
+impl StepFunctionsBus {
+    pub fn new(client: Client, state_machine_arn: String) -> Self {
+        Self {
+            client,
+            state_machine_arn,
+            event_types: None,
+            batching: false,
+        }
+    }
+
+    /// Only start executions for these event types, such as `Created`
+    pub fn with_event_types(mut self, event_types: Vec<String>) -> Self {
+        self.event_types = Some(event_types);
+        self
+    }
+
+    /// Start one execution per batch of events instead of one per event
+    pub fn with_batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Whether an event should start an execution
+    fn accepts(&self, event: &Event) -> bool {
+        match &self.event_types {
+            Some(event_types) => event_types.iter().any(|t| t == event.event_type()),
+            None => true,
+        }
+    }
+
+    async fn start_execution(&self, input: String) -> Result<(), Error> {
+        self.client
+            .start_execution()
+            .state_machine_arn(&self.state_machine_arn)
+            .input(input)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventBus for StepFunctionsBus {
+    type E = Event;
+
+    /// Start an execution with the event as input.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        if !self.accepts(event) {
+            return Ok(());
+        }
+        info!("Starting Step Functions execution");
+        let input = serde_json::to_string(&context::envelope(event)?)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+        self.start_execution(input).await
+    }
+
+    /// Start executions for a batch of events.
+    ///
+    /// Without batching, executions are started concurrently, one request
+    /// per event.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        if !self.batching {
+            let res = join_all(events.iter().map(|event| self.send_event(event))).await;
+            res.into_iter().collect::<Result<Vec<_>, _>>()?;
+            return Ok(());
+        }
+
+        let events: Vec<EventEnvelope> = events
+            .iter()
+            .filter(|e| self.accepts(e))
+            .map(context::envelope)
+            .collect::<Result<_, _>>()?;
+        if events.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "Starting Step Functions execution for {} events",
+            events.len()
+        );
+        self.start_execution(json!({ "events": events }).to_string())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{json_request_builder, mock_sdk_config},
+        Product,
+    };
+    use aws_sdk_sfn::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_request() -> http::Request<SdkBody> {
+        json_request_builder("1.0", "https://states.eu-west-1.amazonaws.com/")
+            .header("x-amz-target", "AWSStepFunctions.StartExecution")
+            .body(SdkBody::from("{}"))
+            .unwrap()
+    }
+
+    fn get_response() -> http::Response<SdkBody> {
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::from(
+                r#"{"executionArn": "arn:aws:states:eu-west-1:123456789012:execution:test:1", "startDate": 1646906400}"#,
+            ))
+            .unwrap()
+    }
+
+    fn get_product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "test".to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    fn get_bus(conn: &TestConnection<SdkBody>, config: Config) -> StepFunctionsBus {
+        StepFunctionsBus::new(
+            Client::from_conf_conn(config, DynConnector::new(conn.clone())),
+            "arn:aws:states:eu-west-1:123456789012:stateMachine:test".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN a bus only starting executions for created products
+        let conn = TestConnection::new(vec![(get_request(), get_response())]);
+        let bus = get_bus(&conn, Config::new(&mock_sdk_config().await))
+            .with_event_types(vec!["Created".to_string()]);
+        let created = Event::Created {
+            product: get_product("1"),
+        };
+
+        // WHEN sending a created and a deleted event
+        bus.send_events(&[
+            created.clone(),
+            Event::Deleted {
+                product: get_product("2"),
+            },
+        ])
+        .await?;
+
+        // THEN a single execution is started with the created event as input
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(
+            body["stateMachineArn"],
+            "arn:aws:states:eu-west-1:123456789012:stateMachine:test"
+        );
+        let input: Event = serde_json::from_str(body["input"].as_str().unwrap()).unwrap();
+        assert_eq!(input, created);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_batching() -> Result<(), Error> {
+        // GIVEN a bus starting one execution per batch
+        let conn = TestConnection::new(vec![(get_request(), get_response())]);
+        let bus = get_bus(&conn, Config::new(&mock_sdk_config().await)).with_batching(true);
+        let events = vec![
+            Event::Created {
+                product: get_product("1"),
+            },
+            Event::Created {
+                product: get_product("2"),
+            },
+        ];
+
+        // WHEN sending a batch of events
+        bus.send_events(&events).await?;
+
+        // THEN a single execution is started with all events as input
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        let input: serde_json::Value =
+            serde_json::from_str(body["input"].as_str().unwrap()).unwrap();
+        let envelopes: Vec<EventEnvelope> =
+            serde_json::from_value(input["events"].clone()).unwrap();
+        assert_eq!(
+            envelopes
+                .iter()
+                .map(|e| e.event.clone())
+                .collect::<Vec<_>>(),
+            events
+        );
+        // AND each event carries its metadata
+        assert!(envelopes.iter().all(|e| e.metadata.is_some()));
+
+        Ok(())
+    }
+}
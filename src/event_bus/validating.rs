@@ -0,0 +1,97 @@
+//! Validating bus
+//!
+//! Bus wrapper checking events against their schema before passing them to
+//! the inner bus. If any event of a batch is invalid, nothing is sent and
+//! `Error::InvalidEvent` describes the problem.
+
+use super::EventBus;
+use crate::{schema::EventValidator, Error, Event};
+use async_trait::async_trait;
+use tracing::{error, instrument};
+
+/// Bus validating events before sending them.
+pub struct ValidatingBus<B> {
+    inner: B,
+    validator: EventValidator,
+}
+
+impl<B> ValidatingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    pub fn new(inner: B, validator: EventValidator) -> Self {
+        Self { inner, validator }
+    }
+
+    fn validate(&self, event: &Event) -> Result<(), Error> {
+        self.validator.validate(event).map_err(|err| {
+            error!("Refusing to publish event: {}", err);
+            err
+        })
+    }
+}
+
+#[async_trait]
+impl<B> EventBus for ValidatingBus<B>
+where
+    B: EventBus<E = Event> + Send + Sync,
+{
+    type E = Event;
+
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.validate(event)?;
+        self.inner.send_event(event).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        for event in events {
+            self.validate(event)?;
+        }
+        self.inner.send_events(events).await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_bus::VoidBus, schema::Schema};
+
+    #[tokio::test]
+    async fn test_send_events_invalid() {
+        // GIVEN a bus requiring products to have images
+        let schema =
+            Schema::parse(r#"{"properties": {"product": {"required": ["images"]}}}"#).unwrap();
+        let bus = ValidatingBus::new(
+            VoidBus,
+            EventValidator::new().with_schema("Created", schema),
+        );
+
+        // WHEN sending a product without images
+        let res = bus
+            .send_events(&[Event::Created {
+                product: crate::Product {
+                    id: "1".to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            }])
+            .await;
+
+        // THEN the event is refused with a description of the problem
+        match res {
+            Err(Error::InvalidEvent(msg)) => assert_eq!(
+                msg,
+                "Created event for product 1 doesn't match its schema: /product: missing property images"
+            ),
+            _ => panic!("Expected InvalidEvent"),
+        }
+    }
+}
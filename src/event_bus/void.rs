@@ -1,7 +1,13 @@
+//! Void bus
+//!
+//! Bus rejecting every event, to test how callers handle failures. Use
+//! `DiscardBus` to accept events without sending them.
+
 use super::EventBus;
 use crate::{Error, Event};
 use async_trait::async_trait;
 
+/// Bus failing to send any event.
 #[derive(Default)]
 pub struct VoidBus;
 
@@ -37,6 +43,8 @@ mod tests {
                 id: "123".to_string(),
                 name: "test".to_string(),
                 price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
             },
         };
         let result = bus.send_event(&event).await;
@@ -51,6 +59,8 @@ mod tests {
                 id: "123".to_string(),
                 name: "test".to_string(),
                 price: 10.0,
+                attributes: Default::default(),
+                images: Default::default(),
             },
         };
         let result = bus.send_events(&[event]).await;
@@ -0,0 +1,491 @@
+//! Webhook bus implementation
+//!
+//! Bus implementation delivering events to the webhook subscriptions of a
+//! store: each event is sent in a `POST` request to every subscription
+//! matching its type.
+//!
+//! Requests are signed so that receivers can verify they come from this
+//! service. The `x-webhook-timestamp` header has the time of the request,
+//! in seconds since the epoch, and `x-webhook-signature` has one
+//! `key_id=signature` pair per signing key, separated by commas. Signatures
+//! are the hex-encoded HMAC-SHA256 of `{timestamp}.{body}`.
+//!
+//! Every active key signs each request, so keys can be rotated without
+//! downtime: add the new key, update the receivers, then remove the old
+//! key. Keys are loaded from a source such as a Secrets Manager secret, and
+//! cached for a few minutes. Subscriptions with their own secret also get a
+//! signature with the key id `subscription`.
+//!
+//! Failed requests are retried on their own, so that a failing subscription
+//! doesn't make the others receive the same events again. Events that still
+//! fail are reported with `Error::EventsFailed`, once per subscription they
+//! couldn't be delivered to. Sending them again delivers them to every
+//! matching subscription, so receivers should skip events whose
+//! `metadata.event_id` they already received.
+
+use super::{context, EventBus};
+use crate::{
+    store::StoreListWebhooks, Error, Event, EventEnvelope, FailedEvent, WebhookSubscription,
+};
+use async_trait::async_trait;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{sync::Mutex, time::sleep};
+use tracing::{error, info, instrument, warn};
+
+/// Signing keys are loaded again after this long
+const KEY_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Number of attempts to deliver an event to a subscription
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying failed requests, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Key id of the signature made with the secret of a subscription
+const SUBSCRIPTION_KEY_ID: &str = "subscription";
+
+/// Secret key signing webhook requests
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct SigningKey {
+    /// Identifier of the key, sent with its signature
+    pub id: String,
+    pub secret: String,
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Don't leak secrets in logs
+        write!(f, "SigningKey({})", self.id)
+    }
+}
+
+/// Content of a secret holding signing keys
+#[derive(Deserialize)]
+struct SigningKeySecret {
+    keys: Vec<SigningKey>,
+}
+
+/// Trait for loading the active signing keys
+#[async_trait]
+pub trait SigningKeySource: Send + Sync {
+    async fn keys(&self) -> Result<Vec<SigningKey>, Error>;
+}
+
+/// Fixed set of signing keys
+#[async_trait]
+impl SigningKeySource for Vec<SigningKey> {
+    async fn keys(&self) -> Result<Vec<SigningKey>, Error> {
+        Ok(self.clone())
+    }
+}
+
+/// Signing keys stored in a Secrets Manager secret
+///
+/// The secret string is a JSON object with the active keys, e.g.
+/// `{"keys": [{"id": "2024-06", "secret": "..."}]}`.
+pub struct SecretsManagerKeys {
+    client: aws_sdk_secretsmanager::Client,
+    secret_id: String,
+}
+
+impl SecretsManagerKeys {
+    pub fn new(client: aws_sdk_secretsmanager::Client, secret_id: String) -> Self {
+        Self { client, secret_id }
+    }
+}
+
+#[async_trait]
+impl SigningKeySource for SecretsManagerKeys {
+    #[instrument(skip(self))]
+    async fn keys(&self) -> Result<Vec<SigningKey>, Error> {
+        info!("Loading webhook signing keys from Secrets Manager");
+        let res = self
+            .client
+            .get_secret_value()
+            .secret_id(&self.secret_id)
+            .send()
+            .await?;
+        let secret = res.secret_string.ok_or(Error::InternalError(
+            "Signing key secret has no string value",
+        ))?;
+        let secret: SigningKeySecret = serde_json::from_str(&secret)
+            .map_err(|_| Error::InternalError("Invalid signing key secret"))?;
+        Ok(secret.keys)
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of a message
+fn hmac_sha256(secret: &str, message: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Value of the signature header for a request body
+pub fn signature_header(keys: &[SigningKey], timestamp: u64, body: &[u8]) -> String {
+    let mut message = format!("{}.", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    keys.iter()
+        .map(|key| format!("{}={}", key.id, hmac_sha256(&key.secret, &message)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Webhook bus implementation.
+pub struct WebhookBus<S> {
+    client: Client,
+    subscriptions: S,
+    key_source: Box<dyn SigningKeySource>,
+    keys: Mutex<Option<(Vec<SigningKey>, Instant)>>,
+}
+
+impl<S> WebhookBus<S>
+where
+    S: StoreListWebhooks,
+{
+    pub fn new(client: Client, subscriptions: S) -> Self {
+        Self {
+            client,
+            subscriptions,
+            key_source: Box::new(Vec::new()),
+            keys: Mutex::new(None),
+        }
+    }
+
+    /// Sign requests with the keys of this source
+    pub fn with_key_source(mut self, key_source: Box<dyn SigningKeySource>) -> Self {
+        self.key_source = key_source;
+        self
+    }
+
+    /// Return the cached signing keys, or load them again
+    async fn signing_keys(&self) -> Result<Vec<SigningKey>, Error> {
+        let mut keys = self.keys.lock().await;
+        if let Some((value, loaded_at)) = keys.as_ref() {
+            if loaded_at.elapsed() < KEY_CACHE_TTL {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = self.key_source.keys().await?;
+        *keys = Some((value.clone(), Instant::now()));
+        Ok(value)
+    }
+
+    fn build_request(
+        &self,
+        subscription: &WebhookSubscription,
+        keys: &[SigningKey],
+        envelope: &EventEnvelope,
+        timestamp: u64,
+    ) -> Result<RequestBuilder, Error> {
+        let body = serde_json::to_vec(envelope)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+
+        let mut keys = keys.to_vec();
+        if !subscription.secret.is_empty() {
+            keys.insert(
+                0,
+                SigningKey {
+                    id: SUBSCRIPTION_KEY_ID.to_string(),
+                    secret: subscription.secret.clone(),
+                },
+            );
+        }
+
+        let mut req = self
+            .client
+            .post(&subscription.url)
+            .header("content-type", "application/json")
+            .header("x-event-type", envelope.event.event_type())
+            .header("x-webhook-id", &subscription.id)
+            .header("x-webhook-timestamp", timestamp.to_string());
+        if !keys.is_empty() {
+            req = req.header(
+                "x-webhook-signature",
+                signature_header(&keys, timestamp, &body),
+            );
+        }
+        Ok(req.body(body))
+    }
+
+    async fn deliver(
+        &self,
+        subscription: &WebhookSubscription,
+        keys: &[SigningKey],
+        envelope: &EventEnvelope,
+    ) -> Result<(), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let res = self
+            .build_request(subscription, keys, envelope, timestamp)?
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling webhook {}: {}", subscription.id, err);
+                Error::InternalError("Unable to reach webhook")
+            })?;
+
+        if !res.status().is_success() {
+            error!(
+                "Webhook {} returned status {}",
+                subscription.id,
+                res.status()
+            );
+            return Err(Error::InternalError("Webhook returned an error"));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> EventBus for WebhookBus<S>
+where
+    S: StoreListWebhooks,
+{
+    type E = Event;
+
+    /// Deliver an event to the subscriptions matching its type.
+    #[instrument(skip(self))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.send_events(std::slice::from_ref(event)).await
+    }
+
+    /// Deliver a batch of events, one request per event and subscription.
+    ///
+    /// Only the failed requests are retried, and the events that still fail
+    /// are returned with the subscription in the message of the failure.
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        let subscriptions = self.subscriptions.webhooks().await?;
+        let keys = self.signing_keys().await?;
+
+        let mut deliveries = Vec::new();
+        for event in events {
+            let envelope = context::envelope(event)?;
+            for subscription in subscriptions.iter().filter(|s| s.matches(event)) {
+                deliveries.push((subscription, envelope.clone()));
+            }
+        }
+        info!("Delivering {} webhook requests", deliveries.len());
+
+        let mut pending: Vec<_> = deliveries.iter().collect();
+        let mut attempt = 1;
+        loop {
+            let res = join_all(
+                pending
+                    .iter()
+                    .map(|(subscription, envelope)| self.deliver(subscription, &keys, envelope)),
+            )
+            .await;
+            let failed: Vec<_> = pending
+                .into_iter()
+                .zip(res)
+                .filter_map(|(delivery, res)| res.err().map(|err| (delivery, err)))
+                .collect();
+            if failed.is_empty() {
+                return Ok(());
+            }
+            if attempt >= DELIVERY_ATTEMPTS {
+                error!("Failed to deliver {} webhook requests", failed.len());
+                return Err(Error::EventsFailed(
+                    failed
+                        .into_iter()
+                        .map(|((subscription, envelope), err)| FailedEvent {
+                            event: envelope.event.clone(),
+                            code: "WebhookFailed".to_string(),
+                            message: format!("Webhook {}: {}", subscription.id, err),
+                        })
+                        .collect(),
+                ));
+            }
+
+            warn!("Retrying {} failed webhook requests", failed.len());
+            sleep(RETRY_DELAY * attempt).await;
+            attempt += 1;
+            pending = failed.into_iter().map(|(delivery, _)| delivery).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::golden,
+        store::{MemoryWebhookStore, StorePutWebhook},
+    };
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn get_subscription(secret: &str) -> WebhookSubscription {
+        WebhookSubscription {
+            id: "hook".to_string(),
+            url: "https://example.com/hooks".to_string(),
+            event_types: Vec::new(),
+            secret: secret.to_string(),
+        }
+    }
+
+    fn get_keys() -> Vec<SigningKey> {
+        vec![
+            SigningKey {
+                id: "new".to_string(),
+                secret: "new-secret".to_string(),
+            },
+            SigningKey {
+                id: "old".to_string(),
+                secret: "old-secret".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // GIVEN the first test case of RFC 4231
+        let key = String::from_utf8(vec![0x0b; 20]).unwrap();
+
+        // WHEN computing the HMAC of the message
+        let mac = hmac_sha256(&key, b"Hi There");
+
+        // THEN it matches the expected value
+        assert_eq!(
+            mac,
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+    }
+
+    #[test]
+    fn test_build_request() -> Result<(), Error> {
+        // GIVEN a bus with two active keys, and a subscription with a secret
+        let bus = WebhookBus::new(Client::new(), MemoryWebhookStore::new());
+        let envelope = context::envelope(&golden::get_events().remove(0).1)?;
+
+        // WHEN building the request for an event
+        let req = bus
+            .build_request(
+                &get_subscription("sub-secret"),
+                &get_keys(),
+                &envelope,
+                1_000,
+            )?
+            .build()
+            .unwrap();
+
+        // THEN the request is signed with every key
+        let body = req.body().unwrap().as_bytes().unwrap();
+        let message = [b"1000.".as_slice(), body].concat();
+        assert_eq!(req.headers()["x-webhook-timestamp"], "1000");
+        assert_eq!(
+            req.headers()["x-webhook-signature"],
+            format!(
+                "subscription={},new={},old={}",
+                hmac_sha256("sub-secret", &message),
+                hmac_sha256("new-secret", &message),
+                hmac_sha256("old-secret", &message),
+            )
+            .as_str()
+        );
+        // AND contains the event
+        let sent: EventEnvelope = serde_json::from_slice(body).unwrap();
+        assert_eq!(sent, envelope);
+
+        Ok(())
+    }
+
+    /// Serve webhook requests, answering `/fail` with errors, and record the
+    /// path of each request
+    async fn serve(listener: TcpListener, paths: Arc<StdMutex<Vec<String>>>) {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 4096];
+            // Read the whole request before answering
+            let path = loop {
+                let n = socket.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length: usize = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            let line = line.to_ascii_lowercase();
+                            line.strip_prefix("content-length:")
+                                .and_then(|v| v.trim().parse().ok())
+                        })
+                        .unwrap_or(0);
+                    if n == 0 || request.len() >= end + 4 + length {
+                        break text.split(' ').nth(1).unwrap_or_default().to_string();
+                    }
+                }
+            };
+            let status = if path == "/fail" {
+                "500 Internal Server Error"
+            } else {
+                "200 OK"
+            };
+            paths.lock().unwrap().push(path);
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events_partial_failure() -> Result<(), Error> {
+        // GIVEN a working and a failing subscription
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let paths = Arc::new(StdMutex::new(Vec::new()));
+        tokio::spawn(serve(listener, paths.clone()));
+        let subscriptions = MemoryWebhookStore::new();
+        for id in ["ok", "fail"] {
+            subscriptions
+                .put_webhook(&WebhookSubscription {
+                    id: id.to_string(),
+                    url: format!("http://127.0.0.1:{}/{}", port, id),
+                    event_types: Vec::new(),
+                    secret: String::new(),
+                })
+                .await?;
+        }
+        let bus = WebhookBus::new(Client::new(), subscriptions);
+        let event = golden::get_events().remove(0).1;
+
+        // WHEN sending an event
+        let res = bus.send_event(&event).await;
+
+        // THEN only the failing subscription is retried
+        let mut paths = paths.lock().unwrap().clone();
+        paths.sort();
+        assert_eq!(paths, vec!["/fail", "/fail", "/fail", "/ok"]);
+        // AND the event is reported as failed for that subscription only
+        match res {
+            Err(Error::EventsFailed(failed)) => {
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].event, event);
+                assert!(failed[0].message.starts_with("Webhook fail:"));
+            }
+            res => panic!("Expected EventsFailed, got {:?}", res),
+        }
+
+        Ok(())
+    }
+}
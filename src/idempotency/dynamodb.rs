@@ -0,0 +1,278 @@
+//! # DynamoDB idempotency store implementation
+//!
+//! Records are items keyed by idempotency key, started with a conditional
+//! put. The `expires_at` attribute holds the expiry time in seconds since the
+//! UNIX epoch, and should be set as the TTL attribute of the table so that
+//! DynamoDB removes expired records.
+
+use super::{IdempotencyRecord, IdempotencyStore, StoredResponse};
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use aws_smithy_http::result::SdkError;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Default time after which a completed record expires
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default time after which an in-progress record expires
+///
+/// This should be longer than the timeout of the functions using the store.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// DynamoDB idempotency store implementation.
+pub struct DynamoDBIdempotencyStore {
+    client: Client,
+    table_name: String,
+    ttl: Duration,
+    lock_timeout: Duration,
+}
+
+impl DynamoDBIdempotencyStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBIdempotencyStore {
+        DynamoDBIdempotencyStore {
+            client,
+            table_name,
+            ttl: DEFAULT_TTL,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    /// Set the time after which a completed record expires
+    pub fn with_ttl(mut self, ttl: Duration) -> DynamoDBIdempotencyStore {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the time after which an in-progress record expires
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> DynamoDBIdempotencyStore {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Get the record for a key
+    async fn get(&self, key: &str) -> Result<Option<IdempotencyRecord>, Error> {
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        res.item.map(to_record).transpose()
+    }
+}
+
+/// Current time in seconds since the UNIX epoch
+fn now_secs() -> Result<u64, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_secs())
+}
+
+/// Convert a DynamoDB item into an idempotency record
+fn to_record(item: HashMap<String, AttributeValue>) -> Result<IdempotencyRecord, Error> {
+    let get_s = |key: &str| item.get(key).and_then(|v| v.as_s().ok()).cloned();
+    let status = match item.get("response_status").map(|v| v.as_n()) {
+        Some(Ok(status)) => Some(
+            status
+                .parse()
+                .map_err(|_| Error::InternalError("Invalid response status"))?,
+        ),
+        Some(Err(_)) => return Err(Error::InternalError("Invalid response status")),
+        None => None,
+    };
+
+    Ok(IdempotencyRecord {
+        key: get_s("id").ok_or(Error::InternalError("Missing id"))?,
+        fingerprint: get_s("fingerprint").ok_or(Error::InternalError("Missing fingerprint"))?,
+        response: status.map(|status| StoredResponse {
+            status,
+            body: get_s("response_body").unwrap_or_default(),
+        }),
+    })
+}
+
+#[async_trait]
+impl IdempotencyStore for DynamoDBIdempotencyStore {
+    /// Start a request
+    ///
+    /// The condition only lets the write through if there is no record for
+    /// this key, or if the record has expired but wasn't removed yet.
+    /// Otherwise, the existing record is read back. If it expired in the
+    /// meantime, the write is attempted again.
+    #[instrument(skip(self))]
+    async fn start(
+        &self,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        loop {
+            info!("Starting request '{}' in DynamoDB table", key);
+            let now = now_secs()?;
+            let res = self
+                .client
+                .put_item()
+                .table_name(&self.table_name)
+                .item("id", AttributeValue::S(key.to_string()))
+                .item("fingerprint", AttributeValue::S(fingerprint.to_string()))
+                .item(
+                    "expires_at",
+                    AttributeValue::N((now + self.lock_timeout.as_secs()).to_string()),
+                )
+                .condition_expression("attribute_not_exists(id) OR expires_at < :now")
+                .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                .send()
+                .await;
+
+            match res {
+                Ok(_) => return Ok(None),
+                // There is already a record for this key
+                Err(SdkError::ServiceError { err, .. })
+                    if err.is_conditional_check_failed_exception() =>
+                {
+                    if let Some(record) = self.get(key).await? {
+                        return Ok(Some(record));
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Store the response of a request
+    #[instrument(skip(self, response))]
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error> {
+        info!("Completing request '{}' in DynamoDB table", key);
+        let expires_at = now_secs()? + self.ttl.as_secs();
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .update_expression(
+                "SET response_status = :status, response_body = :body, expires_at = :expires_at",
+            )
+            .expression_attribute_values(":status", AttributeValue::N(response.status.to_string()))
+            .expression_attribute_values(":body", AttributeValue::S(response.body.clone()))
+            .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove the record of a request
+    #[instrument(skip(self))]
+    async fn abandon(&self, key: &str) -> Result<(), Error> {
+        info!("Abandoning request '{}' in DynamoDB table", key);
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_start() -> Result<(), Error> {
+        // GIVEN an empty idempotency table
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBIdempotencyStore::new(client, "test".to_string());
+
+        // WHEN starting a request
+        let res = store.start("key", "fp").await?;
+
+        // THEN there is no existing record
+        assert_eq!(res, None);
+        // AND the request is a conditional put
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body
+            .contains(r#""ConditionExpression":"attribute_not_exists(id) OR expires_at < :now""#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_completed() -> Result<(), Error> {
+        // GIVEN an idempotency table with a completed record for the key
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(
+                        r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                    ))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        r#"{"Item": {"id": {"S": "key"}, "fingerprint": {"S": "fp"}, "expires_at": {"N": "4102444800"}, "response_status": {"N": "201"}, "response_body": {"S": "{}"}}}"#,
+                    ))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBIdempotencyStore::new(client, "test".to_string());
+
+        // WHEN starting the request again
+        let res = store.start("key", "fp").await?;
+
+        // THEN the existing record is returned with its response
+        assert_eq!(
+            res,
+            Some(IdempotencyRecord {
+                key: "key".to_string(),
+                fingerprint: "fp".to_string(),
+                response: Some(StoredResponse {
+                    status: 201,
+                    body: "{}".to_string(),
+                }),
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! # In-memory idempotency store implementation
+//!
+//! Records only last for the lifetime of the process, which is enough to
+//! deduplicate retries handled by the same function instance or in tests.
+
+use super::{IdempotencyRecord, IdempotencyStore, StoredResponse};
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Default time after which a completed record expires
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default time after which an in-progress record expires
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct MemoryIdempotencyStore {
+    ttl: Duration,
+    lock_timeout: Duration,
+    records: RwLock<HashMap<String, (IdempotencyRecord, Instant)>>,
+}
+
+impl Default for MemoryIdempotencyStore {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TTL,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            records: Default::default(),
+        }
+    }
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the time after which a completed record expires
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the time after which an in-progress record expires
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn start(
+        &self,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let now = Instant::now();
+        let mut records = self.records.write().unwrap();
+        records.retain(|_, (_, expires_at)| *expires_at > now);
+        if let Some((record, _)) = records.get(key) {
+            return Ok(Some(record.clone()));
+        }
+        let record = IdempotencyRecord {
+            key: key.to_string(),
+            fingerprint: fingerprint.to_string(),
+            response: None,
+        };
+        records.insert(key.to_string(), (record, now + self.lock_timeout));
+        Ok(None)
+    }
+
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error> {
+        let mut records = self.records.write().unwrap();
+        if let Some((record, expires_at)) = records.get_mut(key) {
+            record.response = Some(response.clone());
+            *expires_at = Instant::now() + self.ttl;
+        }
+        Ok(())
+    }
+
+    async fn abandon(&self, key: &str) -> Result<(), Error> {
+        self.records.write().unwrap().remove(key);
+        Ok(())
+    }
+}
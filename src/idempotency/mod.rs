@@ -0,0 +1,172 @@
+//! # Request idempotency
+//!
+//! Clients can send an `Idempotency-Key` header with write requests, so that
+//! retrying a request after a timeout doesn't apply it twice. The first
+//! request with a key stores a record while it is in progress, then the
+//! response once it completes. Retries with the same key replay the stored
+//! response instead of running the request again.
+//!
+//! Records also hold a fingerprint of the request, to detect keys reused for
+//! a different request. In-progress records expire after a short lock
+//! timeout, so that a key isn't blocked if the function crashed while
+//! handling the request.
+
+use crate::Error;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBIdempotencyStore;
+pub use memory::MemoryIdempotencyStore;
+
+/// Response stored for a completed request
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Record for an idempotency key
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub fingerprint: String,
+    /// Response of the request, or `None` while the request is in progress
+    pub response: Option<StoredResponse>,
+}
+
+/// Trait for storing idempotency records
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Start a request with an idempotency key
+    ///
+    /// Returns `None` if there was no unexpired record for this key, in which
+    /// case a new in-progress record is stored. Otherwise, returns the
+    /// existing record.
+    async fn start(&self, key: &str, fingerprint: &str)
+        -> Result<Option<IdempotencyRecord>, Error>;
+
+    /// Store the response of a request
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error>;
+
+    /// Remove the record of a request, so that it can be retried
+    async fn abandon(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Outcome of starting a request with an idempotency key
+#[derive(Clone, Debug, PartialEq)]
+pub enum Begin {
+    /// First request with this key, which should be processed
+    New,
+    /// The request was already processed, and its response should be replayed
+    Completed(StoredResponse),
+    /// The request is being processed by another invocation
+    InProgress,
+    /// The key was already used for a different request
+    Mismatch,
+}
+
+/// Start a request with an idempotency key
+pub async fn begin(
+    store: &dyn IdempotencyStore,
+    key: &str,
+    fingerprint: &str,
+) -> Result<Begin, Error> {
+    Ok(match store.start(key, fingerprint).await? {
+        None => Begin::New,
+        Some(record) if record.fingerprint != fingerprint => Begin::Mismatch,
+        Some(IdempotencyRecord {
+            response: Some(response),
+            ..
+        }) => Begin::Completed(response),
+        Some(_) => Begin::InProgress,
+    })
+}
+
+/// Fingerprint of a request
+///
+/// This is the hex-encoded SHA-256 digest of the method, path and body.
+pub fn fingerprint(method: &str, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_begin() -> Result<(), Error> {
+        // GIVEN a store with a completed request
+        let store = MemoryIdempotencyStore::new();
+        let fp = fingerprint("PUT", "/1", b"{}");
+        assert_eq!(begin(&store, "key", &fp).await?, Begin::New);
+        let response = StoredResponse {
+            status: 201,
+            body: "{}".to_string(),
+        };
+        store.complete("key", &response).await?;
+
+        // WHEN starting the same request again
+        let res = begin(&store, "key", &fp).await?;
+
+        // THEN the stored response is returned
+        assert_eq!(res, Begin::Completed(response));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_begin_in_progress() -> Result<(), Error> {
+        // GIVEN a store with a request in progress
+        let store = MemoryIdempotencyStore::new();
+        let fp = fingerprint("DELETE", "/1", b"");
+        begin(&store, "key", &fp).await?;
+
+        // WHEN starting the same request again
+        let res = begin(&store, "key", &fp).await?;
+
+        // THEN the request is reported as in progress
+        assert_eq!(res, Begin::InProgress);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_begin_mismatch() -> Result<(), Error> {
+        // GIVEN a store with a request in progress
+        let store = MemoryIdempotencyStore::new();
+        begin(&store, "key", &fingerprint("PUT", "/1", b"{}")).await?;
+
+        // WHEN starting a different request with the same key
+        let res = begin(&store, "key", &fingerprint("PUT", "/2", b"{}")).await?;
+
+        // THEN the key reuse is detected
+        assert_eq!(res, Begin::Mismatch);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_abandon() -> Result<(), Error> {
+        // GIVEN a store with a request in progress
+        let store = MemoryIdempotencyStore::new();
+        let fp = fingerprint("PUT", "/1", b"{}");
+        begin(&store, "key", &fp).await?;
+
+        // WHEN abandoning the request
+        store.abandon("key").await?;
+
+        // THEN the request can be started again
+        assert_eq!(begin(&store, "key", &fp).await?, Begin::New);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! # In-memory image store implementation
+//!
+//! This image store returns fake URLs, without storing anything. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::ImageStore;
+use crate::Error;
+use async_trait::async_trait;
+
+#[derive(Default)]
+pub struct MemoryImageStore;
+
+impl MemoryImageStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl ImageStore for MemoryImageStore {
+    async fn upload_url(&self, key: &str, content_type: &str) -> Result<String, Error> {
+        Ok(format!(
+            "memory://images/{}?method=PUT&content-type={}",
+            key, content_type
+        ))
+    }
+
+    async fn download_url(&self, key: &str) -> Result<String, Error> {
+        Ok(format!("memory://images/{}", key))
+    }
+}
@@ -0,0 +1,59 @@
+//! # Product images
+//!
+//! Images are kept out of the products table: clients upload and download
+//! them directly with presigned URLs, and products only keep the keys of
+//! their images.
+
+use crate::Error;
+use async_trait::async_trait;
+
+mod memory;
+mod s3;
+
+pub use memory::MemoryImageStore;
+pub use s3::S3ImageStore;
+
+/// Trait for generating presigned image URLs
+///
+/// URLs are only valid for a limited time, so they are generated on every
+/// request rather than stored with the product.
+#[async_trait]
+pub trait ImageStore: Send + Sync {
+    /// URL to upload an image with the given content type
+    async fn upload_url(&self, key: &str, content_type: &str) -> Result<String, Error>;
+
+    /// URL to download an image
+    async fn download_url(&self, key: &str) -> Result<String, Error>;
+}
+
+/// Generate the key of a new image of a product
+///
+/// Keys are grouped by product, with a random suffix so that an upload never
+/// overwrites another image.
+pub fn new_image_key(product_id: &str) -> Result<String, Error> {
+    let mut suffix = [0; 16];
+    getrandom::getrandom(&mut suffix)
+        .map_err(|_| Error::InternalError("Failed to generate image key"))?;
+    let suffix: String = suffix.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("products/{}/{}", product_id, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_image_key() -> Result<(), Error> {
+        // WHEN generating two image keys for the same product
+        let first = new_image_key("1")?;
+        let second = new_image_key("1")?;
+
+        // THEN both keys are under the product prefix
+        assert!(first.starts_with("products/1/"));
+        assert_eq!(first.len(), "products/1/".len() + 32);
+        // AND they are different
+        assert_ne!(first, second);
+
+        Ok(())
+    }
+}
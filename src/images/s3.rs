@@ -0,0 +1,107 @@
+//! # S3 image store implementation
+//!
+//! Image store implementation using presigned S3 URLs. Upload URLs are
+//! signed for the content type of the image, so the client must send the
+//! same `Content-Type` header.
+
+use super::ImageStore;
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::{presigning::config::PresigningConfig, Client};
+use std::time::Duration;
+use tracing::{info, instrument};
+
+/// Default validity of presigned URLs
+const DEFAULT_EXPIRES_IN: Duration = Duration::from_secs(15 * 60);
+
+/// S3 image store implementation.
+pub struct S3ImageStore {
+    client: Client,
+    bucket: String,
+    expires_in: Duration,
+}
+
+impl S3ImageStore {
+    pub fn new(client: Client, bucket: String) -> Self {
+        Self {
+            client,
+            bucket,
+            expires_in: DEFAULT_EXPIRES_IN,
+        }
+    }
+
+    /// Make presigned URLs valid for `expires_in`
+    ///
+    /// S3 doesn't accept presigned URLs valid for more than 7 days.
+    pub fn with_expiry(mut self, expires_in: Duration) -> Self {
+        self.expires_in = expires_in;
+        self
+    }
+
+    fn presigning_config(&self) -> Result<PresigningConfig, Error> {
+        PresigningConfig::expires_in(self.expires_in)
+            .map_err(|_| Error::InternalError("Invalid presigned URL expiry"))
+    }
+}
+
+#[async_trait]
+impl ImageStore for S3ImageStore {
+    /// Presign a `PutObject` request
+    #[instrument(skip(self))]
+    async fn upload_url(&self, key: &str, content_type: &str) -> Result<String, Error> {
+        info!("Presigning upload of image '{}'", key);
+        let req = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .presigned(self.presigning_config()?)
+            .await?;
+
+        Ok(req.uri().to_string())
+    }
+
+    /// Presign a `GetObject` request
+    #[instrument(skip(self))]
+    async fn download_url(&self, key: &str) -> Result<String, Error> {
+        info!("Presigning download of image '{}'", key);
+        let req = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(self.presigning_config()?)
+            .await?;
+
+        Ok(req.uri().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mock_sdk_config;
+
+    /// S3 client signing with fake credentials
+    async fn get_client() -> Client {
+        Client::new(&mock_sdk_config().await)
+    }
+
+    #[tokio::test]
+    async fn test_download_url() -> Result<(), Error> {
+        // GIVEN an S3 image store
+        let store = S3ImageStore::new(get_client().await, "images".to_string())
+            .with_expiry(Duration::from_secs(60));
+
+        // WHEN presigning the download of an image
+        let url = store.download_url("products/1/abc").await?;
+
+        // THEN the URL points to the image and is signed
+        assert!(url.contains("products/1/abc"));
+        assert!(url.contains("X-Amz-Expires=60"));
+        assert!(url.contains("X-Amz-Signature="));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! # DynamoDB lag store implementation
+//!
+//! Each consumer has a single item keyed by its name in `id`, which is
+//! overwritten after every batch. Sequence numbers are stored as strings, as
+//! consumers of the change feed use zero-padded sequences.
+
+use super::{ConsumerPosition, LagStore};
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// DynamoDB lag store implementation.
+pub struct DynamoDBLagStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBLagStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBLagStore {
+        DynamoDBLagStore { client, table_name }
+    }
+}
+
+#[async_trait]
+impl LagStore for DynamoDBLagStore {
+    /// Put the position of a consumer
+    #[instrument(skip(self))]
+    async fn record(&self, position: &ConsumerPosition) -> Result<(), Error> {
+        info!(
+            "Putting position of '{}' in DynamoDB table",
+            position.consumer
+        );
+        let mut req = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(position.consumer.clone()))
+            .item("sequence", AttributeValue::S(position.sequence.clone()))
+            .item(
+                "recorded_at",
+                AttributeValue::N(position.recorded_at.to_string()),
+            );
+        if let Some(event_time) = position.event_time {
+            req = req.item("event_time", AttributeValue::N(event_time.to_string()));
+        }
+        req.send().await?;
+
+        Ok(())
+    }
+
+    /// Scan the positions of all the consumers
+    #[instrument(skip(self))]
+    async fn positions(&self) -> Result<Vec<ConsumerPosition>, Error> {
+        info!("Scanning consumer positions in DynamoDB table");
+        let mut positions = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            positions.extend(
+                res.items
+                    .unwrap_or_default()
+                    .iter()
+                    .map(position_from_item)
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(positions)
+    }
+}
+
+fn position_from_item(item: &HashMap<String, AttributeValue>) -> Result<ConsumerPosition, Error> {
+    let string = |name: &'static str| -> Result<String, Error> {
+        item.get(name)
+            .and_then(|v| v.as_s().ok())
+            .cloned()
+            .ok_or(Error::InternalError(name))
+    };
+    let number = |name: &str| -> Option<u64> { item.get(name)?.as_n().ok()?.parse().ok() };
+
+    Ok(ConsumerPosition {
+        consumer: string("id")?,
+        sequence: string("sequence")?,
+        event_time: number("event_time"),
+        recorded_at: number("recorded_at").ok_or(Error::InternalError("recorded_at"))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_positions() -> Result<(), Error> {
+        // GIVEN a lag table with two consumers
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Items": [
+                        {"id": {"S": "search-index"}, "sequence": {"S": "200"}, "event_time": {"N": "1000"}, "recorded_at": {"N": "2500"}},
+                        {"id": {"S": "projection:products"}, "sequence": {"S": "0100"}, "recorded_at": {"N": "3000"}}
+                    ]}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBLagStore::new(client, "test".to_string());
+
+        // WHEN scanning the positions
+        let positions = store.positions().await?;
+
+        // THEN both positions are returned
+        assert_eq!(
+            positions,
+            vec![
+                ConsumerPosition {
+                    consumer: "search-index".to_string(),
+                    sequence: "200".to_string(),
+                    event_time: Some(1000),
+                    recorded_at: 2500,
+                },
+                ConsumerPosition {
+                    consumer: "projection:products".to_string(),
+                    sequence: "0100".to_string(),
+                    event_time: None,
+                    recorded_at: 3000,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+}
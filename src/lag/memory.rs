@@ -0,0 +1,36 @@
+//! # In-memory lag store implementation
+//!
+//! Positions only last for the lifetime of the process, which is enough for
+//! local runs and tests.
+
+use super::{ConsumerPosition, LagStore};
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryLagStore {
+    positions: RwLock<HashMap<String, ConsumerPosition>>,
+}
+
+impl MemoryLagStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl LagStore for MemoryLagStore {
+    async fn record(&self, position: &ConsumerPosition) -> Result<(), Error> {
+        self.positions
+            .write()
+            .unwrap()
+            .insert(position.consumer.clone(), position.clone());
+        Ok(())
+    }
+
+    async fn positions(&self) -> Result<Vec<ConsumerPosition>, Error> {
+        Ok(self.positions.read().unwrap().values().cloned().collect())
+    }
+}
@@ -0,0 +1,209 @@
+//! # Consumer lag
+//!
+//! Downstream consumers, such as the search indexer and the projections,
+//! record the position of the last event they processed in a shared table:
+//! its sequence number, the time the event happened when it is known, and
+//! the time it was processed.
+//!
+//! Lag is the time between an event and its processing, as of the last
+//! batch. As an idle consumer doesn't record anything, positions also show
+//! how long ago they were recorded, which tells a stalled consumer from one
+//! with nothing to do.
+//!
+//! Each recorded position is also emitted as a `ConsumerLag` metric in the
+//! CloudWatch embedded metric format, so that alarms can be set per
+//! consumer.
+
+use crate::Error;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBLagStore;
+pub use memory::MemoryLagStore;
+
+/// CloudWatch namespace of the lag metrics
+const METRICS_NAMESPACE: &str = "rust-products";
+
+/// Position of a consumer in the event stream
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConsumerPosition {
+    /// Name of the consumer, e.g. `search-index`
+    pub consumer: String,
+    /// Sequence number of the last processed event
+    pub sequence: String,
+    /// Time of the last processed event, in milliseconds since the UNIX
+    /// epoch, if the event source provides it
+    pub event_time: Option<u64>,
+    /// Time the position was recorded, in milliseconds since the UNIX epoch
+    pub recorded_at: u64,
+}
+
+impl ConsumerPosition {
+    /// Time between the last processed event and its processing
+    pub fn lag(&self) -> Option<u64> {
+        self.event_time
+            .map(|event_time| self.recorded_at.saturating_sub(event_time))
+    }
+}
+
+/// Lag of a consumer, as returned by the admin API
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConsumerLag {
+    #[serde(flatten)]
+    pub position: ConsumerPosition,
+    pub lag_ms: Option<u64>,
+    /// Time since the position was recorded
+    pub idle_ms: u64,
+}
+
+/// Trait for storing consumer positions
+#[async_trait]
+pub trait LagStore: Send + Sync {
+    /// Replace the position of a consumer
+    async fn record(&self, position: &ConsumerPosition) -> Result<(), Error>;
+
+    /// Positions of all the consumers
+    async fn positions(&self) -> Result<Vec<ConsumerPosition>, Error>;
+}
+
+/// Current time in milliseconds since the UNIX epoch
+fn now() -> Result<u64, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_millis() as u64)
+}
+
+/// Record the position of a consumer after processing a batch
+///
+/// Lag monitoring must not hold consumers back, so errors are logged rather
+/// than returned.
+pub async fn record_position(
+    store: &dyn LagStore,
+    consumer: &str,
+    sequence: &str,
+    event_time: Option<u64>,
+) {
+    let position = match now() {
+        Ok(recorded_at) => ConsumerPosition {
+            consumer: consumer.to_string(),
+            sequence: sequence.to_string(),
+            event_time,
+            recorded_at,
+        },
+        Err(err) => {
+            warn!("Unable to record the position of {}: {}", consumer, err);
+            return;
+        }
+    };
+
+    if let Some(line) = metric_line(&position) {
+        println!("{}", line);
+    }
+    info!("Recording position {} of {}", sequence, consumer);
+    if let Err(err) = store.record(&position).await {
+        warn!("Unable to record the position of {}: {}", consumer, err);
+    }
+}
+
+/// Lag metric in the CloudWatch embedded metric format
+///
+/// Positions without an event time have no lag to report.
+fn metric_line(position: &ConsumerPosition) -> Option<String> {
+    let lag = position.lag()?;
+    Some(
+        json!({
+            "_aws": {
+                "Timestamp": position.recorded_at,
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [["Consumer"]],
+                    "Metrics": [{"Name": "ConsumerLag", "Unit": "Milliseconds"}],
+                }],
+            },
+            "Consumer": position.consumer,
+            "ConsumerLag": lag,
+        })
+        .to_string(),
+    )
+}
+
+/// Lag of every consumer, sorted by name
+pub async fn consumer_lags(store: &dyn LagStore) -> Result<Vec<ConsumerLag>, Error> {
+    let now = now()?;
+    let mut lags: Vec<ConsumerLag> = store
+        .positions()
+        .await?
+        .into_iter()
+        .map(|position| ConsumerLag {
+            lag_ms: position.lag(),
+            idle_ms: now.saturating_sub(position.recorded_at),
+            position,
+        })
+        .collect();
+    lags.sort_by(|a, b| a.position.consumer.cmp(&b.position.consumer));
+    Ok(lags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_metric_line() {
+        // GIVEN a position processed 1.5 seconds after the event
+        let position = ConsumerPosition {
+            consumer: "search-index".to_string(),
+            sequence: "100".to_string(),
+            event_time: Some(1_000),
+            recorded_at: 2_500,
+        };
+
+        // WHEN formatting the metric
+        let line: Value = serde_json::from_str(&metric_line(&position).unwrap()).unwrap();
+
+        // THEN the lag is reported for the consumer
+        assert_eq!(line["ConsumerLag"], 1_500);
+        assert_eq!(line["Consumer"], "search-index");
+        assert_eq!(
+            line["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Name"],
+            "ConsumerLag"
+        );
+        // AND positions without an event time have no metric
+        assert!(metric_line(&ConsumerPosition {
+            event_time: None,
+            ..position
+        })
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_consumer_lags() -> Result<(), Error> {
+        // GIVEN two consumers that recorded their position
+        let store = MemoryLagStore::new();
+        record_position(&store, "search-index", "200", Some(now()? - 1_000)).await;
+        record_position(&store, "projection:products", "100", None).await;
+
+        // WHEN listing their lag
+        let lags = consumer_lags(&store).await?;
+
+        // THEN each consumer is returned in order
+        let consumers: Vec<&str> = lags
+            .iter()
+            .map(|lag| lag.position.consumer.as_str())
+            .collect();
+        assert_eq!(consumers, vec!["projection:products", "search-index"]);
+        // AND the lag is known when the event time is
+        assert_eq!(lags[0].lag_ms, None);
+        assert!(lags[1].lag_ms.unwrap() >= 1_000);
+
+        Ok(())
+    }
+}
@@ -1,24 +1,85 @@
 //! # Domain logic for the service
+//!
+//! The crate can also be used as a library by other services. The model
+//! and the errors are always available, and Cargo features enable the
+//! adapters:
+//!
+//! * `store`: product stores, such as `store::DynamoDBStore`, and the
+//!   transactional outbox,
+//! * `event-bus`: event buses such as `event_bus::EventBridgeBus`, event
+//!   consumers, schemas and rules,
+//! * `service`: the domain logic, the Lambda handlers and `utils`, which
+//!   configures everything from environment variables.
+//!
+//! Adapters with heavier dependencies have their own features: `http`,
+//! `kinesis`, `iot`, `sqs` and `timestream`.
+//!
+//! The default features build the Lambda functions, so libraries should
+//! disable them, e.g.
+//! `products = { version = "0.1", default-features = false, features = ["store"] }`.
+//! Adapters are built from SDK clients, so that callers keep control of
+//! their AWS configuration. Types are re-exported at the root of the crate,
+//! and these paths are kept stable across minor versions.
 
+#[cfg(feature = "event-bus")]
+pub mod consumer;
+#[cfg(feature = "service")]
 pub mod domain;
+#[cfg(feature = "service")]
 pub mod entrypoints;
 mod error;
+#[cfg(feature = "event-bus")]
 pub mod event_bus;
-mod model;
+#[cfg(feature = "service")]
+pub mod idempotency;
+#[cfg(feature = "service")]
+pub mod images;
+#[cfg(feature = "service")]
+pub mod lag;
+pub mod model;
+#[cfg(feature = "service")]
+pub mod object_store;
+#[cfg(feature = "store")]
+pub mod outbox;
+#[cfg(feature = "service")]
+pub mod projection;
+#[cfg(feature = "service")]
+pub mod recommendations;
+#[cfg(feature = "service")]
+pub mod reconcile;
+#[cfg(feature = "service")]
+pub mod replay;
+#[cfg(feature = "event-bus")]
+pub mod rules;
+#[cfg(feature = "event-bus")]
+pub mod schema;
+#[cfg(feature = "store")]
 pub mod store;
+#[cfg(feature = "service")]
+pub mod tax;
+#[cfg(all(test, any(feature = "store", feature = "event-bus")))]
+mod test_util;
+#[cfg(feature = "service")]
 pub mod utils;
 
-pub use error::Error;
+pub use error::{Error, FailedEvent, SdkErrorDetails, SdkErrorKind};
+#[cfg(feature = "event-bus")]
 use event_bus::EventBus;
-pub use model::{Event, Product, ProductRange};
+pub use model::{
+    AdminProduct, AuditAction, AuditEntry, Change, ChangeRange, Event, EventEnvelope,
+    EventMetadata, PriceBreakdown, PricePoint, Product, ProductBatch, ProductHits, ProductImage,
+    ProductPatch, ProductRange, ProductRevision, ProductView, PublicProduct, WebhookSubscription,
+};
 
 /// Event Service
 ///
 /// This service takes events and publishes them to the event bus.
+#[cfg(feature = "event-bus")]
 pub struct EventService {
     event_bus: Box<dyn EventBus<E = Event> + Send + Sync>,
 }
 
+#[cfg(feature = "event-bus")]
 impl EventService {
     pub fn new(event_bus: Box<dyn EventBus<E = Event> + Send + Sync>) -> Self {
         Self { event_bus }
@@ -2,13 +2,220 @@
 //!
 //! This module contains the representations of the products.
 
+use crate::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Product {
     pub id: String,
     pub name: String,
     pub price: f64,
+    /// Custom attributes for data without a dedicated field
+    ///
+    /// Values are strings, numbers or booleans. The number and size of
+    /// attributes are limited, see `domain::validate_attributes`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub attributes: HashMap<String, Value>,
+    /// Keys of the product images in the image bucket
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+impl Product {
+    /// Version of the product, used as its entity tag
+    ///
+    /// This is a hash of the fields of the product, so that any change gives
+    /// a new version, and every backend computes the same one.
+    pub fn version(&self) -> String {
+        // Objects are serialized with sorted keys through `Value`
+        let json = serde_json::to_value(self)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        Sha256::digest(json.as_bytes())[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Partial update of a product
+///
+/// Fields that are `None` are left unchanged.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ProductPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+}
+
+impl ProductPatch {
+    /// Whether the patch doesn't change any field
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.price.is_none()
+    }
+
+    /// Apply the patch to a product
+    pub fn apply(&self, product: &mut Product) {
+        if let Some(name) = &self.name {
+            product.name = name.clone();
+        }
+        if let Some(price) = self.price {
+            product.price = price;
+        }
+    }
+}
+
+/// Response profile of a product, selected with `?view=`
+///
+/// Without a view, products are returned as stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProductView {
+    /// Minimal view for storefronts
+    Public,
+    /// Detailed view for back-office tooling
+    Admin,
+}
+
+impl FromStr for ProductView {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(ProductView::Public),
+            "admin" => Ok(ProductView::Admin),
+            _ => Err(Error::ClientError("view must be 'public' or 'admin'")),
+        }
+    }
+}
+
+/// Public view of a product
+///
+/// Custom attributes are left out, as they can hold internal data.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PublicProduct {
+    pub id: String,
+    pub name: String,
+    pub price: f64,
+}
+
+impl From<Product> for PublicProduct {
+    fn from(product: Product) -> Self {
+        Self {
+            id: product.id,
+            name: product.name,
+            price: product.price,
+        }
+    }
+}
+
+/// Admin view of a product
+///
+/// The version is the number of audited mutations of the product, and the
+/// timestamps are in milliseconds since the UNIX epoch. Products written
+/// before the audit log existed have no timestamps.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AdminProduct {
+    #[serde(flatten)]
+    pub product: Product,
+    pub version: usize,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    /// Identity of the caller of the last mutation, if it was authenticated
+    pub updated_by: Option<String>,
+}
+
+/// Price of a product in a given country
+///
+/// `net` is the price of the product before tax, and `gross` the price
+/// including tax. `rate` is the tax rate as a fraction, e.g. `0.2` for 20%.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PriceBreakdown {
+    pub id: String,
+    pub country: String,
+    pub net: f64,
+    pub tax: f64,
+    pub gross: f64,
+    pub rate: f64,
+}
+
+/// Presigned URL of a product image
+///
+/// For new images, the URL is used to upload the image with a `PUT`
+/// request. Otherwise, it is used to download the image.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProductImage {
+    pub key: String,
+    pub url: String,
+}
+
+/// Number of times a product was read
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProductHits {
+    pub id: String,
+    pub hits: u64,
+}
+
+/// A past version of a product
+///
+/// The version is the time of the write, in milliseconds since the UNIX
+/// epoch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProductRevision {
+    pub version: u64,
+    pub product: Product,
+}
+
+/// The price of a product at a point in time
+///
+/// The time is in milliseconds since the UNIX epoch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PricePoint {
+    pub time: u64,
+    pub price: f64,
+}
+
+/// A mutation recorded in the audit log
+///
+/// The timestamp is the time of the mutation, in milliseconds since the UNIX
+/// epoch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub timestamp: u64,
+    pub action: AuditAction,
+    /// Identity of the caller, if the request was authenticated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+    /// Id of the API key used for the request, for REST APIs with usage plans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Put,
+    Patch,
+    Delete,
+    HardDelete,
+    Restore,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Put => "put",
+            AuditAction::Patch => "patch",
+            AuditAction::Delete => "delete",
+            AuditAction::HardDelete => "hard_delete",
+            AuditAction::Restore => "restore",
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -18,20 +225,137 @@ pub struct ProductRange {
     pub next: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Products retrieved by id within a latency budget
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProductBatch {
+    pub products: Vec<Product>,
+    /// Whether some products could not be retrieved in time
+    pub partial: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(tag = "type")]
 pub enum Event {
-    Created { product: Product },
-    Updated { old: Product, new: Product },
-    Deleted { product: Product },
+    Created {
+        product: Product,
+    },
+    Updated {
+        old: Product,
+        new: Product,
+    },
+    Deleted {
+        product: Product,
+    },
+    /// A deleted product will be permanently removed at `expires_at`, in
+    /// seconds since the UNIX epoch
+    DeletionScheduled {
+        product: Product,
+        expires_at: u64,
+    },
+    /// A discontinued product was archived with the rest of its category
+    Archived {
+        product: Product,
+    },
+    /// All the products of a category were archived
+    ArchiveCompleted {
+        category: String,
+        /// Number of products archived by this run
+        archived: usize,
+    },
 }
 
 impl Event {
+    /// Id of the product, or category of an `ArchiveCompleted` event
     pub fn id(&self) -> &str {
         match self {
             Event::Created { product } => product.id.as_str(),
             Event::Updated { new, .. } => new.id.as_str(),
             Event::Deleted { product } => product.id.as_str(),
+            Event::DeletionScheduled { product, .. } => product.id.as_str(),
+            Event::Archived { product } => product.id.as_str(),
+            Event::ArchiveCompleted { category, .. } => category.as_str(),
+        }
+    }
+
+    /// Name of the event type, as found in the `type` field once serialized
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Event::Created { .. } => "Created",
+            Event::Updated { .. } => "Updated",
+            Event::Deleted { .. } => "Deleted",
+            Event::DeletionScheduled { .. } => "DeletionScheduled",
+            Event::Archived { .. } => "Archived",
+            Event::ArchiveCompleted { .. } => "ArchiveCompleted",
         }
     }
 }
+
+/// Tracing metadata of a published event
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventMetadata {
+    /// Unique id of this publication of the event
+    pub event_id: String,
+    /// Id shared by all the events caused by the same original request
+    pub correlation_id: String,
+    /// Id of the request or invocation that directly caused the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causation_id: Option<String>,
+    /// Time the event was published, in milliseconds since the UNIX epoch
+    pub occurred_at: u64,
+    /// Tags added by event rules
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Event with its metadata, as serialized by event buses
+///
+/// The metadata is a `metadata` field next to the fields of the event, so
+/// that consumers parsing an `Event` can ignore it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventEnvelope {
+    #[serde(flatten)]
+    pub event: Event,
+    /// Missing from events published before metadata existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<EventMetadata>,
+}
+
+/// A subscriber receiving events over HTTP
+///
+/// An empty list of event types means the subscriber receives all events.
+/// The secret is used to sign payloads and is never serialized back to
+/// clients.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+}
+
+impl WebhookSubscription {
+    /// Whether this subscriber wants to receive the given event
+    pub fn matches(&self, event: &Event) -> bool {
+        self.event_types.is_empty() || self.event_types.iter().any(|t| t == event.event_type())
+    }
+}
+
+/// A change to the catalog
+///
+/// Changes are ordered by their `sequence`, which is a zero-padded string so
+/// that lexicographic order matches the order in which changes happened.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    pub sequence: String,
+    pub event: Event,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChangeRange {
+    pub changes: Vec<Change>,
+    /// Token to pass as `since` to retrieve the following changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
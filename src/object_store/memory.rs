@@ -0,0 +1,39 @@
+//! # In-memory object store implementation
+//!
+//! This is a simple in-memory object store implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::ObjectStore;
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    data: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+        self.data.write().unwrap().insert(key.to_string(), body);
+        Ok(())
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.data
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or(Error::ClientError("Object not found"))
+    }
+}
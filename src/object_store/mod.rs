@@ -0,0 +1,18 @@
+//! # Object storage
+//!
+//! Object stores hold files such as catalog backups, addressed by key.
+
+use crate::Error;
+use async_trait::async_trait;
+
+mod memory;
+mod s3;
+
+pub use memory::MemoryObjectStore;
+pub use s3::S3ObjectStore;
+
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<(), Error>;
+    async fn get_object(&self, key: &str) -> Result<Vec<u8>, Error>;
+}
@@ -0,0 +1,60 @@
+//! # S3 object store implementation
+//!
+//! Object store implementation using the AWS SDK for S3.
+
+use super::ObjectStore;
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+use aws_smithy_http::byte_stream::ByteStream;
+use tracing::{info, instrument};
+
+/// S3 object store implementation.
+pub struct S3ObjectStore {
+    client: Client,
+    bucket: String,
+}
+
+impl S3ObjectStore {
+    pub fn new(client: Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    /// Upload an object
+    #[instrument(skip(self, body))]
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+        info!("Uploading object '{}' to S3 bucket", key);
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Download an object
+    #[instrument(skip(self))]
+    async fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        info!("Downloading object '{}' from S3 bucket", key);
+        let res = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+
+        let body = res
+            .body
+            .collect()
+            .await
+            .map_err(|_| Error::InternalError("Failed to read object body"))?;
+        Ok(body.into_bytes().to_vec())
+    }
+}
@@ -0,0 +1,206 @@
+//! # DynamoDB outbox implementation
+//!
+//! Each entry is an item keyed by its id in `id`, with the serialized event
+//! in `event` and the time of the write in `created_at`. Entries are written
+//! by `DynamoDBStore` in the same transaction as the product.
+
+use super::{Outbox, OutboxEntry};
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, DeleteRequest, WriteRequest},
+    Client,
+};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// Maximum number of items in a BatchWriteItem request
+const BATCH_WRITE_SIZE: usize = 25;
+
+/// Maximum number of BatchWriteItem attempts for a batch
+const MAX_ATTEMPTS: usize = 5;
+
+/// DynamoDB outbox implementation.
+pub struct DynamoDBOutbox {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBOutbox {
+    pub fn new(client: Client, table_name: String) -> DynamoDBOutbox {
+        DynamoDBOutbox { client, table_name }
+    }
+}
+
+/// Convert an entry into the item to write
+pub(crate) fn entry_to_item(entry: &OutboxEntry) -> Result<HashMap<String, AttributeValue>, Error> {
+    let event = serde_json::to_string(&entry.event)
+        .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+    Ok(HashMap::from([
+        ("id".to_owned(), AttributeValue::S(entry.id.clone())),
+        ("event".to_owned(), AttributeValue::S(event)),
+        (
+            "created_at".to_owned(),
+            AttributeValue::N(entry.created_at.to_string()),
+        ),
+    ]))
+}
+
+fn entry_from_item(item: &HashMap<String, AttributeValue>) -> Result<OutboxEntry, Error> {
+    let string = |name: &'static str| -> Result<&String, Error> {
+        item.get(name)
+            .and_then(|v| v.as_s().ok())
+            .ok_or(Error::InternalError(name))
+    };
+    Ok(OutboxEntry {
+        id: string("id")?.clone(),
+        event: serde_json::from_str(string("event")?)
+            .map_err(|_| Error::InternalError("Unable to deserialize event"))?,
+        created_at: item
+            .get("created_at")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or(Error::InternalError("created_at"))?,
+    })
+}
+
+#[async_trait]
+impl Outbox for DynamoDBOutbox {
+    /// Scan all the entries of the outbox
+    #[instrument(skip(self))]
+    async fn pending(&self) -> Result<Vec<OutboxEntry>, Error> {
+        info!("Scanning outbox entries in DynamoDB table");
+        let mut entries = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .consistent_read(true)
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            entries.extend(
+                res.items
+                    .unwrap_or_default()
+                    .iter()
+                    .map(entry_from_item)
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(entries)
+    }
+
+    /// Delete entries in batches, retrying unprocessed items
+    #[instrument(skip(self, entries))]
+    async fn remove(&self, entries: &[OutboxEntry]) -> Result<(), Error> {
+        info!(
+            "Deleting {} outbox entries from DynamoDB table",
+            entries.len()
+        );
+        for chunk in entries.chunks(BATCH_WRITE_SIZE) {
+            let mut requests: Vec<WriteRequest> = chunk
+                .iter()
+                .map(|entry| {
+                    WriteRequest::builder()
+                        .delete_request(
+                            DeleteRequest::builder()
+                                .key("id", AttributeValue::S(entry.id.clone()))
+                                .build(),
+                        )
+                        .build()
+                })
+                .collect();
+
+            let mut attempts = 0;
+            while !requests.is_empty() {
+                if attempts == MAX_ATTEMPTS {
+                    return Err(Error::InternalError("Failed to delete all outbox entries"));
+                }
+                attempts += 1;
+
+                let res = self
+                    .client
+                    .batch_write_item()
+                    .request_items(&self.table_name, requests)
+                    .send()
+                    .await?;
+                requests = res
+                    .unprocessed_items
+                    .and_then(|mut items| items.remove(&self.table_name))
+                    .unwrap_or_default();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{dynamodb_request_builder, mock_sdk_config},
+        Event, Product,
+    };
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_pending() -> Result<(), Error> {
+        // GIVEN an outbox table with one entry
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Items": [
+                        {"id": {"S": "1#100"}, "event": {"S": "{\"type\":\"Deleted\",\"product\":{\"id\":\"1\",\"name\":\"foo\",\"price\":10.0}}"}, "created_at": {"N": "100"}}
+                    ]}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let outbox = DynamoDBOutbox::new(client, "test".to_string());
+
+        // WHEN scanning the pending entries
+        let entries = outbox.pending().await?;
+
+        // THEN the entry is returned with its event
+        assert_eq!(
+            entries,
+            vec![OutboxEntry {
+                id: "1#100".to_string(),
+                event: Event::Deleted {
+                    product: Product {
+                        id: "1".to_string(),
+                        name: "foo".to_string(),
+                        price: 10.0,
+                        attributes: Default::default(),
+                        images: Default::default(),
+                    },
+                },
+                created_at: 100,
+            }]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+//! # In-memory outbox implementation
+//!
+//! Entries are pushed directly rather than written in a transaction, which
+//! is enough for local runs and tests.
+
+use super::{Outbox, OutboxEntry};
+use crate::Error;
+use async_trait::async_trait;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryOutbox {
+    entries: RwLock<Vec<OutboxEntry>>,
+}
+
+impl MemoryOutbox {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add an entry to the outbox
+    pub fn push(&self, entry: OutboxEntry) {
+        self.entries.write().unwrap().push(entry);
+    }
+}
+
+#[async_trait]
+impl Outbox for MemoryOutbox {
+    async fn pending(&self) -> Result<Vec<OutboxEntry>, Error> {
+        Ok(self.entries.read().unwrap().clone())
+    }
+
+    async fn remove(&self, entries: &[OutboxEntry]) -> Result<(), Error> {
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|entry| !entries.iter().any(|e| e.id == entry.id));
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! # Transactional outbox
+//!
+//! Without an outbox, events are published from the DynamoDB stream, or by
+//! the caller after the write. If the function stops between the write and
+//! the publication, the event is lost. With an outbox, the store writes an
+//! entry holding the event in the same transaction as the product, and a
+//! separate function drains the outbox to the event bus.
+//!
+//! Entries are only removed once their events were sent, so events are
+//! delivered at least once, in the order they were written.
+
+#[cfg(feature = "event-bus")]
+use crate::event_bus::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "event-bus")]
+use tracing::info;
+
+mod dynamodb;
+mod memory;
+
+pub(crate) use dynamodb::entry_to_item;
+pub use dynamodb::DynamoDBOutbox;
+pub use memory::MemoryOutbox;
+
+/// Number of entries sent to the event bus at once
+#[cfg(feature = "event-bus")]
+const DRAIN_BATCH_SIZE: usize = 10;
+
+/// Event waiting in the outbox
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OutboxEntry {
+    /// Id of the product and time of the write, e.g. `{id}#{created_at}`
+    pub id: String,
+    pub event: Event,
+    /// Time of the write, in microseconds since the UNIX epoch
+    pub created_at: u64,
+}
+
+impl OutboxEntry {
+    pub fn new(event: Event) -> Result<Self, Error> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_micros() as u64;
+        Ok(Self {
+            id: format!("{}#{}", event.id(), created_at),
+            event,
+            created_at,
+        })
+    }
+}
+
+/// Trait for reading and removing outbox entries
+#[async_trait]
+pub trait Outbox: Send + Sync {
+    /// All the entries waiting to be sent
+    async fn pending(&self) -> Result<Vec<OutboxEntry>, Error>;
+
+    /// Remove entries once their events were sent
+    async fn remove(&self, entries: &[OutboxEntry]) -> Result<(), Error>;
+}
+
+/// Send the events waiting in the outbox, oldest first
+///
+/// Entries are removed after each batch is sent and the bus flushed. If a
+/// batch fails, it stays in the outbox with the following ones and the error
+/// is returned. Returns the number of sent events.
+#[cfg(feature = "event-bus")]
+pub async fn drain(
+    outbox: &dyn Outbox,
+    event_bus: &dyn EventBus<E = Event>,
+) -> Result<usize, Error> {
+    let mut entries = outbox.pending().await?;
+    entries.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+    info!("Draining {} outbox entries", entries.len());
+
+    for batch in entries.chunks(DRAIN_BATCH_SIZE) {
+        let events: Vec<Event> = batch.iter().map(|entry| entry.event.clone()).collect();
+        event_bus.send_events(&events).await?;
+        event_bus.flush().await?;
+        outbox.remove(batch).await?;
+    }
+
+    Ok(entries.len())
+}
+
+#[cfg(all(test, feature = "event-bus"))]
+mod tests {
+    use super::*;
+    use crate::Product;
+    use std::sync::Mutex;
+
+    /// Bus recording the ids of the products of sent events
+    #[derive(Default)]
+    struct RecordingBus {
+        ids: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EventBus for RecordingBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            self.ids
+                .lock()
+                .unwrap()
+                .extend(events.iter().map(|e| e.id().to_string()));
+            Ok(())
+        }
+    }
+
+    fn get_entry(id: &str, created_at: u64) -> OutboxEntry {
+        OutboxEntry {
+            id: format!("{}#{}", id, created_at),
+            event: Event::Created {
+                product: Product {
+                    id: id.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            },
+            created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain() -> Result<(), Error> {
+        // GIVEN an outbox with entries written out of order
+        let outbox = MemoryOutbox::new();
+        outbox.push(get_entry("2", 200));
+        outbox.push(get_entry("1", 100));
+        let bus = RecordingBus::default();
+
+        // WHEN draining it
+        let count = drain(&outbox, &bus).await?;
+
+        // THEN the events are sent oldest first
+        assert_eq!(count, 2);
+        assert_eq!(*bus.ids.lock().unwrap(), vec!["1", "2"]);
+        // AND the outbox is empty
+        assert!(outbox.pending().await?.is_empty());
+
+        Ok(())
+    }
+}
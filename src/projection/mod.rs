@@ -0,0 +1,303 @@
+//! # Projections
+//!
+//! Projections are read models derived from the change feed. As the change
+//! feed keeps every event in sequence order, a projection can be rebuilt from
+//! scratch after a bug by clearing it and replaying the feed through its
+//! `EventHandler`.
+//!
+//! Replays report the sequence of the last processed change as a
+//! checkpoint. Passing it back as `since` resumes an interrupted replay
+//! without starting over. If a lag store is given, the checkpoint is also
+//! recorded there as the position of each projection, named
+//! `projection:{name}`.
+
+use crate::{
+    lag::{self, LagStore},
+    store::{BufferedStore, StoreDelete, StoreGetChanges, StorePut, StorePutMany, StoreScanAll},
+    Change, Error, Event,
+};
+use async_trait::async_trait;
+use tracing::info;
+
+/// Number of changes read from the change feed at once
+pub const REPLAY_BATCH_SIZE: usize = 100;
+
+/// Number of parallel segments used when clearing a product projection
+const RESET_SEGMENTS: usize = 4;
+
+/// Trait for read models built from events
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    /// Name of the projection, used in progress reports
+    fn name(&self) -> &str;
+
+    /// Remove all data from the projection
+    async fn reset(&self) -> Result<(), Error>;
+
+    /// Apply an event to the projection
+    ///
+    /// Events can be delivered more than once, so this must be idempotent.
+    async fn handle(&self, event: &Event) -> Result<(), Error>;
+
+    /// Write changes the projection buffered
+    ///
+    /// This is called before each checkpoint, so that a checkpoint never
+    /// covers changes that could still be lost.
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Progress of a replay
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayProgress {
+    /// Number of changes processed so far
+    pub processed: usize,
+    /// Sequence of the last processed change
+    pub checkpoint: Option<String>,
+}
+
+/// Replay the change feed through a set of handlers
+///
+/// Changes with a sequence strictly greater than `since` are passed to every
+/// handler in sequence order. `report` is called after each batch. If a
+/// handler fails, the error is returned and the last reported checkpoint can
+/// be used to resume the replay.
+pub async fn replay<F>(
+    changes: &dyn StoreGetChanges,
+    handlers: &[&dyn EventHandler],
+    lag: Option<&dyn LagStore>,
+    since: Option<String>,
+    mut report: F,
+) -> Result<ReplayProgress, Error>
+where
+    F: FnMut(&ReplayProgress),
+{
+    let mut progress = ReplayProgress {
+        processed: 0,
+        checkpoint: since,
+    };
+    loop {
+        let batch = changes
+            .since(progress.checkpoint.as_deref(), REPLAY_BATCH_SIZE)
+            .await?;
+        let last = match batch.last() {
+            Some(Change { sequence, .. }) => sequence.clone(),
+            None => break,
+        };
+
+        for change in &batch {
+            for handler in handlers {
+                handler.handle(&change.event).await?;
+            }
+        }
+
+        for handler in handlers {
+            handler.flush().await?;
+        }
+        if let Some(lag) = lag {
+            for handler in handlers {
+                let consumer = format!("projection:{}", handler.name());
+                lag::record_position(lag, &consumer, &last, None).await;
+            }
+        }
+        progress.processed += batch.len();
+        progress.checkpoint = Some(last);
+        report(&progress);
+
+        if batch.len() < REPLAY_BATCH_SIZE {
+            break;
+        }
+    }
+
+    info!("Replayed {} changes", progress.processed);
+    Ok(progress)
+}
+
+/// Rebuild a set of projections from the beginning of the change feed
+pub async fn rebuild<F>(
+    changes: &dyn StoreGetChanges,
+    handlers: &[&dyn EventHandler],
+    lag: Option<&dyn LagStore>,
+    report: F,
+) -> Result<ReplayProgress, Error>
+where
+    F: FnMut(&ReplayProgress),
+{
+    for handler in handlers {
+        info!("Resetting projection {}", handler.name());
+        handler.reset().await?;
+    }
+    replay(changes, handlers, lag, None, report).await
+}
+
+/// Projection keeping the latest version of each product in a store
+///
+/// This is used to maintain a replica of the catalog, e.g. in another
+/// region or account. Deleted products are removed from the replica.
+///
+/// Products are written in batches of up to 25 through a `BufferedStore`,
+/// which is flushed before each checkpoint.
+pub struct ProductProjection<S> {
+    store: BufferedStore<S>,
+}
+
+impl<S> ProductProjection<S>
+where
+    S: StorePutMany + StoreDelete + StoreScanAll,
+{
+    pub fn new(store: S) -> Self {
+        Self {
+            store: BufferedStore::new(store),
+        }
+    }
+
+    /// Buffer the projection writes go through
+    pub fn buffer(&self) -> &BufferedStore<S> {
+        &self.store
+    }
+}
+
+#[async_trait]
+impl<S> EventHandler for ProductProjection<S>
+where
+    S: StorePutMany + StoreDelete + StoreScanAll,
+{
+    fn name(&self) -> &str {
+        "products"
+    }
+
+    async fn reset(&self) -> Result<(), Error> {
+        for product in self.store.scan_all(RESET_SEGMENTS).await? {
+            self.store.hard_delete(&product.id).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, event: &Event) -> Result<(), Error> {
+        match event {
+            Event::Created { product } => self.store.put(product).await.map(|_| ()),
+            Event::Updated { new, .. } => self.store.put(new).await.map(|_| ()),
+            Event::Deleted { product } => self.store.hard_delete(&product.id).await.map(|_| ()),
+            // Already removed from the projection when it was deleted
+            Event::DeletionScheduled { .. } => Ok(()),
+            Event::Archived { product } => self.store.put(product).await.map(|_| ()),
+            Event::ArchiveCompleted { .. } => Ok(()),
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.store.flush().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lag::MemoryLagStore,
+        store::{MemoryChangeStore, MemoryStore, StoreAppendChanges},
+        Product,
+    };
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    async fn get_changes() -> Result<MemoryChangeStore, Error> {
+        let changes = MemoryChangeStore::new();
+        changes
+            .append(&[
+                Change {
+                    sequence: "001".to_string(),
+                    event: Event::Created {
+                        product: get_product("1", 10.0),
+                    },
+                },
+                Change {
+                    sequence: "002".to_string(),
+                    event: Event::Created {
+                        product: get_product("2", 20.0),
+                    },
+                },
+                Change {
+                    sequence: "003".to_string(),
+                    event: Event::Updated {
+                        old: get_product("1", 10.0),
+                        new: get_product("1", 15.0),
+                    },
+                },
+                Change {
+                    sequence: "004".to_string(),
+                    event: Event::Deleted {
+                        product: get_product("2", 20.0),
+                    },
+                },
+            ])
+            .await?;
+        Ok(changes)
+    }
+
+    #[tokio::test]
+    async fn test_rebuild() -> Result<(), Error> {
+        // GIVEN a change feed and a projection with outdated data
+        let changes = get_changes().await?;
+        let store = MemoryStore::new();
+        store.put(&get_product("3", 30.0)).await?;
+        let projection = ProductProjection::new(store);
+
+        // WHEN rebuilding the projection
+        let mut reports = Vec::new();
+        let progress = rebuild(&changes, &[&projection], None, |progress| {
+            reports.push(progress.clone())
+        })
+        .await?;
+
+        // THEN all changes are replayed
+        assert_eq!(progress.processed, 4);
+        assert_eq!(progress.checkpoint.as_deref(), Some("004"));
+        assert_eq!(reports, vec![progress]);
+        // AND the projection only contains the latest version of live products
+        let products = projection.store.scan_all(1).await?;
+        assert_eq!(products, vec![get_product("1", 15.0)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_since() -> Result<(), Error> {
+        // GIVEN a change feed and a projection checkpointed at "002"
+        let changes = get_changes().await?;
+        let projection = ProductProjection::new(MemoryStore::new());
+        let lag = MemoryLagStore::new();
+
+        // WHEN resuming the replay
+        let progress = replay(
+            &changes,
+            &[&projection],
+            Some(&lag),
+            Some("002".to_string()),
+            |_| {},
+        )
+        .await?;
+
+        // THEN only the following changes are replayed
+        assert_eq!(progress.processed, 2);
+        assert_eq!(progress.checkpoint.as_deref(), Some("004"));
+        let products = projection.store.scan_all(1).await?;
+        assert_eq!(products, vec![get_product("1", 15.0)]);
+        // AND the checkpoint is recorded as the position of the projection
+        let positions = lag.positions().await?;
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].consumer, "projection:products");
+        assert_eq!(positions[0].sequence, "004");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+//! # Product recommendations
+//!
+//! Recommendations return the ids of products related to a given product.
+//! The ids are hydrated into products by the domain layer.
+
+use crate::Error;
+use async_trait::async_trait;
+
+mod personalize;
+mod static_categories;
+
+pub use personalize::PersonalizeRecommendations;
+pub use static_categories::StaticRecommendations;
+
+/// Trait for retrieving related products
+///
+/// Ids are returned from the most to the least relevant, and never include
+/// the product itself.
+#[async_trait]
+pub trait Recommendations: Send + Sync {
+    async fn related(&self, id: &str, limit: usize) -> Result<Vec<String>, Error>;
+}
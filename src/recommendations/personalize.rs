@@ -0,0 +1,88 @@
+//! # Amazon Personalize recommendations
+//!
+//! Recommendations implementation using the AWS SDK for Personalize Runtime,
+//! backed by a campaign trained with a related items recipe.
+
+use super::Recommendations;
+use crate::Error;
+use async_trait::async_trait;
+use aws_sdk_personalizeruntime::Client;
+use tracing::{info, instrument};
+
+/// Amazon Personalize recommendations implementation.
+pub struct PersonalizeRecommendations {
+    client: Client,
+    campaign_arn: String,
+}
+
+impl PersonalizeRecommendations {
+    pub fn new(client: Client, campaign_arn: String) -> Self {
+        Self {
+            client,
+            campaign_arn,
+        }
+    }
+}
+
+#[async_trait]
+impl Recommendations for PersonalizeRecommendations {
+    /// Get related items from the campaign
+    #[instrument(skip(self))]
+    async fn related(&self, id: &str, limit: usize) -> Result<Vec<String>, Error> {
+        info!("Getting recommendations from Personalize");
+        let res = self
+            .client
+            .get_recommendations()
+            .campaign_arn(&self.campaign_arn)
+            .item_id(id)
+            .num_results(limit as i32)
+            .send()
+            .await?;
+
+        Ok(res
+            .item_list
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|item| item.item_id)
+            .filter(|item_id| item_id != id)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mock_sdk_config, request_builder};
+    use aws_sdk_personalizeruntime::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_related() -> Result<(), Error> {
+        // GIVEN a campaign recommending two items
+        let conn = TestConnection::new(vec![(
+            request_builder("https://personalize-runtime.eu-west-1.amazonaws.com/recommendations")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"itemList": [{"itemId": "2"}, {"itemId": "3"}]}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let recommendations = PersonalizeRecommendations::new(client, "arn:campaign".to_string());
+
+        // WHEN getting related items
+        let related = recommendations.related("1", 10).await?;
+
+        // THEN the recommended item ids are returned
+        assert_eq!(related, vec!["2".to_string(), "3".to_string()]);
+
+        Ok(())
+    }
+}
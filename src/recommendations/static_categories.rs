@@ -0,0 +1,86 @@
+//! # Static recommendations
+//!
+//! Recommendations based on a static list of categories: products are
+//! related when they share a category. This is intended as a fallback for
+//! local use, when no Personalize campaign is available.
+
+use super::Recommendations;
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct StaticRecommendations {
+    categories: BTreeMap<String, Vec<String>>,
+}
+
+impl StaticRecommendations {
+    /// Create recommendations from a map of categories to product ids
+    pub fn new(categories: BTreeMap<String, Vec<String>>) -> Self {
+        Self { categories }
+    }
+}
+
+#[async_trait]
+impl Recommendations for StaticRecommendations {
+    async fn related(&self, id: &str, limit: usize) -> Result<Vec<String>, Error> {
+        let mut related: Vec<String> = Vec::new();
+        for ids in self.categories.values() {
+            if !ids.iter().any(|i| i == id) {
+                continue;
+            }
+            for other in ids {
+                if other != id && !related.contains(other) {
+                    related.push(other.clone());
+                }
+            }
+        }
+        related.truncate(limit);
+
+        Ok(related)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_recommendations() -> StaticRecommendations {
+        StaticRecommendations::new(BTreeMap::from([
+            (
+                "books".to_string(),
+                vec!["1".to_string(), "2".to_string(), "3".to_string()],
+            ),
+            ("games".to_string(), vec!["1".to_string(), "4".to_string()]),
+            ("music".to_string(), vec!["5".to_string()]),
+        ]))
+    }
+
+    #[tokio::test]
+    async fn test_related() -> Result<(), Error> {
+        // GIVEN a product in two categories
+        let recommendations = get_recommendations();
+
+        // WHEN getting related products
+        let related = recommendations.related("1", 10).await?;
+
+        // THEN we get the products from both categories
+        assert_eq!(related, vec!["2", "3", "4"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_related_limit() -> Result<(), Error> {
+        // GIVEN a product in two categories
+        let recommendations = get_recommendations();
+
+        // WHEN getting a single related product
+        let related = recommendations.related("1", 1).await?;
+
+        // THEN we only get the first one
+        assert_eq!(related, vec!["2"]);
+
+        Ok(())
+    }
+}
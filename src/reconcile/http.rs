@@ -0,0 +1,53 @@
+//! # HTTP catalog source
+//!
+//! Reads the source catalog from an HTTP endpoint answering
+//! `GET {endpoint}?limit={limit}&next={next}` with a page of products, in
+//! the same format as `GET /` on this API: `{"products": [...], "next": "..."}`.
+
+use super::{CatalogSource, PAGE_SIZE};
+use crate::{Error, ProductRange};
+use async_trait::async_trait;
+use reqwest::Client;
+use tracing::{error, instrument};
+
+/// HTTP catalog source implementation.
+pub struct HttpCatalogSource {
+    client: Client,
+    endpoint: String,
+}
+
+impl HttpCatalogSource {
+    pub fn new(client: Client, endpoint: String) -> Self {
+        Self { client, endpoint }
+    }
+}
+
+#[async_trait]
+impl CatalogSource for HttpCatalogSource {
+    #[instrument(skip(self))]
+    async fn page(&self, next: Option<&str>) -> Result<ProductRange, Error> {
+        let mut query = vec![("limit", PAGE_SIZE.to_string())];
+        if let Some(next) = next {
+            query.push(("next", next.to_string()));
+        }
+
+        let res = self
+            .client
+            .get(&self.endpoint)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling catalog source: {}", err);
+                Error::InternalError("Unable to reach catalog source")
+            })?;
+        if !res.status().is_success() {
+            error!("Catalog source returned status {}", res.status());
+            return Err(Error::InternalError("Catalog source returned an error"));
+        }
+
+        res.json::<ProductRange>()
+            .await
+            .map_err(|_| Error::InternalError("Invalid response from catalog source"))
+    }
+}
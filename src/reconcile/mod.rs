@@ -0,0 +1,399 @@
+//! # Catalog reconciliation
+//!
+//! While this service shadows an existing catalog, the existing catalog is
+//! the source of truth. Reconciliation compares the products in the store
+//! with the products of a `CatalogSource`, such as an HTTP endpoint or an S3
+//! export, and reports every discrepancy:
+//!
+//! * products missing from the store,
+//! * products missing from the source,
+//! * products whose fields differ, with the value on each side.
+//!
+//! Both sides are read one page at a time: each page of the source is
+//! compared with the same products fetched by id from the store, then the
+//! store is listed to find products the source doesn't have. Only the ids of
+//! the source products are kept in memory between pages.
+//!
+//! Optionally, the job emits the events that would bring consumers in line
+//! with the source: `Created` for missing products, `Updated` for different
+//! ones and `Deleted` for products the source doesn't have.
+
+use crate::{
+    event_bus::EventBus,
+    object_store::ObjectStore,
+    store::{ProductFilter, StoreBatchGet, StoreGetAll},
+    Error, Event, Product, ProductRange,
+};
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tracing::info;
+
+#[cfg(feature = "http")]
+mod http;
+mod object;
+
+#[cfg(feature = "http")]
+pub use http::HttpCatalogSource;
+pub use object::ObjectCatalogSource;
+
+/// Number of products read from the store at once
+pub const PAGE_SIZE: usize = 100;
+
+/// Trait for reading the catalog reconciliation compares against
+///
+/// `next` is the token returned with the previous page, and sources return
+/// `None` as the next token once all products were returned.
+#[async_trait]
+pub trait CatalogSource: Send + Sync {
+    async fn page(&self, next: Option<&str>) -> Result<ProductRange, Error>;
+}
+
+/// Difference between the store and the source for a single field
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldDiff {
+    /// Name of the field, or `attributes.{key}` for custom attributes
+    pub field: String,
+    pub store: Value,
+    pub source: Value,
+}
+
+/// Discrepancy found for a product, as written in the report
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    MissingInStore { id: String },
+    MissingInSource { id: String },
+    Mismatch { id: String, fields: Vec<FieldDiff> },
+}
+
+/// Summary of a reconciliation
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReconcileSummary {
+    /// Number of products read from the source
+    pub checked: usize,
+    pub missing_in_store: usize,
+    pub missing_in_source: usize,
+    pub mismatched: usize,
+    /// Number of corrective events sent
+    pub events: usize,
+}
+
+impl ReconcileSummary {
+    /// Total number of discrepancies
+    pub fn discrepancies(&self) -> usize {
+        self.missing_in_store + self.missing_in_source + self.mismatched
+    }
+}
+
+/// Compare the fields of a product in the store and in the source
+///
+/// Custom attributes are compared one by one, so that the report shows which
+/// attributes differ rather than the whole map.
+pub fn diff_products(store: &Product, source: &Product) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+    if store.name != source.name {
+        diffs.push(field_diff("name", &store.name, &source.name));
+    }
+    if store.price != source.price {
+        diffs.push(field_diff("price", &store.price, &source.price));
+    }
+    if store.images != source.images {
+        diffs.push(field_diff("images", &store.images, &source.images));
+    }
+
+    let keys: BTreeSet<&String> = store
+        .attributes
+        .keys()
+        .chain(source.attributes.keys())
+        .collect();
+    for key in keys {
+        let (a, b) = (store.attributes.get(key), source.attributes.get(key));
+        if a != b {
+            diffs.push(FieldDiff {
+                field: format!("attributes.{}", key),
+                store: a.cloned().unwrap_or(Value::Null),
+                source: b.cloned().unwrap_or(Value::Null),
+            });
+        }
+    }
+
+    diffs
+}
+
+fn field_diff<T: Serialize>(field: &str, store: &T, source: &T) -> FieldDiff {
+    FieldDiff {
+        field: field.to_string(),
+        store: serde_json::to_value(store).unwrap_or(Value::Null),
+        source: serde_json::to_value(source).unwrap_or(Value::Null),
+    }
+}
+
+/// Discrepancy report, written as JSON lines
+struct Report {
+    summary: ReconcileSummary,
+    body: Vec<u8>,
+    events: Vec<Event>,
+}
+
+impl Report {
+    fn add(&mut self, discrepancy: Discrepancy, event: Event) -> Result<(), Error> {
+        match discrepancy {
+            Discrepancy::MissingInStore { .. } => self.summary.missing_in_store += 1,
+            Discrepancy::MissingInSource { .. } => self.summary.missing_in_source += 1,
+            Discrepancy::Mismatch { .. } => self.summary.mismatched += 1,
+        }
+        serde_json::to_writer(&mut self.body, &discrepancy)
+            .map_err(|_| Error::InternalError("Unable to serialize discrepancy"))?;
+        self.body.push(b'\n');
+        self.events.push(event);
+        Ok(())
+    }
+
+    /// Send the corrective events collected for the current page
+    async fn flush(&mut self, event_bus: Option<&dyn EventBus<E = Event>>) -> Result<(), Error> {
+        let events = std::mem::take(&mut self.events);
+        if let Some(event_bus) = event_bus {
+            if !events.is_empty() {
+                event_bus.send_events(&events).await?;
+                self.summary.events += events.len();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare the store with a source of truth
+///
+/// The discrepancies are written as JSON lines to `report_key`. If an event
+/// bus is given, corrective events are sent after each page.
+pub async fn reconcile(
+    list: &dyn StoreGetAll,
+    batch_get: &dyn StoreBatchGet,
+    source: &dyn CatalogSource,
+    objects: &dyn ObjectStore,
+    report_key: &str,
+    event_bus: Option<&dyn EventBus<E = Event>>,
+) -> Result<ReconcileSummary, Error> {
+    let mut report = Report {
+        summary: ReconcileSummary::default(),
+        body: Vec::new(),
+        events: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+
+    // Compare each page of the source with the store
+    let mut next: Option<String> = None;
+    loop {
+        let page = source.page(next.as_deref()).await?;
+        let ids: Vec<String> = page.products.iter().map(|p| p.id.clone()).collect();
+        let mut existing: HashMap<String, Product> = if ids.is_empty() {
+            HashMap::new()
+        } else {
+            batch_get
+                .get_many(&ids)
+                .await?
+                .into_iter()
+                .map(|p| (p.id.clone(), p))
+                .collect()
+        };
+
+        for product in page.products {
+            report.summary.checked += 1;
+            seen.insert(product.id.clone());
+            match existing.remove(&product.id) {
+                None => report.add(
+                    Discrepancy::MissingInStore {
+                        id: product.id.clone(),
+                    },
+                    Event::Created { product },
+                )?,
+                Some(old) => {
+                    let fields = diff_products(&old, &product);
+                    if !fields.is_empty() {
+                        report.add(
+                            Discrepancy::Mismatch {
+                                id: product.id.clone(),
+                                fields,
+                            },
+                            Event::Updated { old, new: product },
+                        )?;
+                    }
+                }
+            }
+        }
+        report.flush(event_bus).await?;
+        info!(
+            "Compared {} products from the source",
+            report.summary.checked
+        );
+
+        next = page.next;
+        if next.is_none() {
+            break;
+        }
+    }
+
+    // Find products the source doesn't have
+    let filter = ProductFilter::default();
+    let mut next: Option<String> = None;
+    loop {
+        let page = list.all(next.as_deref(), PAGE_SIZE, &filter, None).await?;
+        for product in page.products {
+            if !seen.contains(&product.id) {
+                report.add(
+                    Discrepancy::MissingInSource {
+                        id: product.id.clone(),
+                    },
+                    Event::Deleted { product },
+                )?;
+            }
+        }
+        report.flush(event_bus).await?;
+
+        next = page.next;
+        if next.is_none() {
+            break;
+        }
+    }
+
+    if let Some(event_bus) = event_bus {
+        event_bus.flush().await?;
+    }
+    objects.put_object(report_key, report.body).await?;
+    info!(
+        "Found {} discrepancies, report written to {}",
+        report.summary.discrepancies(),
+        report_key
+    );
+
+    Ok(report.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_bus::{FileBus, FileEnvelope},
+        object_store::MemoryObjectStore,
+        store::{MemoryStore, StorePut},
+    };
+    use serde_json::json;
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: [("color".to_string(), json!("red"))].into_iter().collect(),
+            images: Default::default(),
+        }
+    }
+
+    /// Source returning one product per page
+    struct VecSource(Vec<Product>);
+
+    #[async_trait]
+    impl CatalogSource for VecSource {
+        async fn page(&self, next: Option<&str>) -> Result<ProductRange, Error> {
+            let index: usize = next.map_or(0, |next| next.parse().unwrap());
+            Ok(ProductRange {
+                products: self.0.get(index).cloned().into_iter().collect(),
+                next: (index + 1 < self.0.len()).then(|| (index + 1).to_string()),
+            })
+        }
+    }
+
+    #[test]
+    fn test_diff_products() {
+        // GIVEN two versions of a product
+        let store = get_product("1", 10.0);
+        let mut source = get_product("1", 12.0);
+        source.attributes.insert("color".to_string(), json!("blue"));
+        source.attributes.insert("size".to_string(), json!(42));
+
+        // WHEN comparing them
+        let diffs = diff_products(&store, &source);
+
+        // THEN each different field is reported
+        assert_eq!(
+            diffs,
+            vec![
+                field_diff("price", &10.0, &12.0),
+                field_diff("attributes.color", &json!("red"), &json!("blue")),
+                field_diff("attributes.size", &Value::Null, &json!(42)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() -> Result<(), Error> {
+        // GIVEN a store and a source that disagree
+        let store = MemoryStore::new();
+        store.put(&get_product("1", 10.0)).await?;
+        store.put(&get_product("2", 10.0)).await?;
+        store.put(&get_product("3", 10.0)).await?;
+        let source = VecSource(vec![
+            get_product("1", 10.0),
+            get_product("2", 12.0),
+            get_product("4", 10.0),
+        ]);
+        let objects = MemoryObjectStore::new();
+        let path = std::env::temp_dir().join(format!("reconcile-{}.ndjson", std::process::id()));
+        let bus = FileBus::new(path.clone());
+
+        // WHEN reconciling them
+        let summary = reconcile(
+            &store,
+            &store,
+            &source,
+            &objects,
+            "report.jsonl",
+            Some(&bus),
+        )
+        .await?;
+
+        // THEN every discrepancy is counted
+        assert_eq!(
+            summary,
+            ReconcileSummary {
+                checked: 3,
+                missing_in_store: 1,
+                missing_in_source: 1,
+                mismatched: 1,
+                events: 3,
+            }
+        );
+        // AND written to the report
+        let body = objects.get_object("report.jsonl").await?;
+        let lines: Vec<Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                json!({"kind": "mismatch", "id": "2", "fields": [
+                    {"field": "price", "store": 10.0, "source": 12.0}
+                ]}),
+                json!({"kind": "missing_in_store", "id": "4"}),
+                json!({"kind": "missing_in_source", "id": "3"}),
+            ]
+        );
+        // AND corrective events are sent
+        let events: Vec<&str> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let envelope: FileEnvelope = serde_json::from_str(line).unwrap();
+                envelope.detail.event.event_type()
+            })
+            .collect();
+        assert_eq!(events, vec!["Updated", "Created", "Deleted"]);
+
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! # S3 catalog source
+//!
+//! Reads the source catalog from a JSON lines export in an object store, one
+//! product per line, as written by the `export` action of the backup
+//! function. The export is downloaded once and parsed one page at a time;
+//! the next token is the byte offset of the next line.
+
+use super::{CatalogSource, PAGE_SIZE};
+use crate::{object_store::ObjectStore, Error, Product, ProductRange};
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+use tracing::instrument;
+
+/// S3 catalog source implementation.
+pub struct ObjectCatalogSource<O> {
+    objects: O,
+    key: String,
+    body: OnceCell<Vec<u8>>,
+}
+
+impl<O: ObjectStore> ObjectCatalogSource<O> {
+    pub fn new(objects: O, key: String) -> Self {
+        Self {
+            objects,
+            key,
+            body: OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl<O: ObjectStore> CatalogSource for ObjectCatalogSource<O> {
+    #[instrument(skip(self))]
+    async fn page(&self, next: Option<&str>) -> Result<ProductRange, Error> {
+        let body = self
+            .body
+            .get_or_try_init(|| self.objects.get_object(&self.key))
+            .await?;
+        let mut offset = match next {
+            Some(next) => next
+                .parse::<usize>()
+                .ok()
+                .filter(|offset| *offset <= body.len())
+                .ok_or(Error::ClientError("Invalid next token"))?,
+            None => 0,
+        };
+
+        let mut products = Vec::new();
+        while offset < body.len() && products.len() < PAGE_SIZE {
+            let end = body[offset..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(body.len(), |pos| offset + pos);
+            let line = &body[offset..end];
+            offset = end + 1;
+
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            products.push(
+                serde_json::from_slice::<Product>(line)
+                    .map_err(|_| Error::ClientError("Invalid product in catalog export"))?,
+            );
+        }
+
+        Ok(ProductRange {
+            products,
+            next: (offset < body.len()).then(|| offset.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryObjectStore;
+
+    #[tokio::test]
+    async fn test_page() -> Result<(), Error> {
+        // GIVEN an export with more products than fit in a page
+        let objects = MemoryObjectStore::new();
+        let mut body = Vec::new();
+        for i in 0..PAGE_SIZE + 1 {
+            body.extend_from_slice(
+                format!(r#"{{"id": "{}", "name": "foo", "price": 10.0}}"#, i).as_bytes(),
+            );
+            body.push(b'\n');
+        }
+        objects.put_object("products.jsonl", body).await?;
+        let source = ObjectCatalogSource::new(objects, "products.jsonl".to_string());
+
+        // WHEN reading all pages
+        let first = source.page(None).await?;
+        let second = source.page(first.next.as_deref()).await?;
+
+        // THEN the products are split in two pages
+        assert_eq!(first.products.len(), PAGE_SIZE);
+        assert_eq!(second.products.len(), 1);
+        assert_eq!(second.products[0].id, PAGE_SIZE.to_string());
+        assert_eq!(second.next, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! # Event replay
+//!
+//! Downstream read models, such as a search index or a data lake, can be
+//! rebuilt by publishing past events again. Events are derived from the
+//! history store, which keeps every revision of every product with its write
+//! time as the version: a revision is a `Created` event if it is the first
+//! one of its product, and an `Updated` event from the previous revision
+//! otherwise.
+//!
+//! Deletions are not recorded in the history, so they are not replayed.
+//! Events are published oldest first, so consumers see the revisions of a
+//! product in order.
+
+use crate::{
+    event_bus::EventBus,
+    store::{StoreGetHistory, StoreScanHistory},
+    Error, Event, ProductRevision,
+};
+use std::collections::HashMap;
+use tracing::info;
+
+/// Number of events published at once
+const BATCH_SIZE: usize = 10;
+
+/// Events of the revisions written in `[from, to)`, oldest first
+///
+/// Each event comes with the version of its revision. The full history of
+/// the products found in the range is read, to find the revision preceding
+/// the first one of each product.
+pub async fn events_between(
+    scan: &dyn StoreScanHistory,
+    history: &dyn StoreGetHistory,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(u64, Event)>, Error> {
+    let mut by_product: HashMap<String, Vec<ProductRevision>> = HashMap::new();
+    for revision in scan.revisions_between(from, to).await? {
+        by_product
+            .entry(revision.product.id.clone())
+            .or_default()
+            .push(revision);
+    }
+    info!(
+        "Found revisions of {} products between {} and {}",
+        by_product.len(),
+        from,
+        to
+    );
+
+    let mut events = Vec::new();
+    for (id, mut revisions) in by_product {
+        revisions.sort_by_key(|revision| revision.version);
+        let first = revisions[0].version;
+        // History is returned newest first
+        let mut previous = history
+            .history(&id)
+            .await?
+            .into_iter()
+            .find(|revision| revision.version < first)
+            .map(|revision| revision.product);
+
+        for revision in revisions {
+            let event = match previous.replace(revision.product.clone()) {
+                Some(old) => Event::Updated {
+                    old,
+                    new: revision.product,
+                },
+                None => Event::Created {
+                    product: revision.product,
+                },
+            };
+            events.push((revision.version, event));
+        }
+    }
+
+    events.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.id().cmp(b.1.id())));
+    Ok(events)
+}
+
+/// Publish the events of the revisions written in `[from, to)` again
+///
+/// `report` is called after each batch with the number of published events
+/// and the version of the last one. Returns the number of published events.
+pub async fn replay<F>(
+    scan: &dyn StoreScanHistory,
+    history: &dyn StoreGetHistory,
+    event_bus: &dyn EventBus<E = Event>,
+    from: u64,
+    to: u64,
+    mut report: F,
+) -> Result<usize, Error>
+where
+    F: FnMut(usize, u64),
+{
+    let events = events_between(scan, history, from, to).await?;
+    info!("Replaying {} events", events.len());
+
+    let mut published = 0;
+    for batch in events.chunks(BATCH_SIZE) {
+        let (versions, events): (Vec<u64>, Vec<Event>) = batch.iter().cloned().unzip();
+        event_bus.send_events(&events).await?;
+        published += events.len();
+        report(published, versions[versions.len() - 1]);
+    }
+    event_bus.flush().await?;
+
+    Ok(published)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::{MemoryHistoryStore, StoreAppendHistory},
+        Product,
+    };
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Bus recording sent events
+    #[derive(Default)]
+    struct RecordingBus {
+        events: Mutex<Vec<Event>>,
+    }
+
+    #[async_trait]
+    impl EventBus for RecordingBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            self.events.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    fn get_revision(id: &str, version: u64) -> ProductRevision {
+        ProductRevision {
+            version,
+            product: Product {
+                id: id.to_string(),
+                name: "foo".to_string(),
+                price: version as f64,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay() -> Result<(), Error> {
+        // GIVEN a history with revisions before and in the range
+        let history = MemoryHistoryStore::new();
+        history.append(&get_revision("1", 100)).await?;
+        history.append(&get_revision("1", 200)).await?;
+        history.append(&get_revision("2", 150)).await?;
+        history.append(&get_revision("2", 300)).await?;
+        let bus = RecordingBus::default();
+
+        // WHEN replaying the range [150, 300)
+        let count = replay(&history, &history, &bus, 150, 300, |_, _| {}).await?;
+
+        // THEN the revisions in the range are published oldest first
+        assert_eq!(count, 2);
+        let events = bus.events.lock().unwrap();
+        assert_eq!(
+            events[0],
+            Event::Created {
+                product: get_revision("2", 150).product
+            }
+        );
+        // AND updates hold the revision before the range
+        assert_eq!(
+            events[1],
+            Event::Updated {
+                old: get_revision("1", 100).product,
+                new: get_revision("1", 200).product,
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! # Event rules
+//!
+//! Operators can change how events are published through configuration,
+//! without a new release: each rule has a condition on the fields of an
+//! event, and an action deciding whether the event is published, to which
+//! bus, and with which tags in its metadata.
+//!
+//! Rules are a JSON array, evaluated in order, and the first rule whose
+//! condition matches an event applies to it:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "deals",
+//!     "when": {"all": [
+//!       {"field": "type", "op": "eq", "value": "Updated"},
+//!       {"field": "new.price", "op": "lt", "value": 10}
+//!     ]},
+//!     "action": "publish",
+//!     "target": "deals-bus",
+//!     "tags": {"team": "pricing"}
+//!   },
+//!   {
+//!     "name": "no-scheduled-deletions",
+//!     "when": {"field": "type", "op": "eq", "value": "DeletionScheduled"},
+//!     "action": "suppress"
+//!   }
+//! ]
+//! ```
+//!
+//! Fields are paths in the serialized event, with dots between names, such
+//! as `product.attributes.color`. Conditions can be combined with `all`,
+//! `any` and `not`. Events no rule matches are published as usual.
+//!
+//! Routing tables are a shorter way to send event types to targets, such as
+//! `Deleted=sqs:https://sqs.eu-west-1.amazonaws.com/123456789012/deletes.fifo;Created,Updated=products`:
+//! each route becomes a rule publishing these types to its target.
+//!
+//! `sqs:` targets are published to with `SqsFifoBus`, so their queue must be
+//! a FIFO queue, with a URL ending in `.fifo`. Other queues are rejected when
+//! the rules are parsed.
+
+use crate::{Error, Event};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::error;
+
+/// Comparison of a field with a value
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// The field is one of the values of an array
+    In,
+    /// The field is present if the value is `true`, absent otherwise
+    Exists,
+}
+
+/// Condition on the fields of an event
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Condition {
+    All {
+        all: Vec<Condition>,
+    },
+    Any {
+        any: Vec<Condition>,
+    },
+    Not {
+        not: Box<Condition>,
+    },
+    Compare {
+        field: String,
+        op: Operator,
+        #[serde(default)]
+        value: Value,
+    },
+}
+
+impl Condition {
+    /// Whether the serialized event matches this condition
+    pub fn matches(&self, event: &Value) -> bool {
+        match self {
+            Condition::All { all } => all.iter().all(|c| c.matches(event)),
+            Condition::Any { any } => any.iter().any(|c| c.matches(event)),
+            Condition::Not { not } => !not.matches(event),
+            Condition::Compare { field, op, value } => {
+                let pointer = format!("/{}", field.replace('.', "/"));
+                compare(event.pointer(&pointer), *op, value)
+            }
+        }
+    }
+}
+
+fn compare(field: Option<&Value>, op: Operator, value: &Value) -> bool {
+    let field = match (field, op) {
+        (field, Operator::Exists) => return field.is_some() == value.as_bool().unwrap_or(true),
+        (None, Operator::Ne) => return true,
+        (None, _) => return false,
+        (Some(field), _) => field,
+    };
+    let ordering = || match (field.as_f64(), value.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        _ => match (field.as_str(), value.as_str()) {
+            (Some(a), Some(b)) => Some(a.cmp(b)),
+            _ => None,
+        },
+    };
+    match op {
+        Operator::Eq => field == value || ordering() == Some(std::cmp::Ordering::Equal),
+        Operator::Ne => field != value && ordering() != Some(std::cmp::Ordering::Equal),
+        Operator::Gt => ordering().map_or(false, |o| o.is_gt()),
+        Operator::Gte => ordering().map_or(false, |o| o.is_ge()),
+        Operator::Lt => ordering().map_or(false, |o| o.is_lt()),
+        Operator::Lte => ordering().map_or(false, |o| o.is_le()),
+        Operator::In => value
+            .as_array()
+            .map_or(false, |values| values.contains(field)),
+        Operator::Exists => unreachable!(),
+    }
+}
+
+/// What happens to the events a rule matches
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    /// Publish the event, to the named bus instead of the default one if
+    /// `target` is set, with `tags` added to its metadata
+    Publish {
+        #[serde(default)]
+        target: Option<String>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    },
+    /// Don't publish the event
+    Suppress,
+}
+
+/// Condition and action
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    #[serde(rename = "when")]
+    pub condition: Condition,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Ordered list of rules
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    /// Parse rules from a JSON array
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let rules = serde_json::from_str(data).map_err(|err| {
+            error!("Invalid event rules: {}", err);
+            Error::InitError("Invalid event rules")
+        })?;
+        let rules = Self::new(rules);
+        for target in rules.targets() {
+            validate_target(target)?;
+        }
+        Ok(rules)
+    }
+
+    /// Add rules for the routes of a routing table, after the existing ones
+    ///
+    /// Routes are separated by `;`, and each route sends the event types
+    /// before `=`, separated by `,`, to the target after it.
+    pub fn with_routes(mut self, table: &str) -> Result<Self, Error> {
+        for route in table.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            let (event_types, target) = route.split_once('=').ok_or_else(|| {
+                error!("Invalid event route: {}", route);
+                Error::InitError("Invalid event routes")
+            })?;
+            let event_types: Vec<Value> = event_types
+                .split(',')
+                .map(|t| Value::String(t.trim().to_string()))
+                .collect();
+            let target = target.trim();
+            if target.is_empty() {
+                error!("Invalid event route: {}", route);
+                return Err(Error::InitError("Invalid event routes"));
+            }
+            validate_target(target)?;
+            self.rules.push(Rule {
+                name: format!("route-{}", target),
+                condition: Condition::Compare {
+                    field: "type".to_string(),
+                    op: Operator::In,
+                    value: Value::Array(event_types),
+                },
+                action: Action::Publish {
+                    target: Some(target.to_string()),
+                    tags: BTreeMap::new(),
+                },
+            });
+        }
+        Ok(self)
+    }
+
+    /// Rule applying to an event, if any
+    pub fn evaluate(&self, event: &Event) -> Option<&Rule> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let event = serde_json::to_value(event).ok()?;
+        self.rules
+            .iter()
+            .find(|rule| rule.condition.matches(&event))
+    }
+
+    /// Names of the buses rules route events to
+    pub fn targets(&self) -> Vec<&str> {
+        let mut targets: Vec<&str> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.action {
+                Action::Publish {
+                    target: Some(target),
+                    ..
+                } => Some(target.as_str()),
+                _ => None,
+            })
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+}
+
+/// Check that events can be published to a target
+///
+/// SQS FIFO buses set a message group on every message, which standard
+/// queues reject.
+fn validate_target(target: &str) -> Result<(), Error> {
+    match target.strip_prefix("sqs:") {
+        Some(queue_url) if !queue_url.ends_with(".fifo") => {
+            error!("SQS target is not a FIFO queue: {}", target);
+            Err(Error::InitError("SQS targets must be FIFO queues"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+
+    fn get_update(name: &str, old_price: f64, new_price: f64) -> Event {
+        let product = |price| Product {
+            id: "1".to_string(),
+            name: name.to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+        Event::Updated {
+            old: product(old_price),
+            new: product(new_price),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        // GIVEN rules routing cheap updates and suppressing test products
+        let rules = Rules::parse(
+            r#"[
+                {
+                    "name": "test-products",
+                    "when": {"field": "new.name", "op": "in", "value": ["test", "demo"]},
+                    "action": "suppress"
+                },
+                {
+                    "name": "deals",
+                    "when": {"all": [
+                        {"field": "type", "op": "eq", "value": "Updated"},
+                        {"not": {"field": "new.price", "op": "gte", "value": 10}}
+                    ]},
+                    "action": "publish",
+                    "target": "deals-bus",
+                    "tags": {"team": "pricing"}
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        // THEN the first matching rule applies
+        let rule = rules.evaluate(&get_update("test", 20.0, 5.0)).unwrap();
+        assert_eq!(rule.name, "test-products");
+        assert_eq!(rule.action, Action::Suppress);
+        let rule = rules.evaluate(&get_update("foo", 20.0, 5.0)).unwrap();
+        assert_eq!(rule.name, "deals");
+        // AND events no rule matches are left alone
+        assert_eq!(rules.evaluate(&get_update("foo", 5.0, 20.0)), None);
+        assert_eq!(rules.targets(), vec!["deals-bus"]);
+    }
+
+    #[test]
+    fn test_with_routes() {
+        // GIVEN a routing table sending updates and deletions to other targets
+        let rules = Rules::default()
+            .with_routes(
+                "Deleted=sqs:https://sqs.example.com/deletes.fifo; Created,Updated=products",
+            )
+            .unwrap();
+
+        // THEN each event type is routed to its target
+        let rule = rules.evaluate(&get_update("foo", 5.0, 20.0)).unwrap();
+        assert_eq!(
+            rule.action,
+            Action::Publish {
+                target: Some("products".to_string()),
+                tags: BTreeMap::new(),
+            }
+        );
+        assert_eq!(
+            rules.targets(),
+            vec!["products", "sqs:https://sqs.example.com/deletes.fifo"]
+        );
+        // AND invalid routes are rejected
+        assert!(Rules::default().with_routes("Deleted").is_err());
+        assert!(Rules::default().with_routes("Deleted=").is_err());
+    }
+
+    #[test]
+    fn test_standard_queue_target() {
+        // GIVEN routes and rules sending events to a standard SQS queue
+        let routes = "Deleted=sqs:https://sqs.example.com/deletes";
+        let data = r#"[{"name": "deletes", "when": {"field": "type", "op": "eq", "value": "Deleted"}, "action": "publish", "target": "sqs:https://sqs.example.com/deletes"}]"#;
+
+        // WHEN parsing them
+        let from_routes = Rules::default().with_routes(routes);
+        let from_rules = Rules::parse(data);
+
+        // THEN they are rejected, as only FIFO queues are supported
+        assert!(matches!(
+            from_routes,
+            Err(Error::InitError("SQS targets must be FIFO queues"))
+        ));
+        assert!(matches!(
+            from_rules,
+            Err(Error::InitError("SQS targets must be FIFO queues"))
+        ));
+    }
+
+    #[test]
+    fn test_compare_missing_field() {
+        let event = serde_json::json!({"type": "Deleted", "product": {"id": "1"}});
+        let condition = |data: &str| serde_json::from_str::<Condition>(data).unwrap();
+
+        assert!(
+            !condition(r#"{"field": "product.price", "op": "lt", "value": 10}"#).matches(&event)
+        );
+        assert!(
+            condition(r#"{"field": "product.price", "op": "ne", "value": 10}"#).matches(&event)
+        );
+        assert!(
+            condition(r#"{"field": "product.price", "op": "exists", "value": false}"#)
+                .matches(&event)
+        );
+    }
+}
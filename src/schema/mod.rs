@@ -0,0 +1,367 @@
+//! # Event schemas
+//!
+//! Consumers rely on the shape of published events, so events are checked
+//! against a JSON Schema before being published. A malformed event then
+//! fails the publishing function with a description of the problem, instead
+//! of breaking consumers downstream.
+//!
+//! The schema of all product events is bundled with the crate. Schemas can
+//! also be fetched from the EventBridge Schema Registry (see `registry`),
+//! e.g. to check events against the schemas consumers generated code from.
+//!
+//! Only the subset of JSON Schema draft 4 used by these schemas is
+//! supported: `type`, `enum`, `properties`, `required`,
+//! `additionalProperties`, `items`, `minimum`, `maximum`, `minLength`,
+//! `allOf`, `anyOf`, `oneOf` and local `$ref`s. Other keywords are ignored.
+
+use crate::{Error, Event};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub mod registry;
+
+/// Schema of product events bundled with the crate
+static EVENT_SCHEMA: &str = include_str!("event.json");
+
+/// JSON Schema document
+#[derive(Clone, Debug)]
+pub struct Schema {
+    /// Whole document, used to resolve `$ref`s
+    root: Value,
+    /// Pointer to the schema values are validated against
+    entry: String,
+}
+
+impl Schema {
+    pub fn new(root: Value) -> Result<Self, Error> {
+        if !root.is_object() {
+            return Err(Error::InitError("Schema must be a JSON object"));
+        }
+        Ok(Self {
+            root,
+            entry: String::new(),
+        })
+    }
+
+    /// Parse a schema from a JSON document
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        Self::new(serde_json::from_str(data).map_err(|_| Error::InitError("Invalid schema"))?)
+    }
+
+    /// Validate against the sub-schema at this JSON pointer
+    ///
+    /// Schema Registry schemas describe the whole EventBridge event, so the
+    /// payload is checked against `/properties/detail`.
+    pub fn with_entry(mut self, pointer: &str) -> Result<Self, Error> {
+        if self.root.pointer(pointer).is_none() {
+            return Err(Error::InitError("Schema entry point doesn't exist"));
+        }
+        self.entry = pointer.to_string();
+        Ok(self)
+    }
+
+    /// Validate a value, returning a description of every violation
+    pub fn validate(&self, instance: &Value) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let entry = self.root.pointer(&self.entry).unwrap_or(&self.root);
+        self.validate_node(entry, instance, "", &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_node(
+        &self,
+        schema: &Value,
+        instance: &Value,
+        path: &str,
+        errors: &mut Vec<String>,
+    ) {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(false) => {
+                errors.push(format!("{}: no value is allowed", at(path)));
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer))
+            {
+                Some(target) => self.validate_node(target, instance, path, errors),
+                None => errors.push(format!("{}: unresolved reference {}", at(path), reference)),
+            }
+            return;
+        }
+
+        if let Some(types) = schema.get("type") {
+            let matches = match types {
+                Value::String(t) => has_type(instance, t),
+                Value::Array(types) => types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|t| has_type(instance, t)),
+                _ => true,
+            };
+            if !matches {
+                errors.push(format!("{}: expected type {}", at(path), types));
+                return;
+            }
+        }
+
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(instance) {
+                errors.push(format!(
+                    "{}: {} is not one of {:?}",
+                    at(path),
+                    instance,
+                    values
+                ));
+            }
+        }
+
+        if let Some(n) = instance.as_f64() {
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    errors.push(format!("{}: {} is less than {}", at(path), n, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    errors.push(format!("{}: {} is greater than {}", at(path), n, max));
+                }
+            }
+        }
+
+        if let (Some(s), Some(min)) = (
+            instance.as_str(),
+            schema.get("minLength").and_then(Value::as_u64),
+        ) {
+            if (s.chars().count() as u64) < min {
+                errors.push(format!("{}: shorter than {} characters", at(path), min));
+            }
+        }
+
+        if let Value::Object(object) = instance {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{}: missing property {}", at(path), key));
+                    }
+                }
+            }
+            for (key, value) in object {
+                let child = format!("{}/{}", path, key);
+                match properties.and_then(|p| p.get(key)) {
+                    Some(property) => self.validate_node(property, value, &child, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{}: unexpected property", at(&child)))
+                        }
+                        Some(additional) => self.validate_node(additional, value, &child, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+
+        if let (Value::Array(items), Some(schema)) = (instance, schema.get("items")) {
+            for (i, item) in items.iter().enumerate() {
+                self.validate_node(schema, item, &format!("{}/{}", path, i), errors);
+            }
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for schema in schemas {
+                self.validate_node(schema, instance, path, errors);
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            if self.count_valid(schemas, instance, path) == 0 {
+                errors.push(format!("{}: doesn't match any allowed schema", at(path)));
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            match self.count_valid(schemas, instance, path) {
+                1 => {}
+                0 => errors.push(format!("{}: doesn't match any allowed schema", at(path))),
+                _ => errors.push(format!("{}: matches more than one schema", at(path))),
+            }
+        }
+    }
+
+    /// Number of schemas the value is valid against
+    fn count_valid(&self, schemas: &[Value], instance: &Value, path: &str) -> usize {
+        schemas
+            .iter()
+            .filter(|schema| {
+                let mut errors = Vec::new();
+                self.validate_node(schema, instance, path, &mut errors);
+                errors.is_empty()
+            })
+            .count()
+    }
+}
+
+/// Path of a value, for error messages
+fn at(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn has_type(instance: &Value, t: &str) -> bool {
+    match t {
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "boolean" => instance.is_boolean(),
+        "null" => instance.is_null(),
+        "number" => instance.is_number(),
+        "integer" => {
+            instance.is_i64()
+                || instance.is_u64()
+                || instance.as_f64().map_or(false, |n| n.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+/// Validator checking events against their schema
+#[derive(Clone, Debug)]
+pub struct EventValidator {
+    /// Schema for event types without their own schema
+    default: Option<Schema>,
+    /// Schema for each event type
+    schemas: HashMap<String, Schema>,
+}
+
+impl Default for EventValidator {
+    /// Validator using the bundled schema
+    fn default() -> Self {
+        Self {
+            default: Some(Schema::parse(EVENT_SCHEMA).expect("bundled schema must be valid")),
+            schemas: HashMap::new(),
+        }
+    }
+}
+
+impl EventValidator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Check events of this type, such as `Created`, against this schema
+    pub fn with_schema(mut self, event_type: &str, schema: Schema) -> Self {
+        self.schemas.insert(event_type.to_string(), schema);
+        self
+    }
+
+    /// Check that an event matches its schema
+    pub fn validate(&self, event: &Event) -> Result<(), Error> {
+        let schema = match self
+            .schemas
+            .get(event.event_type())
+            .or(self.default.as_ref())
+        {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+
+        let value = serde_json::to_value(event)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+        schema.validate(&value).map_err(|errors| {
+            Error::InvalidEvent(format!(
+                "{} event for product {} doesn't match its schema: {}",
+                event.event_type(),
+                event.id(),
+                errors.join(", ")
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_events() {
+        // GIVEN the bundled schema
+        let validator = EventValidator::new();
+
+        // THEN every event type is valid
+        for (_, event) in crate::event_bus::golden::get_events() {
+            validator.validate(&event).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate_envelopes() {
+        // GIVEN the bundled schema
+        let schema = Schema::parse(EVENT_SCHEMA).unwrap();
+
+        // THEN every event is valid with its metadata
+        for (_, event) in crate::event_bus::golden::get_events() {
+            let envelope = crate::EventEnvelope {
+                event,
+                metadata: Some(crate::event_bus::golden::get_metadata()),
+            };
+            schema
+                .validate(&serde_json::to_value(&envelope).unwrap())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate_invalid() {
+        // GIVEN a schema requiring a positive price
+        let schema = Schema::parse(
+            r#"{
+                "type": "object",
+                "required": ["price"],
+                "properties": {"price": {"type": "number", "minimum": 0}},
+                "additionalProperties": false
+            }"#,
+        )
+        .unwrap();
+
+        // WHEN validating an invalid value
+        let res = schema.validate(&json!({"price": -1, "extra": true}));
+
+        // THEN every violation is described
+        assert_eq!(
+            res,
+            Err(vec![
+                "/extra: unexpected property".to_string(),
+                "/price: -1 is less than 0".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_entry() {
+        // GIVEN a schema of a whole EventBridge event
+        let schema = Schema::new(json!({
+            "type": "object",
+            "properties": {"detail": {"$ref": "#/components/Detail"}},
+            "components": {"Detail": {"type": "object", "required": ["type"]}}
+        }))
+        .unwrap()
+        .with_entry("/properties/detail")
+        .unwrap();
+
+        // THEN the payload is checked against the detail schema
+        assert!(schema.validate(&json!({"type": "Created"})).is_ok());
+        assert!(schema.validate(&json!({})).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! # EventBridge Schema Registry
+//!
+//! Schemas discovered or uploaded to the Schema Registry describe the whole
+//! EventBridge event. They are exported as JSON Schema draft 4, and events
+//! are checked against the schema of their `detail` property.
+//!
+//! Schemas are fetched once, when the function starts, and kept in memory
+//! for the lifetime of the execution environment.
+
+use super::{EventValidator, Schema};
+use crate::Error;
+use aws_sdk_schemas::Client;
+use tracing::{info, instrument, warn};
+
+/// Source of the events, as in EventBridge
+static SOURCE: &str = "rust-products";
+
+/// Event types, with their EventBridge detail type
+const DETAIL_TYPES: [(&str, &str); 6] = [
+    ("Created", "ProductCreated"),
+    ("Updated", "ProductUpdated"),
+    ("Deleted", "ProductDeleted"),
+    ("DeletionScheduled", "ProductDeletionScheduled"),
+    ("Archived", "ProductArchived"),
+    ("ArchiveCompleted", "ProductArchiveCompleted"),
+];
+
+/// Fetch a schema from a registry
+#[instrument(skip(client))]
+pub async fn fetch_schema(
+    client: &Client,
+    registry_name: &str,
+    schema_name: &str,
+) -> Result<Schema, Error> {
+    info!("Exporting schema from the Schema Registry");
+    let res = client
+        .export_schema()
+        .registry_name(registry_name)
+        .schema_name(schema_name)
+        .r#type("JSONSchemaDraft4")
+        .send()
+        .await?;
+
+    let content = res
+        .content
+        .ok_or(Error::InitError("Schema export has no content"))?;
+    Schema::parse(&content)?.with_entry("/properties/detail")
+}
+
+/// Build a validator from the schemas of a registry
+///
+/// Schemas are named after the source and detail type of the events, as
+/// schema discovery does, e.g. `rust-products@ProductCreated`. Event types
+/// without a schema in the registry are checked against the bundled schema.
+pub async fn load_validator(client: &Client, registry_name: &str) -> Result<EventValidator, Error> {
+    let mut validator = EventValidator::new();
+    for (event_type, detail_type) in DETAIL_TYPES {
+        let schema_name = format!("{}@{}", SOURCE, detail_type);
+        match fetch_schema(client, registry_name, &schema_name).await {
+            Ok(schema) => validator = validator.with_schema(event_type, schema),
+            Err(Error::SdkError(details))
+                if details.code.as_deref() == Some("NotFoundException") =>
+            {
+                warn!("Schema {} not found, using the bundled schema", schema_name);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(validator)
+}
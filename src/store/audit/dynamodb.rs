@@ -0,0 +1,315 @@
+//! # DynamoDB audit store implementation
+//!
+//! Audit store implementation using the AWS SDK for DynamoDB.
+//!
+//! Entries use the product id as the partition key and the timestamp as the
+//! sort key. Each entry is only written if there is no entry for the product
+//! at that timestamp yet, and is moved to the next millisecond otherwise, so
+//! that mutations of the same product within a millisecond keep their own
+//! entry.
+
+use super::{AuditStore, StoreGetAudit, StoreRecordAudit};
+use crate::{store::dynamodb::ext::AttributeValuesExt, AuditAction, AuditEntry, Error};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use aws_smithy_http::result::SdkError;
+use std::collections::HashMap;
+use tracing::{info, instrument, warn};
+
+/// Maximum number of timestamps tried when recording an entry
+const MAX_ATTEMPTS: usize = 10;
+
+/// DynamoDB audit store implementation.
+pub struct DynamoDBAuditStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBAuditStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBAuditStore {
+        DynamoDBAuditStore { client, table_name }
+    }
+}
+
+impl AuditStore for DynamoDBAuditStore {}
+
+#[async_trait]
+impl StoreRecordAudit for DynamoDBAuditStore {
+    /// Write an audit entry
+    ///
+    /// If the product already has an entry at that timestamp, the entry is
+    /// recorded a millisecond later instead.
+    #[instrument(skip(self))]
+    async fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        info!(
+            "Putting audit entry for product '{}' into DynamoDB table",
+            entry.id
+        );
+        let mut entry = entry.clone();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let res = self
+                .client
+                .put_item()
+                .table_name(&self.table_name)
+                .set_item(Some((&entry).into()))
+                .condition_expression("attribute_not_exists(id)")
+                .send()
+                .await;
+
+            match res {
+                Ok(_) => return Ok(()),
+                Err(SdkError::ServiceError { err, .. })
+                    if err.is_conditional_check_failed_exception() && attempts < MAX_ATTEMPTS =>
+                {
+                    warn!(
+                        "Audit entry for product '{}' already exists at {}, retrying",
+                        entry.id, entry.timestamp
+                    );
+                    entry.timestamp += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StoreGetAudit for DynamoDBAuditStore {
+    /// Query all audit entries of a product, newest first
+    #[instrument(skip(self))]
+    async fn entries(&self, id: &str) -> Result<Vec<AuditEntry>, Error> {
+        info!(
+            "Querying audit entries of product '{}' from DynamoDB table",
+            id
+        );
+        let mut entries = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("id = :id")
+                .expression_attribute_values(":id", AttributeValue::S(id.to_owned()))
+                .scan_index_forward(false)
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            entries.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| v.try_into())
+                    .collect::<Result<Vec<AuditEntry>, Error>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(entries)
+    }
+}
+
+impl From<&AuditEntry> for HashMap<String, AttributeValue> {
+    /// Convert a &AuditEntry into a DynamoDB item
+    fn from(value: &AuditEntry) -> HashMap<String, AttributeValue> {
+        let mut retval = HashMap::new();
+        retval.insert("id".to_owned(), AttributeValue::S(value.id.clone()));
+        retval.insert(
+            "timestamp".to_owned(),
+            AttributeValue::N(value.timestamp.to_string()),
+        );
+        retval.insert(
+            "action".to_owned(),
+            AttributeValue::S(value.action.as_str().to_owned()),
+        );
+        if let Some(caller) = &value.caller {
+            retval.insert("caller".to_owned(), AttributeValue::S(caller.clone()));
+        }
+        if let Some(api_key_id) = &value.api_key_id {
+            retval.insert(
+                "api_key_id".to_owned(),
+                AttributeValue::S(api_key_id.clone()),
+            );
+        }
+
+        retval
+    }
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for AuditEntry {
+    type Error = Error;
+
+    /// Try to convert a DynamoDB item into an AuditEntry
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let action = match value.get_s("action").as_deref() {
+            Some("put") => AuditAction::Put,
+            Some("patch") => AuditAction::Patch,
+            Some("delete") => AuditAction::Delete,
+            Some("hard_delete") => AuditAction::HardDelete,
+            Some("restore") => AuditAction::Restore,
+            _ => return Err(Error::InternalError("Missing or unknown action")),
+        };
+
+        Ok(AuditEntry {
+            id: value
+                .get_s("id")
+                .ok_or(Error::InternalError("Missing id"))?,
+            timestamp: value
+                .get_n("timestamp")
+                .ok_or(Error::InternalError("Missing timestamp"))? as u64,
+            action,
+            caller: value.get_s("caller"),
+            api_key_id: value.get_s("api_key_id"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_record() -> Result<(), Error> {
+        // GIVEN an empty DynamoDBAuditStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"timestamp":{"N":"1000"},"action":{"S":"delete"},"caller":{"S":"alice"}},"ConditionExpression":"attribute_not_exists(id)"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBAuditStore::new(client, "test".to_string());
+
+        // WHEN recording an entry
+        store
+            .record(&AuditEntry {
+                id: "1".to_string(),
+                timestamp: 1000,
+                action: AuditAction::Delete,
+                caller: Some("alice".to_string()),
+                api_key_id: None,
+            })
+            .await?;
+
+        // THEN the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_same_timestamp() -> Result<(), Error> {
+        // GIVEN a DynamoDBAuditStore with an entry at the same timestamp
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"timestamp":{"N":"1000"},"action":{"S":"put"}},"ConditionExpression":"attribute_not_exists(id)"}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(r#"{"__type":"com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException","message":"The conditional request failed"}"#))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"timestamp":{"N":"1001"},"action":{"S":"put"}},"ConditionExpression":"attribute_not_exists(id)"}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBAuditStore::new(client, "test".to_string());
+
+        // WHEN recording an entry
+        store
+            .record(&AuditEntry {
+                id: "1".to_string(),
+                timestamp: 1000,
+                action: AuditAction::Put,
+                caller: None,
+                api_key_id: None,
+            })
+            .await?;
+
+        // THEN the entry is recorded at the next millisecond
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_entries() -> Result<(), Error> {
+        // GIVEN a DynamoDBAuditStore with two entries
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r#"{"TableName":"test","KeyConditionExpression":"id = :id","ExpressionAttributeValues":{":id":{"S":"1"}},"ScanIndexForward":false}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "timestamp": {"N": "2000"}, "action": {"S": "delete"}, "caller": {"S": "alice"}}, {"id": {"S": "1"}, "timestamp": {"N": "1000"}, "action": {"S": "put"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBAuditStore::new(client, "test".to_string());
+
+        // WHEN getting the entries of the product
+        let entries = store.entries("1").await?;
+
+        // THEN both entries are returned, newest first
+        assert_eq!(
+            entries,
+            vec![
+                AuditEntry {
+                    id: "1".to_string(),
+                    timestamp: 2000,
+                    action: AuditAction::Delete,
+                    caller: Some("alice".to_string()),
+                    api_key_id: None,
+                },
+                AuditEntry {
+                    id: "1".to_string(),
+                    timestamp: 1000,
+                    action: AuditAction::Put,
+                    caller: None,
+                    api_key_id: None,
+                },
+            ]
+        );
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+}
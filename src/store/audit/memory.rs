@@ -0,0 +1,93 @@
+//! # In-memory audit store implementation
+//!
+//! This is a simple in-memory audit store implementation. It is not intended
+//! to be used in production, but rather as a simple implementation for local
+//! testing purposes.
+
+use super::{AuditStore, StoreGetAudit, StoreRecordAudit};
+use crate::{AuditEntry, Error};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryAuditStore {
+    data: RwLock<HashMap<String, Vec<AuditEntry>>>,
+}
+
+impl MemoryAuditStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl AuditStore for MemoryAuditStore {}
+
+#[async_trait]
+impl StoreRecordAudit for MemoryAuditStore {
+    async fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        self.data
+            .write()
+            .unwrap()
+            .entry(entry.id.clone())
+            .or_default()
+            .push(entry.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetAudit for MemoryAuditStore {
+    async fn entries(&self, id: &str) -> Result<Vec<AuditEntry>, Error> {
+        let mut entries = self
+            .data
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or_default();
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditAction;
+
+    fn get_entry(id: &str, timestamp: u64, action: AuditAction) -> AuditEntry {
+        AuditEntry {
+            id: id.to_string(),
+            timestamp,
+            action,
+            caller: Some("arn:aws:iam::123456789012:user/alice".to_string()),
+            api_key_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries() -> Result<(), Error> {
+        // GIVEN a store with entries for two products
+        let store = MemoryAuditStore::new();
+        store.record(&get_entry("1", 1, AuditAction::Put)).await?;
+        store
+            .record(&get_entry("1", 2, AuditAction::Delete))
+            .await?;
+        store.record(&get_entry("2", 1, AuditAction::Put)).await?;
+
+        // WHEN getting the entries of the first product
+        let entries = store.entries("1").await?;
+
+        // THEN we get its entries, newest first
+        assert_eq!(
+            entries,
+            vec![
+                get_entry("1", 2, AuditAction::Delete),
+                get_entry("1", 1, AuditAction::Put)
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+//! # Audit log storage
+//!
+//! Every mutation of the catalog is recorded in the audit log with the
+//! identity of the caller and the time of the change, so that it can be
+//! reviewed later for a given product.
+
+use crate::{AuditEntry, Error};
+use async_trait::async_trait;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBAuditStore;
+pub use memory::MemoryAuditStore;
+
+pub trait AuditStore: StoreRecordAudit + StoreGetAudit {}
+
+/// Trait for recording an audit entry
+#[async_trait]
+pub trait StoreRecordAudit: Send + Sync {
+    async fn record(&self, entry: &AuditEntry) -> Result<(), Error>;
+}
+
+/// Trait for retrieving the audit entries of a product
+///
+/// Entries are returned from the newest to the oldest.
+#[async_trait]
+pub trait StoreGetAudit: Send + Sync {
+    async fn entries(&self, id: &str) -> Result<Vec<AuditEntry>, Error>;
+}
@@ -0,0 +1,474 @@
+//! # Buffered store
+//!
+//! Store wrapper coalescing single product writes into batches, for write
+//! paths that produce many products one at a time, such as bulk imports or
+//! re-ingesting a stream of changes.
+//!
+//! Puts are kept in memory until the buffer holds `max_size` products, or
+//! until the oldest buffered put is older than `max_age`, then written with
+//! `StorePutMany`, which uses `BatchWriteItem` for DynamoDB. The age is
+//! checked when a product is put, and by the task started with
+//! `spawn_flusher` for consumers that can go quiet with products still
+//! buffered. Callers must call `flush()` once they are done, or use
+//! `spawn_flush_on_shutdown`, or buffered products are lost.
+//!
+//! Buffered products are not visible to reads until they are flushed, so
+//! this wrapper only implements the write traits, and `StoreScanAll`, which
+//! flushes the buffer first. Deleting a product drops its buffered version,
+//! so that a later flush doesn't bring it back. Deletes wait for the flush
+//! in flight, if any, as it could write the product after the delete.
+
+use super::{StoreDelete, StorePut, StorePutMany, StoreScanAll};
+use crate::{Error, Product};
+use async_trait::async_trait;
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{error, info, instrument, warn};
+
+/// Default number of products written in a single batch
+///
+/// This matches the maximum size of a `BatchWriteItem` request.
+const DEFAULT_MAX_SIZE: usize = 25;
+
+/// Default time after which buffered products are written
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Buffer {
+    products: Vec<Product>,
+    /// Time of the oldest buffered put
+    since: Option<Instant>,
+}
+
+/// State shared with the background tasks
+struct Shared<S> {
+    inner: S,
+    buffer: Mutex<Buffer>,
+    /// Held while flushing, and while deleting so that a flush in flight
+    /// can't write a product back after its delete
+    flushing: tokio::sync::Mutex<()>,
+}
+
+/// Store buffering puts and writing them in batches.
+pub struct BufferedStore<S> {
+    shared: Arc<Shared<S>>,
+    max_size: usize,
+    max_age: Duration,
+}
+
+impl<S> BufferedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                inner,
+                buffer: Default::default(),
+                flushing: Default::default(),
+            }),
+            max_size: DEFAULT_MAX_SIZE,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Set the number of buffered products that triggers a flush
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    /// Set the age of the oldest buffered put that triggers a flush
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Number of products waiting to be written
+    pub fn pending(&self) -> usize {
+        self.shared.buffer.lock().unwrap().products.len()
+    }
+
+    /// Store the products are written to
+    pub fn inner(&self) -> &S {
+        &self.shared.inner
+    }
+
+    /// Remove the buffered version of a product
+    fn discard(&self, id: &str) {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        buffer.products.retain(|p| p.id != id);
+        if buffer.products.is_empty() {
+            buffer.since = None;
+        }
+    }
+}
+
+impl<S> Shared<S> {
+    /// Take all buffered products
+    fn take(&self) -> Vec<Product> {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.since = None;
+        mem::take(&mut buffer.products)
+    }
+
+    /// Put products back in the buffer after a failed write
+    ///
+    /// Products that were put again in the meantime are not restored, as
+    /// they would overwrite the newer version.
+    fn restore(&self, products: Vec<Product>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut restored: Vec<Product> = products
+            .into_iter()
+            .filter(|p| !buffer.products.iter().any(|b| b.id == p.id))
+            .collect();
+        restored.append(&mut buffer.products);
+        buffer.products = restored;
+        buffer.since.get_or_insert_with(Instant::now);
+    }
+}
+
+impl<S: StorePutMany> Shared<S> {
+    async fn flush(&self) -> Result<usize, Error> {
+        let _flushing = self.flushing.lock().await;
+        let products = self.take();
+        if products.is_empty() {
+            return Ok(0);
+        }
+
+        info!("Flushing {} buffered products", products.len());
+        match self.inner.put_many(&products).await {
+            Ok(()) => Ok(products.len()),
+            Err(err) => {
+                warn!("Failed to flush buffered products: {}", err);
+                self.restore(products);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<S: StorePutMany> BufferedStore<S> {
+    /// Write all buffered products
+    ///
+    /// Returns the number of written products. If the write fails, the
+    /// products stay in the buffer so that the flush can be retried.
+    #[instrument(skip(self))]
+    pub async fn flush(&self) -> Result<usize, Error> {
+        self.shared.flush().await
+    }
+}
+
+impl<S: StorePutMany + 'static> BufferedStore<S> {
+    /// Flush the buffer every `max_age`, even if nothing is put
+    ///
+    /// The task stops once the store is dropped.
+    pub fn spawn_flusher(&self) -> JoinHandle<()> {
+        let shared = Arc::downgrade(&self.shared);
+        let period = self.max_age.max(Duration::from_millis(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match shared.upgrade() {
+                    Some(shared) => {
+                        if let Err(err) = shared.flush().await {
+                            error!("Failed to flush buffered products: {}", err);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+
+    /// Flush the buffer when the process is asked to stop
+    ///
+    /// Lambda sends `SIGTERM` before shutting down an execution environment
+    /// when extensions are registered; `Ctrl-C` is handled too for tools.
+    /// The task completes with the outcome of the flush, and the caller
+    /// decides how to exit: the process keeps running until it does.
+    pub fn spawn_flush_on_shutdown(&self) -> JoinHandle<Result<usize, Error>> {
+        let shared = Arc::downgrade(&self.shared);
+        tokio::spawn(async move {
+            let sigterm = async {
+                match signal(SignalKind::terminate()) {
+                    Ok(mut sigterm) => {
+                        sigterm.recv().await;
+                    }
+                    Err(err) => {
+                        error!("Failed to listen for SIGTERM: {}", err);
+                        std::future::pending::<()>().await;
+                    }
+                }
+            };
+            tokio::select! {
+                _ = sigterm => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+
+            info!("Shutting down");
+            match shared.upgrade() {
+                Some(shared) => shared.flush().await.map_err(|err| {
+                    error!("Failed to flush buffered products on shutdown: {}", err);
+                    err
+                }),
+                None => Ok(0),
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany> StorePut for BufferedStore<S> {
+    /// Buffer a product, and flush the buffer if it is full or too old
+    ///
+    /// A later put for the same id replaces the buffered product, which is
+    /// then returned. Products that were already flushed are not read back,
+    /// so `None` doesn't mean that the product is new.
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        let (previous, should_flush) = {
+            let mut buffer = self.shared.buffer.lock().unwrap();
+            let previous = match buffer.products.iter_mut().find(|p| p.id == product.id) {
+                Some(buffered) => Some(std::mem::replace(buffered, product.clone())),
+                None => {
+                    buffer.products.push(product.clone());
+                    None
+                }
+            };
+            let since = *buffer.since.get_or_insert_with(Instant::now);
+            let should_flush =
+                buffer.products.len() >= self.max_size || since.elapsed() >= self.max_age;
+            (previous, should_flush)
+        };
+
+        if should_flush {
+            self.flush().await?;
+        }
+        Ok(previous)
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany> StorePutMany for BufferedStore<S> {
+    /// Buffer products, flushing the buffer whenever it is full or too old
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        for product in products {
+            self.put(product).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany + StoreDelete> StoreDelete for BufferedStore<S> {
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let _flushing = self.shared.flushing.lock().await;
+        self.discard(id);
+        self.shared.inner.delete(id).await
+    }
+
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        let _flushing = self.shared.flushing.lock().await;
+        self.discard(id);
+        self.shared.inner.hard_delete(id).await
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany + StoreScanAll> StoreScanAll for BufferedStore<S> {
+    /// Flush the buffer, then scan the inner store
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error> {
+        self.flush().await?;
+        self.shared.inner.scan_all(total_segments).await
+    }
+}
+
+impl<S> Drop for BufferedStore<S> {
+    fn drop(&mut self) {
+        let pending = self.shared.buffer.lock().map_or(0, |b| b.products.len());
+        if pending > 0 {
+            warn!(
+                "Dropping buffered store with {} unflushed products",
+                pending
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, ReadConsistency, StoreCount, StoreGet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Store counting batch writes, which take `delay`
+    #[derive(Default)]
+    struct CountingStore {
+        store: MemoryStore,
+        batches: AtomicUsize,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl StorePutMany for CountingStore {
+        async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+            self.batches.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(self.delay).await;
+            self.store.put_many(products).await
+        }
+    }
+
+    #[async_trait]
+    impl StoreDelete for CountingStore {
+        async fn delete(&self, id: &str) -> Result<bool, Error> {
+            self.store.delete(id).await
+        }
+
+        async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+            self.store.hard_delete(id).await
+        }
+    }
+
+    fn get_product(id: usize) -> Product {
+        Product {
+            id: id.to_string(),
+            name: format!("product {}", id),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_size() -> Result<(), Error> {
+        // GIVEN a buffered store flushing every 2 products
+        let store = BufferedStore::new(CountingStore::default())
+            .with_max_size(2)
+            .with_max_age(Duration::from_secs(60));
+
+        // WHEN putting 3 products
+        for id in 0..3 {
+            store.put(&get_product(id)).await?;
+        }
+
+        // THEN the first 2 products are written in a single batch
+        assert_eq!(store.inner().batches.load(Ordering::Relaxed), 1);
+        assert_eq!(store.inner().store.count().await?, 2);
+        // AND the last product is still buffered
+        assert_eq!(store.pending(), 1);
+
+        // WHEN flushing the store
+        let flushed = store.flush().await?;
+
+        // THEN the last product is written
+        assert_eq!(flushed, 1);
+        assert_eq!(store.inner().store.count().await?, 3);
+        assert_eq!(store.pending(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_age() -> Result<(), Error> {
+        // GIVEN a buffered store without a maximum age
+        let store = BufferedStore::new(CountingStore::default()).with_max_age(Duration::ZERO);
+
+        // WHEN putting a product
+        store.put(&get_product(1)).await?;
+
+        // THEN the product is written right away
+        assert_eq!(store.inner().store.count().await?, 1);
+        assert_eq!(store.pending(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_puts() -> Result<(), Error> {
+        // GIVEN a buffered store
+        let store =
+            BufferedStore::new(CountingStore::default()).with_max_age(Duration::from_secs(60));
+
+        // WHEN putting the same product twice
+        let mut product = get_product(1);
+        store.put(&product).await?;
+        product.price = 20.0;
+        store.put(&product).await?;
+
+        // THEN only the latest version is buffered
+        assert_eq!(store.pending(), 1);
+        store.flush().await?;
+        let stored = store
+            .inner()
+            .store
+            .get("1", ReadConsistency::Strong, None)
+            .await?;
+        assert_eq!(stored.unwrap().price, 20.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spawn_flusher() -> Result<(), Error> {
+        // GIVEN a buffered store with a background flusher
+        let store =
+            BufferedStore::new(CountingStore::default()).with_max_age(Duration::from_millis(10));
+        store.spawn_flusher();
+
+        // WHEN putting a product and waiting
+        store.put(&get_product(1)).await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // THEN the product is written without another put
+        assert_eq!(store.pending(), 0);
+        assert_eq!(store.inner().store.count().await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_discards_buffered() -> Result<(), Error> {
+        // GIVEN a buffered product
+        let store =
+            BufferedStore::new(CountingStore::default()).with_max_age(Duration::from_secs(60));
+        store.put(&get_product(1)).await?;
+
+        // WHEN deleting it before it is flushed
+        store.hard_delete("1").await?;
+        store.flush().await?;
+
+        // THEN it is not written
+        assert_eq!(store.inner().store.count().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_during_flush() -> Result<(), Error> {
+        // GIVEN a buffered product, and a slow inner store
+        let store = BufferedStore::new(CountingStore {
+            delay: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .with_max_age(Duration::from_secs(60));
+        store.put(&get_product(1)).await?;
+
+        // WHEN deleting it while it is being flushed
+        let (flushed, deleted) = tokio::join!(store.flush(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            store.hard_delete("1").await
+        });
+        flushed?;
+        deleted?;
+
+        // THEN the flush doesn't bring it back
+        assert_eq!(store.inner().store.count().await?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! # Binary catalog format
+//!
+//! Compact representation of the full content of a store, used by
+//! `MemoryStore` snapshots and to share test fixtures. Decoding a large
+//! catalog is much faster than parsing the same products as JSON.
+//!
+//! A catalog starts with the `PCAT` magic bytes and a little-endian `u16`
+//! format version, followed by the products encoded with bincode. Custom
+//! attributes are kept as a JSON string, as bincode can't encode arbitrary
+//! JSON values.
+
+use crate::{Error, Product};
+use serde::{Deserialize, Serialize};
+
+/// Bytes at the start of every catalog
+pub const MAGIC: &[u8; 4] = b"PCAT";
+
+/// Current version of the format
+pub const VERSION: u16 = 1;
+
+const HEADER_LENGTH: usize = MAGIC.len() + 2;
+
+/// Content of a store
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Catalog {
+    pub products: Vec<Product>,
+    /// Soft-deleted products
+    pub deleted: Vec<Product>,
+}
+
+/// Product, as encoded in a catalog
+#[derive(Deserialize, Serialize)]
+struct CatalogProduct {
+    id: String,
+    name: String,
+    price: f64,
+    attributes: String,
+    images: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CatalogV1 {
+    products: Vec<CatalogProduct>,
+    deleted: Vec<CatalogProduct>,
+}
+
+impl Catalog {
+    /// Whether the data starts with a catalog header
+    pub fn is_catalog(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let catalog = CatalogV1 {
+            products: encode_products(&self.products)?,
+            deleted: encode_products(&self.deleted)?,
+        };
+
+        let mut data = Vec::with_capacity(HEADER_LENGTH);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, &catalog)
+            .map_err(|_| Error::InternalError("Failed to encode catalog"))?;
+        Ok(data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_LENGTH || !Self::is_catalog(data) {
+            return Err(Error::InternalError("Missing catalog header"));
+        }
+        let version = u16::from_le_bytes([data[MAGIC.len()], data[MAGIC.len() + 1]]);
+        if version != VERSION {
+            return Err(Error::InternalError("Unsupported catalog version"));
+        }
+
+        let catalog: CatalogV1 = bincode::deserialize(&data[HEADER_LENGTH..])
+            .map_err(|_| Error::InternalError("Failed to decode catalog"))?;
+        Ok(Self {
+            products: decode_products(catalog.products)?,
+            deleted: decode_products(catalog.deleted)?,
+        })
+    }
+}
+
+fn encode_products(products: &[Product]) -> Result<Vec<CatalogProduct>, Error> {
+    products
+        .iter()
+        .map(|product| {
+            Ok(CatalogProduct {
+                id: product.id.clone(),
+                name: product.name.clone(),
+                price: product.price,
+                attributes: serde_json::to_string(&product.attributes)
+                    .map_err(|_| Error::InternalError("Failed to encode attributes"))?,
+                images: product.images.clone(),
+            })
+        })
+        .collect()
+}
+
+fn decode_products(products: Vec<CatalogProduct>) -> Result<Vec<Product>, Error> {
+    products
+        .into_iter()
+        .map(|product| {
+            Ok(Product {
+                id: product.id,
+                name: product.name,
+                price: product.price,
+                attributes: serde_json::from_str(&product.attributes)
+                    .map_err(|_| Error::InternalError("Failed to decode attributes"))?,
+                images: product.images,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn get_catalog() -> Catalog {
+        Catalog {
+            products: vec![Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.5,
+                attributes: [
+                    ("color".to_string(), json!("red")),
+                    ("weight".to_string(), json!(1.5)),
+                    ("fragile".to_string(), json!(true)),
+                ]
+                .into_iter()
+                .collect(),
+                images: vec!["products/1/abc".to_string()],
+            }],
+            deleted: vec![Product {
+                id: "2".to_string(),
+                name: "bar".to_string(),
+                price: 20.0,
+                attributes: Default::default(),
+                images: Default::default(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), Error> {
+        let catalog = get_catalog();
+
+        let data = catalog.to_bytes()?;
+
+        assert!(data.starts_with(b"PCAT\x01\x00"));
+        assert_eq!(Catalog::from_bytes(&data)?, catalog);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_version() -> Result<(), Error> {
+        // GIVEN a catalog with a version from the future
+        let mut data = get_catalog().to_bytes()?;
+        data[MAGIC.len()] = 2;
+
+        // WHEN decoding it
+        let res = Catalog::from_bytes(&data);
+
+        // THEN decoding fails
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_header() {
+        assert!(Catalog::from_bytes(b"{\"products\": []}").is_err());
+    }
+}
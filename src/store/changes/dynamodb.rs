@@ -0,0 +1,250 @@
+//! # DynamoDB change store implementation
+//!
+//! Change store implementation using the AWS SDK for DynamoDB.
+//!
+//! All changes share the same partition key and use the sequence as the sort
+//! key, so that they can be queried in order.
+//!
+//! As a single partition, the feed is limited to about 1000 write units per
+//! second, that is 1000 changes of up to 1 KB per second. Writes above that
+//! are throttled: the unprocessed items are retried, then the append fails
+//! and the stream batch is retried. Catalogs with more changes need to
+//! spread the feed across several partition keys, such as one per day, and
+//! read the buckets in order.
+
+use super::{ChangeStore, StoreAppendChanges, StoreGetChanges};
+use crate::{store::dynamodb::ext::AttributeValuesExt, Change, Error};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, PutRequest, WriteRequest},
+    Client,
+};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// Partition key value shared by all changes
+///
+/// This caps the write throughput of the feed, see the module documentation.
+const FEED: &str = "products";
+
+/// Maximum number of items in a `BatchWriteItem` request
+const BATCH_SIZE: usize = 25;
+
+/// Maximum number of attempts to write unprocessed items
+const MAX_ATTEMPTS: usize = 5;
+
+/// DynamoDB change store implementation.
+pub struct DynamoDBChangeStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBChangeStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBChangeStore {
+        DynamoDBChangeStore { client, table_name }
+    }
+}
+
+impl ChangeStore for DynamoDBChangeStore {}
+
+#[async_trait]
+impl StoreAppendChanges for DynamoDBChangeStore {
+    /// Write changes in batches
+    #[instrument(skip(self, changes))]
+    async fn append(&self, changes: &[Change]) -> Result<(), Error> {
+        info!("Writing {} changes to DynamoDB table", changes.len());
+        for chunk in changes.chunks(BATCH_SIZE) {
+            let mut requests = chunk
+                .iter()
+                .map(|change| {
+                    Ok(WriteRequest::builder()
+                        .put_request(
+                            PutRequest::builder()
+                                .set_item(Some(change.try_into()?))
+                                .build(),
+                        )
+                        .build())
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            // Retry unprocessed items
+            //
+            // DynamoDB can return unprocessed items when the table is
+            // throttled, which need to be sent again.
+            let mut attempts = 0;
+            while !requests.is_empty() {
+                if attempts == MAX_ATTEMPTS {
+                    return Err(Error::InternalError("Failed to write all changes"));
+                }
+                attempts += 1;
+
+                let res = self
+                    .client
+                    .batch_write_item()
+                    .request_items(&self.table_name, requests)
+                    .send()
+                    .await?;
+                requests = res
+                    .unprocessed_items
+                    .and_then(|mut items| items.remove(&self.table_name))
+                    .unwrap_or_default();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetChanges for DynamoDBChangeStore {
+    /// Query changes in sequence order
+    #[instrument(skip(self))]
+    async fn since(&self, since: Option<&str>, limit: usize) -> Result<Vec<Change>, Error> {
+        info!("Querying changes from DynamoDB table");
+        let mut req = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .limit(limit as i32)
+            .expression_attribute_names("#feed", "feed")
+            .expression_attribute_values(":feed", AttributeValue::S(FEED.to_owned()));
+        req = match since {
+            Some(since) => req
+                .key_condition_expression("#feed = :feed AND #seq > :since")
+                .expression_attribute_names("#seq", "sequence")
+                .expression_attribute_values(":since", AttributeValue::S(since.to_owned())),
+            None => req.key_condition_expression("#feed = :feed"),
+        };
+        let res = req.send().await?;
+
+        res.items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| v.try_into())
+            .collect()
+    }
+}
+
+impl TryFrom<&Change> for HashMap<String, AttributeValue> {
+    type Error = Error;
+
+    /// Try to convert a &Change into a DynamoDB item
+    fn try_from(value: &Change) -> Result<Self, Self::Error> {
+        let event = serde_json::to_string(&value.event)
+            .map_err(|_| Error::InternalError("Unable to serialize event"))?;
+
+        let mut retval = HashMap::new();
+        retval.insert("feed".to_owned(), AttributeValue::S(FEED.to_owned()));
+        retval.insert(
+            "sequence".to_owned(),
+            AttributeValue::S(value.sequence.clone()),
+        );
+        retval.insert("event".to_owned(), AttributeValue::S(event));
+
+        Ok(retval)
+    }
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for Change {
+    type Error = Error;
+
+    /// Try to convert a DynamoDB item into a Change
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let event = value
+            .get_s("event")
+            .ok_or(Error::InternalError("Missing event"))?;
+
+        Ok(Change {
+            sequence: value
+                .get_s("sequence")
+                .ok_or(Error::InternalError("Missing sequence"))?,
+            event: serde_json::from_str(&event)
+                .map_err(|_| Error::InternalError("Unable to parse event"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{dynamodb_request_builder, mock_sdk_config},
+        Event, Product,
+    };
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_append() -> Result<(), Error> {
+        // GIVEN an empty DynamoDBChangeStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchWriteItem")
+                .body(SdkBody::from(r#"{"RequestItems":{"test":[{"PutRequest":{"Item":{"feed":{"S":"products"},"sequence":{"S":"001"},"event":{"S":"{\"type\":\"Deleted\",\"product\":{\"id\":\"1\",\"name\":\"test1\",\"price\":1.5}}"}}}}]}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"UnprocessedItems": {}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBChangeStore::new(client, "test".to_string());
+
+        // WHEN appending a change
+        store
+            .append(&[Change {
+                sequence: "001".to_string(),
+                event: Event::Deleted {
+                    product: Product {
+                        id: "1".to_string(),
+                        name: "test1".to_string(),
+                        price: 1.5,
+                        attributes: Default::default(),
+                        images: Default::default(),
+                    },
+                },
+            }])
+            .await?;
+
+        // THEN the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_since() -> Result<(), Error> {
+        // GIVEN a DynamoDBChangeStore with one change after "001"
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r##"{"TableName":"test","Limit":10,"KeyConditionExpression":"#feed = :feed AND #seq > :since","ExpressionAttributeNames":{"#feed":"feed","#seq":"sequence"},"ExpressionAttributeValues":{":feed":{"S":"products"},":since":{"S":"001"}}}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"feed": {"S": "products"}, "sequence": {"S": "002"}, "event": {"S": "{\"type\":\"Created\",\"product\":{\"id\":\"1\",\"name\":\"test1\",\"price\":1.5}}"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBChangeStore::new(client, "test".to_string());
+
+        // WHEN getting changes since "001"
+        let changes = store.since(Some("001"), 10).await?;
+
+        // THEN the change is returned
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].sequence, "002");
+        assert_eq!(changes[0].event.id(), "1");
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+}
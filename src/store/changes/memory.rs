@@ -0,0 +1,112 @@
+//! # In-memory change store implementation
+//!
+//! This is a simple in-memory change store implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::{ChangeStore, StoreAppendChanges, StoreGetChanges};
+use crate::{Change, Error, Event};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryChangeStore {
+    data: RwLock<BTreeMap<String, Event>>,
+}
+
+impl MemoryChangeStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl ChangeStore for MemoryChangeStore {}
+
+#[async_trait]
+impl StoreAppendChanges for MemoryChangeStore {
+    async fn append(&self, changes: &[Change]) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        for change in changes {
+            data.insert(change.sequence.clone(), change.event.clone());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetChanges for MemoryChangeStore {
+    async fn since(&self, since: Option<&str>, limit: usize) -> Result<Vec<Change>, Error> {
+        let start = match since {
+            Some(since) => Bound::Excluded(since.to_string()),
+            None => Bound::Unbounded,
+        };
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(sequence, event)| Change {
+                sequence: sequence.clone(),
+                event: event.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+
+    fn get_change(sequence: &str) -> Change {
+        Change {
+            sequence: sequence.to_string(),
+            event: Event::Created {
+                product: Product {
+                    id: sequence.to_string(),
+                    name: "foo".to_string(),
+                    price: 10.0,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_since() -> Result<(), Error> {
+        // GIVEN a store with three changes
+        let store = MemoryChangeStore::new();
+        store
+            .append(&[get_change("001"), get_change("002"), get_change("003")])
+            .await?;
+
+        // WHEN getting the changes since the first one
+        let changes = store.since(Some("001"), 10).await?;
+
+        // THEN we get the two following changes in order
+        assert_eq!(changes, vec![get_change("002"), get_change("003")]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_since_limit() -> Result<(), Error> {
+        // GIVEN a store with three changes
+        let store = MemoryChangeStore::new();
+        store
+            .append(&[get_change("003"), get_change("001"), get_change("002")])
+            .await?;
+
+        // WHEN getting the first two changes
+        let changes = store.since(None, 2).await?;
+
+        // THEN we get the two oldest changes
+        assert_eq!(changes, vec![get_change("001"), get_change("002")]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! # Change feed storage
+//!
+//! Changes derived from the DynamoDB stream are persisted in sequence order,
+//! so clients can incrementally sync the catalog without consuming events
+//! from the event bus.
+
+use crate::{Change, Error};
+use async_trait::async_trait;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBChangeStore;
+pub use memory::MemoryChangeStore;
+
+pub trait ChangeStore: StoreAppendChanges + StoreGetChanges {}
+
+/// Trait for recording changes
+#[async_trait]
+pub trait StoreAppendChanges: Send + Sync {
+    async fn append(&self, changes: &[Change]) -> Result<(), Error>;
+}
+
+/// Trait for retrieving changes
+///
+/// This returns at most `limit` changes with a sequence strictly greater
+/// than `since`, in sequence order. If `since` is `None`, changes are
+/// returned from the beginning of the feed.
+#[async_trait]
+pub trait StoreGetChanges: Send + Sync {
+    async fn since(&self, since: Option<&str>, limit: usize) -> Result<Vec<Change>, Error>;
+}
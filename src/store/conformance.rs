@@ -0,0 +1,75 @@
+//! # Store conformance checks
+//!
+//! Properties that every store backend must satisfy, written against the
+//! store traits so that each backend can run them from its own tests.
+
+use super::{ProductFilter, StoreGetAll, StorePut};
+use crate::{Error, Product};
+use rand::{distributions::Alphanumeric, Rng};
+use std::collections::HashSet;
+
+/// Check that paginating through `all()` never loses or duplicates products
+///
+/// This inserts `count` products with random ids into an empty store, then
+/// reads them back with a random page size for each request, including
+/// zero.
+pub async fn check_pagination<S>(store: &S, count: usize) -> Result<(), Error>
+where
+    S: StorePut + StoreGetAll,
+{
+    let mut rng = rand::thread_rng();
+
+    let mut inserted = HashSet::new();
+    while inserted.len() < count {
+        let len = rng.gen_range(1..12);
+        let id: String = (&mut rng)
+            .sample_iter(&Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect();
+        if !inserted.insert(id.clone()) {
+            continue;
+        }
+        store
+            .put(&Product {
+                id,
+                name: "foo".to_string(),
+                price: rng.gen_range(1.0..100.0),
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut next: Option<String> = None;
+    loop {
+        let limit = rng.gen_range(0..=count + 1);
+        let page = store
+            .all(next.as_deref(), limit, &ProductFilter::default(), None)
+            .await?;
+        // Stores return at least one product per page, to make progress
+        assert!(
+            page.products.len() <= limit.max(1),
+            "page exceeds limit {}",
+            limit
+        );
+        for product in page.products {
+            assert!(
+                seen.insert(product.id.clone()),
+                "{} returned twice",
+                product.id
+            );
+        }
+        next = match page.next {
+            Some(token) if Some(&token) == next.as_ref() => {
+                panic!("next token {} did not advance", token)
+            }
+            Some(token) => Some(token),
+            None => break,
+        };
+    }
+
+    assert_eq!(seen, inserted);
+    Ok(())
+}
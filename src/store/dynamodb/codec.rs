@@ -0,0 +1,194 @@
+//! # DynamoDB item codecs
+//!
+//! Codecs convert values, such as products, from and to DynamoDB items.
+//! `SerdeCodec` works for any type implementing `Serialize` and
+//! `Deserialize`, the same way `serde_dynamo` does: structs become items
+//! with an attribute per field, maps become `M` values and sequences `L`
+//! values. Adding a field to `Product` is then enough to store it, read it
+//! back and decode it from stream records.
+//!
+//! Codecs are generic over the attribute type. The DynamoDB Streams handlers
+//! convert the images of stream records into SDK attribute values, and
+//! decode them with the codec of the store through an `ItemDecoder`.
+
+use crate::Error;
+use aws_sdk_dynamodb::model::AttributeValue;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use tracing::error;
+
+/// Attribute values that can be converted from and to JSON values
+pub trait ItemAttribute: Sized {
+    fn from_json(value: &Value) -> Self;
+
+    /// Convert into a JSON value
+    ///
+    /// Binary values and sets have no JSON equivalent and return `None`.
+    fn to_json(&self) -> Option<Value>;
+}
+
+/// Trait for converting values from and to DynamoDB items
+pub trait ItemCodec<T, A = AttributeValue>: Send + Sync {
+    fn encode(&self, value: &T) -> Result<HashMap<String, A>, Error>;
+
+    fn decode(&self, item: &HashMap<String, A>) -> Result<T, Error>;
+}
+
+/// Codec for types implementing `Serialize` and `Deserialize`
+///
+/// Fields serialized as `null` are left out of the item, as DynamoDB
+/// attributes are optional. Fields that are missing from an item must have
+/// a `#[serde(default)]` to be decoded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SerdeCodec;
+
+impl<T, A> ItemCodec<T, A> for SerdeCodec
+where
+    T: Serialize + DeserializeOwned,
+    A: ItemAttribute,
+{
+    fn encode(&self, value: &T) -> Result<HashMap<String, A>, Error> {
+        match serde_json::to_value(value) {
+            Ok(Value::Object(fields)) => Ok(fields
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), A::from_json(v)))
+                .collect()),
+            Ok(_) => Err(Error::InternalError("Only structs can be encoded as items")),
+            Err(err) => {
+                error!("Unable to encode item: {}", err);
+                Err(Error::InternalError("Unable to encode item"))
+            }
+        }
+    }
+
+    fn decode(&self, item: &HashMap<String, A>) -> Result<T, Error> {
+        let fields: Map<String, Value> = item
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.to_json()?)))
+            .collect();
+        serde_json::from_value(Value::Object(fields)).map_err(|err| {
+            error!("Unable to decode item: {}", err);
+            Error::InternalError("Unable to decode item")
+        })
+    }
+}
+
+/// Format a JSON number as a DynamoDB number
+///
+/// Floats are formatted without a trailing `.0`, so that `10.0` is stored
+/// as `10`.
+pub(crate) fn format_number(n: &Number) -> String {
+    match n.as_f64() {
+        Some(f) if n.is_f64() => f.to_string(),
+        _ => n.to_string(),
+    }
+}
+
+impl ItemAttribute for AttributeValue {
+    fn from_json(value: &Value) -> Self {
+        match value {
+            Value::Null => AttributeValue::Null(true),
+            Value::Bool(b) => AttributeValue::Bool(*b),
+            Value::Number(n) => AttributeValue::N(format_number(n)),
+            Value::String(s) => AttributeValue::S(s.clone()),
+            Value::Array(values) => {
+                AttributeValue::L(values.iter().map(AttributeValue::from_json).collect())
+            }
+            Value::Object(values) => AttributeValue::M(
+                values
+                    .iter()
+                    .map(|(k, v)| (k.clone(), AttributeValue::from_json(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn to_json(&self) -> Option<Value> {
+        Some(match self {
+            AttributeValue::Null(_) => Value::Null,
+            AttributeValue::Bool(b) => Value::Bool(*b),
+            AttributeValue::N(n) => Value::Number(serde_json::from_str(n).ok()?),
+            AttributeValue::S(s) => Value::String(s.clone()),
+            AttributeValue::L(values) => {
+                Value::Array(values.iter().filter_map(|v| v.to_json()).collect())
+            }
+            AttributeValue::M(values) => Value::Object(
+                values
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.clone(), v.to_json()?)))
+                    .collect(),
+            ),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_product() -> Result<(), Error> {
+        // GIVEN a product with custom attributes
+        let product = Product {
+            id: "id".to_owned(),
+            name: "name".to_owned(),
+            price: 10.0,
+            attributes: [("size".to_string(), json!({"eu": 42}))]
+                .into_iter()
+                .collect(),
+            images: Default::default(),
+        };
+
+        // WHEN encoding it
+        let item: HashMap<String, AttributeValue> = SerdeCodec.encode(&product)?;
+
+        // THEN each field is an attribute
+        assert_eq!(item.get("id").unwrap().as_s().unwrap(), "id");
+        assert_eq!(item.get("name").unwrap().as_s().unwrap(), "name");
+        assert_eq!(item.get("price").unwrap().as_n().unwrap(), "10");
+        assert_eq!(
+            item.get("attributes").unwrap().as_m().unwrap()["size"],
+            AttributeValue::M(HashMap::from([(
+                "eu".to_string(),
+                AttributeValue::N("42".to_string())
+            )]))
+        );
+        // AND empty fields skipped by serde are left out
+        assert!(!item.contains_key("images"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_product() -> Result<(), Error> {
+        // GIVEN an item without optional fields
+        let item = HashMap::from([
+            ("id".to_owned(), AttributeValue::S("id".to_owned())),
+            ("name".to_owned(), AttributeValue::S("name".to_owned())),
+            ("price".to_owned(), AttributeValue::N("1.0".to_owned())),
+            (
+                "deleted_at".to_owned(),
+                AttributeValue::N("1000".to_owned()),
+            ),
+        ]);
+
+        // WHEN decoding it
+        let product: Product = SerdeCodec.decode(&item)?;
+
+        // THEN the fields are read, and unknown attributes ignored
+        assert_eq!(product.id, "id");
+        assert_eq!(product.name, "name");
+        assert_eq!(product.price, 1.0);
+        assert!(product.attributes.is_empty());
+        // AND missing fields are an error
+        let item = HashMap::from([("id".to_owned(), AttributeValue::S("id".to_owned()))]);
+        assert!(ItemCodec::<Product>::decode(&SerdeCodec, &item).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! # Product item decoder
+//!
+//! Decodes the items of the product table into products. The DynamoDB store
+//! uses it for the items it reads, and the DynamoDB Streams handlers for the
+//! images of stream records, so that both decode items the same way.
+//!
+//! With a key prefix, only the items of that namespace belong to the
+//! decoder, and the prefix is removed from the ids of the products.
+//!
+//! Items of sharded products are decoded into the product they are a copy
+//! of: the shard number is removed from the id, and the attributes used to
+//! find the latest shard are left out. The time of the last write, which
+//! other items can record too, is always left out.
+
+use super::{ItemCodec, SerdeCodec, PREVIOUS, SHARD, UPDATED_AT};
+use crate::{Error, Product};
+use aws_sdk_dynamodb::model::AttributeValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Decoder of the items of the product table
+///
+/// Cloning it is cheap, as the codec is shared.
+#[derive(Clone)]
+pub struct ItemDecoder {
+    pub(super) codec: Arc<dyn ItemCodec<Product>>,
+    key_prefix: String,
+}
+
+impl Default for ItemDecoder {
+    fn default() -> Self {
+        ItemDecoder::new(SerdeCodec)
+    }
+}
+
+impl ItemDecoder {
+    pub fn new(codec: impl ItemCodec<Product> + 'static) -> ItemDecoder {
+        ItemDecoder {
+            codec: Arc::new(codec),
+            key_prefix: String::new(),
+        }
+    }
+
+    /// Only decode the items of the given namespace
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> ItemDecoder {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Whether the item with this key belongs to the namespace
+    pub fn owns(&self, key: &str) -> bool {
+        key.starts_with(self.key_prefix.as_str())
+    }
+
+    /// Remove the namespace from a product id read from the table
+    pub fn strip_prefix<'a>(&self, id: &'a str) -> &'a str {
+        id.strip_prefix(self.key_prefix.as_str()).unwrap_or(id)
+    }
+
+    /// Decode an item into a product outside of the namespace
+    pub fn decode(&self, item: &HashMap<String, AttributeValue>) -> Result<Product, Error> {
+        if !is_shard(item) {
+            let mut product = if item.contains_key(UPDATED_AT) {
+                let mut item = item.clone();
+                item.remove(UPDATED_AT);
+                self.codec.decode(&item)?
+            } else {
+                self.codec.decode(item)?
+            };
+            product.id = self.strip_prefix(&product.id).to_owned();
+            return Ok(product);
+        }
+
+        let mut item = item.clone();
+        for name in [SHARD, UPDATED_AT, PREVIOUS] {
+            item.remove(name);
+        }
+        let mut product = self.codec.decode(&item)?;
+        product.id = self.strip_prefix(&product.id).to_owned();
+        if let Some((id, _)) = product.id.rsplit_once('#') {
+            product.id = id.to_owned();
+        }
+        Ok(product)
+    }
+
+    /// Decode the version a sharded item replaced, if any
+    ///
+    /// Each write to a sharded product records the latest version across
+    /// all shards at the time of the write, as the previous copy in the
+    /// same shard is usually outdated.
+    pub fn decode_previous(
+        &self,
+        item: &HashMap<String, AttributeValue>,
+    ) -> Result<Option<Product>, Error> {
+        match item.get(PREVIOUS) {
+            Some(AttributeValue::M(previous)) => Ok(Some(self.codec.decode(previous)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Whether an item is one of the shards of a product
+fn is_shard(item: &HashMap<String, AttributeValue>) -> bool {
+    item.contains_key(SHARD)
+}
@@ -0,0 +1,187 @@
+//! # DynamoDB S3 exports
+//!
+//! Readers for the native DynamoDB export to S3 format, as produced by
+//! point-in-time recovery exports. An export contains a `manifest-files.json`
+//! file listing the data files, one JSON object per line, and gzipped data
+//! files with one item per line, such as:
+//!
+//! ```json
+//! {"Item": {"id": {"S": "foo"}, "name": {"S": "Foo"}, "price": {"N": "10.5"}}}
+//! ```
+//!
+//! Items are converted into products with the same codec as items read
+//! from the table.
+
+use super::{ItemCodec, SerdeCodec, DELETED_AT};
+use crate::{Error, Product};
+use aws_sdk_dynamodb::model::AttributeValue;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+
+/// Entry of the `manifest-files.json` file
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestFile {
+    data_file_s3_key: String,
+}
+
+/// Line of a data file
+#[derive(Deserialize)]
+struct ExportLine {
+    #[serde(rename = "Item")]
+    item: HashMap<String, Value>,
+}
+
+/// Return the S3 keys of the data files listed in a `manifest-files.json`
+pub fn parse_export_manifest(body: &[u8]) -> Result<Vec<String>, Error> {
+    body.split(|b| *b == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|line| {
+            serde_json::from_slice::<ManifestFile>(line)
+                .map(|file| file.data_file_s3_key)
+                .map_err(|_| Error::ClientError("Invalid DynamoDB export manifest"))
+        })
+        .collect()
+}
+
+/// Return the products in a gzipped data file
+///
+/// Soft-deleted items are skipped.
+pub fn parse_export_data(body: &[u8]) -> Result<Vec<Product>, Error> {
+    let mut products = Vec::new();
+    for line in BufReader::new(GzDecoder::new(body)).lines() {
+        let line = line.map_err(|_| Error::ClientError("Invalid DynamoDB export data file"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line: ExportLine = serde_json::from_str(&line)
+            .map_err(|_| Error::ClientError("Invalid item in DynamoDB export"))?;
+        let item = line
+            .item
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, to_attribute_value(value)?)))
+            .collect::<HashMap<_, _>>();
+        if item.contains_key(DELETED_AT) {
+            continue;
+        }
+        products.push(
+            SerdeCodec
+                .decode(&item)
+                .map_err(|_| Error::ClientError("Invalid product in DynamoDB export"))?,
+        );
+    }
+
+    Ok(products)
+}
+
+/// Convert an attribute value in DynamoDB JSON into an `AttributeValue`
+///
+/// Binary attributes are not used by products, and are skipped rather than
+/// decoded.
+fn to_attribute_value(value: Value) -> Option<AttributeValue> {
+    let (kind, value) = match value {
+        Value::Object(map) if map.len() == 1 => map.into_iter().next()?,
+        _ => return None,
+    };
+
+    Some(match (kind.as_str(), value) {
+        ("S", Value::String(s)) => AttributeValue::S(s),
+        ("N", Value::String(n)) => AttributeValue::N(n),
+        ("BOOL", Value::Bool(b)) => AttributeValue::Bool(b),
+        ("NULL", Value::Bool(b)) => AttributeValue::Null(b),
+        ("SS", Value::Array(values)) => AttributeValue::Ss(strings(values)?),
+        ("NS", Value::Array(values)) => AttributeValue::Ns(strings(values)?),
+        ("L", Value::Array(values)) => {
+            AttributeValue::L(values.into_iter().filter_map(to_attribute_value).collect())
+        }
+        ("M", Value::Object(map)) => AttributeValue::M(
+            map.into_iter()
+                .filter_map(|(key, value)| Some((key, to_attribute_value(value)?)))
+                .collect(),
+        ),
+        _ => return None,
+    })
+}
+
+/// Convert a JSON array of strings, as used by string and number sets
+fn strings(values: Vec<Value>) -> Option<Vec<String>> {
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(s) => Some(s),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn gzip(data: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parse_export_manifest() -> Result<(), Error> {
+        let manifest = r#"{"itemCount":2,"md5Checksum":"abc","etag":"def","dataFileS3Key":"AWSDynamoDB/01/data/a.json.gz"}
+{"itemCount":1,"md5Checksum":"ghi","etag":"jkl","dataFileS3Key":"AWSDynamoDB/01/data/b.json.gz"}
+"#;
+
+        let keys = parse_export_manifest(manifest.as_bytes())?;
+
+        assert_eq!(
+            keys,
+            vec![
+                "AWSDynamoDB/01/data/a.json.gz",
+                "AWSDynamoDB/01/data/b.json.gz"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_export_data() -> Result<(), Error> {
+        // GIVEN a data file with a live and a deleted item
+        let data = gzip(
+            r#"{"Item":{"id":{"S":"1"},"name":{"S":"foo"},"price":{"N":"10.5"},"tags":{"SS":["a"]}}}
+{"Item":{"id":{"S":"2"},"name":{"S":"bar"},"price":{"N":"1"},"deleted_at":{"N":"1"}}}
+"#,
+        );
+
+        // WHEN parsing the data file
+        let products = parse_export_data(&data)?;
+
+        // THEN only the live item is returned
+        assert_eq!(
+            products,
+            vec![Product {
+                id: "1".to_string(),
+                name: "foo".to_string(),
+                price: 10.5,
+                attributes: Default::default(),
+                images: Default::default(),
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_export_data_invalid() {
+        let data = gzip("{\"Item\":{\"id\":{\"S\":\"1\"}}}\n");
+
+        let res = parse_export_data(&data);
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+}
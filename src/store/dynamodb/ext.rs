@@ -11,6 +11,7 @@ use std::collections::HashMap;
 pub trait AttributeValuesExt {
     fn get_s(&self, key: &str) -> Option<String>;
     fn get_n(&self, key: &str) -> Option<f64>;
+    fn get_ss(&self, key: &str) -> Option<Vec<String>>;
 }
 
 impl AttributeValuesExt for HashMap<String, AttributeValue> {
@@ -45,6 +46,22 @@ impl AttributeValuesExt for HashMap<String, AttributeValue> {
     fn get_n(&self, key: &str) -> Option<f64> {
         self.get(key)?.as_n().ok()?.parse::<f64>().ok()
     }
+
+    /// Return a string set from a key
+    ///
+    /// E.g. if you run `get_ss("event_types")` on a DynamoDB item structured
+    /// like this, you will retrieve the value `vec!["Created", "Deleted"]`.
+    ///
+    /// ```json
+    /// {
+    ///   "event_types": {
+    ///     "SS": ["Created", "Deleted"]
+    ///   }
+    /// }
+    /// ```
+    fn get_ss(&self, key: &str) -> Option<Vec<String>> {
+        Some(self.get(key)?.as_ss().ok()?.to_owned())
+    }
 }
 
 #[cfg(test)]
@@ -82,4 +99,15 @@ mod tests {
 
         assert_eq!(item.get_n("foo"), None);
     }
+
+    #[test]
+    fn attributevalue_get_ss() {
+        let mut item = HashMap::new();
+        item.insert(
+            "event_types".to_owned(),
+            AttributeValue::Ss(vec!["Created".to_owned()]),
+        );
+
+        assert_eq!(item.get_ss("event_types"), Some(vec!["Created".to_owned()]));
+    }
 }
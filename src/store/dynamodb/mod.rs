@@ -1,26 +1,922 @@
 //! # DynamoDB store implementation
 //!
 //! Store implementation using the AWS SDK for DynamoDB.
+//!
+//! Product ids can be prefixed with a namespace, so that multiple instances
+//! of the application can share the same table. The prefix is added when
+//! writing to the table and removed when reading from it.
+//!
+//! Very hot product ids can be sharded to avoid throttling a single
+//! partition: each write goes to one of several items, with the shard number
+//! appended to the key as `{id}#{shard}`, and reads fan out to all shards to
+//! return the most recent write. Listings skip the shards and return sharded
+//! products on their first page instead.
+//!
+//! Each write to a shard also holds the version it replaced, the latest one
+//! across shards, so that DynamoDB Streams records of a shard are converted
+//! into events against the latest version rather than the outdated copy in
+//! that shard. DynamoDB Streams only orders the records of a single item
+//! though, so the events of a sharded product can be delivered out of order.
+//! Deletes, restores and scheduled deletions change every shard, and produce
+//! an event per shard.
+//!
+//! Products are converted from and to items by an `ItemCodec`, `SerdeCodec`
+//! by default. `item_decoder` returns a decoder using the same codec, for
+//! the DynamoDB Streams handlers to decode stream records like the store.
+//!
+//! With an outbox table, puts, updates, deletes, restores and scheduled
+//! deletions read the item first, and also write an outbox entry holding
+//! their event in the same transaction, see the `outbox` module. `put_many`,
+//! and the deletes, restores and scheduled deletions of sharded products,
+//! write no outbox entry, as they don't fit in a single transaction.
+//!
+//! Writes at an expected version read the item too, and are conditioned on
+//! it being unchanged, so that they only apply if the version of the product
+//! was the expected one when it was written. Sharded products are checked
+//! before the write instead.
 
-use super::{Store, StoreDelete, StoreGet, StoreGetAll, StorePut};
-use crate::{Error, Product, ProductRange};
+use super::{
+    project, ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet, StoreCount,
+    StoreDelete, StoreExpire, StoreGet, StoreGetAll, StoreGetWriteTime, StoreHealth, StorePut,
+    StorePutConditional, StorePutMany, StoreRestore, StoreScanAll, StoreUpdate,
+};
+use crate::{
+    outbox::{entry_to_item, OutboxEntry},
+    Error, Event, Product, ProductBatch, ProductPatch, ProductRange, SdkErrorDetails,
+};
 use async_trait::async_trait;
-use aws_sdk_dynamodb::{model::AttributeValue, Client};
-use std::collections::HashMap;
-use tracing::{info, instrument};
+use aws_sdk_dynamodb::{
+    client::fluent_builders::Scan,
+    error::{TransactWriteItemsError, TransactWriteItemsErrorKind},
+    model::{
+        AttributeValue, Delete, KeysAndAttributes, Put, PutRequest, ReturnValue, Select,
+        TransactWriteItem, Update, WriteRequest,
+    },
+    output::BatchGetItemOutput,
+    Client,
+};
+use aws_smithy_http::result::SdkError;
+use futures::{
+    future::{join_all, select, Either},
+    pin_mut,
+    stream::{self, StreamExt},
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, timeout_at, Instant};
+use tracing::{info, instrument, warn};
 
-mod ext;
+mod codec;
+mod decoder;
+mod export;
+pub(super) mod ext;
+mod partiql;
+pub use codec::{ItemAttribute, ItemCodec, SerdeCodec};
+pub use decoder::ItemDecoder;
+pub use export::{parse_export_data, parse_export_manifest};
 use ext::AttributeValuesExt;
+pub use partiql::DynamoDBPartiQLStore;
+
+/// Attribute marking soft-deleted items, as seconds since the UNIX epoch
+const DELETED_AT: &str = "deleted_at";
+
+/// TTL attribute of the table, as seconds since the UNIX epoch
+const EXPIRES_AT: &str = "expires_at";
+
+/// Filter expression excluding soft-deleted items
+const NOT_DELETED: &str = "attribute_not_exists(deleted_at)";
+
+/// Attribute holding the shard number of sharded items
+const SHARD: &str = "shard";
+
+/// Attribute holding the time of the write of sharded items, as microseconds
+/// since the UNIX epoch
+const UPDATED_AT: &str = "updated_at";
+
+/// Attribute of sharded items holding the latest version of the product
+/// before the write, as a map
+const PREVIOUS: &str = "previous";
+
+/// Filter expression excluding sharded items
+const NOT_SHARDED: &str = "attribute_not_exists(shard)";
+
+/// Attribute holding the custom attributes of a product, as a map
+const ATTRIBUTES: &str = "attributes";
+
+/// Attribute holding the image keys of a product, as a list
+const IMAGES: &str = "images";
+
+/// Optional attributes of product items, which must still be missing from an
+/// item read without them for a conditional write to succeed
+const OPTIONAL_ATTRIBUTES: [&str; 4] = [ATTRIBUTES, IMAGES, DELETED_AT, EXPIRES_AT];
+
+/// Maximum number of keys in a `BatchGetItem` request
+const BATCH_GET_SIZE: usize = 100;
+
+/// Default number of `BatchGetItem` requests in flight at once
+const BATCH_GET_CONCURRENCY: usize = 4;
+
+/// Maximum number of items in a `BatchWriteItem` request
+const BATCH_WRITE_SIZE: usize = 25;
+
+/// Maximum number of attempts to read unprocessed keys or write unprocessed
+/// items
+const MAX_ATTEMPTS: usize = 5;
+
+/// Condition expression checking that an item is still as it was read
+///
+/// Returns the expression, and its attribute names and values if any.
+/// Optional attributes missing from the item must still be missing, so that
+/// a concurrent soft delete or expiry fails the condition.
+#[allow(clippy::type_complexity)]
+fn unchanged_condition(
+    item: Option<HashMap<String, AttributeValue>>,
+) -> (
+    String,
+    Option<HashMap<String, String>>,
+    Option<HashMap<String, AttributeValue>>,
+) {
+    let item = match item {
+        Some(item) if !item.is_empty() => item,
+        _ => return ("attribute_not_exists(id)".to_string(), None, None),
+    };
+
+    let mut attributes: Vec<(String, AttributeValue)> = item.into_iter().collect();
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut conditions = Vec::new();
+    let mut names = HashMap::new();
+    let mut values = HashMap::new();
+    for name in OPTIONAL_ATTRIBUTES {
+        if !attributes.iter().any(|(n, _)| n == name) {
+            conditions.push(format!("attribute_not_exists(#{})", name));
+            names.insert(format!("#{}", name), name.to_owned());
+        }
+    }
+    for (i, (name, value)) in attributes.into_iter().enumerate() {
+        conditions.push(format!("#c{} = :c{}", i, i));
+        names.insert(format!("#c{}", i), name);
+        values.insert(format!(":c{}", i), value);
+    }
+    (conditions.join(" AND "), Some(names), Some(values))
+}
+
+/// Write to an item of the product table, made in a transaction with the
+/// outbox entry of its event, if any
+enum ItemWrite {
+    Put(HashMap<String, AttributeValue>),
+    Update {
+        expression: String,
+        values: HashMap<String, AttributeValue>,
+    },
+    Delete,
+}
+
+/// Projection expression to retrieve only some fields of a product
+///
+/// The id and the deletion marker are always retrieved. All attributes use
+/// placeholders, as `name` is a reserved word in DynamoDB expressions.
+fn projection(fields: &[ProductField]) -> (String, Vec<(String, String)>) {
+    let mut attributes = vec![ProductField::Id.as_str(), DELETED_AT];
+    attributes.extend(
+        fields
+            .iter()
+            .filter(|f| **f != ProductField::Id)
+            .map(|f| f.as_str()),
+    );
+
+    let expression = attributes
+        .iter()
+        .map(|a| format!("#{}", a))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let names = attributes
+        .iter()
+        .map(|a| (format!("#{}", a), a.to_string()))
+        .collect();
+    (expression, names)
+}
 
 /// DynamoDB store implementation.
 pub struct DynamoDBStore {
     client: Client,
     table_name: String,
+    key_prefix: String,
+    hot_ids: HashSet<String>,
+    shards: usize,
+    next_shard: AtomicUsize,
+    batch_get_concurrency: usize,
+    hedge_after: Option<Duration>,
+    decoder: ItemDecoder,
+    outbox_table_name: Option<String>,
+    record_write_times: bool,
 }
 
 impl DynamoDBStore {
     pub fn new(client: Client, table_name: String) -> DynamoDBStore {
-        DynamoDBStore { client, table_name }
+        DynamoDBStore {
+            client,
+            table_name,
+            key_prefix: String::new(),
+            hot_ids: HashSet::new(),
+            shards: 1,
+            next_shard: AtomicUsize::new(0),
+            batch_get_concurrency: BATCH_GET_CONCURRENCY,
+            hedge_after: None,
+            decoder: ItemDecoder::default(),
+            outbox_table_name: None,
+            record_write_times: false,
+        }
+    }
+
+    /// Record the time of each put and update of a product in its item, for
+    /// `get_with_write_time`
+    ///
+    /// Writes to sharded products always record their time.
+    pub fn with_write_times(mut self) -> DynamoDBStore {
+        self.record_write_times = true;
+        self
+    }
+
+    /// Write the event of each change to the given outbox table, in the same
+    /// transaction as the product
+    ///
+    /// This doesn't apply to `put_many`, nor to the deletes, restores and
+    /// scheduled deletions of sharded products.
+    pub fn with_outbox(mut self, table_name: impl Into<String>) -> DynamoDBStore {
+        self.outbox_table_name = Some(table_name.into());
+        self
+    }
+
+    /// Convert products from and to items with a custom codec
+    pub fn with_codec(mut self, codec: impl ItemCodec<Product> + 'static) -> DynamoDBStore {
+        self.decoder = ItemDecoder::new(codec).with_key_prefix(self.key_prefix.clone());
+        self
+    }
+
+    /// Decoder of the items of the table, using the codec of the store
+    pub fn item_decoder(&self) -> ItemDecoder {
+        self.decoder.clone()
+    }
+
+    /// Prefix all product ids with the given namespace
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> DynamoDBStore {
+        self.key_prefix = key_prefix.into();
+        self.decoder = self.decoder.with_key_prefix(self.key_prefix.clone());
+        self
+    }
+
+    /// Spread writes to the given product ids across `shards` items
+    ///
+    /// Sharding is disabled with less than two shards.
+    pub fn with_sharding(
+        mut self,
+        hot_ids: impl IntoIterator<Item = String>,
+        shards: usize,
+    ) -> DynamoDBStore {
+        self.hot_ids = hot_ids.into_iter().collect();
+        self.shards = shards;
+        self
+    }
+
+    /// Send up to `concurrency` `BatchGetItem` requests at once when
+    /// retrieving many products
+    pub fn with_batch_get_concurrency(mut self, concurrency: usize) -> DynamoDBStore {
+        self.batch_get_concurrency = concurrency;
+        self
+    }
+
+    /// Send `BatchGetItem` requests again if they take longer than `delay`
+    pub fn with_hedging(mut self, delay: Duration) -> DynamoDBStore {
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    /// Key of a product in the table
+    fn key(&self, id: &str) -> AttributeValue {
+        AttributeValue::S(format!("{}{}", self.key_prefix, id))
+    }
+
+    /// Whether writes to this product are sharded
+    fn is_sharded(&self, id: &str) -> bool {
+        self.shards > 1 && self.hot_ids.contains(id)
+    }
+
+    /// Whether any product is sharded
+    fn has_sharding(&self) -> bool {
+        self.shards > 1 && !self.hot_ids.is_empty()
+    }
+
+    /// Keys of all the shards of a product
+    fn shard_keys(&self, id: &str) -> Vec<AttributeValue> {
+        (0..self.shards)
+            .map(|shard| AttributeValue::S(format!("{}{}#{}", self.key_prefix, id, shard)))
+            .collect()
+    }
+
+    /// Convert a DynamoDB item into a Product outside of the namespace
+    ///
+    /// If only some `fields` were retrieved, the missing fields keep their
+    /// default value.
+    fn to_product(
+        &self,
+        item: HashMap<String, AttributeValue>,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Product, Error> {
+        match fields {
+            None => self.decoder.decode(&item),
+            Some(_) => self.decoder.decode(&with_defaults(item)),
+        }
+    }
+
+    /// Convert a product into the item to write
+    ///
+    /// Writes to sharded products are spread across shards in a round-robin
+    /// fashion, and record their time so that reads return the latest one.
+    /// They also hold the `previous` latest version of the product, if any.
+    fn to_item(
+        &self,
+        product: &Product,
+        previous: Option<&Product>,
+    ) -> Result<HashMap<String, AttributeValue>, Error> {
+        let mut item = self.decoder.codec.encode(product)?;
+        if self.is_sharded(&product.id) {
+            let shard = self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards;
+            item.insert("id".to_owned(), self.shard_keys(&product.id).remove(shard));
+            item.insert(SHARD.to_owned(), AttributeValue::N(shard.to_string()));
+            item.insert(UPDATED_AT.to_owned(), now_micros()?);
+            if let Some(previous) = previous {
+                item.insert(
+                    PREVIOUS.to_owned(),
+                    AttributeValue::M(self.decoder.codec.encode(previous)?),
+                );
+            }
+        } else {
+            item.insert("id".to_owned(), self.key(&product.id));
+            if self.record_write_times {
+                item.insert(UPDATED_AT.to_owned(), now_micros()?);
+            }
+        }
+        Ok(item)
+    }
+
+    /// Only return items that are not deleted, belong to the namespace and
+    /// match the product filter
+    fn scan_filter(&self, mut req: Scan, filter: &ProductFilter) -> Scan {
+        let mut conditions = vec![NOT_DELETED];
+        if self.has_sharding() {
+            conditions.push(NOT_SHARDED);
+        }
+        if !self.key_prefix.is_empty() {
+            conditions.push("begins_with(id, :key_prefix)");
+            req = req.expression_attribute_values(
+                ":key_prefix",
+                AttributeValue::S(self.key_prefix.clone()),
+            );
+        }
+        if let Some(min_price) = filter.min_price {
+            conditions.push("price >= :min_price");
+            req = req.expression_attribute_values(
+                ":min_price",
+                AttributeValue::N(format!("{:}", min_price)),
+            );
+        }
+        if let Some(max_price) = filter.max_price {
+            conditions.push("price <= :max_price");
+            req = req.expression_attribute_values(
+                ":max_price",
+                AttributeValue::N(format!("{:}", max_price)),
+            );
+        }
+        if let Some(name) = &filter.name_contains {
+            // `name` is a reserved word in DynamoDB expressions
+            conditions.push("contains(#name, :name)");
+            req = req
+                .expression_attribute_names("#name", "name")
+                .expression_attribute_values(":name", AttributeValue::S(name.clone()));
+        }
+
+        // Attribute filters match any type the query string value parses to
+        //
+        // `attributes` is a reserved word, and keys could be reserved words
+        // too, so they all use placeholders.
+        let mut conditions: Vec<String> = conditions.into_iter().map(String::from).collect();
+        if !filter.attributes.is_empty() {
+            req = req.expression_attribute_names("#attributes", ATTRIBUTES);
+        }
+        for (i, (key, value)) in filter.attributes.iter().enumerate() {
+            let path = format!("#attributes.#attr{}", i);
+            let mut alternatives = vec![format!("{} = :attr{}s", path, i)];
+            req = req
+                .expression_attribute_names(format!("#attr{}", i), key)
+                .expression_attribute_values(
+                    format!(":attr{}s", i),
+                    AttributeValue::S(value.clone()),
+                );
+            if let Ok(n) = value.parse::<f64>() {
+                alternatives.push(format!("{} = :attr{}n", path, i));
+                req = req.expression_attribute_values(
+                    format!(":attr{}n", i),
+                    AttributeValue::N(format!("{:}", n)),
+                );
+            }
+            if let Ok(b) = value.parse::<bool>() {
+                alternatives.push(format!("{} = :attr{}b", path, i));
+                req = req
+                    .expression_attribute_values(format!(":attr{}b", i), AttributeValue::Bool(b));
+            }
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        req.filter_expression(conditions.join(" AND "))
+    }
+
+    /// Get items by key, retrying unprocessed keys
+    ///
+    /// Deleted items are included, as the caller might need to compare them
+    /// with other shards.
+    async fn batch_get_items(
+        &self,
+        keys: Vec<AttributeValue>,
+        consistency: ReadConsistency,
+    ) -> Result<Vec<HashMap<String, AttributeValue>>, Error> {
+        self.batch_get_items_until(keys, consistency, None)
+            .await
+            .map(|(items, _)| items)
+    }
+
+    /// Get items by key in parallel batches, until an optional deadline
+    ///
+    /// Batches are sent with up to `batch_get_concurrency` requests in
+    /// flight. If the deadline passes, the items read so far are returned
+    /// and the result is flagged as partial.
+    async fn batch_get_items_until(
+        &self,
+        keys: Vec<AttributeValue>,
+        consistency: ReadConsistency,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<HashMap<String, AttributeValue>>, bool), Error> {
+        let results: Vec<_> = stream::iter(keys.chunks(BATCH_GET_SIZE))
+            .map(|chunk| self.batch_get_chunk(chunk, consistency, deadline))
+            .buffer_unordered(self.batch_get_concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut items = Vec::new();
+        let mut partial = false;
+        for res in results {
+            let (chunk_items, chunk_partial) = res?;
+            items.extend(chunk_items);
+            partial |= chunk_partial;
+        }
+
+        Ok((items, partial))
+    }
+
+    /// Get a single batch of items, retrying unprocessed keys
+    async fn batch_get_chunk(
+        &self,
+        keys: &[AttributeValue],
+        consistency: ReadConsistency,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<HashMap<String, AttributeValue>>, bool), Error> {
+        let mut builder = KeysAndAttributes::builder().set_keys(Some(
+            keys.iter()
+                .map(|key| HashMap::from([("id".to_owned(), key.clone())]))
+                .collect(),
+        ));
+        if consistency == ReadConsistency::Strong {
+            builder = builder.consistent_read(true);
+        }
+        let mut keys = Some(builder.build());
+        let mut items = Vec::new();
+
+        // Retry unprocessed keys
+        //
+        // DynamoDB can return unprocessed keys when the table is throttled,
+        // which need to be requested again.
+        let mut attempts = 0;
+        while let Some(request) = keys.take() {
+            if attempts == MAX_ATTEMPTS {
+                return Err(Error::InternalError("Failed to get all items"));
+            }
+            attempts += 1;
+
+            let res = match deadline {
+                Some(deadline) => match timeout_at(deadline, self.send_batch_get(request)).await {
+                    Ok(res) => res?,
+                    // Out of time: return what was read so far
+                    Err(_) => return Ok((items, true)),
+                },
+                None => self.send_batch_get(request).await?,
+            };
+
+            items.extend(
+                res.responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default(),
+            );
+            keys = res
+                .unprocessed_keys
+                .and_then(|mut keys| keys.remove(&self.table_name))
+                .filter(|keys| keys.keys.as_ref().map_or(false, |k| !k.is_empty()));
+        }
+
+        Ok((items, false))
+    }
+
+    /// Send a `BatchGetItem` request, hedging slow requests
+    ///
+    /// If hedging is enabled and no response arrived after the hedging delay,
+    /// the same request is sent again and the first response wins. This cuts
+    /// tail latency at the cost of extra read capacity.
+    async fn send_batch_get(
+        &self,
+        request: KeysAndAttributes,
+    ) -> Result<BatchGetItemOutput, Error> {
+        let send = || {
+            self.client
+                .batch_get_item()
+                .request_items(&self.table_name, request.clone())
+                .send()
+        };
+        let delay = match self.hedge_after {
+            Some(delay) => delay,
+            None => return Ok(send().await?),
+        };
+
+        let first = send();
+        let hedged = async {
+            sleep(delay).await;
+            send().await
+        };
+        pin_mut!(first, hedged);
+        match select(first, hedged).await {
+            Either::Left((res, _)) | Either::Right((res, _)) => Ok(res?),
+        }
+    }
+
+    /// Get a sharded product by reading all its shards
+    ///
+    /// The most recent write wins, even if it was deleted afterwards.
+    async fn get_sharded(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+    ) -> Result<Option<Product>, Error> {
+        Ok(self
+            .get_latest_shard(id, consistency)
+            .await?
+            .map(|(product, _)| product))
+    }
+
+    /// Get the latest copy of a sharded product, with the time of its write
+    async fn get_latest_shard(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+    ) -> Result<Option<(Product, Option<u64>)>, Error> {
+        info!("Getting {} shards of item with id '{}'", self.shards, id);
+        let items = self
+            .batch_get_items(self.shard_keys(id), consistency)
+            .await?;
+        let latest = items.into_iter().max_by(|a, b| {
+            let a = a.get_n(UPDATED_AT).unwrap_or_default();
+            let b = b.get_n(UPDATED_AT).unwrap_or_default();
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Ok(match latest {
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let written_at = write_time(&item);
+                Some((self.to_product(item, None)?, written_at))
+            }
+            _ => None,
+        })
+    }
+
+    /// Write a product and the outbox entry of its event in a transaction
+    ///
+    /// The previous item is read first, and the write is conditioned on it
+    /// being unchanged, so that the event holds the product it replaced. The
+    /// transaction is attempted again if a concurrent write changed it.
+    /// Sharded products are written without condition, as their latest
+    /// version can be in any shard.
+    async fn put_with_outbox(
+        &self,
+        product: &Product,
+        outbox_table_name: &str,
+    ) -> Result<Option<Product>, Error> {
+        let sharded = self.is_sharded(&product.id);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (previous, condition) = if sharded {
+                let previous = self
+                    .get_sharded(&product.id, ReadConsistency::Strong)
+                    .await?;
+                (previous, None)
+            } else {
+                let item = self
+                    .client
+                    .get_item()
+                    .table_name(&self.table_name)
+                    .key("id", self.key(&product.id))
+                    .consistent_read(true)
+                    .send()
+                    .await?
+                    .item;
+                let previous = match &item {
+                    Some(item) if !item.is_empty() && !item.contains_key(DELETED_AT) => {
+                        Some(self.to_product(item.clone(), None)?)
+                    }
+                    _ => None,
+                };
+                (previous, Some(unchanged_condition(item)))
+            };
+
+            let event = match &previous {
+                Some(old) => Event::Updated {
+                    old: old.clone(),
+                    new: product.clone(),
+                },
+                None => Event::Created {
+                    product: product.clone(),
+                },
+            };
+            let mut put = Put::builder()
+                .table_name(&self.table_name)
+                .set_item(Some(self.to_item(product, previous.as_ref())?));
+            if let Some((expression, names, values)) = condition {
+                put = put
+                    .condition_expression(expression)
+                    .set_expression_attribute_names(names)
+                    .set_expression_attribute_values(values);
+            }
+            let entry = Put::builder()
+                .table_name(outbox_table_name)
+                .set_item(Some(entry_to_item(&OutboxEntry::new(event)?)?))
+                .build();
+
+            let res = self
+                .client
+                .transact_write_items()
+                .transact_items(TransactWriteItem::builder().put(put.build()).build())
+                .transact_items(TransactWriteItem::builder().put(entry).build())
+                .send()
+                .await;
+            match res.map_err(Error::from) {
+                Ok(_) => return Ok(previous),
+                Err(Error::SdkError(SdkErrorDetails {
+                    code: Some(code), ..
+                })) if code == "TransactionCanceledException" && attempts < MAX_ATTEMPTS => {
+                    warn!(
+                        "Item with id '{}' changed during the transaction, retrying",
+                        product.id
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Change an item, and write the outbox entry of its event in the same
+    /// transaction if an outbox table is given
+    ///
+    /// `change` gets the current item, read with strong consistency, and
+    /// returns the write to make with its event, if any, along with the
+    /// result. Like puts, the write is conditioned on the item being
+    /// unchanged, and attempted again if a concurrent write changed it.
+    async fn change_item<T>(
+        &self,
+        id: &str,
+        outbox_table_name: Option<&str>,
+        change: impl Fn(
+            Option<HashMap<String, AttributeValue>>,
+        ) -> Result<(Option<(ItemWrite, Option<Event>)>, T), Error>,
+    ) -> Result<T, Error> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let item = self
+                .client
+                .get_item()
+                .table_name(&self.table_name)
+                .key("id", self.key(id))
+                .consistent_read(true)
+                .send()
+                .await?
+                .item
+                .filter(|item| !item.is_empty());
+            let (write, event, res) = match change(item.clone())? {
+                (Some((write, event)), res) => (write, event, res),
+                (None, res) => return Ok(res),
+            };
+
+            let (expression, names, mut values) = unchanged_condition(item);
+            let write = match write {
+                ItemWrite::Put(item) => TransactWriteItem::builder().put(
+                    Put::builder()
+                        .table_name(&self.table_name)
+                        .set_item(Some(item))
+                        .condition_expression(expression)
+                        .set_expression_attribute_names(names)
+                        .set_expression_attribute_values(values)
+                        .build(),
+                ),
+                ItemWrite::Update {
+                    expression: update,
+                    values: update_values,
+                } => {
+                    if !update_values.is_empty() {
+                        values
+                            .get_or_insert_with(HashMap::new)
+                            .extend(update_values);
+                    }
+                    TransactWriteItem::builder().update(
+                        Update::builder()
+                            .table_name(&self.table_name)
+                            .key("id", self.key(id))
+                            .update_expression(update)
+                            .condition_expression(expression)
+                            .set_expression_attribute_names(names)
+                            .set_expression_attribute_values(values)
+                            .build(),
+                    )
+                }
+                ItemWrite::Delete => TransactWriteItem::builder().delete(
+                    Delete::builder()
+                        .table_name(&self.table_name)
+                        .key("id", self.key(id))
+                        .condition_expression(expression)
+                        .set_expression_attribute_names(names)
+                        .set_expression_attribute_values(values)
+                        .build(),
+                ),
+            };
+            let mut req = self
+                .client
+                .transact_write_items()
+                .transact_items(write.build());
+            if let (Some(outbox_table_name), Some(event)) = (outbox_table_name, event) {
+                let entry = Put::builder()
+                    .table_name(outbox_table_name)
+                    .set_item(Some(entry_to_item(&OutboxEntry::new(event)?)?))
+                    .build();
+                req = req.transact_items(TransactWriteItem::builder().put(entry).build());
+            }
+
+            match req.send().await.map_err(Error::from) {
+                Ok(_) => return Ok(res),
+                Err(Error::SdkError(SdkErrorDetails {
+                    code: Some(code), ..
+                })) if code == "TransactionCanceledException" && attempts < MAX_ATTEMPTS => {
+                    warn!(
+                        "Item with id '{}' changed during the transaction, retrying",
+                        id
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Get all sharded products matching a filter
+    async fn get_hot_products(&self, filter: &ProductFilter) -> Result<Vec<Product>, Error> {
+        let res = join_all(
+            self.hot_ids
+                .iter()
+                .map(|id| self.get_sharded(id, ReadConsistency::Eventual)),
+        )
+        .await;
+
+        Ok(res
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .filter(|product| filter.matches(product))
+            .collect())
+    }
+
+    /// Soft delete the item with the given key
+    ///
+    /// Returns `false` if the item doesn't exist or is already deleted.
+    async fn soft_delete_key(&self, key: AttributeValue, now: u64) -> Result<bool, Error> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", key)
+            .update_expression("SET deleted_at = :deleted_at")
+            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)")
+            .expression_attribute_values(":deleted_at", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // Nothing to delete
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Restore the soft-deleted item with the given key
+    ///
+    /// This also cancels any scheduled removal of the item. Returns `false`
+    /// if the item doesn't exist or isn't deleted.
+    async fn restore_key(&self, key: AttributeValue) -> Result<bool, Error> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", key)
+            .update_expression("REMOVE deleted_at, expires_at")
+            .condition_expression("attribute_exists(deleted_at)")
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // The item doesn't exist or isn't deleted
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Set the TTL attribute of the item with the given key
+    async fn set_expiry_key(&self, key: AttributeValue, at: u64) -> Result<(), Error> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", key)
+            .update_expression("SET expires_at = :expires_at")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":expires_at", AttributeValue::N(at.to_string()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            // Nothing to expire
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Scan a single segment of the table until all its pages are retrieved
+    #[instrument(skip(self))]
+    async fn scan_segment(
+        &self,
+        segment: usize,
+        total_segments: usize,
+    ) -> Result<Vec<Product>, Error> {
+        let mut products = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let req = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .segment(segment as i32)
+                .total_segments(total_segments as i32)
+                .set_exclusive_start_key(last_evaluated_key);
+            let res = self
+                .scan_filter(req, &ProductFilter::default())
+                .send()
+                .await?;
+
+            products.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| self.to_product(v, None))
+                    .collect::<Result<Vec<Product>, Error>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(products)
     }
 }
 
@@ -29,176 +925,1648 @@ impl Store for DynamoDBStore {}
 #[async_trait]
 impl StoreGetAll for DynamoDBStore {
     /// Get all items
+    ///
+    /// DynamoDB applies the filter after reading `limit` items, so a page
+    /// can contain fewer products than requested even if there are more.
     #[instrument(skip(self))]
-    async fn all(&self, next: Option<&str>) -> Result<ProductRange, Error> {
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
         // Scan DynamoDB table
         info!("Scanning DynamoDB table");
-        let mut req = self.client.scan().table_name(&self.table_name).limit(20);
+        let first_page = next.is_none();
+        let mut req = self
+            .client
+            .scan()
+            .table_name(&self.table_name)
+            // DynamoDB rejects a limit of zero
+            .limit(limit.max(1) as i32);
         req = if let Some(next) = next {
-            req.exclusive_start_key("id", AttributeValue::S(next.to_owned()))
+            req.exclusive_start_key("id", self.key(next))
         } else {
             req
         };
-        let res = req.send().await?;
+        if let Some(fields) = fields {
+            let (expression, names) = projection(fields);
+            req = req.projection_expression(expression);
+            for (placeholder, name) in names {
+                req = req.expression_attribute_names(placeholder, name);
+            }
+        }
+        let res = self.scan_filter(req, filter).send().await?;
 
         // Build response
         let products = match res.items {
             Some(items) => items
                 .into_iter()
-                .map(|v| v.try_into())
+                .map(|v| self.to_product(v, fields))
                 .collect::<Result<Vec<Product>, Error>>()?,
             None => Vec::default(),
         };
-        let next = res.last_evaluated_key.map(|m| m.get_s("id").unwrap());
+        let next = res.last_evaluated_key.map(|m| {
+            self.decoder
+                .strip_prefix(&m.get_s("id").unwrap())
+                .to_owned()
+        });
+
+        // Sharded products are excluded from the scan, and returned on the
+        // first page instead
+        let mut products = products;
+        if first_page && self.has_sharding() {
+            let hot_products = self.get_hot_products(filter).await?;
+            products.extend(hot_products.into_iter().map(|p| project(p, fields)));
+        }
+
         Ok(ProductRange { products, next })
     }
 }
 
+#[async_trait]
+impl StoreScanAll for DynamoDBStore {
+    /// Get all items using parallel scans
+    ///
+    /// Each segment is scanned concurrently with `join_all`, and the results
+    /// are merged once all segments are complete.
+    #[instrument(skip(self))]
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error> {
+        info!(
+            "Scanning DynamoDB table with {} parallel segments",
+            total_segments
+        );
+        let res =
+            join_all((0..total_segments).map(|segment| self.scan_segment(segment, total_segments)))
+                .await;
+
+        let mut products: Vec<Product> = res
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        if self.has_sharding() {
+            products.extend(self.get_hot_products(&ProductFilter::default()).await?);
+        }
+
+        Ok(products)
+    }
+}
+
+#[async_trait]
+impl StoreCount for DynamoDBStore {
+    /// Count items
+    ///
+    /// This scans the whole table but only returns the number of matching
+    /// items for each page, which are then added together.
+    #[instrument(skip(self))]
+    async fn count(&self) -> Result<usize, Error> {
+        info!("Counting items in DynamoDB table");
+        let mut count = 0;
+        let mut last_evaluated_key = None;
+
+        loop {
+            let req = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .select(Select::Count)
+                .set_exclusive_start_key(last_evaluated_key);
+            let res = self
+                .scan_filter(req, &ProductFilter::default())
+                .send()
+                .await?;
+
+            count += res.count as usize;
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        // Sharded products are excluded from the scan
+        if self.has_sharding() {
+            count += self
+                .get_hot_products(&ProductFilter::default())
+                .await?
+                .len();
+        }
+
+        Ok(count)
+    }
+}
+
 #[async_trait]
 impl StoreGet for DynamoDBStore {
     /// Get item
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        Ok(self
+            .get_with_write_time(id, consistency, fields)
+            .await?
+            .map(|(product, _)| product))
+    }
+}
+
+#[async_trait]
+impl StoreGetWriteTime for DynamoDBStore {
+    /// Get item, with the time of its last write if it was recorded
     #[instrument(skip(self))]
-    async fn get(&self, id: &str) -> Result<Option<Product>, Error> {
+    async fn get_with_write_time(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<(Product, Option<u64>)>, Error> {
+        if self.is_sharded(id) {
+            return Ok(self
+                .get_latest_shard(id, consistency)
+                .await?
+                .map(|(product, written_at)| (project(product, fields), written_at)));
+        }
+
         info!("Getting item with id '{}' from DynamoDB table", id);
-        let res = self
+        let mut req = self
             .client
             .get_item()
             .table_name(&self.table_name)
-            .key("id", AttributeValue::S(id.to_owned()))
-            .send()
-            .await?;
+            .key("id", self.key(id));
+        if consistency == ReadConsistency::Strong {
+            req = req.consistent_read(true);
+        }
+        if let Some(fields) = fields {
+            let (expression, names) = projection(fields);
+            req = req
+                .projection_expression(format!("{}, #{}", expression, UPDATED_AT))
+                .expression_attribute_names(format!("#{}", UPDATED_AT), UPDATED_AT);
+            for (placeholder, name) in names {
+                req = req.expression_attribute_names(placeholder, name);
+            }
+        }
+        let res = req.send().await?;
 
         Ok(match res.item {
-            Some(item) => Some(item.try_into()?),
-            None => None,
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let written_at = write_time(&item);
+                Some((self.to_product(item, fields)?, written_at))
+            }
+            _ => None,
         })
     }
 }
 
+#[async_trait]
+impl StoreBatchGet for DynamoDBStore {
+    /// Get items in parallel batches
+    #[instrument(skip(self, ids))]
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        info!("Getting {} items from DynamoDB table", ids.len());
+        self.get_many_until(ids, None)
+            .await
+            .map(|batch| batch.products)
+    }
+
+    /// Get items in parallel batches, stopping at the end of the budget
+    ///
+    /// Batches that answered in time are kept, even if others didn't.
+    #[instrument(skip(self, ids))]
+    async fn get_many_within(
+        &self,
+        ids: &[String],
+        budget: Duration,
+    ) -> Result<ProductBatch, Error> {
+        info!(
+            "Getting {} items from DynamoDB table within {:?}",
+            ids.len(),
+            budget
+        );
+        self.get_many_until(ids, Some(Instant::now() + budget))
+            .await
+    }
+}
+
+impl DynamoDBStore {
+    /// Get products by id, until an optional deadline
+    ///
+    /// All the shards of sharded products are read in the same batches as
+    /// the other products, and the most recent write of each wins.
+    async fn get_many_until(
+        &self,
+        ids: &[String],
+        deadline: Option<Instant>,
+    ) -> Result<ProductBatch, Error> {
+        let keys = ids
+            .iter()
+            .flat_map(|id| match self.is_sharded(id) {
+                true => self.shard_keys(id),
+                false => vec![self.key(id)],
+            })
+            .collect();
+        let (items, partial) = self
+            .batch_get_items_until(keys, ReadConsistency::Eventual, deadline)
+            .await?;
+
+        // Keep the most recent write of each sharded product
+        let mut latest: HashMap<String, HashMap<String, AttributeValue>> = HashMap::new();
+        let mut products = Vec::new();
+        for item in items {
+            if !item.contains_key(SHARD) {
+                if !item.contains_key(DELETED_AT) {
+                    products.push(self.to_product(item, None)?);
+                }
+                continue;
+            }
+            let id = item.get_s("id").unwrap_or_default();
+            let id = id.rsplit_once('#').map_or(id.as_str(), |(id, _)| id);
+            let updated_at = item.get_n(UPDATED_AT).unwrap_or_default();
+            match latest.get(id) {
+                Some(other) if other.get_n(UPDATED_AT).unwrap_or_default() >= updated_at => {}
+                _ => {
+                    latest.insert(id.to_owned(), item);
+                }
+            }
+        }
+        for item in latest.into_values() {
+            if !item.contains_key(DELETED_AT) {
+                products.push(self.to_product(item, None)?);
+            }
+        }
+
+        Ok(ProductBatch { products, partial })
+    }
+}
+
 #[async_trait]
 impl StorePut for DynamoDBStore {
     /// Create or update an item
+    ///
+    /// The previous item is returned by `PutItem`. Sharded products are read
+    /// before the write instead, as their latest version can be in any shard.
+    /// With an outbox, the item is read first and written in a transaction
+    /// with the outbox entry.
     #[instrument(skip(self))]
-    async fn put(&self, product: &Product) -> Result<(), Error> {
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
         info!("Putting item with id '{}' into DynamoDB table", product.id);
-        self.client
+        if let Some(outbox_table_name) = &self.outbox_table_name {
+            return self.put_with_outbox(product, outbox_table_name).await;
+        }
+        let sharded = self.is_sharded(&product.id);
+        let previous = if sharded {
+            self.get_sharded(&product.id, ReadConsistency::Strong)
+                .await?
+        } else {
+            None
+        };
+
+        let res = self
+            .client
             .put_item()
             .table_name(&self.table_name)
-            .set_item(Some(product.into()))
+            .set_item(Some(self.to_item(product, previous.as_ref())?))
+            .return_values(ReturnValue::AllOld)
             .send()
             .await?;
 
-        Ok(())
+        if sharded {
+            return Ok(previous);
+        }
+        match res.attributes {
+            Some(item) if !item.is_empty() && !item.contains_key(DELETED_AT) => {
+                Ok(Some(self.to_product(item, None)?))
+            }
+            _ => Ok(None),
+        }
     }
 }
 
+/// Condition of writes creating a product
+const ABSENT_CONDITION: &str = "attribute_not_exists(id) OR attribute_exists(deleted_at)";
+
 #[async_trait]
-impl StoreDelete for DynamoDBStore {
-    /// Delete item
+impl StorePutConditional for DynamoDBStore {
+    /// Create an item unless a live one exists
+    ///
+    /// The write is conditioned on the item being absent or soft-deleted.
+    /// With an outbox, the condition applies to the transaction, which then
+    /// also writes the `Created` event. Sharded products are read first and
+    /// written without condition, as their latest version can be in any
+    /// shard, so concurrent creations can both succeed.
     #[instrument(skip(self))]
-    async fn delete(&self, id: &str) -> Result<(), Error> {
-        info!("Deleting item with id '{}' from DynamoDB table", id);
-        self.client
-            .delete_item()
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        info!("Creating item with id '{}' in DynamoDB table", product.id);
+        if self.is_sharded(&product.id) {
+            if self
+                .get_sharded(&product.id, ReadConsistency::Strong)
+                .await?
+                .is_some()
+            {
+                return Ok(false);
+            }
+            self.put(product).await?;
+            return Ok(true);
+        }
+
+        let item = self.to_item(product, None)?;
+        if let Some(outbox_table_name) = &self.outbox_table_name {
+            let put = Put::builder()
+                .table_name(&self.table_name)
+                .set_item(Some(item))
+                .condition_expression(ABSENT_CONDITION)
+                .build();
+            let entry = Put::builder()
+                .table_name(outbox_table_name)
+                .set_item(Some(entry_to_item(&OutboxEntry::new(Event::Created {
+                    product: product.clone(),
+                })?)?))
+                .build();
+            let res = self
+                .client
+                .transact_write_items()
+                .transact_items(TransactWriteItem::builder().put(put).build())
+                .transact_items(TransactWriteItem::builder().put(entry).build())
+                .send()
+                .await;
+            return match res {
+                Ok(_) => Ok(true),
+                // A live item already exists
+                Err(SdkError::ServiceError { err, .. }) if product_condition_failed(&err) => {
+                    Ok(false)
+                }
+                Err(err) => Err(err.into()),
+            };
+        }
+
+        let res = self
+            .client
+            .put_item()
             .table_name(&self.table_name)
-            .key("id", AttributeValue::S(id.to_owned()))
+            .set_item(Some(item))
+            .condition_expression(ABSENT_CONDITION)
             .send()
-            .await?;
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // A live item already exists
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replace an item if the product is at the expected version
+    ///
+    /// The item is read first, and the write conditioned on it being
+    /// unchanged, in a transaction with the outbox entry if there is an
+    /// outbox. Sharded products are read first and written without
+    /// condition, as with `put_if_absent`.
+    #[instrument(skip(self))]
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        info!(
+            "Replacing item with id '{}' at version {} in DynamoDB table",
+            product.id, version
+        );
+        if self.is_sharded(&product.id) {
+            return match self
+                .get_sharded(&product.id, ReadConsistency::Strong)
+                .await?
+            {
+                Some(old) if old.version() == version => {
+                    self.put(product).await?;
+                    Ok(Some(old))
+                }
+                _ => Ok(None),
+            };
+        }
+
+        let outbox_table_name = self.outbox_table_name.as_deref();
+        self.change_item(&product.id, outbox_table_name, |item| match item {
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let old = self.to_product(item, None)?;
+                if old.version() != version {
+                    return Ok((None, None));
+                }
+                let write = ItemWrite::Put(self.to_item(product, None)?);
+                let event = Event::Updated {
+                    old: old.clone(),
+                    new: product.clone(),
+                };
+                Ok((Some((write, Some(event))), Some(old)))
+            }
+            // Nothing to replace
+            _ => Ok((None, None)),
+        })
+        .await
+    }
+
+    /// Patch an item if the product is at the expected version
+    ///
+    /// The patched product is written as a whole, under the same condition
+    /// as `put_if_version`.
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        info!(
+            "Updating item with id '{}' at version {} in DynamoDB table",
+            id, version
+        );
+        if self.is_sharded(id) {
+            let mut product = match self.get_sharded(id, ReadConsistency::Strong).await? {
+                Some(product) if product.version() == version => product,
+                _ => return Ok(None),
+            };
+            patch.apply(&mut product);
+            self.put(&product).await?;
+            return Ok(Some(product));
+        }
+
+        let outbox_table_name = self.outbox_table_name.as_deref();
+        self.change_item(id, outbox_table_name, |item| match item {
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let old = self.to_product(item, None)?;
+                if old.version() != version {
+                    return Ok((None, None));
+                }
+                let mut new = old.clone();
+                patch.apply(&mut new);
+                let write = ItemWrite::Put(self.to_item(&new, None)?);
+                let event = Event::Updated {
+                    old,
+                    new: new.clone(),
+                };
+                Ok((Some((write, Some(event))), Some(new)))
+            }
+            // Nothing to update
+            _ => Ok((None, None)),
+        })
+        .await
+    }
+
+    /// Soft delete an item if the product is at the expected version
+    ///
+    /// Sharded products are read first and deleted without condition.
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        info!(
+            "Soft deleting item with id '{}' at version {} from DynamoDB table",
+            id, version
+        );
+        if self.is_sharded(id) {
+            return match self.get_sharded(id, ReadConsistency::Strong).await? {
+                Some(product) if product.version() == version => self.delete(id).await,
+                _ => Ok(false),
+            };
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_secs();
+        let outbox_table_name = self.outbox_table_name.as_deref();
+        self.change_item(id, outbox_table_name, |item| match item {
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let product = self.to_product(item, None)?;
+                if product.version() != version {
+                    return Ok((None, false));
+                }
+                let write = ItemWrite::Update {
+                    expression: "SET deleted_at = :deleted_at".to_string(),
+                    values: HashMap::from([(
+                        ":deleted_at".to_string(),
+                        AttributeValue::N(now.to_string()),
+                    )]),
+                };
+                Ok((Some((write, Some(Event::Deleted { product }))), true))
+            }
+            // Nothing to delete
+            _ => Ok((None, false)),
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StorePutMany for DynamoDBStore {
+    /// Create or update items in batches
+    ///
+    /// `BatchWriteItem` rejects requests with duplicate keys, so only the
+    /// last product with a given id is written. Sharded products are read
+    /// first, to record the version they replace.
+    #[instrument(skip(self, products))]
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        info!("Putting {} items into DynamoDB table", products.len());
+        let latest: HashMap<&str, &Product> = products.iter().map(|p| (p.id.as_str(), p)).collect();
+        let products: Vec<&Product> = latest.into_values().collect();
+
+        // Sharded products hold their latest version before the write
+        let mut previous = HashMap::new();
+        for product in products.iter().filter(|p| self.is_sharded(&p.id)) {
+            let latest = self
+                .get_sharded(&product.id, ReadConsistency::Strong)
+                .await?;
+            previous.insert(product.id.as_str(), latest);
+        }
+
+        for chunk in products.chunks(BATCH_WRITE_SIZE) {
+            let mut requests = chunk
+                .iter()
+                .map(|product| {
+                    Ok(WriteRequest::builder()
+                        .put_request(
+                            PutRequest::builder()
+                                .set_item(Some(self.to_item(
+                                    product,
+                                    previous.get(product.id.as_str()).and_then(Option::as_ref),
+                                )?))
+                                .build(),
+                        )
+                        .build())
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            // Retry unprocessed items
+            //
+            // DynamoDB can return unprocessed items when the table is
+            // throttled, which need to be sent again.
+            let mut attempts = 0;
+            while !requests.is_empty() {
+                if attempts == MAX_ATTEMPTS {
+                    return Err(Error::InternalError("Failed to put all items"));
+                }
+                attempts += 1;
+
+                let res = self
+                    .client
+                    .batch_write_item()
+                    .request_items(&self.table_name, requests)
+                    .send()
+                    .await?;
+                requests = res
+                    .unprocessed_items
+                    .and_then(|mut items| items.remove(&self.table_name))
+                    .unwrap_or_default();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreUpdate for DynamoDBStore {
+    /// Partially update an item
+    ///
+    /// The update expression only sets the patched fields, so concurrent
+    /// updates of other fields are not lost. The condition prevents creating
+    /// an item if it doesn't exist, and updating a deleted one.
+    ///
+    /// Sharded products are read, patched and written to a new shard
+    /// instead, as updating a single shard could patch an outdated copy.
+    #[instrument(skip(self))]
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        info!("Updating item with id '{}' in DynamoDB table", id);
+        if self.is_sharded(id) {
+            let mut product = match self.get_sharded(id, ReadConsistency::Strong).await? {
+                Some(product) => product,
+                None => return Ok(None),
+            };
+            patch.apply(&mut product);
+            self.put(&product).await?;
+            return Ok(Some(product));
+        }
+        if let Some(outbox_table_name) = &self.outbox_table_name {
+            if patch.is_empty() {
+                return self.get(id, ReadConsistency::Strong, None).await;
+            }
+            return self
+                .change_item(id, Some(outbox_table_name), |item| match item {
+                    Some(item) if !item.contains_key(DELETED_AT) => {
+                        let old = self.to_product(item, None)?;
+                        let mut new = old.clone();
+                        patch.apply(&mut new);
+                        let write = ItemWrite::Put(self.to_item(&new, None)?);
+                        let event = Event::Updated {
+                            old,
+                            new: new.clone(),
+                        };
+                        Ok((Some((write, Some(event))), Some(new)))
+                    }
+                    // Nothing to update
+                    _ => Ok((None, None)),
+                })
+                .await;
+        }
+
+        let mut req = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", self.key(id))
+            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)")
+            .return_values(ReturnValue::AllNew);
+        let mut updates = Vec::new();
+        if let Some(name) = &patch.name {
+            // `name` is a reserved word in DynamoDB expressions
+            updates.push("#name = :name");
+            req = req
+                .expression_attribute_names("#name", "name")
+                .expression_attribute_values(":name", AttributeValue::S(name.clone()));
+        }
+        if let Some(price) = patch.price {
+            updates.push("price = :price");
+            req =
+                req.expression_attribute_values(":price", AttributeValue::N(format!("{:}", price)));
+        }
+        if updates.is_empty() {
+            return self.get(id, ReadConsistency::Strong, None).await;
+        }
+        if self.record_write_times {
+            updates.push("updated_at = :updated_at");
+            req = req.expression_attribute_values(":updated_at", now_micros()?);
+        }
+        let res = req
+            .update_expression(format!("SET {}", updates.join(", ")))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) => res
+                .attributes
+                .map(|item| self.to_product(item, None))
+                .transpose(),
+            // Nothing to update
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDelete for DynamoDBStore {
+    /// Soft delete item
+    ///
+    /// This sets the `deleted_at` attribute on the item. The condition
+    /// prevents creating an empty item if it doesn't exist, and keeps the
+    /// original deletion time if it was already deleted.
+    #[instrument(skip(self))]
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        info!("Soft deleting item with id '{}' from DynamoDB table", id);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_secs();
+        if !self.is_sharded(id) {
+            if let Some(outbox_table_name) = &self.outbox_table_name {
+                return self
+                    .change_item(id, Some(outbox_table_name), |item| match item {
+                        Some(item) if !item.contains_key(DELETED_AT) => {
+                            let product = self.to_product(item, None)?;
+                            let write = ItemWrite::Update {
+                                expression: "SET deleted_at = :deleted_at".to_string(),
+                                values: HashMap::from([(
+                                    ":deleted_at".to_string(),
+                                    AttributeValue::N(now.to_string()),
+                                )]),
+                            };
+                            Ok((Some((write, Some(Event::Deleted { product }))), true))
+                        }
+                        // Nothing to delete
+                        _ => Ok((None, false)),
+                    })
+                    .await;
+            }
+            return self.soft_delete_key(self.key(id), now).await;
+        }
+
+        let deleted = join_all(
+            self.shard_keys(id)
+                .into_iter()
+                .map(|key| self.soft_delete_key(key, now)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+        Ok(deleted.into_iter().any(|deleted| deleted))
+    }
+
+    /// Permanently delete item
+    ///
+    /// The previous item is returned by `DeleteItem` to know whether it
+    /// existed.
+    #[instrument(skip(self))]
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        info!("Deleting item with id '{}' from DynamoDB table", id);
+        let keys = if self.is_sharded(id) {
+            self.shard_keys(id)
+        } else if let Some(outbox_table_name) = &self.outbox_table_name {
+            return self
+                .change_item(id, Some(outbox_table_name), |item| match item {
+                    Some(item) => {
+                        let product = self.to_product(item, None)?;
+                        let event = Event::Deleted { product };
+                        Ok((Some((ItemWrite::Delete, Some(event))), true))
+                    }
+                    None => Ok((None, false)),
+                })
+                .await;
+        } else {
+            vec![self.key(id)]
+        };
+        let res = join_all(keys.into_iter().map(|key| {
+            self.client
+                .delete_item()
+                .table_name(&self.table_name)
+                .key("id", key)
+                .return_values(ReturnValue::AllOld)
+                .send()
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(res
+            .into_iter()
+            .any(|res| res.attributes.map_or(false, |item| !item.is_empty())))
+    }
+}
+
+#[async_trait]
+impl StoreRestore for DynamoDBStore {
+    /// Restore a soft-deleted item
+    #[instrument(skip(self))]
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        info!("Restoring item with id '{}' in DynamoDB table", id);
+        if !self.is_sharded(id) {
+            if let Some(outbox_table_name) = &self.outbox_table_name {
+                return self
+                    .change_item(id, Some(outbox_table_name), |item| match item {
+                        Some(item) if item.contains_key(DELETED_AT) => {
+                            let product = self.to_product(item, None)?;
+                            let write = ItemWrite::Update {
+                                expression: "REMOVE deleted_at, expires_at".to_string(),
+                                values: HashMap::new(),
+                            };
+                            Ok((Some((write, Some(Event::Created { product }))), true))
+                        }
+                        // The item doesn't exist or isn't deleted
+                        _ => Ok((None, false)),
+                    })
+                    .await;
+            }
+            return self.restore_key(self.key(id)).await;
+        }
+
+        let restored = join_all(
+            self.shard_keys(id)
+                .into_iter()
+                .map(|key| self.restore_key(key)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+        Ok(restored.into_iter().any(|restored| restored))
+    }
+}
+
+#[async_trait]
+impl StoreExpire for DynamoDBStore {
+    /// Set the `expires_at` TTL attribute on the item
+    ///
+    /// DynamoDB deletes the item within a few days after that time, without
+    /// consuming write capacity.
+    #[instrument(skip(self))]
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        info!("Setting expiry of item with id '{}' in DynamoDB table", id);
+        let keys = if self.is_sharded(id) {
+            self.shard_keys(id)
+        } else if let Some(outbox_table_name) = &self.outbox_table_name {
+            let expires_at = AttributeValue::N(at.to_string());
+            return self
+                .change_item(id, Some(outbox_table_name), |item| match item {
+                    Some(item) => {
+                        let changed = item.get(EXPIRES_AT) != Some(&expires_at);
+                        let product = self.to_product(item, None)?;
+                        let write = ItemWrite::Update {
+                            expression: "SET expires_at = :expires_at".to_string(),
+                            values: HashMap::from([(
+                                ":expires_at".to_string(),
+                                expires_at.clone(),
+                            )]),
+                        };
+                        let event = changed.then_some(Event::DeletionScheduled {
+                            product,
+                            expires_at: at,
+                        });
+                        Ok((Some((write, event)), ()))
+                    }
+                    // Nothing to expire
+                    None => Ok((None, ())),
+                })
+                .await;
+        } else {
+            vec![self.key(id)]
+        };
+        join_all(keys.into_iter().map(|key| self.set_expiry_key(key, at)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreHealth for DynamoDBStore {
+    /// Check that the table exists and can be described
+    #[instrument(skip(self))]
+    async fn ping(&self) -> Result<(), Error> {
+        info!("Describing DynamoDB table '{}'", self.table_name);
+        self.client
+            .describe_table()
+            .table_name(&self.table_name)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Whether a transaction was cancelled because the condition on its first
+/// item, the product, failed
+///
+/// Transactions can also be cancelled by conflicts with concurrent writes or
+/// throttling, which aren't failed conditions.
+fn product_condition_failed(err: &TransactWriteItemsError) -> bool {
+    match &err.kind {
+        TransactWriteItemsErrorKind::TransactionCanceledException(err) => {
+            err.cancellation_reasons()
+                .and_then(|reasons| reasons.first())
+                .and_then(|reason| reason.code())
+                == Some("ConditionalCheckFailed")
+        }
+        _ => false,
+    }
+}
+
+/// Current time, as an attribute value in microseconds since the UNIX epoch
+fn now_micros() -> Result<AttributeValue, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+        .as_micros();
+    Ok(AttributeValue::N(now.to_string()))
+}
+
+/// Time of the last write of an item, if it was recorded
+fn write_time(item: &HashMap<String, AttributeValue>) -> Option<u64> {
+    item.get(UPDATED_AT)?.as_n().ok()?.parse().ok()
+}
+
+/// Fill the fields missing from a projected item with their default value
+fn with_defaults(mut item: HashMap<String, AttributeValue>) -> HashMap<String, AttributeValue> {
+    item.entry("name".to_owned())
+        .or_insert_with(|| AttributeValue::S(String::new()));
+    item.entry("price".to_owned())
+        .or_insert_with(|| AttributeValue::N("0".to_owned()));
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{dynamodb_request_builder, mock_sdk_config},
+        Error,
+    };
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_all_empty() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with no items
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","FilterExpression":"attribute_not_exists(deleted_at)","Limit":20}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": []}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items
+        let res = store.all(None, 20, &ProductFilter::default(), None).await?;
+
+        // THEN the response is empty
+        assert_eq!(res.products.len(), 0);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","FilterExpression":"attribute_not_exists(deleted_at)","Limit":20}"#)).unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items
+        let res = store.all(None, 20, &ProductFilter::default(), None).await?;
+
+        // THEN the response has one item
+        assert_eq!(res.products.len(), 1);
+        // AND the item has the correct id
+        assert_eq!(res.products[0].id, "1");
+        // AND the item has the correct name
+        assert_eq!(res.products[0].name, "test1");
+        // AND the item has the correct price
+        assert_eq!(res.products[0].price, 1.0);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_limit() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","FilterExpression":"attribute_not_exists(deleted_at)","Limit":5}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": []}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items with a limit of 5
+        store.all(None, 5, &ProductFilter::default(), None).await?;
+
+        // THEN the request contains the limit
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_next() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a last evaluated key
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","FilterExpression":"attribute_not_exists(deleted_at)","Limit":20}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Items": [], "LastEvaluatedKey": {"id": {"S": "1"}}}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items
+        let res = store.all(None, 20, &ProductFilter::default(), None).await?;
+
+        // THEN the response has a next key
+        assert_eq!(res.next, Some("1".to_string()));
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_filter() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r##"{"TableName":"test","Limit":20,"FilterExpression":"attribute_not_exists(deleted_at) AND price >= :min_price AND price <= :max_price AND contains(#name, :name)","ExpressionAttributeNames":{"#name":"name"},"ExpressionAttributeValues":{":min_price":{"N":"1"},":max_price":{"N":"9.5"},":name":{"S":"foo"}}}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": []}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items matching a filter
+        let filter = ProductFilter {
+            min_price: Some(1.0),
+            max_price: Some(9.5),
+            name_contains: Some("foo".to_string()),
+            attributes: Vec::new(),
+        };
+        store.all(None, 20, &filter, None).await?;
+
+        // THEN the request contains the filter expression
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_filter_attributes() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r##"{"TableName":"test","Limit":20,"FilterExpression":"attribute_not_exists(deleted_at) AND (#attributes.#attr0 = :attr0s OR #attributes.#attr0 = :attr0n)","ExpressionAttributeNames":{"#attributes":"attributes","#attr0":"weight"},"ExpressionAttributeValues":{":attr0s":{"S":"2"},":attr0n":{"N":"2"}}}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "10"}, "attributes": {"M": {"weight": {"N": "2"}}}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items with a given attribute value
+        let filter = ProductFilter {
+            attributes: vec![("weight".to_string(), "2".to_string())],
+            ..Default::default()
+        };
+        let res = store.all(None, 20, &filter, None).await?;
+
+        // THEN the request matches the value as a string or a number
+        conn.assert_requests_match(&vec![]);
+        // AND the attributes of the item are returned
+        assert_eq!(res.products[0].attributes["weight"], 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scan_all() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with two pages of items
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Segment":0,"TotalSegments":1,"FilterExpression":"attribute_not_exists(deleted_at)"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}], "LastEvaluatedKey": {"id": {"S": "1"}}}"#))
+                .unwrap(),
+        ), (
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Segment":0,"TotalSegments":1,"FilterExpression":"attribute_not_exists(deleted_at)","ExclusiveStartKey":{"id":{"S":"1"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "2"}, "name": {"S": "test2"}, "price": {"N": "2.0"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN scanning all items with a single segment
+        let res = store.scan_all(1).await?;
+
+        // THEN the response contains the items from both pages
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].id, "1");
+        assert_eq!(res[1].id, "2");
+        // AND the requests match the expected requests
+        assert_eq!(conn.requests().len(), 2);
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with two pages of items
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.Scan")
+                    .body(SdkBody::from(r#"{"TableName":"test","Select":"COUNT","FilterExpression":"attribute_not_exists(deleted_at)"}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Count": 2, "ScannedCount": 3, "LastEvaluatedKey": {"id": {"S": "2"}}}"#))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.Scan")
+                    .body(SdkBody::from(r#"{"TableName":"test","Select":"COUNT","ExclusiveStartKey":{"id":{"S":"2"}},"FilterExpression":"attribute_not_exists(deleted_at)"}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Count": 1, "ScannedCount": 1}"#))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN counting the items
+        let count = store.count().await?;
+
+        // THEN the counts of both pages are added
+        assert_eq!(count, 3);
+        // AND the requests match the expected requests
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN deleting an item
+        let deleted = store.delete("1").await?;
+
+        // THEN the item is deleted
+        assert!(deleted);
+        // AND a single update request is sent
+        //
+        // The deletion timestamp changes on every run, so we cannot match the
+        // request body exactly.
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("SET deleted_at = :deleted_at"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore without the item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN deleting the item
+        let deleted = store.delete("1").await?;
+
+        // THEN the deletion succeeds, but nothing is deleted
+        assert!(!deleted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a soft-deleted item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "UpdateExpression": "REMOVE deleted_at, expires_at", "ConditionExpression": "attribute_exists(deleted_at)"}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN restoring the item
+        let restored = store.restore("1").await?;
+
+        // THEN the item is restored
+        assert!(restored);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "UpdateExpression": "SET expires_at = :expires_at", "ConditionExpression": "attribute_exists(id)", "ExpressionAttributeValues": {":expires_at": {"N": "1641081600"}}}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN setting the expiry of the item
+        store.set_expiry("1", 1641081600).await?;
+
+        // THEN the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "UpdateExpression": "SET price = :price", "ConditionExpression": "attribute_exists(id) AND attribute_not_exists(deleted_at)", "ExpressionAttributeValues": {":price": {"N": "12.5"}}, "ReturnValues": "ALL_NEW"}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "12.5"}}}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN patching the price of the item
+        let patch = ProductPatch {
+            price: Some(12.5),
+            ..Default::default()
+        };
+        let product = store.update("1", &patch).await?;
+
+        // THEN the updated product is returned
+        assert_eq!(
+            product,
+            Some(Product {
+                id: "1".to_string(),
+                name: "test1".to_string(),
+                price: 12.5,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+        );
+        // AND the request only sets the price
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_missing() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore without the item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN patching the item
+        let patch = ProductPatch {
+            name: Some("foo".to_string()),
+            ..Default::default()
+        };
+        let product = store.update("1", &patch).await?;
+
+        // THEN no product is returned
+        assert_eq!(product, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hard_delete() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "ReturnValues": "ALL_OLD"}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.5"}}}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN hard deleting an item
+        let deleted = store.hard_delete("1").await?;
+
+        // THEN the item is deleted
+        assert!(deleted);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting an item
+        let res = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN the response has the correct values
+        if let Some(product) = res {
+            assert_eq!(product.id, "1");
+            assert_eq!(product.name, "test1");
+            assert_eq!(product.price, 1.0);
+        } else {
+            panic!("Expected product to be Some");
+        }
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_deleted() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one soft-deleted item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "deleted_at": {"N": "1640995200"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting the item
+        let res = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN no item is returned
+        assert_eq!(res, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_strong() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "ConsistentRead": true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting an item with a strongly consistent read
+        let res = store.get("1", ReadConsistency::Strong, None).await?;
+
+        // THEN the item is returned
+        assert!(res.is_some());
+        // AND the request asks for a consistent read
+        conn.assert_requests_match(&vec![]);
 
         Ok(())
     }
-}
 
-impl From<&Product> for HashMap<String, AttributeValue> {
-    /// Convert a &Product into a DynamoDB item
-    fn from(value: &Product) -> HashMap<String, AttributeValue> {
-        let mut retval = HashMap::new();
-        retval.insert("id".to_owned(), AttributeValue::S(value.id.clone()));
-        retval.insert("name".to_owned(), AttributeValue::S(value.name.clone()));
-        retval.insert(
-            "price".to_owned(),
-            AttributeValue::N(format!("{:}", value.price)),
+    #[tokio::test]
+    async fn test_get_key_prefix() -> Result<(), Error> {
+        // GIVEN a namespaced DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r##"{"TableName": "test", "Key": {"id": {"S": "dev#1"}}}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r##"{"Item": {"id": {"S": "dev#1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"##))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
         );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_key_prefix("dev#");
 
-        retval
-    }
-}
-impl TryFrom<HashMap<String, AttributeValue>> for Product {
-    type Error = Error;
+        // WHEN getting an item
+        let res = store.get("1", ReadConsistency::Eventual, None).await?;
 
-    /// Try to convert a DynamoDB item into a Product
-    ///
-    /// This could fail as the DynamoDB item might be missing some fields.
-    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        Ok(Product {
-            id: value
-                .get_s("id")
-                .ok_or(Error::InternalError("Missing id"))?,
-            name: value
-                .get_s("name")
-                .ok_or(Error::InternalError("Missing name"))?,
-            price: value
-                .get_n("price")
-                .ok_or(Error::InternalError("Missing price"))?,
-        })
+        // THEN the product id doesn't contain the namespace
+        assert_eq!(res.map(|p| p.id), Some("1".to_string()));
+        // AND the request uses the namespaced key
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::Error;
-    use aws_sdk_dynamodb::{Client, Config, Credentials, Region};
-    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
-    use aws_smithy_http::body::SdkBody;
+    #[tokio::test]
+    async fn test_all_key_prefix() -> Result<(), Error> {
+        // GIVEN a namespaced DynamoDBStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r##"{"TableName":"test","Limit":20,"ExclusiveStartKey":{"id":{"S":"dev#0"}},"FilterExpression":"attribute_not_exists(deleted_at) AND begins_with(id, :key_prefix)","ExpressionAttributeValues":{":key_prefix":{"S":"dev#"}}}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r##"{"Items": [{"id": {"S": "dev#1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}], "LastEvaluatedKey": {"id": {"S": "dev#1"}}}"##))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_key_prefix("dev#");
 
-    /// Config for mocking DynamoDB
-    async fn get_mock_config() -> Config {
-        let cfg = aws_config::from_env()
-            .region(Region::new("eu-west-1"))
-            .credentials_provider(Credentials::new(
-                "accesskey",
-                "privatekey",
-                None,
-                None,
-                "dummy",
-            ))
-            .load()
-            .await;
+        // WHEN getting the next page of items
+        let res = store
+            .all(Some("0"), 20, &ProductFilter::default(), None)
+            .await?;
 
-        Config::new(&cfg)
-    }
+        // THEN the products and next key don't contain the namespace
+        assert_eq!(res.products[0].id, "1");
+        assert_eq!(res.next, Some("1".to_string()));
+        // AND the request is filtered on the namespace
+        conn.assert_requests_match(&vec![]);
 
-    fn get_request_builder() -> http::request::Builder {
-        http::Request::builder()
-            .header("content-type", "application/x-amz-json-1.0")
-            .uri(http::uri::Uri::from_static(
-                "https://dynamodb.eu-west-1.amazonaws.com/",
-            ))
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_all_empty() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore with no items
+    async fn test_get_many() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one live and one deleted item
         let conn = TestConnection::new(vec![(
-            get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.Scan")
-                .body(SdkBody::from(r#"{"TableName":"test","Limit":20}"#))
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from(r#"{"RequestItems":{"test":{"Keys":[{"id":{"S":"1"}},{"id":{"S":"2"}}]}}}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(r#"{"Items": []}"#))
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}, {"id": {"S": "2"}, "name": {"S": "test2"}, "price": {"N": "2.0"}, "deleted_at": {"N": "1"}}]}}"#))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
 
-        // WHEN getting all items
-        let res = store.all(None).await?;
+        // WHEN getting both items
+        let res = store.get_many(&["1".to_string(), "2".to_string()]).await?;
 
-        // THEN the response is empty
-        assert_eq!(res.products.len(), 0);
+        // THEN only the live item is returned
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, "1");
         // AND the request matches the expected request
         conn.assert_requests_match(&vec![]);
 
@@ -206,186 +2574,664 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_all() -> Result<(), Error> {
+    async fn test_all_fields() -> Result<(), Error> {
         // GIVEN a DynamoDBStore with one item
         let conn = TestConnection::new(vec![(
-            get_request_builder()
+            dynamodb_request_builder()
                 .header("x-amz-target", "DynamoDB_20120810.Scan")
-                .body(SdkBody::from(r#"{"TableName":"test","Limit":20}"#)).unwrap(),
+                .body(SdkBody::from(r##"{"TableName":"test","Limit":20,"ProjectionExpression":"#id, #deleted_at, #name","ExpressionAttributeNames":{"#id":"id","#deleted_at":"deleted_at","#name":"name"},"FilterExpression":"attribute_not_exists(deleted_at)"}"##))
+                .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}]}"#))
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}}]}"#))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
 
-        // WHEN getting all items
-        let res = store.all(None).await?;
+        // WHEN getting only the names of all items
+        let res = store
+            .all(
+                None,
+                20,
+                &ProductFilter::default(),
+                Some(&[ProductField::Name]),
+            )
+            .await?;
 
-        // THEN the response has one item
-        assert_eq!(res.products.len(), 1);
-        // AND the item has the correct id
-        assert_eq!(res.products[0].id, "1");
-        // AND the item has the correct name
+        // THEN the price is left to its default value
         assert_eq!(res.products[0].name, "test1");
-        // AND the item has the correct price
-        assert_eq!(res.products[0].price, 1.0);
-        // AND the request matches the expected request
+        assert_eq!(res.products[0].price, 0.0);
+        // AND the request uses a projection expression
         conn.assert_requests_match(&vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_all_next() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore with a last evaluated key
+    async fn test_ping() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
         let conn = TestConnection::new(vec![(
-            get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.Scan")
-                .body(SdkBody::from(r#"{"TableName":"test","Limit":20}"#))
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DescribeTable")
+                .body(SdkBody::from(r#"{"TableName":"test"}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(
-                    r#"{"Items": [], "LastEvaluatedKey": {"id": {"S": "1"}}}"#,
-                ))
+                .body(SdkBody::from(r#"{"Table": {"TableName": "test"}}"#))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
 
-        // WHEN getting all items
-        let res = store.all(None).await?;
+        // WHEN pinging the store
+        store.ping().await?;
 
-        // THEN the response has a next key
-        assert_eq!(res.next, Some("1".to_string()));
-        // AND the request matches the expected request
+        // THEN the request matches the expected request
         conn.assert_requests_match(&vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_delete() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore
+    async fn test_put() -> Result<(), Error> {
+        // GIVEN an empty DynamoDBStore and a product
         let conn = TestConnection::new(vec![(
-            get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
-                .body(SdkBody::from(
-                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#,
-                ))
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"price":{"N":"1.5"}},"ReturnValues":"ALL_OLD"}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from("{}"))
+                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}}}"#))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
 
-        // WHEN deleting an item
-        store.delete("1").await?;
+        // WHEN putting an item
+        let previous = store.put(&product).await?;
 
         // THEN the request matches the expected request
         conn.assert_requests_match(&vec![]);
+        // AND the previous item is returned
+        assert_eq!(previous.map(|p| p.name), Some("test0".to_string()));
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_get() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore with one item
+    async fn test_put_if_absent_exists() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a live item
         let conn = TestConnection::new(vec![(
-            get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.GetItem")
-                .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#))
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"price":{"N":"1.5"}},"ConditionExpression":"attribute_not_exists(id) OR attribute_exists(deleted_at)"}"#))
                 .unwrap(),
             http::Response::builder()
-                .status(200)
-                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#))
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+                ))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
 
-        // WHEN getting an item
-        let res = store.get("1").await?;
+        // WHEN creating the item
+        let created = store.put_if_absent(&product).await?;
 
-        // THEN the response has the correct values
-        if let Some(product) = res {
-            assert_eq!(product.id, "1");
-            assert_eq!(product.name, "test1");
-            assert_eq!(product.price, 1.0);
-        } else {
-            panic!("Expected product to be Some");
-        }
-        // AND the request matches the expected request
+        // THEN the write is conditioned on the item being absent
         conn.assert_requests_match(&vec![]);
+        // AND nothing is created
+        assert!(!created);
+
+        Ok(())
+    }
+
+    async fn put_if_absent_cancelled(reasons: &str) -> Result<bool, Error> {
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(format!(
+                    r#"{{"__type": "com.amazonaws.dynamodb.v20120810#TransactionCanceledException", "Message": "Transaction cancelled", "CancellationReasons": {}}}"#,
+                    reasons
+                )))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox");
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+
+        store.put_if_absent(&product).await
+    }
+
+    #[tokio::test]
+    async fn test_put_if_absent_with_outbox_exists() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an outbox and a live item
+        // WHEN creating the item
+        let created =
+            put_if_absent_cancelled(r#"[{"Code": "ConditionalCheckFailed"}, {"Code": "None"}]"#)
+                .await?;
+
+        // THEN nothing is created
+        assert!(!created);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_put() -> Result<(), Error> {
-        // GIVEN an empty DynamoDBStore and a product
+    async fn test_put_if_absent_with_outbox_conflict() {
+        // GIVEN a DynamoDBStore with an outbox and a concurrent write
+        // WHEN creating the item
+        let res =
+            put_if_absent_cancelled(r#"[{"Code": "TransactionConflict"}, {"Code": "None"}]"#).await;
+
+        // THEN the cancellation is returned as an error
+        assert!(matches!(res, Err(Error::SdkError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_if_version_changed() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a product changed since it was read
         let conn = TestConnection::new(vec![(
-            get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.PutItem")
-                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"price":{"N":"1.5"}}}"#))
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from("{}"))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.5"}}}"#))
+                .body(SdkBody::from(
+                    r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test2"}, "price": {"N": "1.0"}}}"#,
+                ))
                 .unwrap(),
         )]);
-        let client =
-            Client::from_conf_conn(get_mock_config().await, DynConnector::new(conn.clone()));
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
         let store = DynamoDBStore::new(client, "test".to_string());
+        let read = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: 1.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+        let mut product = read.clone();
+        product.price = 2.0;
+
+        // WHEN replacing the product at the version that was read
+        let replaced = store.put_if_version(&product, &read.version()).await?;
+
+        // THEN nothing is written
+        assert_eq!(conn.requests().len(), 1);
+        assert_eq!(replaced, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_with_outbox() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an outbox and no product
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox");
         let product = Product {
             id: "1".to_string(),
             name: "test1".to_string(),
             price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
         };
 
-        // WHEN putting an item
-        store.put(&product).await?;
+        // WHEN putting the product
+        let previous = store.put(&product).await?;
 
-        // THEN the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        // THEN there was no previous product
+        assert_eq!(previous, None);
+        // AND the product is created with a Created event in the outbox
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].actual.body().bytes().unwrap()).unwrap();
+        let items = &body["TransactItems"];
+        assert_eq!(items[0]["Put"]["TableName"], "test");
+        assert_eq!(
+            items[0]["Put"]["ConditionExpression"],
+            "attribute_not_exists(id)"
+        );
+        assert_eq!(items[1]["Put"]["TableName"], "outbox");
+        let event: serde_json::Value =
+            serde_json::from_str(items[1]["Put"]["Item"]["event"]["S"].as_str().unwrap()).unwrap();
+        assert_eq!(event["type"], "Created");
 
         Ok(())
     }
 
     #[test]
-    fn product_from_dynamodb() {
-        let mut value = HashMap::new();
-        value.insert("id".to_owned(), AttributeValue::S("id".to_owned()));
-        value.insert("name".to_owned(), AttributeValue::S("name".to_owned()));
-        value.insert("price".to_owned(), AttributeValue::N("1.0".to_owned()));
+    fn test_unchanged_condition_optional_attributes() {
+        // GIVEN an item that expires, without a deletion time
+        let item = HashMap::from([
+            ("id".to_owned(), AttributeValue::S("1".to_owned())),
+            (
+                EXPIRES_AT.to_owned(),
+                AttributeValue::N("1641081600".to_owned()),
+            ),
+        ]);
+
+        // WHEN building the condition for a write of the item
+        let (expression, names, values) = unchanged_condition(Some(item));
 
-        let product = Product::try_from(value).unwrap();
-        assert_eq!(product.id, "id");
-        assert_eq!(product.name, "name");
-        assert_eq!(product.price, 1.0);
+        // THEN the optional attributes missing from the item must still be missing
+        assert_eq!(
+            expression,
+            "attribute_not_exists(#attributes) AND attribute_not_exists(#images) AND attribute_not_exists(#deleted_at) AND #c0 = :c0 AND #c1 = :c1"
+        );
+        let names = names.unwrap();
+        assert_eq!(names["#deleted_at"], DELETED_AT);
+        assert_eq!(names["#c0"], EXPIRES_AT);
+        assert_eq!(names["#c1"], "id");
+        assert_eq!(values.unwrap().len(), 2);
     }
 
-    #[test]
-    fn product_to_dynamodb() -> Result<(), Error> {
+    #[tokio::test]
+    async fn test_delete_with_outbox() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an outbox and a product
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#,
+                    ))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox");
+
+        // WHEN deleting the product
+        let deleted = store.delete("1").await?;
+
+        // THEN the product is deleted
+        assert!(deleted);
+        // AND the deletion is conditioned on the item read
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].actual.body().bytes().unwrap()).unwrap();
+        let items = &body["TransactItems"];
+        assert_eq!(items[0]["Update"]["TableName"], "test");
+        assert_eq!(
+            items[0]["Update"]["UpdateExpression"],
+            "SET deleted_at = :deleted_at"
+        );
+        assert_eq!(
+            items[0]["Update"]["ConditionExpression"],
+            "attribute_not_exists(#attributes) AND attribute_not_exists(#images) AND attribute_not_exists(#deleted_at) AND attribute_not_exists(#expires_at) AND #c0 = :c0 AND #c1 = :c1 AND #c2 = :c2"
+        );
+        // AND a Deleted event is written to the outbox
+        assert_eq!(items[1]["Put"]["TableName"], "outbox");
+        let event: serde_json::Value =
+            serde_json::from_str(items[1]["Put"]["Item"]["event"]["S"].as_str().unwrap()).unwrap();
+        assert_eq!(event["type"], "Deleted");
+        assert_eq!(event["product"]["id"], "1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry_with_outbox() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an outbox and a product without expiry
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#,
+                    ))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox");
+
+        // WHEN setting the expiry of the product
+        store.set_expiry("1", 1641081600).await?;
+
+        // THEN the write is conditioned on the product being neither deleted
+        // nor expiring
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].actual.body().bytes().unwrap()).unwrap();
+        let update = &body["TransactItems"][0]["Update"];
+        let condition = update["ConditionExpression"].as_str().unwrap();
+        assert!(condition.contains("attribute_not_exists(#deleted_at)"));
+        assert!(condition.contains("attribute_not_exists(#expires_at)"));
+        assert_eq!(
+            update["ExpressionAttributeNames"]["#expires_at"],
+            "expires_at"
+        );
+        // AND a DeletionScheduled event is written to the outbox
+        let event: serde_json::Value = serde_json::from_str(
+            body["TransactItems"][1]["Put"]["Item"]["event"]["S"]
+                .as_str()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(event["type"], "DeletionScheduled");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_sharded() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a sharded product
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Responses": {"test": []}}"#))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_sharding(["1".to_string()], 4);
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+
+        // WHEN putting the product
+        let previous = store.put(&product).await?;
+
+        // THEN the shards are read first, and there was no previous product
+        assert_eq!(previous, None);
+        // AND the item is written to the first shard
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains(r#""id":{"S":"1#0"}"#));
+        assert!(body.contains(r#""shard":{"N":"0"}"#));
+        assert!(body.contains(r#""updated_at":{"N":"#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_sharded_previous() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a sharded product written to a shard
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1#3"}, "name": {"S": "old"}, "price": {"N": "1.0"}, "shard": {"N": "3"}, "updated_at": {"N": "1"}, "previous": {"M": {"id": {"S": "1"}, "name": {"S": "older"}, "price": {"N": "0.5"}}}}]}}"#))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_sharding(["1".to_string()], 4);
         let product = Product {
-            id: "id".to_owned(),
-            name: "name".to_owned(),
+            id: "1".to_string(),
+            name: "new".to_string(),
             price: 1.5,
+            attributes: Default::default(),
+            images: Default::default(),
+        };
+
+        // WHEN putting the product
+        let previous = store.put(&product).await?;
+
+        // THEN the latest version is returned without the shard attributes
+        let previous = previous.unwrap();
+        assert_eq!(previous.id, "1");
+        assert_eq!(previous.name, "old");
+        // AND the new shard holds that version
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains(r#""id":{"S":"1#0"}"#));
+        assert!(body.contains(r#""previous":{"M":{"#));
+        assert!(body.contains(r#""name":{"S":"old"}"#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_sharded() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a product written to two shards
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from(r#"{"RequestItems":{"test":{"Keys":[{"id":{"S":"1#0"}},{"id":{"S":"1#1"}}]}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1#0"}, "name": {"S": "old"}, "price": {"N": "1.0"}, "shard": {"N": "0"}, "updated_at": {"N": "1"}}, {"id": {"S": "1#1"}, "name": {"S": "new"}, "price": {"N": "2.0"}, "shard": {"N": "1"}, "updated_at": {"N": "2"}}]}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_sharding(["1".to_string()], 2);
+
+        // WHEN getting the product
+        let res = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN the most recent write is returned without the shard suffix
+        let product = res.unwrap();
+        assert_eq!(product.id, "1");
+        assert_eq!(product.name, "new");
+        // AND the request reads all shards
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_many_sharded() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a sharded and an unsharded product
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from(r#"{"RequestItems":{"test":{"Keys":[{"id":{"S":"1#0"}},{"id":{"S":"1#1"}},{"id":{"S":"2"}}]}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1#1"}, "name": {"S": "new"}, "price": {"N": "2.0"}, "shard": {"N": "1"}, "updated_at": {"N": "2"}}, {"id": {"S": "2"}, "name": {"S": "test2"}, "price": {"N": "2.0"}}, {"id": {"S": "1#0"}, "name": {"S": "old"}, "price": {"N": "1.0"}, "shard": {"N": "0"}, "updated_at": {"N": "1"}}]}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_sharding(["1".to_string()], 2);
+
+        // WHEN getting both products
+        let mut res = store.get_many(&["1".to_string(), "2".to_string()]).await?;
+        res.sort_by(|a, b| a.id.cmp(&b.id));
+
+        // THEN the most recent write of the sharded product is returned
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].id, "1");
+        assert_eq!(res[0].name, "new");
+        assert_eq!(res[1].id, "2");
+        // AND all keys are read in a single request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_many_within_parallel() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore and more ids than fit in a single request
+        let response = || {
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}]}}"#))
+                .unwrap()
         };
+        let request = || {
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap()
+        };
+        let conn = TestConnection::new(vec![(request(), response()), (request(), response())]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBStore::new(client, "test".to_string()).with_batch_get_concurrency(2);
+        let ids: Vec<String> = (0..150).map(|i| i.to_string()).collect();
+
+        // WHEN getting the products within a generous budget
+        let res = store.get_many_within(&ids, Duration::from_secs(5)).await?;
 
-        let value: HashMap<String, AttributeValue> = (&product).into();
-        assert_eq!(value.get("id").unwrap().as_s().unwrap(), "id");
-        assert_eq!(value.get("name").unwrap().as_s().unwrap(), "name");
-        assert_eq!(value.get("price").unwrap().as_n().unwrap(), "1.5");
+        // THEN the ids are split across two requests
+        assert_eq!(conn.requests().len(), 2);
+        // AND the results of both requests are returned
+        assert_eq!(res.products.len(), 2);
+        assert!(!res.partial);
 
         Ok(())
     }
@@ -0,0 +1,947 @@
+//! # DynamoDB PartiQL store implementation
+//!
+//! Store implementation using PartiQL statements through the
+//! `ExecuteStatement` and `BatchExecuteStatement` APIs, instead of the
+//! item-level APIs used by `DynamoDBStore`. Both stores use the same table
+//! layout, so they can be compared against the same table.
+//!
+//! PartiQL statements are easier to extend with ad-hoc queries, but don't
+//! support parallel scans or `Select::Count`: scans and counts read all pages
+//! sequentially. Pagination tokens are the opaque `NextToken` returned by
+//! DynamoDB.
+//!
+//! Key prefixes and write sharding are not supported: use this store with
+//! tables written by an unprefixed, unsharded `DynamoDBStore`.
+
+use super::{
+    with_defaults, ItemCodec, SerdeCodec, ATTRIBUTES, DELETED_AT, EXPIRES_AT, IMAGES,
+    OPTIONAL_ATTRIBUTES,
+};
+use crate::{
+    store::{
+        ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet, StoreCount,
+        StoreDelete, StoreExpire, StoreGet, StoreGetAll, StoreHealth, StorePut,
+        StorePutConditional, StorePutMany, StoreRestore, StoreScanAll, StoreUpdate,
+    },
+    Error, Product, ProductPatch, ProductRange,
+};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, BatchStatementRequest},
+    Client,
+};
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::retry::ProvideErrorKind;
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Maximum number of statements in a `BatchExecuteStatement` request
+const BATCH_STATEMENT_SIZE: usize = 25;
+
+/// DynamoDB PartiQL store implementation.
+pub struct DynamoDBPartiQLStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBPartiQLStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBPartiQLStore {
+        DynamoDBPartiQLStore { client, table_name }
+    }
+
+    /// Build a `SELECT` statement for products matching a filter
+    ///
+    /// Returns the statement and its parameters, in order.
+    fn select(
+        &self,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> (String, Vec<AttributeValue>) {
+        let projection = match fields {
+            None => "*".to_string(),
+            Some(fields) => {
+                let mut attributes = vec![ProductField::Id.as_str()];
+                attributes.extend(
+                    fields
+                        .iter()
+                        .filter(|f| **f != ProductField::Id)
+                        .map(|f| f.as_str()),
+                );
+                attributes
+                    .iter()
+                    .map(|a| format!("\"{}\"", a))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+
+        let mut conditions = vec![format!("{} IS MISSING", DELETED_AT)];
+        let mut parameters = Vec::new();
+        if let Some(min_price) = filter.min_price {
+            conditions.push("price >= ?".to_string());
+            parameters.push(AttributeValue::N(format!("{:}", min_price)));
+        }
+        if let Some(max_price) = filter.max_price {
+            conditions.push("price <= ?".to_string());
+            parameters.push(AttributeValue::N(format!("{:}", max_price)));
+        }
+        if let Some(name) = &filter.name_contains {
+            conditions.push("contains(\"name\", ?)".to_string());
+            parameters.push(AttributeValue::S(name.clone()));
+        }
+        for (key, value) in &filter.attributes {
+            // Keys are quoted identifiers, with quotes escaped by doubling them
+            let path = format!("\"{}\".\"{}\"", ATTRIBUTES, key.replace('"', "\"\""));
+            let mut alternatives = vec![format!("{} = ?", path)];
+            parameters.push(AttributeValue::S(value.clone()));
+            if let Ok(n) = value.parse::<f64>() {
+                alternatives.push(format!("{} = ?", path));
+                parameters.push(AttributeValue::N(format!("{:}", n)));
+            }
+            if let Ok(b) = value.parse::<bool>() {
+                alternatives.push(format!("{} = ?", path));
+                parameters.push(AttributeValue::Bool(b));
+            }
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        let statement = format!(
+            "SELECT {} FROM \"{}\" WHERE {}",
+            projection,
+            self.table_name,
+            conditions.join(" AND ")
+        );
+        (statement, parameters)
+    }
+
+    /// Run a `SELECT` statement until all its pages are retrieved
+    async fn select_all(&self, fields: Option<&[ProductField]>) -> Result<Vec<Product>, Error> {
+        let (statement, parameters) = self.select(&ProductFilter::default(), fields);
+        let mut products = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let res = self
+                .client
+                .execute_statement()
+                .statement(&statement)
+                .set_parameters(Some(parameters.clone()).filter(|p| !p.is_empty()))
+                .set_next_token(next_token)
+                .send()
+                .await?;
+
+            products.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|item| to_product(item, fields))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a token for the next page
+            next_token = match res.next_token {
+                Some(token) => Some(token),
+                None => break,
+            };
+        }
+
+        Ok(products)
+    }
+
+    /// Run a statement that updates a single item
+    ///
+    /// Returns `false` if the statement's conditions were not met.
+    async fn execute_update(
+        &self,
+        statement: String,
+        parameters: Vec<AttributeValue>,
+    ) -> Result<bool, Error> {
+        let res = self
+            .client
+            .execute_statement()
+            .statement(statement)
+            .set_parameters(Some(parameters))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("ConditionalCheckFailedException") =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Get a live item with strong consistency, if the product is at the
+    /// expected version
+    ///
+    /// Returns the item with the product.
+    async fn get_at_version(
+        &self,
+        id: &str,
+        version: &str,
+    ) -> Result<Option<(HashMap<String, AttributeValue>, Product)>, Error> {
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "SELECT * FROM \"{}\" WHERE id = ?",
+                self.table_name
+            ))
+            .parameters(AttributeValue::S(id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        match res.items.unwrap_or_default().into_iter().next() {
+            Some(item) if !item.contains_key(DELETED_AT) => {
+                let product = to_product(item.clone(), None)?;
+                Ok((product.version() == version).then_some((item, product)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Conditions checking that an item is still as it was read
+///
+/// Returns the conditions, and their parameters in order. Product
+/// attributes missing from the item must still be missing.
+fn unchanged_conditions(
+    item: HashMap<String, AttributeValue>,
+) -> (Vec<String>, Vec<AttributeValue>) {
+    let mut attributes: Vec<_> = item.into_iter().collect();
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut conditions = Vec::new();
+    let mut parameters = Vec::new();
+    for name in OPTIONAL_ATTRIBUTES {
+        if !attributes.iter().any(|(n, _)| n == name) {
+            conditions.push(format!("\"{}\" IS MISSING", name));
+        }
+    }
+    for (name, value) in attributes {
+        conditions.push(format!("\"{}\" = ?", name.replace('"', "\"\"")));
+        parameters.push(value);
+    }
+    (conditions, parameters)
+}
+
+/// Convert a DynamoDB item into a Product
+///
+/// If only some `fields` were retrieved, the missing fields keep their
+/// default value.
+fn to_product(
+    item: HashMap<String, AttributeValue>,
+    fields: Option<&[ProductField]>,
+) -> Result<Product, Error> {
+    match fields {
+        None => SerdeCodec.decode(&item),
+        Some(_) => SerdeCodec.decode(&with_defaults(item)),
+    }
+}
+
+impl Store for DynamoDBPartiQLStore {}
+
+#[async_trait]
+impl StoreGetAll for DynamoDBPartiQLStore {
+    /// Get a page of items
+    ///
+    /// As with scans, DynamoDB applies the filter after reading `limit`
+    /// items, so a page can contain fewer products than requested.
+    #[instrument(skip(self))]
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
+        info!("Selecting items from DynamoDB table");
+        let (statement, parameters) = self.select(filter, fields);
+        let res = self
+            .client
+            .execute_statement()
+            .statement(statement)
+            .set_parameters(Some(parameters).filter(|p| !p.is_empty()))
+            // DynamoDB rejects a limit of zero
+            .limit(limit.max(1) as i32)
+            .set_next_token(next.map(String::from))
+            .send()
+            .await?;
+
+        let products = res
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| to_product(item, fields))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ProductRange {
+            products,
+            next: res.next_token,
+        })
+    }
+}
+
+#[async_trait]
+impl StoreScanAll for DynamoDBPartiQLStore {
+    /// Get all items
+    ///
+    /// PartiQL doesn't support parallel scans, so pages are read one after
+    /// the other regardless of `total_segments`.
+    #[instrument(skip(self))]
+    async fn scan_all(&self, _total_segments: usize) -> Result<Vec<Product>, Error> {
+        info!("Selecting all items from DynamoDB table");
+        self.select_all(None).await
+    }
+}
+
+#[async_trait]
+impl StoreCount for DynamoDBPartiQLStore {
+    /// Count items by selecting only their ids
+    #[instrument(skip(self))]
+    async fn count(&self) -> Result<usize, Error> {
+        info!("Counting items in DynamoDB table");
+        Ok(self.select_all(Some(&[ProductField::Id])).await?.len())
+    }
+}
+
+#[async_trait]
+impl StoreGet for DynamoDBPartiQLStore {
+    /// Get item
+    #[instrument(skip(self))]
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        info!("Selecting item with id '{}' from DynamoDB table", id);
+        let (statement, mut parameters) = self.select(&ProductFilter::default(), fields);
+        parameters.push(AttributeValue::S(id.to_string()));
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!("{} AND id = ?", statement))
+            .set_parameters(Some(parameters))
+            .consistent_read(consistency == ReadConsistency::Strong)
+            .send()
+            .await?;
+
+        res.items
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .map(|item| to_product(item, fields))
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl StoreBatchGet for DynamoDBPartiQLStore {
+    /// Get items in batches of statements
+    #[instrument(skip(self, ids))]
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        info!("Selecting {} items from DynamoDB table", ids.len());
+        let statement = format!("SELECT * FROM \"{}\" WHERE id = ?", self.table_name);
+        let mut products = Vec::new();
+
+        for chunk in ids.chunks(BATCH_STATEMENT_SIZE) {
+            let res = self
+                .client
+                .batch_execute_statement()
+                .set_statements(Some(
+                    chunk
+                        .iter()
+                        .map(|id| {
+                            BatchStatementRequest::builder()
+                                .statement(&statement)
+                                .parameters(AttributeValue::S(id.clone()))
+                                .build()
+                        })
+                        .collect(),
+                ))
+                .send()
+                .await?;
+
+            for response in res.responses.unwrap_or_default() {
+                if response.error.is_some() {
+                    return Err(Error::InternalError("Failed to get all items"));
+                }
+                match response.item {
+                    Some(item) if !item.contains_key(DELETED_AT) => {
+                        products.push(SerdeCodec.decode(&item)?)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(products)
+    }
+}
+
+#[async_trait]
+impl StorePut for DynamoDBPartiQLStore {
+    /// Create or update an item
+    ///
+    /// PartiQL has no upsert: `INSERT` fails if the item already exists, in
+    /// which case the item is updated instead. Updates remove the deletion
+    /// marker, as replacing an item would, and return the previous item.
+    #[instrument(skip(self))]
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        info!(
+            "Inserting item with id '{}' into DynamoDB table",
+            product.id
+        );
+        let mut item: HashMap<String, AttributeValue> = SerdeCodec.encode(product)?;
+        let attributes = item
+            .remove(ATTRIBUTES)
+            .unwrap_or_else(|| AttributeValue::M(HashMap::new()));
+        let images = item
+            .remove(IMAGES)
+            .unwrap_or_else(|| AttributeValue::L(Vec::new()));
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "INSERT INTO \"{}\" VALUE {{'id': ?, 'name': ?, 'price': ?, '{}': ?, '{}': ?}}",
+                self.table_name, ATTRIBUTES, IMAGES
+            ))
+            .parameters(AttributeValue::S(product.id.clone()))
+            .parameters(AttributeValue::S(product.name.clone()))
+            .parameters(AttributeValue::N(format!("{:}", product.price)))
+            .parameters(attributes.clone())
+            .parameters(images.clone())
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(None),
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("DuplicateItemException") =>
+            {
+                info!("Updating existing item with id '{}'", product.id);
+                let res = self
+                    .client
+                    .execute_statement()
+                    .statement(format!(
+                        "UPDATE \"{}\" SET \"name\" = ? SET price = ? SET \"{}\" = ? SET \"{}\" = ? REMOVE {} WHERE id = ? RETURNING ALL OLD *",
+                        self.table_name, ATTRIBUTES, IMAGES, DELETED_AT
+                    ))
+                    .parameters(AttributeValue::S(product.name.clone()))
+                    .parameters(AttributeValue::N(format!("{:}", product.price)))
+                    .parameters(attributes)
+                    .parameters(images)
+                    .parameters(AttributeValue::S(product.id.clone()))
+                    .send()
+                    .await?;
+
+                match res.items.unwrap_or_default().into_iter().next() {
+                    Some(item) if !item.contains_key(DELETED_AT) => {
+                        Ok(Some(to_product(item, None)?))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl StorePutConditional for DynamoDBPartiQLStore {
+    /// Create an item unless a live one exists
+    ///
+    /// If `INSERT` fails because the item exists, a soft-deleted item is
+    /// replaced with an `UPDATE` conditioned on its deletion marker.
+    #[instrument(skip(self))]
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        info!("Creating item with id '{}' in DynamoDB table", product.id);
+        let mut item: HashMap<String, AttributeValue> = SerdeCodec.encode(product)?;
+        let attributes = item
+            .remove(ATTRIBUTES)
+            .unwrap_or_else(|| AttributeValue::M(HashMap::new()));
+        let images = item
+            .remove(IMAGES)
+            .unwrap_or_else(|| AttributeValue::L(Vec::new()));
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "INSERT INTO \"{}\" VALUE {{'id': ?, 'name': ?, 'price': ?, '{}': ?, '{}': ?}}",
+                self.table_name, ATTRIBUTES, IMAGES
+            ))
+            .parameters(AttributeValue::S(product.id.clone()))
+            .parameters(AttributeValue::S(product.name.clone()))
+            .parameters(AttributeValue::N(format!("{:}", product.price)))
+            .parameters(attributes.clone())
+            .parameters(images.clone())
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => return Ok(true),
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("DuplicateItemException") => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "UPDATE \"{}\" SET \"name\" = ? SET price = ? SET \"{}\" = ? SET \"{}\" = ? REMOVE {} REMOVE {} WHERE id = ? AND {} IS NOT MISSING",
+                self.table_name, ATTRIBUTES, IMAGES, DELETED_AT, EXPIRES_AT, DELETED_AT
+            ))
+            .parameters(AttributeValue::S(product.name.clone()))
+            .parameters(AttributeValue::N(format!("{:}", product.price)))
+            .parameters(attributes)
+            .parameters(images)
+            .parameters(AttributeValue::S(product.id.clone()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // The existing item isn't deleted
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("ConditionalCheckFailedException") =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replace an item if the product is at the expected version
+    ///
+    /// The item is read first, and the `UPDATE` conditioned on every
+    /// attribute being unchanged. If a concurrent write changed the item,
+    /// nothing is written.
+    #[instrument(skip(self))]
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        info!(
+            "Replacing item with id '{}' at version {} in DynamoDB table",
+            product.id, version
+        );
+        let (item, old) = match self.get_at_version(&product.id, version).await? {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+
+        let mut new: HashMap<String, AttributeValue> = SerdeCodec.encode(product)?;
+        let attributes = new
+            .remove(ATTRIBUTES)
+            .unwrap_or_else(|| AttributeValue::M(HashMap::new()));
+        let images = new
+            .remove(IMAGES)
+            .unwrap_or_else(|| AttributeValue::L(Vec::new()));
+        let (conditions, condition_parameters) = unchanged_conditions(item);
+        let mut parameters = vec![
+            AttributeValue::S(product.name.clone()),
+            AttributeValue::N(format!("{:}", product.price)),
+            attributes,
+            images,
+        ];
+        parameters.extend(condition_parameters);
+        let replaced = self
+            .execute_update(
+                format!(
+                    "UPDATE \"{}\" SET \"name\" = ? SET price = ? SET \"{}\" = ? SET \"{}\" = ? WHERE {}",
+                    self.table_name,
+                    ATTRIBUTES,
+                    IMAGES,
+                    conditions.join(" AND ")
+                ),
+                parameters,
+            )
+            .await?;
+        Ok(replaced.then_some(old))
+    }
+
+    /// Patch an item if the product is at the expected version
+    ///
+    /// The patched product is written under the same condition as
+    /// `put_if_version`.
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let mut product = match self.get(id, ReadConsistency::Strong, None).await? {
+            Some(product) => product,
+            None => return Ok(None),
+        };
+        patch.apply(&mut product);
+        let updated = self.put_if_version(&product, version).await?.is_some();
+        Ok(updated.then_some(product))
+    }
+
+    /// Soft delete an item if the product is at the expected version
+    ///
+    /// The item is read first, and the `UPDATE` conditioned on every
+    /// attribute being unchanged.
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        info!(
+            "Soft deleting item with id '{}' at version {} from DynamoDB table",
+            id, version
+        );
+        let item = match self.get_at_version(id, version).await? {
+            Some((item, _)) => item,
+            None => return Ok(false),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_secs();
+        let (conditions, condition_parameters) = unchanged_conditions(item);
+        let mut parameters = vec![AttributeValue::N(now.to_string())];
+        parameters.extend(condition_parameters);
+        self.execute_update(
+            format!(
+                "UPDATE \"{}\" SET {} = ? WHERE {}",
+                self.table_name,
+                DELETED_AT,
+                conditions.join(" AND ")
+            ),
+            parameters,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl StorePutMany for DynamoDBPartiQLStore {
+    /// Create or update items concurrently
+    ///
+    /// A batch of `INSERT` statements would fail for existing items, so each
+    /// product goes through `put`. Only the last product with a given id is
+    /// written, as concurrent writes to the same item could land in any order.
+    #[instrument(skip(self, products))]
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        let latest: HashMap<&str, &Product> = products.iter().map(|p| (p.id.as_str(), p)).collect();
+        let products: Vec<&Product> = latest.into_values().collect();
+
+        for chunk in products.chunks(BATCH_STATEMENT_SIZE) {
+            join_all(chunk.iter().map(|product| self.put(product)))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreUpdate for DynamoDBPartiQLStore {
+    /// Partially update an item
+    ///
+    /// `UPDATE` statements fail with a conditional check error if no item
+    /// matches the `WHERE` clause, which means there is nothing to update.
+    #[instrument(skip(self))]
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        info!("Updating item with id '{}' in DynamoDB table", id);
+        let mut sets = Vec::new();
+        let mut parameters = Vec::new();
+        if let Some(name) = &patch.name {
+            sets.push("SET \"name\" = ?");
+            parameters.push(AttributeValue::S(name.clone()));
+        }
+        if let Some(price) = patch.price {
+            sets.push("SET price = ?");
+            parameters.push(AttributeValue::N(format!("{:}", price)));
+        }
+        if sets.is_empty() {
+            return self.get(id, ReadConsistency::Strong, None).await;
+        }
+        parameters.push(AttributeValue::S(id.to_string()));
+
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "UPDATE \"{}\" {} WHERE id = ? AND {} IS MISSING RETURNING ALL NEW *",
+                self.table_name,
+                sets.join(" "),
+                DELETED_AT
+            ))
+            .set_parameters(Some(parameters))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) => res
+                .items
+                .unwrap_or_default()
+                .into_iter()
+                .next()
+                .map(|item| to_product(item, None))
+                .transpose(),
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("ConditionalCheckFailedException") =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDelete for DynamoDBPartiQLStore {
+    /// Soft delete item
+    ///
+    /// The condition keeps the original deletion time if the item was already
+    /// deleted. Missing items are ignored.
+    #[instrument(skip(self))]
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        info!("Soft deleting item with id '{}' from DynamoDB table", id);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_secs();
+        self.execute_update(
+            format!(
+                "UPDATE \"{}\" SET {} = ? WHERE id = ? AND {} IS MISSING",
+                self.table_name, DELETED_AT, DELETED_AT
+            ),
+            vec![
+                AttributeValue::N(now.to_string()),
+                AttributeValue::S(id.to_string()),
+            ],
+        )
+        .await
+    }
+
+    /// Permanently delete item
+    ///
+    /// The statement returns the deleted item to know whether it existed.
+    #[instrument(skip(self))]
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        info!("Deleting item with id '{}' from DynamoDB table", id);
+        let res = self
+            .client
+            .execute_statement()
+            .statement(format!(
+                "DELETE FROM \"{}\" WHERE id = ? RETURNING ALL OLD *",
+                self.table_name
+            ))
+            .parameters(AttributeValue::S(id.to_string()))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) => Ok(!res.items.unwrap_or_default().is_empty()),
+            // Nothing to delete
+            Err(SdkError::ServiceError { err, .. })
+                if err.code() == Some("ConditionalCheckFailedException") =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl StoreRestore for DynamoDBPartiQLStore {
+    /// Restore a soft-deleted item
+    #[instrument(skip(self))]
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        info!("Restoring item with id '{}' in DynamoDB table", id);
+        self.execute_update(
+            format!(
+                "UPDATE \"{}\" REMOVE {} REMOVE {} WHERE id = ? AND {} IS NOT MISSING",
+                self.table_name, DELETED_AT, EXPIRES_AT, DELETED_AT
+            ),
+            vec![AttributeValue::S(id.to_string())],
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl StoreExpire for DynamoDBPartiQLStore {
+    /// Set the `expires_at` TTL attribute on the item
+    ///
+    /// Missing items are ignored.
+    #[instrument(skip(self))]
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        info!("Setting expiry of item with id '{}' in DynamoDB table", id);
+        self.execute_update(
+            format!(
+                "UPDATE \"{}\" SET {} = ? WHERE id = ?",
+                self.table_name, EXPIRES_AT
+            ),
+            vec![
+                AttributeValue::N(at.to_string()),
+                AttributeValue::S(id.to_string()),
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreHealth for DynamoDBPartiQLStore {
+    /// Check that the table exists and can be described
+    #[instrument(skip(self))]
+    async fn ping(&self) -> Result<(), Error> {
+        info!("Describing DynamoDB table '{}'", self.table_name);
+        self.client
+            .describe_table()
+            .table_name(&self.table_name)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_all() -> Result<(), Error> {
+        // GIVEN a DynamoDBPartiQLStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.ExecuteStatement")
+                .body(SdkBody::from(r#"{"Statement":"SELECT * FROM \"test\" WHERE deleted_at IS MISSING AND price >= ?","Parameters":[{"N":"5"}],"Limit":20}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "10"}}], "NextToken": "token"}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBPartiQLStore::new(client, "test".to_string());
+        let filter = ProductFilter {
+            min_price: Some(5.0),
+            ..Default::default()
+        };
+
+        // WHEN getting a page of items
+        let res = store.all(None, 20, &filter, None).await?;
+
+        // THEN the item and the next token are returned
+        assert_eq!(res.products.len(), 1);
+        assert_eq!(res.next.as_deref(), Some("token"));
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get() -> Result<(), Error> {
+        // GIVEN a DynamoDBPartiQLStore with one item
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.ExecuteStatement")
+                .body(SdkBody::from(r#"{"Statement":"SELECT * FROM \"test\" WHERE deleted_at IS MISSING AND id = ?","Parameters":[{"S":"1"}],"ConsistentRead":false}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "10"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBPartiQLStore::new(client, "test".to_string());
+
+        // WHEN getting the item
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN the item is returned
+        assert_eq!(product.unwrap().name, "test1");
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_existing() -> Result<(), Error> {
+        // GIVEN a DynamoDBPartiQLStore with an existing item
+        let conn = TestConnection::new(vec![
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.ExecuteStatement")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(
+                        r#"{"__type": "com.amazonaws.dynamodb.v20120810#DuplicateItemException", "message": "Duplicate primary key exists in table"}"#,
+                    ))
+                    .unwrap(),
+            ),
+            (
+                dynamodb_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.ExecuteStatement")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}}]}"#,
+                    ))
+                    .unwrap(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBPartiQLStore::new(client, "test".to_string());
+
+        // WHEN putting the item
+        let previous = store
+            .put(&Product {
+                id: "1".to_string(),
+                name: "test1".to_string(),
+                price: 1.5,
+                attributes: Default::default(),
+                images: Default::default(),
+            })
+            .await?;
+
+        // THEN the item is updated after the insert failed
+        let requests = conn.requests();
+        assert_eq!(requests.len(), 2);
+        let body = std::str::from_utf8(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("UPDATE"));
+        assert!(body.contains("RETURNING ALL OLD *"));
+        // AND the previous item is returned
+        assert_eq!(previous.map(|p| p.name), Some("test0".to_string()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! # DynamoDB history store implementation
+//!
+//! History store implementation using the AWS SDK for DynamoDB.
+//!
+//! Revisions use the product id as the partition key and the version as the
+//! sort key.
+
+use super::{HistoryStore, StoreAppendHistory, StoreGetHistory, StoreScanHistory};
+use crate::{
+    store::dynamodb::{ext::AttributeValuesExt, ItemCodec, SerdeCodec},
+    Error, ProductRevision,
+};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// DynamoDB history store implementation.
+pub struct DynamoDBHistoryStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBHistoryStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBHistoryStore {
+        DynamoDBHistoryStore { client, table_name }
+    }
+}
+
+impl HistoryStore for DynamoDBHistoryStore {}
+
+#[async_trait]
+impl StoreAppendHistory for DynamoDBHistoryStore {
+    /// Write a revision
+    #[instrument(skip(self))]
+    async fn append(&self, revision: &ProductRevision) -> Result<(), Error> {
+        info!(
+            "Putting revision {} of product '{}' into DynamoDB table",
+            revision.version, revision.product.id
+        );
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(revision.try_into()?))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetHistory for DynamoDBHistoryStore {
+    /// Query all revisions of a product, newest first
+    #[instrument(skip(self))]
+    async fn history(&self, id: &str) -> Result<Vec<ProductRevision>, Error> {
+        info!("Querying history of product '{}' from DynamoDB table", id);
+        let mut revisions = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("id = :id")
+                .expression_attribute_values(":id", AttributeValue::S(id.to_owned()))
+                .scan_index_forward(false)
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            revisions.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| v.try_into())
+                    .collect::<Result<Vec<ProductRevision>, Error>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(revisions)
+    }
+}
+
+#[async_trait]
+impl StoreScanHistory for DynamoDBHistoryStore {
+    /// Scan the revisions of all products in a time range
+    #[instrument(skip(self))]
+    async fn revisions_between(&self, from: u64, to: u64) -> Result<Vec<ProductRevision>, Error> {
+        info!(
+            "Scanning revisions between {} and {} from DynamoDB table",
+            from, to
+        );
+        let mut revisions = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#version >= :from AND #version < :to")
+                .expression_attribute_names("#version", "version")
+                .expression_attribute_values(":from", AttributeValue::N(from.to_string()))
+                .expression_attribute_values(":to", AttributeValue::N(to.to_string()))
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            revisions.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| v.try_into())
+                    .collect::<Result<Vec<ProductRevision>, Error>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(revisions)
+    }
+}
+
+impl TryFrom<&ProductRevision> for HashMap<String, AttributeValue> {
+    type Error = Error;
+
+    /// Convert a &ProductRevision into a DynamoDB item
+    ///
+    /// The item holds the attributes of the product, and its version.
+    fn try_from(value: &ProductRevision) -> Result<Self, Self::Error> {
+        let mut retval: HashMap<String, AttributeValue> = SerdeCodec.encode(&value.product)?;
+        retval.insert(
+            "version".to_owned(),
+            AttributeValue::N(value.version.to_string()),
+        );
+
+        Ok(retval)
+    }
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for ProductRevision {
+    type Error = Error;
+
+    /// Try to convert a DynamoDB item into a ProductRevision
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let version = value
+            .get_n("version")
+            .ok_or(Error::InternalError("Missing version"))? as u64;
+        let product = SerdeCodec.decode(&value)?;
+
+        Ok(ProductRevision { version, product })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{dynamodb_request_builder, mock_sdk_config},
+        Product,
+    };
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_append() -> Result<(), Error> {
+        // GIVEN an empty DynamoDBHistoryStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"price":{"N":"1.5"},"version":{"N":"1000"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBHistoryStore::new(client, "test".to_string());
+
+        // WHEN appending a revision
+        store
+            .append(&ProductRevision {
+                version: 1000,
+                product: Product {
+                    id: "1".to_string(),
+                    name: "test1".to_string(),
+                    price: 1.5,
+                    attributes: Default::default(),
+                    images: Default::default(),
+                },
+            })
+            .await?;
+
+        // THEN the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_history() -> Result<(), Error> {
+        // GIVEN a DynamoDBHistoryStore with two revisions
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r#"{"TableName":"test","KeyConditionExpression":"id = :id","ExpressionAttributeValues":{":id":{"S":"1"}},"ScanIndexForward":false}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test2"}, "price": {"N": "2.0"}, "version": {"N": "2000"}}, {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "1000"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBHistoryStore::new(client, "test".to_string());
+
+        // WHEN getting the history of the product
+        let history = store.history("1").await?;
+
+        // THEN both revisions are returned, newest first
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].version, 2000);
+        assert_eq!(history[0].product.name, "test2");
+        assert_eq!(history[1].version, 1000);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! # In-memory history store implementation
+//!
+//! This is a simple in-memory history store implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::{HistoryStore, StoreAppendHistory, StoreGetHistory, StoreScanHistory};
+use crate::{Error, ProductRevision};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryHistoryStore {
+    data: RwLock<HashMap<String, Vec<ProductRevision>>>,
+}
+
+impl MemoryHistoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl HistoryStore for MemoryHistoryStore {}
+
+#[async_trait]
+impl StoreAppendHistory for MemoryHistoryStore {
+    async fn append(&self, revision: &ProductRevision) -> Result<(), Error> {
+        self.data
+            .write()
+            .unwrap()
+            .entry(revision.product.id.clone())
+            .or_default()
+            .push(revision.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetHistory for MemoryHistoryStore {
+    async fn history(&self, id: &str) -> Result<Vec<ProductRevision>, Error> {
+        let mut revisions = self
+            .data
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or_default();
+        revisions.sort_by(|a, b| b.version.cmp(&a.version));
+        Ok(revisions)
+    }
+}
+
+#[async_trait]
+impl StoreScanHistory for MemoryHistoryStore {
+    async fn revisions_between(&self, from: u64, to: u64) -> Result<Vec<ProductRevision>, Error> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .values()
+            .flatten()
+            .filter(|revision| revision.version >= from && revision.version < to)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Product;
+
+    fn get_revision(id: &str, version: u64) -> ProductRevision {
+        ProductRevision {
+            version,
+            product: Product {
+                id: id.to_string(),
+                name: "foo".to_string(),
+                price: version as f64,
+                attributes: Default::default(),
+                images: Default::default(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history() -> Result<(), Error> {
+        // GIVEN a store with revisions for two products
+        let store = MemoryHistoryStore::new();
+        store.append(&get_revision("1", 1)).await?;
+        store.append(&get_revision("1", 2)).await?;
+        store.append(&get_revision("2", 1)).await?;
+
+        // WHEN getting the history of the first product
+        let history = store.history("1").await?;
+
+        // THEN we get its revisions, newest first
+        assert_eq!(history, vec![get_revision("1", 2), get_revision("1", 1)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_history_empty() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryHistoryStore::new();
+
+        // WHEN getting the history of a product
+        let history = store.history("1").await?;
+
+        // THEN we get no revisions
+        assert!(history.is_empty());
+
+        Ok(())
+    }
+}
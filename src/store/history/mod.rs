@@ -0,0 +1,38 @@
+//! # Product history storage
+//!
+//! Every revision of a product is appended to the history store when the
+//! product is written, so that past versions can be retrieved later.
+
+use crate::{Error, ProductRevision};
+use async_trait::async_trait;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBHistoryStore;
+pub use memory::MemoryHistoryStore;
+
+pub trait HistoryStore: StoreAppendHistory + StoreGetHistory + StoreScanHistory {}
+
+/// Trait for recording a product revision
+#[async_trait]
+pub trait StoreAppendHistory: Send + Sync {
+    async fn append(&self, revision: &ProductRevision) -> Result<(), Error>;
+}
+
+/// Trait for retrieving the revisions of a product
+///
+/// Revisions are returned from the newest to the oldest.
+#[async_trait]
+pub trait StoreGetHistory: Send + Sync {
+    async fn history(&self, id: &str) -> Result<Vec<ProductRevision>, Error>;
+}
+
+/// Trait for retrieving the revisions of all products in a time range
+///
+/// This returns the revisions with a version in `[from, to)`, in no
+/// particular order.
+#[async_trait]
+pub trait StoreScanHistory: Send + Sync {
+    async fn revisions_between(&self, from: u64, to: u64) -> Result<Vec<ProductRevision>, Error>;
+}
@@ -3,64 +3,376 @@
 //! This is a simple in-memory store implementation. It is not intended to be
 //! used in production, but rather as a simple implementation for local
 //! testing purposes.
-
-use super::{Store, StoreDelete, StoreGet, StoreGetAll, StorePut};
-use crate::{Error, Product, ProductRange};
+//!
+//! Products are kept sorted by id, which allows paginating through them using
+//! the id of the last product of a page as the `next` token. Soft-deleted
+//! products are moved to a separate map until they are restored.
+//!
+//! With `with_snapshot`, the content of the store is loaded from an encrypted
+//! snapshot file on start, and saved back periodically with
+//! `spawn_snapshots` and when the store is dropped, so that it survives
+//! restarts. Snapshots use the binary `Catalog` format, while older JSON
+//! snapshots can still be loaded.
+
+use super::snapshot::{self, SnapshotKey};
+use super::{
+    project, Catalog, ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet,
+    StoreCount, StoreDelete, StoreExpire, StoreGet, StoreGetAll, StoreGetWriteTime, StoreHealth,
+    StorePut, StorePutConditional, StorePutMany, StoreRestore, StoreScanAll, StoreUpdate,
+};
+use crate::{Error, Product, ProductPatch, ProductRange};
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info};
 
 #[derive(Default)]
 pub struct MemoryStore {
-    data: RwLock<HashMap<String, Product>>,
+    data: RwLock<BTreeMap<String, Product>>,
+    deleted: RwLock<BTreeMap<String, Product>>,
+    expiries: RwLock<BTreeMap<String, u64>>,
+    snapshot: Option<(PathBuf, SnapshotKey)>,
 }
 
 impl MemoryStore {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Create a store holding the products of a catalog
+    pub fn from_catalog(catalog: Catalog) -> Self {
+        let store = Self::new();
+        *store.data.write().unwrap() = by_id(catalog.products);
+        *store.deleted.write().unwrap() = by_id(catalog.deleted);
+        store
+    }
+
+    /// Copy the products of the store into a catalog
+    pub fn catalog(&self) -> Catalog {
+        Catalog {
+            products: self.data.read().unwrap().values().cloned().collect(),
+            deleted: self.deleted.read().unwrap().values().cloned().collect(),
+        }
+    }
+
+    /// Scheduled removal time of a product, set with `StoreExpire`
+    ///
+    /// Expired products are not removed from memory.
+    pub fn expires_at(&self, id: &str) -> Option<u64> {
+        self.expiries.read().unwrap().get(id).copied()
+    }
+
+    /// Persist the store to an encrypted snapshot file
+    ///
+    /// If the file already exists, the store is loaded from it. This fails if
+    /// the snapshot can't be decrypted with `key`.
+    pub fn with_snapshot(mut self, path: PathBuf, key: SnapshotKey) -> Result<Self, Error> {
+        if let Some(data) = snapshot::read(&path, &key)? {
+            let catalog = if Catalog::is_catalog(&data) {
+                Catalog::from_bytes(&data)?
+            } else {
+                serde_json::from_slice(&data)
+                    .map_err(|_| Error::InternalError("Failed to parse snapshot"))?
+            };
+            info!(
+                "Loaded {} products from snapshot {}",
+                catalog.products.len(),
+                path.display()
+            );
+            self.data = RwLock::new(by_id(catalog.products));
+            self.deleted = RwLock::new(by_id(catalog.deleted));
+        }
+        self.snapshot = Some((path, key));
+        Ok(self)
+    }
+
+    /// Save the store to its snapshot file
+    ///
+    /// This does nothing if the store wasn't configured with `with_snapshot`.
+    pub fn save_snapshot(&self) -> Result<(), Error> {
+        let (path, key) = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => return Ok(()),
+        };
+        snapshot::write(path, key, &self.catalog().to_bytes()?)
+    }
+
+    /// Save the store to its snapshot file every `period`
+    ///
+    /// The task stops once the store is dropped.
+    pub fn spawn_snapshots(self: &Arc<Self>, period: Duration) -> tokio::task::JoinHandle<()> {
+        let store = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match store.upgrade() {
+                    Some(store) => {
+                        if let Err(err) = store.save_snapshot() {
+                            error!("Failed to save snapshot: {}", err);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+impl Drop for MemoryStore {
+    fn drop(&mut self) {
+        if let Err(err) = self.save_snapshot() {
+            error!("Failed to save snapshot on shutdown: {}", err);
+        }
+    }
+}
+
+/// Index products by id
+fn by_id(products: Vec<Product>) -> BTreeMap<String, Product> {
+    products.into_iter().map(|p| (p.id.clone(), p)).collect()
 }
 
 impl Store for MemoryStore {}
 
 #[async_trait]
 impl StoreGetAll for MemoryStore {
-    async fn all(&self, _: Option<&str>) -> Result<ProductRange, Error> {
-        Ok(ProductRange {
-            products: self
-                .data
-                .read()
-                .unwrap()
-                .iter()
-                .map(|(_, v)| v.clone())
-                .collect(),
-            next: None,
-        })
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
+        // An empty page can't carry a next token, so it would end the listing
+        // early: always return at least one product
+        let limit = limit.max(1);
+        // Fetch one more product than requested to know if there is a next page
+        let mut products: Vec<Product> = self
+            .data
+            .read()
+            .unwrap()
+            .values()
+            .skip_while(|p| next.map_or(false, |next| p.id.as_str() <= next))
+            .filter(|p| filter.matches(p))
+            .take(limit + 1)
+            .map(|p| project(p.clone(), fields))
+            .collect();
+
+        let next = if products.len() > limit {
+            products.truncate(limit);
+            products.last().map(|p| p.id.clone())
+        } else {
+            None
+        };
+
+        Ok(ProductRange { products, next })
+    }
+}
+
+#[async_trait]
+impl StoreScanAll for MemoryStore {
+    async fn scan_all(&self, _: usize) -> Result<Vec<Product>, Error> {
+        Ok(self.data.read().unwrap().values().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl StoreCount for MemoryStore {
+    async fn count(&self) -> Result<usize, Error> {
+        Ok(self.data.read().unwrap().len())
     }
 }
 
 #[async_trait]
 impl StoreGet for MemoryStore {
-    async fn get(&self, id: &str) -> Result<Option<Product>, Error> {
-        Ok(self.data.read().unwrap().get(id).cloned())
+    async fn get(
+        &self,
+        id: &str,
+        _: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|p| project(p.clone(), fields)))
+    }
+}
+
+#[async_trait]
+impl StoreGetWriteTime for MemoryStore {
+    /// Get a product, whose write time is never recorded
+    async fn get_with_write_time(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<(Product, Option<u64>)>, Error> {
+        Ok(self
+            .get(id, consistency, fields)
+            .await?
+            .map(|product| (product, None)))
+    }
+}
+
+#[async_trait]
+impl StoreBatchGet for MemoryStore {
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        let data = self.data.read().unwrap();
+        Ok(ids.iter().filter_map(|id| data.get(id).cloned()).collect())
     }
 }
 
 #[async_trait]
 impl StorePut for MemoryStore {
-    async fn put(&self, product: &Product) -> Result<(), Error> {
-        self.data
-            .write()
-            .unwrap()
-            .insert(product.id.clone(), product.clone());
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        // Putting a product over a soft-deleted one replaces it
+        let mut data = self.data.write().unwrap();
+        self.deleted.write().unwrap().remove(&product.id);
+        Ok(data.insert(product.id.clone(), product.clone()))
+    }
+}
+
+#[async_trait]
+impl StorePutConditional for MemoryStore {
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        let mut data = self.data.write().unwrap();
+        if data.contains_key(&product.id) {
+            return Ok(false);
+        }
+        self.deleted.write().unwrap().remove(&product.id);
+        data.insert(product.id.clone(), product.clone());
+        Ok(true)
+    }
+
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let mut data = self.data.write().unwrap();
+        match data.get(&product.id) {
+            Some(current) if current.version() == version => {
+                Ok(data.insert(product.id.clone(), product.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let mut data = self.data.write().unwrap();
+        match data.get_mut(id) {
+            Some(product) if product.version() == version => {
+                patch.apply(product);
+                Ok(Some(product.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        let mut data = self.data.write().unwrap();
+        if data.get(id).map(Product::version).as_deref() != Some(version) {
+            return Ok(false);
+        }
+        if let Some(product) = data.remove(id) {
+            self.deleted
+                .write()
+                .unwrap()
+                .insert(id.to_string(), product);
+        }
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl StorePutMany for MemoryStore {
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        let mut deleted = self.deleted.write().unwrap();
+        for product in products {
+            deleted.remove(&product.id);
+            data.insert(product.id.clone(), product.clone());
+        }
         Ok(())
     }
 }
 
 #[async_trait]
 impl StoreDelete for MemoryStore {
-    async fn delete(&self, id: &str) -> Result<(), Error> {
-        self.data.write().unwrap().remove(id);
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let mut data = self.data.write().unwrap();
+        Ok(match data.remove(id) {
+            Some(product) => {
+                self.deleted
+                    .write()
+                    .unwrap()
+                    .insert(id.to_string(), product);
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        let mut data = self.data.write().unwrap();
+        let removed = data.remove(id).is_some();
+        let purged = self.deleted.write().unwrap().remove(id).is_some();
+        self.expiries.write().unwrap().remove(id);
+        Ok(removed || purged)
+    }
+}
+
+#[async_trait]
+impl StoreUpdate for MemoryStore {
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        Ok(self.data.write().unwrap().get_mut(id).map(|product| {
+            patch.apply(product);
+            product.clone()
+        }))
+    }
+}
+
+#[async_trait]
+impl StoreRestore for MemoryStore {
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        let mut data = self.data.write().unwrap();
+        Ok(match self.deleted.write().unwrap().remove(id) {
+            Some(product) => {
+                data.insert(id.to_string(), product);
+                self.expiries.write().unwrap().remove(id);
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+#[async_trait]
+impl StoreExpire for MemoryStore {
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        let exists = self.data.read().unwrap().contains_key(id)
+            || self.deleted.read().unwrap().contains_key(id);
+        if exists {
+            self.expiries.write().unwrap().insert(id.to_string(), at);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreHealth for MemoryStore {
+    async fn ping(&self) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -68,7 +380,7 @@ impl StoreDelete for MemoryStore {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Error;
+    use crate::{store::conformance, Error};
 
     struct ConstProduct<'a> {
         id: &'a str,
@@ -82,6 +394,8 @@ mod tests {
                 id: self.id.to_string(),
                 name: self.name.to_string(),
                 price: self.price,
+                attributes: Default::default(),
+                images: Default::default(),
             }
         }
     }
@@ -114,7 +428,7 @@ mod tests {
         let store = MemoryStore::new();
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20, &ProductFilter::default(), None).await?;
 
         // THEN we get an empty list
         assert_eq!(all.products.len(), 0);
@@ -133,7 +447,7 @@ mod tests {
         }
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20, &ProductFilter::default(), None).await?;
 
         // THEN we get the product
         assert_eq!(all.products.len(), 1);
@@ -155,7 +469,7 @@ mod tests {
         }
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20, &ProductFilter::default(), None).await?;
 
         // THEN we get the products
         assert_eq!(all.products.len(), 2);
@@ -165,6 +479,201 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_all_limit() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        {
+            let mut data = store.data.write().unwrap();
+            data.insert(product0.id.clone(), product0.clone());
+            data.insert(product1.id.clone(), product1.clone());
+        }
+
+        // WHEN we get all products with a limit of 1
+        let all = store.all(None, 1, &ProductFilter::default(), None).await?;
+
+        // THEN we get the first product
+        assert_eq!(all.products, vec![product0.clone()]);
+        // AND a next token
+        assert_eq!(all.next, Some(product0.id.clone()));
+
+        // WHEN we get the next page
+        let all = store
+            .all(all.next.as_deref(), 1, &ProductFilter::default(), None)
+            .await?;
+
+        // THEN we get the second product
+        assert_eq!(all.products, vec![product1]);
+        // AND no next token
+        assert_eq!(all.next, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_limit_zero() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        {
+            let mut data = store.data.write().unwrap();
+            data.insert(product0.id.clone(), product0.clone());
+            data.insert(product1.id.clone(), product1);
+        }
+
+        // WHEN we get all products with a limit of 0
+        let all = store.all(None, 0, &ProductFilter::default(), None).await?;
+
+        // THEN we still get the first product and a next token
+        assert_eq!(all.products, vec![product0.clone()]);
+        assert_eq!(all.next, Some(product0.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_pagination() -> Result<(), Error> {
+        for count in [0, 1, 2, 17, 100] {
+            conformance::check_pagination(&MemoryStore::new(), count).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_filter() -> Result<(), Error> {
+        // GIVEN a store with a cheap and an expensive product
+        let product0: Product = PRODUCT_0.into();
+        let mut product1: Product = PRODUCT_1.into();
+        product1.name = "bar".to_string();
+        product1.price = 100.0;
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+        store.put(&product1).await?;
+
+        // WHEN we get all products above a minimum price
+        let filter = ProductFilter {
+            min_price: Some(50.0),
+            ..Default::default()
+        };
+        let all = store.all(None, 20, &filter, None).await?;
+
+        // THEN we only get the expensive product
+        assert_eq!(all.products, vec![product1]);
+
+        // WHEN we get all products whose name contains "fo"
+        let filter = ProductFilter {
+            name_contains: Some("fo".to_string()),
+            ..Default::default()
+        };
+        let all = store.all(None, 20, &filter, None).await?;
+
+        // THEN we only get the first product
+        assert_eq!(all.products, vec![product0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_filter_attributes() -> Result<(), Error> {
+        // GIVEN a store with products with different attributes
+        let mut product0: Product = PRODUCT_0.into();
+        product0
+            .attributes
+            .insert("color".to_string(), serde_json::json!("red"));
+        product0
+            .attributes
+            .insert("fragile".to_string(), serde_json::json!(true));
+        let mut product1: Product = PRODUCT_1.into();
+        product1
+            .attributes
+            .insert("color".to_string(), serde_json::json!("blue"));
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+        store.put(&product1).await?;
+
+        // WHEN we get all red and fragile products
+        let filter = ProductFilter {
+            attributes: vec![
+                ("color".to_string(), "red".to_string()),
+                ("fragile".to_string(), "true".to_string()),
+            ],
+            ..Default::default()
+        };
+        let all = store.all(None, 20, &filter, None).await?;
+
+        // THEN we only get the first product
+        assert_eq!(all.products, vec![product0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_fields() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+
+        // WHEN we get only the names of all products
+        let all = store
+            .all(
+                None,
+                20,
+                &ProductFilter::default(),
+                Some(&[ProductField::Name]),
+            )
+            .await?;
+
+        // THEN the price is reset to its default value
+        assert_eq!(all.products[0].name, product0.name);
+        assert_eq!(all.products[0].price, 0.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scan_all() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        {
+            let mut data = store.data.write().unwrap();
+            data.insert(product0.id.clone(), product0.clone());
+            data.insert(product1.id.clone(), product1.clone());
+        }
+
+        // WHEN we scan all products
+        let all = store.scan_all(4).await?;
+
+        // THEN we get the products
+        assert_eq!(all, vec![product0, product1]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count() -> Result<(), Error> {
+        // GIVEN a store with a product and a deleted product
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+        store.put(&product1).await?;
+        store.delete(&product1.id).await?;
+
+        // WHEN we count the products
+        let count = store.count().await?;
+
+        // THEN the deleted product is not counted
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_delete() -> Result<(), Error> {
         // GIVEN a store with a product
@@ -180,8 +689,15 @@ mod tests {
 
         // THEN the length of the store is 0
         assert_eq!(store.data.read().unwrap().len(), 0);
+        // AND the product is kept as soft-deleted
+        assert_eq!(store.deleted.read().unwrap().len(), 1);
         // AND the product is not returned
-        assert_eq!(store.get(&product0.id).await?, None);
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            None
+        );
 
         Ok(())
     }
@@ -204,9 +720,114 @@ mod tests {
         // THEN the length of the store is 1
         assert_eq!(store.data.read().unwrap().len(), 1);
         // AND the product is not returned
-        assert_eq!(store.get(&product0.id).await?, None);
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            None
+        );
         // AND the second product is returned
-        assert_eq!(store.get(&product1.id).await?, Some(product1));
+        assert_eq!(
+            store
+                .get(&product1.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hard_delete() -> Result<(), Error> {
+        // GIVEN a store with a soft-deleted product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        {
+            let mut deleted = store.deleted.write().unwrap();
+            deleted.insert(product0.id.clone(), product0.clone());
+        }
+
+        // WHEN hard deleting the product
+        store.hard_delete(&product0.id).await?;
+
+        // THEN the product is gone
+        assert_eq!(store.deleted.read().unwrap().len(), 0);
+        // AND it cannot be restored
+        assert!(!store.restore(&product0.id).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+
+        // WHEN deleting then restoring the product
+        store.delete(&product0.id).await?;
+        let restored = store.restore(&product0.id).await?;
+
+        // THEN the product is restored
+        assert!(restored);
+        // AND the product is returned
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product0)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_if_absent() -> Result<(), Error> {
+        // GIVEN a store with a live and a soft-deleted product
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        store.put(&product0).await?;
+        store.put(&product1).await?;
+        store.delete(&product1.id).await?;
+
+        // WHEN creating both products again
+        let mut renamed = product0.clone();
+        renamed.name = "renamed".to_string();
+        let created0 = store.put_if_absent(&renamed).await?;
+        let created1 = store.put_if_absent(&product1).await?;
+
+        // THEN the live product is left alone
+        assert!(!created0);
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product0)
+        );
+        // AND the soft-deleted one is created again
+        assert!(created1);
+        assert_eq!(
+            store
+                .get(&product1.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_missing() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+
+        // WHEN restoring a product that was never deleted
+        let restored = store.restore("1").await?;
+
+        // THEN nothing is restored
+        assert!(!restored);
 
         Ok(())
     }
@@ -222,7 +843,9 @@ mod tests {
         }
 
         // WHEN getting the product
-        let product = store.get(&product0.id).await?;
+        let product = store
+            .get(&product0.id, ReadConsistency::Eventual, None)
+            .await?;
 
         // THEN the product is returned
         assert_eq!(product, Some(product0));
@@ -242,7 +865,12 @@ mod tests {
         // THEN the length of the store is 1
         assert_eq!(store.data.read().unwrap().len(), 1);
         // AND the product is returned
-        assert_eq!(store.get(&product0.id).await?, Some(product0));
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product0)
+        );
 
         Ok(())
     }
@@ -261,9 +889,75 @@ mod tests {
         // THEN the length of the store is 2
         assert_eq!(store.data.read().unwrap().len(), 2);
         // AND the products are returned
-        assert_eq!(store.get(&product0.id).await?, Some(product0));
-        assert_eq!(store.get(&product1.id).await?, Some(product1));
+        assert_eq!(
+            store
+                .get(&product0.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product0)
+        );
+        assert_eq!(
+            store
+                .get(&product1.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(product1)
+        );
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_snapshot() -> Result<(), Error> {
+        // GIVEN a store persisted to a snapshot
+        let path = std::env::temp_dir().join("test_memory_store_snapshot.bin");
+        let _ = std::fs::remove_file(&path);
+        let key = SnapshotKey::new([1; 32]);
+        let store = MemoryStore::new().with_snapshot(path.clone(), key.clone())?;
+        store.put(&PRODUCT_0.into()).await?;
+        store.put(&PRODUCT_1.into()).await?;
+        store.delete(PRODUCT_1.id).await?;
+
+        // WHEN the store is dropped and loaded again
+        drop(store);
+        let store = MemoryStore::new().with_snapshot(path.clone(), key)?;
+
+        // THEN the products are restored
+        assert_eq!(
+            store
+                .get(PRODUCT_0.id, ReadConsistency::Eventual, None)
+                .await?,
+            Some(PRODUCT_0.into())
+        );
+        // AND deleted products can still be restored
+        assert!(store.restore(PRODUCT_1.id).await?);
+
+        // AND the snapshot can't be loaded with another key
+        let res = MemoryStore::new().with_snapshot(path.clone(), SnapshotKey::new([2; 32]));
+        assert!(res.is_err());
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_json() -> Result<(), Error> {
+        // GIVEN a snapshot saved as JSON by an older version
+        let path = std::env::temp_dir().join("test_memory_store_snapshot_json.bin");
+        let key = SnapshotKey::new([1; 32]);
+        let catalog = Catalog {
+            products: vec![PRODUCT_0.into()],
+            deleted: Vec::new(),
+        };
+        snapshot::write(&path, &key, &serde_json::to_vec(&catalog).unwrap())?;
+
+        // WHEN loading the store
+        let store = MemoryStore::new().with_snapshot(path.clone(), key)?;
+
+        // THEN the products are restored
+        assert_eq!(store.catalog(), catalog);
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
 }
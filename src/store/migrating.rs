@@ -0,0 +1,381 @@
+//! # Migrating store
+//!
+//! Store wrapper used to move products from one backend to another without
+//! downtime. Writes go to both backends, and single product reads are served
+//! by the new backend, falling back to the old one for products that were
+//! not copied yet.
+//!
+//! The old backend stays the source of truth until the migration is done:
+//! a failed write to the old backend fails the request, while a failed write
+//! to the new backend is only counted as a divergence. For the same reason,
+//! listings and counts are served by the old backend, as the new one might
+//! only hold part of the products.
+
+use super::{
+    ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet, StoreCount, StoreDelete,
+    StoreExpire, StoreGet, StoreGetAll, StoreHealth, StorePut, StorePutConditional, StorePutMany,
+    StoreRestore, StoreScanAll, StoreUpdate,
+};
+use crate::{Error, Product, ProductPatch, ProductRange};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{instrument, warn};
+
+/// Counters of the differences between the two backends
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MigrationStats {
+    /// Reads served by the old backend, as the product was missing from the
+    /// new one
+    pub read_fallbacks: u64,
+    /// Writes that succeeded on the old backend but failed on the new one
+    pub write_failures: u64,
+}
+
+/// Store writing to two backends and reading from the new one.
+pub struct MigratingStore<O, N> {
+    old: O,
+    new: N,
+    read_fallbacks: AtomicU64,
+    write_failures: AtomicU64,
+}
+
+impl<O, N> MigratingStore<O, N> {
+    pub fn new(old: O, new: N) -> Self {
+        Self {
+            old,
+            new,
+            read_fallbacks: AtomicU64::new(0),
+            write_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Current divergence counters
+    pub fn stats(&self) -> MigrationStats {
+        MigrationStats {
+            read_fallbacks: self.read_fallbacks.load(Ordering::Relaxed),
+            write_failures: self.write_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Record the result of a write to the new backend
+    fn check_new_write<T>(&self, op: &str, res: Result<T, Error>) {
+        if let Err(err) = res {
+            warn!("Failed to {} in the new backend: {}", op, err);
+            self.write_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<O: Store, N: Store> Store for MigratingStore<O, N> {}
+
+#[async_trait]
+impl<O: StoreGetAll, N: Send + Sync> StoreGetAll for MigratingStore<O, N> {
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
+        self.old.all(next, limit, filter, fields).await
+    }
+}
+
+#[async_trait]
+impl<O: StoreScanAll, N: Send + Sync> StoreScanAll for MigratingStore<O, N> {
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error> {
+        self.old.scan_all(total_segments).await
+    }
+}
+
+#[async_trait]
+impl<O: StoreCount, N: Send + Sync> StoreCount for MigratingStore<O, N> {
+    async fn count(&self) -> Result<usize, Error> {
+        self.old.count().await
+    }
+}
+
+#[async_trait]
+impl<O: StoreGet, N: StoreGet> StoreGet for MigratingStore<O, N> {
+    #[instrument(skip(self))]
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        if let Some(product) = self.new.get(id, consistency, fields).await? {
+            return Ok(Some(product));
+        }
+
+        let product = self.old.get(id, consistency, fields).await?;
+        if product.is_some() {
+            warn!("Product {} is missing from the new backend", id);
+            self.read_fallbacks.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(product)
+    }
+}
+
+#[async_trait]
+impl<O: StoreBatchGet, N: StoreBatchGet> StoreBatchGet for MigratingStore<O, N> {
+    #[instrument(skip(self, ids))]
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        let mut products = self.new.get_many(ids).await?;
+        let missing: Vec<String> = ids
+            .iter()
+            .filter(|id| !products.iter().any(|p| &p.id == *id))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return Ok(products);
+        }
+
+        let fallbacks = self.old.get_many(&missing).await?;
+        if !fallbacks.is_empty() {
+            warn!(
+                "{} products are missing from the new backend",
+                fallbacks.len()
+            );
+            self.read_fallbacks
+                .fetch_add(fallbacks.len() as u64, Ordering::Relaxed);
+        }
+        products.extend(fallbacks);
+        Ok(products)
+    }
+}
+
+#[async_trait]
+impl<O: StorePut, N: StorePut> StorePut for MigratingStore<O, N> {
+    /// Put the product in both backends
+    ///
+    /// The previous product is taken from the old backend, which has all of
+    /// them.
+    #[instrument(skip(self))]
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        let previous = self.old.put(product).await?;
+        self.check_new_write("put product", self.new.put(product).await);
+        Ok(previous)
+    }
+}
+
+#[async_trait]
+impl<O: StorePutConditional, N: StorePut + StoreDelete> StorePutConditional
+    for MigratingStore<O, N>
+{
+    /// Create the product in the old backend, then copy it to the new one
+    ///
+    /// Only the old backend, which has all the products, decides whether
+    /// the product exists.
+    #[instrument(skip(self))]
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        if !self.old.put_if_absent(product).await? {
+            return Ok(false);
+        }
+        self.check_new_write("create product", self.new.put(product).await);
+        Ok(true)
+    }
+
+    /// Replace the product in the old backend, then copy it to the new one
+    ///
+    /// Only the old backend decides whether the product is at the expected
+    /// version, as with `put_if_absent`.
+    #[instrument(skip(self))]
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let previous = self.old.put_if_version(product, version).await?;
+        if previous.is_some() {
+            self.check_new_write("replace product", self.new.put(product).await);
+        }
+        Ok(previous)
+    }
+
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let product = self.old.update_if_version(id, patch, version).await?;
+        if let Some(product) = &product {
+            self.check_new_write("update product", self.new.put(product).await);
+        }
+        Ok(product)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        let deleted = self.old.delete_if_version(id, version).await?;
+        if deleted {
+            self.check_new_write("delete product", self.new.delete(id).await);
+        }
+        Ok(deleted)
+    }
+}
+
+#[async_trait]
+impl<O: StorePutMany, N: StorePutMany> StorePutMany for MigratingStore<O, N> {
+    #[instrument(skip(self, products))]
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        self.old.put_many(products).await?;
+        self.check_new_write("put products", self.new.put_many(products).await);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<O: StoreDelete, N: StoreDelete> StoreDelete for MigratingStore<O, N> {
+    /// Delete a product in both backends
+    ///
+    /// The result of the old backend is returned, as the product might not
+    /// have been copied to the new one.
+    #[instrument(skip(self))]
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let deleted = self.old.delete(id).await?;
+        self.check_new_write("delete product", self.new.delete(id).await);
+        Ok(deleted)
+    }
+
+    #[instrument(skip(self))]
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        let deleted = self.old.hard_delete(id).await?;
+        self.check_new_write("hard delete product", self.new.hard_delete(id).await);
+        Ok(deleted)
+    }
+}
+
+#[async_trait]
+impl<O: StoreUpdate, N: StorePut> StoreUpdate for MigratingStore<O, N> {
+    /// Update the product in the old backend, then copy the result
+    ///
+    /// The product might not have been copied to the new backend yet, so
+    /// the whole product is written there instead of the patch.
+    #[instrument(skip(self))]
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        let product = self.old.update(id, patch).await?;
+        if let Some(product) = &product {
+            self.check_new_write("update product", self.new.put(product).await);
+        }
+        Ok(product)
+    }
+}
+
+#[async_trait]
+impl<O: StoreRestore, N: StoreRestore> StoreRestore for MigratingStore<O, N> {
+    /// Restore a product in both backends
+    ///
+    /// The result of the old backend is returned, as the product might not
+    /// have been copied to the new one.
+    #[instrument(skip(self))]
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        let restored = self.old.restore(id).await?;
+        self.check_new_write("restore product", self.new.restore(id).await);
+        Ok(restored)
+    }
+}
+
+#[async_trait]
+impl<O: StoreExpire, N: StoreExpire> StoreExpire for MigratingStore<O, N> {
+    #[instrument(skip(self))]
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        self.old.set_expiry(id, at).await?;
+        self.check_new_write("set product expiry", self.new.set_expiry(id, at).await);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<O: StoreHealth, N: StoreHealth> StoreHealth for MigratingStore<O, N> {
+    async fn ping(&self) -> Result<(), Error> {
+        self.old.ping().await?;
+        self.new.ping().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{conformance, MemoryStore};
+
+    fn get_product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put() -> Result<(), Error> {
+        // GIVEN two empty backends
+        let store = MigratingStore::new(MemoryStore::new(), MemoryStore::new());
+
+        // WHEN putting a product
+        store.put(&get_product("1")).await?;
+
+        // THEN the product is written to both backends
+        assert!(store
+            .old
+            .get("1", ReadConsistency::Eventual, None)
+            .await?
+            .is_some());
+        assert!(store
+            .new
+            .get("1", ReadConsistency::Eventual, None)
+            .await?
+            .is_some());
+        assert_eq!(store.stats(), MigrationStats::default());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_pagination() -> Result<(), Error> {
+        let store = MigratingStore::new(MemoryStore::new(), MemoryStore::new());
+        conformance::check_pagination(&store, 50).await
+    }
+
+    #[tokio::test]
+    async fn test_get_fallback() -> Result<(), Error> {
+        // GIVEN a product only in the old backend
+        let old = MemoryStore::new();
+        old.put(&get_product("1")).await?;
+        let store = MigratingStore::new(old, MemoryStore::new());
+
+        // WHEN getting the product
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN it is read from the old backend
+        assert_eq!(product, Some(get_product("1")));
+        // AND the fallback is counted
+        assert_eq!(store.stats().read_fallbacks, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_many_fallback() -> Result<(), Error> {
+        // GIVEN a product in each backend
+        let old = MemoryStore::new();
+        old.put(&get_product("1")).await?;
+        let new = MemoryStore::new();
+        new.put(&get_product("2")).await?;
+        let store = MigratingStore::new(old, new);
+
+        // WHEN getting both products
+        let products = store.get_many(&["1".to_string(), "2".to_string()]).await?;
+
+        // THEN both are returned
+        assert_eq!(products.len(), 2);
+        // AND only the product from the old backend is counted
+        assert_eq!(store.stats().read_fallbacks, 1);
+
+        Ok(())
+    }
+}
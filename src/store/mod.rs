@@ -1,13 +1,81 @@
-use crate::{Error, Product, ProductRange};
+use crate::{Error, Product, ProductBatch, ProductPatch, ProductRange};
 use async_trait::async_trait;
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
 
+mod audit;
+mod buffered;
+mod catalog;
+mod changes;
+#[cfg(any(test, feature = "test-util"))]
+pub mod conformance;
 mod dynamodb;
+mod history;
 mod memory;
+mod migrating;
+mod popularity;
+mod prices;
+mod query;
+mod regional;
+mod search;
+mod snapshot;
+mod tiered;
+mod webhooks;
 
-pub use dynamodb::DynamoDBStore;
+pub use audit::{
+    AuditStore, DynamoDBAuditStore, MemoryAuditStore, StoreGetAudit, StoreRecordAudit,
+};
+pub use buffered::BufferedStore;
+pub use catalog::Catalog;
+pub use changes::{
+    ChangeStore, DynamoDBChangeStore, MemoryChangeStore, StoreAppendChanges, StoreGetChanges,
+};
+pub use dynamodb::{
+    parse_export_data, parse_export_manifest, DynamoDBPartiQLStore, DynamoDBStore, ItemAttribute,
+    ItemCodec, ItemDecoder, SerdeCodec,
+};
+pub use history::{
+    DynamoDBHistoryStore, HistoryStore, MemoryHistoryStore, StoreAppendHistory, StoreGetHistory,
+    StoreScanHistory,
+};
 pub use memory::MemoryStore;
+pub use migrating::{MigratingStore, MigrationStats};
+pub use popularity::{
+    DynamoDBPopularityStore, MemoryPopularityStore, PopularityStore, StoreGetHits, StoreGetPopular,
+    StorePutPopular, StoreRecordHit,
+};
+#[cfg(feature = "timestream")]
+pub use prices::TimestreamPriceHistory;
+pub use prices::{MemoryPriceHistory, PriceHistoryStore, StoreGetPriceHistory, StoreRecordPrices};
+pub use query::{Query, StoreFind};
+pub use regional::RegionalStore;
+#[cfg(feature = "http")]
+pub use search::OpenSearchIndex;
+pub use search::{MemoryIndex, SearchIndex, StoreIndex, StoreSearch};
+pub use snapshot::SnapshotKey;
+pub use tiered::TieredStore;
+pub use webhooks::{
+    DynamoDBWebhookStore, MemoryWebhookStore, StoreDeleteWebhook, StoreGetWebhook,
+    StoreListWebhooks, StorePutWebhook, WebhookStore,
+};
 
-pub trait Store: StoreGetAll + StoreGet + StorePut + StoreDelete {}
+pub trait Store:
+    StoreGetAll
+    + StoreScanAll
+    + StoreCount
+    + StoreGet
+    + StoreBatchGet
+    + StorePut
+    + StorePutConditional
+    + StorePutMany
+    + StoreUpdate
+    + StoreDelete
+    + StoreRestore
+    + StoreExpire
+    + StoreHealth
+{
+}
 
 /// Trait for retrieving all products
 ///
@@ -17,25 +85,311 @@ pub trait Store: StoreGetAll + StoreGet + StorePut + StoreDelete {}
 /// A given store could return only a partial list of all the products. If
 /// this is the case, the `next` parameter should be used to retrieve the
 /// next page of products.
+///
+/// The `limit` parameter is the maximum number of products to return in a
+/// single page. Stores may return fewer products than this, but a limit of
+/// zero is treated as one. `store::conformance::check_pagination` checks
+/// that paginating never loses or duplicates products.
+///
+/// Only products matching the `filter` are returned. If `fields` is set,
+/// only those fields are retrieved (see `project`).
 #[async_trait]
 pub trait StoreGetAll: Send + Sync {
-    async fn all(&self, next: Option<&str>) -> Result<ProductRange, Error>;
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error>;
+}
+
+/// Fields of a product that can be retrieved individually
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProductField {
+    Id,
+    Name,
+    Price,
+    Attributes,
+    Images,
+}
+
+impl ProductField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProductField::Id => "id",
+            ProductField::Name => "name",
+            ProductField::Price => "price",
+            ProductField::Attributes => "attributes",
+            ProductField::Images => "images",
+        }
+    }
+}
+
+impl FromStr for ProductField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(ProductField::Id),
+            "name" => Ok(ProductField::Name),
+            "price" => Ok(ProductField::Price),
+            "attributes" => Ok(ProductField::Attributes),
+            "images" => Ok(ProductField::Images),
+            _ => Err(Error::ClientError("unknown product field")),
+        }
+    }
+}
+
+/// Keep only some fields of a product
+///
+/// The id is always kept, while the other fields that are not in `fields`
+/// are reset to their default value. If `fields` is `None`, the product is
+/// returned unchanged.
+pub fn project(mut product: Product, fields: Option<&[ProductField]>) -> Product {
+    if let Some(fields) = fields {
+        if !fields.contains(&ProductField::Name) {
+            product.name = String::default();
+        }
+        if !fields.contains(&ProductField::Price) {
+            product.price = f64::default();
+        }
+        if !fields.contains(&ProductField::Attributes) {
+            product.attributes.clear();
+        }
+        if !fields.contains(&ProductField::Images) {
+            product.images.clear();
+        }
+    }
+    product
+}
+
+/// Criteria to filter products when listing them
+///
+/// Price bounds are inclusive, and the name match is case-sensitive.
+/// Attribute filters are pairs of keys and values that must all be equal to
+/// the attributes of the product. Values come from query strings, so they
+/// match string attributes with the same text, as well as number and boolean
+/// attributes with the same value once parsed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProductFilter {
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub name_contains: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl ProductFilter {
+    /// Whether a product matches the filter
+    pub fn matches(&self, product: &Product) -> bool {
+        self.min_price.map_or(true, |min| product.price >= min)
+            && self.max_price.map_or(true, |max| product.price <= max)
+            && self
+                .name_contains
+                .as_deref()
+                .map_or(true, |name| product.name.contains(name))
+            && self.attributes.iter().all(|(key, expected)| {
+                product
+                    .attributes
+                    .get(key)
+                    .map_or(false, |value| attribute_matches(value, expected))
+            })
+    }
+}
+
+/// Whether an attribute value matches a value from a query string
+fn attribute_matches(value: &Value, expected: &str) -> bool {
+    match value {
+        Value::String(s) => s == expected,
+        Value::Number(n) => n.as_f64().is_some() && expected.parse::<f64>().ok() == n.as_f64(),
+        Value::Bool(b) => expected.parse::<bool>() == Ok(*b),
+        _ => false,
+    }
+}
+
+/// Trait for retrieving every product at once
+///
+/// Unlike `StoreGetAll`, this returns all products in the store without
+/// pagination, which is useful for bulk export use cases. Stores can split
+/// the work into `total_segments` parallel scans.
+#[async_trait]
+pub trait StoreScanAll: Send + Sync {
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error>;
+}
+
+/// Trait for counting products
+///
+/// Soft-deleted products are not counted.
+#[async_trait]
+pub trait StoreCount: Send + Sync {
+    async fn count(&self) -> Result<usize, Error>;
+}
+
+/// Consistency level for reads
+///
+/// Eventually consistent reads might not reflect the result of a recently
+/// completed write, while strongly consistent reads always do.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReadConsistency {
+    #[default]
+    Eventual,
+    Strong,
 }
 
 /// Trait for retrieving a single product
+///
+/// If `fields` is set, only those fields are retrieved (see `project`).
 #[async_trait]
 pub trait StoreGet: Send + Sync {
-    async fn get(&self, id: &str) -> Result<Option<Product>, Error>;
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error>;
+}
+
+/// Trait for retrieving a single product with the time of its last write
+///
+/// The time is in microseconds since the UNIX epoch. It is `None` if the
+/// store didn't record it, e.g. for products written before it did.
+#[async_trait]
+pub trait StoreGetWriteTime: Send + Sync {
+    async fn get_with_write_time(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<(Product, Option<u64>)>, Error>;
+}
+
+/// Trait for retrieving multiple products by id
+///
+/// Products that don't exist or are deleted are skipped, and the products
+/// can be returned in any order.
+#[async_trait]
+pub trait StoreBatchGet: Send + Sync {
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error>;
+
+    /// Retrieve products within a latency budget
+    ///
+    /// If the budget is exceeded, the products retrieved so far are returned
+    /// and the batch is flagged as partial. By default, nothing is returned
+    /// when the budget is exceeded.
+    async fn get_many_within(
+        &self,
+        ids: &[String],
+        budget: Duration,
+    ) -> Result<ProductBatch, Error> {
+        match tokio::time::timeout(budget, self.get_many(ids)).await {
+            Ok(products) => Ok(ProductBatch {
+                products: products?,
+                partial: false,
+            }),
+            Err(_) => Ok(ProductBatch {
+                products: Vec::new(),
+                partial: true,
+            }),
+        }
+    }
 }
 
 /// Trait for storing a single product
+///
+/// Returns the product that was replaced, or `None` if there was no product
+/// with that id. Soft-deleted products are not returned, as putting a
+/// product over them creates it again.
 #[async_trait]
 pub trait StorePut: Send + Sync {
-    async fn put(&self, product: &Product) -> Result<(), Error>;
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error>;
+}
+
+/// Trait for writing a product only if it is in an expected state
+///
+/// `put_if_absent` returns `false` without writing anything if there is
+/// already a product with that id. Soft-deleted products don't count, as
+/// with `StorePut`.
+///
+/// The `_if_version` methods only replace, update or soft delete a product
+/// if its current `Product::version` is the given one. They return `None`
+/// or `false` without writing anything otherwise, including when there is
+/// no product with that id. `put_if_version` returns the replaced product,
+/// and `update_if_version` the updated one.
+///
+/// Backends check and write atomically where they can.
+#[async_trait]
+pub trait StorePutConditional: Send + Sync {
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error>;
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error>;
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error>;
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error>;
+}
+
+/// Trait for storing several products at once
+///
+/// Implementations use batch APIs where the backend provides them. Products
+/// are not written atomically: if an error is returned, some of them might
+/// have been stored. If several products share an id, the last one wins.
+#[async_trait]
+pub trait StorePutMany: Send + Sync {
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error>;
+}
+
+/// Trait for partially updating a single product
+///
+/// Returns the updated product, or `None` if there was no product with that
+/// id. Soft-deleted products are not updated.
+#[async_trait]
+pub trait StoreUpdate: Send + Sync {
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error>;
 }
 
 /// Trait for deleting a single product
+///
+/// `delete` performs a soft delete: the product is marked as deleted and
+/// hidden from reads, but it can still be restored with `StoreRestore`.
+/// `hard_delete` removes the product permanently.
+///
+/// Both return `false` if there was nothing to delete: `delete` if there was
+/// no product with that id or it was already soft-deleted, and `hard_delete`
+/// if there was no product at all, soft-deleted or not.
 #[async_trait]
 pub trait StoreDelete: Send + Sync {
-    async fn delete(&self, id: &str) -> Result<(), Error>;
+    async fn delete(&self, id: &str) -> Result<bool, Error>;
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error>;
+}
+
+/// Trait for restoring a soft-deleted product
+///
+/// Returns `false` if there was no soft-deleted product with that id.
+#[async_trait]
+pub trait StoreRestore: Send + Sync {
+    async fn restore(&self, id: &str) -> Result<bool, Error>;
+}
+
+/// Trait for scheduling the permanent removal of a product
+///
+/// `at` is a time in seconds since the UNIX epoch. Once it has passed, the
+/// product is removed by the storage backend, e.g. through DynamoDB TTL.
+/// Setting the expiry of a missing product does nothing.
+#[async_trait]
+pub trait StoreExpire: Send + Sync {
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error>;
+}
+
+/// Trait for checking that the store is reachable
+///
+/// This is used by readiness probes and startup checks.
+#[async_trait]
+pub trait StoreHealth: Send + Sync {
+    async fn ping(&self) -> Result<(), Error>;
 }
@@ -0,0 +1,266 @@
+//! # DynamoDB popularity store implementation
+//!
+//! Popularity store implementation using the AWS SDK for DynamoDB.
+//!
+//! Hits of a product are spread across several counter items, keyed as
+//! `{id}#{shard}`, which are incremented with atomic `ADD` updates. This
+//! keeps a popular product from throttling a single partition. The
+//! materialized list of popular products is stored in a single item under
+//! the `top` key, which can't clash with counter keys.
+
+use super::{PopularityStore, StoreGetHits, StoreGetPopular, StorePutPopular, StoreRecordHit};
+use crate::{store::dynamodb::ext::AttributeValuesExt, Error, ProductHits};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Default number of counters per product
+const DEFAULT_SHARDS: u32 = 10;
+
+/// Key of the materialized list of popular products
+const TOP_KEY: &str = "top";
+
+/// DynamoDB popularity store implementation.
+pub struct DynamoDBPopularityStore {
+    client: Client,
+    table_name: String,
+    shards: u32,
+}
+
+impl DynamoDBPopularityStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBPopularityStore {
+        DynamoDBPopularityStore {
+            client,
+            table_name,
+            shards: DEFAULT_SHARDS,
+        }
+    }
+
+    /// Set the number of counters per product
+    pub fn with_shards(mut self, shards: u32) -> DynamoDBPopularityStore {
+        self.shards = shards.max(1);
+        self
+    }
+
+    /// Pick a counter for the next hit
+    ///
+    /// Function instances don't share any state, so the shard is derived
+    /// from the clock rather than a counter, which would start at zero in
+    /// every instance.
+    fn shard(&self) -> u32 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default()
+            % self.shards
+    }
+}
+
+impl PopularityStore for DynamoDBPopularityStore {}
+
+#[async_trait]
+impl StoreRecordHit for DynamoDBPopularityStore {
+    /// Increment one of the counters of a product
+    #[instrument(skip(self))]
+    async fn record_hit(&self, id: &str) -> Result<(), Error> {
+        let shard = self.shard();
+        info!("Recording hit on shard {} of product '{}'", shard, id);
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(format!("{}#{}", id, shard)))
+            .update_expression("SET product_id = :product_id ADD hits :one")
+            .expression_attribute_values(":product_id", AttributeValue::S(id.to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetHits for DynamoDBPopularityStore {
+    /// Scan all counters and add them up per product
+    #[instrument(skip(self))]
+    async fn hits(&self) -> Result<Vec<ProductHits>, Error> {
+        info!("Scanning hit counters from DynamoDB table");
+        let mut hits: HashMap<String, u64> = HashMap::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("attribute_exists(product_id)")
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            for item in res.items.unwrap_or_default() {
+                let id = item
+                    .get_s("product_id")
+                    .ok_or(Error::InternalError("Missing product_id"))?;
+                *hits.entry(id).or_default() += item.get_n("hits").unwrap_or_default() as u64;
+            }
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(hits
+            .into_iter()
+            .map(|(id, hits)| ProductHits { id, hits })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StoreGetPopular for DynamoDBPopularityStore {
+    /// Get the materialized list of popular products
+    #[instrument(skip(self))]
+    async fn popular(&self) -> Result<Vec<ProductHits>, Error> {
+        info!("Getting popular products from DynamoDB table");
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(TOP_KEY.to_string()))
+            .send()
+            .await?;
+
+        let products = match res.item.and_then(|mut item| item.remove("products")) {
+            Some(AttributeValue::L(products)) => products,
+            _ => return Ok(Vec::new()),
+        };
+        products
+            .into_iter()
+            .map(|product| match product {
+                AttributeValue::M(item) => Ok(ProductHits {
+                    id: item.get_s("id").ok_or(Error::InternalError("Missing id"))?,
+                    hits: item
+                        .get_n("hits")
+                        .ok_or(Error::InternalError("Missing hits"))?
+                        as u64,
+                }),
+                _ => Err(Error::InternalError("Invalid popular product")),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl StorePutPopular for DynamoDBPopularityStore {
+    /// Replace the materialized list of popular products
+    #[instrument(skip(self, popular))]
+    async fn put_popular(&self, popular: &[ProductHits]) -> Result<(), Error> {
+        info!(
+            "Putting {} popular products into DynamoDB table",
+            popular.len()
+        );
+        let products = popular
+            .iter()
+            .map(|product| {
+                AttributeValue::M(HashMap::from([
+                    ("id".to_owned(), AttributeValue::S(product.id.clone())),
+                    (
+                        "hits".to_owned(),
+                        AttributeValue::N(product.hits.to_string()),
+                    ),
+                ]))
+            })
+            .collect();
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(TOP_KEY.to_string()))
+            .item("products", AttributeValue::L(products))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_hits() -> Result<(), Error> {
+        // GIVEN a DynamoDBPopularityStore with two counters for a product
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","FilterExpression":"attribute_exists(product_id)"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1#0"}, "product_id": {"S": "1"}, "hits": {"N": "2"}}, {"id": {"S": "1#3"}, "product_id": {"S": "1"}, "hits": {"N": "5"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBPopularityStore::new(client, "test".to_string());
+
+        // WHEN getting the hits
+        let hits = store.hits().await?;
+
+        // THEN the counters are added up
+        assert_eq!(
+            hits,
+            vec![ProductHits {
+                id: "1".to_string(),
+                hits: 7
+            }]
+        );
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_popular() -> Result<(), Error> {
+        // GIVEN a DynamoDBPopularityStore with materialized popular products
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"top"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "top"}, "products": {"L": [{"M": {"id": {"S": "2"}, "hits": {"N": "10"}}}, {"M": {"id": {"S": "1"}, "hits": {"N": "3"}}}]}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBPopularityStore::new(client, "test".to_string());
+
+        // WHEN getting the popular products
+        let popular = store.popular().await?;
+
+        // THEN they are returned in order
+        assert_eq!(popular.len(), 2);
+        assert_eq!(popular[0].id, "2");
+        assert_eq!(popular[0].hits, 10);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+}
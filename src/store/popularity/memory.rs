@@ -0,0 +1,95 @@
+//! # In-memory popularity store implementation
+//!
+//! This is a simple in-memory popularity store implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::{PopularityStore, StoreGetHits, StoreGetPopular, StorePutPopular, StoreRecordHit};
+use crate::{Error, ProductHits};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryPopularityStore {
+    hits: RwLock<HashMap<String, u64>>,
+    popular: RwLock<Vec<ProductHits>>,
+}
+
+impl MemoryPopularityStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl PopularityStore for MemoryPopularityStore {}
+
+#[async_trait]
+impl StoreRecordHit for MemoryPopularityStore {
+    async fn record_hit(&self, id: &str) -> Result<(), Error> {
+        *self
+            .hits
+            .write()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default() += 1;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetHits for MemoryPopularityStore {
+    async fn hits(&self) -> Result<Vec<ProductHits>, Error> {
+        Ok(self
+            .hits
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, hits)| ProductHits {
+                id: id.clone(),
+                hits: *hits,
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StoreGetPopular for MemoryPopularityStore {
+    async fn popular(&self) -> Result<Vec<ProductHits>, Error> {
+        Ok(self.popular.read().unwrap().clone())
+    }
+}
+
+#[async_trait]
+impl StorePutPopular for MemoryPopularityStore {
+    async fn put_popular(&self, popular: &[ProductHits]) -> Result<(), Error> {
+        *self.popular.write().unwrap() = popular.to_vec();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_hit() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryPopularityStore::new();
+
+        // WHEN recording two hits on a product
+        store.record_hit("1").await?;
+        store.record_hit("1").await?;
+
+        // THEN both hits are counted
+        assert_eq!(
+            store.hits().await?,
+            vec![ProductHits {
+                id: "1".to_string(),
+                hits: 2
+            }]
+        );
+
+        Ok(())
+    }
+}
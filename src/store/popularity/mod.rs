@@ -0,0 +1,50 @@
+//! # Product popularity storage
+//!
+//! Reads of a product are counted as hits. Counting every read would turn
+//! popular products into hot keys, so stores are expected to spread the hits
+//! of a product across several counters and add them up when needed.
+//!
+//! Adding up all counters is too expensive to do on every request, so the
+//! most popular products are periodically materialized into a single list.
+
+use crate::{Error, ProductHits};
+use async_trait::async_trait;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBPopularityStore;
+pub use memory::MemoryPopularityStore;
+
+pub trait PopularityStore:
+    StoreRecordHit + StoreGetHits + StoreGetPopular + StorePutPopular
+{
+}
+
+/// Trait for counting a hit on a product
+#[async_trait]
+pub trait StoreRecordHit: Send + Sync {
+    async fn record_hit(&self, id: &str) -> Result<(), Error>;
+}
+
+/// Trait for retrieving the number of hits of all products
+///
+/// Products are returned in no particular order.
+#[async_trait]
+pub trait StoreGetHits: Send + Sync {
+    async fn hits(&self) -> Result<Vec<ProductHits>, Error>;
+}
+
+/// Trait for retrieving the materialized list of popular products
+///
+/// Products are returned from the most to the least popular.
+#[async_trait]
+pub trait StoreGetPopular: Send + Sync {
+    async fn popular(&self) -> Result<Vec<ProductHits>, Error>;
+}
+
+/// Trait for replacing the materialized list of popular products
+#[async_trait]
+pub trait StorePutPopular: Send + Sync {
+    async fn put_popular(&self, popular: &[ProductHits]) -> Result<(), Error>;
+}
@@ -0,0 +1,120 @@
+//! # In-memory price history implementation
+//!
+//! This is a simple in-memory price history implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::{PriceHistoryStore, StoreGetPriceHistory, StoreRecordPrices};
+use crate::{Error, PricePoint};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryPriceHistory {
+    data: RwLock<HashMap<String, Vec<PricePoint>>>,
+}
+
+impl MemoryPriceHistory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl PriceHistoryStore for MemoryPriceHistory {}
+
+#[async_trait]
+impl StoreRecordPrices for MemoryPriceHistory {
+    async fn record_prices(&self, prices: &[(String, PricePoint)]) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        for (id, point) in prices {
+            data.entry(id.clone()).or_default().push(point.clone());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetPriceHistory for MemoryPriceHistory {
+    async fn price_history(&self, id: &str, range: Range<u64>) -> Result<Vec<PricePoint>, Error> {
+        let mut points: Vec<PricePoint> = self
+            .data
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|points| {
+                points
+                    .iter()
+                    .filter(|p| range.contains(&p.time))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        points.sort_by_key(|p| p.time);
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_price_history() -> Result<(), Error> {
+        // GIVEN a store with price points for two products
+        let store = MemoryPriceHistory::new();
+        store
+            .record_prices(&[
+                (
+                    "1".to_string(),
+                    PricePoint {
+                        time: 300,
+                        price: 12.0,
+                    },
+                ),
+                (
+                    "1".to_string(),
+                    PricePoint {
+                        time: 100,
+                        price: 10.0,
+                    },
+                ),
+                (
+                    "2".to_string(),
+                    PricePoint {
+                        time: 200,
+                        price: 5.0,
+                    },
+                ),
+                (
+                    "1".to_string(),
+                    PricePoint {
+                        time: 500,
+                        price: 15.0,
+                    },
+                ),
+            ])
+            .await?;
+
+        // WHEN retrieving the history of a product in a range
+        let points = store.price_history("1", 100..500).await?;
+
+        // THEN the points in the range are returned, oldest first
+        assert_eq!(
+            points,
+            vec![
+                PricePoint {
+                    time: 100,
+                    price: 10.0
+                },
+                PricePoint {
+                    time: 300,
+                    price: 12.0
+                },
+            ]
+        );
+
+        Ok(())
+    }
+}
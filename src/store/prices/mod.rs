@@ -0,0 +1,36 @@
+//! # Price history storage
+//!
+//! Every price change is recorded as a point in time, so that the evolution
+//! of the price of a product can be charted. Unlike the product history,
+//! this only keeps the price and is queried by time range.
+
+use crate::{Error, PricePoint};
+use async_trait::async_trait;
+use std::ops::Range;
+
+mod memory;
+#[cfg(feature = "timestream")]
+mod timestream;
+
+pub use memory::MemoryPriceHistory;
+#[cfg(feature = "timestream")]
+pub use timestream::TimestreamPriceHistory;
+
+pub trait PriceHistoryStore: StoreRecordPrices + StoreGetPriceHistory {}
+
+/// Trait for recording price changes
+///
+/// `prices` are pairs of product ids and price points.
+#[async_trait]
+pub trait StoreRecordPrices: Send + Sync {
+    async fn record_prices(&self, prices: &[(String, PricePoint)]) -> Result<(), Error>;
+}
+
+/// Trait for retrieving the price history of a product
+///
+/// This returns the price points with a time in `range`, in milliseconds
+/// since the Unix epoch, from the oldest to the newest.
+#[async_trait]
+pub trait StoreGetPriceHistory: Send + Sync {
+    async fn price_history(&self, id: &str, range: Range<u64>) -> Result<Vec<PricePoint>, Error>;
+}
@@ -0,0 +1,263 @@
+//! # Timestream price history implementation
+//!
+//! Price history implementation using Amazon Timestream.
+//!
+//! Each price point is a `price` measure of type `DOUBLE`, with the product
+//! id as the `id` dimension. Timestream clients must use the endpoints
+//! returned by `DescribeEndpoints`, see `utils::get_price_history_store`.
+
+use super::{PriceHistoryStore, StoreGetPriceHistory, StoreRecordPrices};
+use crate::{Error, PricePoint};
+use async_trait::async_trait;
+use aws_sdk_timestreamwrite::model::{Dimension, MeasureValueType, Record, TimeUnit};
+use std::ops::Range;
+use tracing::{info, instrument};
+
+/// Maximum number of records in a WriteRecords request
+const WRITE_BATCH_SIZE: usize = 100;
+
+/// Name of the measure holding the price
+const MEASURE_NAME: &str = "price";
+
+pub struct TimestreamPriceHistory {
+    write_client: aws_sdk_timestreamwrite::Client,
+    query_client: aws_sdk_timestreamquery::Client,
+    database_name: String,
+    table_name: String,
+}
+
+impl TimestreamPriceHistory {
+    pub fn new(
+        write_client: aws_sdk_timestreamwrite::Client,
+        query_client: aws_sdk_timestreamquery::Client,
+        database_name: String,
+        table_name: String,
+    ) -> Self {
+        Self {
+            write_client,
+            query_client,
+            database_name,
+            table_name,
+        }
+    }
+}
+
+impl PriceHistoryStore for TimestreamPriceHistory {}
+
+#[async_trait]
+impl StoreRecordPrices for TimestreamPriceHistory {
+    /// Write price points in batches of 100 records
+    #[instrument(skip(self, prices))]
+    async fn record_prices(&self, prices: &[(String, PricePoint)]) -> Result<(), Error> {
+        info!("Writing {} price points to Timestream", prices.len());
+        for chunk in prices.chunks(WRITE_BATCH_SIZE) {
+            let records = chunk
+                .iter()
+                .map(|(id, point)| {
+                    Record::builder()
+                        .dimensions(Dimension::builder().name("id").value(id).build())
+                        .measure_name(MEASURE_NAME)
+                        .measure_value(point.price.to_string())
+                        .measure_value_type(MeasureValueType::Double)
+                        .time(point.time.to_string())
+                        .time_unit(TimeUnit::Milliseconds)
+                        .build()
+                })
+                .collect();
+
+            self.write_client
+                .write_records()
+                .database_name(&self.database_name)
+                .table_name(&self.table_name)
+                .set_records(Some(records))
+                .send()
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreGetPriceHistory for TimestreamPriceHistory {
+    /// Query the price points of a product, oldest first
+    #[instrument(skip(self))]
+    async fn price_history(&self, id: &str, range: Range<u64>) -> Result<Vec<PricePoint>, Error> {
+        info!("Querying price history of product '{}' from Timestream", id);
+        let query = format!(
+            "SELECT to_milliseconds(time), measure_value::double FROM \"{}\".\"{}\" \
+             WHERE id = '{}' AND measure_name = '{}' \
+             AND time >= from_milliseconds({}) AND time < from_milliseconds({}) \
+             ORDER BY time ASC",
+            self.database_name,
+            self.table_name,
+            // Escape quotes, as Timestream queries don't support parameters
+            id.replace('\'', "''"),
+            MEASURE_NAME,
+            range.start,
+            range.end,
+        );
+
+        let mut points = Vec::new();
+        let mut next_token = None;
+        loop {
+            let res = self
+                .query_client
+                .query()
+                .query_string(&query)
+                .set_next_token(next_token)
+                .send()
+                .await?;
+
+            for row in res.rows.unwrap_or_default() {
+                let data = row.data.unwrap_or_default();
+                let (time, price) = match data.as_slice() {
+                    [time, price] => (time, price),
+                    _ => return Err(Error::InternalError("Unexpected number of columns")),
+                };
+                points.push(PricePoint {
+                    time: time
+                        .scalar_value
+                        .as_deref()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or(Error::InternalError("Missing time"))?,
+                    price: price
+                        .scalar_value
+                        .as_deref()
+                        .ok_or(Error::InternalError("Missing price"))?
+                        .parse()?,
+                });
+            }
+
+            next_token = res.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{json_request_builder, mock_sdk_config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    fn get_store(
+        config: &aws_types::config::Config,
+        write_conn: TestConnection<SdkBody>,
+        query_conn: TestConnection<SdkBody>,
+    ) -> TimestreamPriceHistory {
+        TimestreamPriceHistory::new(
+            aws_sdk_timestreamwrite::Client::from_conf_conn(
+                aws_sdk_timestreamwrite::Config::new(config),
+                DynConnector::new(write_conn),
+            ),
+            aws_sdk_timestreamquery::Client::from_conf_conn(
+                aws_sdk_timestreamquery::Config::new(config),
+                DynConnector::new(query_conn),
+            ),
+            "products".to_string(),
+            "prices".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_record_prices() -> Result<(), Error> {
+        // GIVEN a Timestream price history
+        let config = mock_sdk_config().await;
+        let write_conn = TestConnection::new(vec![(
+            json_request_builder("1.0", "https://ingest.timestream.eu-west-1.amazonaws.com/")
+                .header("x-amz-target", "Timestream_20181101.WriteRecords")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let store = get_store(&config, write_conn.clone(), TestConnection::new(vec![]));
+
+        // WHEN recording a price point
+        store
+            .record_prices(&[(
+                "1".to_string(),
+                PricePoint {
+                    time: 1646906400000,
+                    price: 12.5,
+                },
+            )])
+            .await?;
+
+        // THEN a single write request is sent with the price as a double
+        let requests = write_conn.requests();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(
+            body["Records"][0],
+            serde_json::json!({
+                "Dimensions": [{"Name": "id", "Value": "1"}],
+                "MeasureName": "price",
+                "MeasureValue": "12.5",
+                "MeasureValueType": "DOUBLE",
+                "Time": "1646906400000",
+                "TimeUnit": "MILLISECONDS"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_price_history() -> Result<(), Error> {
+        // GIVEN a Timestream price history with two price points
+        let config = mock_sdk_config().await;
+        let query_conn = TestConnection::new(vec![(
+            json_request_builder("1.0", "https://query.timestream.eu-west-1.amazonaws.com/")
+                .header("x-amz-target", "Timestream_20181101.Query")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"QueryId": "q", "ColumnInfo": [], "Rows": [
+                        {"Data": [{"ScalarValue": "1646906400000"}, {"ScalarValue": "10.0"}]},
+                        {"Data": [{"ScalarValue": "1646992800000"}, {"ScalarValue": "12.5"}]}
+                    ]}"#,
+                ))
+                .unwrap(),
+        )]);
+        let store = get_store(&config, TestConnection::new(vec![]), query_conn.clone());
+
+        // WHEN querying the price history
+        let points = store
+            .price_history("1", 1646906400000..1647000000000)
+            .await?;
+
+        // THEN the price points are returned
+        assert_eq!(
+            points,
+            vec![
+                PricePoint {
+                    time: 1646906400000,
+                    price: 10.0
+                },
+                PricePoint {
+                    time: 1646992800000,
+                    price: 12.5
+                },
+            ]
+        );
+        // AND the query filters on the product and time range
+        let requests = query_conn.requests();
+        let body = std::str::from_utf8(requests[0].actual.body().bytes().unwrap()).unwrap();
+        assert!(body.contains("WHERE id = '1'"));
+        assert!(body.contains("time < from_milliseconds(1647000000000)"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! # Product queries
+//!
+//! `Query` gathers the criteria of a product search in a single value, so
+//! that new criteria don't change the signature of every layer:
+//!
+//! ```
+//! use products::store::Query;
+//!
+//! let query = Query::new()
+//!     .category("shoes")
+//!     .price_between(10.0, 50.0)
+//!     .limit(50);
+//! ```
+//!
+//! Stores answer queries through `StoreFind`. Every store implementing
+//! `StoreGetAll` gets it, and translates the filter the same way as for
+//! listings: to a filter expression for DynamoDB, to a `WHERE` clause for
+//! PartiQL, and to `ProductFilter::matches` in memory.
+
+use super::{ProductFilter, StoreGetAll};
+use crate::{Error, ProductRange};
+use async_trait::async_trait;
+
+/// Number of products returned when no limit is set
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Search criteria for products
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    pub filter: ProductFilter,
+    /// Maximum number of products to return
+    pub limit: usize,
+    /// Token returned with the previous page
+    pub next: Option<String>,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            filter: ProductFilter::default(),
+            limit: DEFAULT_LIMIT,
+            next: None,
+        }
+    }
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Only match products with this `category` attribute
+    pub fn category(self, category: &str) -> Self {
+        self.attribute("category", category)
+    }
+
+    /// Only match products where the attribute `key` has this value
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        self.filter
+            .attributes
+            .push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Only match products priced between `min` and `max`, inclusive
+    pub fn price_between(mut self, min: f64, max: f64) -> Self {
+        self.filter.min_price = Some(min);
+        self.filter.max_price = Some(max);
+        self
+    }
+
+    /// Only match products whose name contains `name`
+    pub fn name_contains(mut self, name: &str) -> Self {
+        self.filter.name_contains = Some(name.to_string());
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Continue from the page that returned this `next` token
+    pub fn after(mut self, next: &str) -> Self {
+        self.next = Some(next.to_string());
+        self
+    }
+}
+
+/// Trait for searching products
+#[async_trait]
+pub trait StoreFind: Send + Sync {
+    async fn find(&self, query: &Query) -> Result<ProductRange, Error>;
+}
+
+#[async_trait]
+impl<T: StoreGetAll> StoreFind for T {
+    async fn find(&self, query: &Query) -> Result<ProductRange, Error> {
+        self.all(query.next.as_deref(), query.limit, &query.filter, None)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::MemoryStore, store::StorePut, Product};
+    use serde_json::json;
+
+    fn get_product(id: &str, category: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: [("category".to_string(), json!(category))]
+                .into_iter()
+                .collect(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find() -> Result<(), Error> {
+        // GIVEN a store with products in two categories
+        let store = MemoryStore::new();
+        store.put(&get_product("1", "shoes", 10.0)).await?;
+        store.put(&get_product("2", "shoes", 60.0)).await?;
+        store.put(&get_product("3", "hats", 20.0)).await?;
+
+        // WHEN finding cheap shoes
+        let res = store
+            .find(&Query::new().category("shoes").price_between(0.0, 50.0))
+            .await?;
+
+        // THEN only the matching product is returned
+        assert_eq!(res.products, vec![get_product("1", "shoes", 10.0)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+//! # Regional store
+//!
+//! Store wrapper for DynamoDB global tables, where functions run in several
+//! regions but only one region takes writes. Single product reads go to the
+//! replica in the function's own region, which avoids a cross-region round
+//! trip, while writes and strongly consistent reads go to the writer region.
+//!
+//! Replicas catch up within about a second, so a product written through
+//! this store is read from the writer region until `max_lag` has passed
+//! since its last write. Writes from other processes are detected with the
+//! write time the local replica holds: a product whose local copy was
+//! written less than `max_lag` ago is being changed, and could have newer
+//! writes on the way, so it is read from the writer region as well. So are
+//! products missing from the local replica, which could have just been
+//! created. A write to a product that wasn't written recently is only seen
+//! in the local region once it has been replicated.
+//!
+//! Without a writer store, every request goes to the local store.
+
+use super::{
+    ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet, StoreCount, StoreDelete,
+    StoreExpire, StoreGet, StoreGetAll, StoreGetWriteTime, StoreHealth, StorePut,
+    StorePutConditional, StorePutMany, StoreRestore, StoreScanAll, StoreUpdate,
+};
+use crate::{Error, Product, ProductBatch, ProductPatch, ProductRange};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument};
+
+/// Default time after which a write is assumed to be replicated
+const DEFAULT_MAX_LAG: Duration = Duration::from_secs(2);
+
+/// Store routing reads to the local replica and writes to the writer region.
+pub struct RegionalStore<S> {
+    local: S,
+    writer: Option<S>,
+    /// Time of the last write of recently written products
+    updated_at: Mutex<HashMap<String, Instant>>,
+    max_lag: Duration,
+}
+
+impl<S> RegionalStore<S> {
+    pub fn new(local: S) -> Self {
+        Self {
+            local,
+            writer: None,
+            updated_at: Default::default(),
+            max_lag: DEFAULT_MAX_LAG,
+        }
+    }
+
+    /// Set the store of the writer region
+    pub fn with_writer(mut self, writer: S) -> Self {
+        self.writer = Some(writer);
+        self
+    }
+
+    /// Set the time after which a write is assumed to be replicated
+    pub fn with_max_lag(mut self, max_lag: Duration) -> Self {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// Store taking writes
+    fn writer(&self) -> &S {
+        self.writer.as_ref().unwrap_or(&self.local)
+    }
+
+    /// Record a write, and forget writes that have been replicated
+    fn written(&self, id: &str) {
+        if self.writer.is_none() {
+            return;
+        }
+        let mut updated_at = self.updated_at.lock().unwrap();
+        let max_lag = self.max_lag;
+        updated_at.retain(|_, at| at.elapsed() < max_lag);
+        updated_at.insert(id.to_string(), Instant::now());
+    }
+
+    /// Whether the local replica could be missing the last write of a product
+    fn may_lag(&self, id: &str) -> bool {
+        self.updated_at
+            .lock()
+            .unwrap()
+            .get(id)
+            .map_or(false, |at| at.elapsed() < self.max_lag)
+    }
+
+    /// Whether a write at the given time, in microseconds since the UNIX
+    /// epoch, could be followed by writes not yet replicated
+    fn is_recent(&self, written_at: u64) -> Result<bool, Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InternalError("System time is before the UNIX epoch"))?
+            .as_micros() as u64;
+        Ok(now.saturating_sub(written_at) < self.max_lag.as_micros() as u64)
+    }
+}
+
+impl<S: Store + StoreGetWriteTime> Store for RegionalStore<S> {}
+
+#[async_trait]
+impl<S: StoreGet + StoreGetWriteTime> StoreGet for RegionalStore<S> {
+    #[instrument(skip(self))]
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return self.local.get(id, consistency, fields).await,
+        };
+        if consistency == ReadConsistency::Strong || self.may_lag(id) {
+            info!("Reading product {} from the writer region", id);
+            return writer.get(id, consistency, fields).await;
+        }
+
+        match self
+            .local
+            .get_with_write_time(id, consistency, fields)
+            .await?
+        {
+            Some((_, Some(written_at))) if self.is_recent(written_at)? => {
+                info!(
+                    "Reading recently written product {} from the writer region",
+                    id
+                );
+                writer.get(id, consistency, fields).await
+            }
+            Some((product, _)) => Ok(Some(product)),
+            None => {
+                info!("Reading missing product {} from the writer region", id);
+                writer.get(id, consistency, fields).await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: StoreGetAll> StoreGetAll for RegionalStore<S> {
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
+        self.local.all(next, limit, filter, fields).await
+    }
+}
+
+#[async_trait]
+impl<S: StoreScanAll> StoreScanAll for RegionalStore<S> {
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error> {
+        self.local.scan_all(total_segments).await
+    }
+}
+
+#[async_trait]
+impl<S: StoreCount> StoreCount for RegionalStore<S> {
+    async fn count(&self) -> Result<usize, Error> {
+        self.local.count().await
+    }
+}
+
+#[async_trait]
+impl<S: StoreBatchGet> StoreBatchGet for RegionalStore<S> {
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        self.local.get_many(ids).await
+    }
+
+    async fn get_many_within(
+        &self,
+        ids: &[String],
+        budget: Duration,
+    ) -> Result<ProductBatch, Error> {
+        self.local.get_many_within(ids, budget).await
+    }
+}
+
+#[async_trait]
+impl<S: StorePut> StorePut for RegionalStore<S> {
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        let res = self.writer().put(product).await;
+        self.written(&product.id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StorePutConditional> StorePutConditional for RegionalStore<S> {
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        let res = self.writer().put_if_absent(product).await;
+        self.written(&product.id);
+        res
+    }
+
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let res = self.writer().put_if_version(product, version).await;
+        self.written(&product.id);
+        res
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let res = self.writer().update_if_version(id, patch, version).await;
+        self.written(id);
+        res
+    }
+
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        let res = self.writer().delete_if_version(id, version).await;
+        self.written(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany> StorePutMany for RegionalStore<S> {
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        let res = self.writer().put_many(products).await;
+        for product in products {
+            self.written(&product.id);
+        }
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreUpdate> StoreUpdate for RegionalStore<S> {
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        let res = self.writer().update(id, patch).await;
+        self.written(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreDelete> StoreDelete for RegionalStore<S> {
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let res = self.writer().delete(id).await;
+        self.written(id);
+        res
+    }
+
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        let res = self.writer().hard_delete(id).await;
+        self.written(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreRestore> StoreRestore for RegionalStore<S> {
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        let res = self.writer().restore(id).await;
+        self.written(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreExpire> StoreExpire for RegionalStore<S> {
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        let res = self.writer().set_expiry(id, at).await;
+        self.written(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreHealth> StoreHealth for RegionalStore<S> {
+    async fn ping(&self) -> Result<(), Error> {
+        self.local.ping().await?;
+        if let Some(writer) = &self.writer {
+            writer.ping().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    /// Store with a replica that hasn't caught up with the writer
+    async fn get_lagging_store(max_lag: Duration) -> Result<RegionalStore<MemoryStore>, Error> {
+        let store = RegionalStore::new(MemoryStore::new())
+            .with_writer(MemoryStore::new())
+            .with_max_lag(max_lag);
+        store.local.put(&get_product("1", 10.0)).await?;
+        store.put(&get_product("1", 20.0)).await?;
+        Ok(store)
+    }
+
+    #[tokio::test]
+    async fn test_get_after_write() -> Result<(), Error> {
+        // GIVEN a product written through the store, not yet replicated
+        let store = get_lagging_store(Duration::from_secs(60)).await?;
+
+        // WHEN reading it
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN it is read from the writer region
+        assert_eq!(product, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_routing() -> Result<(), Error> {
+        // GIVEN a product written longer than the replication lag ago
+        let store = get_lagging_store(Duration::ZERO).await?;
+
+        // WHEN reading it with both consistencies
+        let eventual = store.get("1", ReadConsistency::Eventual, None).await?;
+        let strong = store.get("1", ReadConsistency::Strong, None).await?;
+
+        // THEN the eventually consistent read goes to the local replica
+        assert_eq!(eventual, Some(get_product("1", 10.0)));
+        // AND the strongly consistent read to the writer region
+        assert_eq!(strong, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+
+    /// Store reporting the same write time for all its products
+    struct WrittenStore {
+        store: MemoryStore,
+        written_at: u64,
+    }
+
+    #[async_trait]
+    impl StoreGet for WrittenStore {
+        async fn get(
+            &self,
+            id: &str,
+            consistency: ReadConsistency,
+            fields: Option<&[ProductField]>,
+        ) -> Result<Option<Product>, Error> {
+            self.store.get(id, consistency, fields).await
+        }
+    }
+
+    #[async_trait]
+    impl StoreGetWriteTime for WrittenStore {
+        async fn get_with_write_time(
+            &self,
+            id: &str,
+            consistency: ReadConsistency,
+            fields: Option<&[ProductField]>,
+        ) -> Result<Option<(Product, Option<u64>)>, Error> {
+            Ok(self
+                .store
+                .get(id, consistency, fields)
+                .await?
+                .map(|product| (product, Some(self.written_at))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_written_elsewhere() -> Result<(), Error> {
+        // GIVEN a product whose local copy was just written by another
+        // process, with a later write not yet replicated
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let local = WrittenStore {
+            store: MemoryStore::new(),
+            written_at: now.as_micros() as u64,
+        };
+        local.store.put(&get_product("1", 10.0)).await?;
+        let writer = WrittenStore {
+            store: MemoryStore::new(),
+            written_at: now.as_micros() as u64,
+        };
+        writer.store.put(&get_product("1", 20.0)).await?;
+        let store = RegionalStore::new(local)
+            .with_writer(writer)
+            .with_max_lag(Duration::from_secs(60));
+
+        // WHEN reading it
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN it is read from the writer region
+        assert_eq!(product, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_locally() -> Result<(), Error> {
+        // GIVEN a product created by another process, not yet replicated
+        let store = RegionalStore::new(MemoryStore::new()).with_writer(MemoryStore::new());
+        store.writer().put(&get_product("1", 20.0)).await?;
+
+        // WHEN reading it
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN it is read from the writer region
+        assert_eq!(product, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+}
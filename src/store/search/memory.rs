@@ -0,0 +1,178 @@
+//! # In-memory search index implementation
+//!
+//! This is a simple in-memory search index implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+//!
+//! Each term of the query is compared to the words of the product names: an
+//! exact match scores more than a prefix, which scores more than a word with
+//! a single typo.
+
+use super::{SearchIndex, StoreIndex, StoreSearch};
+use crate::{Error, Product};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryIndex {
+    data: RwLock<BTreeMap<String, Product>>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl SearchIndex for MemoryIndex {}
+
+#[async_trait]
+impl StoreIndex for MemoryIndex {
+    async fn index(&self, products: &[Product]) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        for product in products {
+            data.insert(product.id.clone(), product.clone());
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, ids: &[String]) -> Result<(), Error> {
+        let mut data = self.data.write().unwrap();
+        for id in ids {
+            data.remove(id);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreSearch for MemoryIndex {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<Product>, Error> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let mut results: Vec<(u32, Product)> = self
+            .data
+            .read()
+            .unwrap()
+            .values()
+            .map(|product| (score(&terms, &product.name), product))
+            .filter(|(score, _)| *score > 0)
+            .map(|(score, product)| (score, product.clone()))
+            .collect();
+
+        // Stable sort, so that products with the same score stay sorted by id
+        results.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(results
+            .into_iter()
+            .take(limit)
+            .map(|(_, product)| product)
+            .collect())
+    }
+}
+
+/// Relevance of a product name for the query terms
+fn score(terms: &[String], name: &str) -> u32 {
+    let words: Vec<String> = name.split_whitespace().map(str::to_lowercase).collect();
+    terms
+        .iter()
+        .map(|term| {
+            words
+                .iter()
+                .map(|word| {
+                    if word == term {
+                        3
+                    } else if word.starts_with(term.as_str()) {
+                        2
+                    } else if is_one_edit_away(term, word) {
+                        1
+                    } else {
+                        0
+                    }
+                })
+                .max()
+                .unwrap_or(0)
+        })
+        .sum()
+}
+
+/// Whether two words differ by at most one insertion, deletion or
+/// substitution
+fn is_one_edit_away(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+
+    // Skip the common prefix and suffix, and check what remains
+    let prefix = short.iter().zip(&long).take_while(|(x, y)| x == y).count();
+    let suffix = short[prefix..]
+        .iter()
+        .rev()
+        .zip(long[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    long.len() - prefix - suffix <= 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_product(id: &str, name: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            name: name.to_string(),
+            price: 10.0,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search() -> Result<(), Error> {
+        // GIVEN an index with three products
+        let index = MemoryIndex::new();
+        index
+            .index(&[
+                get_product("1", "Red shoes"),
+                get_product("2", "Blue shoe"),
+                get_product("3", "Green hat"),
+            ])
+            .await?;
+
+        // WHEN searching with a typo
+        let products = index.search("shoes reed", 10).await?;
+
+        // THEN matching products are returned by relevance
+        assert_eq!(
+            products.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove() -> Result<(), Error> {
+        // GIVEN an index with a product
+        let index = MemoryIndex::new();
+        index.index(&[get_product("1", "Red shoes")]).await?;
+
+        // WHEN removing it
+        index.remove(&["1".to_string()]).await?;
+
+        // THEN it can't be found anymore
+        assert!(index.search("shoes", 10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_one_edit_away() {
+        assert!(is_one_edit_away("shoe", "shoes"));
+        assert!(is_one_edit_away("reed", "red"));
+        assert!(is_one_edit_away("hat", "hot"));
+        assert!(!is_one_edit_away("hat", "shoe"));
+    }
+}
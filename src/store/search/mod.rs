@@ -0,0 +1,39 @@
+//! # Product search
+//!
+//! Products are copied to a search index when they are written, so that
+//! they can be searched by name with full-text and fuzzy matching. The index
+//! is eventually consistent with the product table.
+
+use crate::{Error, Product};
+use async_trait::async_trait;
+
+mod memory;
+#[cfg(feature = "http")]
+mod opensearch;
+
+pub use memory::MemoryIndex;
+#[cfg(feature = "http")]
+pub use opensearch::OpenSearchIndex;
+
+pub trait SearchIndex: StoreIndex + StoreSearch {}
+
+/// Trait for keeping the search index up to date
+#[async_trait]
+pub trait StoreIndex: Send + Sync {
+    /// Add or replace products in the index
+    async fn index(&self, products: &[Product]) -> Result<(), Error>;
+
+    /// Remove products from the index
+    ///
+    /// Ids that aren't in the index are ignored.
+    async fn remove(&self, ids: &[String]) -> Result<(), Error>;
+}
+
+/// Trait for searching products
+///
+/// This returns at most `limit` products matching `query`, from the most to
+/// the least relevant.
+#[async_trait]
+pub trait StoreSearch: Send + Sync {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<Product>, Error>;
+}
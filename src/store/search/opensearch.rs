@@ -0,0 +1,211 @@
+//! # OpenSearch search index implementation
+//!
+//! Search index implementation using the OpenSearch REST API.
+//!
+//! Products are indexed with their id as the document id, and writes use the
+//! bulk API. Searches match the query against the product names, allowing
+//! for typos, and rank results by relevance.
+
+use super::{SearchIndex, StoreIndex, StoreSearch};
+use crate::{Error, Product};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info, instrument};
+
+/// Response from the search API
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: Hits,
+}
+
+#[derive(Deserialize)]
+struct Hits {
+    hits: Vec<Hit>,
+}
+
+#[derive(Deserialize)]
+struct Hit {
+    #[serde(rename = "_source")]
+    source: Product,
+}
+
+/// Response from the bulk API
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+}
+
+pub struct OpenSearchIndex {
+    client: Client,
+    endpoint: String,
+    index_name: String,
+    credentials: Option<(String, String)>,
+}
+
+impl OpenSearchIndex {
+    pub fn new(client: Client, endpoint: String, index_name: String) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            index_name,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate requests with HTTP basic authentication
+    ///
+    /// This is needed for domains using fine-grained access control with an
+    /// internal user database.
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        self.credentials = Some((username, password));
+        self
+    }
+
+    fn authenticate(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.credentials {
+            Some((username, password)) => req.basic_auth(username, Some(password)),
+            None => req,
+        }
+    }
+
+    /// Send a bulk request made of NDJSON lines
+    async fn bulk(&self, lines: Vec<serde_json::Value>) -> Result<(), Error> {
+        let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let res = self
+            .authenticate(
+                self.client
+                    .post(format!("{}/{}/_bulk", self.endpoint, self.index_name))
+                    .header("Content-Type", "application/x-ndjson")
+                    .body(body),
+            )
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling OpenSearch: {}", err);
+                Error::InternalError("Unable to reach OpenSearch")
+            })?;
+
+        if !res.status().is_success() {
+            error!("OpenSearch returned status {}", res.status());
+            return Err(Error::InternalError("OpenSearch returned an error"));
+        }
+        let res: BulkResponse = res
+            .json()
+            .await
+            .map_err(|_| Error::InternalError("Invalid response from OpenSearch"))?;
+        // Deleting a missing document is not an error in bulk requests
+        if res.errors {
+            return Err(Error::InternalError("OpenSearch failed to index products"));
+        }
+        Ok(())
+    }
+}
+
+impl SearchIndex for OpenSearchIndex {}
+
+#[async_trait]
+impl StoreIndex for OpenSearchIndex {
+    #[instrument(skip(self, products))]
+    async fn index(&self, products: &[Product]) -> Result<(), Error> {
+        if products.is_empty() {
+            return Ok(());
+        }
+        info!("Indexing {} products in OpenSearch", products.len());
+        let lines = products
+            .iter()
+            .flat_map(|product| [json!({"index": {"_id": product.id}}), json!(product)])
+            .collect();
+        self.bulk(lines).await
+    }
+
+    #[instrument(skip(self, ids))]
+    async fn remove(&self, ids: &[String]) -> Result<(), Error> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        info!("Removing {} products from OpenSearch", ids.len());
+        let lines = ids
+            .iter()
+            .map(|id| json!({"delete": {"_id": id}}))
+            .collect();
+        self.bulk(lines).await
+    }
+}
+
+#[async_trait]
+impl StoreSearch for OpenSearchIndex {
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<Product>, Error> {
+        info!("Searching products in OpenSearch");
+        let res = self
+            .authenticate(
+                self.client
+                    .post(format!("{}/{}/_search", self.endpoint, self.index_name))
+                    .json(&search_query(query, limit)),
+            )
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling OpenSearch: {}", err);
+                Error::InternalError("Unable to reach OpenSearch")
+            })?;
+
+        if !res.status().is_success() {
+            error!("OpenSearch returned status {}", res.status());
+            return Err(Error::InternalError("OpenSearch returned an error"));
+        }
+        res.json::<SearchResponse>()
+            .await
+            .map(|res| res.hits.hits.into_iter().map(|hit| hit.source).collect())
+            .map_err(|_| Error::InternalError("Invalid response from OpenSearch"))
+    }
+}
+
+/// Body of a search request
+///
+/// Exact matches rank higher than fuzzy ones, as both clauses match them.
+fn search_query(query: &str, limit: usize) -> serde_json::Value {
+    json!({
+        "size": limit,
+        "query": {
+            "bool": {
+                "should": [
+                    {"match": {"name": {"query": query, "boost": 2}}},
+                    {"match": {"name": {"query": query, "fuzziness": "AUTO"}}}
+                ],
+                "minimum_should_match": 1
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_query() {
+        let query = search_query("red shoes", 10);
+
+        assert_eq!(query["size"], 10);
+        assert_eq!(
+            query["query"]["bool"]["should"][1]["match"]["name"]["fuzziness"],
+            "AUTO"
+        );
+    }
+
+    #[test]
+    fn test_parse_search_response() {
+        let res: SearchResponse = serde_json::from_str(
+            r#"{"took": 3, "hits": {"total": {"value": 1}, "hits": [
+                {"_id": "1", "_score": 1.2, "_source": {"id": "1", "name": "Red shoes", "price": 10.0}}
+            ]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(res.hits.hits.len(), 1);
+        assert_eq!(res.hits.hits[0].source.name, "Red shoes");
+    }
+}
@@ -0,0 +1,159 @@
+//! # Encrypted snapshots
+//!
+//! Snapshots are encrypted with AES-256-GCM before being written to disk.
+//! Each snapshot uses a random nonce, stored in front of the ciphertext, and
+//! the authentication tag detects snapshots that were tampered with or
+//! encrypted with another key.
+//!
+//! Keys are random 256-bit keys, rather than derived from passphrases, so
+//! that they can't be guessed from a stolen snapshot, e.g. one generated with
+//! `openssl rand -base64 32`.
+
+use crate::Error;
+use aes_gcm::{
+    aead::{Aead, NewAead},
+    Aes256Gcm, Key, Nonce,
+};
+use aws_smithy_types::base64;
+use std::{fs, path::Path};
+
+/// Length of an AES-GCM nonce, in bytes
+const NONCE_LENGTH: usize = 12;
+
+/// Length of an AES-256 key, in bytes
+const KEY_LENGTH: usize = 32;
+
+/// Key used to encrypt snapshots
+#[derive(Clone)]
+pub struct SnapshotKey([u8; KEY_LENGTH]);
+
+impl SnapshotKey {
+    pub fn new(key: [u8; KEY_LENGTH]) -> Self {
+        Self(key)
+    }
+
+    /// Read a base64-encoded key
+    ///
+    /// This fails if the key isn't valid base64, or isn't 32 bytes long.
+    pub fn from_base64(key: &str) -> Result<Self, Error> {
+        let key = base64::decode(key.trim())
+            .map_err(|_| Error::ClientError("Snapshot key must be base64-encoded"))?;
+        let key = key
+            .try_into()
+            .map_err(|_| Error::ClientError("Snapshot key must be 32 bytes long"))?;
+        Ok(Self(key))
+    }
+}
+
+impl std::fmt::Debug for SnapshotKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("SnapshotKey(..)")
+    }
+}
+
+/// Encrypt and write a snapshot
+///
+/// The snapshot is written to a temporary file first, then renamed, so that
+/// a crash while writing doesn't corrupt the previous snapshot.
+pub(crate) fn write(path: &Path, key: &SnapshotKey, data: &[u8]) -> Result<(), Error> {
+    let mut nonce = [0; NONCE_LENGTH];
+    getrandom::getrandom(&mut nonce)
+        .map_err(|_| Error::InternalError("Failed to generate snapshot nonce"))?;
+    let ciphertext = cipher(key)
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| Error::InternalError("Failed to encrypt snapshot"))?;
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, [&nonce[..], &ciphertext].concat())
+        .map_err(|_| Error::InternalError("Failed to write snapshot"))?;
+    fs::rename(&tmp, path).map_err(|_| Error::InternalError("Failed to write snapshot"))
+}
+
+/// Read and decrypt a snapshot
+///
+/// Returns `None` if there is no snapshot yet.
+pub(crate) fn read(path: &Path, key: &SnapshotKey) -> Result<Option<Vec<u8>>, Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(_) => return Err(Error::InternalError("Failed to read snapshot")),
+    };
+    if bytes.len() < NONCE_LENGTH {
+        return Err(Error::InternalError("Snapshot is truncated"));
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    cipher(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Some)
+        .map_err(|_| Error::InternalError("Failed to decrypt snapshot"))
+}
+
+fn cipher(key: &SnapshotKey) -> Aes256Gcm {
+    Aes256Gcm::new(Key::from_slice(&key.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() -> Result<(), Error> {
+        // GIVEN a snapshot written with a key
+        let path = std::env::temp_dir().join("test_snapshot_write_read.bin");
+        let key = SnapshotKey::new([1; KEY_LENGTH]);
+        write(&path, &key, b"hello")?;
+
+        // WHEN reading it back
+        let data = read(&path, &key)?;
+
+        // THEN the data is decrypted
+        assert_eq!(data.as_deref(), Some(&b"hello"[..]));
+        // AND the file doesn't contain the plaintext
+        let raw = fs::read(&path).unwrap();
+        assert!(!raw.windows(5).any(|w| w == b"hello"));
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_wrong_key() -> Result<(), Error> {
+        // GIVEN a snapshot written with a key
+        let path = std::env::temp_dir().join("test_snapshot_wrong_key.bin");
+        write(&path, &SnapshotKey::new([1; KEY_LENGTH]), b"hello")?;
+
+        // WHEN reading it with another key
+        let res = read(&path, &SnapshotKey::new([2; KEY_LENGTH]));
+
+        // THEN decryption fails
+        assert!(res.is_err());
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_missing() -> Result<(), Error> {
+        let path = std::env::temp_dir().join("test_snapshot_missing.bin");
+
+        let data = read(&path, &SnapshotKey::new([1; KEY_LENGTH]))?;
+
+        assert_eq!(data, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_base64() -> Result<(), Error> {
+        // A 32-byte key is accepted
+        let key = SnapshotKey::from_base64(&base64::encode([1; KEY_LENGTH]))?;
+        assert_eq!(key.0, [1; KEY_LENGTH]);
+
+        // Keys of other lengths, such as passphrases, are rejected
+        assert!(SnapshotKey::from_base64(&base64::encode([1; 16])).is_err());
+        assert!(SnapshotKey::from_base64("secret").is_err());
+        assert!(SnapshotKey::from_base64("not base64!").is_err());
+
+        Ok(())
+    }
+}
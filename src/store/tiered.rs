@@ -0,0 +1,481 @@
+//! # Tiered store
+//!
+//! Store wrapper keeping recently read products in a process-local LRU
+//! cache, in front of a slower backend such as DynamoDB. Lambda reuses the
+//! process across warm invocations, so popular products are served without
+//! a network round trip.
+//!
+//! Cached products are fresh for `ttl`. Between `ttl` and `stale_ttl`, they
+//! are still returned, but a background task reads the product again from
+//! the backend (stale-while-revalidate). Older entries are treated as
+//! misses. As the Lambda execution environment is frozen between
+//! invocations, a revalidation can complete during the next invocation.
+//!
+//! Only eventually consistent single product reads use the cache: strongly
+//! consistent reads always go to the backend. Writes through this wrapper
+//! evict the product, but writes from other processes are only seen once
+//! the entry expires.
+
+use super::{
+    project, ProductField, ProductFilter, ReadConsistency, Store, StoreBatchGet, StoreCount,
+    StoreDelete, StoreExpire, StoreGet, StoreGetAll, StoreHealth, StorePut, StorePutConditional,
+    StorePutMany, StoreRestore, StoreScanAll, StoreUpdate,
+};
+use crate::{Error, Product, ProductBatch, ProductPatch, ProductRange};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, instrument, warn};
+
+/// Default maximum number of cached products
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Default time during which a cached product is fresh
+const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+/// Default time during which a cached product can be served while it is
+/// revalidated
+const DEFAULT_STALE_TTL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    product: Product,
+    fetched_at: Instant,
+    /// Position in the LRU order
+    used: u64,
+    /// Whether a background revalidation is running
+    refreshing: bool,
+}
+
+/// LRU cache of products
+///
+/// `order` maps the last use of each entry to its id, so the least recently
+/// used entry is the first one.
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl Cache {
+    /// Mark an entry as used and return it
+    fn touch(&mut self, id: &str) -> Option<&mut Entry> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(id)?;
+        self.order.remove(&entry.used);
+        self.order.insert(self.clock, id.to_string());
+        entry.used = self.clock;
+        Some(entry)
+    }
+
+    fn insert(&mut self, product: Product, capacity: usize) {
+        self.remove(&product.id);
+        while self.entries.len() >= capacity {
+            let used = match self.order.keys().next() {
+                Some(used) => *used,
+                None => break,
+            };
+            if let Some(id) = self.order.remove(&used) {
+                self.entries.remove(&id);
+            }
+        }
+        self.clock += 1;
+        self.order.insert(self.clock, product.id.clone());
+        self.entries.insert(
+            product.id.clone(),
+            Entry {
+                product,
+                fetched_at: Instant::now(),
+                used: self.clock,
+                refreshing: false,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.order.remove(&entry.used);
+        }
+    }
+}
+
+/// Result of a cache lookup
+enum Lookup {
+    Fresh(Product),
+    /// The product should be revalidated in the background
+    Stale(Product),
+    Miss,
+}
+
+/// Store caching single product reads in memory.
+pub struct TieredStore<S> {
+    inner: Arc<S>,
+    cache: Arc<Mutex<Cache>>,
+    capacity: usize,
+    ttl: Duration,
+    stale_ttl: Duration,
+}
+
+impl<S> TieredStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            cache: Default::default(),
+            capacity: DEFAULT_CAPACITY,
+            ttl: DEFAULT_TTL,
+            stale_ttl: DEFAULT_STALE_TTL,
+        }
+    }
+
+    /// Set the maximum number of cached products
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Set how long cached products are fresh, and how long they can be
+    /// served while being revalidated
+    ///
+    /// `stale_ttl` is measured from when the product was read, like `ttl`.
+    /// Setting it to `ttl` disables stale-while-revalidate.
+    pub fn with_ttl(mut self, ttl: Duration, stale_ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self.stale_ttl = stale_ttl.max(ttl);
+        self
+    }
+
+    /// Evict a product from the cache
+    pub fn invalidate(&self, id: &str) {
+        self.cache.lock().unwrap().remove(id);
+    }
+
+    fn lookup(&self, id: &str) -> Lookup {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = match cache.touch(id) {
+            Some(entry) => entry,
+            None => return Lookup::Miss,
+        };
+        let age = entry.fetched_at.elapsed();
+        if age < self.ttl {
+            Lookup::Fresh(entry.product.clone())
+        } else if age < self.stale_ttl {
+            // Only start one revalidation per entry
+            if entry.refreshing {
+                Lookup::Fresh(entry.product.clone())
+            } else {
+                entry.refreshing = true;
+                Lookup::Stale(entry.product.clone())
+            }
+        } else {
+            cache.remove(id);
+            Lookup::Miss
+        }
+    }
+
+    /// Store the result of a read from the backend
+    fn fill(cache: &Mutex<Cache>, id: &str, product: Option<&Product>, capacity: usize) {
+        let mut cache = cache.lock().unwrap();
+        match product {
+            Some(product) => cache.insert(product.clone(), capacity),
+            None => cache.remove(id),
+        }
+    }
+}
+
+impl<S> TieredStore<S>
+where
+    S: StoreGet + 'static,
+{
+    /// Read a product again from the backend without blocking the caller
+    fn revalidate(&self, id: &str) {
+        let inner = self.inner.clone();
+        let cache = self.cache.clone();
+        let capacity = self.capacity;
+        let id = id.to_string();
+        tokio::spawn(async move {
+            match inner.get(&id, ReadConsistency::Eventual, None).await {
+                Ok(product) => Self::fill(&cache, &id, product.as_ref(), capacity),
+                Err(err) => {
+                    warn!("Failed to revalidate product {}: {}", id, err);
+                    if let Some(entry) = cache.lock().unwrap().entries.get_mut(&id) {
+                        entry.refreshing = false;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<S: Store + 'static> Store for TieredStore<S> {}
+
+#[async_trait]
+impl<S: StoreGet + 'static> StoreGet for TieredStore<S> {
+    #[instrument(skip(self))]
+    async fn get(
+        &self,
+        id: &str,
+        consistency: ReadConsistency,
+        fields: Option<&[ProductField]>,
+    ) -> Result<Option<Product>, Error> {
+        if consistency == ReadConsistency::Eventual {
+            match self.lookup(id) {
+                Lookup::Fresh(product) => return Ok(Some(project(product, fields))),
+                Lookup::Stale(product) => {
+                    info!("Revalidating cached product {}", id);
+                    self.revalidate(id);
+                    return Ok(Some(project(product, fields)));
+                }
+                Lookup::Miss => (),
+            }
+        }
+
+        // Read the whole product, so that it can serve any projection later
+        let product = self.inner.get(id, consistency, None).await?;
+        Self::fill(&self.cache, id, product.as_ref(), self.capacity);
+        Ok(product.map(|p| project(p, fields)))
+    }
+}
+
+#[async_trait]
+impl<S: StoreGetAll> StoreGetAll for TieredStore<S> {
+    async fn all(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: &ProductFilter,
+        fields: Option<&[ProductField]>,
+    ) -> Result<ProductRange, Error> {
+        self.inner.all(next, limit, filter, fields).await
+    }
+}
+
+#[async_trait]
+impl<S: StoreScanAll> StoreScanAll for TieredStore<S> {
+    async fn scan_all(&self, total_segments: usize) -> Result<Vec<Product>, Error> {
+        self.inner.scan_all(total_segments).await
+    }
+}
+
+#[async_trait]
+impl<S: StoreCount> StoreCount for TieredStore<S> {
+    async fn count(&self) -> Result<usize, Error> {
+        self.inner.count().await
+    }
+}
+
+#[async_trait]
+impl<S: StoreBatchGet> StoreBatchGet for TieredStore<S> {
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn get_many_within(
+        &self,
+        ids: &[String],
+        budget: Duration,
+    ) -> Result<ProductBatch, Error> {
+        self.inner.get_many_within(ids, budget).await
+    }
+}
+
+#[async_trait]
+impl<S: StorePut> StorePut for TieredStore<S> {
+    async fn put(&self, product: &Product) -> Result<Option<Product>, Error> {
+        let res = self.inner.put(product).await;
+        self.invalidate(&product.id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StorePutConditional> StorePutConditional for TieredStore<S> {
+    async fn put_if_absent(&self, product: &Product) -> Result<bool, Error> {
+        let res = self.inner.put_if_absent(product).await;
+        self.invalidate(&product.id);
+        res
+    }
+
+    async fn put_if_version(
+        &self,
+        product: &Product,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let res = self.inner.put_if_version(product, version).await;
+        self.invalidate(&product.id);
+        res
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        patch: &ProductPatch,
+        version: &str,
+    ) -> Result<Option<Product>, Error> {
+        let res = self.inner.update_if_version(id, patch, version).await;
+        self.invalidate(id);
+        res
+    }
+
+    async fn delete_if_version(&self, id: &str, version: &str) -> Result<bool, Error> {
+        let res = self.inner.delete_if_version(id, version).await;
+        self.invalidate(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StorePutMany> StorePutMany for TieredStore<S> {
+    async fn put_many(&self, products: &[Product]) -> Result<(), Error> {
+        let res = self.inner.put_many(products).await;
+        for product in products {
+            self.invalidate(&product.id);
+        }
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreUpdate> StoreUpdate for TieredStore<S> {
+    async fn update(&self, id: &str, patch: &ProductPatch) -> Result<Option<Product>, Error> {
+        let res = self.inner.update(id, patch).await;
+        self.invalidate(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreDelete> StoreDelete for TieredStore<S> {
+    async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let res = self.inner.delete(id).await;
+        self.invalidate(id);
+        res
+    }
+
+    async fn hard_delete(&self, id: &str) -> Result<bool, Error> {
+        let res = self.inner.hard_delete(id).await;
+        self.invalidate(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreRestore> StoreRestore for TieredStore<S> {
+    async fn restore(&self, id: &str) -> Result<bool, Error> {
+        let res = self.inner.restore(id).await;
+        self.invalidate(id);
+        res
+    }
+}
+
+#[async_trait]
+impl<S: StoreExpire> StoreExpire for TieredStore<S> {
+    async fn set_expiry(&self, id: &str, at: u64) -> Result<(), Error> {
+        self.inner.set_expiry(id, at).await
+    }
+}
+
+#[async_trait]
+impl<S: StoreHealth> StoreHealth for TieredStore<S> {
+    async fn ping(&self) -> Result<(), Error> {
+        self.inner.ping().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn get_product(id: &str, price: f64) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "foo".to_string(),
+            price,
+            attributes: Default::default(),
+            images: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_cached() -> Result<(), Error> {
+        // GIVEN a product read through the cache
+        let store = TieredStore::new(MemoryStore::new());
+        store.inner.put(&get_product("1", 10.0)).await?;
+        store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // WHEN the product changes in the backend and is read again
+        store.inner.put(&get_product("1", 20.0)).await?;
+        let cached = store.get("1", ReadConsistency::Eventual, None).await?;
+        let strong = store.get("1", ReadConsistency::Strong, None).await?;
+
+        // THEN the eventually consistent read is served from the cache
+        assert_eq!(cached, Some(get_product("1", 10.0)));
+        // AND the strongly consistent read from the backend
+        assert_eq!(strong, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_stale() -> Result<(), Error> {
+        // GIVEN a cache where products are stale immediately
+        let store =
+            TieredStore::new(MemoryStore::new()).with_ttl(Duration::ZERO, Duration::from_secs(60));
+        store.inner.put(&get_product("1", 10.0)).await?;
+        store.get("1", ReadConsistency::Eventual, None).await?;
+        store.inner.put(&get_product("1", 20.0)).await?;
+
+        // WHEN reading the stale product
+        let stale = store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // THEN the cached product is returned
+        assert_eq!(stale, Some(get_product("1", 10.0)));
+        // AND it is revalidated in the background
+        tokio::task::yield_now().await;
+        let product = store.cache.lock().unwrap().entries["1"].product.clone();
+        assert_eq!(product, get_product("1", 20.0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_invalidates() -> Result<(), Error> {
+        // GIVEN a cached product
+        let store = TieredStore::new(MemoryStore::new());
+        store.put(&get_product("1", 10.0)).await?;
+        store.get("1", ReadConsistency::Eventual, None).await?;
+
+        // WHEN updating it through the store
+        store.put(&get_product("1", 20.0)).await?;
+
+        // THEN the next read returns the new product
+        let product = store.get("1", ReadConsistency::Eventual, None).await?;
+        assert_eq!(product, Some(get_product("1", 20.0)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capacity() -> Result<(), Error> {
+        // GIVEN a cache holding two products
+        let store = TieredStore::new(MemoryStore::new()).with_capacity(2);
+        for id in ["1", "2", "3"] {
+            store.inner.put(&get_product(id, 10.0)).await?;
+        }
+        store.get("1", ReadConsistency::Eventual, None).await?;
+        store.get("2", ReadConsistency::Eventual, None).await?;
+
+        // WHEN using the first product, then reading a third one
+        store.get("1", ReadConsistency::Eventual, None).await?;
+        store.get("3", ReadConsistency::Eventual, None).await?;
+
+        // THEN the least recently used product is evicted
+        let cache = store.cache.lock().unwrap();
+        let mut ids: Vec<&String> = cache.entries.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "3"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! # DynamoDB webhook store implementation
+//!
+//! Webhook store implementation using the AWS SDK for DynamoDB.
+
+use super::{
+    StoreDeleteWebhook, StoreGetWebhook, StoreListWebhooks, StorePutWebhook, WebhookStore,
+};
+use crate::{store::dynamodb::ext::AttributeValuesExt, Error, WebhookSubscription};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// DynamoDB webhook store implementation.
+pub struct DynamoDBWebhookStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBWebhookStore {
+    pub fn new(client: Client, table_name: String) -> DynamoDBWebhookStore {
+        DynamoDBWebhookStore { client, table_name }
+    }
+}
+
+impl WebhookStore for DynamoDBWebhookStore {}
+
+#[async_trait]
+impl StoreListWebhooks for DynamoDBWebhookStore {
+    /// Scan all subscriptions
+    #[instrument(skip(self))]
+    async fn webhooks(&self) -> Result<Vec<WebhookSubscription>, Error> {
+        info!("Scanning webhook subscriptions in DynamoDB table");
+        let mut subscriptions = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await?;
+
+            subscriptions.extend(
+                res.items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| v.try_into())
+                    .collect::<Result<Vec<WebhookSubscription>, Error>>()?,
+            );
+
+            // Stop when DynamoDB doesn't return a key for the next page
+            last_evaluated_key = match res.last_evaluated_key {
+                Some(key) => Some(key),
+                None => break,
+            };
+        }
+
+        Ok(subscriptions)
+    }
+}
+
+#[async_trait]
+impl StoreGetWebhook for DynamoDBWebhookStore {
+    /// Get a subscription by id
+    #[instrument(skip(self))]
+    async fn webhook(&self, id: &str) -> Result<Option<WebhookSubscription>, Error> {
+        info!("Getting webhook subscription '{}' from DynamoDB table", id);
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_owned()))
+            .send()
+            .await?;
+
+        Ok(match res.item {
+            Some(item) => Some(item.try_into()?),
+            None => None,
+        })
+    }
+}
+
+#[async_trait]
+impl StorePutWebhook for DynamoDBWebhookStore {
+    /// Create or replace a subscription
+    #[instrument(skip(self, subscription), fields(id = %subscription.id))]
+    async fn put_webhook(&self, subscription: &WebhookSubscription) -> Result<(), Error> {
+        info!(
+            "Putting webhook subscription '{}' into DynamoDB table",
+            subscription.id
+        );
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(subscription.into()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreDeleteWebhook for DynamoDBWebhookStore {
+    /// Delete a subscription
+    #[instrument(skip(self))]
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        info!("Deleting webhook subscription '{}' from DynamoDB table", id);
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_owned()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl From<&WebhookSubscription> for HashMap<String, AttributeValue> {
+    /// Convert a &WebhookSubscription into a DynamoDB item
+    fn from(value: &WebhookSubscription) -> HashMap<String, AttributeValue> {
+        let mut retval = HashMap::new();
+        retval.insert("id".to_owned(), AttributeValue::S(value.id.clone()));
+        retval.insert("url".to_owned(), AttributeValue::S(value.url.clone()));
+        retval.insert("secret".to_owned(), AttributeValue::S(value.secret.clone()));
+        // DynamoDB doesn't accept empty sets
+        if !value.event_types.is_empty() {
+            retval.insert(
+                "event_types".to_owned(),
+                AttributeValue::Ss(value.event_types.clone()),
+            );
+        }
+
+        retval
+    }
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for WebhookSubscription {
+    type Error = Error;
+
+    /// Try to convert a DynamoDB item into a WebhookSubscription
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        Ok(WebhookSubscription {
+            id: value
+                .get_s("id")
+                .ok_or(Error::InternalError("Missing id"))?,
+            url: value
+                .get_s("url")
+                .ok_or(Error::InternalError("Missing url"))?,
+            event_types: value.get_ss("event_types").unwrap_or_default(),
+            secret: value
+                .get_s("secret")
+                .ok_or(Error::InternalError("Missing secret"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dynamodb_request_builder, mock_sdk_config};
+    use aws_sdk_dynamodb::{Client, Config};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    #[tokio::test]
+    async fn test_put_webhook() -> Result<(), Error> {
+        // GIVEN an empty DynamoDBWebhookStore
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"url":{"S":"https://example.com/hook"},"secret":{"S":"secret"},"event_types":{"SS":["Created"]}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBWebhookStore::new(client, "test".to_string());
+
+        // WHEN putting a subscription
+        store
+            .put_webhook(&WebhookSubscription {
+                id: "1".to_string(),
+                url: "https://example.com/hook".to_string(),
+                event_types: vec!["Created".to_string()],
+                secret: "secret".to_string(),
+            })
+            .await?;
+
+        // THEN the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webhooks() -> Result<(), Error> {
+        // GIVEN a DynamoDBWebhookStore with one subscription
+        let conn = TestConnection::new(vec![(
+            dynamodb_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "url": {"S": "https://example.com/hook"}, "secret": {"S": "secret"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(
+            Config::new(&mock_sdk_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let store = DynamoDBWebhookStore::new(client, "test".to_string());
+
+        // WHEN listing the subscriptions
+        let subscriptions = store.webhooks().await?;
+
+        // THEN the subscription is returned, without event type filters
+        assert_eq!(
+            subscriptions,
+            vec![WebhookSubscription {
+                id: "1".to_string(),
+                url: "https://example.com/hook".to_string(),
+                event_types: vec![],
+                secret: "secret".to_string(),
+            }]
+        );
+        // AND the request matches the expected request
+        conn.assert_requests_match(&vec![]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! # In-memory webhook store implementation
+//!
+//! This is a simple in-memory webhook store implementation. It is not
+//! intended to be used in production, but rather as a simple implementation
+//! for local testing purposes.
+
+use super::{
+    StoreDeleteWebhook, StoreGetWebhook, StoreListWebhooks, StorePutWebhook, WebhookStore,
+};
+use crate::{Error, WebhookSubscription};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct MemoryWebhookStore {
+    data: RwLock<BTreeMap<String, WebhookSubscription>>,
+}
+
+impl MemoryWebhookStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl WebhookStore for MemoryWebhookStore {}
+
+#[async_trait]
+impl StoreListWebhooks for MemoryWebhookStore {
+    async fn webhooks(&self) -> Result<Vec<WebhookSubscription>, Error> {
+        Ok(self.data.read().unwrap().values().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl StoreGetWebhook for MemoryWebhookStore {
+    async fn webhook(&self, id: &str) -> Result<Option<WebhookSubscription>, Error> {
+        Ok(self.data.read().unwrap().get(id).cloned())
+    }
+}
+
+#[async_trait]
+impl StorePutWebhook for MemoryWebhookStore {
+    async fn put_webhook(&self, subscription: &WebhookSubscription) -> Result<(), Error> {
+        self.data
+            .write()
+            .unwrap()
+            .insert(subscription.id.clone(), subscription.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreDeleteWebhook for MemoryWebhookStore {
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        self.data.write().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_subscription(id: &str) -> WebhookSubscription {
+        WebhookSubscription {
+            id: id.to_string(),
+            url: "https://example.com/hook".to_string(),
+            event_types: vec!["Created".to_string()],
+            secret: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_get() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryWebhookStore::new();
+
+        // WHEN putting a subscription
+        store.put_webhook(&get_subscription("1")).await?;
+
+        // THEN it can be retrieved
+        assert_eq!(store.webhook("1").await?, Some(get_subscription("1")));
+        assert_eq!(store.webhooks().await?, vec![get_subscription("1")]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<(), Error> {
+        // GIVEN a store with a subscription
+        let store = MemoryWebhookStore::new();
+        store.put_webhook(&get_subscription("1")).await?;
+
+        // WHEN deleting the subscription
+        store.delete_webhook("1").await?;
+
+        // THEN it is no longer there
+        assert_eq!(store.webhook("1").await?, None);
+
+        Ok(())
+    }
+}
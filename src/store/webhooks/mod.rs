@@ -0,0 +1,42 @@
+//! # Webhook subscription storage
+//!
+//! Subscriptions are managed at runtime through the admin API and read by
+//! the components delivering events to subscribers.
+
+use crate::{Error, WebhookSubscription};
+use async_trait::async_trait;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBWebhookStore;
+pub use memory::MemoryWebhookStore;
+
+pub trait WebhookStore:
+    StoreListWebhooks + StoreGetWebhook + StorePutWebhook + StoreDeleteWebhook
+{
+}
+
+/// Trait for retrieving all webhook subscriptions
+#[async_trait]
+pub trait StoreListWebhooks: Send + Sync {
+    async fn webhooks(&self) -> Result<Vec<WebhookSubscription>, Error>;
+}
+
+/// Trait for retrieving a single webhook subscription
+#[async_trait]
+pub trait StoreGetWebhook: Send + Sync {
+    async fn webhook(&self, id: &str) -> Result<Option<WebhookSubscription>, Error>;
+}
+
+/// Trait for storing a single webhook subscription
+#[async_trait]
+pub trait StorePutWebhook: Send + Sync {
+    async fn put_webhook(&self, subscription: &WebhookSubscription) -> Result<(), Error>;
+}
+
+/// Trait for deleting a single webhook subscription
+#[async_trait]
+pub trait StoreDeleteWebhook: Send + Sync {
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error>;
+}
@@ -0,0 +1,64 @@
+//! # External tax provider
+//!
+//! Tax calculator delegating to an external HTTP provider. The provider is
+//! expected to answer `GET {endpoint}/{country}` with a JSON object such as
+//! `{"rate": 0.2}`, and with a 404 status code for unsupported countries.
+
+use super::TaxCalculator;
+use crate::Error;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use tracing::{error, info, instrument};
+
+/// Response from the tax provider
+#[derive(Deserialize)]
+struct RateResponse {
+    rate: f64,
+}
+
+/// External tax provider implementation.
+pub struct ExternalTaxCalculator {
+    client: Client,
+    endpoint: String,
+}
+
+impl ExternalTaxCalculator {
+    pub fn new(client: Client, endpoint: String) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl TaxCalculator for ExternalTaxCalculator {
+    /// Get the rate for a country from the provider
+    #[instrument(skip(self))]
+    async fn rate(&self, country: &str) -> Result<f64, Error> {
+        info!("Fetching tax rate from external provider");
+        let res = self
+            .client
+            .get(format!("{}/{}", self.endpoint, country))
+            .send()
+            .await
+            .map_err(|err| {
+                error!("Error calling tax provider: {}", err);
+                Error::InternalError("Unable to reach tax provider")
+            })?;
+
+        match res.status() {
+            StatusCode::NOT_FOUND => Err(Error::ClientError("Unsupported country")),
+            status if !status.is_success() => {
+                error!("Tax provider returned status {}", status);
+                Err(Error::InternalError("Tax provider returned an error"))
+            }
+            _ => res
+                .json::<RateResponse>()
+                .await
+                .map(|body| body.rate)
+                .map_err(|_| Error::InternalError("Invalid response from tax provider")),
+        }
+    }
+}
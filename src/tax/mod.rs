@@ -0,0 +1,26 @@
+//! # Tax calculation
+//!
+//! Tax calculators return the sales tax rate applicable in a country. The
+//! breakdown of a price into net, tax and gross amounts is computed by the
+//! domain layer.
+
+use crate::Error;
+use async_trait::async_trait;
+
+#[cfg(feature = "http")]
+mod external;
+mod static_rates;
+
+#[cfg(feature = "http")]
+pub use external::ExternalTaxCalculator;
+pub use static_rates::StaticTaxCalculator;
+
+/// Trait for retrieving tax rates
+///
+/// Countries are identified by their ISO 3166-1 alpha-2 code, in upper case.
+/// Rates are returned as a fraction, e.g. `0.2` for 20%. Countries that the
+/// calculator doesn't support return a `ClientError`.
+#[async_trait]
+pub trait TaxCalculator: Send + Sync {
+    async fn rate(&self, country: &str) -> Result<f64, Error>;
+}
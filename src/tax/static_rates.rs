@@ -0,0 +1,67 @@
+//! # Static tax rates
+//!
+//! Tax calculator based on a fixed table of rates per country, such as one
+//! provided through configuration.
+
+use super::TaxCalculator;
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct StaticTaxCalculator {
+    rates: BTreeMap<String, f64>,
+}
+
+impl StaticTaxCalculator {
+    /// Create a calculator from a map of country codes to rates
+    pub fn new(rates: BTreeMap<String, f64>) -> Self {
+        Self {
+            rates: rates
+                .into_iter()
+                .map(|(country, rate)| (country.to_uppercase(), rate))
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl TaxCalculator for StaticTaxCalculator {
+    async fn rate(&self, country: &str) -> Result<f64, Error> {
+        self.rates
+            .get(country)
+            .copied()
+            .ok_or(Error::ClientError("Unsupported country"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate() -> Result<(), Error> {
+        // GIVEN a calculator configured with lower case country codes
+        let calculator = StaticTaxCalculator::new(BTreeMap::from([("fr".to_string(), 0.2)]));
+
+        // WHEN getting the rate for a country
+        let rate = calculator.rate("FR").await?;
+
+        // THEN we get the configured rate
+        assert_eq!(rate, 0.2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_unsupported() {
+        // GIVEN an empty calculator
+        let calculator = StaticTaxCalculator::default();
+
+        // WHEN getting the rate for a country
+        let res = calculator.rate("FR").await;
+
+        // THEN the country is rejected
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+}
@@ -0,0 +1,45 @@
+//! # Test fixtures
+//!
+//! Fixtures shared by the unit tests of the AWS adapters, which mock the
+//! services with a `TestConnection`.
+
+use aws_types::{region::Region, Credentials};
+
+/// SDK configuration for mocked clients
+///
+/// Requests are signed with static credentials for `eu-west-1`, so that the
+/// tests don't depend on the environment. Build the configuration of a
+/// service with `Config::new(&mock_sdk_config().await)`.
+pub async fn mock_sdk_config() -> aws_types::config::Config {
+    aws_config::from_env()
+        .region(Region::new("eu-west-1"))
+        .credentials_provider(Credentials::new(
+            "accesskey",
+            "privatekey",
+            None,
+            None,
+            "dummy",
+        ))
+        .load()
+        .await
+}
+
+/// Builder of the requests expected by a mocked client
+pub fn request_builder(uri: &'static str) -> http::request::Builder {
+    http::Request::builder().uri(http::uri::Uri::from_static(uri))
+}
+
+/// Builder of the requests expected by a mocked client of a service using
+/// an AWS JSON protocol, such as `"1.0"` for DynamoDB or `"1.1"` for
+/// EventBridge
+pub fn json_request_builder(version: &str, uri: &'static str) -> http::request::Builder {
+    request_builder(uri).header(
+        "content-type",
+        format!("application/x-amz-json-{}", version),
+    )
+}
+
+/// Builder of the requests expected by a mocked DynamoDB client
+pub fn dynamodb_request_builder() -> http::request::Builder {
+    json_request_builder("1.0", "https://dynamodb.eu-west-1.amazonaws.com/")
+}
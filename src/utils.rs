@@ -1,5 +1,9 @@
-use crate::{event_bus, store};
-use tracing::{info, instrument};
+use crate::{
+    consumer, event_bus, idempotency, images, lag, object_store, outbox, recommendations, rules,
+    schema, store, store::StoreHealth, tax, Error,
+};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info, instrument, warn};
 
 /// Setup tracing
 pub fn setup_tracing() {
@@ -8,33 +12,1332 @@ pub fn setup_tracing() {
         .json()
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("failed to set tracing subscriber");
+    report_runtime();
+}
+
+/// Build the Tokio runtime of a function
+///
+/// Lambda allocates vCPUs in proportion to the memory of the function, so
+/// the defaults might not suit every function. `TOKIO_WORKER_THREADS` sets
+/// the number of worker threads (one per vCPU by default), and
+/// `TOKIO_MAX_BLOCKING_THREADS` the size of the blocking pool (512 by
+/// default).
+pub fn runtime() -> tokio::runtime::Runtime {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(threads) = worker_threads() {
+        builder.worker_threads(threads);
+    }
+    if let Some(threads) = max_blocking_threads() {
+        builder.max_blocking_threads(threads);
+    }
+    builder.build().expect("failed to build the Tokio runtime")
+}
+
+/// Number of Tokio worker threads, from `TOKIO_WORKER_THREADS`
+fn worker_threads() -> Option<usize> {
+    std::env::var("TOKIO_WORKER_THREADS")
+        .ok()
+        .map(|v| v.parse().expect("TOKIO_WORKER_THREADS must be a number"))
+}
+
+/// Size of the Tokio blocking pool, from `TOKIO_MAX_BLOCKING_THREADS`
+fn max_blocking_threads() -> Option<usize> {
+    std::env::var("TOKIO_MAX_BLOCKING_THREADS").ok().map(|v| {
+        v.parse()
+            .expect("TOKIO_MAX_BLOCKING_THREADS must be a number")
+    })
+}
+
+/// Log the architecture, memory and runtime configuration of the function
+fn report_runtime() {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let memory =
+        std::env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE").unwrap_or_else(|_| "unknown".to_string());
+    info!(
+        arch = std::env::consts::ARCH,
+        memory_mb = memory.as_str(),
+        cpus,
+        worker_threads = worker_threads().unwrap_or(cpus),
+        max_blocking_threads = max_blocking_threads().unwrap_or(512),
+        "Starting function"
+    );
 }
 
 /// Initialize a store
+///
+/// If `OUTBOX_TABLE_NAME` is set, writes also write their event to that
+/// outbox table in the same transaction.
+///
+/// If `WRITER_REGION` is set to another region than the function's, the table
+/// is a global table: writes and strongly consistent reads go to the writer
+/// region, and other reads to the local replica. Products written by the
+/// function are read from the writer region for `REPLICATION_LAG_MS`
+/// milliseconds (2000 by default), and so are products whose local copy was
+/// written by another function within that time, as write times are
+/// recorded whenever `WRITER_REGION` is set.
 #[instrument]
 pub async fn get_store() -> impl store::Store {
     // Get AWS Configuration
     let config = aws_config::load_from_env().await;
 
     // Initialize a DynamoDB store
-    let table_name = std::env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+    let table_name = table_name("TABLE_NAME", "products");
     info!(
         "Initializing DynamoDB store with table name: {}",
         table_name
     );
-    let client = aws_sdk_dynamodb::Client::new(&config);
+    let store = store::RegionalStore::new(dynamodb_store(&config, &table_name));
+    let store = match std::env::var("WRITER_REGION") {
+        Ok(region)
+            if !region.is_empty()
+                && Some(region.as_str()) != config.region().map(|r| r.as_ref()) =>
+        {
+            info!("Sending writes to region: {}", region);
+            let writer_config = aws_config::from_env()
+                .region(aws_types::region::Region::new(region))
+                .load()
+                .await;
+            let max_lag = std::env::var("REPLICATION_LAG_MS")
+                .map(|v| {
+                    Duration::from_millis(v.parse().expect("REPLICATION_LAG_MS must be a number"))
+                })
+                .unwrap_or(Duration::from_secs(2));
+            store
+                .with_writer(dynamodb_store(&writer_config, &table_name))
+                .with_max_lag(max_lag)
+        }
+        _ => store,
+    };
+
+    // Fail fast if the function can't access the table
+    if verify_permissions() {
+        if let Err(err) = store.ping().await {
+            permission_check_failed("dynamodb:DescribeTable", &table_name, err);
+        }
+    }
+
+    store
+}
+
+/// Initialize the decoder of the items of the product table
+///
+/// This is the decoder of the store returned by `get_store`, so that the
+/// DynamoDB Streams handlers decode the items the same way.
+pub async fn get_item_decoder() -> store::ItemDecoder {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    let table_name = table_name("TABLE_NAME", "products");
+    dynamodb_store(&config, &table_name).item_decoder()
+}
+
+/// Initialize a DynamoDB store for the region of `config`
+fn dynamodb_store(config: &aws_types::config::Config, table_name: &str) -> store::DynamoDBStore {
+    let client = dynamodb_client(config);
+    let store = store::DynamoDBStore::new(client, table_name.to_string())
+        .with_key_prefix(key_prefix())
+        .with_sharding(hot_product_ids(), product_shards())
+        .with_batch_get_concurrency(batch_get_concurrency());
+    let store = match batch_get_hedge_delay() {
+        Some(delay) => store.with_hedging(delay),
+        None => store,
+    };
+    // Record write times for the functions reading from other regions
+    let store = match std::env::var("WRITER_REGION") {
+        Ok(region) if !region.is_empty() => store.with_write_times(),
+        _ => store,
+    };
+    let store = match std::env::var("OUTBOX_TABLE_NAME") {
+        Ok(outbox_table_name) => {
+            info!("Writing events to outbox table: {}", outbox_table_name);
+            store.with_outbox(outbox_table_name)
+        }
+        Err(_) => store,
+    };
+    store
+}
+
+/// Initialize a DynamoDB store with an in-memory cache for single product
+/// reads
+///
+/// The cache holds up to `CACHE_CAPACITY` products (1000 by default).
+/// Products are fresh for `CACHE_TTL_MS` milliseconds (5 seconds by
+/// default), then served while being revalidated until `CACHE_STALE_TTL_MS`
+/// (60 seconds by default).
+#[instrument]
+pub async fn get_tiered_store() -> store::TieredStore<impl store::Store> {
+    let capacity = std::env::var("CACHE_CAPACITY")
+        .map(|v| v.parse().expect("CACHE_CAPACITY must be a number"))
+        .unwrap_or(1000);
+    let ttl = std::env::var("CACHE_TTL_MS")
+        .map(|v| Duration::from_millis(v.parse().expect("CACHE_TTL_MS must be a number")))
+        .unwrap_or(Duration::from_secs(5));
+    let stale_ttl = std::env::var("CACHE_STALE_TTL_MS")
+        .map(|v| Duration::from_millis(v.parse().expect("CACHE_STALE_TTL_MS must be a number")))
+        .unwrap_or(Duration::from_secs(60));
+    info!(
+        "Caching up to {} products for {:?} ({:?} stale)",
+        capacity, ttl, stale_ttl
+    );
+
+    store::TieredStore::new(get_store().await)
+        .with_capacity(capacity)
+        .with_ttl(ttl, stale_ttl)
+}
+
+/// Initialize an in-memory store
+///
+/// If the `MEMORY_STORE_SNAPSHOT_PATH` environment variable is set, the store
+/// is loaded from that file and saved back to it every
+/// `MEMORY_STORE_SNAPSHOT_INTERVAL` seconds (60 by default) and on shutdown.
+/// Snapshots are encrypted with `MEMORY_STORE_SNAPSHOT_KEY`, a random 32-byte
+/// key encoded in base64.
+#[instrument]
+pub async fn get_memory_store() -> Arc<store::MemoryStore> {
+    let path = match std::env::var("MEMORY_STORE_SNAPSHOT_PATH") {
+        Ok(path) if !path.is_empty() => path,
+        _ => {
+            info!("Initializing in-memory store without snapshots");
+            return Arc::new(store::MemoryStore::new());
+        }
+    };
+
+    info!("Initializing in-memory store with snapshot: {}", path);
+    let key = std::env::var("MEMORY_STORE_SNAPSHOT_KEY")
+        .expect("MEMORY_STORE_SNAPSHOT_KEY must be set with MEMORY_STORE_SNAPSHOT_PATH");
+    let key = store::SnapshotKey::from_base64(&key)
+        .expect("MEMORY_STORE_SNAPSHOT_KEY must be a base64-encoded 32-byte key");
+    let interval = std::env::var("MEMORY_STORE_SNAPSHOT_INTERVAL")
+        .map(|v| {
+            v.parse()
+                .expect("MEMORY_STORE_SNAPSHOT_INTERVAL must be a number of seconds")
+        })
+        .unwrap_or(60);
+    let store = store::MemoryStore::new()
+        .with_snapshot(path.into(), key)
+        .expect("failed to load the in-memory store snapshot");
+
+    let store = Arc::new(store);
+    store.spawn_snapshots(Duration::from_secs(interval));
+    store
+}
+
+/// Initialize a change store
+#[instrument]
+pub async fn get_change_store() -> impl store::ChangeStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB change store
+    let table_name = table_name("CHANGES_TABLE_NAME", "changes");
+    info!(
+        "Initializing DynamoDB change store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    store::DynamoDBChangeStore::new(client, table_name)
+}
+
+/// Initialize the store of the product projection
+///
+/// The projection is a replica of the catalog rebuilt from the change feed,
+/// stored in the `PROJECTION_TABLE_NAME` table.
+#[instrument]
+pub async fn get_projection_store() -> impl store::Store {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB store
+    let table_name = table_name("PROJECTION_TABLE_NAME", "projection");
+    info!(
+        "Initializing DynamoDB projection store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
     store::DynamoDBStore::new(client, table_name)
 }
 
+/// Initialize the search index
+///
+/// Products are indexed in the `OPENSEARCH_INDEX` index (`products` by
+/// default) of the OpenSearch domain at `OPENSEARCH_ENDPOINT`. Requests are
+/// authenticated with `OPENSEARCH_USERNAME` and `OPENSEARCH_PASSWORD` if set.
+#[cfg(feature = "http")]
+#[instrument]
+pub fn get_search_index() -> impl store::SearchIndex {
+    let endpoint = std::env::var("OPENSEARCH_ENDPOINT").expect("OPENSEARCH_ENDPOINT must be set");
+    let index_name = std::env::var("OPENSEARCH_INDEX").unwrap_or_else(|_| "products".to_string());
+    info!(
+        "Initializing OpenSearch index {} with endpoint: {}",
+        index_name, endpoint
+    );
+    let index = store::OpenSearchIndex::new(reqwest::Client::new(), endpoint, index_name);
+
+    match (
+        std::env::var("OPENSEARCH_USERNAME"),
+        std::env::var("OPENSEARCH_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) => index.with_credentials(username, password),
+        _ => index,
+    }
+}
+
+/// Initialize a price history store
+///
+/// Price points are stored in the `PRICE_HISTORY_TABLE_NAME` table of the
+/// `PRICE_HISTORY_DATABASE_NAME` Timestream database.
+#[cfg(feature = "timestream")]
+#[instrument]
+pub async fn get_price_history_store() -> impl store::PriceHistoryStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    let database_name = std::env::var("PRICE_HISTORY_DATABASE_NAME")
+        .expect("PRICE_HISTORY_DATABASE_NAME must be set");
+    let table_name =
+        std::env::var("PRICE_HISTORY_TABLE_NAME").expect("PRICE_HISTORY_TABLE_NAME must be set");
+    info!(
+        "Initializing Timestream price history with table: {}.{}",
+        database_name, table_name
+    );
+
+    // Timestream requires clients to discover their endpoints
+    let write_endpoint = aws_sdk_timestreamwrite::Client::new(&config)
+        .describe_endpoints()
+        .send()
+        .await
+        .ok()
+        .and_then(|res| res.endpoints?.into_iter().next()?.address)
+        .expect("failed to discover the Timestream write endpoint");
+    let write_config = aws_sdk_timestreamwrite::config::Builder::from(&config)
+        .endpoint_resolver(aws_sdk_timestreamwrite::Endpoint::immutable(
+            format!("https://{}", write_endpoint)
+                .parse()
+                .expect("invalid Timestream write endpoint"),
+        ))
+        .build();
+    let query_endpoint = aws_sdk_timestreamquery::Client::new(&config)
+        .describe_endpoints()
+        .send()
+        .await
+        .ok()
+        .and_then(|res| res.endpoints?.into_iter().next()?.address)
+        .expect("failed to discover the Timestream query endpoint");
+    let query_config = aws_sdk_timestreamquery::config::Builder::from(&config)
+        .endpoint_resolver(aws_sdk_timestreamquery::Endpoint::immutable(
+            format!("https://{}", query_endpoint)
+                .parse()
+                .expect("invalid Timestream query endpoint"),
+        ))
+        .build();
+
+    store::TimestreamPriceHistory::new(
+        aws_sdk_timestreamwrite::Client::from_conf(write_config),
+        aws_sdk_timestreamquery::Client::from_conf(query_config),
+        database_name,
+        table_name,
+    )
+}
+
+/// Initialize a history store
+#[instrument]
+pub async fn get_history_store() -> impl store::HistoryStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB history store
+    let table_name = table_name("HISTORY_TABLE_NAME", "history");
+    info!(
+        "Initializing DynamoDB history store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    store::DynamoDBHistoryStore::new(client, table_name)
+}
+
+/// Initialize a popularity store
+#[instrument]
+pub async fn get_popularity_store() -> impl store::PopularityStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB popularity store
+    let table_name = table_name("POPULARITY_TABLE_NAME", "popularity");
+    info!(
+        "Initializing DynamoDB popularity store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    store::DynamoDBPopularityStore::new(client, table_name)
+}
+
+/// Initialize an audit store
+#[instrument]
+pub async fn get_audit_store() -> impl store::AuditStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB audit store
+    let table_name = table_name("AUDIT_TABLE_NAME", "audit");
+    info!(
+        "Initializing DynamoDB audit store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    store::DynamoDBAuditStore::new(client, table_name)
+}
+
+/// Initialize a webhook subscription store
+#[instrument]
+pub async fn get_webhook_store() -> impl store::WebhookStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize a DynamoDB webhook store
+    let table_name = table_name("WEBHOOKS_TABLE_NAME", "webhooks");
+    info!(
+        "Initializing DynamoDB webhook store with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    store::DynamoDBWebhookStore::new(client, table_name)
+}
+
+/// Initialize a recommendations provider
+///
+/// Amazon Personalize is used if the `PERSONALIZE_CAMPAIGN_ARN` environment
+/// variable is set. Otherwise, related products are computed from the
+/// categories in the `PRODUCT_CATEGORIES` environment variable, a JSON object
+/// mapping category names to product ids.
+#[instrument]
+pub async fn get_recommendations() -> Box<dyn recommendations::Recommendations> {
+    match std::env::var("PERSONALIZE_CAMPAIGN_ARN") {
+        Ok(campaign_arn) if !campaign_arn.is_empty() => {
+            // Get AWS Configuration
+            let config = aws_config::load_from_env().await;
+
+            info!(
+                "Initializing Personalize recommendations with campaign: {}",
+                campaign_arn
+            );
+            let client = aws_sdk_personalizeruntime::Client::new(&config);
+            Box::new(recommendations::PersonalizeRecommendations::new(
+                client,
+                campaign_arn,
+            ))
+        }
+        _ => {
+            info!("Initializing static recommendations");
+            let categories = std::env::var("PRODUCT_CATEGORIES")
+                .map(|v| serde_json::from_str(&v).expect("PRODUCT_CATEGORIES must be valid JSON"))
+                .unwrap_or_default();
+            Box::new(recommendations::StaticRecommendations::new(categories))
+        }
+    }
+}
+
+/// Initialize a tax calculator
+///
+/// Rates are fetched from an external provider if the `TAX_PROVIDER_URL`
+/// environment variable is set and the `http` feature is enabled. Otherwise,
+/// they are read from the `TAX_RATES` environment variable, a JSON object
+/// mapping country codes to rates.
+#[instrument]
+pub fn get_tax_calculator() -> Box<dyn tax::TaxCalculator> {
+    match std::env::var("TAX_PROVIDER_URL") {
+        #[cfg(feature = "http")]
+        Ok(endpoint) if !endpoint.is_empty() => {
+            info!(
+                "Initializing external tax provider with endpoint: {}",
+                endpoint
+            );
+            Box::new(tax::ExternalTaxCalculator::new(
+                reqwest::Client::new(),
+                endpoint,
+            ))
+        }
+        _ => {
+            info!("Initializing static tax rates");
+            let rates = std::env::var("TAX_RATES")
+                .map(|v| serde_json::from_str(&v).expect("TAX_RATES must be valid JSON"))
+                .unwrap_or_default();
+            Box::new(tax::StaticTaxCalculator::new(rates))
+        }
+    }
+}
+
+/// Namespace of this instance of the application
+///
+/// This is controlled by the `NAMESPACE` environment variable (e.g. `dev` or
+/// `pr-42`), so that multiple instances can share the same AWS account.
+pub fn namespace() -> Option<String> {
+    std::env::var("NAMESPACE").ok().filter(|v| !v.is_empty())
+}
+
+/// Initialize a DynamoDB client
+///
+/// Requests are sent to the `DYNAMODB_ENDPOINT` environment variable if set,
+/// e.g. `http://localhost:8000` for DynamoDB Local or `http://localhost:4566`
+/// for LocalStack, so that functions can run without an AWS account.
+fn dynamodb_client(config: &aws_types::config::Config) -> aws_sdk_dynamodb::Client {
+    match std::env::var("DYNAMODB_ENDPOINT") {
+        Ok(endpoint) if !endpoint.is_empty() => {
+            info!("Using DynamoDB endpoint: {}", endpoint);
+            let uri = endpoint
+                .parse()
+                .expect("DYNAMODB_ENDPOINT must be a valid URI");
+            let dynamodb_config = aws_sdk_dynamodb::config::Builder::from(config)
+                .endpoint_resolver(aws_sdk_dynamodb::Endpoint::immutable(uri))
+                .build();
+            aws_sdk_dynamodb::Client::from_conf(dynamodb_config)
+        }
+        _ => aws_sdk_dynamodb::Client::new(config),
+    }
+}
+
+/// Name of a DynamoDB table
+///
+/// The table name from the `var` environment variable takes precedence.
+/// Otherwise, the name is derived from the namespace as `{namespace}-{name}`.
+fn table_name(var: &str, name: &str) -> String {
+    match (std::env::var(var), namespace()) {
+        (Ok(table_name), _) => table_name,
+        (Err(_), Some(namespace)) => format!("{}-{}", namespace, name),
+        (Err(_), None) => panic!("{} or NAMESPACE must be set", var),
+    }
+}
+
+/// Initialize duplicate suppression for event consumers
+///
+/// Claims are stored in DynamoDB if the `IDEMPOTENCY_TABLE_NAME` environment
+/// variable is set. Otherwise, they are kept in memory, which only suppresses
+/// duplicates delivered to the same function instance.
+///
+/// Claims in progress expire after `IDEMPOTENCY_CLAIM_TTL_MS` milliseconds
+/// (15 minutes by default), which should be about the function timeout.
+#[instrument]
+pub async fn get_idempotency() -> Box<dyn consumer::Idempotency> {
+    match std::env::var("IDEMPOTENCY_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => {
+            // Get AWS Configuration
+            let config = aws_config::load_from_env().await;
+
+            info!(
+                "Initializing DynamoDB idempotency with table name: {}",
+                table_name
+            );
+            let client = dynamodb_client(&config);
+            let idempotency = consumer::DynamoDBIdempotency::new(client, table_name);
+            match idempotency_claim_ttl() {
+                Some(claim_ttl) => Box::new(idempotency.with_claim_ttl(claim_ttl)),
+                None => Box::new(idempotency),
+            }
+        }
+        _ => {
+            info!("Initializing in-memory idempotency");
+            let idempotency = consumer::MemoryIdempotency::new();
+            match idempotency_claim_ttl() {
+                Some(claim_ttl) => Box::new(idempotency.with_claim_ttl(claim_ttl)),
+                None => Box::new(idempotency),
+            }
+        }
+    }
+}
+
+/// Time after which claims in progress expire, from the
+/// `IDEMPOTENCY_CLAIM_TTL_MS` environment variable
+fn idempotency_claim_ttl() -> Option<Duration> {
+    std::env::var("IDEMPOTENCY_CLAIM_TTL_MS").ok().map(|v| {
+        Duration::from_millis(
+            v.parse()
+                .expect("IDEMPOTENCY_CLAIM_TTL_MS must be a number"),
+        )
+    })
+}
+
+/// Initialize stream checkpoints for event consumers
+///
+/// Checkpoints are stored in DynamoDB if the `CHECKPOINT_TABLE_NAME`
+/// environment variable is set. Otherwise, they are kept in memory, which only
+/// skips records re-delivered to the same function instance.
+#[instrument]
+pub async fn get_checkpoints() -> Box<dyn consumer::Checkpoints> {
+    match std::env::var("CHECKPOINT_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => {
+            // Get AWS Configuration
+            let config = aws_config::load_from_env().await;
+
+            info!(
+                "Initializing DynamoDB checkpoints with table name: {}",
+                table_name
+            );
+            let client = dynamodb_client(&config);
+            Box::new(consumer::DynamoDBCheckpoints::new(client, table_name))
+        }
+        _ => {
+            info!("Initializing in-memory checkpoints");
+            Box::new(consumer::MemoryCheckpoints::new())
+        }
+    }
+}
+
+/// Initialize the store of consumer positions
+///
+/// Positions are stored in DynamoDB if the `LAG_TABLE_NAME` environment
+/// variable is set. Otherwise, they are kept in memory, which only shows the
+/// consumers of the same process.
+#[instrument]
+pub async fn get_lag_store() -> Box<dyn lag::LagStore> {
+    match std::env::var("LAG_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => {
+            // Get AWS Configuration
+            let config = aws_config::load_from_env().await;
+
+            info!(
+                "Initializing DynamoDB lag store with table name: {}",
+                table_name
+            );
+            let client = dynamodb_client(&config);
+            Box::new(lag::DynamoDBLagStore::new(client, table_name))
+        }
+        _ => {
+            info!("Initializing in-memory lag store");
+            Box::new(lag::MemoryLagStore::new())
+        }
+    }
+}
+
+/// Initialize the outbox the store writes events to
+#[instrument]
+pub async fn get_outbox() -> outbox::DynamoDBOutbox {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    let table_name = std::env::var("OUTBOX_TABLE_NAME").expect("OUTBOX_TABLE_NAME must be set");
+    info!(
+        "Initializing DynamoDB outbox with table name: {}",
+        table_name
+    );
+    let client = dynamodb_client(&config);
+    outbox::DynamoDBOutbox::new(client, table_name)
+}
+
+/// Initialize an idempotency store for write requests
+///
+/// Records are stored in DynamoDB if the `REQUEST_IDEMPOTENCY_TABLE_NAME`
+/// environment variable is set. Otherwise, they are kept in memory, which only
+/// deduplicates retries handled by the same function instance.
+#[instrument]
+pub async fn get_idempotency_store() -> Box<dyn idempotency::IdempotencyStore> {
+    match std::env::var("REQUEST_IDEMPOTENCY_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => {
+            // Get AWS Configuration
+            let config = aws_config::load_from_env().await;
+
+            info!(
+                "Initializing DynamoDB idempotency store with table name: {}",
+                table_name
+            );
+            let client = dynamodb_client(&config);
+            Box::new(idempotency::DynamoDBIdempotencyStore::new(
+                client, table_name,
+            ))
+        }
+        _ => {
+            info!("Initializing in-memory idempotency store");
+            Box::new(idempotency::MemoryIdempotencyStore::new())
+        }
+    }
+}
+
+/// Initialize an object store for catalog backups
+#[instrument]
+pub async fn get_object_store() -> impl object_store::ObjectStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize an S3 object store
+    let bucket = std::env::var("BACKUP_BUCKET_NAME").expect("BACKUP_BUCKET_NAME must be set");
+    info!("Initializing S3 object store with bucket: {}", bucket);
+    let client = aws_sdk_s3::Client::new(&config);
+    object_store::S3ObjectStore::new(client, bucket)
+}
+
+/// Initialize an image store for product images
+///
+/// Presigned URLs are valid for `IMAGE_URL_EXPIRY` seconds (15 minutes by
+/// default).
+#[instrument]
+pub async fn get_image_store() -> impl images::ImageStore {
+    // Get AWS Configuration
+    let config = aws_config::load_from_env().await;
+
+    // Initialize an S3 image store
+    let bucket = std::env::var("IMAGE_BUCKET_NAME").expect("IMAGE_BUCKET_NAME must be set");
+    info!("Initializing S3 image store with bucket: {}", bucket);
+    let client = aws_sdk_s3::Client::new(&config);
+    let store = images::S3ImageStore::new(client, bucket);
+    match std::env::var("IMAGE_URL_EXPIRY") {
+        Ok(expiry) => store.with_expiry(Duration::from_secs(
+            expiry.parse().expect("IMAGE_URL_EXPIRY must be a number"),
+        )),
+        Err(_) => store,
+    }
+}
+
+/// Prefix for product ids, to share a table between namespaces
+fn key_prefix() -> String {
+    namespace()
+        .map(|namespace| format!("{}#", namespace))
+        .unwrap_or_default()
+}
+
+/// Product ids receiving enough traffic to be sharded
+///
+/// This is a comma-separated list in the `HOT_PRODUCT_IDS` environment
+/// variable.
+fn hot_product_ids() -> Vec<String> {
+    std::env::var("HOT_PRODUCT_IDS")
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Number of `BatchGetItem` requests in flight at once, from
+/// `BATCH_GET_CONCURRENCY`
+fn batch_get_concurrency() -> usize {
+    std::env::var("BATCH_GET_CONCURRENCY")
+        .map(|v| v.parse().expect("BATCH_GET_CONCURRENCY must be a number"))
+        .unwrap_or(4)
+}
+
+/// Delay before sending slow `BatchGetItem` requests again, from
+/// `BATCH_GET_HEDGE_MS`
+///
+/// Hedging is disabled if the variable is not set.
+fn batch_get_hedge_delay() -> Option<Duration> {
+    std::env::var("BATCH_GET_HEDGE_MS")
+        .ok()
+        .map(|v| Duration::from_millis(v.parse().expect("BATCH_GET_HEDGE_MS must be a number")))
+}
+
+/// Number of shards for hot product ids, from `PRODUCT_SHARDS`
+fn product_shards() -> usize {
+    std::env::var("PRODUCT_SHARDS")
+        .map(|v| v.parse().expect("PRODUCT_SHARDS must be a number"))
+        .unwrap_or(10)
+}
+
+/// Latency budget when retrieving products by id
+///
+/// This is controlled by the `BATCH_GET_BUDGET_MS` environment variable and
+/// defaults to 1 second.
+pub fn batch_get_budget() -> Duration {
+    std::env::var("BATCH_GET_BUDGET_MS")
+        .map(|v| Duration::from_millis(v.parse().expect("BATCH_GET_BUDGET_MS must be a number")))
+        .unwrap_or(Duration::from_secs(1))
+}
+
+/// Whether hard deletes are allowed
+///
+/// This is controlled by the `ALLOW_HARD_DELETE` environment variable and is
+/// disabled by default.
+pub fn allow_hard_delete() -> bool {
+    std::env::var("ALLOW_HARD_DELETE")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+/// Route of an API function sitting behind an Application Load Balancer
+///
+/// `ALB_ROUTE` overrides the template of the function, e.g. `/products/{id}`
+/// when the listener rule forwards `/products/*`, and
+/// `ALB_HEALTH_CHECK_PATH` sets the path of the target group health checks.
+#[cfg(feature = "apigateway")]
+pub fn get_alb_route(template: &str) -> crate::entrypoints::lambda::alb::AlbRoute {
+    let template = std::env::var("ALB_ROUTE").unwrap_or_else(|_| template.to_string());
+    let route = crate::entrypoints::lambda::alb::AlbRoute::new(template);
+    match std::env::var("ALB_HEALTH_CHECK_PATH") {
+        Ok(path) => route.with_health_check_path(path),
+        Err(_) => route,
+    }
+}
+
 /// Create an event service
+///
+/// Events are appended to a local NDJSON file if the `EVENT_BUS_FILE`
+/// environment variable is set, which is useful for offline development.
+/// Otherwise, they are sent to the EventBridge bus named by `EVENT_BUS_NAME`,
+/// or discarded if it isn't set.
+///
+/// Events that can't be delivered are sent to the SQS queue at
+/// `DEAD_LETTER_QUEUE_URL`, or stored in the `DEAD_LETTER_TABLE_NAME` table,
+/// if either is set.
+///
+/// Event rules in `EVENT_RULES` or `EVENT_RULES_FILE` can suppress events,
+/// route them to other EventBridge buses, or tag them.
+///
+/// With `EVENT_SCHEMA_VALIDATION=true`, events are checked against the
+/// bundled schema before being sent, or against the schemas of the
+/// `SCHEMA_REGISTRY_NAME` registry if set.
+///
+/// If `EVENT_BATCH_SIZE` or `EVENT_BATCH_MAX_LATENCY_MS` is set, events are
+/// buffered and sent in batches. Callers must flush the bus once done. With
+/// `EVENT_PRIORITY_LANES=true`, only minor updates are batched, and other
+/// events are sent right away with retries. Updates are minor if the price
+/// changes by less than `EVENT_MINOR_PRICE_CHANGE`, 0.01 by default.
+///
+/// Only the event types listed in `EVENT_TYPES` are published if it is set,
+/// and updates that only change the price by less than
+/// `EVENT_MIN_PRICE_CHANGE` are dropped.
 #[instrument]
-pub async fn get_event_bus() -> impl event_bus::EventBus<E = crate::Event> {
+pub async fn get_event_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = get_filtered_bus().await;
+
+    let max_size = std::env::var("EVENT_BATCH_SIZE").ok().map(|v| {
+        v.parse::<usize>()
+            .expect("EVENT_BATCH_SIZE must be a positive integer")
+    });
+    let max_latency = std::env::var("EVENT_BATCH_MAX_LATENCY_MS").ok().map(|v| {
+        Duration::from_millis(
+            v.parse()
+                .expect("EVENT_BATCH_MAX_LATENCY_MS must be a number of milliseconds"),
+        )
+    });
+    let priority_lanes = std::env::var("EVENT_PRIORITY_LANES")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if priority_lanes {
+        let mut bus = event_bus::PriorityBus::new(event_bus);
+        if let Some(max_size) = max_size {
+            bus = bus.with_max_size(max_size);
+        }
+        if let Some(max_latency) = max_latency {
+            bus = bus.with_max_latency(max_latency);
+        }
+        if let Ok(change) = std::env::var("EVENT_MINOR_PRICE_CHANGE") {
+            bus = bus.with_classifier(
+                event_bus::priority::DefaultClassifier::with_minor_price_change(
+                    change
+                        .parse()
+                        .expect("EVENT_MINOR_PRICE_CHANGE must be a number"),
+                ),
+            );
+        }
+        info!("Publishing events through priority lanes");
+        return Box::new(bus);
+    }
+    if max_size.is_none() && max_latency.is_none() {
+        return event_bus;
+    }
+
+    let mut bus = event_bus::BatchingBus::new(event_bus);
+    if let Some(max_size) = max_size {
+        bus = bus.with_max_size(max_size);
+    }
+    if let Some(max_latency) = max_latency {
+        bus = bus.with_max_latency(max_latency);
+    }
+    info!(
+        "Batching events with max size {:?} and max latency {:?}",
+        max_size, max_latency
+    );
+    Box::new(bus)
+}
+
+/// Create the bus events are delivered to, dropping filtered out events
+async fn get_filtered_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = get_validating_bus().await;
+
+    let types = std::env::var("EVENT_TYPES").ok();
+    let min_price_change = std::env::var("EVENT_MIN_PRICE_CHANGE").ok().map(|v| {
+        v.parse::<f64>()
+            .expect("EVENT_MIN_PRICE_CHANGE must be a number")
+    });
+    if types.is_none() && min_price_change.is_none() {
+        return event_bus;
+    }
+
+    let mut bus = event_bus::FilteredBus::new(event_bus);
+    if let Some(types) = types {
+        info!("Publishing only events of types: {}", types);
+        bus = bus.with_filter(event_bus::filtered::EventTypeFilter::new(
+            types.split(',').map(str::trim).filter(|t| !t.is_empty()),
+        ));
+    }
+    if let Some(min_price_change) = min_price_change {
+        info!(
+            "Dropping updates changing the price by less than {}",
+            min_price_change
+        );
+        bus = bus.with_filter(event_bus::filtered::PriceChangeFilter::new(
+            min_price_change,
+        ));
+    }
+    Box::new(bus)
+}
+
+/// Create the bus events are delivered to, validating events if enabled
+async fn get_validating_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = get_reliable_bus().await;
+
+    let validate = std::env::var("EVENT_SCHEMA_VALIDATION")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if !validate {
+        return event_bus;
+    }
+    let validator = match std::env::var("SCHEMA_REGISTRY_NAME") {
+        Ok(registry_name) => {
+            info!("Loading event schemas from registry: {}", registry_name);
+            let config = aws_config::load_from_env().await;
+            schema::registry::load_validator(&aws_sdk_schemas::Client::new(&config), &registry_name)
+                .await
+                .expect("failed to load event schemas")
+        }
+        Err(_) => {
+            info!("Validating events against the bundled schema");
+            schema::EventValidator::new()
+        }
+    };
+    Box::new(event_bus::ValidatingBus::new(event_bus, validator))
+}
+
+/// Create the bus events are delivered to, with its dead-letter queue
+async fn get_reliable_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = get_dedup_bus().await;
+
+    #[cfg(feature = "sqs")]
+    if let Ok(queue_url) = std::env::var("DEAD_LETTER_QUEUE_URL") {
+        info!("Initializing SQS dead-letter queue with URL: {}", queue_url);
+        let config = aws_config::load_from_env().await;
+        let queue = event_bus::dead_letter::SqsDeadLetterQueue::new(
+            aws_sdk_sqs::Client::new(&config),
+            queue_url,
+        );
+        return Box::new(event_bus::DeadLetterBus::new(event_bus, Box::new(queue)));
+    }
+    if let Ok(table_name) = std::env::var("DEAD_LETTER_TABLE_NAME") {
+        info!(
+            "Initializing DynamoDB dead-letter queue with table name: {}",
+            table_name
+        );
+        let config = aws_config::load_from_env().await;
+        let queue = event_bus::dead_letter::DynamoDBDeadLetterQueue::new(
+            dynamodb_client(&config),
+            table_name,
+        );
+        return Box::new(event_bus::DeadLetterBus::new(event_bus, Box::new(queue)));
+    }
+
+    event_bus
+}
+
+/// Create the bus events are delivered to, dropping events already published
+///
+/// Events with a deterministic id, such as those of stream records, are
+/// claimed in the `EVENT_DEDUP_TABLE_NAME` table if it is set, with claims in
+/// progress expiring after `IDEMPOTENCY_CLAIM_TTL_MS`.
+async fn get_dedup_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = get_routing_bus().await;
+
+    match std::env::var("EVENT_DEDUP_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => {
+            info!(
+                "Deduplicating events with DynamoDB table name: {}",
+                table_name
+            );
+            let config = aws_config::load_from_env().await;
+            let idempotency =
+                consumer::DynamoDBIdempotency::new(dynamodb_client(&config), table_name);
+            let idempotency = match idempotency_claim_ttl() {
+                Some(claim_ttl) => idempotency.with_claim_ttl(claim_ttl),
+                None => idempotency,
+            };
+            Box::new(event_bus::DedupBus::new(event_bus, Box::new(idempotency)))
+        }
+        _ => event_bus,
+    }
+}
+
+/// Create the bus events are delivered to, applying event rules if set
+///
+/// Rules are read from the `EVENT_RULES` environment variable, or from the
+/// file at `EVENT_RULES_FILE`, followed by the routing table in
+/// `EVENT_ROUTES`. Events routed to a `sqs:` target are sent to the SQS FIFO
+/// queue with that URL, and other events routed to a target to the
+/// EventBridge bus with that name, with an optional `eventbridge:` prefix.
+async fn get_routing_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let event_bus = instrument_bus(get_delivery_bus().await, "default");
+
+    let data = match (
+        std::env::var("EVENT_RULES"),
+        std::env::var("EVENT_RULES_FILE"),
+    ) {
+        (Ok(data), _) => Some(data),
+        (_, Ok(path)) => {
+            Some(std::fs::read_to_string(&path).expect("failed to read EVENT_RULES_FILE"))
+        }
+        _ => None,
+    };
+    let routes = std::env::var("EVENT_ROUTES").ok();
+    if data.is_none() && routes.is_none() {
+        return event_bus;
+    }
+    let mut rules = match data {
+        Some(data) => rules::Rules::parse(&data).expect("failed to parse event rules"),
+        None => rules::Rules::default(),
+    };
+    if let Some(routes) = routes {
+        rules = rules
+            .with_routes(&routes)
+            .expect("failed to parse EVENT_ROUTES");
+    }
+    let targets: Vec<String> = rules.targets().into_iter().map(String::from).collect();
+    info!("Applying event rules with targets: {:?}", targets);
+
+    let mut bus = event_bus::RoutingBus::new(event_bus, rules);
+    if !targets.is_empty() {
+        let config = aws_config::load_from_env().await;
+        let eventbridge = aws_sdk_eventbridge::Client::new(&config);
+        #[cfg(feature = "sqs")]
+        let sqs = aws_sdk_sqs::Client::new(&config);
+        for target in targets {
+            let target_bus: Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> =
+                match target.split_once(':') {
+                    #[cfg(feature = "sqs")]
+                    Some(("sqs", queue_url)) => Box::new(event_bus::SqsFifoBus::new(
+                        sqs.clone(),
+                        queue_url.to_string(),
+                    )),
+                    #[cfg(not(feature = "sqs"))]
+                    Some(("sqs", _)) => panic!("sqs: targets require the sqs feature"),
+                    Some(("eventbridge", name)) => Box::new(event_bus::EventBridgeBus::new(
+                        eventbridge.clone(),
+                        name.to_string(),
+                    )),
+                    _ => Box::new(event_bus::EventBridgeBus::new(
+                        eventbridge.clone(),
+                        target.clone(),
+                    )),
+                };
+            bus = bus.with_target(target.clone(), instrument_bus(target_bus, &target));
+        }
+    }
+    Box::new(bus)
+}
+
+/// Create the consumer receiving events from the `EVENT_QUEUE_URL` queue
+#[cfg(feature = "sqs")]
+pub async fn get_event_consumer() -> event_bus::SqsEventConsumer {
+    let queue_url = std::env::var("EVENT_QUEUE_URL").expect("EVENT_QUEUE_URL must be set");
+    info!("Initializing SQS consumer with queue URL: {}", queue_url);
+    let config = aws_config::load_from_env().await;
+    event_bus::SqsEventConsumer::new(aws_sdk_sqs::Client::new(&config), queue_url)
+}
+
+/// Emit metrics about the events sent to a bus, if `EVENT_METRICS` is set
+fn instrument_bus(
+    event_bus: Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync>,
+    name: &str,
+) -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    let enabled = std::env::var("EVENT_METRICS")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if !enabled {
+        return event_bus;
+    }
+    info!("Emitting metrics for bus: {}", name);
+    Box::new(event_bus::InstrumentedBus::new(event_bus, name))
+}
+
+/// Create the bus events are delivered to
+///
+/// Destinations whose Cargo feature is disabled are skipped, such as Kinesis
+/// without the `kinesis` feature.
+async fn get_delivery_bus() -> Box<dyn event_bus::EventBus<E = crate::Event> + Send + Sync> {
+    if let Ok(path) = std::env::var("EVENT_BUS_FILE") {
+        info!("Initializing file bus with path: {}", path);
+        let max_bytes = std::env::var("EVENT_BUS_FILE_MAX_BYTES")
+            .map(|v| {
+                v.parse()
+                    .expect("EVENT_BUS_FILE_MAX_BYTES must be a number")
+            })
+            .unwrap_or(event_bus::file::DEFAULT_MAX_BYTES);
+        return Box::new(event_bus::FileBus::new(path.into()).with_max_bytes(max_bytes));
+    }
+
+    // Deliver to HTTP endpoints if any API destination is set
+    #[cfg(feature = "http")]
+    if let Some(event_bus) = get_api_destination_bus() {
+        return Box::new(event_bus);
+    }
+
     // Get AWS Configuration
     let config = aws_config::load_from_env().await;
 
-    // Initialize an EventBridge if the environment variable is set
-    let event_bus_name = std::env::var("EVENT_BUS_NAME").expect("EVENT_BUS_NAME must be set");
+    // Deliver to the subscriptions of the webhook store if enabled
+    #[cfg(feature = "http")]
+    if std::env::var("WEBHOOK_DELIVERY").map_or(false, |v| v == "true") {
+        info!("Initializing webhook bus");
+        let mut event_bus =
+            event_bus::WebhookBus::new(reqwest::Client::new(), get_webhook_store().await);
+        if let Ok(secret_id) = std::env::var("WEBHOOK_SIGNING_SECRET_ID") {
+            info!(
+                "Signing webhook requests with keys from secret: {}",
+                secret_id
+            );
+            event_bus =
+                event_bus.with_key_source(Box::new(event_bus::webhook::SecretsManagerKeys::new(
+                    aws_sdk_secretsmanager::Client::new(&config),
+                    secret_id,
+                )));
+        }
+        return Box::new(event_bus);
+    }
+
+    // Send to an SQS FIFO queue if its URL is set, for ordered delivery
+    #[cfg(feature = "sqs")]
+    if let Ok(queue_url) = std::env::var("EVENT_QUEUE_URL") {
+        info!("Initializing SQS FIFO bus with queue URL: {}", queue_url);
+        return Box::new(event_bus::SqsFifoBus::new(
+            aws_sdk_sqs::Client::new(&config),
+            queue_url,
+        ));
+    }
+
+    // Write to a Firehose delivery stream if its name is set
+    #[cfg(feature = "kinesis")]
+    if let Ok(delivery_stream_name) = std::env::var("DELIVERY_STREAM_NAME") {
+        info!(
+            "Initializing Firehose bus with delivery stream: {}",
+            delivery_stream_name
+        );
+        return Box::new(
+            event_bus::FirehoseBus::new(
+                aws_sdk_firehose::Client::new(&config),
+                delivery_stream_name,
+            )
+            .with_serializer(get_event_serializer()),
+        );
+    }
+
+    // Write to a Kinesis data stream if its name is set
+    #[cfg(feature = "kinesis")]
+    if let Ok(stream_name) = std::env::var("KINESIS_STREAM_NAME") {
+        info!("Initializing Kinesis bus with stream: {}", stream_name);
+        let mut event_bus =
+            event_bus::KinesisBus::new(aws_sdk_kinesis::Client::new(&config), stream_name)
+                .with_serializer(get_event_serializer());
+        if std::env::var("KINESIS_AGGREGATION").map_or(false, |v| v == "true") {
+            let max_bytes = std::env::var("KINESIS_AGGREGATION_MAX_BYTES")
+                .map(|v| {
+                    v.parse()
+                        .expect("KINESIS_AGGREGATION_MAX_BYTES must be a number")
+                })
+                .unwrap_or(event_bus::kinesis::DEFAULT_AGGREGATION_MAX_BYTES);
+            event_bus = event_bus.with_aggregation(max_bytes);
+        }
+        return Box::new(event_bus);
+    }
+
+    // Start Step Functions executions if the state machine is set
+    if let Ok(state_machine_arn) = std::env::var("STATE_MACHINE_ARN") {
+        info!(
+            "Initializing Step Functions bus with state machine: {}",
+            state_machine_arn
+        );
+        let mut event_bus =
+            event_bus::StepFunctionsBus::new(aws_sdk_sfn::Client::new(&config), state_machine_arn)
+                .with_batching(
+                    std::env::var("STATE_MACHINE_BATCHING")
+                        .map(|v| v == "true")
+                        .unwrap_or(false),
+                );
+        if let Ok(event_types) = std::env::var("STATE_MACHINE_EVENT_TYPES") {
+            event_bus =
+                event_bus.with_event_types(event_types.split(',').map(String::from).collect());
+        }
+        return Box::new(event_bus);
+    }
+
+    // Publish to IoT Core topics if the data endpoint is set
+    #[cfg(feature = "iot")]
+    if let Ok(endpoint) = std::env::var("IOT_ENDPOINT") {
+        info!("Initializing IoT Core bus with endpoint: {}", endpoint);
+        let uri = format!("https://{}", endpoint)
+            .parse()
+            .expect("IOT_ENDPOINT must be a valid host name");
+        let iot_config = aws_sdk_iotdataplane::config::Builder::from(&config)
+            .endpoint_resolver(aws_sdk_iotdataplane::Endpoint::immutable(uri))
+            .build();
+        let mut event_bus =
+            event_bus::IotMqttBus::new(aws_sdk_iotdataplane::Client::from_conf(iot_config));
+        if let Ok(topic_template) = std::env::var("IOT_TOPIC_TEMPLATE") {
+            event_bus = event_bus.with_topic_template(topic_template);
+        }
+        if let Ok(qos) = std::env::var("IOT_QOS") {
+            event_bus = event_bus
+                .with_qos(qos.parse().expect("IOT_QOS must be a number"))
+                .expect("IOT_QOS must be 0 or 1");
+        }
+        return Box::new(event_bus);
+    }
+
+    // Initialize an EventBridge if the environment variable is set, and
+    // discard events otherwise
+    let event_bus_name = match std::env::var("EVENT_BUS_NAME") {
+        Ok(event_bus_name) => event_bus_name,
+        Err(_) => {
+            warn!("No event destination set, discarding events");
+            return Box::new(event_bus::DiscardBus::new());
+        }
+    };
     info!("Initializing EventBridge bus with name: {}", event_bus_name);
     let client = aws_sdk_eventbridge::Client::new(&config);
-    event_bus::EventBridgeBus::new(client, event_bus_name)
+    let event_bus = event_bus::EventBridgeBus::new(client, event_bus_name.clone());
+
+    // Fail fast if the function can't access the event bus
+    if verify_permissions() {
+        if let Err(err) = event_bus.verify_access().await {
+            permission_check_failed("events:DescribeEventBus", &event_bus_name, err);
+        }
+    }
+
+    Box::new(event_bus)
+}
+
+/// Create the serializer of binary-friendly buses
+///
+/// `EVENT_SERIALIZATION` is `json` (the default), `avro` or `protobuf`.
+/// With `EVENT_SCHEMA_ID`, Avro and Protobuf payloads start with the id of
+/// their schema in a Confluent-compatible schema registry.
+#[cfg(feature = "kinesis")]
+fn get_event_serializer() -> Box<dyn event_bus::EventSerializer> {
+    let format = std::env::var("EVENT_SERIALIZATION").unwrap_or_else(|_| "json".to_string());
+    let schema_id = std::env::var("EVENT_SCHEMA_ID")
+        .ok()
+        .map(|v| v.parse().expect("EVENT_SCHEMA_ID must be a number"));
+    info!("Serializing events as {}", format);
+    match format.as_str() {
+        "json" => Box::new(event_bus::JsonSerializer),
+        "avro" => {
+            let serializer = event_bus::AvroSerializer::new();
+            Box::new(match schema_id {
+                Some(schema_id) => serializer.with_schema_id(schema_id),
+                None => serializer,
+            })
+        }
+        "protobuf" => {
+            let serializer = event_bus::ProtobufSerializer::new();
+            Box::new(match schema_id {
+                Some(schema_id) => serializer.with_schema_id(schema_id),
+                None => serializer,
+            })
+        }
+        _ => panic!("EVENT_SERIALIZATION must be json, avro or protobuf"),
+    }
+}
+
+/// Create a bus delivering events to HTTP endpoints
+///
+/// `API_DESTINATION_ENDPOINT` receives all events, and
+/// `API_DESTINATION_ENDPOINTS` sets endpoints per event type, e.g.
+/// `Created=https://a.example.com,Deleted=https://b.example.com`. Requests
+/// are authorized with an API key (`API_DESTINATION_API_KEY_NAME` and
+/// `API_DESTINATION_API_KEY_VALUE`), basic authentication
+/// (`API_DESTINATION_USERNAME` and `API_DESTINATION_PASSWORD`) or OAuth
+/// client credentials (`API_DESTINATION_OAUTH_TOKEN_URL`,
+/// `API_DESTINATION_OAUTH_CLIENT_ID`, `API_DESTINATION_OAUTH_CLIENT_SECRET`
+/// and optionally `API_DESTINATION_OAUTH_SCOPE`).
+#[cfg(feature = "http")]
+fn get_api_destination_bus() -> Option<event_bus::ApiDestinationBus> {
+    let default_endpoint = std::env::var("API_DESTINATION_ENDPOINT").ok();
+    let endpoints = std::env::var("API_DESTINATION_ENDPOINTS").ok();
+    if default_endpoint.is_none() && endpoints.is_none() {
+        return None;
+    }
+
+    let var = |name: &str| std::env::var(name).ok();
+    let auth = if let Some(token_url) = var("API_DESTINATION_OAUTH_TOKEN_URL") {
+        event_bus::api_destination::ConnectionAuth::OAuthClientCredentials {
+            token_url,
+            client_id: var("API_DESTINATION_OAUTH_CLIENT_ID")
+                .expect("API_DESTINATION_OAUTH_CLIENT_ID must be set"),
+            client_secret: var("API_DESTINATION_OAUTH_CLIENT_SECRET")
+                .expect("API_DESTINATION_OAUTH_CLIENT_SECRET must be set"),
+            scope: var("API_DESTINATION_OAUTH_SCOPE"),
+        }
+    } else if let Some(username) = var("API_DESTINATION_USERNAME") {
+        event_bus::api_destination::ConnectionAuth::Basic {
+            username,
+            password: var("API_DESTINATION_PASSWORD")
+                .expect("API_DESTINATION_PASSWORD must be set"),
+        }
+    } else {
+        event_bus::api_destination::ConnectionAuth::ApiKey {
+            name: var("API_DESTINATION_API_KEY_NAME")
+                .expect("API_DESTINATION_API_KEY_NAME must be set"),
+            value: var("API_DESTINATION_API_KEY_VALUE")
+                .expect("API_DESTINATION_API_KEY_VALUE must be set"),
+        }
+    };
+    info!(
+        "Initializing API destination bus with {:?} authorization",
+        auth
+    );
+
+    let mut event_bus = event_bus::ApiDestinationBus::new(reqwest::Client::new(), auth);
+    if let Some(endpoint) = default_endpoint {
+        event_bus = event_bus.with_default_endpoint(endpoint);
+    }
+    for pair in endpoints.iter().flat_map(|e| e.split(',')) {
+        let (event_type, endpoint) = pair
+            .split_once('=')
+            .expect("API_DESTINATION_ENDPOINTS must contain TYPE=URL pairs");
+        event_bus = event_bus.with_endpoint(event_type.trim(), endpoint.trim().to_string());
+    }
+    Some(event_bus)
+}
+
+/// Whether to verify permissions at startup
+///
+/// This is controlled by the `VERIFY_PERMISSIONS` environment variable and is
+/// disabled by default, as it requires the `dynamodb:DescribeTable` and
+/// `events:DescribeEventBus` permissions and adds latency to cold starts.
+pub fn verify_permissions() -> bool {
+    std::env::var("VERIFY_PERMISSIONS")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+/// Log an actionable error for a failed permission check and abort
+///
+/// The message returned by AWS contains the ARN of the role and of the
+/// resource, so it is logged as-is alongside the action that was checked.
+fn permission_check_failed(action: &str, resource: &str, err: Error) -> ! {
+    let reason = match &err {
+        Error::SdkError(details) if details.code.as_deref() == Some("AccessDeniedException") => {
+            format!("the function role is not allowed to call {}", action)
+        }
+        Error::SdkError(details)
+            if details.code.as_deref() == Some("ResourceNotFoundException") =>
+        {
+            "the resource does not exist in this account and region".to_string()
+        }
+        _ => format!("{} failed", action),
+    };
+    error!(
+        action,
+        resource, "Permission check failed for '{}': {} ({})", resource, reason, err
+    );
+    panic!("Permission check failed for '{}': {}", resource, reason);
 }
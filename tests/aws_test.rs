@@ -29,6 +29,8 @@ fn get_random_product() -> Product {
         name: get_random_string(16),
         // Price with 2 decimal digits
         price: (rng.gen::<f64>() * 25600.0).round() / 100.0,
+        attributes: Default::default(),
+        images: Default::default(),
     }
 }
 
@@ -51,7 +53,7 @@ async fn test_flow() -> Result<(), E> {
     // Get product
     println!("GET product");
     let res = client
-        .get(format!("{}/{}", api_url, product.id))
+        .get(format!("{}/{}?consistent=true", api_url, product.id))
         .send()
         .await?;
     assert_eq!(res.status(), StatusCode::OK);
@@ -79,7 +81,7 @@ async fn test_flow() -> Result<(), E> {
     // Get product again
     println!("GET product again");
     let res = client
-        .get(format!("{}/{}", api_url, product.id))
+        .get(format!("{}/{}?consistent=true", api_url, product.id))
         .send()
         .await?;
     assert_eq!(res.status(), StatusCode::NOT_FOUND);
@@ -96,6 +98,8 @@ async fn test_put_product_with_invalid_id() -> Result<(), E> {
         id: "invalid id".to_string(),
         name: get_random_string(16),
         price: 0.0,
+        attributes: Default::default(),
+        images: Default::default(),
     };
 
     // Put new product